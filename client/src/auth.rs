@@ -0,0 +1,249 @@
+// =============================================================================
+// lnurl-auth (LUD-04)
+// =============================================================================
+//
+// Flow:
+//   1. GET /auth-challenge          → { k1: "<hex 32 bytes>" }
+//   2. Sign k1 CLN signmessage style, with the domain's LUD-05 linking key
+//      (keys.rs), or through our node itself
+//   3. GET /auth-response?k1=<k1>&signature=<zbase>&pubkey=<linking_key>
+//
+// ⚠️  The "catch": send the zbase signature, NOT the DER-hex one. The
+//     server uses CLN checkmessage which expects zbase format.
+//
+// LUD-04 login links (tag=login) from other services carry k1 themselves
+// and take the DER signature over its raw bytes, as `sig` and `key` added
+// to the same URL. Only a linking key can sign those.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lnurl_models::encoding;
+use lnurl_models::{AuthChallenge, AuthResponse, LOGIN_TAG};
+use tracing::{debug, info};
+use url::Url;
+
+use crate::error::{lnurl_error, usage_error};
+use crate::http::Http;
+use crate::keys::LinkingKey;
+use crate::target::Target;
+use crate::wallet::Wallet;
+
+/// The key a login is signed with
+#[async_trait]
+pub trait Signer: Send {
+    /// The pubkey the service will know us by, hex
+    async fn pubkey(&mut self) -> Result<String>;
+
+    /// Signs like CLN's signmessage, returning zbase32 (our server's auth)
+    async fn sign_message(&mut self, message: &str) -> Result<String>;
+
+    /// Signs the raw k1 bytes, returning hex DER (LUD-04 login links)
+    async fn sign_k1(&mut self, k1: &[u8; 32]) -> Result<String>;
+}
+
+#[async_trait]
+impl Signer for LinkingKey {
+    async fn pubkey(&mut self) -> Result<String> {
+        Ok(self.public.to_string())
+    }
+
+    async fn sign_message(&mut self, message: &str) -> Result<String> {
+        Ok(self.sign_message_zbase(message))
+    }
+
+    async fn sign_k1(&mut self, k1: &[u8; 32]) -> Result<String> {
+        Ok(self.sign_k1_der(k1))
+    }
+}
+
+/// Signs with the node's identity key, which ties the login to the node
+/// (see keys.rs)
+pub struct NodeSigner<'a>(pub &'a mut dyn Wallet);
+
+#[async_trait]
+impl Signer for NodeSigner<'_> {
+    async fn pubkey(&mut self) -> Result<String> {
+        Ok(self.0.node_info().await?.id)
+    }
+
+    async fn sign_message(&mut self, message: &str) -> Result<String> {
+        self.0.sign_message(message).await
+    }
+
+    async fn sign_k1(&mut self, _k1: &[u8; 32]) -> Result<String> {
+        Err(usage_error!("LUD-04 login links need a linking key, the node key cannot sign them"))
+    }
+}
+
+/// A successful login
+#[derive(Debug, Clone)]
+pub struct AuthOutcome {
+    pub pubkey: String, // who we logged in as
+    pub event: Option<String>,
+    /// Our server's session token, for `url`'s origin
+    pub token: Option<String>,
+    pub url: Url, // the URL that logged us in
+}
+
+pub fn is_login_link(url: &Url) -> bool {
+    url.query_pairs().any(|(k, v)| k == "tag" && v == LOGIN_TAG)
+}
+
+/// Logs in at `target`: a LUD-04 login link as specified, anything else as
+/// our server's /auth-challenge
+pub async fn auth(http: &Http, target: &Target, signer: &mut dyn Signer) -> Result<AuthOutcome> {
+    info!("Starting LNURL-auth with {}...", target);
+    if is_login_link(target.url()) {
+        return login(http, target.url(), signer).await;
+    }
+
+    // Step 1: GET /auth-challenge
+    let (challenge_url, response_url) = endpoints(target)?;
+    let challenge = fetch_challenge(http, &challenge_url).await?;
+
+    // Step 2: Sign k1 the way CLN signmessage does
+    let pubkey = signer.pubkey().await?;
+    let zbase = signer.sign_message(&challenge.k1).await?; // ← zbase, not the DER signature
+    debug!(zbase = %zbase, "Signed k1");
+    info!("Logging in as {}", pubkey);
+
+    // Step 3: GET /auth-response?k1=<k1>&signature=<zbase>&pubkey=<pubkey>
+    let auth_url = format!(
+        "{}?k1={}&signature={}&pubkey={}",
+        response_url,
+        challenge.k1,
+        zbase,
+        pubkey
+    );
+    info!("Calling auth endpoint");
+
+    let auth_resp: AuthResponse = http.callback_json(&auth_url).await?;
+    let auth_url = Url::parse(&auth_url).context("Invalid auth URL")?;
+    finish(auth_resp, pubkey, auth_url)
+}
+
+/// Where our server hands out the challenge and takes the response. An
+/// LNURL for it points at /auth-challenge, the response goes next to it.
+pub fn endpoints(target: &Target) -> Result<(String, String)> {
+    match target {
+        Target::Base(_) => Ok((
+            target.endpoint("auth-challenge"),
+            target.endpoint("auth-response"),
+        )),
+        Target::Endpoint(url) => {
+            let response_url = url
+                .join("auth-response")
+                .map_err(|e| usage_error!("Cannot derive the auth-response URL: {}", e))?;
+            Ok((url.to_string(), response_url.to_string()))
+        }
+    }
+}
+
+/// Step 1: GET /auth-challenge
+pub async fn fetch_challenge(http: &Http, challenge_url: &str) -> Result<AuthChallenge> {
+    info!("Requesting auth challenge from {}...", challenge_url);
+    let challenge: AuthChallenge = http.get_json(challenge_url).await?;
+    info!("Received k1: {}", challenge.k1);
+    Ok(challenge)
+}
+
+/// The k1 a LUD-04 login link carries
+pub fn login_k1(url: &Url) -> Result<[u8; 32]> {
+    if !is_login_link(url) {
+        return Err(usage_error!(
+            "Not a LUD-04 login link (tag=login&k1=...), this server's own auth uses zbase signatures"
+        ));
+    }
+    let k1 = url
+        .query_pairs()
+        .find(|(k, _)| k == "k1")
+        .map(|(_, v)| v.into_owned())
+        .ok_or_else(|| lnurl_error!("Login link has no k1"))?;
+    encoding::hex_array(&k1).map_err(|e| lnurl_error!("Login link k1 {}: {}", k1, e))
+}
+
+/// LUD-04 as specified: the link carries k1, we sign its raw bytes and send
+/// the DER signature and linking key back to the same URL
+pub async fn login(http: &Http, url: &Url, signer: &mut dyn Signer) -> Result<AuthOutcome> {
+    let k1 = login_k1(url)?;
+    if let Some((_, action)) = url.query_pairs().find(|(k, _)| k == "action") {
+        info!("Action: {}", action);
+    }
+
+    let signature = signer.sign_k1(&k1).await?;
+    let pubkey = signer.pubkey().await?;
+    info!("Logging in as {}", pubkey);
+
+    // GET <url>&sig=<hex DER>&key=<hex linking key>
+    let mut auth_url = url.clone();
+    auth_url
+        .query_pairs_mut()
+        .append_pair("sig", &signature)
+        .append_pair("key", &pubkey);
+    info!("Calling login link");
+
+    let auth_resp: AuthResponse = http.callback_json(auth_url.as_str()).await?;
+    finish(auth_resp, pubkey, auth_url)
+}
+
+fn finish(auth_resp: AuthResponse, pubkey: String, url: Url) -> Result<AuthOutcome> {
+    if !auth_resp.is_ok() {
+        return Err(lnurl_error!(
+            "Authentication failed: {}",
+            auth_resp.reason.unwrap_or_else(|| "unknown".to_string())
+        ));
+    }
+    Ok(AuthOutcome {
+        pubkey,
+        event: auth_resp.event,
+        token: auth_resp.token,
+        url,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn link(k1: &str) -> Url {
+        let mut url = Url::parse("https://service.example/login?tag=login").unwrap();
+        url.query_pairs_mut().append_pair("k1", k1);
+        url
+    }
+
+    #[test]
+    fn login_links_need_the_tag_and_k1() {
+        let k1 = "00".repeat(32);
+        let untagged = Url::parse(&format!("https://service.example/login?k1={}", k1)).unwrap();
+        assert!(login_k1(&untagged).is_err());
+        let no_k1 = Url::parse("https://service.example/login?tag=login").unwrap();
+        assert!(login_k1(&no_k1).is_err());
+        assert_eq!(login_k1(&link(&k1)).unwrap(), [0; 32]);
+    }
+
+    proptest! {
+        #[test]
+        fn k1_round_trips(k1: [u8; 32], upper: bool) {
+            let hex = match upper {
+                true => hex::encode_upper(k1),
+                false => hex::encode(k1),
+            };
+            prop_assert_eq!(login_k1(&link(&hex)).unwrap(), k1);
+        }
+
+        #[test]
+        fn k1_must_be_32_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            prop_assume!(bytes.len() != 32);
+            prop_assert!(login_k1(&link(&hex::encode(bytes))).is_err());
+        }
+
+        #[test]
+        fn k1_never_panics(k1 in "\\PC*") {
+            let result = login_k1(&link(&k1));
+            // Only 64 hex digits make a k1
+            let valid = k1.len() == 64 && k1.chars().all(|c| c.is_ascii_hexdigit());
+            prop_assert_eq!(result.is_ok(), valid);
+        }
+    }
+}
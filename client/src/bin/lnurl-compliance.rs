@@ -0,0 +1,716 @@
+// =============================================================================
+// lnurl-compliance — LUD conformance checks against a running server
+// =============================================================================
+//
+// Points at any LNURL service, ours or someone else's, and reports where its
+// replies stray from the specs:
+//
+//   fields   — required fields missing, or present under another casing
+//              (min_withdrawable for minWithdrawable, "ok" for "OK")
+//   errors   — callback failures as {"status":"ERROR","reason":...}
+//   single-use k1 — a k1 that worked once must not work twice
+//
+// A server base URL is probed at our endpoints (request-channel,
+// request-withdraw, auth-challenge); an LNURL, lightning address or endpoint
+// URL is checked as whatever its tag says. Nothing that moves funds is done unless asked:
+// channel requests are declined with cancel=1, logins use a throwaway key
+// (which may leave an account behind), and a withdraw is only redeemed into
+// an invoice passed with --invoice.
+//
+// Exits 1 when any check fails, so it can gate CI.
+
+use clap::Parser;
+use lnurl_client::keys::{self, LinkingKey};
+use lnurl_client::pay::metadata_description;
+use lnurl_client::target::{parse_pay_target, Target};
+use lnurl_models::encoding;
+use lnurl_models::{CHANNEL_REQUEST_TAG, LOGIN_TAG, PAY_REQUEST_TAG, WITHDRAW_REQUEST_TAG};
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use std::time::Duration;
+use url::Url;
+
+// =============================================================================
+// CLI Parsing
+// =============================================================================
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "lnurl-compliance",
+    version,
+    about = "Check an LNURL service against the LUD specs"
+)]
+struct Cli {
+    /// Server base URLs, endpoint URLs or LNURLs
+    #[arg(required = true)]
+    targets: Vec<String>,
+
+    /// An invoice to redeem a withdraw request into, to check that its k1
+    /// is single-use (the server pays it)
+    #[arg(long)]
+    invoice: Option<String>,
+
+    /// Seconds to wait for each reply
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+}
+
+// =============================================================================
+// Report
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    checks: Vec<(Outcome, String)>,
+}
+
+impl Report {
+    fn record(&mut self, outcome: Outcome, what: impl Into<String>) {
+        let what = what.into();
+        let label = match outcome {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "SKIP",
+        };
+        println!("  {}  {}", label, what);
+        self.checks.push((outcome, what));
+    }
+
+    fn pass(&mut self, what: impl Into<String>) {
+        self.record(Outcome::Pass, what);
+    }
+
+    fn fail(&mut self, what: impl Into<String>) {
+        self.record(Outcome::Fail, what);
+    }
+
+    fn skip(&mut self, what: impl Into<String>) {
+        self.record(Outcome::Skip, what);
+    }
+
+    /// Passes `what` when there are no violations, fails once per violation
+    fn violations(&mut self, what: &str, violations: Vec<String>) {
+        if violations.is_empty() {
+            self.pass(what);
+        }
+        for violation in violations {
+            self.fail(format!("{}: {}", what, violation));
+        }
+    }
+
+    fn count(&self, outcome: Outcome) -> usize {
+        self.checks.iter().filter(|(o, _)| *o == outcome).count()
+    }
+}
+
+// =============================================================================
+// Spec checks
+// =============================================================================
+
+/// Keys compared the way a sloppy server might mix them up
+fn fold_case(key: &str) -> String {
+    key.chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Required fields that are missing or misspelt, and optional ones sent
+/// under another casing
+fn field_violations(
+    body: &Map<String, Value>,
+    required: &[&str],
+    optional: &[&str],
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    for &field in required.iter().chain(optional) {
+        if body.contains_key(field) {
+            continue;
+        }
+        let misspelt = body.keys().find(|key| fold_case(key) == fold_case(field));
+        match misspelt {
+            Some(key) => violations.push(format!("`{}` should be `{}`", key, field)),
+            None if required.contains(&field) => violations.push(format!("`{}` is missing", field)),
+            None => {}
+        }
+    }
+    violations
+}
+
+/// Problems with a callback's `status`, which must be "OK" or "ERROR"
+fn status_violations(body: &Map<String, Value>) -> Vec<String> {
+    let mut violations = field_violations(body, &["status"], &[]);
+    if let Some(status) = body.get("status") {
+        match status.as_str() {
+            Some("OK") | Some("ERROR") => {}
+            Some(other)
+                if other.eq_ignore_ascii_case("ok") || other.eq_ignore_ascii_case("error") =>
+            {
+                violations.push(format!("status `{}` should be upper case", other))
+            }
+            _ => violations.push(format!("status {} is neither \"OK\" nor \"ERROR\"", status)),
+        }
+    }
+    if body.get("status").and_then(Value::as_str) == Some("ERROR")
+        && !body.get("reason").is_some_and(Value::is_string)
+    {
+        violations.push("an ERROR without a `reason`".to_string());
+    }
+    violations
+}
+
+fn is_msat(body: &Map<String, Value>, field: &str) -> bool {
+    body.get(field).is_some_and(Value::is_u64)
+}
+
+// =============================================================================
+// HTTP
+// =============================================================================
+
+struct Probe {
+    http: reqwest::Client,
+    invoice: Option<String>,
+}
+
+/// What a GET returned: the status, and the body if it was a JSON object
+struct Reply {
+    status: StatusCode,
+    body: Option<Map<String, Value>>,
+}
+
+impl Probe {
+    async fn get(&self, url: &str) -> Result<Reply, String> {
+        let response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("GET {} failed: {}", url, e))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| format!("Reading {} failed: {}", url, e))?;
+        let body = match serde_json::from_str(&text) {
+            Ok(Value::Object(body)) => Some(body),
+            _ => None,
+        };
+        Ok(Reply { status, body })
+    }
+
+    /// GETs a callback, recording that it replied with a well-formed status
+    async fn callback(
+        &self,
+        report: &mut Report,
+        what: &str,
+        url: &Url,
+    ) -> Option<Map<String, Value>> {
+        match self.get(url.as_str()).await {
+            Ok(Reply {
+                body: Some(body), ..
+            }) => {
+                report.violations(&format!("{}: status", what), status_violations(&body));
+                Some(body)
+            }
+            Ok(Reply { status, body: None }) => {
+                report.fail(format!("{}: {} without a JSON object", what, status));
+                None
+            }
+            Err(e) => {
+                report.fail(format!("{}: {}", what, e));
+                None
+            }
+        }
+    }
+
+    /// The pay callback's success reply has no status, only `pr`
+    async fn callback_invoice(&self, report: &mut Report, url: &Url) -> Option<Map<String, Value>> {
+        match self.get(url.as_str()).await {
+            Ok(Reply {
+                body: Some(body), ..
+            }) if body.contains_key("status") => {
+                report.violations("invoice at minSendable: status", status_violations(&body));
+                report.fail("invoice at minSendable: refused");
+                None
+            }
+            Ok(Reply {
+                body: Some(body), ..
+            }) => Some(body),
+            Ok(Reply { status, body: None }) => {
+                report.fail(format!(
+                    "invoice at minSendable: {} without a JSON object",
+                    status
+                ));
+                None
+            }
+            Err(e) => {
+                report.fail(format!("invoice at minSendable: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Expects an ERROR from `url`
+    async fn refused(&self, report: &mut Report, what: &str, url: &Url) {
+        if let Some(body) = self.callback(report, what, url).await {
+            match body.get("status").and_then(Value::as_str) {
+                Some("ERROR") => report.pass(format!("{}: refused", what)),
+                _ => report.fail(format!("{}: accepted", what)),
+            }
+        }
+    }
+}
+
+fn with_query(url: &str, pairs: &[(&str, &str)]) -> Result<Url, String> {
+    let mut url = Url::parse(url).map_err(|e| format!("invalid callback {}: {}", url, e))?;
+    url.query_pairs_mut().extend_pairs(pairs);
+    Ok(url)
+}
+
+/// A key nobody else has, for logins and channel cancels
+fn throwaway_key(domain: &str) -> LinkingKey {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).expect("OS randomness");
+    keys::linking_key(&seed, domain).expect("valid seed")
+}
+
+// =============================================================================
+// Flows
+// =============================================================================
+
+/// LUD-02: check the request, then decline it twice
+async fn channel_request(probe: &Probe, report: &mut Report, body: &Map<String, Value>) {
+    let required = ["tag", "uri", "callback", "k1"];
+    report.violations(
+        "channelRequest fields",
+        field_violations(body, &required, &[]),
+    );
+
+    let uri = body.get("uri").and_then(Value::as_str).unwrap_or_default();
+    match encoding::parse_node_uri(uri) {
+        Ok((_, Some(_))) => report.pass("uri is <pubkey>@<host>:<port>"),
+        _ => report.fail(format!("uri `{}` is not <pubkey>@<host>:<port>", uri)),
+    }
+
+    let (Some(callback), Some(k1)) = (
+        body.get("callback").and_then(Value::as_str),
+        body.get("k1").and_then(Value::as_str),
+    ) else {
+        return report.skip("open-channel callback: no callback and k1");
+    };
+    let domain = Url::parse(callback)
+        .ok()
+        .and_then(|u| u.host_str().map(String::from));
+    let node_id = throwaway_key(&domain.unwrap_or_default())
+        .public
+        .to_string();
+
+    let unknown = with_query(
+        callback,
+        &[("remoteid", &node_id), ("k1", "0"), ("cancel", "1")],
+    );
+    let cancel = with_query(
+        callback,
+        &[("remoteid", &node_id), ("k1", k1), ("cancel", "1")],
+    );
+    let (unknown, cancel) = match (unknown, cancel) {
+        (Ok(unknown), Ok(cancel)) => (unknown, cancel),
+        (Err(e), _) | (_, Err(e)) => return report.fail(e),
+    };
+
+    probe.refused(report, "unknown k1", &unknown).await;
+    if let Some(reply) = probe.callback(report, "cancel", &cancel).await {
+        match reply.get("status").and_then(Value::as_str) {
+            Some("OK") => {
+                probe
+                    .refused(report, "single-use k1: cancel again", &cancel)
+                    .await
+            }
+            _ => report.skip("single-use k1: the first cancel was refused"),
+        }
+    }
+}
+
+/// LUD-03: check the request, then redeem it if we were given an invoice
+async fn withdraw_request(probe: &Probe, report: &mut Report, body: &Map<String, Value>) {
+    let required = [
+        "tag",
+        "callback",
+        "k1",
+        "defaultDescription",
+        "minWithdrawable",
+        "maxWithdrawable",
+    ];
+    let optional = ["balanceCheck", "payLink"];
+    report.violations(
+        "withdrawRequest fields",
+        field_violations(body, &required, &optional),
+    );
+
+    match (body.get("minWithdrawable"), body.get("maxWithdrawable")) {
+        (Some(min), Some(max))
+            if is_msat(body, "minWithdrawable") && is_msat(body, "maxWithdrawable") =>
+        {
+            match min.as_u64() <= max.as_u64() {
+                true => report.pass("minWithdrawable <= maxWithdrawable"),
+                false => report.fail(format!("minWithdrawable {} > maxWithdrawable {}", min, max)),
+            }
+        }
+        _ => report.fail("minWithdrawable and maxWithdrawable are not msat integers"),
+    }
+
+    let (Some(callback), Some(k1)) = (
+        body.get("callback").and_then(Value::as_str),
+        body.get("k1").and_then(Value::as_str),
+    ) else {
+        return report.skip("withdraw callback: no callback and k1");
+    };
+
+    match with_query(callback, &[("k1", "0"), ("pr", "lnbc1")]) {
+        Ok(unknown) => probe.refused(report, "unknown k1", &unknown).await,
+        Err(e) => return report.fail(e),
+    }
+
+    let Some(invoice) = &probe.invoice else {
+        return report.skip("single-use k1: needs --invoice");
+    };
+    let Ok(redeem) = with_query(callback, &[("k1", k1), ("pr", invoice)]) else {
+        return;
+    };
+    if let Some(reply) = probe.callback(report, "withdraw", &redeem).await {
+        match reply.get("status").and_then(Value::as_str) {
+            Some("OK") => {
+                probe
+                    .refused(report, "single-use k1: withdraw again", &redeem)
+                    .await
+            }
+            _ => report.skip("single-use k1: the invoice was refused"),
+        }
+    }
+}
+
+/// LUD-06: check the request and ask for invoices in and out of bounds
+async fn pay_request(probe: &Probe, report: &mut Report, body: &Map<String, Value>) {
+    let required = ["tag", "callback", "metadata", "minSendable", "maxSendable"];
+    let optional = ["commentAllowed", "allowsNostr", "nostrPubkey"];
+    report.violations(
+        "payRequest fields",
+        field_violations(body, &required, &optional),
+    );
+
+    match body
+        .get("metadata")
+        .and_then(Value::as_str)
+        .map(metadata_description)
+    {
+        Some(Ok(_)) => report.pass("metadata has one text/plain entry"),
+        Some(Err(e)) => report.fail(format!("metadata: {:#}", e)),
+        None => report.fail("metadata is not a string"),
+    }
+
+    let (Some(callback), Some(min), Some(max)) = (
+        body.get("callback").and_then(Value::as_str),
+        body.get("minSendable").and_then(Value::as_u64),
+        body.get("maxSendable").and_then(Value::as_u64),
+    ) else {
+        return report.skip("pay callback: no callback and bounds");
+    };
+    if min > max {
+        return report.fail(format!("minSendable {} > maxSendable {}", min, max));
+    }
+
+    if let Ok(url) = with_query(callback, &[("amount", &min.to_string())]) {
+        if let Some(reply) = probe.callback_invoice(report, &url).await {
+            report.violations(
+                "invoice fields",
+                field_violations(&reply, &["pr", "routes"], &["successAction"]),
+            );
+            if !reply.get("routes").is_some_and(Value::is_array) {
+                report.fail("routes is not an array");
+            }
+        }
+    }
+    if let Ok(url) = with_query(callback, &[("amount", &max.saturating_add(1).to_string())]) {
+        probe
+            .refused(report, "amount above maxSendable", &url)
+            .await;
+    }
+}
+
+/// LUD-04: sign the k1 with a throwaway key, then replay the signature
+async fn login(probe: &Probe, report: &mut Report, url: &Url) {
+    let k1 = url
+        .query_pairs()
+        .find(|(k, _)| k == "k1")
+        .map(|(_, v)| v.into_owned());
+    let Some(k1_bytes) = k1.and_then(|k1| encoding::hex_array::<32>(&k1).ok()) else {
+        return report.fail("login link: k1 is not 32 bytes of hex");
+    };
+    report.pass("login link carries a 32-byte k1");
+
+    if let Some((_, action)) = url.query_pairs().find(|(k, _)| k == "action") {
+        match ["register", "login", "link", "auth"].contains(&action.as_ref()) {
+            true => report.pass("action is one LUD-04 defines"),
+            false => report.fail(format!("unknown action `{}`", action)),
+        }
+    }
+
+    let key = throwaway_key(url.host_str().unwrap_or_default());
+    let mut signed = url.clone();
+    signed
+        .query_pairs_mut()
+        .append_pair("sig", &key.sign_k1_der(&k1_bytes))
+        .append_pair("key", &key.public.to_string());
+
+    login_twice(probe, report, &signed).await;
+}
+
+/// Our server's /auth-challenge: the same, with a CLN-style signature
+async fn auth_challenge(
+    probe: &Probe,
+    report: &mut Report,
+    challenge_url: &Url,
+    body: &Map<String, Value>,
+) {
+    report.violations(
+        "auth challenge fields",
+        field_violations(body, &["k1"], &[]),
+    );
+    let Some(k1) = body.get("k1").and_then(Value::as_str) else {
+        return;
+    };
+    let Ok(response_url) = challenge_url.join("auth-response") else {
+        return report.fail("no auth-response next to the challenge");
+    };
+
+    let key = throwaway_key(challenge_url.host_str().unwrap_or_default());
+    let pubkey = key.public.to_string();
+
+    // On a k1 of its own, as a failed login may spend it
+    let fresh_k1 = match probe.get(challenge_url.as_str()).await {
+        Ok(Reply {
+            body: Some(body), ..
+        }) => body.get("k1").and_then(Value::as_str).map(String::from),
+        _ => None,
+    };
+    match fresh_k1 {
+        Some(fresh_k1) => {
+            let mut forged = response_url.clone();
+            forged
+                .query_pairs_mut()
+                .append_pair("k1", &fresh_k1)
+                .append_pair("signature", &key.sign_message_zbase("not the k1"))
+                .append_pair("pubkey", &pubkey);
+            probe
+                .refused(report, "signature over another message", &forged)
+                .await;
+        }
+        None => report.skip("signature over another message: no second challenge"),
+    }
+
+    let mut signed = response_url;
+    signed
+        .query_pairs_mut()
+        .append_pair("k1", k1)
+        .append_pair("signature", &key.sign_message_zbase(k1))
+        .append_pair("pubkey", &pubkey);
+    login_twice(probe, report, &signed).await;
+}
+
+async fn login_twice(probe: &Probe, report: &mut Report, signed: &Url) {
+    if let Some(reply) = probe.callback(report, "login", signed).await {
+        match reply.get("status").and_then(Value::as_str) {
+            Some("OK") => {
+                probe
+                    .refused(report, "single-use k1: log in again", signed)
+                    .await
+            }
+            _ => report.fail("login with a valid signature was refused"),
+        }
+    }
+}
+
+/// Fetches `url` and checks it as whatever its tag says
+async fn endpoint(probe: &Probe, report: &mut Report, url: &Url) {
+    if url.query_pairs().any(|(k, v)| k == "tag" && v == LOGIN_TAG) {
+        println!("{} (login, LUD-04)", url);
+        return login(probe, report, url).await;
+    }
+
+    let reply = match probe.get(url.as_str()).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            println!("{}", url);
+            return report.fail(e);
+        }
+    };
+    let Some(body) = reply.body else {
+        println!("{}", url);
+        return report.fail(format!("{} without a JSON object", reply.status));
+    };
+
+    match body.get("tag").and_then(Value::as_str) {
+        Some(CHANNEL_REQUEST_TAG) => {
+            println!("{} (channelRequest, LUD-02)", url);
+            channel_request(probe, report, &body).await;
+        }
+        Some(WITHDRAW_REQUEST_TAG) => {
+            println!("{} (withdrawRequest, LUD-03)", url);
+            withdraw_request(probe, report, &body).await;
+        }
+        Some(PAY_REQUEST_TAG) => {
+            println!("{} (payRequest, LUD-06)", url);
+            pay_request(probe, report, &body).await;
+        }
+        None if body.contains_key("k1") && !body.contains_key("status") => {
+            println!("{} (auth challenge)", url);
+            auth_challenge(probe, report, url, &body).await;
+        }
+        _ if body.contains_key("status") => {
+            println!("{}", url);
+            report.violations("error reply", status_violations(&body));
+            report.skip("the service refused the request");
+        }
+        other => {
+            println!("{}", url);
+            report.violations("tag", field_violations(&body, &["tag"], &[]));
+            if let Some(tag) = other {
+                report.fail(format!("unknown tag `{}`", tag));
+            }
+        }
+    }
+}
+
+/// A URL with a path or a query names an endpoint, not a server to probe
+fn as_endpoint(target: Target) -> Target {
+    match target {
+        Target::Base(url) if url.path() != "/" || url.query().is_some() => Target::Endpoint(url),
+        target => target,
+    }
+}
+
+// =============================================================================
+// Main
+// =============================================================================
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(cli.timeout))
+        .build()
+        .expect("HTTP client");
+    let probe = Probe {
+        http,
+        invoice: cli.invoice,
+    };
+
+    let mut report = Report::default();
+    for input in &cli.targets {
+        match parse_pay_target(input).map(as_endpoint) {
+            Ok(Target::Endpoint(url)) => endpoint(&probe, &mut report, &url).await,
+            Ok(target @ Target::Base(_)) => {
+                for path in ["request-channel", "request-withdraw", "auth-challenge"] {
+                    let url = Url::parse(&target.endpoint(path)).expect("base URL joins");
+                    match probe.get(url.as_str()).await {
+                        Ok(reply) if reply.status == StatusCode::NOT_FOUND => {
+                            println!("{}", url);
+                            report.skip("not offered");
+                        }
+                        _ => endpoint(&probe, &mut report, &url).await,
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{}", input);
+                report.fail(format!("{:#}", e));
+            }
+        }
+        println!();
+    }
+
+    println!(
+        "{} passed, {} failed, {} skipped",
+        report.count(Outcome::Pass),
+        report.count(Outcome::Fail),
+        report.count(Outcome::Skip)
+    );
+    if report.count(Outcome::Fail) > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn misspelt_fields_are_named() {
+        let body = object(json!({"min_withdrawable": 1, "MaxWithdrawable": 2, "k1": "x"}));
+        let violations = field_violations(
+            &body,
+            &["k1", "minWithdrawable", "maxWithdrawable", "callback"],
+            &["balanceCheck"],
+        );
+        assert_eq!(
+            violations,
+            [
+                "`min_withdrawable` should be `minWithdrawable`",
+                "`MaxWithdrawable` should be `maxWithdrawable`",
+                "`callback` is missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_optional_fields_are_fine() {
+        let body = object(json!({"callback": "https://x"}));
+        assert!(field_violations(&body, &["callback"], &["balanceCheck"]).is_empty());
+        let body = object(json!({"callback": "https://x", "balance_check": "https://y"}));
+        assert_eq!(
+            field_violations(&body, &["callback"], &["balanceCheck"]),
+            ["`balance_check` should be `balanceCheck`"]
+        );
+    }
+
+    #[test]
+    fn statuses() {
+        assert!(status_violations(&object(json!({"status": "OK"}))).is_empty());
+        assert!(status_violations(&object(json!({"status": "ERROR", "reason": "no"}))).is_empty());
+        assert_eq!(
+            status_violations(&object(json!({"status": "ok"}))),
+            ["status `ok` should be upper case"]
+        );
+        assert_eq!(
+            status_violations(&object(json!({"status": "ERROR"}))),
+            ["an ERROR without a `reason`"]
+        );
+        assert_eq!(
+            status_violations(&object(json!({"reason": "no"}))),
+            ["`status` is missing"]
+        );
+        assert_eq!(
+            status_violations(&object(json!({"Status": "OK"}))),
+            ["`Status` should be `status`"]
+        );
+        assert_eq!(
+            status_violations(&object(json!({"status": 200}))),
+            ["status 200 is neither \"OK\" nor \"ERROR\""]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+// =============================================================================
+// BOLT-11 invoices, offline
+// =============================================================================
+//
+// The wallets decode invoices through the node (decodepay, DecodePayReq),
+// which is what the flows trust. `decode` reads one without a node, for
+// looking at an invoice before doing anything with it: the fields are
+// checked against the bech32 checksum and the payee is recovered from the
+// signature, but nothing says the invoice is still payable. The standalone
+// wallet, having no node, decodes its invoices with it (wallet/standalone.rs).
+
+use anyhow::Result;
+use bech32::primitives::decode::CheckedHrpstring;
+use bech32::{Bech32, Fe32};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, Secp256k1};
+use sha2::{Digest, Sha256};
+
+use crate::error::usage_error;
+
+/// BOLT-11 defaults for fields an invoice leaves out
+const DEFAULT_EXPIRY_SECS: u64 = 3600;
+const DEFAULT_MIN_FINAL_CLTV: u64 = 18;
+
+/// Timestamp (35 bits) and recoverable signature (520 bits), in 5-bit groups
+const TIMESTAMP_LEN: usize = 7;
+const SIGNATURE_LEN: usize = 104;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bolt11 {
+    pub network: &'static str,
+    pub amount_msat: Option<u64>, // None: any amount
+    pub timestamp: u64,           // unix seconds
+    pub expiry_secs: u64,
+    pub payment_hash: [u8; 32],
+    pub payment_secret: Option<[u8; 32]>,
+    pub description: Option<String>,
+    pub description_hash: Option<[u8; 32]>,
+    pub payee: String, // hex node id, from the n field or the signature
+    pub min_final_cltv: u64,
+    pub route_hints: usize, // hops over unannounced channels offered
+}
+
+impl Bolt11 {
+    /// Unix time past which the payee no longer accepts a payment
+    pub fn expires_at(&self) -> u64 {
+        self.timestamp.saturating_add(self.expiry_secs)
+    }
+}
+
+fn network(currency: &str) -> Option<&'static str> {
+    match currency {
+        "bc" => Some("bitcoin"),
+        "tb" => Some("testnet"),
+        "tbs" => Some("signet"),
+        "bcrt" => Some("regtest"),
+        "sb" => Some("simnet"),
+        _ => None,
+    }
+}
+
+/// The amount part of the human readable part: a number of bitcoin, with a
+/// milli/micro/nano/pico multiplier
+fn parse_amount(amount: &str) -> Option<u64> {
+    let (digits, msat_per_unit) = match amount.chars().last()? {
+        'm' => (&amount[..amount.len() - 1], 100_000_000),
+        'u' => (&amount[..amount.len() - 1], 100_000),
+        'n' => (&amount[..amount.len() - 1], 100),
+        'p' => {
+            // Tenths of a msat, which have to add up to whole ones
+            let pico: u64 = amount[..amount.len() - 1].parse().ok()?;
+            return pico.is_multiple_of(10).then_some(pico / 10);
+        }
+        _ => (amount, 100_000_000_000),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<u64>().ok()?.checked_mul(msat_per_unit)
+}
+
+fn to_int(groups: &[u8]) -> u64 {
+    groups
+        .iter()
+        .fold(0u64, |n, group| (n << 5) | u64::from(*group))
+}
+
+/// Packs 5-bit groups into bytes, zero-padding the last one
+fn to_bytes(groups: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(groups.len() * 5 / 8 + 1);
+    let (mut acc, mut bits) = (0u32, 0);
+    for group in groups {
+        acc = (acc << 5) | u32::from(*group);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if bits > 0 {
+        bytes.push((acc << (8 - bits)) as u8);
+    }
+    bytes
+}
+
+/// A field holding exactly 32 bytes (52 groups); others are to be skipped
+fn array32(groups: &[u8]) -> Option<[u8; 32]> {
+    match groups.len() {
+        52 => to_bytes(groups)[..32].try_into().ok(),
+        _ => None,
+    }
+}
+
+/// Decodes `invoice` (either case, optionally prefixed with lightning:)
+pub fn decode(invoice: &str) -> Result<Bolt11> {
+    let invoice = invoice.trim();
+    let invoice = match invoice.get(..10) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("lightning:") => &invoice[10..],
+        _ => invoice,
+    };
+    let checked = CheckedHrpstring::new::<Bech32>(invoice)
+        .map_err(|e| usage_error!("Invalid BOLT-11 invoice: {}", e))?;
+
+    let hrp = checked.hrp().to_lowercase();
+    let Some(rest) = hrp.strip_prefix("ln") else {
+        return Err(usage_error!(
+            "Not a BOLT-11 invoice: {} does not start with ln",
+            hrp
+        ));
+    };
+    let split = rest
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (currency, amount) = rest.split_at(split);
+    let network = network(currency)
+        .ok_or_else(|| usage_error!("Unknown BOLT-11 currency prefix: {}", currency))?;
+    let amount_msat = match amount {
+        "" => None,
+        amount => Some(
+            parse_amount(amount)
+                .ok_or_else(|| usage_error!("Invalid BOLT-11 amount: {}", amount))?,
+        ),
+    };
+
+    let data: Vec<u8> = checked
+        .data_part_ascii_no_checksum()
+        .iter()
+        .map(|c| Fe32::from_char(char::from(*c)).map(Fe32::to_u8))
+        .collect::<Result<_, _>>()
+        .map_err(|e| usage_error!("Invalid BOLT-11 invoice: {}", e))?;
+    if data.len() < TIMESTAMP_LEN + SIGNATURE_LEN {
+        return Err(usage_error!("BOLT-11 invoice too short"));
+    }
+    let (signed, signature) = data.split_at(data.len() - SIGNATURE_LEN);
+
+    let mut decoded = Bolt11 {
+        network,
+        amount_msat,
+        timestamp: to_int(&signed[..TIMESTAMP_LEN]),
+        expiry_secs: DEFAULT_EXPIRY_SECS,
+        payment_hash: [0; 32],
+        payment_secret: None,
+        description: None,
+        description_hash: None,
+        payee: String::new(),
+        min_final_cltv: DEFAULT_MIN_FINAL_CLTV,
+        route_hints: 0,
+    };
+    let mut payment_hash = None;
+    let mut fields = &signed[TIMESTAMP_LEN..];
+    while !fields.is_empty() {
+        if fields.len() < 3 {
+            return Err(usage_error!("Truncated BOLT-11 field"));
+        }
+        let (tag, len) = (fields[0], to_int(&fields[1..3]) as usize);
+        let value = fields
+            .get(3..3 + len)
+            .ok_or_else(|| usage_error!("Truncated BOLT-11 field"))?;
+        fields = &fields[3 + len..];
+        // Readers skip fields they don't know, and known ones of a bad length
+        match Fe32::try_from(tag).map(Fe32::to_char).unwrap_or('?') {
+            'p' if payment_hash.is_none() => payment_hash = array32(value),
+            's' => decoded.payment_secret = array32(value),
+            'h' => decoded.description_hash = array32(value),
+            'd' => {
+                decoded.description = Some(
+                    String::from_utf8(to_bytes(value)[..len * 5 / 8].to_vec())
+                        .map_err(|_| usage_error!("BOLT-11 description is not UTF-8"))?,
+                )
+            }
+            'x' => decoded.expiry_secs = to_int(value),
+            'c' => decoded.min_final_cltv = to_int(value),
+            'n' if len == 53 => decoded.payee = hex::encode(&to_bytes(value)[..33]),
+            // pubkey, short_channel_id, fee base, fee ppm, cltv delta: 51 bytes
+            'r' => decoded.route_hints += len * 5 / 8 / 51,
+            _ => {}
+        }
+    }
+    decoded.payment_hash =
+        payment_hash.ok_or_else(|| usage_error!("BOLT-11 invoice has no payment hash"))?;
+
+    // Signed: the human readable part's bytes, then the data, padded to bytes
+    let mut preimage = hrp.into_bytes();
+    preimage.extend(to_bytes(signed));
+    let digest: [u8; 32] = Sha256::digest(&preimage).into();
+    let signature = to_bytes(signature);
+    let recovered = RecoveryId::from_i32(i32::from(signature[64]))
+        .and_then(|id| RecoverableSignature::from_compact(&signature[..64], id))
+        .and_then(|signature| {
+            Secp256k1::verification_only().recover_ecdsa(&Message::from_digest(digest), &signature)
+        })
+        .map_err(|e| usage_error!("Invalid BOLT-11 signature: {}", e))?;
+    let recovered = hex::encode(recovered.serialize());
+    if decoded.payee.is_empty() {
+        decoded.payee = recovered;
+    } else if decoded.payee != recovered {
+        return Err(usage_error!("BOLT-11 invoice is not signed by its payee"));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From BOLT-11's examples: "Please make a donation of any amount using
+    // payment_hash 0001020304050607080900010203040506070809000102030405060708090102
+    // to me @03e7156ae33b0a208d0744199163177e909e80176e55d97a2f221ede0f934dd9ad"
+    const DONATION: &str = "lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyql";
+
+    #[test]
+    fn decodes_the_spec_example() {
+        let invoice = decode(DONATION).unwrap();
+        assert_eq!(invoice.network, "bitcoin");
+        assert_eq!(invoice.amount_msat, None);
+        assert_eq!(invoice.timestamp, 1496314658);
+        assert_eq!(invoice.expires_at(), 1496314658 + 3600);
+        assert_eq!(
+            hex::encode(invoice.payment_hash),
+            "0001020304050607080900010203040506070809000102030405060708090102"
+        );
+        assert_eq!(invoice.payment_secret, Some([0x11; 32]));
+        assert_eq!(
+            invoice.description.as_deref(),
+            Some("Please consider supporting this project")
+        );
+        assert_eq!(
+            invoice.payee,
+            "03e7156ae33b0a208d0744199163177e909e80176e55d97a2f221ede0f934dd9ad"
+        );
+        assert_eq!(invoice.min_final_cltv, 18);
+
+        let prefixed = format!("lightning:{}", DONATION.to_uppercase());
+        assert_eq!(decode(&prefixed).unwrap(), invoice);
+    }
+
+    #[test]
+    fn tampering_breaks_the_checksum() {
+        let tampered = DONATION.replacen("lnbc1", "lntb1", 1);
+        assert!(decode(&tampered).is_err());
+    }
+
+    #[test]
+    fn amounts_with_multipliers() {
+        assert_eq!(parse_amount("2500u"), Some(250_000_000));
+        assert_eq!(parse_amount("20m"), Some(2_000_000_000));
+        assert_eq!(parse_amount("1"), Some(100_000_000_000));
+        assert_eq!(parse_amount("10n"), Some(1_000));
+        assert_eq!(parse_amount("10p"), Some(1));
+        assert_eq!(parse_amount("11p"), None);
+        assert_eq!(parse_amount("m"), None);
+        assert_eq!(parse_amount("99999999999999999999m"), None);
+    }
+}
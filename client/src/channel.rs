@@ -0,0 +1,283 @@
+// =============================================================================
+// request-channel (LUD-02)
+// =============================================================================
+//
+// Flow:
+//   1. GET /request-channel          → { uri, callback, k1 }
+//   2. Connect to the server's node at `uri`
+//   3. GET <callback>?remoteid=<our pubkey>&k1=<k1>&private=<0|1>[&amount=<sat>]
+//   4. Optionally wait until the channel is usable
+//
+// Declining instead is the same callback with cancel=1, which releases k1.
+
+use anyhow::{anyhow, Context, Result};
+use lnurl_models::encoding;
+use lnurl_models::{ChannelRequest, LnurlParams, OpenChannelResponse};
+use secp256k1::PublicKey;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::error::lnurl_error;
+use crate::history;
+use crate::http::Http;
+use crate::target::Target;
+use crate::wallet::{ChannelProgress, NodeInfo, Wallet};
+
+const CHANNEL_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What we ask the server for in the open-channel callback
+#[derive(Debug, Default)]
+pub struct ChannelOptions {
+    pub amount_sat: Option<u64>, // None: let the server pick
+    pub private: bool,
+}
+
+/// The channel the server opened to us, as far as it told
+#[derive(Debug, Clone)]
+pub struct OpenedChannel {
+    pub peer_id: String, // the server's node, for `wait_for_channel`
+    pub txid: Option<String>,
+    pub channel_id: Option<String>,
+    pub capacity_sat: Option<u64>,
+    pub private: Option<bool>,
+}
+
+/// Step 1: GET /request-channel
+pub async fn fetch_request(http: &Http, target: &Target) -> Result<ChannelRequest> {
+    http.get_params(&target.endpoint("request-channel"), LnurlParams::into_channel_request)
+        .await
+}
+
+/// Steps 1-3. `announce_address` is the host:port our node is reachable on,
+/// detected from the node if None.
+pub async fn request_channel(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    target: &Target,
+    announce_address: Option<&str>,
+    options: &ChannelOptions,
+) -> Result<OpenedChannel> {
+    info!("Requesting channel info from {}...", target);
+
+    // Step 1: GET /request-channel, while fetching our node URI
+    let (node_uri, request) = tokio::join!(
+        node_uri(wallet, announce_address, is_local_host(target.url())),
+        fetch_request(http, target),
+    );
+
+    open_channel(wallet, http, &node_uri?, &request?, options).await
+}
+
+/// Steps 2-3 of the channel request, once the request params are known
+pub async fn open_channel(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    node_uri: &str,
+    request: &ChannelRequest,
+    options: &ChannelOptions,
+) -> Result<OpenedChannel> {
+    info!("Node URI: {}", node_uri);
+    info!(uri = %request.uri, callback = %request.callback, k1 = %request.k1, "Received channel request");
+
+    // Step 2: Connect to the server's Lightning node
+    let peer_id = connect_to_node(wallet, &request.uri).await?;
+
+    // Step 3: Call open-channel callback with just the pubkey hex
+    let (node_id, _) = encoding::parse_node_uri(node_uri)
+        .map_err(|e| lnurl_error!("Invalid own node URI {}: {}", node_uri, e))?;
+    let open_url = open_channel_url(request, &node_id.to_string(), options)?;
+    info!("Calling open-channel callback");
+
+    let open_resp: OpenChannelResponse = http.callback_json(open_url.as_str())
+        .await
+        .context("Failed to open channel")?;
+    if !open_resp.is_ok() {
+        return Err(lnurl_error!(
+            "Channel open failed: {}",
+            open_resp.reason.unwrap_or_else(|| "unknown".to_string())
+        ));
+    }
+
+    history::note(|operation| {
+        operation.amount_msat = open_resp.capacity_sat.map(|sat| sat * 1000);
+        operation.txid = open_resp.txid.clone();
+    });
+    if open_resp.private.is_some_and(|private| private != options.private) {
+        warn!("The server did not honour the requested privacy");
+    }
+    Ok(OpenedChannel {
+        peer_id: peer_id.to_string(),
+        txid: open_resp.txid,
+        channel_id: open_resp.channel_id,
+        capacity_sat: open_resp.capacity_sat,
+        private: open_resp.private,
+    })
+}
+
+/// The open-channel callback asking for a channel to `node_id`
+pub fn open_channel_url(
+    request: &ChannelRequest,
+    node_id: &str,
+    options: &ChannelOptions,
+) -> Result<Url> {
+    let mut open_url = Url::parse(&request.callback)
+        .map_err(|e| lnurl_error!("Invalid callback URL {}: {}", request.callback, e))?;
+    {
+        let mut query = open_url.query_pairs_mut();
+        query
+            .append_pair("remoteid", node_id)
+            .append_pair("k1", &request.k1)
+            .append_pair("private", if options.private { "1" } else { "0" });
+        if let Some(amount_sat) = options.amount_sat {
+            query.append_pair("amount", &amount_sat.to_string());
+        }
+    }
+    Ok(open_url)
+}
+
+/// Step 4: polls the wallet until the channel is usable, logging
+/// confirmations as blocks come in. Returns its short channel id, if the
+/// node has one for it.
+pub async fn wait_for_channel(
+    wallet: &mut dyn Wallet,
+    channel: &OpenedChannel,
+) -> Result<Option<String>> {
+    info!("Waiting for the channel to confirm (Ctrl-C to stop, it opens regardless)...");
+    let mut last_progress = String::new();
+    loop {
+        let progress = match wallet
+            .channel_progress(&channel.peer_id, channel.txid.as_deref())
+            .await?
+        {
+            None => "Channel not visible to our node yet".to_string(),
+            Some(ChannelProgress::Ready { short_channel_id }) => return Ok(short_channel_id),
+            Some(ChannelProgress::Opening { confirmations, status }) => {
+                // The node's own status line, e.g. "Funding needs 2 more confirmations"
+                match (confirmations, status) {
+                    (Some(confirmations), Some(status)) => {
+                        format!("{} confirmations: {}", confirmations, status)
+                    }
+                    (Some(confirmations), None) => format!("{} confirmations", confirmations),
+                    (None, Some(status)) => status,
+                    (None, None) => "Channel pending".to_string(),
+                }
+            }
+            Some(ChannelProgress::Closed(state)) => {
+                return Err(anyhow!("Channel went to {} before it became usable", state));
+            }
+        };
+        if progress != last_progress {
+            info!("{}", progress);
+            last_progress = progress;
+        }
+        tokio::time::sleep(CHANNEL_POLL_INTERVAL).await;
+    }
+}
+
+/// The open-channel callback declining `k1` (the request's own if None)
+pub fn cancel_url(request: &ChannelRequest, node_id: &str, k1: Option<&str>) -> Result<Url> {
+    let mut cancel_url = Url::parse(&request.callback)
+        .map_err(|e| lnurl_error!("Invalid callback URL {}: {}", request.callback, e))?;
+    cancel_url
+        .query_pairs_mut()
+        .append_pair("remoteid", node_id)
+        .append_pair("k1", k1.unwrap_or(&request.k1))
+        .append_pair("cancel", "1");
+    Ok(cancel_url)
+}
+
+/// Declines `k1` (the request's own if None) with cancel=1
+pub async fn cancel_channel(
+    http: &Http,
+    request: &ChannelRequest,
+    node_id: &str,
+    k1: Option<&str>,
+) -> Result<()> {
+    let cancel_url = cancel_url(request, node_id, k1)?;
+    info!("Calling open-channel callback with cancel=1");
+
+    let cancel_resp: OpenChannelResponse = http.callback_json(cancel_url.as_str())
+        .await
+        .context("Failed to cancel channel request")?;
+    if !cancel_resp.is_ok() {
+        return Err(lnurl_error!(
+            "Cancel failed: {}",
+            cancel_resp.reason.unwrap_or_else(|| "unknown".to_string())
+        ));
+    }
+    Ok(())
+}
+
+// =============================================================================
+// Node helpers
+// =============================================================================
+
+/// Picks the address other nodes can reach us on, in the wallet's order.
+/// Loopback only qualifies when the server is local too.
+fn pick_node_address(info: &NodeInfo, server_is_local: bool) -> Option<String> {
+    let usable = |host: &str| match IpAddr::from_str(host) {
+        Ok(ip) => !ip.is_unspecified() && (server_is_local || !ip.is_loopback()),
+        Err(_) => true, // DNS name
+    };
+
+    info.addresses
+        .iter()
+        .find(|(host, _)| usable(host))
+        .map(|(host, port)| match IpAddr::from_str(host) {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+            _ => format!("{}:{}", host, port),
+        })
+}
+
+pub fn is_local_host(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        Some(url::Host::Domain(domain)) => domain == "localhost",
+        None => false,
+    }
+}
+
+/// Returns "pubkey@host:port" URI for our own node. `announce_address`
+/// overrides detection; without any usable address only the pubkey is returned.
+pub async fn node_uri(
+    wallet: &mut dyn Wallet,
+    announce_address: Option<&str>,
+    server_is_local: bool,
+) -> Result<String> {
+    let node = wallet.node_info().await?;
+    info!("Node pubkey: {}", node.id);
+
+    let address = match announce_address {
+        Some(address) => Some(address.to_string()),
+        None => pick_node_address(&node, server_is_local),
+    };
+    match address {
+        Some(address) => Ok(format!("{}@{}", node.id, address)),
+        None => {
+            warn!("Node has no reachable address (set announce_address or --announce-address)");
+            Ok(node.id)
+        }
+    }
+}
+
+/// Connects to the node at `node_uri`, returning its pubkey
+async fn connect_to_node(wallet: &mut dyn Wallet, node_uri: &str) -> Result<PublicKey> {
+    let (pubkey, address) = encoding::parse_node_uri(node_uri)
+        .map_err(|e| lnurl_error!("Invalid node URI {}: {}", node_uri, e))?;
+    let address = address.ok_or_else(|| lnurl_error!("Node URI has no address: {}", node_uri))?;
+    let (ip_addr, port) = address
+        .rsplit_once(':')
+        .and_then(|(ip, port)| Some((ip.parse::<Ipv4Addr>().ok()?, port.parse::<u16>().ok()?)))
+        .ok_or_else(|| lnurl_error!("Invalid node address in {}", node_uri))?;
+
+    info!("Connecting to node {}@{}:{}...", pubkey, ip_addr, port);
+    wallet
+        .connect_peer(&pubkey.to_string(), &ip_addr.to_string(), port)
+        .await?;
+    info!("Connected");
+    Ok(pubkey)
+}
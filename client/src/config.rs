@@ -0,0 +1,322 @@
+// =============================================================================
+// Configuration
+// =============================================================================
+//
+// Read from ~/.config/lnurl-client/config.toml (or $XDG_CONFIG_HOME, or
+// --config <path>), then overridden by environment variables:
+//
+//   LNURL_CLIENT_BACKEND           cln, lnd or standalone, see wallet/
+//   LNURL_CLIENT_CLN_RPC           path to the CLN lightning-rpc socket
+//   LNURL_CLIENT_LND_ADDRESS       LND gRPC endpoint, https://host:port
+//   LNURL_CLIENT_LND_TLS_CERT      path to LND's tls.cert
+//   LNURL_CLIENT_LND_MACAROON      path to an LND macaroon (admin.macaroon)
+//   LNURL_CLIENT_SEED              path to the LNURL-auth seed (see keys.rs)
+//   LNURL_CLIENT_ANNOUNCE_ADDRESS  host:port other nodes reach us on
+//   LNURL_CLIENT_NETWORK           bitcoin, testnet4, signet, regtest, ...
+//   LNURL_CLIENT_HTTP_TIMEOUT      seconds
+//   LNURL_CLIENT_HTTP_RETRIES      retries of transient HTTP failures
+//   LNURL_CLIENT_USER_AGENT
+//   LNURL_CLIENT_PROXY             socks5h://host:port
+//   LNURL_CLIENT_CACERT            extra PEM CA bundle to trust
+//   LNURL_CLIENT_NOSTR_KEY         Nostr secret key for zaps, nsec1... or hex
+//
+// Example config.toml:
+//
+//   network = "testnet4"
+//   announce_address = "192.168.27.72:49735"  # default: detected from getinfo
+//   backend = "lnd"                           # default: cln, or standalone
+//
+//   [lnd]
+//   address = "https://127.0.0.1:10009"
+//   tls_cert = "/home/me/.lnd/tls.cert"
+//   macaroon = "/home/me/.lnd/data/chain/bitcoin/testnet4/admin.macaroon"
+//
+//   [http]
+//   timeout_secs = 30
+//   retries = 2
+//   proxy = "socks5h://127.0.0.1:9050"        # Tor
+//   cacert = "/etc/lnurl/dev-ca.pem"
+//
+//   [nostr]
+//   secret_key = "nsec1..."                   # for zaps, keep the file private
+//   relays = ["wss://relay.damus.io", "wss://nos.lol"]
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_NETWORK: &str = "testnet4";
+const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_HTTP_RETRIES: u32 = 2;
+const DEFAULT_LND_ADDRESS: &str = "https://127.0.0.1:10009";
+
+/// Which Lightning node the client drives, see wallet/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Cln,
+    Lnd,
+    /// No node: own keys, invoices from elsewhere
+    Standalone,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub backend: Backend,
+    /// Defaults to ~/.lightning/<network>/lightning-rpc
+    pub cln_rpc_path: Option<PathBuf>,
+    pub lnd: LndConfig,
+    /// LNURL-auth seed, defaults to `seed` next to the config file
+    pub seed_path: Option<PathBuf>,
+    /// host:port other nodes reach us on, overrides detection from getinfo
+    pub announce_address: Option<String>,
+    pub network: String,
+    pub http: HttpConfig,
+    pub nostr: NostrConfig,
+    /// Stop short of every callback; only from --dry-run, never the file
+    #[serde(skip)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LndConfig {
+    pub address: String,
+    /// Defaults to ~/.lnd/tls.cert
+    pub tls_cert: Option<PathBuf>,
+    /// Defaults to ~/.lnd/data/chain/bitcoin/<network>/admin.macaroon
+    pub macaroon: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub timeout_secs: u64,
+    /// Extra attempts after a transient failure, see http.rs
+    pub retries: u32,
+    pub user_agent: String,
+    /// socks5h:// (DNS through the proxy) or socks5://, see http.rs
+    pub proxy: Option<String>,
+    /// PEM CA certificate(s) trusted on top of the built-in roots
+    pub cacert: Option<PathBuf>,
+    /// Skip certificate verification; only from --insecure, never the file
+    #[serde(skip)]
+    pub insecure: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NostrConfig {
+    /// nsec1... or hex, signs zap requests
+    pub secret_key: Option<String>,
+    /// Where zap receipts go, unless `zap --relay` says otherwise
+    pub relays: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backend: Backend::default(),
+            cln_rpc_path: None,
+            lnd: LndConfig::default(),
+            seed_path: None,
+            announce_address: None,
+            network: DEFAULT_NETWORK.to_string(),
+            http: HttpConfig::default(),
+            nostr: NostrConfig::default(),
+            dry_run: false,
+        }
+    }
+}
+
+impl Default for LndConfig {
+    fn default() -> Self {
+        LndConfig {
+            address: DEFAULT_LND_ADDRESS.to_string(),
+            tls_cert: None,
+            macaroon: None,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout_secs: DEFAULT_HTTP_TIMEOUT_SECS,
+            retries: DEFAULT_HTTP_RETRIES,
+            user_agent: concat!("lnurl-client/", env!("CARGO_PKG_VERSION")).to_string(),
+            proxy: None,
+            cacert: None,
+            insecure: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads `path` (which must exist) or the default location (which may not),
+    /// then applies env overrides
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None => match default_path() {
+                Some(path) if path.exists() => Config::from_file(&path)?,
+                _ => Config::default(),
+            },
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&raw).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(v) = std::env::var("LNURL_CLIENT_BACKEND") {
+            self.backend = clap::ValueEnum::from_str(&v, true)
+                .map_err(|_| anyhow!("LNURL_CLIENT_BACKEND must be cln, lnd or standalone"))?;
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_CLN_RPC") {
+            self.cln_rpc_path = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_LND_ADDRESS") {
+            self.lnd.address = v;
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_LND_TLS_CERT") {
+            self.lnd.tls_cert = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_LND_MACAROON") {
+            self.lnd.macaroon = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_SEED") {
+            self.seed_path = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_ANNOUNCE_ADDRESS") {
+            self.announce_address = Some(v);
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_NETWORK") {
+            self.network = v;
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_HTTP_TIMEOUT") {
+            self.http.timeout_secs = v
+                .parse()
+                .map_err(|_| anyhow!("LNURL_CLIENT_HTTP_TIMEOUT must be a number of seconds"))?;
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_HTTP_RETRIES") {
+            self.http.retries = v
+                .parse()
+                .map_err(|_| anyhow!("LNURL_CLIENT_HTTP_RETRIES must be a number"))?;
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_USER_AGENT") {
+            self.http.user_agent = v;
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_PROXY") {
+            self.http.proxy = Some(v);
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_CACERT") {
+            self.http.cacert = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var("LNURL_CLIENT_NOSTR_KEY") {
+            self.nostr.secret_key = Some(v);
+        }
+        Ok(())
+    }
+
+    pub fn seed_path(&self) -> Result<PathBuf> {
+        match &self.seed_path {
+            Some(path) => Ok(path.clone()),
+            None => config_dir()
+                .map(|dir| dir.join("seed"))
+                .ok_or_else(|| anyhow!("HOME not set, configure seed_path explicitly")),
+        }
+    }
+
+    pub fn cln_rpc_path(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.cln_rpc_path {
+            return Ok(path.clone());
+        }
+        let home = std::env::var("HOME")
+            .map_err(|_| anyhow!("HOME not set, configure cln_rpc_path explicitly"))?;
+        Ok(PathBuf::from(home)
+            .join(".lightning")
+            .join(&self.network)
+            .join("lightning-rpc"))
+    }
+
+    pub fn lnd_tls_cert_path(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.lnd.tls_cert {
+            return Ok(path.clone());
+        }
+        Ok(lnd_dir()?.join("tls.cert"))
+    }
+
+    pub fn lnd_macaroon_path(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.lnd.macaroon {
+            return Ok(path.clone());
+        }
+        // LND calls the main chain mainnet where CLN says bitcoin
+        let network = match self.network.as_str() {
+            "bitcoin" => "mainnet",
+            other => other,
+        };
+        Ok(lnd_dir()?
+            .join("data")
+            .join("chain")
+            .join("bitcoin")
+            .join(network)
+            .join("admin.macaroon"))
+    }
+}
+
+fn lnd_dir() -> Result<PathBuf> {
+    let home =
+        std::env::var("HOME").map_err(|_| anyhow!("HOME not set, configure the [lnd] paths"))?;
+    Ok(PathBuf::from(home).join(".lnd"))
+}
+
+/// $XDG_CONFIG_HOME/lnurl-client, falling back to ~/.config
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("lnurl-client"))
+}
+
+/// sessions.json in `config_dir()`, see sessions.rs
+pub fn sessions_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("sessions.json"))
+}
+
+/// history.sqlite in `config_dir()`, see history.rs
+pub fn history_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("history.sqlite"))
+}
+
+/// config.toml in `config_dir()`
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// Creates or replaces `path`, readable by us only (seed, session tokens)
+#[cfg(unix)]
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, contents)
+}
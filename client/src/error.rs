@@ -0,0 +1,113 @@
+// =============================================================================
+// Errors and exit codes
+// =============================================================================
+//
+// Every failure maps to an exit code scripts can branch on:
+//
+//   0  success
+//   1  anything not classified below
+//   2  usage error: bad arguments or config, or a value the server's bounds
+//      rule out (clap uses 2 for its own errors too)
+//   3  network error: the LNURL server is unreachable, timed out, TLS failed
+//   4  LNURL error: the server rejected the request (bad k1, ERROR status,
+//      non-2xx) or sent something that doesn't follow the spec
+//   5  backend error: CLN unreachable or an RPC call failed
+//   6  payment failure: our payment failed, or the incoming one never came
+//
+// Errors carry their class as a `ClientError` somewhere in the anyhow chain.
+// HTTP and CLN RPC errors need no wrapping, they are recognised by type.
+
+use std::fmt;
+
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NETWORK: i32 = 3;
+pub const EXIT_LNURL: i32 = 4;
+pub const EXIT_BACKEND: i32 = 5;
+pub const EXIT_PAYMENT: i32 = 6;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Usage(String),
+    Lnurl(String),
+    Backend(String),
+    Payment(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Usage(message)
+            | ClientError::Lnurl(message)
+            | ClientError::Backend(message)
+            | ClientError::Payment(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    // Looks through any context added on the way up
+    if let Some(error) = error.downcast_ref::<ClientError>() {
+        return match error {
+            ClientError::Usage(_) => EXIT_USAGE,
+            ClientError::Lnurl(_) => EXIT_LNURL,
+            ClientError::Backend(_) => EXIT_BACKEND,
+            ClientError::Payment(_) => EXIT_PAYMENT,
+        };
+    }
+
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            // A body that isn't what we asked for is the server's fault
+            return if e.is_decode() {
+                EXIT_LNURL
+            } else {
+                EXIT_NETWORK
+            };
+        }
+        if cause.is::<serde_json::Error>() {
+            return EXIT_LNURL;
+        }
+        if cause.is::<cln_rpc::RpcError>()
+            || cause.is::<tonic::Status>()
+            || cause.is::<tonic::transport::Error>()
+        {
+            return EXIT_BACKEND;
+        }
+    }
+    EXIT_OTHER
+}
+
+// Like anyhow!, but classified
+
+#[macro_export]
+macro_rules! usage_error {
+    ($($arg:tt)*) => {
+        anyhow::Error::new($crate::error::ClientError::Usage(format!($($arg)*)))
+    };
+}
+
+#[macro_export]
+macro_rules! lnurl_error {
+    ($($arg:tt)*) => {
+        anyhow::Error::new($crate::error::ClientError::Lnurl(format!($($arg)*)))
+    };
+}
+
+#[macro_export]
+macro_rules! backend_error {
+    ($($arg:tt)*) => {
+        anyhow::Error::new($crate::error::ClientError::Backend(format!($($arg)*)))
+    };
+}
+
+#[macro_export]
+macro_rules! payment_error {
+    ($($arg:tt)*) => {
+        anyhow::Error::new($crate::error::ClientError::Payment(format!($($arg)*)))
+    };
+}
+
+pub use crate::{backend_error, lnurl_error, payment_error, usage_error};
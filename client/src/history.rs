@@ -0,0 +1,292 @@
+// =============================================================================
+// Operation history
+// =============================================================================
+//
+// Every flow the client runs is recorded in history.sqlite next to the config
+// file: when it ran, against which URL and how it ended, plus what the flow
+// learnt on the way (amount, invoice, preimage, funding txid). Flows add
+// those with `note` as they go, main writes the operation out once it is
+// over. Flows run side by side (batch-withdraw) each get their own operation
+// with `scope`. `lnurl-client history` lists them, `history show <id>` prints one.
+//
+// The balanceCheck links withdraw requests come with (LUD-15) are kept here
+// too, for `lnurl-client balance`.
+//
+// Preimages prove payment, so the file is readable by us only. The history
+// is a convenience: failing to write it only logs a warning.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::cell::RefCell;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS operations (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    flow        TEXT NOT NULL,
+    url         TEXT NOT NULL,
+    started_at  INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL,
+    outcome     TEXT NOT NULL,
+    error       TEXT,
+    amount_msat INTEGER,
+    invoice     TEXT,
+    preimage    TEXT,
+    txid        TEXT
+);
+CREATE TABLE IF NOT EXISTS balance_links (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    url          TEXT NOT NULL UNIQUE,
+    saved_at     INTEGER NOT NULL,
+    checked_at   INTEGER,
+    balance_msat INTEGER
+);
+";
+
+const COLUMNS: &str = "id, flow, url, started_at, finished_at, outcome, error, \
+                       amount_msat, invoice, preimage, txid";
+
+/// What is known about the flow being run
+#[derive(Debug, Default, Clone)]
+pub struct Operation {
+    pub flow: String, // the subcommand, e.g. request-withdraw
+    pub url: String,
+    pub started_at: u64, // unix seconds
+    pub amount_msat: Option<u64>,
+    pub invoice: Option<String>,
+    pub preimage: Option<String>, // hex
+    pub txid: Option<String>,     // channel funding tx
+}
+
+/// A recorded operation
+#[derive(Debug)]
+pub struct Entry {
+    pub id: i64,
+    pub operation: Operation,
+    pub finished_at: u64,
+    pub outcome: String, // ok or error
+    pub error: Option<String>,
+}
+
+/// A saved balanceCheck link and what it said last
+#[derive(Debug)]
+pub struct BalanceLink {
+    pub id: i64,
+    pub url: String,
+    pub saved_at: u64,
+    pub checked_at: Option<u64>,
+    pub balance_msat: Option<u64>, // maxWithdrawable
+}
+
+static CURRENT: Mutex<Option<Operation>> = Mutex::new(None);
+
+tokio::task_local! {
+    /// Takes the place of CURRENT inside `scope`
+    static SCOPED: RefCell<Operation>;
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 2024-05-01T12:00:00Z
+pub fn format_time(unix_secs: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(unix_secs)).to_string()
+}
+
+/// Starts recording a run of `flow` against `url`
+pub fn begin(flow: &str, url: &str) {
+    *CURRENT.lock().unwrap() = Some(Operation {
+        flow: flow.to_string(),
+        url: url.to_string(),
+        started_at: unix_now(),
+        ..Default::default()
+    });
+}
+
+/// Adds to the operation being recorded, if any
+pub fn note(update: impl FnOnce(&mut Operation)) {
+    let mut update = Some(update);
+    let scoped = SCOPED.try_with(|operation| {
+        if let Some(update) = update.take() {
+            update(&mut operation.borrow_mut());
+        }
+    });
+    if scoped.is_ok() {
+        return;
+    }
+    if let (Some(update), Some(operation)) = (update, CURRENT.lock().unwrap().as_mut()) {
+        update(operation);
+    }
+}
+
+/// Runs `future` recording a run of `flow` against `url` of its own, which
+/// is returned for the caller to write out
+pub async fn scope<F: Future>(flow: &str, url: &str, future: F) -> (F::Output, Operation) {
+    let operation = Operation {
+        flow: flow.to_string(),
+        url: url.to_string(),
+        started_at: unix_now(),
+        ..Default::default()
+    };
+    SCOPED
+        .scope(RefCell::new(operation), async {
+            let output = future.await;
+            (output, SCOPED.with(|operation| operation.take()))
+        })
+        .await
+}
+
+/// Stops recording, returning the operation to write out
+pub fn take() -> Option<Operation> {
+    CURRENT.lock().unwrap().take()
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<History> {
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+            }
+            // An empty file is an empty database, with our permissions
+            crate::config::write_private(path, b"")
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open history {}", path.display()))?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to set up history {}", path.display()))?;
+        Ok(History { conn })
+    }
+
+    /// Writes out a finished operation, returning its id
+    pub fn record(&self, operation: &Operation, error: Option<&anyhow::Error>) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO operations (flow, url, started_at, finished_at, outcome, error,
+                                     amount_msat, invoice, preimage, txid)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                operation.flow,
+                operation.url,
+                operation.started_at as i64,
+                unix_now() as i64,
+                if error.is_some() { "error" } else { "ok" },
+                error.map(|e| format!("{:#}", e)),
+                operation.amount_msat.map(|msat| msat as i64),
+                operation.invoice,
+                operation.preimage,
+                operation.txid,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Most recent first
+    pub fn list(&self, limit: usize) -> Result<Vec<Entry>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM operations ORDER BY id DESC LIMIT ?1",
+            COLUMNS
+        ))?;
+        let entries = statement
+            .query_map(params![limit as i64], entry_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Entry>> {
+        let entry = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM operations WHERE id = ?1", COLUMNS),
+                params![id],
+                entry_from_row,
+            )
+            .optional()?;
+        Ok(entry)
+    }
+
+    /// Returns the link's id, the existing one if it was saved before
+    pub fn save_balance_link(&self, url: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO balance_links (url, saved_at) VALUES (?1, ?2)",
+            params![url, unix_now() as i64],
+        )?;
+        let id = self.conn.query_row(
+            "SELECT id FROM balance_links WHERE url = ?1",
+            params![url],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    pub fn balance_links(&self) -> Result<Vec<BalanceLink>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, url, saved_at, checked_at, balance_msat FROM balance_links ORDER BY id",
+        )?;
+        let links = statement
+            .query_map([], balance_link_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(links)
+    }
+
+    pub fn balance_link(&self, id: i64) -> Result<Option<BalanceLink>> {
+        let link = self
+            .conn
+            .query_row(
+                "SELECT id, url, saved_at, checked_at, balance_msat FROM balance_links
+                 WHERE id = ?1",
+                params![id],
+                balance_link_from_row,
+            )
+            .optional()?;
+        Ok(link)
+    }
+
+    /// Records a check of link `id`, which may have handed out a new `url`
+    pub fn update_balance_link(&self, id: i64, url: &str, balance_msat: u64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE balance_links SET url = ?2, checked_at = ?3, balance_msat = ?4 WHERE id = ?1",
+            params![id, url, unix_now() as i64, balance_msat as i64],
+        )?;
+        Ok(())
+    }
+}
+
+fn balance_link_from_row(row: &Row) -> rusqlite::Result<BalanceLink> {
+    Ok(BalanceLink {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        saved_at: row.get::<_, i64>(2)? as u64,
+        checked_at: row.get::<_, Option<i64>>(3)?.map(|t| t as u64),
+        balance_msat: row.get::<_, Option<i64>>(4)?.map(|msat| msat as u64),
+    })
+}
+
+fn entry_from_row(row: &Row) -> rusqlite::Result<Entry> {
+    Ok(Entry {
+        id: row.get(0)?,
+        operation: Operation {
+            flow: row.get(1)?,
+            url: row.get(2)?,
+            started_at: row.get::<_, i64>(3)? as u64,
+            amount_msat: row.get::<_, Option<i64>>(7)?.map(|msat| msat as u64),
+            invoice: row.get(8)?,
+            preimage: row.get(9)?,
+            txid: row.get(10)?,
+        },
+        finished_at: row.get::<_, i64>(4)? as u64,
+        outcome: row.get(5)?,
+        error: row.get(6)?,
+    })
+}
@@ -0,0 +1,261 @@
+// =============================================================================
+// HTTP
+// =============================================================================
+//
+// One client per run so connections to the server are reused. Requests that
+// fail transiently (connection refused, timeout, 5xx, 429) are retried with
+// exponential backoff, but only when repeating them is harmless:
+//
+//   get_json       fetching LNURL params, payRequest callbacks: always safe
+//   callback_json  callbacks that consume a k1: retried only if the request
+//                  never reached the server, otherwise the retry would just
+//                  be told the k1 is already used
+//
+// Retries and the timeout come from [http] in the config, --retries and
+// --timeout.
+//
+// With a SOCKS5 proxy (--proxy, e.g. Tor on socks5h://127.0.0.1:9050) every
+// request goes through it. .onion URLs are refused unless the proxy also
+// resolves names (socks5h), so they never hit the local resolver or leave
+// over the clearnet.
+//
+// Certificates are verified against the built-in roots plus --cacert, so a
+// dev server's self-signed CA can be trusted without turning verification
+// off. --insecure does turn it off, loudly.
+//
+// Saved auth sessions (sessions.rs) are attached as bearer tokens to the
+// origin they belong to.
+
+use anyhow::{Context, Result};
+use lnurl_models::{LnurlParams, WrongTag};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::config::HttpConfig;
+use crate::error::{lnurl_error, usage_error};
+use crate::sessions::Sessions;
+
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(8);
+
+pub struct Http {
+    client: reqwest::Client,
+    retries: u32,
+    remote_dns: bool, // behind a socks5h proxy
+    sessions: Sessions,
+}
+
+/// How far a failed attempt got, which decides whether it may be repeated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Nothing reached the server
+    NotSent,
+    /// The server may have acted on the request
+    Transient,
+    /// Retrying won't help (4xx, bad JSON)
+    Permanent,
+    /// 404, which some callers take as "not supported"
+    NotFound,
+}
+
+impl Http {
+    pub fn new(config: &HttpConfig, sessions: Sessions) -> Result<Http> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(config.user_agent.as_str())
+            .timeout(Duration::from_secs(config.timeout_secs));
+
+        if let Some(path) = &config.cacert {
+            let pem = std::fs::read(path)
+                .map_err(|e| usage_error!("Failed to read {}: {}", path.display(), e))?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+                usage_error!("Invalid PEM certificate in {}: {}", path.display(), e)
+            })?;
+            if certs.is_empty() {
+                return Err(usage_error!("No certificate found in {}", path.display()));
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if config.insecure {
+            warn!("TLS certificate verification is disabled (--insecure)");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        let mut remote_dns = false;
+        if let Some(proxy) = &config.proxy {
+            let url = url::Url::parse(proxy)
+                .map_err(|e| usage_error!("Invalid proxy URL {}: {}", proxy, e))?;
+            remote_dns = match url.scheme() {
+                "socks5h" => true,
+                "socks5" => false,
+                other => {
+                    return Err(usage_error!(
+                        "Unsupported proxy scheme {}, use socks5h:// or socks5://",
+                        other
+                    ))
+                }
+            };
+            // Also stops reqwest from picking up HTTP(S)_PROXY behind our back
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy)
+                    .map_err(|e| usage_error!("Invalid proxy {}: {}", proxy, e))?,
+            );
+        }
+
+        let client = builder.build().context("Failed to build HTTP client")?;
+        Ok(Http {
+            client,
+            retries: config.retries,
+            remote_dns,
+            sessions,
+        })
+    }
+
+    /// Onion services only resolve inside Tor, so they need DNS over the proxy
+    fn check_onion(&self, url: &str) -> Result<()> {
+        let is_onion = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.ends_with(".onion")))
+            .unwrap_or(false);
+        if is_onion && !self.remote_dns {
+            return Err(usage_error!(
+                "{} is an onion service, pass --proxy socks5h://127.0.0.1:9050 (Tor)",
+                url
+            ));
+        }
+        Ok(())
+    }
+
+    /// GETs `url` and decodes the JSON body, retrying transient failures.
+    /// Non-2xx replies become an error carrying the server's `reason`, if it
+    /// sent one.
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.get_json_retrying(url, false).await.map_err(|(e, _)| e)
+    }
+
+    /// Like `get_json`, but a 404 is None rather than an error, for
+    /// endpoints a server may not have
+    pub async fn get_json_if_found<T: DeserializeOwned>(&self, url: &str) -> Result<Option<T>> {
+        match self.get_json_retrying(url, false).await {
+            Ok(value) => Ok(Some(value)),
+            Err((_, Failure::NotFound)) => Ok(None),
+            Err((e, _)) => Err(e),
+        }
+    }
+
+    /// Like `get_json`, for the first reply of a flow: `into` takes the
+    /// params of the flow we're in, other tags are the server's fault
+    pub async fn get_params<T>(
+        &self,
+        url: &str,
+        into: fn(LnurlParams) -> std::result::Result<T, WrongTag>,
+    ) -> Result<T> {
+        let params: LnurlParams = self.get_json(url).await?;
+        into(params).map_err(|e| lnurl_error!("{}", e))
+    }
+
+    /// Like `get_json`, for callbacks that consume a k1
+    pub async fn callback_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.get_json_retrying(url, true).await.map_err(|(e, _)| e)
+    }
+
+    async fn get_json_retrying<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        consumes_k1: bool,
+    ) -> std::result::Result<T, (anyhow::Error, Failure)> {
+        self.check_onion(url).map_err(|e| (e, Failure::Permanent))?;
+        let mut attempt = 0;
+        loop {
+            let (error, failure) = match self.get_json_once(url).await {
+                Ok(value) => return Ok(value),
+                Err(failed) => failed,
+            };
+            let retryable = match failure {
+                Failure::NotSent => true,
+                Failure::Transient => !consumes_k1,
+                Failure::Permanent | Failure::NotFound => false,
+            };
+            if !retryable || attempt >= self.retries {
+                return Err((error, failure));
+            }
+
+            let delay = BACKOFF_BASE
+                .saturating_mul(1 << attempt.min(16))
+                .min(BACKOFF_MAX);
+            attempt += 1;
+            warn!(
+                "{:#}, retrying in {:.1}s ({}/{})",
+                error,
+                delay.as_secs_f32(),
+                attempt,
+                self.retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn get_json_once<T: DeserializeOwned>(
+        &self,
+        url: &str,
+    ) -> std::result::Result<T, (anyhow::Error, Failure)> {
+        let mut request = self.client.get(url);
+        if let Some(token) = url::Url::parse(url)
+            .ok()
+            .and_then(|url| self.sessions.token_for(&url))
+        {
+            request = request.bearer_auth(token);
+        }
+        let request = request
+            .build()
+            .with_context(|| format!("Invalid request URL {}", url))
+            .map_err(|e| (e, Failure::Permanent))?;
+        debug!(headers = ?request.headers(), "> GET {}", url);
+
+        let response = self.client.execute(request).await.map_err(|e| {
+            let failure = if e.is_connect() {
+                Failure::NotSent
+            } else {
+                Failure::Transient
+            };
+            (
+                anyhow::Error::new(e).context(format!("Request to {} failed", url)),
+                failure,
+            )
+        })?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.map_err(|e| {
+            let error =
+                anyhow::Error::new(e).context(format!("Failed to read response from {}", url));
+            (error, Failure::Transient)
+        })?;
+        debug!(?headers, "< {} {}", status, body);
+
+        if !status.is_success() {
+            let reason = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body["reason"].as_str().map(str::to_string))
+                .unwrap_or_else(|| "no reason given".to_string());
+            let failure =
+                if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    Failure::Transient
+                } else if status == reqwest::StatusCode::NOT_FOUND {
+                    Failure::NotFound
+                } else {
+                    Failure::Permanent
+                };
+            return Err((
+                lnurl_error!("Server returned {}: {}", status, reason),
+                failure,
+            ));
+        }
+
+        serde_json::from_str(&body)
+            .with_context(|| format!("Invalid response from {}", url))
+            .map_err(|e| (e, Failure::Permanent))
+    }
+}
@@ -0,0 +1,114 @@
+// =============================================================================
+// LNURL-auth keys (LUD-05)
+// =============================================================================
+//
+// Logging in with the node's identity key would let every service link the
+// user to their node, and to each other. Instead each domain gets its own
+// linking key, derived from a local seed:
+//
+//   hashingKey           = m/138'/0
+//   derivationMaterial   = HMAC-SHA256(key = hashingKey, msg = domain)
+//   linkingKey           = m/138'/<d0>/<d1>/<d2>/<d3>
+//
+// where d0..d3 are the first 16 bytes of derivationMaterial as big-endian
+// u32s. The seed is 32 random bytes, hex in the seed file (see config.rs),
+// created on first use. Lose it and every account it logged into is gone.
+//
+// Without a node (backend = "standalone", see wallet/standalone.rs) the seed
+// also stands in for the node identity key, at m/138'/1.
+
+use anyhow::{anyhow, Context, Result};
+use bitcoin::bip32::{ChildNumber, Xpriv};
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use lnurl_models::signature;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use std::path::Path;
+
+const LUD05_PURPOSE: u32 = 138;
+
+/// A per-domain LUD-05 linking key
+pub struct LinkingKey {
+    secret: SecretKey,
+    pub public: PublicKey,
+}
+
+/// Reads the seed at `path`, or creates one there (readable by us only)
+pub fn load_or_create_seed(path: &Path) -> Result<[u8; 32]> {
+    if path.exists() {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read seed {}", path.display()))?;
+        let bytes = hex::decode(raw.trim())
+            .with_context(|| format!("Seed {} is not hex", path.display()))?;
+        return bytes
+            .try_into()
+            .map_err(|_| anyhow!("Seed {} must be 32 bytes", path.display()));
+    }
+
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| anyhow!("No randomness for the seed: {}", e))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    crate::config::write_private(path, hex::encode(seed).as_bytes())
+        .with_context(|| format!("Failed to write seed {}", path.display()))?;
+    tracing::warn!(
+        "Created a new LNURL-auth seed at {}, back it up",
+        path.display()
+    );
+    Ok(seed)
+}
+
+/// Derives the linking key for `domain` (the LNURL's host) from `seed`
+pub fn linking_key(seed: &[u8; 32], domain: &str) -> Result<LinkingKey> {
+    let secp = Secp256k1::new();
+    let master = Xpriv::new_master(bitcoin::NetworkKind::Main, seed)?;
+    let purpose = ChildNumber::from_hardened_idx(LUD05_PURPOSE)?;
+
+    let hashing_key = master.derive_priv(&secp, &[purpose, ChildNumber::from(0)])?;
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&hashing_key.private_key.secret_bytes());
+    engine.input(domain.as_bytes());
+    let material = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+
+    let mut path = vec![purpose];
+    for chunk in material[..16].chunks_exact(4) {
+        let index = u32::from_be_bytes(chunk.try_into().expect("4-byte chunk"));
+        path.push(ChildNumber::from(index));
+    }
+    let secret = master.derive_priv(&secp, &path)?.private_key;
+
+    Ok(LinkingKey {
+        secret,
+        public: secret.public_key(&secp),
+    })
+}
+
+/// The key standalone mode signs with where a node would use its identity key
+pub fn standalone_identity_key(seed: &[u8; 32]) -> Result<LinkingKey> {
+    let secp = Secp256k1::new();
+    let master = Xpriv::new_master(bitcoin::NetworkKind::Main, seed)?;
+    let path = [
+        ChildNumber::from_hardened_idx(LUD05_PURPOSE)?,
+        ChildNumber::from(1),
+    ];
+    let secret = master.derive_priv(&secp, &path)?.private_key;
+
+    Ok(LinkingKey {
+        secret,
+        public: secret.public_key(&secp),
+    })
+}
+
+impl LinkingKey {
+    /// Signs like CLN's signmessage, so servers verifying with checkmessage
+    /// (ours) accept it
+    pub fn sign_message_zbase(&self, message: &str) -> String {
+        signature::sign_message(&self.secret, message)
+    }
+
+    /// LUD-04: hex DER signature over the raw k1 bytes
+    pub fn sign_k1_der(&self, k1: &[u8; 32]) -> String {
+        let signature = Secp256k1::new().sign_ecdsa(&Message::from_digest(*k1), &self.secret);
+        hex::encode(signature.serialize_der())
+    }
+}
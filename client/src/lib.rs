@@ -0,0 +1,43 @@
+// =============================================================================
+// lnurl-client
+// =============================================================================
+//
+// The LNURL flows behind the lnurl-client CLI, for other Rust wallets to
+// embed. Each flow is an async function that takes the wallet to use
+// (wallet::Wallet: CLN, LND, standalone, or one of the embedder's own) and
+// an Http client, and returns what happened instead of printing it:
+//
+//   channel  — channel::request_channel → OpenedChannel (LUD-02)
+//   withdraw — withdraw::withdraw → WithdrawOutcome (LUD-03)
+//   auth     — auth::auth with a Signer → AuthOutcome (LUD-04/05)
+//   pay      — pay::pay → PayOutcome (LUD-06)
+//
+// Every flow also comes in pieces (fetch the request, then redeem it; build
+// the callback URL without calling it) for callers that want to look before
+// they leap, as the CLI does for --dry-run. Errors are anyhow errors carrying
+// a ClientError class, see error.rs.
+//
+// Progress is logged with `tracing`. Flows run inside a history scope note
+// what they learn on the way (history.rs), and nothing otherwise.
+
+pub mod auth;
+pub mod bolt11;
+pub mod channel;
+pub mod config;
+pub mod error;
+pub mod history;
+pub mod http;
+pub mod keys;
+pub mod nostr;
+pub mod pay;
+pub mod qr;
+pub mod sessions;
+pub mod target;
+pub mod wallet;
+pub mod withdraw;
+
+pub use auth::{auth, AuthOutcome, Signer};
+pub use channel::{request_channel, OpenedChannel};
+pub use pay::{pay, PayOutcome};
+pub use target::{parse_target, Target};
+pub use withdraw::{withdraw, WithdrawOptions, WithdrawOutcome};
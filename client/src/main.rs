@@ -1,459 +1,1512 @@
 use anyhow::{Context, Result, anyhow};
-use cln_rpc::ClnRpc;
-use secp256k1::PublicKey;
-use serde::Deserialize;
-use std::net::IpAddr;
-use std::net::Ipv4Addr;
-use std::str::FromStr;
+use clap::{ArgAction, Parser, Subcommand};
+use futures_util::stream::{self, StreamExt};
+use lnurl_client::auth::{self, AuthOutcome, NodeSigner};
+use lnurl_client::bolt11::{self, Bolt11};
+use lnurl_client::channel::{self, ChannelOptions, OpenedChannel};
+use lnurl_client::config::{self, Backend, Config};
+use lnurl_client::error::{exit_code, lnurl_error, usage_error, EXIT_USAGE};
+use lnurl_client::history::{self, History};
+use lnurl_client::http::Http;
+use lnurl_client::pay;
+use lnurl_client::sessions::Sessions;
+use lnurl_client::target::{
+    encode_lnurl, parse_amount_msat, parse_lud17, parse_pay_target, parse_target,
+    pay_link_target, withdraw_link_target, Target,
+};
+use lnurl_client::wallet::{self, InvoiceOptions, RouteHints, SentPayment, Wallet};
+use lnurl_client::withdraw::{
+    self, Confirmation, WithdrawInvoice, WithdrawOptions, WithdrawOutcome,
+    DEFAULT_INVOICE_EXPIRY_SECS, DEFAULT_WITHDRAW_WAIT_SECS,
+};
+use lnurl_client::{keys, nostr, qr};
+use lnurl_models::{ChannelRequest, LnurlParams, SuccessAction, WithdrawRequest};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
 use url::Url;
 
-// ⚠️ UPDATE THIS to match your local CLN socket path
-const CLN_RPC_PATH: &str = "/home/linoux/.lightning/testnet4/lightning-rpc";
-
 // =============================================================================
 // CLI Parsing
 // =============================================================================
 
-#[derive(Debug)]
+#[derive(Debug, Parser)]
+#[command(name = "lnurl-client", version, about = "LNURL client for a local CLN or LND node")]
+struct Cli {
+    /// Config file [default: ~/.config/lnurl-client/config.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Lightning node to use [default: cln, or backend]
+    #[arg(long, global = true, value_enum)]
+    backend: Option<Backend>,
+
+    /// Only print errors besides the outcome
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Show progress (-v), or full HTTP requests and responses (-vv)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// HTTP timeout in seconds [default: 30, or http.timeout_secs]
+    #[arg(long, global = true)]
+    timeout: Option<u64>,
+
+    /// Retries of transient HTTP failures [default: 2, or http.retries]
+    #[arg(long, global = true)]
+    retries: Option<u32>,
+
+    /// SOCKS5 proxy for all HTTP requests, e.g. socks5h://127.0.0.1:9050 for Tor
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+
+    /// Also trust the CA certificate(s) in this PEM file, e.g. a dev server's
+    #[arg(long, global = true, value_name = "PATH")]
+    cacert: Option<PathBuf>,
+
+    /// Accept any TLS certificate. For self-signed test servers only
+    #[arg(long, global = true)]
+    insecure: bool,
+
+    /// Fetch and check the LNURL, then print the callback that would be
+    /// called instead of calling it. Creates, pays and consumes nothing
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
 enum Commands {
-    RequestChannel { url: Url },
-    RequestWithdraw { url: Url },
-    Auth { url: Url },
-}
-
-fn print_usage() {
-    eprintln!("Usage:");
-    eprintln!("  lnurl-client request-channel <url|ip:port>");
-    eprintln!("  lnurl-client request-withdraw <url|ip:port>");
-    eprintln!("  lnurl-client auth <url|ip:port>");
-}
-
-fn parse_url_or_ip(input: &str) -> Result<Url> {
-    // First try parsing as a full URL
-    if let Ok(url) = Url::parse(input) {
-        return Ok(url);
-    }
-
-    // Handle IPv6 with port: [::1]:8080
-    if let Some(bracket_end) = input.find("]:") {
-        if input.starts_with('[') {
-            let ip_part = &input[1..bracket_end];
-            let port_part = &input[bracket_end + 2..];
-            if port_part.parse::<u16>().is_ok() {
-                if let Ok(ip) = IpAddr::from_str(ip_part) {
-                    let url_str = format!("http://[{}]:{}", ip, port_part);
-                    return Url::parse(&url_str)
-                        .context("Failed to convert IPv6 with port to URL");
-                }
-            }
-        }
+    /// Ask the server to open a channel to our node (LUD-02)
+    RequestChannel {
+        /// Server URL, ip[:port], or LNURL
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// host:port our node is reachable on [default: detected from getinfo]
+        #[arg(long)]
+        announce_address: Option<String>,
+        /// Channel capacity in sats [default: the server's maximum]
+        #[arg(long)]
+        amount: Option<u64>,
+        /// Ask for an unannounced channel
+        #[arg(long, conflicts_with = "public")]
+        private: bool,
+        /// Ask for an announced channel (default)
+        #[arg(long)]
+        public: bool,
+        /// Exit once the funding tx is broadcast instead of waiting until the
+        /// channel is usable
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Decline a channel request, releasing its k1 on the server (LUD-02 cancel)
+    CancelChannel {
+        /// Server URL, ip[:port], or LNURL
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// k1 from an earlier channel request [default: request a fresh one]
+        #[arg(long)]
+        k1: Option<String>,
+    },
+    /// Withdraw sats from the server into a fresh invoice (LUD-03)
+    RequestWithdraw {
+        /// Server URL, ip[:port], or LNURL
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Amount to withdraw: 21000, 21000msat or 21sat [default: --max]
+        #[arg(long, value_parser = parse_amount_msat, conflicts_with = "max")]
+        amount: Option<u64>,
+        /// Withdraw maxWithdrawable
+        #[arg(long)]
+        max: bool,
+        /// Submit this BOLT-11 invoice (e.g. from another wallet) instead of creating one
+        #[arg(long, conflicts_with_all = ["amount", "max"])]
+        pr: Option<String>,
+        /// Description of the created invoice [default: the server's defaultDescription]
+        #[arg(long, conflicts_with = "pr")]
+        description: Option<String>,
+        /// Expiry of the created invoice, in seconds
+        #[arg(long, default_value_t = DEFAULT_INVOICE_EXPIRY_SECS, conflicts_with = "pr")]
+        expiry: u64,
+        /// min_final_cltv_expiry of the created invoice, in blocks [default: the node's]
+        #[arg(long, value_name = "BLOCKS", conflicts_with = "pr")]
+        cltv: Option<u32>,
+        /// Add route hints for our unannounced channels to the created
+        /// invoice: all of them, or just the given short channel ids (CLN)
+        #[arg(long, value_name = "SCID", num_args = 0.., value_delimiter = ',', conflicts_with = "pr")]
+        expose_private_channels: Option<Vec<String>>,
+        /// Also show the created invoice as a QR code
+        #[arg(long, conflicts_with = "pr")]
+        show_qr: bool,
+        /// How long to wait for the server to pay, in seconds
+        #[arg(long, default_value_t = DEFAULT_WITHDRAW_WAIT_SECS)]
+        wait_timeout: u64,
+        /// URL the server should POST to when the balance changes (LUD-15),
+        /// e.g. a `balance --listen` on this machine
+        #[arg(long)]
+        balance_notify: Option<Url>,
+        /// Once the withdraw is paid, pay this amount into the server's
+        /// payLink (LUD-19): 21000, 21000msat or 21sat
+        #[arg(long, value_name = "AMOUNT", value_parser = parse_amount_msat)]
+        then_pay: Option<u64>,
+    },
+    /// Sweep a file of withdraw links (e.g. event vouchers) into our node, in full
+    BatchWithdraw {
+        /// One URL or LNURL per line; blank lines and # comments are skipped
+        file: PathBuf,
+        /// How many vouchers to withdraw at the same time
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        concurrency: u16,
+        /// How long to wait for each payment, in seconds
+        #[arg(long, default_value_t = DEFAULT_WITHDRAW_WAIT_SECS)]
+        wait_timeout: u64,
+        /// min_final_cltv_expiry of the created invoices, in blocks [default: the node's]
+        #[arg(long, value_name = "BLOCKS")]
+        cltv: Option<u32>,
+        /// Add route hints for our unannounced channels to the created
+        /// invoices: all of them, or just the given short channel ids (CLN)
+        #[arg(long, value_name = "SCID", num_args = 0.., value_delimiter = ',')]
+        expose_private_channels: Option<Vec<String>>,
+        /// Also write the summary to this file, as CSV
+        #[arg(long, value_name = "PATH")]
+        report: Option<PathBuf>,
+    },
+    /// Log in to the server with a per-domain linking key (LUD-04/05)
+    Auth {
+        /// Server URL, ip[:port], or LNURL
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Sign with the node's identity key instead, as before linking keys
+        #[arg(long, conflicts_with = "spec")]
+        node_key: bool,
+        /// Standard LUD-04: DER `sig` + `key` on a tag=login link, for
+        /// third-party services (implied by such links)
+        #[arg(long)]
+        spec: bool,
+    },
+    /// Forget the saved auth session for a server
+    Logout {
+        /// Server URL, ip[:port], or LNURL
+        #[arg(value_parser = parse_target, required_unless_present = "all")]
+        target: Option<Target>,
+        /// Forget every saved session
+        #[arg(long, conflicts_with = "target")]
+        all: bool,
+    },
+    /// Pay an LNURL-pay endpoint from our node (LUD-06)
+    Pay {
+        /// Server URL, ip[:port], or LNURL
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Amount to send, in millisatoshis
+        #[arg(long)]
+        amount: u64,
+        /// Comment for the recipient (LUD-12), if the server accepts one
+        #[arg(long)]
+        comment: Option<String>,
+        /// Pay the server's BOLT12 offer through the node if it advertises
+        /// one, falling back to the callback when that doesn't work
+        #[arg(long)]
+        prefer_bolt12: bool,
+        /// Once paid, withdraw this amount from the server's withdrawLink
+        /// (LUD-19): 21000, 21000msat or 21sat
+        #[arg(long, value_name = "AMOUNT", value_parser = parse_amount_msat)]
+        then_withdraw: Option<u64>,
+    },
+    /// Zap someone on Nostr through their LNURL-pay server (NIP-57)
+    Zap {
+        /// Lightning address (user@domain), server URL, or LNURL
+        #[arg(value_parser = parse_pay_target)]
+        target: Target,
+        /// Amount to send: 21000, 21000msat or 21sat
+        #[arg(long, value_parser = parse_amount_msat)]
+        amount: u64,
+        /// Relay for the zap receipt, repeatable [default: nostr.relays from the config]
+        #[arg(long = "relay", value_name = "URL")]
+        relays: Vec<String>,
+        /// Note to zap (note1... or hex id) rather than just its author
+        #[arg(long)]
+        note: Option<String>,
+        /// Recipient's Nostr key (npub1... or hex) [default: the note's author]
+        #[arg(long)]
+        pubkey: Option<String>,
+        /// Public message that goes with the zap
+        #[arg(long)]
+        comment: Option<String>,
+        /// How long to wait for the zap receipt, in seconds
+        #[arg(long, default_value_t = DEFAULT_ZAP_RECEIPT_WAIT_SECS)]
+        wait_timeout: u64,
+    },
+    /// Fetch an LNURL and run the flow matching its tag
+    Handle {
+        /// lnurl1..., lnurlw://... (LUD-17), optionally prefixed with
+        /// lightning:, or a decoded URL
+        #[arg(value_parser = parse_target, required_unless_present = "from_image")]
+        target: Option<Target>,
+        /// Read the LNURL from a QR code in this image (PNG or JPEG) instead
+        #[arg(long, conflicts_with = "target")]
+        from_image: Option<PathBuf>,
+        /// host:port our node is reachable on, for channel requests
+        #[arg(long)]
+        announce_address: Option<String>,
+    },
+    /// Show what an LNURL, lightning address or BOLT-11 invoice holds,
+    /// offline: no request is made and the node is not used
+    Decode {
+        /// lnurl1..., lnurlw://... (LUD-17), user@domain or lnbc... (optionally
+        /// prefixed with lightning:)
+        input: String,
+    },
+    /// Show an LNURL, invoice or URL (encoded as an LNURL) as a QR code
+    Qr {
+        /// lnurl1..., BOLT-11/12 string (optionally prefixed with lightning:) or URL
+        data: String,
+    },
+    /// Re-query a saved balanceCheck link (LUD-15), or list them
+    Balance {
+        /// Id from the list, or a balanceCheck URL [default: list saved links]
+        #[arg(conflicts_with = "listen")]
+        link: Option<String>,
+        /// Instead, listen on host:port for balanceNotify POSTs and print them
+        #[arg(long)]
+        listen: Option<String>,
+    },
+    /// List the flows run so far, most recent first
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+        /// How many to list
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum HistoryCommand {
+    /// Everything recorded about one of them
+    Show {
+        /// Its id, as listed by `history`
+        id: i64,
+    },
+}
+
+impl Commands {
+    /// The flow to record in the history, and where it starts
+    fn history_label(&self) -> Option<(&'static str, String)> {
+        let (flow, target) = match self {
+            Commands::RequestChannel { target, .. } => ("request-channel", target),
+            Commands::CancelChannel { target, .. } => ("cancel-channel", target),
+            Commands::RequestWithdraw { target, .. } => ("request-withdraw", target),
+            Commands::Pay { target, .. } => ("pay", target),
+            Commands::Zap { target, .. } => ("zap", target),
+            Commands::Auth { target, .. } => ("auth", target),
+            Commands::Handle {
+                target: Some(target),
+                ..
+            } => ("handle", target),
+            // Replaced by the LNURL once the QR code is read
+            Commands::Handle {
+                from_image: Some(path),
+                ..
+            } => return Some(("handle", path.display().to_string())),
+            // Each voucher is recorded as a request-withdraw of its own
+            Commands::BatchWithdraw { .. } => return None,
+            Commands::Handle { .. }
+            | Commands::Logout { .. }
+            | Commands::Decode { .. }
+            | Commands::Qr { .. }
+            | Commands::Balance { .. }
+            | Commands::History { .. } => return None,
+        };
+        Some((flow, target.url().to_string()))
     }
+}
 
-    // Handle IPv4 with port: 192.168.1.1:8080
-    if let Some(colon_pos) = input.rfind(':') {
-        let ip_part = &input[..colon_pos];
-        let port_part = &input[colon_pos + 1..];
-        if port_part.parse::<u16>().is_ok() {
-            if let Ok(ip) = IpAddr::from_str(ip_part) {
-                let url_str = format!("http://{}:{}", ip, port_part);
-                return Url::parse(&url_str)
-                    .context("Failed to convert IP:port to URL");
-            }
-        }
+fn target_from_image(path: &std::path::Path) -> Result<Target> {
+    let content = qr::decode_image(path)?;
+    info!("QR code: {}", content);
+    let target = parse_target(&content)
+        .map_err(|e| usage_error!("QR code in {} is not an LNURL: {:#}", path.display(), e))?;
+    history::note(|operation| operation.url = target.url().to_string());
+    Ok(target)
+}
+
+// =============================================================================
+// Dry run
+// =============================================================================
+
+/// What --dry-run prints instead of calling `url`. <placeholders> stand for
+/// what only the real flow would produce (invoice, node key, signature).
+fn print_planned_call(callback: &str, url: &str) {
+    println!("Dry run, would call the {}:", callback);
+    println!("  {}", url.replace("%3C", "<").replace("%3E", ">"));
+}
+
+// =============================================================================
+// request-channel (LUD-02), see channel.rs
+// =============================================================================
+
+async fn channel_request(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    announce_address: Option<&str>,
+    options: &ChannelOptions,
+    wait: bool,
+) -> Result<()> {
+    if config.dry_run {
+        info!("Requesting channel info from {}...", target);
+        let resp = channel::fetch_request(http, target).await?;
+        return plan_open_channel(&resp, options);
     }
 
-    // Plain IP with no port
-    if let Ok(ip) = IpAddr::from_str(input) {
-        let url_str = format!("http://{}", ip);
-        return Url::parse(&url_str).context("Failed to convert IP to URL");
+    let mut wallet = wallet::connect(config).await?;
+    let opened = channel::request_channel(
+        wallet.as_mut(),
+        http,
+        target,
+        announce_address.or(config.announce_address.as_deref()),
+        options,
+    )
+    .await?;
+    report_channel(wallet.as_mut(), &opened, wait).await
+}
+
+/// Prints the opened channel, then waits for it to be usable if `wait`
+async fn report_channel(wallet: &mut dyn Wallet, opened: &OpenedChannel, wait: bool) -> Result<()> {
+    println!("Channel opened successfully!");
+    if let Some(txid) = &opened.txid {
+        println!("  Transaction ID: {}", txid);
+    }
+    if let Some(channel_id) = &opened.channel_id {
+        println!("  Channel ID: {}", channel_id);
+    }
+    if let Some(capacity_sat) = opened.capacity_sat {
+        println!("  Capacity: {} sats", capacity_sat);
     }
+    if let Some(private) = opened.private {
+        println!("  Private: {}", private);
+    }
+    if !wait {
+        return Ok(());
+    }
+
+    let short_channel_id = channel::wait_for_channel(wallet, opened).await?;
+    println!("Channel is ready to use!");
+    if let Some(scid) = short_channel_id {
+        println!("  Short channel ID: {}", scid);
+    }
+    Ok(())
+}
 
-    Err(anyhow!("Invalid URL or IP address: {}", input))
+/// --dry-run of steps 2-3
+fn plan_open_channel(resp: &ChannelRequest, options: &ChannelOptions) -> Result<()> {
+    info!(uri = %resp.uri, callback = %resp.callback, k1 = %resp.k1, "Received channel request");
+    println!("Dry run, would connect to {}", resp.uri);
+    let open_url = channel::open_channel_url(resp, "<node-pubkey>", options)?;
+    print_planned_call("open-channel callback", open_url.as_str());
+    Ok(())
 }
 
-fn parse_args() -> Result<Commands> {
-    let args: Vec<String> = std::env::args().collect();
+/// Fetches the channel request only to learn the callback, then declines
+/// `k1` (or the fresh one) with cancel=1
+async fn cancel_channel(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    k1: Option<&str>,
+) -> Result<()> {
+    info!("Requesting channel info from {}...", target);
 
-    if args.len() < 2 {
-        print_usage();
-        return Err(anyhow!("No command provided"));
+    if config.dry_run {
+        let resp = channel::fetch_request(http, target).await?;
+        let cancel_url = channel::cancel_url(&resp, "<node-pubkey>", k1)?;
+        print_planned_call("open-channel callback", cancel_url.as_str());
+        return Ok(());
     }
+    let mut wallet = wallet::connect(config).await?;
+    let (node, resp) = tokio::join!(wallet.node_info(), channel::fetch_request(http, target));
+    let resp = resp?;
 
-    match args[1].as_str() {
-        "request-channel" => {
-            if args.len() < 3 {
-                return Err(anyhow!("request-channel requires a <url> argument"));
-            } else if args.len() > 3 {
-                return Err(anyhow!("request-channel does not accept additional arguments"));
-            }
-            Ok(Commands::RequestChannel {
-                url: parse_url_or_ip(&args[2])?,
-            })
+    channel::cancel_channel(http, &resp, &node?.id, k1).await?;
+    println!("Channel request {} cancelled", k1.unwrap_or(&resp.k1));
+    Ok(())
+}
+
+// =============================================================================
+// request-withdraw (LUD-03), see withdraw.rs
+// =============================================================================
+
+/// What request-withdraw does around the withdraw itself
+#[derive(Debug, Default)]
+struct WithdrawExtras {
+    show_qr: bool,              // of the created invoice
+    then_pay_msat: Option<u64>, // LUD-19, paid into the payLink afterwards
+}
+
+async fn withdraw_request(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    invoice: WithdrawInvoice,
+    options: &WithdrawOptions,
+    extras: &WithdrawExtras,
+) -> Result<()> {
+    info!("Requesting withdraw info from {}...", target);
+
+    if config.dry_run {
+        let resp = withdraw::fetch_request(http, target).await?;
+        plan_withdraw(&resp, &invoice, options)?;
+        return then_pay(config, http, resp.pay_link.as_deref(), extras.then_pay_msat).await;
+    }
+
+    // Step 1: GET /request-withdraw (while connecting to our node)
+    let (wallet, resp) = tokio::join!(
+        wallet::connect(config),
+        withdraw::fetch_request(http, target),
+    );
+    let resp = resp?;
+
+    redeem_withdraw(wallet?.as_mut(), http, &resp, invoice, options, extras.show_qr).await?;
+    then_pay(config, http, resp.pay_link.as_deref(), extras.then_pay_msat).await
+}
+
+/// LUD-19: runs the pay flow against the withdraw's payLink if --then-pay
+/// asked for it, or mentions the link otherwise. The payment is recorded in
+/// the history as a pay of its own.
+async fn then_pay(
+    config: &Config,
+    http: &Http,
+    pay_link: Option<&str>,
+    amount_msat: Option<u64>,
+) -> Result<()> {
+    let (pay_link, amount_msat) = match (pay_link, amount_msat) {
+        (Some(pay_link), Some(amount_msat)) => (pay_link, amount_msat),
+        (Some(pay_link), None) => {
+            info!("The server offers a payLink to pay back into: {}", pay_link);
+            return Ok(());
         }
-        "request-withdraw" => {
-            if args.len() < 3 {
-                return Err(anyhow!("request-withdraw requires a <url> argument"));
-            } else if args.len() > 3 {
-                return Err(anyhow!("request-withdraw does not accept additional arguments"));
-            }
-            Ok(Commands::RequestWithdraw {
-                url: parse_url_or_ip(&args[2])?,
-            })
+        (None, Some(_)) => {
+            return Err(lnurl_error!("The withdraw request has no payLink for --then-pay"));
         }
-        "auth" => {
-            if args.len() < 3 {
-                return Err(anyhow!("auth requires a <url> argument"));
-            } else if args.len() > 3 {
-                return Err(anyhow!("auth does not accept additional arguments"));
+        (None, None) => return Ok(()),
+    };
+    let target = pay_link_target(pay_link)
+        .map_err(|e| lnurl_error!("Invalid payLink {}: {:#}", pay_link, e))?;
+    if config.dry_run {
+        return pay_request(config, http, &target, amount_msat, None, false, None).await;
+    }
+    println!("Paying {} msat into the payLink {}...", amount_msat, target);
+    let pay = pay_request(config, http, &target, amount_msat, None, false, None);
+    let (result, operation) = history::scope("pay", target.url().as_str(), pay).await;
+    record_history(&operation, result.as_ref().err());
+    result
+}
+
+/// --expose-private-channels: absent, bare (all of them) or a list of scids
+fn route_hints(expose_private_channels: Option<Vec<String>>) -> RouteHints {
+    match expose_private_channels {
+        None => RouteHints::Default,
+        Some(scids) if scids.is_empty() => RouteHints::AllPrivate,
+        Some(scids) => RouteHints::Channels(scids),
+    }
+}
+
+/// Steps 2-5 of the withdraw request, once the request params are known
+async fn redeem_withdraw(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    resp: &WithdrawRequest,
+    invoice: WithdrawInvoice,
+    options: &WithdrawOptions,
+    show_qr: bool,
+) -> Result<()> {
+    if let Some(link) = &resp.balance_check {
+        save_balance_link(link);
+    }
+
+    let pending = withdraw::submit(wallet, http, resp, invoice, options).await?;
+    if show_qr {
+        println!("{}", qr::render(&pending.bolt11.to_uppercase())?);
+    }
+    if pending.is_own_invoice() {
+        println!("Withdraw request accepted! Waiting for incoming payment...");
+    } else {
+        println!("Withdraw request accepted! Waiting for the server to pay...");
+    }
+
+    let outcome = pending.wait(wallet, http).await?;
+    report_withdraw(&outcome);
+    Ok(())
+}
+
+fn report_withdraw(outcome: &WithdrawOutcome) {
+    match &outcome.confirmation {
+        Confirmation::Received(payment) => {
+            println!("Payment received!");
+            if let Some(amount_msat) = payment.amount_msat {
+                println!("  Amount: {} msat", amount_msat);
+            }
+            if let Some(paid_at) = payment.paid_at {
+                println!("  Paid at: {}", history::format_time(paid_at));
             }
-            Ok(Commands::Auth {
-                url: parse_url_or_ip(&args[2])?,
-            })
         }
-        _ => {
-            print_usage();
-            Err(anyhow!("Unknown command: {}", args[1]))
+        Confirmation::Server => println!("The server paid the invoice."),
+        Confirmation::Unconfirmed => {
+            println!("The payment goes to the wallet that issued the invoice.")
         }
     }
+    if let Some(preimage) = &outcome.preimage {
+        println!("  Preimage: {}", hex::encode(preimage));
+    }
+    if outcome.preimage_verified {
+        println!("  Proof of payment verified: sha256(preimage) is the payment hash");
+    }
+}
+
+/// --dry-run of steps 2-4. A given invoice is passed on unchecked, checking
+/// it takes the node.
+fn plan_withdraw(
+    resp: &WithdrawRequest,
+    invoice: &WithdrawInvoice,
+    options: &WithdrawOptions,
+) -> Result<()> {
+    info!(
+        callback = %resp.callback,
+        k1 = %resp.k1,
+        min_withdrawable_msat = resp.min_withdrawable,
+        max_withdrawable_msat = resp.max_withdrawable,
+        "Received withdraw request"
+    );
+    let bolt11 = match invoice {
+        WithdrawInvoice::Create { amount_msat, .. } => {
+            let amount_msat = amount_msat.unwrap_or(resp.max_withdrawable);
+            withdraw::check_bounds(amount_msat, resp)?;
+            format!("<invoice-{}-msat>", amount_msat)
+        }
+        WithdrawInvoice::Existing(bolt11) => bolt11.clone(),
+    };
+    let callback_url = withdraw::callback_url(resp, &bolt11, options)?;
+    print_planned_call("withdraw callback", callback_url.as_str());
+    Ok(())
 }
 
 // =============================================================================
-// CLN Helpers
+// batch-withdraw
 // =============================================================================
+//
+// Sweeps a file of withdraw links, one per line, into fresh invoices of our
+// node, each for its maxWithdrawable. A voucher that fails doesn't stop the
+// others. Each is recorded in the history as a request-withdraw of its own,
+// and the batch ends with a summary of them all.
+
+/// How one voucher went
+struct VoucherResult {
+    line: usize, // in the file
+    link: String,
+    amount_msat: Option<u64>,
+    invoice: Option<String>,
+    error: Option<anyhow::Error>,
+}
 
-/// Returns "pubkey@ip:port" URI for our own node
-fn get_node_uri(ln_client: &mut ClnRpc, rt: &tokio::runtime::Runtime) -> Result<String> {
-    match rt.block_on(ln_client.call(cln_rpc::Request::Getinfo(
-        cln_rpc::model::requests::GetinfoRequest {},
-    )))? {
-        cln_rpc::model::Response::Getinfo(response) => {
-            let pubkey = response.id.to_string();
-            println!("Node pubkey: {}", pubkey);
-            // ⚠️ UPDATE this to your node's actual listening address
-            Ok(format!("{}@{}", pubkey, "192.168.27.72:49735"))
-            //Ok(format!("{}@{}", pubkey, "192.168.27.72:9735"))
+/// The links in `path`, with their line numbers
+fn read_vouchers(path: &Path) -> Result<Vec<(usize, String)>> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| usage_error!("Failed to read {}: {}", path.display(), e))?;
+    Ok(raw
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| (number, line.to_string()))
+        .collect())
+}
+
+async fn batch_withdraw(
+    config: &Config,
+    http: &Http,
+    path: &Path,
+    concurrency: usize,
+    wait_timeout: Duration,
+    invoice_options: &InvoiceOptions,
+    report: Option<&Path>,
+) -> Result<()> {
+    let vouchers = read_vouchers(path)?;
+    if vouchers.is_empty() {
+        return Err(usage_error!("No withdraw links in {}", path.display()));
+    }
+    let total = vouchers.len();
+    info!("Withdrawing {} vouchers, {} at a time...", total, concurrency);
+
+    let options = WithdrawOptions {
+        wait_timeout,
+        ..WithdrawOptions::default()
+    };
+    let done = std::cell::Cell::new(0);
+    let mut results: Vec<VoucherResult> = stream::iter(vouchers)
+        .map(|(line, link)| {
+            let (options, done) = (&options, &done);
+            async move {
+                let withdraw = async {
+                    let target = parse_target(&link)?;
+                    let invoice = WithdrawInvoice::Create {
+                        amount_msat: None,
+                        description: None,
+                        options: invoice_options.clone(),
+                    };
+                    let extras = WithdrawExtras::default();
+                    withdraw_request(config, http, &target, invoice, options, &extras).await
+                };
+                let (result, operation) = history::scope("request-withdraw", &link, withdraw).await;
+                if !config.dry_run {
+                    record_history(&operation, result.as_ref().err());
+                }
+
+                done.set(done.get() + 1);
+                match &result {
+                    Ok(()) if config.dry_run => {
+                        println!("[{}/{}] line {}: ready to withdraw", done.get(), total, line)
+                    }
+                    Ok(()) => println!(
+                        "[{}/{}] line {}: withdrew {} msat",
+                        done.get(),
+                        total,
+                        line,
+                        operation.amount_msat.unwrap_or_default()
+                    ),
+                    Err(e) => println!("[{}/{}] line {}: failed: {:#}", done.get(), total, line, e),
+                }
+                VoucherResult {
+                    line,
+                    link,
+                    amount_msat: operation.amount_msat,
+                    invoice: operation.invoice,
+                    error: result.err(),
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    results.sort_by_key(|result| result.line);
+
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    if config.dry_run {
+        println!("Dry run: {} of {} vouchers ready to withdraw", total - failed, total);
+    } else {
+        print_batch_summary(&results);
+        if let Some(report) = report {
+            write_batch_report(report, &results)?;
+            println!("Report written to {}", report.display());
         }
-        _ => Err(anyhow!("Unexpected response type from getinfo")),
     }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} vouchers failed", failed, total));
+    }
+    Ok(())
 }
 
-/// Returns just the hex pubkey of our own node
-fn get_node_pubkey(ln_client: &mut ClnRpc, rt: &tokio::runtime::Runtime) -> Result<String> {
-    match rt.block_on(ln_client.call(cln_rpc::Request::Getinfo(
-        cln_rpc::model::requests::GetinfoRequest {},
-    )))? {
-        cln_rpc::model::Response::Getinfo(response) => Ok(response.id.to_string()),
-        _ => Err(anyhow!("Unexpected response type from getinfo")),
+fn print_batch_summary(results: &[VoucherResult]) {
+    println!();
+    println!("{:>5}  {:<7}  {:>14}  LINK", "LINE", "OUTCOME", "AMOUNT (msat)");
+    for result in results {
+        println!(
+            "{:>5}  {:<7}  {:>14}  {}",
+            result.line,
+            if result.error.is_some() { "error" } else { "ok" },
+            match (&result.error, result.amount_msat) {
+                (None, Some(amount_msat)) => amount_msat.to_string(),
+                _ => "-".to_string(),
+            },
+            result.link
+        );
     }
+    let withdrawn: Vec<u64> = results
+        .iter()
+        .filter(|result| result.error.is_none())
+        .filter_map(|result| result.amount_msat)
+        .collect();
+    println!(
+        "{} of {} vouchers withdrawn, {} msat in total",
+        withdrawn.len(),
+        results.len(),
+        withdrawn.iter().sum::<u64>()
+    );
 }
 
-fn connect_to_node(
-    ln_client: &mut ClnRpc,
-    rt: &tokio::runtime::Runtime,
-    node_uri: &str,
-) -> Result<()> {
-    let parsed = node_uri.split('@').collect::<Vec<&str>>();
-    if parsed.len() != 2 {
-        return Err(anyhow!("Invalid node URI: {}", node_uri));
-    }
-    let pubkey = PublicKey::from_str(parsed[0])?;
-    let host = parsed[1];
-    let parts = host.split(':').collect::<Vec<&str>>();
-    let ip_addr: Ipv4Addr = parts[0].parse()?;
-    let port: u16 = parts[1].parse()?;
-
-    println!("Connecting to node {}@{}:{}...", pubkey, ip_addr, port);
-
-    let request = cln_rpc::model::requests::ConnectRequest {
-        id: pubkey.to_string(),
-        host: Some(ip_addr.to_string()),
-        port: Some(port),
-    };
+/// line,link,outcome,amount_msat,invoice,error
+fn write_batch_report(path: &Path, results: &[VoucherResult]) -> Result<()> {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
 
-    rt.block_on(ln_client.call(cln_rpc::Request::Connect(request)))?;
-    println!("Connected.");
-    Ok(())
+    let mut csv = String::from("line,link,outcome,amount_msat,invoice,error\n");
+    for result in results {
+        let error = result.error.as_ref().map(|e| format!("{:#}", e));
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            result.line,
+            field(&result.link),
+            if result.error.is_some() { "error" } else { "ok" },
+            result.amount_msat.map(|msat| msat.to_string()).unwrap_or_default(),
+            result.invoice.as_deref().unwrap_or_default(),
+            field(error.as_deref().unwrap_or_default()),
+        ));
+    }
+    std::fs::write(path, csv).with_context(|| format!("Failed to write {}", path.display()))
 }
 
 // =============================================================================
-// request-channel (LUD-02)
+// pay (LUD-06), see pay.rs
 // =============================================================================
 
-#[derive(Debug, Deserialize)]
-struct ChannelRequestResponse {
-    uri: String,
-    callback: String,
-    k1: String,
-}
+async fn pay_request(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    amount_msat: u64,
+    comment: Option<&str>,
+    prefer_bolt12: bool,
+    then_withdraw_msat: Option<u64>, // LUD-19, withdrawn from the withdrawLink afterwards
+) -> Result<()> {
+    info!("Requesting pay info from {}...", target);
+
+    if config.dry_run {
+        let resp = pay::fetch_request(http, target).await?;
+        let callback_url = pay::callback_url(&resp, amount_msat, comment)?;
+        match resp.bolt12.as_deref().filter(|_| prefer_bolt12) {
+            Some(offer) => {
+                println!("Dry run, would fetch an invoice from the BOLT12 offer:");
+                println!("  {}", offer);
+                print_planned_call("pay callback if that fails", callback_url.as_str());
+            }
+            None => print_planned_call("pay callback", callback_url.as_str()),
+        }
+        return then_withdraw(config, http, resp.withdraw_link.as_deref(), then_withdraw_msat).await;
+    }
 
-#[derive(Debug, Deserialize)]
-struct ChannelOpenResponse {
-    status: String,
-    reason: Option<String>,
-    txid: Option<String>,
-    channel_id: Option<String>,
+    // Step 1: GET /request-pay (while connecting to our node)
+    let (wallet, resp) = tokio::join!(
+        wallet::connect(config),
+        pay::fetch_request(http, target),
+    );
+    let (mut wallet, resp) = (wallet?, resp?);
+
+    let outcome =
+        pay::pay_request(wallet.as_mut(), http, &resp, amount_msat, comment, prefer_bolt12)
+            .await?;
+    report_payment(&outcome.payment);
+    if let Some(action) = &outcome.success_action {
+        show_success_action(action, &outcome.payment.preimage);
+    }
+    then_withdraw(config, http, resp.withdraw_link.as_deref(), then_withdraw_msat).await
 }
 
-fn channel_request(url: &Url) -> Result<()> {
-    println!("Requesting channel info from {}...", url);
+/// LUD-19: runs the withdraw flow against the pay request's withdrawLink if
+/// --then-withdraw asked for it, or mentions the link otherwise. The
+/// withdraw is recorded in the history as one of its own.
+async fn then_withdraw(
+    config: &Config,
+    http: &Http,
+    withdraw_link: Option<&str>,
+    amount_msat: Option<u64>,
+) -> Result<()> {
+    let (withdraw_link, amount_msat) = match (withdraw_link, amount_msat) {
+        (Some(withdraw_link), Some(amount_msat)) => (withdraw_link, amount_msat),
+        (Some(withdraw_link), None) => {
+            info!("The server offers a withdrawLink to take sats back out: {}", withdraw_link);
+            return Ok(());
+        }
+        (None, Some(_)) => {
+            return Err(lnurl_error!("The pay request has no withdrawLink for --then-withdraw"));
+        }
+        (None, None) => return Ok(()),
+    };
+    let target = withdraw_link_target(withdraw_link)
+        .map_err(|e| lnurl_error!("Invalid withdrawLink {}: {:#}", withdraw_link, e))?;
+    let invoice = WithdrawInvoice::Create {
+        amount_msat: Some(amount_msat),
+        description: None,
+        options: withdraw::default_invoice_options(),
+    };
+    let (options, extras) = (WithdrawOptions::default(), WithdrawExtras::default());
+    // Boxed: a withdraw may go on to pay (--then-pay), which comes back here
+    let withdraw = Box::pin(withdraw_request(config, http, &target, invoice, &options, &extras));
+    if config.dry_run {
+        return withdraw.await;
+    }
+    println!("Withdrawing {} msat from the withdrawLink {}...", amount_msat, target);
+    let (result, operation) =
+        history::scope("request-withdraw", target.url().as_str(), withdraw).await;
+    record_history(&operation, result.as_ref().err());
+    result
+}
 
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_io()
-        .build()
-        .context("Failed to create Tokio runtime")?;
-    let mut ln_client = rt.block_on(cln_rpc::ClnRpc::new(CLN_RPC_PATH))?;
+fn report_payment(paid: &SentPayment) {
+    println!("Payment sent!");
+    println!("  Preimage: {}", hex::encode(&paid.preimage));
+    println!("  Amount sent: {} msat", paid.amount_sent_msat);
+}
 
-    // Get our pubkey (truncated to just the hex, no @host:port)
-    let mut node_uri = get_node_uri(&mut ln_client, &rt)?;
-    println!("Node URI: {}", node_uri);
+fn show_success_action(action: &SuccessAction, preimage: &[u8]) {
+    println!("Message from the recipient:");
+    match action {
+        SuccessAction::Message { message } => println!("  {}", message),
+        SuccessAction::Url { description, url } => {
+            println!("  {}", description);
+            println!("  {}", url);
+        }
+        SuccessAction::Aes {
+            description,
+            ciphertext,
+            iv,
+        } => {
+            println!("  {}", description);
+            match pay::decrypt_success_action(preimage, ciphertext, iv) {
+                Ok(secret) => println!("  {}", secret),
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+    }
+}
 
-    // Step 1: GET /request-channel
-    let request_url = format!("{}/request-channel", url.as_str().trim_end_matches('/'));
-    let resp: ChannelRequestResponse = ureq::get(&request_url).call()?.into_json()?;
+// =============================================================================
+// zap (NIP-57)
+// =============================================================================
+//
+// A pay whose invoice commits to a signed Nostr event instead of the
+// metadata. Flow:
+//   1. GET the pay request, which must have allowsNostr and a nostrPubkey
+//   2. Sign a zap request (kind 9734) with our Nostr key: who is zapped (and
+//      which of their notes), how much, and the relays for the receipt
+//   3. GET <callback>?amount=<msat>&nostr=<zap request>&lnurl=<lnurl>
+//   4. Check the invoice commits to the amount and sha256(zap request), pay it
+//   5. Wait on the relays for the zap receipt (kind 9735) for our invoice,
+//      signed by nostrPubkey
+//
+// A missing receipt doesn't fail the zap: the payment went through.
 
-    println!("Received channel request:");
-    println!("  URI: {}", resp.uri);
-    println!("  Callback: {}", resp.callback);
-    println!("  k1: {}", resp.k1);
+const DEFAULT_ZAP_RECEIPT_WAIT_SECS: u64 = 60;
+const NOTE_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
 
-    // Step 2: Connect to the server's Lightning node
-    connect_to_node(&mut ln_client, &rt, &resp.uri)?;
+struct ZapOptions {
+    relays: Vec<String>,
+    note: Option<String>,
+    pubkey: Option<String>,
+    comment: Option<String>,
+    wait_timeout: Duration, // for the receipt
+}
 
-    // Step 3: Strip the @host:port part to get just the pubkey hex
-    //         secp256k1 compressed pubkey = 33 bytes = 66 hex chars
-    let _ = node_uri.split_off(secp256k1::constants::PUBLIC_KEY_SIZE * 2);
+async fn zap(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    amount_msat: u64,
+    options: &ZapOptions,
+) -> Result<()> {
+    let key = config.nostr.secret_key.as_deref().ok_or_else(|| {
+        usage_error!("Zaps are signed with a Nostr key, set nostr.secret_key or LNURL_CLIENT_NOSTR_KEY")
+    })?;
+    let key = nostr::NostrKey::parse(key)
+        .map_err(|e| usage_error!("Invalid Nostr secret key: {:#}", e))?;
+    let relays = match options.relays.is_empty() {
+        true => config.nostr.relays.clone(),
+        false => options.relays.clone(),
+    };
+    if relays.is_empty() {
+        return Err(usage_error!("No relays for the zap receipt, pass --relay or set nostr.relays"));
+    }
+    let note = match &options.note {
+        Some(note) => Some(
+            nostr::parse_event_id(note).map_err(|e| usage_error!("Invalid --note: {:#}", e))?,
+        ),
+        None => None,
+    };
+    let recipient = match (&options.pubkey, &note) {
+        (Some(pubkey), _) => {
+            nostr::parse_pubkey(pubkey).map_err(|e| usage_error!("Invalid --pubkey: {:#}", e))?
+        }
+        (None, Some(note)) => note_author(&relays, note).await?,
+        (None, None) => return Err(usage_error!("Zapping a profile needs its --pubkey")),
+    };
 
-    // Step 4: Call open-channel callback
-    let open_url = format!(
-        "{}?remoteid={}&k1={}&private=0",
-        resp.callback, node_uri, resp.k1
+    // Step 1: GET the pay request
+    info!("Requesting pay info from {}...", target);
+    let request_url = target.endpoint("request-pay");
+    let resp = pay::fetch_request(http, target).await?;
+    if !resp.allows_nostr {
+        return Err(lnurl_error!("This server does not take zaps (no allowsNostr)"));
+    }
+    let server_key = resp
+        .nostr_pubkey
+        .as_deref()
+        .ok_or_else(|| lnurl_error!("The server allows Nostr but has no nostrPubkey"))?;
+    let server_key = nostr::parse_pubkey(server_key)
+        .map_err(|e| lnurl_error!("Invalid nostrPubkey {}: {:#}", server_key, e))?;
+
+    // Step 2: Sign the zap request
+    let lnurl = encode_lnurl(&Url::parse(&request_url)?)?.to_lowercase();
+    let zap_request = nostr::zap_request(
+        &key,
+        &recipient,
+        note.as_deref(),
+        amount_msat,
+        &lnurl,
+        &relays,
+        options.comment.as_deref().unwrap_or_default(),
     );
-    println!("Open URL: {}", open_url);
+    let zap_request_json = serde_json::to_string(&zap_request)?;
+    debug!("Zap request: {}", zap_request_json);
+
+    // Step 3: GET <callback>?amount=<msat>&nostr=<zap request>&lnurl=<lnurl>
+    let mut callback_url = pay::callback_url(&resp, amount_msat, None)?;
+    callback_url
+        .query_pairs_mut()
+        .append_pair("nostr", &zap_request_json)
+        .append_pair("lnurl", &lnurl);
+    if config.dry_run {
+        print_planned_call("zap callback", callback_url.as_str());
+        return Ok(());
+    }
 
-    let open_resp = match ureq::get(&open_url).call() {
-        Ok(resp) => resp.into_json::<ChannelOpenResponse>()?,
-        Err(e) => return Err(anyhow!("Failed to open channel: {}", e)),
-    };
+    // Step 4: Check the invoice and pay it
+    let mut wallet = wallet::connect(config).await?;
+    let (cb_resp, paid) = pay::fetch_and_pay(
+        wallet.as_mut(),
+        http,
+        &callback_url,
+        amount_msat,
+        &zap_request_json,
+        "zap request",
+    )
+    .await?;
+    report_payment(&paid);
+
+    // Step 5: Wait for the receipt
+    println!("Waiting for the zap receipt...");
+    let filter = serde_json::json!({
+        "kinds": [nostr::KIND_ZAP_RECEIPT],
+        "#p": [recipient],
+        // Relays and servers' clocks differ, the receipt may be early
+        "since": zap_request.created_at.saturating_sub(600),
+    });
+    let receipt = nostr::find_event(
+        &relays,
+        filter,
+        nostr::Until::Timeout,
+        options.wait_timeout,
+        |event| event.pubkey == server_key && event.tag("bolt11") == Some(cb_resp.pr.as_str()),
+    )
+    .await?;
+    match receipt {
+        Some(receipt) => println!("Zap receipt published: {}", receipt.id),
+        None => warn!(
+            "No zap receipt on the relays within {}s (--wait-timeout)",
+            options.wait_timeout.as_secs()
+        ),
+    }
 
-    println!("Open response: {:?}", open_resp);
+    Ok(())
+}
 
-    if open_resp.status == "OK" {
-        println!("Channel opened successfully!");
-        if let Some(txid) = open_resp.txid {
-            println!("  Transaction ID: {}", txid);
-        }
-        if let Some(channel_id) = open_resp.channel_id {
-            println!("  Channel ID: {}", channel_id);
+/// Who wrote `note`, from the relays
+async fn note_author(relays: &[String], note: &str) -> Result<String> {
+    let filter = serde_json::json!({ "ids": [note] });
+    let event = nostr::find_event(
+        relays,
+        filter,
+        nostr::Until::Stored,
+        NOTE_LOOKUP_TIMEOUT,
+        |event| event.id == note,
+    )
+    .await?;
+    event
+        .map(|event| event.pubkey)
+        .ok_or_else(|| usage_error!("Note {} not found on the relays, pass its author's --pubkey", note))
+}
+
+// =============================================================================
+// lnurl-auth (LUD-04), see auth.rs
+// =============================================================================
+
+/// Loads the seed and derives the linking key for `url`'s host
+fn linking_key_for(config: &Config, url: &Url) -> Result<keys::LinkingKey> {
+    let domain = url
+        .host_str()
+        .ok_or_else(|| usage_error!("{} has no host to derive a linking key for", url))?;
+    let seed = keys::load_or_create_seed(&config.seed_path()?)?;
+    keys::linking_key(&seed, domain)
+}
+
+async fn auth(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    node_key: bool,
+    spec: bool,
+) -> Result<()> {
+    if spec || auth::is_login_link(target.url()) {
+        if node_key {
+            return Err(usage_error!(
+                "LUD-04 login links need a linking key, --node-key cannot be used"
+            ));
         }
-    } else {
-        eprintln!(
-            "Channel open failed: {}",
-            open_resp.reason.unwrap_or_else(|| "unknown".to_string())
+        return login(config, http, target.url()).await;
+    }
+
+    if config.dry_run {
+        info!("Starting LNURL-auth with {}...", target);
+        let (challenge_url, response_url) = auth::endpoints(target)?;
+        let challenge = auth::fetch_challenge(http, &challenge_url).await?;
+        let key = if node_key { "<node-pubkey>" } else { "<linking-key>" };
+        print_planned_call(
+            "auth endpoint",
+            &format!("{}?k1={}&signature=<signature>&pubkey={}", response_url, challenge.k1, key),
         );
+        return Ok(());
+    }
+
+    // Signed with the linking key for this domain (LUD-05) unless asked to
+    // use the node key
+    let outcome = if node_key {
+        let mut wallet = wallet::connect(config).await?;
+        auth::auth(http, target, &mut NodeSigner(wallet.as_mut())).await?
+    } else {
+        let mut linking_key = linking_key_for(config, target.url())?;
+        auth::auth(http, target, &mut linking_key).await?
+    };
+    report_auth(&outcome)
+}
+
+/// LUD-04 login links, signed with the linking key for the link's domain
+async fn login(config: &Config, http: &Http, url: &Url) -> Result<()> {
+    if !auth::is_login_link(url) {
+        return Err(usage_error!(
+            "--spec needs a LUD-04 login link (tag=login&k1=...), this server's own auth uses zbase signatures"
+        ));
+    }
+    if config.dry_run {
+        auth::login_k1(url)?;
+        let mut auth_url = url.clone();
+        auth_url
+            .query_pairs_mut()
+            .append_pair("sig", "<signature>")
+            .append_pair("key", "<linking-key>");
+        print_planned_call("login link", auth_url.as_str());
+        return Ok(());
     }
 
+    let mut linking_key = linking_key_for(config, url)?;
+    let outcome = auth::login(http, url, &mut linking_key).await?;
+    report_auth(&outcome)
+}
+
+/// Reports the outcome and keeps any session token for later requests
+fn report_auth(outcome: &AuthOutcome) -> Result<()> {
+    println!("Authentication successful!");
+    if let Some(event) = &outcome.event {
+        println!("  Event: {}", event);
+    }
+    if let Some(token) = &outcome.token {
+        let origin = Sessions::load(config::sessions_path())?.store(&outcome.url, token)?;
+        println!("  Session saved for {} (lnurl-client logout to forget it)", origin);
+    }
+    Ok(())
+}
+
+fn logout(target: Option<&Target>) -> Result<()> {
+    let mut sessions = Sessions::load(config::sessions_path())?;
+    match target {
+        Some(target) => {
+            if sessions.remove(target.url())? {
+                println!("Logged out of {}", target);
+            } else {
+                println!("No session saved for {}", target);
+            }
+        }
+        None => println!("Forgot {} session(s)", sessions.clear()?),
+    }
     Ok(())
 }
 
 // =============================================================================
-// request-withdraw (LUD-03)
+// handle (tag dispatch)
 // =============================================================================
 
-#[derive(Debug, Deserialize)]
-#[allow(non_snake_case)]
-struct WithdrawRequestResponse {
-    callback: String,
-    k1: String,
-    tag: String,
-    defaultDescription: Option<String>,
-    minWithdrawable: u64, // millisatoshis
-    maxWithdrawable: u64, // millisatoshis
-}
-
-#[derive(Debug, Deserialize)]
-struct WithdrawCallbackResponse {
-    status: String,
-    reason: Option<String>,
-}
-
-fn withdraw_request(url: &Url) -> Result<()> {
-    println!("Requesting withdraw info from {}...", url);
-
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_io()
-        .build()
-        .context("Failed to create Tokio runtime")?;
-    let mut ln_client = rt.block_on(cln_rpc::ClnRpc::new(CLN_RPC_PATH))?;
-
-    // Step 1: GET /request-withdraw
-    let request_url = format!("{}/request-withdraw", url.as_str().trim_end_matches('/'));
-    let resp: WithdrawRequestResponse = ureq::get(&request_url).call()?.into_json()?;
-
-    println!("Received withdraw request:");
-    println!("  Callback: {}", resp.callback);
-    println!("  k1: {}", resp.k1);
-    println!("  Tag: {}", resp.tag);
-    println!("  Min withdrawable: {} msat", resp.minWithdrawable);
-    println!("  Max withdrawable: {} msat", resp.maxWithdrawable);
-    if let Some(ref desc) = resp.defaultDescription {
-        println!("  Description: {}", desc);
-    }
-
-    // Step 2: Pick an amount (withdraw the maximum available)
-    let withdraw_amount_msat = resp.maxWithdrawable;
-    println!("\nWithdrawing {} msat...", withdraw_amount_msat);
-
-    // Step 3: Create a BOLT-11 invoice via CLN
-    let label = format!(
-        "lnurl-withdraw-{}",
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
-    );
+/// Fetches the target once and continues with the flow its `tag` names, so
+/// single-use links are not requested twice
+async fn handle(
+    config: &Config,
+    http: &Http,
+    target: &Target,
+    announce_address: Option<&str>,
+) -> Result<()> {
+    // Auth links are not fetched: the tag is in the URL itself
+    if auth::is_login_link(target.url()) {
+        return login(config, http, target.url()).await;
+    }
 
-    let description = resp.defaultDescription
-        .as_deref()
-        .unwrap_or("LNURL withdraw");
+    let url = target.url().to_string();
+    info!("Fetching {}...", url);
+    let body: serde_json::Value = http.get_json(&url).await?;
+    let tag = body["tag"].as_str().unwrap_or_default().to_string();
+    info!("LNURL tag: {}", tag);
+
+    match tag.as_str() {
+        // Our own /auth-challenge has no tag, only a k1
+        "" if body.get("k1").is_some() => return auth(config, http, target, false, false).await,
+        "" => return Err(lnurl_error!("Response has no tag, is this an LNURL endpoint?")),
+        other if !LnurlParams::TAGS.contains(&other) => {
+            return Err(lnurl_error!("Unsupported LNURL tag: {}", other))
+        }
+        _ => {}
+    }
+    let params: LnurlParams =
+        serde_json::from_value(body).with_context(|| format!("Malformed {}", tag))?;
 
-    let invoice_request = cln_rpc::model::requests::InvoiceRequest {
-        amount_msat: cln_rpc::primitives::AmountOrAny::Amount(
-            cln_rpc::primitives::Amount::from_msat(withdraw_amount_msat),
-        ),
-        label: label.clone(),
-        description: description.to_string(),
-        expiry: Some(600),
-        fallbacks: None,
-        preimage: None,
-        cltv: None,
-        deschashonly: None,
-        exposeprivatechannels: None,
+    match params {
+        LnurlParams::ChannelRequest(resp) => {
+            if config.dry_run {
+                return plan_open_channel(&resp, &ChannelOptions::default());
+            }
+            let mut wallet = wallet::connect(config).await?;
+            let node_uri = channel::node_uri(
+                wallet.as_mut(),
+                announce_address.or(config.announce_address.as_deref()),
+                channel::is_local_host(target.url()),
+            )
+            .await?;
+            let options = ChannelOptions::default();
+            let opened =
+                channel::open_channel(wallet.as_mut(), http, &node_uri, &resp, &options).await?;
+            report_channel(wallet.as_mut(), &opened, true).await
+        }
+        LnurlParams::WithdrawRequest(resp) => {
+            let (invoice, options) = (WithdrawInvoice::default(), WithdrawOptions::default());
+            if config.dry_run {
+                return plan_withdraw(&resp, &invoice, &options);
+            }
+            let mut wallet = wallet::connect(config).await?;
+            redeem_withdraw(wallet.as_mut(), http, &resp, invoice, &options, false).await
+        }
+        LnurlParams::PayRequest(_) => Err(usage_error!(
+            "This is a pay request, run `lnurl-client pay <lnurl> --amount <msat>`"
+        )),
+    }
+}
+
+// =============================================================================
+// decode
+// =============================================================================
+
+/// Prints what `input` holds, from the string alone
+fn print_decoded(input: &str) -> Result<()> {
+    let input = input.trim();
+    let unprefixed = match input.get(..10) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("lightning:") => &input[10..],
+        _ => input,
     };
+    let is_lnurl = unprefixed.get(..6).is_some_and(|p| p.eq_ignore_ascii_case("lnurl1"));
+    let lud17 = parse_lud17(unprefixed);
+    let is_ln = unprefixed.get(..2).is_some_and(|p| p.eq_ignore_ascii_case("ln"));
+    if is_ln && !is_lnurl && lud17.is_none() {
+        return print_decoded_invoice(&bolt11::decode(unprefixed)?);
+    }
 
-    let bolt11 = match rt.block_on(ln_client.call(cln_rpc::Request::Invoice(invoice_request)))? {
-        cln_rpc::Response::Invoice(inv) => {
-            println!("Created invoice: {}", inv.bolt11);
-            inv.bolt11
+    let (url, tag) = match lud17 {
+        Some(parsed) => parsed.map(|(url, tag)| (url, Some(tag.to_string())))?,
+        None => {
+            let url = match parse_pay_target(unprefixed)? {
+                Target::Endpoint(url) => url,
+                Target::Base(_) => {
+                    return Err(usage_error!(
+                        "Not an LNURL, lightning address or invoice: {}",
+                        input
+                    ))
+                }
+            };
+            // A link may carry its tag (LUD-17), login links always do (LUD-04)
+            let tag = url.query_pairs().find(|(key, _)| key == "tag");
+            let tag = match tag {
+                Some((_, tag)) => Some(tag.into_owned()),
+                None if !is_lnurl => Some("payRequest".to_string()), // user@domain
+                None => None,
+            };
+            (url, tag)
         }
-        _ => return Err(anyhow!("Unexpected response from invoice creation")),
     };
 
-    // Step 4: GET /withdraw?k1=<k1>&pr=<bolt11>
-    let callback_url = format!("{}?k1={}&pr={}", resp.callback, resp.k1, bolt11);
-    println!("Calling withdraw callback: {}", callback_url);
+    println!("URL: {}", url);
+    if let Some(host) = url.host_str() {
+        println!("Domain: {}", host);
+    }
+    match tag {
+        Some(tag) => println!("Tag: {}", tag),
+        None => println!("Tag: not in the link, the server's response has it"),
+    }
+    if let Some((_, k1)) = url.query_pairs().find(|(key, _)| key == "k1") {
+        println!("k1: {}", k1);
+    }
+    if url.scheme() == "http" && !url.host_str().is_some_and(|host| host.ends_with(".onion")) {
+        println!("Warning: plain http on a clearnet host, which LUD-01 does not allow");
+    }
+    Ok(())
+}
+
+fn print_decoded_invoice(invoice: &Bolt11) -> Result<()> {
+    println!("BOLT-11 invoice on {}", invoice.network);
+    match invoice.amount_msat {
+        Some(amount_msat) => println!("  Amount: {} msat", amount_msat),
+        None => println!("  Amount: any, chosen by the payer"),
+    }
+    if let Some(description) = &invoice.description {
+        println!("  Description: {}", description);
+    }
+    if let Some(hash) = &invoice.description_hash {
+        println!("  Description hash: {}", hex::encode(hash));
+    }
+    println!("  Payment hash: {}", hex::encode(invoice.payment_hash));
+    println!("  Payee: {}", invoice.payee);
+    println!("  Created: {}", history::format_time(invoice.timestamp));
+    let expired = match invoice.expires_at() <= history::unix_now() {
+        true => " (expired)",
+        false => "",
+    };
+    println!("  Expires: {}{}", history::format_time(invoice.expires_at()), expired);
+    println!("  min_final_cltv_expiry: {} blocks", invoice.min_final_cltv);
+    if invoice.route_hints > 0 {
+        println!("  Route hints: {} hop(s)", invoice.route_hints);
+    }
+    Ok(())
+}
 
-    let cb_resp: WithdrawCallbackResponse = ureq::get(&callback_url).call()?.into_json()?;
-    println!("Withdraw response: {:?}", cb_resp);
+// =============================================================================
+// qr
+// =============================================================================
 
-    if cb_resp.status == "OK" {
-        println!("\nWithdraw request accepted! Waiting for incoming payment...");
+/// Prints `input` as a QR code for a wallet to scan, and the exact string it
+/// encodes underneath
+fn print_qr(input: &str) -> Result<()> {
+    let input = input.trim();
+    let input = match input.get(..10) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("lightning:") => &input[10..],
+        _ => input,
+    };
 
-        // Step 5: Block until the invoice is paid
-        let wait_request = cln_rpc::model::requests::WaitinvoiceRequest { label };
-        match rt.block_on(ln_client.call(cln_rpc::Request::WaitInvoice(wait_request)))? {
-            cln_rpc::Response::WaitInvoice(inv) => {
-                println!("Payment received!");
-                println!("  Amount: {:?}", inv.amount_received_msat);
-                println!("  Paid at: {:?}", inv.paid_at);
-            }
-            _ => println!("Unexpected response while waiting for invoice"),
-        }
+    // lnurl1..., lnbc..., lno1...: bech32 already, just uppercase it
+    let is_bech32 = input.len() > 2
+        && input[..2].eq_ignore_ascii_case("ln")
+        && input.chars().all(|c| c.is_ascii_alphanumeric());
+    let payload = if is_bech32 {
+        input.to_uppercase()
     } else {
-        eprintln!(
-            "Withdraw failed: {}",
-            cb_resp.reason.unwrap_or_else(|| "unknown".to_string())
-        );
-    }
+        let url = Url::parse(input)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| usage_error!("Not an LNURL, invoice or http(s) URL: {}", input))?;
+        encode_lnurl(&url)?
+    };
 
+    println!("{}", qr::render(&payload)?);
+    println!("{}", payload);
     Ok(())
 }
 
 // =============================================================================
-// lnurl-auth (LUD-04)
+// balance (LUD-15)
 // =============================================================================
 //
-// Flow:
-//   1. GET /auth-challenge          → { k1: "<hex 32 bytes>" }
-//   2. Sign k1 using CLN signmessage
-//   3. GET /auth-response?k1=<k1>&signature=<zbase>&pubkey=<node_pubkey>
-//
-// ⚠️  The "catch": send the `zbase` field from signmessage's response,
-//     NOT the `signature` (DER-hex) field. The server uses CLN checkmessage
-//     which expects zbase format.
-
-#[derive(Debug, Deserialize)]
-struct AuthChallengeResponse {
-    k1: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct AuthResponse {
-    status: String,
-    #[serde(default)]
-    event: Option<String>,
-    #[serde(default)]
-    reason: Option<String>,
-}
-
-fn auth(url: &Url) -> Result<()> {
-    println!("Starting LNURL-auth with {}...", url);
-
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_io()
-        .build()
-        .context("Failed to create Tokio runtime")?;
-    let mut ln_client = rt.block_on(cln_rpc::ClnRpc::new(CLN_RPC_PATH))?;
-
-    // Step 1: Get our node pubkey
-    let pubkey = get_node_pubkey(&mut ln_client, &rt)?;
-    println!("Node pubkey: {}", pubkey);
-
-    // Step 2: GET /auth-challenge
-    let challenge_url = format!("{}/auth-challenge", url.as_str().trim_end_matches('/'));
-    println!("Requesting auth challenge from {}...", challenge_url);
-    let challenge: AuthChallengeResponse = ureq::get(&challenge_url).call()?.into_json()?;
-    println!("Received k1: {}", challenge.k1);
-
-    // Step 3: Sign k1 using CLN signmessage
-    let sign_request = cln_rpc::model::requests::SignmessageRequest {
-        message: challenge.k1.clone(),
+// A withdrawRequest may carry a balanceCheck URL that returns a fresh
+// withdrawRequest for the same balance later on. We keep those links and
+// re-query them on demand. The other half, balanceNotify, is a URL we hand
+// to the withdraw callback for the service to POST to whenever the balance
+// changes; `balance --listen` is a minimal receiver for it, for testing.
+
+/// Keeps a balanceCheck link for `balance`. Never fails the withdraw.
+fn save_balance_link(link: &str) {
+    if let Err(e) = Url::parse(link) {
+        warn!("Ignoring invalid balanceCheck link {}: {}", link, e);
+        return;
+    }
+    match open_history().and_then(|history| history.save_balance_link(link)) {
+        Ok(id) => println!("Balance check link saved (lnurl-client balance {})", id),
+        Err(e) => warn!("Failed to save the balanceCheck link: {:#}", e),
+    }
+}
+
+async fn balance(http: &Http, link: Option<&str>) -> Result<()> {
+    let Some(link) = link else {
+        return list_balance_links();
     };
 
-    let zbase = match rt.block_on(ln_client.call(cln_rpc::Request::SignMessage(sign_request)))? {
-        cln_rpc::Response::SignMessage(resp) => {
-            println!("Signature (hex DER): {}", resp.signature);
-            println!("Recid: {}", resp.recid);
-            println!("Zbase: {}", resp.zbase);
-            resp.zbase // ← use zbase, not resp.signature
+    // A saved link's id, or a link given directly
+    let (saved_id, url) = match link.parse::<i64>() {
+        Ok(id) => {
+            let saved = open_history()?
+                .balance_link(id)?
+                .ok_or_else(|| usage_error!("No saved balance link {}", id))?;
+            (Some(id), saved.url)
         }
-        _ => return Err(anyhow!("Unexpected response from signmessage")),
+        Err(_) => (None, parse_target(link)?.url().to_string()),
     };
 
-    // Step 4: GET /auth-response?k1=<k1>&signature=<zbase>&pubkey=<pubkey>
-    let auth_url = format!(
-        "{}/auth-response?k1={}&signature={}&pubkey={}",
-        url.as_str().trim_end_matches('/'),
-        challenge.k1,
-        zbase,
-        pubkey
+    info!("Checking balance at {}...", url);
+    let resp = http.get_params(&url, LnurlParams::into_withdraw_request).await?;
+
+    println!("Balance: {} msat withdrawable", resp.max_withdrawable);
+    println!("  Minimum: {} msat", resp.min_withdrawable);
+    if !resp.default_description.is_empty() {
+        println!("  Description: {}", resp.default_description);
+    }
+
+    // The service may hand out a new link each time
+    let next_url = resp.balance_check.as_deref().unwrap_or(&url);
+    match saved_id {
+        Some(id) => open_history()?.update_balance_link(id, next_url, resp.max_withdrawable)?,
+        None if resp.balance_check.is_some() => save_balance_link(next_url),
+        None => {}
+    }
+    Ok(())
+}
+
+fn list_balance_links() -> Result<()> {
+    let links = open_history()?.balance_links()?;
+    if links.is_empty() {
+        println!("No balance links saved yet, they come with withdraw requests that offer one");
+        return Ok(());
+    }
+    println!(
+        "{:>4}  {:<20}  {:>14}  URL",
+        "ID", "LAST CHECKED", "BALANCE (msat)"
     );
-    println!("Calling auth endpoint: {}", auth_url);
+    for link in links {
+        println!(
+            "{:>4}  {:<20}  {:>14}  {}",
+            link.id,
+            history::format_time(link.checked_at.unwrap_or(link.saved_at)),
+            link.balance_msat
+                .map(|msat| msat.to_string())
+                .unwrap_or_default(),
+            link.url
+        );
+    }
+    Ok(())
+}
 
-    let auth_resp: AuthResponse = ureq::get(&auth_url).call()?.into_json()?;
-    println!("Auth response: {:?}", auth_resp);
+/// Prints every request that reaches `addr` and answers 200, until Ctrl-C
+async fn listen_balance_notify(addr: &str) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-    if auth_resp.status == "OK" {
-        println!("\nAuthentication successful!");
-        if let Some(event) = auth_resp.event {
-            println!("  Event: {}", event);
-        }
-    } else {
-        eprintln!(
-            "Authentication failed: {}",
-            auth_resp.reason.unwrap_or_else(|| "unknown".to_string())
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| usage_error!("Cannot listen on {}: {}", addr, e))?;
+    println!(
+        "Listening for balanceNotify on {} (Ctrl-C to stop)",
+        listener.local_addr()?
+    );
+
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; 8192];
+            let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await;
+            let Ok(Ok(len)) = read else {
+                debug!("No request from {}", peer);
+                return;
+            };
+            let request = String::from_utf8_lossy(&buffer[..len]);
+            println!(
+                "{} balance notification from {}: {}",
+                history::format_time(history::unix_now()),
+                peer,
+                request.lines().next().unwrap_or_default()
+            );
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .await;
+        });
+    }
+}
+
+// =============================================================================
+// history
+// =============================================================================
+
+fn open_history() -> Result<History> {
+    let path = config::history_path()
+        .ok_or_else(|| usage_error!("No config directory to keep the history in (HOME not set)"))?;
+    History::open(&path)
+}
+
+/// Writes out a finished flow. Never fails the run it records.
+fn record_history(operation: &history::Operation, error: Option<&anyhow::Error>) {
+    match open_history().and_then(|history| history.record(operation, error)) {
+        Ok(id) => debug!("Recorded as history entry {}", id),
+        Err(e) => warn!("Failed to record history: {:#}", e),
+    }
+}
+
+fn list_history(limit: usize) -> Result<()> {
+    let entries = open_history()?.list(limit)?;
+    if entries.is_empty() {
+        println!("No history yet");
+        return Ok(());
+    }
+    println!(
+        "{:>5}  {:<20}  {:<16}  {:<7}  {:>14}  URL",
+        "ID", "STARTED", "FLOW", "OUTCOME", "AMOUNT (msat)"
+    );
+    for entry in entries {
+        println!(
+            "{:>5}  {:<20}  {:<16}  {:<7}  {:>14}  {}",
+            entry.id,
+            history::format_time(entry.operation.started_at),
+            entry.operation.flow,
+            entry.outcome,
+            entry
+                .operation
+                .amount_msat
+                .map(|msat| msat.to_string())
+                .unwrap_or_default(),
+            entry.operation.url
         );
     }
+    Ok(())
+}
 
+fn show_history_entry(id: i64) -> Result<()> {
+    let entry = open_history()?
+        .get(id)
+        .and_then(|entry| entry.ok_or_else(|| usage_error!("No history entry {}", id)))?;
+    let operation = &entry.operation;
+
+    println!("Entry {}: {} {}", entry.id, operation.flow, entry.outcome);
+    println!("  URL: {}", operation.url);
+    println!("  Started: {}", history::format_time(operation.started_at));
+    println!("  Finished: {}", history::format_time(entry.finished_at));
+    if let Some(error) = &entry.error {
+        println!("  Error: {}", error);
+    }
+    if let Some(amount_msat) = operation.amount_msat {
+        println!("  Amount: {} msat", amount_msat);
+    }
+    if let Some(invoice) = &operation.invoice {
+        println!("  Invoice: {}", invoice);
+    }
+    if let Some(preimage) = &operation.preimage {
+        println!("  Preimage: {}", preimage);
+    }
+    if let Some(txid) = &operation.txid {
+        println!("  Funding txid: {}", txid);
+    }
     Ok(())
 }
 
@@ -461,23 +1514,253 @@ fn auth(url: &Url) -> Result<()> {
 // Main
 // =============================================================================
 
-fn main() {
-    let command = match parse_args() {
-        Ok(command) => command,
+/// Logs go to stderr, leaving stdout to the outcome. Other crates (reqwest,
+/// hyper, rustls) stay silent even at -vv.
+fn init_logging(quiet: bool, verbose: u8) {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_target(false)
+                .without_time(),
+        )
+        .with(Targets::new().with_target(env!("CARGO_CRATE_NAME"), level))
+        .init();
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    init_logging(cli.quiet, cli.verbose);
+
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
         Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
+            eprintln!("Error: {:#}", e);
+            std::process::exit(EXIT_USAGE);
         }
     };
 
-    let result = match command {
-        Commands::RequestChannel { url } => channel_request(&url),
-        Commands::RequestWithdraw { url } => withdraw_request(&url),
-        Commands::Auth { url } => auth(&url),
+    if let Some(backend) = cli.backend {
+        config.backend = backend;
+    }
+    if let Some(timeout) = cli.timeout {
+        config.http.timeout_secs = timeout;
+    }
+    if let Some(retries) = cli.retries {
+        config.http.retries = retries;
+    }
+    if cli.proxy.is_some() {
+        config.http.proxy = cli.proxy;
+    }
+    if cli.cacert.is_some() {
+        config.http.cacert = cli.cacert;
+    }
+    config.http.insecure = cli.insecure;
+    config.dry_run = cli.dry_run;
+
+    let sessions = match Sessions::load(config::sessions_path()) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(EXIT_USAGE);
+        }
     };
+    let http = match Http::new(&config.http, sessions) {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    // A dry run did nothing worth remembering
+    if let Some((flow, url)) = cli.command.history_label().filter(|_| !config.dry_run) {
+        history::begin(flow, &url);
+    }
+
+    let result = match cli.command {
+        // --public is the default, clap already rejects it alongside --private
+        Commands::RequestChannel {
+            target,
+            announce_address,
+            amount,
+            private,
+            public: _,
+            no_wait,
+        } => {
+            let options = ChannelOptions {
+                amount_sat: amount,
+                private,
+            };
+            channel_request(
+                &config,
+                &http,
+                &target,
+                announce_address.as_deref(),
+                &options,
+                !no_wait,
+            )
+            .await
+        }
+        Commands::CancelChannel { target, k1 } => {
+            cancel_channel(&config, &http, &target, k1.as_deref()).await
+        }
+        // --max is the default, clap already rejects it alongside --amount/--pr
+        Commands::RequestWithdraw {
+            target,
+            amount,
+            max: _,
+            pr,
+            description,
+            expiry,
+            cltv,
+            expose_private_channels,
+            show_qr,
+            wait_timeout,
+            balance_notify,
+            then_pay,
+        } => {
+            let invoice = match pr {
+                Some(bolt11) => WithdrawInvoice::Existing(bolt11),
+                None => WithdrawInvoice::Create {
+                    amount_msat: amount,
+                    description,
+                    options: InvoiceOptions {
+                        expiry_secs: expiry,
+                        cltv,
+                        route_hints: route_hints(expose_private_channels),
+                    },
+                },
+            };
+            withdraw_request(
+                &config,
+                &http,
+                &target,
+                invoice,
+                &WithdrawOptions {
+                    wait_timeout: Duration::from_secs(wait_timeout),
+                    balance_notify,
+                },
+                &WithdrawExtras {
+                    show_qr,
+                    then_pay_msat: then_pay,
+                },
+            )
+            .await
+        }
+        Commands::BatchWithdraw {
+            file,
+            concurrency,
+            wait_timeout,
+            cltv,
+            expose_private_channels,
+            report,
+        } => {
+            batch_withdraw(
+                &config,
+                &http,
+                &file,
+                concurrency.into(),
+                Duration::from_secs(wait_timeout),
+                &InvoiceOptions {
+                    cltv,
+                    route_hints: route_hints(expose_private_channels),
+                    ..withdraw::default_invoice_options()
+                },
+                report.as_deref(),
+            )
+            .await
+        }
+        Commands::Pay {
+            target,
+            amount,
+            comment,
+            prefer_bolt12,
+            then_withdraw,
+        } => {
+            pay_request(
+                &config,
+                &http,
+                &target,
+                amount,
+                comment.as_deref(),
+                prefer_bolt12,
+                then_withdraw,
+            )
+            .await
+        }
+        Commands::Zap {
+            target,
+            amount,
+            relays,
+            note,
+            pubkey,
+            comment,
+            wait_timeout,
+        } => {
+            let options = ZapOptions {
+                relays,
+                note,
+                pubkey,
+                comment,
+                wait_timeout: Duration::from_secs(wait_timeout),
+            };
+            zap(&config, &http, &target, amount, &options).await
+        }
+        Commands::Auth {
+            target,
+            node_key,
+            spec,
+        } => auth(&config, &http, &target, node_key, spec).await,
+        Commands::Logout { target, all: _ } => logout(target.as_ref()),
+        Commands::Handle {
+            target,
+            from_image,
+            announce_address,
+        } => {
+            let target = match (target, from_image) {
+                (Some(target), _) => Ok(target),
+                (None, Some(path)) => target_from_image(&path),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            match target {
+                Ok(target) => handle(&config, &http, &target, announce_address.as_deref()).await,
+                Err(e) => Err(e),
+            }
+        }
+        Commands::Decode { input } => print_decoded(&input),
+        Commands::Qr { data } => print_qr(&data),
+        Commands::Balance {
+            link,
+            listen: Some(addr),
+        } => {
+            let _ = link; // clap rejects it alongside --listen
+            listen_balance_notify(&addr).await
+        }
+        Commands::Balance { link, listen: None } => balance(&http, link.as_deref()).await,
+        Commands::History { command, limit } => match command {
+            None => list_history(limit),
+            Some(HistoryCommand::Show { id }) => show_history_entry(id),
+        },
+    };
+
+    if let Some(operation) = history::take() {
+        record_history(&operation, result.as_ref().err());
+    }
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+        eprintln!("Error: {:#}", e);
+        std::process::exit(exit_code(&e));
     }
 }
@@ -0,0 +1,170 @@
+// =============================================================================
+// Nostr (NIP-01 events, NIP-57 zaps)
+// =============================================================================
+//
+// Just what `zap` needs: our key from the config, signed events, and relay
+// subscriptions that wait for one event. Relays are spoken to directly over
+// websockets, not through --proxy.
+//
+// Events, and how they are signed, are lnurl-models' (models/src/nostr.rs),
+// shared with the server's notifications.
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::{future, SinkExt, StreamExt};
+use lnurl_models::encoding;
+use secp256k1::{Keypair, Secp256k1};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message as Frame;
+use tracing::{debug, warn};
+
+pub use lnurl_models::nostr::Event;
+
+pub const KIND_ZAP_REQUEST: u32 = 9734;
+pub const KIND_ZAP_RECEIPT: u32 = 9735;
+
+/// Our Nostr identity, from `nostr.secret_key` in the config
+pub struct NostrKey {
+    keypair: Keypair,
+}
+
+impl NostrKey {
+    /// Accepts nsec1... or 64 hex chars
+    pub fn parse(secret: &str) -> Result<NostrKey> {
+        let bytes = decode_key(secret.trim(), "nsec")?;
+        let keypair = Keypair::from_seckey_slice(&Secp256k1::new(), &bytes)
+            .map_err(|_| anyhow!("Not a valid Nostr secret key"))?;
+        Ok(NostrKey { keypair })
+    }
+
+    /// Hex x-only pubkey, as events carry it
+    pub fn public_hex(&self) -> String {
+        self.keypair.x_only_public_key().0.to_string()
+    }
+}
+
+/// npub1... or hex → hex, as tags and filters carry pubkeys
+pub fn parse_pubkey(input: &str) -> Result<String> {
+    Ok(hex::encode(decode_key(input.trim(), "npub")?))
+}
+
+/// note1... or hex → hex event id
+pub fn parse_event_id(input: &str) -> Result<String> {
+    Ok(hex::encode(decode_key(input.trim(), "note")?))
+}
+
+/// 32 bytes, as NIP-19 bech32 with `hrp` or as hex
+fn decode_key(input: &str, hrp: &str) -> Result<[u8; 32]> {
+    encoding::bech32_or_hex32(input, hrp).with_context(|| format!("Invalid {}", hrp))
+}
+
+/// NIP-57 zap request, for the pay callback: who gets `amount_msat`, for
+/// which of their notes, and where the server should publish the receipt
+pub fn zap_request(
+    key: &NostrKey,
+    recipient: &str,
+    event_id: Option<&str>,
+    amount_msat: u64,
+    lnurl: &str,
+    relays: &[String],
+    comment: &str,
+) -> Event {
+    let mut relays_tag = vec!["relays".to_string()];
+    relays_tag.extend(relays.iter().cloned());
+    let mut tags = vec![
+        relays_tag,
+        vec!["amount".to_string(), amount_msat.to_string()],
+        vec!["lnurl".to_string(), lnurl.to_string()],
+        vec!["p".to_string(), recipient.to_string()],
+    ];
+    if let Some(event_id) = event_id {
+        tags.push(vec!["e".to_string(), event_id.to_string()]);
+    }
+    Event::sign(&key.keypair, KIND_ZAP_REQUEST, tags, comment)
+}
+
+/// What to do once a relay has sent all the events it has stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// Give up on that relay: looking something up
+    Stored,
+    /// Keep listening for new ones: waiting for something to happen
+    Timeout,
+}
+
+/// Asks every relay at once for events matching `filter`, returning the
+/// first validly signed one `accept` takes; None once `timeout` is up or no
+/// relay has one. Relays that can't be reached are only logged.
+pub async fn find_event(
+    relays: &[String],
+    filter: Value,
+    until: Until,
+    timeout: Duration,
+    accept: impl Fn(&Event) -> bool,
+) -> Result<Option<Event>> {
+    if relays.is_empty() {
+        return Err(anyhow!("No relays to ask"));
+    }
+    let searches = relays
+        .iter()
+        .map(|relay| Box::pin(search_relay(relay, &filter, until, &accept)));
+    match tokio::time::timeout(timeout, future::select_ok(searches)).await {
+        Ok(Ok((event, _))) => Ok(Some(event)),
+        Ok(Err(_)) | Err(_) => Ok(None),
+    }
+}
+
+async fn search_relay(
+    relay: &str,
+    filter: &Value,
+    until: Until,
+    accept: &impl Fn(&Event) -> bool,
+) -> Result<Event> {
+    let search = async {
+        let (mut socket, _) = tokio_tungstenite::connect_async(relay).await?;
+        let mut subscription = [0u8; 8];
+        let _ = getrandom::getrandom(&mut subscription);
+        let subscription = hex::encode(subscription);
+        let request = json!(["REQ", subscription, filter]).to_string();
+        socket.send(Frame::Text(request)).await?;
+
+        while let Some(frame) = socket.next().await {
+            let Frame::Text(text) = frame? else {
+                continue;
+            };
+            let Ok(message) = serde_json::from_str::<Vec<Value>>(&text) else {
+                debug!(relay, "Ignoring {}", text);
+                continue;
+            };
+            match message.first().and_then(Value::as_str) {
+                Some("EVENT") => {
+                    let event = message
+                        .get(2)
+                        .and_then(|event| serde_json::from_value::<Event>(event.clone()).ok());
+                    match event {
+                        Some(event) if event.verify().is_ok() && accept(&event) => {
+                            let _ = socket.close(None).await;
+                            return Ok(event);
+                        }
+                        _ => debug!(relay, "Skipping event"),
+                    }
+                }
+                Some("EOSE") if until == Until::Stored => {
+                    let _ = socket.close(None).await;
+                    return Err(anyhow!("no such event"));
+                }
+                Some("CLOSED") => {
+                    let reason = message.get(2).and_then(Value::as_str).unwrap_or_default();
+                    return Err(anyhow!("subscription closed: {}", reason));
+                }
+                Some("NOTICE") => debug!(relay, "Notice: {}", text),
+                _ => {}
+            }
+        }
+        Err(anyhow!("connection closed"))
+    };
+    search.await.inspect_err(|e| match until {
+        Until::Stored => debug!("Relay {}: {:#}", relay, e),
+        Until::Timeout => warn!("Relay {}: {:#}", relay, e),
+    })
+}
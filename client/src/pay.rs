@@ -0,0 +1,311 @@
+// =============================================================================
+// pay (LUD-06)
+// =============================================================================
+//
+// Flow:
+//   1. GET /request-pay             → { callback, minSendable, maxSendable, metadata }
+//   2. GET <callback>?amount=<msat>[&comment=<text>] → { pr: "<bolt11>" }
+//   3. Check the invoice commits to exactly that amount and to sha256(metadata)
+//   4. Pay it with our node
+//   5. Hand back the successAction, if any (LUD-09); `aes` ones decrypt with
+//      the preimage (LUD-10)
+//
+// Preferring BOLT12 with a `bolt12` offer in the response, steps 2-5 become
+// fetchinvoice + pay on the offer, unless the node (or the offer's issuer)
+// can't do BOLT12, in which case the flow carries on with the callback.
+
+use anyhow::{anyhow, Context, Result};
+use lnurl_models::{LnurlParams, PayCallbackResponse, PayRequest, SuccessAction};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::error::{lnurl_error, payment_error, usage_error};
+use crate::history;
+use crate::http::Http;
+use crate::target::Target;
+use crate::wallet::{SentPayment, Wallet};
+
+/// How a payment ended
+#[derive(Debug, Clone)]
+pub struct PayOutcome {
+    pub invoice: String, // BOLT-11, or BOLT-12 if paid through the offer
+    pub payment: SentPayment,
+    /// What the recipient has to say now that it's paid. Ones we can't read
+    /// are left out.
+    pub success_action: Option<SuccessAction>,
+}
+
+/// Step 1: GET /request-pay
+pub async fn fetch_request(http: &Http, target: &Target) -> Result<PayRequest> {
+    http.get_params(&target.endpoint("request-pay"), LnurlParams::into_pay_request)
+        .await
+}
+
+/// The whole payment: fetches the request at `target` and pays it
+pub async fn pay(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    target: &Target,
+    amount_msat: u64,
+    comment: Option<&str>,
+    prefer_bolt12: bool,
+) -> Result<PayOutcome> {
+    info!("Requesting pay info from {}...", target);
+    let request = fetch_request(http, target).await?;
+    pay_request(wallet, http, &request, amount_msat, comment, prefer_bolt12).await
+}
+
+/// Steps 2-5, once the request params are known
+pub async fn pay_request(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    request: &PayRequest,
+    amount_msat: u64,
+    comment: Option<&str>,
+    prefer_bolt12: bool,
+) -> Result<PayOutcome> {
+    // Checks the amount and comment, for the offer too
+    let callback_url = callback_url(request, amount_msat, comment)?;
+
+    if prefer_bolt12 {
+        match &request.bolt12 {
+            Some(offer) => {
+                if let Some(outcome) = pay_offer(wallet, offer, amount_msat, comment).await? {
+                    return Ok(outcome);
+                }
+            }
+            None => info!("The server advertises no BOLT12 offer, using the callback"),
+        }
+    }
+
+    // Steps 2-4
+    let (cb_resp, payment) = fetch_and_pay(
+        wallet,
+        http,
+        &callback_url,
+        amount_msat,
+        &request.metadata,
+        "metadata",
+    )
+    .await?;
+
+    // Step 5: successAction
+    let success_action = cb_resp
+        .success_action
+        .and_then(|action| read_success_action(action, &callback_url));
+    Ok(PayOutcome {
+        invoice: cb_resp.pr,
+        payment,
+        success_action,
+    })
+}
+
+/// Returns the text/plain description, rejecting malformed metadata
+pub fn metadata_description(metadata: &str) -> Result<String> {
+    let entries: Vec<Vec<serde_json::Value>> =
+        serde_json::from_str(metadata).context("Pay request metadata is not a JSON array")?;
+
+    let mut description = None;
+    for entry in &entries {
+        match (entry.first().and_then(|v| v.as_str()), entry.get(1)) {
+            (Some("text/plain"), Some(serde_json::Value::String(text))) => {
+                if description.replace(text.clone()).is_some() {
+                    return Err(lnurl_error!("Pay request metadata has more than one text/plain entry"));
+                }
+            }
+            (Some(_), Some(_)) => {}
+            _ => return Err(lnurl_error!("Malformed pay request metadata entry: {:?}", entry)),
+        }
+    }
+    description.ok_or_else(|| lnurl_error!("Pay request metadata has no text/plain entry"))
+}
+
+/// The description hash an invoice must carry for `metadata`: sha256 of the
+/// string exactly as the server sent it, not of any re-serialization
+pub fn description_hash(metadata: &str) -> [u8; 32] {
+    Sha256::digest(metadata.as_bytes()).into()
+}
+
+/// Checks the pay request against what we want to send, and returns the
+/// callback asking for the invoice
+pub fn callback_url(
+    request: &PayRequest,
+    amount_msat: u64,
+    comment: Option<&str>,
+) -> Result<Url> {
+    let description = metadata_description(&request.metadata)?;
+
+    info!(
+        callback = %request.callback,
+        description = %description,
+        min_sendable_msat = request.min_sendable,
+        max_sendable_msat = request.max_sendable,
+        comment_allowed = request.comment_allowed,
+        "Received pay request"
+    );
+
+    if amount_msat < request.min_sendable || amount_msat > request.max_sendable {
+        return Err(usage_error!(
+            "Amount {} msat is outside the allowed range {}-{} msat",
+            amount_msat,
+            request.min_sendable,
+            request.max_sendable
+        ));
+    }
+
+    if let Some(comment) = comment {
+        let length = comment.chars().count() as u64;
+        if request.comment_allowed == 0 {
+            return Err(usage_error!("This server does not accept comments"));
+        }
+        if length > request.comment_allowed {
+            return Err(usage_error!(
+                "Comment is {} characters, the server allows at most {}",
+                length,
+                request.comment_allowed
+            ));
+        }
+    }
+
+    let mut callback_url = Url::parse(&request.callback)
+        .map_err(|e| lnurl_error!("Invalid callback URL {}: {}", request.callback, e))?;
+    {
+        let mut query = callback_url.query_pairs_mut();
+        query.append_pair("amount", &amount_msat.to_string());
+        if let Some(comment) = comment {
+            query.append_pair("comment", comment); // percent-encoded by the serializer
+        }
+    }
+    Ok(callback_url)
+}
+
+/// Steps 2-4: GET <callback>?amount=<msat>[&...], check the invoice commits
+/// to exactly that amount and to sha256(`committed`) (the metadata, or for a
+/// zap the zap request), then pay it. Returns the callback's response and
+/// the payment.
+pub async fn fetch_and_pay(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    callback_url: &Url,
+    amount_msat: u64,
+    committed: &str,
+    what: &str,
+) -> Result<(PayCallbackResponse, SentPayment)> {
+    info!("Calling pay callback");
+    let body: serde_json::Value = http.get_json(callback_url.as_str()).await?;
+    if body["status"].as_str() == Some("ERROR") {
+        return Err(lnurl_error!(
+            "Pay request failed: {}",
+            body["reason"].as_str().unwrap_or("unknown")
+        ));
+    }
+    let cb_resp: PayCallbackResponse =
+        serde_json::from_value(body).context("Malformed pay callback response")?;
+    info!("Received invoice: {}", cb_resp.pr);
+
+    // The invoice must be for our amount and commit to `committed`
+    let decoded = wallet.decode_invoice(&cb_resp.pr).await?;
+    if !decoded.valid {
+        return Err(lnurl_error!("Invalid invoice from the server: {}", cb_resp.pr));
+    }
+
+    let invoice_amount = decoded.amount_msat;
+    if invoice_amount != Some(amount_msat) {
+        return Err(lnurl_error!(
+            "Invoice amount {:?} msat does not match the requested {} msat",
+            invoice_amount,
+            amount_msat
+        ));
+    }
+
+    match decoded.description_hash {
+        Some(hash) if hash == description_hash(committed) => {}
+        Some(_) => {
+            return Err(lnurl_error!("Invoice description hash does not match the {}", what))
+        }
+        None => return Err(lnurl_error!("Invoice has no description hash")),
+    }
+
+    let payment = pay_invoice(wallet, &cb_resp.pr, amount_msat).await?;
+    Ok((cb_resp, payment))
+}
+
+/// Pays a BOLT12 `offer` through the node: fetchinvoice, then pay. None if
+/// no invoice could be had (no BOLT12 support on either side, say), so
+/// nothing was paid and the callback can be tried instead.
+async fn pay_offer(
+    wallet: &mut dyn Wallet,
+    offer: &str,
+    amount_msat: u64,
+    comment: Option<&str>,
+) -> Result<Option<PayOutcome>> {
+    info!("Fetching an invoice from the BOLT12 offer {}", offer);
+    let invoice = match wallet.fetch_invoice(offer, amount_msat, comment).await {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            warn!("Can't use the BOLT12 offer, falling back to the callback: {:#}", e);
+            return Ok(None);
+        }
+    };
+    info!("Received invoice: {}", invoice);
+
+    let payment = pay_invoice(wallet, &invoice, amount_msat).await?;
+    Ok(Some(PayOutcome {
+        invoice,
+        payment,
+        success_action: None,
+    }))
+}
+
+async fn pay_invoice(wallet: &mut dyn Wallet, invoice: &str, amount_msat: u64) -> Result<SentPayment> {
+    info!("Paying {} msat...", amount_msat);
+    history::note(|operation| {
+        operation.amount_msat = Some(amount_msat);
+        operation.invoice = Some(invoice.to_string());
+    });
+    let payment = wallet
+        .pay(invoice)
+        .await
+        .map_err(|e| payment_error!("Payment failed: {}", e))?;
+    history::note(|operation| operation.preimage = Some(hex::encode(&payment.preimage)));
+    Ok(payment)
+}
+
+fn read_success_action(action: serde_json::Value, callback: &Url) -> Option<SuccessAction> {
+    let action: SuccessAction = match serde_json::from_value(action.clone()) {
+        Ok(action) => action,
+        Err(_) => {
+            warn!("Ignoring unsupported successAction: {}", action);
+            return None;
+        }
+    };
+    if let SuccessAction::Url { url, .. } = &action {
+        // LUD-09: the URL must be on the callback's domain
+        let same_domain = Url::parse(url)
+            .map(|url| url.host_str() == callback.host_str())
+            .unwrap_or(false);
+        if !same_domain {
+            warn!("The successAction URL is not on the service's domain");
+        }
+    }
+    Some(action)
+}
+
+/// Decrypts an `aes` successAction payload (LUD-10)
+pub fn decrypt_success_action(preimage: &[u8], ciphertext: &str, iv: &str) -> Result<String> {
+    use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+    use base64::Engine;
+    type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let ciphertext = engine.decode(ciphertext).context("successAction ciphertext is not base64")?;
+    let iv = engine.decode(iv).context("successAction iv is not base64")?;
+
+    let decryptor = Aes256CbcDec::new_from_slices(preimage, &iv)
+        .map_err(|_| anyhow!("successAction needs a 32-byte preimage and 16-byte iv"))?;
+    let plaintext = decryptor
+        .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt successAction (bad padding)"))?;
+    String::from_utf8(plaintext).context("Decrypted successAction is not UTF-8")
+}
@@ -0,0 +1,56 @@
+// =============================================================================
+// QR codes
+// =============================================================================
+//
+// Wallets and services often only show an LNURL as a QR code. `handle
+// --from-image` reads it from a screenshot or photo (PNG or JPEG) instead of
+// having it typed in.
+//
+// The other way round, `qr` and `--show-qr` draw LNURLs and invoices in the
+// terminal for a mobile wallet to scan, so the client can play the service
+// side in interop tests. Bech32 strings are uppercased first, which fits the
+// denser alphanumeric QR mode (LUD-01).
+
+use anyhow::Result;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{EcLevel, QrCode};
+use std::path::Path;
+use tracing::debug;
+
+use crate::error::usage_error;
+
+/// Returns the text of the first readable QR code in the image at `path`
+pub fn decode_image(path: &Path) -> Result<String> {
+    let image = image::open(path)
+        .map_err(|e| usage_error!("Failed to read image {}: {}", path.display(), e))?
+        .to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32).0[0],
+    );
+
+    let grids = prepared.detect_grids();
+    debug!("Found {} QR code(s) in {}", grids.len(), path.display());
+    for grid in &grids {
+        match grid.decode() {
+            Ok((_, content)) => return Ok(content),
+            Err(e) => debug!("Unreadable QR code: {}", e),
+        }
+    }
+    Err(usage_error!("No readable QR code in {}", path.display()))
+}
+
+/// Draws `data` as a QR code of unicode half blocks, two modules per line
+pub fn render(data: &str) -> Result<String> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)
+        .map_err(|e| usage_error!("Cannot fit {} characters in a QR code: {}", data.len(), e))?;
+    // Blocks are drawn in the terminal's foreground colour, usually light on
+    // dark, so the light modules get the blocks
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
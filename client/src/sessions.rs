@@ -0,0 +1,90 @@
+// =============================================================================
+// Auth sessions
+// =============================================================================
+//
+// A successful auth against our server returns a session token. It is kept
+// in sessions.json next to the config file, keyed by origin (host:port), and
+// sent as `Authorization: Bearer <token>` on every later request to that
+// origin, e.g. so request-withdraw gets a voucher bound to the account.
+// `logout` forgets it again.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use url::Url;
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    path: Option<PathBuf>,            // None: nowhere to persist, nothing is stored
+    tokens: BTreeMap<String, String>, // origin -> token
+}
+
+/// host:port, with the scheme's default port filled in
+pub fn origin(url: &Url) -> Option<String> {
+    Some(format!(
+        "{}:{}",
+        url.host_str()?,
+        url.port_or_known_default()?
+    ))
+}
+
+impl Sessions {
+    pub fn load(path: Option<PathBuf>) -> Result<Sessions> {
+        let tokens = match &path {
+            Some(path) if path.exists() => {
+                let raw = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                serde_json::from_str(&raw)
+                    .with_context(|| format!("Invalid sessions file {}", path.display()))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(Sessions { path, tokens })
+    }
+
+    pub fn token_for(&self, url: &Url) -> Option<&str> {
+        self.tokens.get(&origin(url)?).map(String::as_str)
+    }
+
+    /// Returns the origin the token was stored for
+    pub fn store(&mut self, url: &Url, token: &str) -> Result<String> {
+        let origin = origin(url).context("URL has no host to keep a session for")?;
+        self.tokens.insert(origin.clone(), token.to_string());
+        self.save()?;
+        Ok(origin)
+    }
+
+    /// Returns whether there was a session to forget
+    pub fn remove(&mut self, url: &Url) -> Result<bool> {
+        let removed = match origin(url) {
+            Some(origin) => self.tokens.remove(&origin).is_some(),
+            None => false,
+        };
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns how many sessions were forgotten
+    pub fn clear(&mut self) -> Result<usize> {
+        let count = self.tokens.len();
+        self.tokens.clear();
+        self.save()?;
+        Ok(count)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            anyhow::bail!("No config directory to keep sessions in (HOME not set)");
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        // Tokens are bearer credentials
+        let json = serde_json::to_string_pretty(&self.tokens)?;
+        crate::config::write_private(path, json.as_bytes())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
@@ -0,0 +1,365 @@
+// =============================================================================
+// Targets
+// =============================================================================
+//
+// What a flow is pointed at: a server's base URL or ip[:port], to which the
+// flow appends its own path (/request-withdraw, ...), or a complete endpoint
+// as decoded from a bech32 LNURL (LUD-01), a lightning address (LUD-16) or a
+// payLink or withdrawLink (LUD-19).
+
+use anyhow::{anyhow, Context, Result};
+use lnurl_models::encoding;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use url::Url;
+
+use crate::error::usage_error;
+
+/// Where a flow starts: a server base URL, to which the flow appends its own
+/// path, or a complete endpoint, as decoded from an LNURL
+#[derive(Debug, Clone)]
+pub enum Target {
+    Base(Url),
+    Endpoint(Url),
+}
+
+impl Target {
+    pub fn url(&self) -> &Url {
+        match self {
+            Target::Base(url) | Target::Endpoint(url) => url,
+        }
+    }
+
+    /// The URL to fetch for a flow whose path on our server is `path`
+    pub fn endpoint(&self, path: &str) -> String {
+        match self {
+            Target::Base(url) => format!("{}/{}", url.as_str().trim_end_matches('/'), path),
+            Target::Endpoint(url) => url.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.url())
+    }
+}
+
+/// Parses an amount in millisatoshis; `sat`/`sats` suffixes multiply by 1000
+pub fn parse_amount_msat(input: &str) -> Result<u64> {
+    let input = input.trim().to_ascii_lowercase();
+    let (number, multiplier) = if let Some(n) = input.strip_suffix("msat") {
+        (n, 1)
+    } else if let Some(n) = input.strip_suffix("sats").or_else(|| input.strip_suffix("sat")) {
+        (n, 1000)
+    } else {
+        (input.as_str(), 1)
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| anyhow!("Invalid amount: {} (expected e.g. 21000, 21000msat or 21sat)", input))
+}
+
+/// Decodes a bech32 `lnurl1...` string (LUD-01) into the URL it encodes
+pub fn decode_lnurl(lnurl: &str) -> Result<Url> {
+    let url = encoding::decode_lnurl(lnurl).context("Invalid LNURL")?;
+    Url::parse(&url).with_context(|| format!("LNURL decodes to an invalid URL: {}", url))
+}
+
+/// Encodes `url` as a bech32 LNURL (LUD-01), uppercase as it goes in QR codes
+pub fn encode_lnurl(url: &Url) -> Result<String> {
+    encoding::encode_lnurl(url.as_str())
+        .map_err(|e| usage_error!("URL too long for an LNURL: {}", e))
+}
+
+/// Accepts `lightning:` URIs, bech32 LNURLs, LUD-17 links (lnurlw://...), and
+/// anything `parse_url_or_ip` takes
+pub fn parse_target(input: &str) -> Result<Target> {
+    let input = input.trim();
+    let input = match input.get(..10) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("lightning:") => &input[10..],
+        _ => input,
+    };
+
+    if input.len() > 6 && input.get(..6).is_some_and(|p| p.eq_ignore_ascii_case("lnurl1")) {
+        return Ok(Target::Endpoint(decode_lnurl(input)?));
+    }
+    if let Some(lud17) = parse_lud17(input) {
+        return lud17.map(|(url, _)| Target::Endpoint(url));
+    }
+    if input.get(..10).is_some_and(|s| s.eq_ignore_ascii_case("lightning:")) {
+        return Err(anyhow!("Nested lightning: prefix in {}", input));
+    }
+
+    parse_url_or_ip(input).map(Target::Base)
+}
+
+/// Like `parse_target`, plus lightning addresses (user@domain, LUD-16)
+pub fn parse_pay_target(input: &str) -> Result<Target> {
+    let address = input.trim();
+    if let Some((user, domain)) = address.split_once('@') {
+        if !user.is_empty() && !domain.is_empty() && !address.contains(['/', ':']) {
+            // Onion services go over plain http, Tor encrypts already
+            let scheme = if domain.ends_with(".onion") { "http" } else { "https" };
+            let url = format!("{}://{}/.well-known/lnurlp/{}", scheme, domain, user);
+            return Url::parse(&url)
+                .map(Target::Endpoint)
+                .map_err(|_| anyhow!("Invalid lightning address: {}", address));
+        }
+    }
+    parse_target(input)
+}
+
+/// A payLink is a whole endpoint, as an LNURL, a URL or (LUD-17) lnurlp://
+pub fn pay_link_target(pay_link: &str) -> Result<Target> {
+    link_target(pay_link)
+}
+
+/// A withdrawLink (LUD-19) likewise, or lnurlw://
+pub fn withdraw_link_target(withdraw_link: &str) -> Result<Target> {
+    link_target(withdraw_link)
+}
+
+fn link_target(link: &str) -> Result<Target> {
+    match parse_target(link)? {
+        Target::Base(url) | Target::Endpoint(url) => Ok(Target::Endpoint(url)),
+    }
+}
+
+/// LUD-17 schemes, and the tag of the endpoints they stand for
+const LUD17_SCHEMES: [(&str, &str); 4] = [
+    ("lnurlc://", "channelRequest"),
+    ("lnurlw://", "withdrawRequest"),
+    ("lnurlp://", "payRequest"),
+    ("keyauth://", "login"),
+];
+
+/// A LUD-17 link (lnurlw://host/path, ...) as the endpoint it stands for and
+/// the tag its scheme implies; None for anything else
+pub fn parse_lud17(input: &str) -> Option<Result<(Url, &'static str)>> {
+    let (scheme, tag) = LUD17_SCHEMES.iter().find(|(scheme, _)| {
+        input.get(..scheme.len()).is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })?;
+    Some(lud17_url(&input[scheme.len()..]).map(|url| (url, *tag)))
+}
+
+/// The URL behind what follows a LUD-17 scheme
+fn lud17_url(rest: &str) -> Result<Url> {
+    let url = Url::parse(&format!("https://{}", rest))?;
+    // Onion services go over plain http, Tor encrypts already
+    if url.host_str().is_some_and(|host| host.ends_with(".onion")) {
+        return Ok(Url::parse(&format!("http://{}", rest))?);
+    }
+    Ok(url)
+}
+
+fn parse_url_or_ip(input: &str) -> Result<Url> {
+    // IPs first: `fe80::1` would also parse as a URL, with scheme fe80
+
+    // Handle IPv6 with port: [::1]:8080
+    if let Some(bracket_end) = input.find("]:") {
+        if input.starts_with('[') {
+            let ip_part = &input[1..bracket_end];
+            let port_part = &input[bracket_end + 2..];
+            if port_part.parse::<u16>().is_ok() {
+                if let Ok(ip) = Ipv6Addr::from_str(ip_part) {
+                    let url_str = format!("http://[{}]:{}", ip, port_part);
+                    return Url::parse(&url_str)
+                        .context("Failed to convert IPv6 with port to URL");
+                }
+            }
+        }
+    }
+
+    // Handle IPv4 with port: 192.168.1.1:8080 (IPv6 needs the brackets,
+    // fe80::1:2 is an address, not fe80::1 port 2)
+    if let Some(colon_pos) = input.rfind(':') {
+        let ip_part = &input[..colon_pos];
+        let port_part = &input[colon_pos + 1..];
+        if port_part.parse::<u16>().is_ok() {
+            if let Ok(ip) = Ipv4Addr::from_str(ip_part) {
+                let url_str = format!("http://{}:{}", ip, port_part);
+                return Url::parse(&url_str)
+                    .context("Failed to convert IP:port to URL");
+            }
+        }
+    }
+
+    // Plain IP with no port: 192.168.1.1, ::1 or [::1]
+    if let Ok(ip) = Ipv4Addr::from_str(input) {
+        let url_str = format!("http://{}", ip);
+        return Url::parse(&url_str).context("Failed to convert IP to URL");
+    }
+    let unbracketed = input
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(input);
+    if let Ok(ip) = Ipv6Addr::from_str(unbracketed) {
+        let url_str = format!("http://[{}]", ip);
+        return Url::parse(&url_str).context("Failed to convert IPv6 to URL");
+    }
+
+    // Then a full URL
+    if let Ok(url) = Url::parse(input) {
+        return Ok(url);
+    }
+
+    Err(anyhow!("Invalid URL or IP address: {}", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use url::Host;
+
+    fn base(input: &str) -> Url {
+        match parse_target(input).unwrap() {
+            Target::Base(url) => url,
+            other => panic!("{} parsed as {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn ipv6_that_looks_like_a_url() {
+        // Used to parse as scheme fe80, and fe80::1:2 as fe80::1 port 2
+        for ip in ["fe80::1", "fe80::1:2", "abcd::ef:80"] {
+            let ipv6 = Ipv6Addr::from_str(ip).unwrap();
+            assert_eq!(base(ip).host(), Some(Host::Ipv6(ipv6)), "{}", ip);
+            assert_eq!(base(ip).port(), None, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn multibyte_input_is_not_sliced() {
+        // Used to panic slicing the lnurl1 prefix mid-character
+        assert!(parse_target("ĀĀĀĀ").is_err());
+        assert!(parse_target("lnurlĀ").is_err());
+    }
+
+    #[test]
+    fn lud17_schemes_name_their_tag() {
+        let (url, tag) = parse_lud17("lnurlw://shop.example/w?k1=ab").unwrap().unwrap();
+        assert_eq!((url.as_str(), tag), ("https://shop.example/w?k1=ab", "withdrawRequest"));
+        let (url, tag) = parse_lud17("KEYAUTH://abc.onion/login").unwrap().unwrap();
+        assert_eq!((url.as_str(), tag), ("http://abc.onion/login", "login"));
+        assert!(parse_lud17("https://shop.example").is_none());
+    }
+
+    #[test]
+    fn lud17_links_are_targets() {
+        let endpoint = |input: &str| match parse_target(input).unwrap() {
+            Target::Endpoint(url) => url.to_string(),
+            other => panic!("{} parsed as {:?}", input, other),
+        };
+        assert_eq!(endpoint("lnurlp://shop.example/p/1"), "https://shop.example/p/1");
+        assert_eq!(endpoint("lightning:LNURLW://abc.onion/w?k1=ab"), "http://abc.onion/w?k1=ab");
+        assert_eq!(
+            endpoint("lnurlc://10.0.0.2:3000/request-channel"),
+            "https://10.0.0.2:3000/request-channel"
+        );
+        assert_eq!(
+            endpoint("keyauth://shop.example/login?tag=login&k1=ab"),
+            "https://shop.example/login?tag=login&k1=ab"
+        );
+        assert!(parse_target("lnurlw://").is_err());
+    }
+
+    #[test]
+    fn lud19_links_are_endpoints() {
+        let target = withdraw_link_target("lnurlw://shop.example/request-withdraw").unwrap();
+        assert!(matches!(target, Target::Endpoint(_)));
+        assert_eq!(target.url().as_str(), "https://shop.example/request-withdraw");
+        let target = pay_link_target("https://shop.example/request-pay").unwrap();
+        assert!(matches!(target, Target::Endpoint(_)));
+    }
+
+    #[test]
+    fn malformed_ports_are_rejected() {
+        assert!(parse_target("1.2.3.4:99999").is_err());
+        assert!(parse_target("[::1]:x").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(input in "\\PC*") {
+            let _ = parse_target(&input);
+            let _ = parse_pay_target(&input);
+        }
+
+        #[test]
+        fn never_panics_on_ip_lookalikes(input in "\\[?[0-9a-fA-F:.%\\]]{0,50}") {
+            let _ = parse_target(&input);
+        }
+
+        #[test]
+        fn ipv4_with_port(ip: Ipv4Addr, port: u16) {
+            let url = base(&format!("{}:{}", ip, port));
+            prop_assert_eq!(url.scheme(), "http");
+            prop_assert_eq!(url.host(), Some(Host::Ipv4(ip)));
+            prop_assert_eq!(url.port_or_known_default(), Some(port));
+        }
+
+        #[test]
+        fn ipv6_with_port(ip: Ipv6Addr, port: u16) {
+            let url = base(&format!("[{}]:{}", ip, port));
+            prop_assert_eq!(url.scheme(), "http");
+            prop_assert_eq!(url.host(), Some(Host::Ipv6(ip)));
+            prop_assert_eq!(url.port_or_known_default(), Some(port));
+        }
+
+        #[test]
+        fn plain_ips(v4: Ipv4Addr, v6: Ipv6Addr) {
+            let (v4_url, v6_url) = (base(&v4.to_string()), base(&v6.to_string()));
+            prop_assert_eq!(v4_url.host(), Some(Host::Ipv4(v4)));
+            prop_assert_eq!(v6_url.host(), Some(Host::Ipv6(v6)));
+            prop_assert_eq!(v6_url.port(), None);
+            prop_assert_eq!(base(&format!("[{}]", v6)), v6_url);
+        }
+
+        #[test]
+        fn surrounding_whitespace_is_ignored(ip: Ipv4Addr, port: u16, pad in "[ \t\n]{0,3}") {
+            let input = format!("{}:{}", ip, port);
+            prop_assert_eq!(base(&format!("{pad}{input}{pad}")), base(&input));
+        }
+
+        #[test]
+        fn urls_round_trip(host in "[a-z][a-z0-9]{0,10}(-[a-z0-9]{1,10})?(\\.[a-z]{2,6}){1,2}",
+                           port in proptest::option::of(1u16..),
+                           path in "(/[a-zA-Z0-9_-]{1,10}){0,3}") {
+            let port = port.map(|port| format!(":{}", port)).unwrap_or_default();
+            let url = Url::parse(&format!("https://{}{}{}", host, port, path)).unwrap();
+            prop_assert_eq!(base(url.as_str()), url);
+        }
+
+        #[test]
+        fn lnurls_round_trip(ip: Ipv4Addr, port: u16, path in "(/[a-z0-9-]{1,10}){1,3}") {
+            let url = Url::parse(&format!("http://{}:{}{}", ip, port, path)).unwrap();
+            let lnurl = encode_lnurl(&url).unwrap();
+            prop_assert_eq!(decode_lnurl(&lnurl).unwrap(), url.clone());
+            for input in [lnurl.clone(), lnurl.to_lowercase(), format!("lightning:{}", lnurl)] {
+                match parse_target(&input).unwrap() {
+                    Target::Endpoint(endpoint) => prop_assert_eq!(&endpoint, &url),
+                    other => panic!("{} parsed as {:?}", input, other),
+                }
+            }
+        }
+
+        #[test]
+        fn amounts_round_trip(msat: u64, sat in 0..=u64::MAX / 1000) {
+            prop_assert_eq!(parse_amount_msat(&msat.to_string()).unwrap(), msat);
+            prop_assert_eq!(parse_amount_msat(&format!("{}msat", msat)).unwrap(), msat);
+            prop_assert_eq!(parse_amount_msat(&format!("{} sats", sat)).unwrap(), sat * 1000);
+        }
+
+        #[test]
+        fn sat_amounts_do_not_overflow(sat in u64::MAX / 1000 + 1..) {
+            let parsed = parse_amount_msat(&format!("{}sat", sat));
+            prop_assert!(parsed.is_err());
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// Core Lightning over cln-rpc
+
+use anyhow::Result;
+use async_trait::async_trait;
+use cln_rpc::model::responses::ListpeerchannelsChannelsState as ChannelState;
+use cln_rpc::model::{requests, responses};
+use cln_rpc::primitives::{Sha256, ShortChannelId};
+use cln_rpc::{ClnRpc, Request, Response};
+
+use super::{
+    ChannelProgress, DecodedInvoice, InvoiceOptions, NodeInfo, OwnInvoice, ReceivedPayment,
+    RouteHints, SentPayment, Wallet,
+};
+use crate::config::Config;
+use crate::error::{backend_error, usage_error};
+
+pub struct ClnWallet {
+    rpc: ClnRpc,
+}
+
+impl ClnWallet {
+    pub async fn connect(config: &Config) -> Result<ClnWallet> {
+        let path = config.cln_rpc_path()?;
+        let rpc = ClnRpc::new(&path).await.map_err(|e| {
+            backend_error!(
+                "Failed to connect to CLN RPC at {}: {:#}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(ClnWallet { rpc })
+    }
+
+    async fn getinfo(&mut self) -> Result<responses::GetinfoResponse> {
+        match self
+            .rpc
+            .call(Request::Getinfo(requests::GetinfoRequest {}))
+            .await?
+        {
+            Response::Getinfo(info) => Ok(info),
+            _ => Err(backend_error!("Unexpected response type from getinfo")),
+        }
+    }
+
+    async fn listpeerchannels(&mut self) -> Result<Vec<responses::ListpeerchannelsChannels>> {
+        let request = requests::ListpeerchannelsRequest { id: None };
+        match self.rpc.call(Request::ListPeerChannels(request)).await? {
+            Response::ListPeerChannels(list) => Ok(list.channels),
+            _ => Err(backend_error!(
+                "Unexpected response type from listpeerchannels"
+            )),
+        }
+    }
+
+    /// Our usable unannounced channels, for route hints
+    async fn private_channels(&mut self) -> Result<Vec<ShortChannelId>> {
+        Ok(self
+            .listpeerchannels()
+            .await?
+            .into_iter()
+            .filter(|channel| {
+                channel.private == Some(true) && channel.state == ChannelState::CHANNELD_NORMAL
+            })
+            .filter_map(|channel| channel.short_channel_id)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Wallet for ClnWallet {
+    async fn node_info(&mut self) -> Result<NodeInfo> {
+        use responses::{GetinfoAddressType, GetinfoBindingType};
+
+        let info = self.getinfo().await?;
+        // Announced addresses first, then listening bindings
+        let announced = info
+            .address
+            .iter()
+            .flatten()
+            .filter(|a| {
+                matches!(
+                    a.item_type,
+                    GetinfoAddressType::IPV4 | GetinfoAddressType::IPV6 | GetinfoAddressType::DNS
+                )
+            })
+            .filter_map(|a| Some((a.address.clone()?, a.port)));
+        let bound = info
+            .binding
+            .iter()
+            .flatten()
+            .filter(|b| {
+                matches!(
+                    b.item_type,
+                    GetinfoBindingType::IPV4 | GetinfoBindingType::IPV6
+                )
+            })
+            .filter_map(|b| Some((b.address.clone()?, b.port?)));
+
+        Ok(NodeInfo {
+            id: info.id.to_string(),
+            addresses: announced.chain(bound).collect(),
+        })
+    }
+
+    async fn connect_peer(&mut self, id: &str, host: &str, port: u16) -> Result<()> {
+        let request = requests::ConnectRequest {
+            id: id.to_string(),
+            host: Some(host.to_string()),
+            port: Some(port),
+        };
+        self.rpc.call(Request::Connect(request)).await?;
+        Ok(())
+    }
+
+    async fn decode_invoice(&mut self, bolt11: &str) -> Result<DecodedInvoice> {
+        let request = requests::DecodeRequest {
+            string: bolt11.to_string(),
+        };
+        match self.rpc.call(Request::Decode(request)).await? {
+            Response::Decode(decoded) => Ok(DecodedInvoice {
+                valid: decoded.valid,
+                amount_msat: decoded.amount_msat.map(|a| a.msat()),
+                payment_hash: decoded.payment_hash.map(hash_bytes),
+                description_hash: decoded.description_hash.map(hash_bytes),
+            }),
+            _ => Err(backend_error!("Unexpected response from decode")),
+        }
+    }
+
+    async fn create_invoice(
+        &mut self,
+        amount_msat: u64,
+        description: &str,
+        options: &InvoiceOptions,
+    ) -> Result<OwnInvoice> {
+        let exposeprivatechannels = match &options.route_hints {
+            RouteHints::Default => None,
+            RouteHints::AllPrivate => Some(self.private_channels().await?),
+            RouteHints::Channels(scids) => Some(
+                scids
+                    .iter()
+                    .map(|scid| {
+                        scid.parse::<ShortChannelId>()
+                            .map_err(|e| usage_error!("Invalid short channel id {}: {}", scid, e))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+        };
+        let label = format!(
+            "lnurl-withdraw-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );
+        let request = requests::InvoiceRequest {
+            amount_msat: cln_rpc::primitives::AmountOrAny::Amount(
+                cln_rpc::primitives::Amount::from_msat(amount_msat),
+            ),
+            label: label.clone(),
+            description: description.to_string(),
+            expiry: Some(options.expiry_secs),
+            fallbacks: None,
+            preimage: None,
+            cltv: options.cltv,
+            deschashonly: None,
+            exposeprivatechannels,
+        };
+        match self.rpc.call(Request::Invoice(request)).await? {
+            Response::Invoice(invoice) => Ok(OwnInvoice {
+                bolt11: invoice.bolt11,
+                handle: label,
+            }),
+            _ => Err(backend_error!("Unexpected response from invoice creation")),
+        }
+    }
+
+    async fn find_invoice(&mut self, bolt11: &str) -> Result<Option<String>> {
+        let request = requests::ListinvoicesRequest {
+            label: None,
+            invstring: Some(bolt11.to_string()),
+            payment_hash: None,
+            offer_id: None,
+            index: None,
+            start: None,
+            limit: None,
+        };
+        match self.rpc.call(Request::ListInvoices(request)).await? {
+            Response::ListInvoices(list) => Ok(list.invoices.into_iter().next().map(|i| i.label)),
+            _ => Err(backend_error!("Unexpected response from listinvoices")),
+        }
+    }
+
+    async fn wait_invoice(&mut self, handle: &str) -> Result<ReceivedPayment> {
+        // CLN errors out once the invoice expires
+        let request = requests::WaitinvoiceRequest {
+            label: handle.to_string(),
+        };
+        match self.rpc.call(Request::WaitInvoice(request)).await? {
+            Response::WaitInvoice(invoice) => Ok(ReceivedPayment {
+                amount_msat: invoice.amount_received_msat.map(|a| a.msat()),
+                paid_at: invoice.paid_at,
+                preimage: invoice.payment_preimage.map(|preimage| preimage.to_vec()),
+            }),
+            _ => Err(backend_error!(
+                "Unexpected response while waiting for invoice"
+            )),
+        }
+    }
+
+    async fn pay(&mut self, bolt11: &str) -> Result<SentPayment> {
+        let request = requests::PayRequest {
+            bolt11: bolt11.to_string(),
+            amount_msat: None,
+            label: None,
+            riskfactor: None,
+            maxfeepercent: None,
+            retry_for: None,
+            maxdelay: None,
+            exemptfee: None,
+            localinvreqid: None,
+            exclude: None,
+            maxfee: None,
+            description: None,
+            partial_msat: None,
+        };
+        match self.rpc.call(Request::Pay(request)).await? {
+            Response::Pay(paid) => Ok(SentPayment {
+                preimage: paid.payment_preimage.to_vec(),
+                amount_sent_msat: paid.amount_sent_msat.msat(),
+            }),
+            _ => Err(backend_error!("Unexpected response from pay")),
+        }
+    }
+
+    async fn fetch_invoice(
+        &mut self,
+        offer: &str,
+        amount_msat: u64,
+        payer_note: Option<&str>,
+    ) -> Result<String> {
+        // Needs offers enabled (experimental-offers before v24.11)
+        let request = requests::FetchinvoiceRequest {
+            offer: offer.to_string(),
+            amount_msat: Some(cln_rpc::primitives::Amount::from_msat(amount_msat)),
+            quantity: None,
+            recurrence_counter: None,
+            recurrence_start: None,
+            recurrence_label: None,
+            timeout: None,
+            payer_note: payer_note.map(str::to_string),
+        };
+        match self.rpc.call(Request::FetchInvoice(request)).await? {
+            Response::FetchInvoice(fetched) => Ok(fetched.invoice),
+            _ => Err(backend_error!("Unexpected response from fetchinvoice")),
+        }
+    }
+
+    async fn sign_message(&mut self, message: &str) -> Result<String> {
+        let request = requests::SignmessageRequest {
+            message: message.to_string(),
+        };
+        match self.rpc.call(Request::SignMessage(request)).await? {
+            Response::SignMessage(signed) => {
+                tracing::debug!(signature = %signed.signature, recid = %signed.recid, "Signed message");
+                Ok(signed.zbase)
+            }
+            _ => Err(backend_error!("Unexpected response from signmessage")),
+        }
+    }
+
+    async fn channel_progress(
+        &mut self,
+        peer_id: &str,
+        funding_txid: Option<&str>,
+    ) -> Result<Option<ChannelProgress>> {
+        let blockheight = self.getinfo().await?.blockheight;
+        let channels = self.listpeerchannels().await?;
+        let Some(channel) = channels.into_iter().find(|channel| {
+            channel.peer_id.to_string() == peer_id
+                && funding_txid.is_none_or(|txid| channel.funding_txid.as_deref() == Some(txid))
+        }) else {
+            return Ok(None);
+        };
+
+        Ok(Some(match channel.state {
+            ChannelState::CHANNELD_NORMAL => ChannelProgress::Ready {
+                short_channel_id: channel.short_channel_id.map(|scid| scid.to_string()),
+            },
+            ChannelState::OPENINGD
+            | ChannelState::CHANNELD_AWAITING_LOCKIN
+            | ChannelState::DUALOPEND_OPEN_INIT
+            | ChannelState::DUALOPEND_AWAITING_LOCKIN => ChannelProgress::Opening {
+                // The scid is the funding tx's block, once it has one
+                confirmations: Some(
+                    channel
+                        .short_channel_id
+                        .map_or(0, |scid| blockheight.saturating_sub(scid.block()) + 1),
+                ),
+                // e.g. "Funding needs 2 more confirmations"
+                status: channel.status.and_then(|status| status.last().cloned()),
+            },
+            other => ChannelProgress::Closed(format!("{:?}", other)),
+        }))
+    }
+}
+
+/// cln-rpc's hashes come from its own, older bitcoin_hashes, whose `Hash`
+/// trait isn't ours to import
+fn hash_bytes(hash: Sha256) -> [u8; 32] {
+    let bytes: &[u8; 32] = hash.as_ref();
+    *bytes
+}
@@ -0,0 +1,153 @@
+// =============================================================================
+// Wallet backends
+// =============================================================================
+//
+// Everything the flows need from a Lightning node, so the same CLI can drive
+// LNURL servers from either implementation:
+//
+//   cln  — Core Lightning over its unix socket (cln-rpc), the default
+//   lnd  — LND over gRPC, with its TLS certificate and a macaroon
+//   standalone — no node, see standalone.rs
+//
+// Selected with `backend` in the config, LNURL_CLIENT_BACKEND or --backend.
+// Backend failures keep their own error type (cln_rpc::RpcError,
+// tonic::Status) so error.rs classifies them as backend errors.
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::config::{Backend, Config};
+
+mod cln;
+mod lnd;
+mod standalone;
+
+/// What `node_info` reports about our node
+#[derive(Debug, Clone)]
+pub struct NodeInfo {
+    pub id: String, // hex pubkey
+    /// Addresses other nodes may reach us on, most likely first
+    pub addresses: Vec<(String, u16)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedInvoice {
+    /// Whose fault an invalid invoice is depends on where it came from, so
+    /// decoding one is not an error
+    pub valid: bool,
+    pub amount_msat: Option<u64>,
+    /// What the preimage of a paid invoice hashes to
+    pub payment_hash: Option<[u8; 32]>,
+    pub description_hash: Option<[u8; 32]>,
+}
+
+/// How `create_invoice` makes an invoice
+#[derive(Debug, Clone)]
+pub struct InvoiceOptions {
+    pub expiry_secs: u64,
+    /// min_final_cltv_expiry in blocks, the node's default if None
+    pub cltv: Option<u32>,
+    pub route_hints: RouteHints,
+}
+
+/// Which unannounced channels an invoice carries route hints for. A node
+/// with only unannounced channels can't be paid without them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RouteHints {
+    /// Whatever the node does by default
+    #[default]
+    Default,
+    AllPrivate,
+    /// Just these short channel ids (CLN only)
+    Channels(Vec<String>),
+}
+
+/// An invoice we issued
+#[derive(Debug, Clone)]
+pub struct OwnInvoice {
+    pub bolt11: String,
+    /// What `wait_invoice` needs to find it again (CLN label, LND payment hash)
+    pub handle: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReceivedPayment {
+    pub amount_msat: Option<u64>,
+    pub paid_at: Option<u64>, // unix seconds
+    pub preimage: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SentPayment {
+    pub preimage: Vec<u8>,
+    pub amount_sent_msat: u64,
+}
+
+/// Where a channel stands, for waiting until it can be used
+#[derive(Debug, Clone)]
+pub enum ChannelProgress {
+    Opening {
+        confirmations: Option<u32>, // None when the backend can't tell
+        status: Option<String>,     // the node's own description
+    },
+    Ready {
+        short_channel_id: Option<String>,
+    },
+    /// Closing or closed before it was ever usable
+    Closed(String),
+}
+
+#[async_trait]
+pub trait Wallet: Send {
+    async fn node_info(&mut self) -> Result<NodeInfo>;
+
+    async fn connect_peer(&mut self, id: &str, host: &str, port: u16) -> Result<()>;
+
+    async fn decode_invoice(&mut self, bolt11: &str) -> Result<DecodedInvoice>;
+
+    async fn create_invoice(
+        &mut self,
+        amount_msat: u64,
+        description: &str,
+        options: &InvoiceOptions,
+    ) -> Result<OwnInvoice>;
+
+    /// Returns the handle of `bolt11` if our node issued it
+    async fn find_invoice(&mut self, bolt11: &str) -> Result<Option<String>>;
+
+    /// Blocks until the invoice is paid; an error once it can't be anymore
+    async fn wait_invoice(&mut self, handle: &str) -> Result<ReceivedPayment>;
+
+    async fn pay(&mut self, bolt11: &str) -> Result<SentPayment>;
+
+    /// Asks the issuer of a BOLT12 offer for an invoice of `amount_msat`,
+    /// which `pay` then takes
+    async fn fetch_invoice(
+        &mut self,
+        offer: &str,
+        amount_msat: u64,
+        payer_note: Option<&str>,
+    ) -> Result<String>;
+
+    /// Signs with the node key like CLN's signmessage, returning zbase32
+    async fn sign_message(&mut self, message: &str) -> Result<String>;
+
+    /// Our channel with `peer_id` (the one funded by `funding_txid`, if
+    /// given), or None while the node doesn't know about it yet
+    async fn channel_progress(
+        &mut self,
+        peer_id: &str,
+        funding_txid: Option<&str>,
+    ) -> Result<Option<ChannelProgress>>;
+}
+
+/// Connects to the backend the config selects
+pub async fn connect(config: &Config) -> Result<Box<dyn Wallet>> {
+    match config.backend {
+        Backend::Cln => Ok(Box::new(cln::ClnWallet::connect(config).await?)),
+        Backend::Lnd => Ok(Box::new(lnd::LndWallet::connect(config).await?)),
+        Backend::Standalone => Ok(Box::new(
+            standalone::StandaloneWallet::connect(config).await?,
+        )),
+    }
+}
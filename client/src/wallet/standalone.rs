@@ -0,0 +1,111 @@
+// No node at all
+//
+// For exercising the auth and withdraw callbacks of a server from a machine
+// without Lightning: the identity key comes from the LNURL-auth seed (see
+// keys.rs), invoices are decoded offline (bolt11.rs), and withdraws go
+// into an invoice given with --pr, whose payment only the server's
+// /withdraw-status can report. Anything that needs a real node (opening a
+// channel, creating an invoice, paying) fails with a usage error.
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{
+    ChannelProgress, DecodedInvoice, InvoiceOptions, NodeInfo, OwnInvoice, ReceivedPayment,
+    SentPayment, Wallet,
+};
+use crate::bolt11;
+use crate::config::Config;
+use crate::error::usage_error;
+use crate::keys::{self, LinkingKey};
+
+pub struct StandaloneWallet {
+    identity: LinkingKey,
+}
+
+impl StandaloneWallet {
+    pub async fn connect(config: &Config) -> Result<StandaloneWallet> {
+        let seed = keys::load_or_create_seed(&config.seed_path()?)?;
+        Ok(StandaloneWallet {
+            identity: keys::standalone_identity_key(&seed)?,
+        })
+    }
+}
+
+fn no_node(what: &str) -> anyhow::Error {
+    usage_error!("Standalone mode has no node to {}", what)
+}
+
+#[async_trait]
+impl Wallet for StandaloneWallet {
+    async fn node_info(&mut self) -> Result<NodeInfo> {
+        Ok(NodeInfo {
+            id: self.identity.public.to_string(),
+            addresses: Vec::new(),
+        })
+    }
+
+    async fn connect_peer(&mut self, _id: &str, _host: &str, _port: u16) -> Result<()> {
+        Err(no_node("connect to peers"))
+    }
+
+    async fn decode_invoice(&mut self, bolt11: &str) -> Result<DecodedInvoice> {
+        Ok(match bolt11::decode(bolt11) {
+            Ok(invoice) => DecodedInvoice {
+                valid: true,
+                amount_msat: invoice.amount_msat,
+                payment_hash: Some(invoice.payment_hash),
+                description_hash: invoice.description_hash,
+            },
+            Err(_) => DecodedInvoice {
+                valid: false,
+                amount_msat: None,
+                payment_hash: None,
+                description_hash: None,
+            },
+        })
+    }
+
+    async fn create_invoice(
+        &mut self,
+        _amount_msat: u64,
+        _description: &str,
+        _options: &InvoiceOptions,
+    ) -> Result<OwnInvoice> {
+        Err(no_node("create invoices, pass one with --pr"))
+    }
+
+    async fn find_invoice(&mut self, _bolt11: &str) -> Result<Option<String>> {
+        // Never ours: the withdraw waits on the server instead
+        Ok(None)
+    }
+
+    async fn wait_invoice(&mut self, _handle: &str) -> Result<ReceivedPayment> {
+        Err(no_node("receive payments"))
+    }
+
+    async fn pay(&mut self, _bolt11: &str) -> Result<SentPayment> {
+        Err(no_node("pay invoices"))
+    }
+
+    async fn fetch_invoice(
+        &mut self,
+        _offer: &str,
+        _amount_msat: u64,
+        _payer_note: Option<&str>,
+    ) -> Result<String> {
+        Err(no_node("pay offers"))
+    }
+
+    async fn sign_message(&mut self, message: &str) -> Result<String> {
+        Ok(self.identity.sign_message_zbase(message))
+    }
+
+    async fn channel_progress(
+        &mut self,
+        _peer_id: &str,
+        _funding_txid: Option<&str>,
+    ) -> Result<Option<ChannelProgress>> {
+        Err(no_node("open channels"))
+    }
+}
@@ -0,0 +1,426 @@
+// =============================================================================
+// request-withdraw (LUD-03)
+// =============================================================================
+//
+// Flow:
+//   1. GET /request-withdraw         → { callback, k1, min/maxWithdrawable, ... }
+//   2. Pick an amount, the maximum unless asked for less
+//   3. Create an invoice for it with our node (or take one we're given)
+//   4. GET <callback>?k1=<k1>&pr=<bolt11>[&balanceNotify=<url>]
+//   5. Wait for the payment: through the node if the invoice is ours, and
+//      through our server's /withdraw-status, which also tells when its
+//      payment failed and nothing is coming
+//
+// Steps 2-4 are `submit`, step 5 is `PendingWithdraw::wait`, so a caller can
+// tell the user the server accepted the invoice before the wait begins.
+
+use anyhow::Result;
+use lnurl_models::{
+    LnurlParams, StatusResponse, WithdrawRequest, WithdrawStatusResponse, WithdrawalStatus,
+};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::error::{lnurl_error, payment_error, usage_error};
+use crate::history;
+use crate::http::Http;
+use crate::target::Target;
+use crate::wallet::{InvoiceOptions, ReceivedPayment, RouteHints, Wallet};
+
+pub const DEFAULT_INVOICE_EXPIRY_SECS: u64 = 600;
+pub const DEFAULT_WITHDRAW_WAIT_SECS: u64 = 600;
+const WITHDRAW_STATUS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How the withdraw is carried out, whichever invoice is used
+#[derive(Debug)]
+pub struct WithdrawOptions {
+    pub wait_timeout: Duration,      // for the payment to arrive
+    pub balance_notify: Option<Url>, // LUD-15, sent with the callback
+}
+
+impl Default for WithdrawOptions {
+    fn default() -> Self {
+        WithdrawOptions {
+            wait_timeout: Duration::from_secs(DEFAULT_WITHDRAW_WAIT_SECS),
+            balance_notify: None,
+        }
+    }
+}
+
+/// The invoice handed to the withdraw callback
+#[derive(Debug)]
+pub enum WithdrawInvoice {
+    /// Created by our node. No amount withdraws maxWithdrawable, no
+    /// description uses the server's defaultDescription.
+    Create {
+        amount_msat: Option<u64>,
+        description: Option<String>,
+        options: InvoiceOptions,
+    },
+    /// A BOLT-11 invoice from elsewhere, e.g. another wallet
+    Existing(String),
+}
+
+impl Default for WithdrawInvoice {
+    /// maxWithdrawable into a fresh invoice
+    fn default() -> Self {
+        WithdrawInvoice::Create {
+            amount_msat: None,
+            description: None,
+            options: default_invoice_options(),
+        }
+    }
+}
+
+/// Invoice options for a withdraw that didn't ask for anything special
+pub fn default_invoice_options() -> InvoiceOptions {
+    InvoiceOptions {
+        expiry_secs: DEFAULT_INVOICE_EXPIRY_SECS,
+        cltv: None,
+        route_hints: RouteHints::Default,
+    }
+}
+
+/// How we know the invoice was paid
+#[derive(Debug, Clone)]
+pub enum Confirmation {
+    /// Our node received the payment
+    Received(ReceivedPayment),
+    /// The server reports paying it, into another wallet's invoice
+    Server,
+    /// Nobody can tell: the invoice is another wallet's and the server has
+    /// no /withdraw-status
+    Unconfirmed,
+}
+
+/// How a withdraw ended
+#[derive(Debug, Clone)]
+pub struct WithdrawOutcome {
+    pub amount_msat: u64,
+    pub bolt11: String,
+    pub confirmation: Confirmation,
+    /// From our node or the server, whichever had it
+    pub preimage: Option<Vec<u8>>,
+    /// The preimage hashes to the invoice's payment hash, proof of payment
+    /// that doesn't rest on anyone's word. A mismatch fails the withdraw.
+    pub preimage_verified: bool,
+}
+
+/// An invoice the server accepted, and is paying
+#[derive(Debug)]
+pub struct PendingWithdraw {
+    pub amount_msat: u64,
+    pub bolt11: String,
+    handle: Option<String>, // if our node issued the invoice
+    payment_hash: Option<[u8; 32]>,
+    status_url: Option<Url>,
+    wait_timeout: Duration,
+}
+
+/// Step 1: GET /request-withdraw
+pub async fn fetch_request(http: &Http, target: &Target) -> Result<WithdrawRequest> {
+    http.get_params(&target.endpoint("request-withdraw"), LnurlParams::into_withdraw_request)
+        .await
+}
+
+/// The whole withdraw: fetches the request at `target` and redeems it
+pub async fn withdraw(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    target: &Target,
+    invoice: WithdrawInvoice,
+    options: &WithdrawOptions,
+) -> Result<WithdrawOutcome> {
+    info!("Requesting withdraw info from {}...", target);
+    let request = fetch_request(http, target).await?;
+    redeem(wallet, http, &request, invoice, options).await
+}
+
+/// Steps 2-5, once the request params are known
+pub async fn redeem(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    request: &WithdrawRequest,
+    invoice: WithdrawInvoice,
+    options: &WithdrawOptions,
+) -> Result<WithdrawOutcome> {
+    submit(wallet, http, request, invoice, options)
+        .await?
+        .wait(wallet, http)
+        .await
+}
+
+pub fn check_bounds(amount_msat: u64, request: &WithdrawRequest) -> Result<()> {
+    if amount_msat < request.min_withdrawable || amount_msat > request.max_withdrawable {
+        return Err(usage_error!(
+            "Amount {} msat is outside the allowed range {}-{} msat",
+            amount_msat,
+            request.min_withdrawable,
+            request.max_withdrawable
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the amount of a BOLT-11 invoice, and its wallet handle if our own
+/// node issued it
+async fn inspect_invoice(wallet: &mut dyn Wallet, bolt11: &str) -> Result<(u64, Option<String>)> {
+    let decoded = wallet.decode_invoice(bolt11).await?;
+    if !decoded.valid {
+        return Err(usage_error!("Invalid invoice: {}", bolt11));
+    }
+    let amount_msat = decoded
+        .amount_msat
+        .ok_or_else(|| usage_error!("The invoice has no amount, withdraw invoices need one"))?;
+    let handle = wallet.find_invoice(bolt11).await?;
+
+    Ok((amount_msat, handle))
+}
+
+/// Steps 2-4: pick the amount, get the invoice, hand it to the callback
+pub async fn submit(
+    wallet: &mut dyn Wallet,
+    http: &Http,
+    request: &WithdrawRequest,
+    invoice: WithdrawInvoice,
+    options: &WithdrawOptions,
+) -> Result<PendingWithdraw> {
+    info!(
+        callback = %request.callback,
+        k1 = %request.k1,
+        min_withdrawable_msat = request.min_withdrawable,
+        max_withdrawable_msat = request.max_withdrawable,
+        description = %request.default_description,
+        "Received withdraw request"
+    );
+
+    let (amount_msat, bolt11, handle) = match invoice {
+        WithdrawInvoice::Create {
+            amount_msat,
+            description,
+            options: invoice_options,
+        } => {
+            // Step 2: Pick an amount (the maximum available unless one was given)
+            let amount_msat = amount_msat.unwrap_or(request.max_withdrawable);
+            check_bounds(amount_msat, request)?;
+            info!("Withdrawing {} msat...", amount_msat);
+
+            // Step 3: Create a BOLT-11 invoice with our node
+            let description = description
+                .as_deref()
+                .or(Some(request.default_description.as_str()).filter(|d| !d.is_empty()))
+                .unwrap_or("LNURL withdraw");
+            let invoice = wallet
+                .create_invoice(amount_msat, description, &invoice_options)
+                .await?;
+            info!("Created invoice: {}", invoice.bolt11);
+            (amount_msat, invoice.bolt11, Some(invoice.handle))
+        }
+        WithdrawInvoice::Existing(bolt11) => {
+            // Steps 2-3 are skipped: the amount comes from the given invoice.
+            // We can only wait for the payment if our own node issued it.
+            let (amount_msat, handle) = inspect_invoice(wallet, &bolt11).await?;
+            check_bounds(amount_msat, request)?;
+            info!("Withdrawing {} msat into the given invoice...", amount_msat);
+            (amount_msat, bolt11, handle)
+        }
+    };
+    history::note(|operation| {
+        operation.amount_msat = Some(amount_msat);
+        operation.invoice = Some(bolt11.clone());
+    });
+
+    // For checking the preimage we may get back. Decoding can't wait until
+    // then, the node is busy waiting for the payment.
+    let payment_hash = wallet.decode_invoice(&bolt11).await?.payment_hash;
+
+    // Step 4: GET /withdraw?k1=<k1>&pr=<bolt11>[&balanceNotify=<url>]
+    let callback_url = callback_url(request, &bolt11, options)?;
+    info!("Calling withdraw callback");
+
+    let cb_resp: StatusResponse = http.callback_json(callback_url.as_str()).await?;
+    if !cb_resp.is_ok() {
+        return Err(lnurl_error!(
+            "Withdraw failed: {}",
+            cb_resp.reason.unwrap_or_else(|| "unknown".to_string())
+        ));
+    }
+
+    Ok(PendingWithdraw {
+        amount_msat,
+        bolt11,
+        handle,
+        payment_hash,
+        status_url: status_url(request),
+        wait_timeout: options.wait_timeout,
+    })
+}
+
+pub fn callback_url(
+    request: &WithdrawRequest,
+    bolt11: &str,
+    options: &WithdrawOptions,
+) -> Result<Url> {
+    let mut callback_url = Url::parse(&request.callback)
+        .map_err(|e| lnurl_error!("Invalid callback URL {}: {}", request.callback, e))?;
+    {
+        let mut query = callback_url.query_pairs_mut();
+        query.append_pair("k1", &request.k1).append_pair("pr", bolt11);
+        if let Some(balance_notify) = &options.balance_notify {
+            query.append_pair("balanceNotify", balance_notify.as_str());
+        }
+    }
+    Ok(callback_url)
+}
+
+/// /withdraw-status next to the callback, on our server
+fn status_url(request: &WithdrawRequest) -> Option<Url> {
+    let mut url = Url::parse(&request.callback).ok()?.join("withdraw-status").ok()?;
+    url.query_pairs_mut().append_pair("k1", &request.k1);
+    Some(url)
+}
+
+impl PendingWithdraw {
+    /// Whether our node issued the invoice, so the payment can be seen
+    /// arriving rather than only taken from the server
+    pub fn is_own_invoice(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Step 5: waits for the payment, up to the wait timeout
+    pub async fn wait(self, wallet: &mut dyn Wallet, http: &Http) -> Result<WithdrawOutcome> {
+        let server_outcome = watch_withdraw_status(http, self.status_url.as_ref());
+        let deadline = tokio::time::sleep(self.wait_timeout);
+        tokio::pin!(server_outcome, deadline);
+
+        let (confirmation, preimage) = match &self.handle {
+            None => tokio::select! {
+                outcome = &mut server_outcome => match outcome {
+                    Some(ServerOutcome::Paid(preimage)) => (Confirmation::Server, preimage),
+                    Some(ServerOutcome::Failed) => {
+                        return Err(payment_error!("The server failed to pay the invoice"))
+                    }
+                    None => (Confirmation::Unconfirmed, None),
+                },
+                _ = &mut deadline => return Err(payment_error!(
+                    "The server had not paid after the {}s wait timeout",
+                    self.wait_timeout.as_secs()
+                )),
+            },
+            Some(handle) => {
+                let invoice_paid = wallet.wait_invoice(handle);
+                tokio::pin!(invoice_paid);
+                let mut watching_server = true;
+                loop {
+                    tokio::select! {
+                        result = &mut invoice_paid => {
+                            let payment = result
+                                .map_err(|e| payment_error!("Invoice was not paid: {}", e))?;
+                            let preimage = payment.preimage.clone();
+                            break (Confirmation::Received(payment), preimage);
+                        }
+                        // Once the server reports paid, waitinvoice returns right after
+                        outcome = &mut server_outcome, if watching_server => {
+                            if matches!(outcome, Some(ServerOutcome::Failed)) {
+                                return Err(payment_error!("The server failed to pay the invoice"));
+                            }
+                            watching_server = false;
+                        }
+                        _ = &mut deadline => {
+                            return Err(payment_error!(
+                                "Invoice was not paid within the {}s wait timeout",
+                                self.wait_timeout.as_secs()
+                            ));
+                        }
+                    }
+                }
+            }
+        };
+
+        let preimage_verified = match &preimage {
+            Some(preimage) => {
+                history::note(|operation| operation.preimage = Some(hex::encode(preimage)));
+                verify_preimage(self.payment_hash, preimage)?
+            }
+            None => false,
+        };
+        Ok(WithdrawOutcome {
+            amount_msat: self.amount_msat,
+            bolt11: self.bolt11,
+            confirmation,
+            preimage,
+            preimage_verified,
+        })
+    }
+}
+
+/// Checks that the preimage hashes to the invoice's payment hash. False if
+/// there is no payment hash to check against.
+fn verify_preimage(payment_hash: Option<[u8; 32]>, preimage: &[u8]) -> Result<bool> {
+    let Some(payment_hash) = payment_hash else {
+        warn!("The invoice has no payment hash, the preimage can't be checked");
+        return Ok(false);
+    };
+    let hash: [u8; 32] = Sha256::digest(preimage).into();
+    if hash != payment_hash {
+        return Err(payment_error!(
+            "The preimage {} does not match the invoice's payment hash {}",
+            hex::encode(preimage),
+            hex::encode(payment_hash)
+        ));
+    }
+    Ok(true)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ServerOutcome {
+    Paid(Option<Vec<u8>>), // the preimage, if the server shares it
+    Failed,
+}
+
+/// Polls /withdraw-status until the server's payment settles one way or the
+/// other. None if the server can't tell us: not our server, an older one,
+/// or the endpoint failed.
+async fn watch_withdraw_status(http: &Http, url: Option<&Url>) -> Option<ServerOutcome> {
+    let url = url?;
+    loop {
+        let status = match http.get_json_if_found::<WithdrawStatusResponse>(url.as_str()).await {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                debug!("Server has no /withdraw-status");
+                return None;
+            }
+            Err(e) => {
+                warn!("Withdraw status unavailable: {:#}", e);
+                return None;
+            }
+        };
+        if !status.is_ok() {
+            warn!(
+                "Withdraw status unavailable: {}",
+                status.reason.as_deref().unwrap_or("unknown")
+            );
+            return None;
+        }
+        match status.withdrawal_status {
+            Some(WithdrawalStatus::Paid) => {
+                // A bad preimage is as good as none, the payment still happened
+                let preimage = status.preimage.as_deref().and_then(|preimage| {
+                    hex::decode(preimage)
+                        .inspect_err(|_| warn!("Server sent a bad preimage: {}", preimage))
+                        .ok()
+                });
+                return Some(ServerOutcome::Paid(preimage));
+            }
+            Some(WithdrawalStatus::Failed) => return Some(ServerOutcome::Failed),
+            Some(WithdrawalStatus::Pending) => debug!("Server payment still pending"),
+            None => {
+                warn!("Server sent no withdraw status");
+                return None;
+            }
+        }
+        tokio::time::sleep(WITHDRAW_STATUS_INTERVAL).await;
+    }
+}
@@ -0,0 +1,167 @@
+// =============================================================================
+// Golden vectors
+// =============================================================================
+//
+// Encodings other wallets must agree with byte for byte. A failure here means
+// an interop break, not a test to update:
+//
+//   LUD-01 — the spec's bech32 LNURL, and the links our server hands out
+//   LUD-06 — description hashes: sha256 of the metadata string as sent
+//
+// The frozen values were computed independently of this crate (Python's
+// hashlib and the BIP-173 reference bech32 code).
+
+use lnurl_client::pay::{description_hash, metadata_description};
+use lnurl_client::target::{decode_lnurl, encode_lnurl};
+use lnurl_client::{parse_target, Target};
+use url::Url;
+
+// -----------------------------------------------------------------------------
+// LUD-01
+// -----------------------------------------------------------------------------
+
+/// The example in LUD-01
+const LUD01_LNURL: &str = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+const LUD01_URL: &str =
+    "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+
+/// What our server's endpoints encode to
+const SERVER_LNURLS: [(&str, &str); 3] = [
+    (
+        "http://192.168.27.72:3000/request-withdraw",
+        "LNURL1DP68GUP69UHNZWFJ9CCNVWPWXGMJUDEJ8GENQVPS9AEX2UT4V4EHGTTHD96XSERJV9MSTG7QVJ",
+    ),
+    (
+        "http://192.168.27.72:3000/request-channel",
+        "LNURL1DP68GUP69UHNZWFJ9CCNVWPWXGMJUDEJ8GENQVPS9AEX2UT4V4EHGTTRDPSKUMN9DSYMEJKU",
+    ),
+    (
+        "http://192.168.27.72:3000/auth-challenge",
+        "LNURL1DP68GUP69UHNZWFJ9CCNVWPWXGMJUDEJ8GENQVPS9ASH2ARG943KSCTVD3JKUEM97TZ6YJ",
+    ),
+];
+
+#[test]
+fn lud01_vector_decodes() {
+    assert_eq!(decode_lnurl(LUD01_LNURL).unwrap().as_str(), LUD01_URL);
+    assert_eq!(
+        decode_lnurl(&LUD01_LNURL.to_lowercase()).unwrap().as_str(),
+        LUD01_URL
+    );
+}
+
+#[test]
+fn lud01_vector_encodes() {
+    let url = Url::parse(LUD01_URL).unwrap();
+    assert_eq!(encode_lnurl(&url).unwrap(), LUD01_LNURL);
+}
+
+#[test]
+fn server_links_are_frozen() {
+    for (url, lnurl) in SERVER_LNURLS {
+        assert_eq!(
+            encode_lnurl(&Url::parse(url).unwrap()).unwrap(),
+            lnurl,
+            "{}",
+            url
+        );
+        assert_eq!(decode_lnurl(lnurl).unwrap().as_str(), url);
+    }
+}
+
+#[test]
+fn lnurls_parse_as_the_endpoint_they_encode() {
+    for input in [
+        LUD01_LNURL.to_string(),
+        LUD01_LNURL.to_lowercase(),
+        format!("lightning:{}", LUD01_LNURL),
+        format!("LIGHTNING:{}", LUD01_LNURL.to_lowercase()),
+    ] {
+        match parse_target(&input).unwrap() {
+            Target::Endpoint(url) => assert_eq!(url.as_str(), LUD01_URL, "{}", input),
+            other => panic!("{} parsed as {:?}", input, other),
+        }
+    }
+}
+
+#[test]
+fn corrupted_lnurls_are_rejected() {
+    // One character off fails the checksum
+    let mut flipped = LUD01_LNURL.to_string();
+    flipped.replace_range(20..21, "Q");
+    assert_ne!(flipped, LUD01_LNURL);
+    assert!(decode_lnurl(&flipped).is_err());
+
+    // Bech32 is all upper or all lower case
+    let mixed = format!("lnurl1{}", &LUD01_LNURL[6..]);
+    assert!(decode_lnurl(&mixed).is_err());
+
+    // Right checksum, wrong prefix
+    assert!(decode_lnurl("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").is_err());
+}
+
+// -----------------------------------------------------------------------------
+// LUD-06
+// -----------------------------------------------------------------------------
+
+/// (metadata as sent, its text/plain, sha256 hex)
+const METADATA: [(&str, &str, &str); 4] = [
+    (
+        r#"[["text/plain", "lorem ipsum blah blah"]]"#,
+        "lorem ipsum blah blah",
+        "f7d6f565d86b0065d2d2af771106de4f8477f1f14b4cca4c009c0d53bb9be25f",
+    ),
+    (
+        r#"[["text/plain","Pay to satoshi"],["text/identifier","satoshi@service.com"]]"#,
+        "Pay to satoshi",
+        "3e3865d65abdf143f036fc06fb0cd2809fa013cfb4f30ed45ff86311d7dbafca",
+    ),
+    (
+        r#"[["text/plain","Withdrawal from service"],["image/png;base64","iVBORw0KGgo="]]"#,
+        "Withdrawal from service",
+        "6b092de9939c7fbddb0d7474183d4370cc27d8da4c7d887603daaedf29f5602b",
+    ),
+    (
+        r#"[["text/plain","Café ☕ for 1 sat"]]"#,
+        "Café ☕ for 1 sat",
+        "f419f58f001ff1179558ed9ca76a2d926dab83088ee6654e251672ef43e2ceb7",
+    ),
+];
+
+#[test]
+fn description_hashes_are_frozen() {
+    for (metadata, description, hash) in METADATA {
+        assert_eq!(metadata_description(metadata).unwrap(), description);
+        assert_eq!(
+            hex::encode(description_hash(metadata)),
+            hash,
+            "{}",
+            metadata
+        );
+    }
+}
+
+#[test]
+fn description_hash_is_over_the_exact_string() {
+    // The same JSON without the space hashes differently: wallets must not
+    // re-serialize the metadata before hashing it
+    let compact = r#"[["text/plain","lorem ipsum blah blah"]]"#;
+    assert_eq!(
+        hex::encode(description_hash(compact)),
+        "d824d0ea606c5a9665279c31cf185528a8df2875ea93f1f75e501e354b33e90a"
+    );
+    assert_ne!(description_hash(compact), description_hash(METADATA[0].0));
+}
+
+#[test]
+fn malformed_metadata_is_rejected() {
+    for metadata in [
+        "not json",
+        r#"{"text/plain": "lorem"}"#,
+        r#"[["image/png;base64","iVBORw0KGgo="]]"#,
+        r#"[["text/plain","one"],["text/plain","two"]]"#,
+        r#"[["text/plain"]]"#,
+    ] {
+        assert!(metadata_description(metadata).is_err(), "{}", metadata);
+    }
+}
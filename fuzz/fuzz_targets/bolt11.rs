@@ -0,0 +1,19 @@
+// Arbitrary invoices against a live withdraw k1. The mock node decodes
+// `lntb<msat>`, so besides malformed strings the fuzzer reaches amountless
+// invoices and every amount up to u64::MAX.
+
+#![no_main]
+
+mod common;
+
+use common::{block_on, escape, get, k1};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let pr = escape(data);
+    block_on(async {
+        let k1 = k1("/request-withdraw").await;
+        get(&format!("/withdraw?k1={}&pr={}", k1, pr)).await;
+        get(&format!("/withdraw-status?k1={}", k1)).await;
+    });
+});
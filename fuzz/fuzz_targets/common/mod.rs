@@ -0,0 +1,229 @@
+// =============================================================================
+// Shared fuzzing harness
+// =============================================================================
+//
+// One router over a mock node and in-memory storage, kept across runs on a
+// single-threaded runtime, plus helpers to fetch fresh k1s and to put
+// arbitrary bytes into a query string. The mock decodes `lntb<msat>`
+// invoices like the handler tests do, so the fuzzer can reach every amount
+// check; only GOOD_SIGNATURE verifies, and the invoices it issues are
+// never paid.
+
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode, Uri};
+use axum::Router;
+use cln_rpc::primitives::PublicKey;
+use lnurl_server::backend::{
+    Backend, BackendResult, ChannelBalance, ChannelFees, DecodedInvoice, FeeUpdate, FundedChannel,
+    Funds, InvoiceStatus, IssuedInvoice, NewInvoice, NodeStatus, Payment,
+};
+use lnurl_server::storage::MemoryStorage;
+use lnurl_server::{app, AppState, IP_ADDRESS, NODE_URI};
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+pub const NODE_ID: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+pub const WALLET_ID: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+pub const GOOD_SIGNATURE: &str = "d9good";
+
+struct MockNode;
+
+#[async_trait]
+impl Backend for MockNode {
+    async fn node_id(&self) -> BackendResult<String> {
+        Ok(NODE_ID.to_string())
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice> {
+        let amount_msat = match bolt11.strip_prefix("lntb") {
+            Some("") => None,
+            Some(amount) => Some(
+                amount
+                    .parse()
+                    .map_err(|_| "Invalid bech32 string".to_string())?,
+            ),
+            None => return Err("Invalid bech32 string".to_string().into()),
+        };
+        Ok(DecodedInvoice {
+            amount_msat,
+            description: None,
+        })
+    }
+
+    async fn fund_channel(
+        &self,
+        _node_id: PublicKey,
+        _capacity_sat: u64,
+        _announce: bool,
+    ) -> BackendResult<FundedChannel> {
+        Ok(FundedChannel {
+            channel_id: "cc".repeat(32),
+            txid: "aa".repeat(32),
+            tx: "0200".to_string(),
+            outnum: 1,
+            mindepth: Some(3),
+        })
+    }
+
+    async fn pay(&self, _bolt11: &str) -> BackendResult<Payment> {
+        Ok(Payment {
+            preimage: vec![0x11; 32],
+            amount_sent_msat: 0,
+        })
+    }
+
+    async fn check_message(
+        &self,
+        _message: &str,
+        zbase: &str,
+        _pubkey: PublicKey,
+    ) -> BackendResult<bool> {
+        Ok(zbase == GOOD_SIGNATURE)
+    }
+
+    /// One usable channel, deep enough for any withdraw
+    async fn funds(&self) -> BackendResult<Funds> {
+        Ok(Funds {
+            onchain_sat: 250_000,
+            onchain_unconfirmed_sat: 0,
+            channels: vec![ChannelBalance {
+                peer_id: WALLET_ID.to_string(),
+                channel_id: Some("cc".repeat(32)), // the one fund_channel opens
+                short_channel_id: Some("80000x1x0".to_string()),
+                state: "CHANNELD_NORMAL".to_string(),
+                outbound_msat: u64::MAX / 200,
+                inbound_msat: 0,
+            }],
+        })
+    }
+
+    /// Settings not in the update are CLN's defaults
+    async fn set_channel_fees(
+        &self,
+        _channel_id: &str,
+        update: &FeeUpdate,
+    ) -> BackendResult<ChannelFees> {
+        Ok(ChannelFees {
+            base_msat: update.base_msat.unwrap_or(1_000),
+            ppm: update.ppm.unwrap_or(10),
+            htlc_min_msat: update.htlc_min_msat.unwrap_or(0),
+            htlc_max_msat: update.htlc_max_msat.unwrap_or(99_000_000),
+        })
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        _label: &str,
+        _description: &str,
+        _expiry_secs: u64,
+    ) -> BackendResult<String> {
+        Ok(format!("lntb{}", amount_msat))
+    }
+
+    async fn create_invoice_hashed(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+        _preimage: Option<&[u8; 32]>,
+    ) -> BackendResult<NewInvoice> {
+        let bolt11 = self
+            .create_invoice(amount_msat, label, description, expiry_secs)
+            .await?;
+        Ok(NewInvoice {
+            bolt11,
+            payment_hash: "ee".repeat(32),
+        })
+    }
+
+    /// Keeps no invoices, so none is ever found paid
+    async fn invoice_status(&self, _label: &str) -> BackendResult<Option<InvoiceStatus>> {
+        Ok(None)
+    }
+
+    async fn invoice_by_hash(&self, _payment_hash: &str) -> BackendResult<Option<IssuedInvoice>> {
+        Ok(None)
+    }
+
+    async fn opening_feerate_perkb(&self) -> BackendResult<u64> {
+        Ok(1_000)
+    }
+
+    /// Synced, at a fixed height
+    async fn node_status(&self) -> BackendResult<NodeStatus> {
+        Ok(NodeStatus {
+            blockheight: 800_000,
+            sync_warning: None,
+        })
+    }
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    })
+}
+
+fn router() -> &'static Router {
+    static ROUTER: OnceLock<Router> = OnceLock::new();
+    ROUTER.get_or_init(|| {
+        NODE_URI.get_or_init(|| format!("{}@{}", NODE_ID, IP_ADDRESS));
+        app(AppState::new(
+            Arc::new(MockNode),
+            Arc::new(MemoryStorage::default()),
+        ))
+    })
+}
+
+/// Runs `future` to completion, along with whatever it spawned meanwhile
+/// (background payments)
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// GETs `uri`, returning the status and the body when it is JSON. Panics in
+/// the handlers propagate, which is what the fuzzer is looking for.
+pub async fn get(uri: &str) -> (StatusCode, serde_json::Value) {
+    let Ok(uri) = uri.parse::<Uri>() else {
+        return (StatusCode::BAD_REQUEST, serde_json::Value::Null);
+    };
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let response = router().clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+    (status, json)
+}
+
+/// A fresh k1 from `/request-withdraw`, `/request-channel` or `/auth-challenge`
+pub async fn k1(endpoint: &str) -> String {
+    let (status, body) = get(endpoint).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["k1"].as_str().expect("k1 in response").to_string()
+}
+
+/// Percent-encodes every byte but the unreserved ones, so any input makes a
+/// valid query value that decodes back to the same bytes
+pub fn escape(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for &b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
@@ -0,0 +1,23 @@
+// Arbitrary k1s in every callback that takes one: unknown, already spent and
+// malformed k1s must all be refused without a panic.
+
+#![no_main]
+
+mod common;
+
+use common::{block_on, escape, get, GOOD_SIGNATURE, WALLET_ID};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let k1 = escape(data);
+    block_on(async {
+        get(&format!("/withdraw?k1={}&pr=lntb10000", k1)).await;
+        get(&format!("/withdraw-status?k1={}", k1)).await;
+        get(&format!("/open-channel?remoteid={}&k1={}", WALLET_ID, k1)).await;
+        get(&format!(
+            "/auth-response?k1={}&signature={}&pubkey={}",
+            k1, GOOD_SIGNATURE, WALLET_ID
+        ))
+        .await;
+    });
+});
@@ -0,0 +1,21 @@
+// Arbitrary strings through the client's decoders: bech32 LNURLs, the
+// targets the CLI accepts, and the k1 of login links.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lnurl_client::auth::login_k1;
+use lnurl_client::target::{decode_lnurl, parse_pay_target, parse_target};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(url) = decode_lnurl(input) {
+        let _ = login_k1(&url);
+    }
+    if let Ok(target) = parse_target(input) {
+        let _ = login_k1(target.url());
+    }
+    let _ = parse_pay_target(input);
+});
@@ -0,0 +1,24 @@
+// Arbitrary node pubkeys against live k1s: the channel callback's `remoteid`
+// and the auth callback's `pubkey`.
+
+#![no_main]
+
+mod common;
+
+use common::{block_on, escape, get, k1, GOOD_SIGNATURE};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let pubkey = escape(data);
+    block_on(async {
+        let channel_k1 = k1("/request-channel").await;
+        get(&format!("/open-channel?remoteid={}&k1={}", pubkey, channel_k1)).await;
+
+        let auth_k1 = k1("/auth-challenge").await;
+        get(&format!(
+            "/auth-response?k1={}&signature={}&pubkey={}",
+            auth_k1, GOOD_SIGNATURE, pubkey
+        ))
+        .await;
+    });
+});
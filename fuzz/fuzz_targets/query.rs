@@ -0,0 +1,30 @@
+// Raw query strings on every endpoint: missing, repeated and mistyped
+// parameters, bad percent-encoding. The first byte picks the endpoint.
+
+#![no_main]
+
+mod common;
+
+use common::{block_on, get};
+use libfuzzer_sys::fuzz_target;
+
+const ENDPOINTS: [&str; 7] = [
+    "/request-channel",
+    "/open-channel",
+    "/request-withdraw",
+    "/withdraw",
+    "/withdraw-status",
+    "/auth-challenge",
+    "/auth-response",
+];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&pick, query)) = data.split_first() else {
+        return;
+    };
+    let endpoint = ENDPOINTS[pick as usize % ENDPOINTS.len()];
+    let query = String::from_utf8_lossy(query);
+    block_on(async {
+        get(&format!("{}?{}", endpoint, query)).await;
+    });
+});
@@ -0,0 +1,126 @@
+// LUD-04: the wallet signs a k1 with a key of its own and sends it back with
+// the pubkey. A spec login link carries the k1 in its URL (tag=login); our
+// server also hands one out at /auth-challenge, and answers a successful
+// login with a session token.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Status;
+
+/// The `event` of a successful login
+pub const LOGGED_IN_EVENT: &str = "LOGGEDIN";
+/// The `event` of a first login, which created the account
+pub const REGISTERED_EVENT: &str = "REGISTERED";
+
+/// GET /auth-challenge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthChallenge {
+    pub k1: String, // hex, 32 random bytes
+    /// The LUD-04 login link of the k1 (tag=login), for a wallet to sign
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lnurl: Option<String>, // url as an LNURL
+}
+
+/// The login callback's reply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthResponse {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Our server's session, sent back as `Authorization: Bearer <token>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// A successful login, as our server's auth webhook delivers it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginEvent {
+    pub event: String, // REGISTERED_EVENT or LOGGED_IN_EVENT
+    pub linking_key: String,
+    /// LUD-04's `action` of the login link: register, login, link or auth
+    pub action: String,
+    pub at: u64, // unix seconds
+}
+
+impl AuthResponse {
+    pub fn logged_in(token: String) -> AuthResponse {
+        AuthResponse {
+            status: Status::Ok,
+            event: Some(LOGGED_IN_EVENT.to_string()),
+            reason: None,
+            token: Some(token),
+        }
+    }
+
+    pub fn registered(token: String) -> AuthResponse {
+        AuthResponse {
+            event: Some(REGISTERED_EVENT.to_string()),
+            ..AuthResponse::logged_in(token)
+        }
+    }
+
+    pub fn error(reason: impl Into<String>) -> AuthResponse {
+        AuthResponse {
+            status: Status::Error,
+            event: None,
+            reason: Some(reason.into()),
+            token: None,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn logged_in() {
+        assert_eq!(
+            serde_json::to_value(AuthResponse::logged_in("t0ken".to_string())).unwrap(),
+            json!({"status": "OK", "event": "LOGGEDIN", "token": "t0ken"})
+        );
+        assert_eq!(
+            serde_json::to_value(AuthResponse::registered("t0ken".to_string())).unwrap(),
+            json!({"status": "OK", "event": "REGISTERED", "token": "t0ken"})
+        );
+    }
+
+    #[test]
+    fn error() {
+        let error = AuthResponse::error("Signature verification failed");
+        assert!(!error.is_ok());
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            json!({"status": "ERROR", "reason": "Signature verification failed"})
+        );
+    }
+
+    #[test]
+    fn spec_only_reply_parses() {
+        // Any LUD-04 service, which has no sessions to hand out
+        let reply: AuthResponse = serde_json::from_value(json!({"status": "OK"})).unwrap();
+        assert!(reply.is_ok());
+        assert_eq!(reply.event, None);
+        assert_eq!(reply.token, None);
+    }
+
+    #[test]
+    fn challenge() {
+        let challenge: AuthChallenge = serde_json::from_value(json!({"k1": "ab"})).unwrap();
+        assert_eq!(challenge.k1, "ab");
+        assert_eq!(challenge.url, None);
+        assert_eq!(
+            serde_json::to_value(challenge).unwrap(),
+            json!({"k1": "ab"})
+        );
+    }
+}
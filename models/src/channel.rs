@@ -0,0 +1,146 @@
+// LUD-02: the server hands out its node URI, the wallet connects and asks
+// for a channel through the callback (remoteid, k1, private, or cancel=1).
+
+use serde::{Deserialize, Serialize};
+
+use crate::Status;
+
+/// LUD-02 channelRequest, less its tag (see LnurlParams)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelRequest {
+    pub uri: String, // <pubkey>@<host>:<port>
+    pub callback: String,
+    pub k1: String,
+    /// Not LUD-02: a server selling channels asks for this bolt11 to be
+    /// paid before it opens one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pr: Option<String>,
+    /// What the payment buys, with `pr`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity_sat: Option<u64>,
+}
+
+/// The open-channel callback's reply. LUD-02 only asks for a status; the
+/// rest is what our server tells about the channel it opened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenChannelResponse {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mindepth: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>, // hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outnum: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<String>, // hex, the funding transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity_sat: Option<u64>, // what the server actually opened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private: Option<bool>,
+}
+
+impl OpenChannelResponse {
+    /// OK without details, for a cancelled request; opened channels fill
+    /// in the rest
+    pub fn ok() -> OpenChannelResponse {
+        OpenChannelResponse {
+            status: Status::Ok,
+            reason: None,
+            mindepth: None,
+            channel_id: None,
+            outnum: None,
+            tx: None,
+            txid: None,
+            capacity_sat: None,
+            private: None,
+        }
+    }
+
+    pub fn error(reason: impl Into<String>) -> OpenChannelResponse {
+        OpenChannelResponse {
+            status: Status::Error,
+            reason: Some(reason.into()),
+            ..OpenChannelResponse::ok()
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn bare_ok_has_only_a_status() {
+        assert_eq!(
+            serde_json::to_value(OpenChannelResponse::ok()).unwrap(),
+            json!({"status": "OK"})
+        );
+    }
+
+    #[test]
+    fn error_has_a_reason() {
+        let error = OpenChannelResponse::error("private must be 1 or 0");
+        assert!(!error.is_ok());
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            json!({"status": "ERROR", "reason": "private must be 1 or 0"})
+        );
+    }
+
+    #[test]
+    fn opened_channel_details_round_trip() {
+        let opened = OpenChannelResponse {
+            mindepth: Some(3),
+            channel_id: Some("ab".repeat(32)),
+            outnum: Some(1),
+            txid: Some("cd".repeat(32)),
+            capacity_sat: Some(100_000),
+            private: Some(true),
+            ..OpenChannelResponse::ok()
+        };
+        let value = serde_json::to_value(&opened).unwrap();
+        assert_eq!(value["capacity_sat"], 100_000);
+        assert_eq!(value["private"], true);
+        assert!(value.get("tx").is_none());
+        assert_eq!(
+            serde_json::from_value::<OpenChannelResponse>(value).unwrap(),
+            opened
+        );
+    }
+
+    #[test]
+    fn spec_only_reply_parses() {
+        // Any LUD-02 server, which sends nothing but the status
+        let reply: OpenChannelResponse = serde_json::from_value(json!({"status": "OK"})).unwrap();
+        assert!(reply.is_ok());
+        assert_eq!(reply.txid, None);
+    }
+
+    #[test]
+    fn channel_request_needs_uri_callback_and_k1() {
+        let request = json!({
+            "uri": "02abc@127.0.0.1:9735",
+            "callback": "https://service.example/open-channel",
+            "k1": "k1",
+        });
+        assert!(serde_json::from_value::<ChannelRequest>(request.clone()).is_ok());
+        for field in ["uri", "callback", "k1"] {
+            let mut partial = request.clone();
+            partial.as_object_mut().unwrap().remove(field);
+            assert!(
+                serde_json::from_value::<ChannelRequest>(partial).is_err(),
+                "{}",
+                field
+            );
+        }
+    }
+}
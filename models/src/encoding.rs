@@ -0,0 +1,212 @@
+// Hex and bech32 as the two binaries read and write them: fixed-size byte
+// strings, node pubkeys and URIs, NIP-19 keys and LUD-01 LNURLs. Parsing is
+// strict: a value of the wrong length is an error, never cut or padded to
+// fit.
+
+use bech32::{Bech32, Hrp};
+use secp256k1::PublicKey;
+use std::fmt;
+
+const LNURL_HRP: &str = "lnurl";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
+    Hex,
+    /// Bytes expected and found
+    Length {
+        expected: usize,
+        found: usize,
+    },
+    Bech32(String),
+    /// A bech32 string for something else, e.g. an npub for an nsec
+    Hrp {
+        expected: String,
+        found: String,
+    },
+    /// 33 bytes that are not a compressed point
+    Pubkey,
+    /// Not `<pubkey>[@<host>:<port>]`
+    NodeUri,
+    /// An LNURL that doesn't decode to text
+    Utf8,
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodingError::Hex => write!(f, "Not hex"),
+            EncodingError::Length { expected, found } => {
+                write!(f, "Expected {} bytes, got {}", expected, found)
+            }
+            EncodingError::Bech32(e) => write!(f, "Invalid bech32: {}", e),
+            EncodingError::Hrp { expected, found } => {
+                write!(f, "Expected {}1..., got prefix {}", expected, found)
+            }
+            EncodingError::Pubkey => write!(f, "Not a valid public key"),
+            EncodingError::NodeUri => write!(f, "Expected <pubkey>@<host>:<port>"),
+            EncodingError::Utf8 => write!(f, "LNURL does not encode UTF-8 text"),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+/// Exactly N bytes of hex, either case
+pub fn hex_array<const N: usize>(input: &str) -> Result<[u8; N], EncodingError> {
+    let bytes = hex::decode(input).map_err(|_| EncodingError::Hex)?;
+    let found = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| EncodingError::Length { expected: N, found })
+}
+
+/// A node pubkey: 33 bytes of hex, compressed
+pub fn parse_pubkey(input: &str) -> Result<PublicKey, EncodingError> {
+    let bytes: [u8; 33] = hex_array(input)?;
+    PublicKey::from_slice(&bytes).map_err(|_| EncodingError::Pubkey)
+}
+
+/// `<pubkey>@<host>:<port>`, or a bare pubkey, split into the pubkey and the
+/// address if there is one
+pub fn parse_node_uri(uri: &str) -> Result<(PublicKey, Option<&str>), EncodingError> {
+    match uri.split_once('@') {
+        None => Ok((parse_pubkey(uri)?, None)),
+        Some((pubkey, address)) => {
+            let valid = match address.rsplit_once(':') {
+                Some((host, port)) => {
+                    !host.is_empty() && !host.contains('@') && port.parse::<u16>().is_ok()
+                }
+                None => false,
+            };
+            if !valid {
+                return Err(EncodingError::NodeUri);
+            }
+            Ok((parse_pubkey(pubkey)?, Some(address)))
+        }
+    }
+}
+
+/// 32 bytes, as NIP-19 bech32 with `hrp` (e.g. npub1...) or as hex
+pub fn bech32_or_hex32(input: &str, hrp: &str) -> Result<[u8; 32], EncodingError> {
+    let input = input.trim();
+    match input.get(..hrp.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(hrp) => {
+            let bytes = decode_bech32(input, hrp)?;
+            let found = bytes.len();
+            bytes.try_into().map_err(|_| EncodingError::Length {
+                expected: 32,
+                found,
+            })
+        }
+        _ => hex_array(input),
+    }
+}
+
+fn decode_bech32(input: &str, hrp: &str) -> Result<Vec<u8>, EncodingError> {
+    let (found, data) = bech32::decode(input).map_err(|e| EncodingError::Bech32(e.to_string()))?;
+    if !found.as_str().eq_ignore_ascii_case(hrp) {
+        return Err(EncodingError::Hrp {
+            expected: hrp.to_string(),
+            found: found.to_string(),
+        });
+    }
+    Ok(data)
+}
+
+/// `url` as a LUD-01 LNURL, uppercase as it goes in QR codes
+pub fn encode_lnurl(url: &str) -> Result<String, EncodingError> {
+    let hrp = Hrp::parse(LNURL_HRP).expect("valid hrp");
+    let lnurl = bech32::encode::<Bech32>(hrp, url.as_bytes())
+        .map_err(|e| EncodingError::Bech32(e.to_string()))?;
+    Ok(lnurl.to_uppercase())
+}
+
+/// What a LUD-01 LNURL (lnurl1..., either case) encodes
+pub fn decode_lnurl(lnurl: &str) -> Result<String, EncodingError> {
+    let data = decode_bech32(lnurl, LNURL_HRP)?;
+    String::from_utf8(data).map_err(|_| EncodingError::Utf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The generator point G
+    const PUBKEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn hex_must_be_the_exact_length() {
+        assert_eq!(hex_array::<2>("aBcd"), Ok([0xab, 0xcd]));
+        assert_eq!(
+            hex_array::<2>("abcdef"),
+            Err(EncodingError::Length {
+                expected: 2,
+                found: 3
+            })
+        );
+        assert_eq!(hex_array::<2>("abc"), Err(EncodingError::Hex));
+        assert_eq!(hex_array::<2>("abzz"), Err(EncodingError::Hex));
+    }
+
+    #[test]
+    fn pubkeys_are_compressed_points() {
+        assert_eq!(parse_pubkey(PUBKEY).unwrap().to_string(), PUBKEY);
+        // An x-only key is one byte short, not a truncated or padded pubkey
+        assert_eq!(
+            parse_pubkey(&PUBKEY[2..]),
+            Err(EncodingError::Length {
+                expected: 33,
+                found: 32
+            })
+        );
+        let not_on_curve = format!("02{}", "00".repeat(32));
+        assert_eq!(parse_pubkey(&not_on_curve), Err(EncodingError::Pubkey));
+    }
+
+    #[test]
+    fn node_uris_split_at_the_at() {
+        let uri = format!("{}@192.168.27.72:9735", PUBKEY);
+        let (pubkey, address) = parse_node_uri(&uri).unwrap();
+        assert_eq!(pubkey.to_string(), PUBKEY);
+        assert_eq!(address, Some("192.168.27.72:9735"));
+        assert_eq!(parse_node_uri(PUBKEY).unwrap().1, None);
+        let uri = format!("{}@[::1]:9735", PUBKEY);
+        assert_eq!(parse_node_uri(&uri).unwrap().1, Some("[::1]:9735"));
+
+        let no_port = format!("{}@192.168.27.72", PUBKEY);
+        assert_eq!(parse_node_uri(&no_port), Err(EncodingError::NodeUri));
+        let two_ats = format!("{}@{}@host:9735", PUBKEY, PUBKEY);
+        assert_eq!(parse_node_uri(&two_ats), Err(EncodingError::NodeUri));
+    }
+
+    #[test]
+    fn nip19_keys_or_hex() {
+        // NIP-19's example npub
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        let hex = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+        assert_eq!(hex::encode(bech32_or_hex32(npub, "npub").unwrap()), hex);
+        assert_eq!(hex::encode(bech32_or_hex32(hex, "npub").unwrap()), hex);
+        assert_eq!(bech32_or_hex32(npub, "nsec"), Err(EncodingError::Hex));
+        assert!(matches!(
+            bech32_or_hex32(&npub.replace("npub", "nsec"), "nsec"),
+            Err(EncodingError::Bech32(_))
+        ));
+    }
+
+    #[test]
+    fn lnurls_round_trip() {
+        let url = "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+        let lnurl = encode_lnurl(url).unwrap();
+        assert!(lnurl.starts_with("LNURL1"));
+        assert_eq!(decode_lnurl(&lnurl).unwrap(), url);
+        assert_eq!(decode_lnurl(&lnurl.to_lowercase()).unwrap(), url);
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        assert_eq!(
+            decode_lnurl(npub),
+            Err(EncodingError::Hrp {
+                expected: "lnurl".to_string(),
+                found: "npub".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,287 @@
+// =============================================================================
+// LNURL messages
+// =============================================================================
+//
+// The JSON bodies lnurl-server sends and lnurl-client reads, defined once so
+// the two can't drift apart:
+//
+//   channel  — LUD-02 channelRequest and the open-channel callback
+//   withdraw — LUD-03 withdrawRequest, the withdraw callback, /withdraw-status
+//   pay      — LUD-06 payRequest, the pay callback, successAction (LUD-09/10)
+//   auth     — LUD-04 login, as our /auth-challenge and /auth-response do it
+//   signature — node signatures: CLN's zbase and LUD-04's DER, converted
+//   encoding — strict hex, pubkey, node URI, NIP-19 and LNURL parsing
+//   nostr    — NIP-01 events, signed and verified, for zaps and notifications
+//   webhook  — signing and verifying webhook deliveries
+//
+// Field names are the specs' camelCase on the wire and snake_case in Rust.
+// Optional fields are left out when unset and default when missing, so
+// replies from servers that predate an extension still parse. Extensions
+// only our server speaks (open-channel details, /withdraw-status) keep the
+// snake_case names they have always had.
+//
+// The first reply of a flow names the flow in its `tag`; LnurlParams is that
+// reply, parsed by tag.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+mod auth;
+mod channel;
+pub mod encoding;
+pub mod nostr;
+mod pay;
+pub mod signature;
+mod webhook;
+mod withdraw;
+
+pub use auth::{AuthChallenge, AuthResponse, LoginEvent, LOGGED_IN_EVENT, REGISTERED_EVENT};
+pub use channel::{ChannelRequest, OpenChannelResponse};
+pub use pay::{PayCallbackResponse, PayRequest, SuccessAction, VerifyResponse};
+pub use webhook::{
+    sign_webhook, sign_webhook_at, verify_webhook, verify_webhook_at, WebhookError,
+    DEFAULT_TOLERANCE_SECS, SIGNATURE_HEADER,
+};
+pub use withdraw::{WithdrawRequest, WithdrawStatusResponse, WithdrawalStatus};
+
+pub const CHANNEL_REQUEST_TAG: &str = "channelRequest";
+pub const WITHDRAW_REQUEST_TAG: &str = "withdrawRequest";
+pub const PAY_REQUEST_TAG: &str = "payRequest";
+/// Only ever in a URL's query string (LUD-04), never in a JSON body
+pub const LOGIN_TAG: &str = "login";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+/// `{"status": "OK"}` or `{"status": "ERROR", "reason": "..."}`, the reply to
+/// a callback, and to anything that fails
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl StatusResponse {
+    pub fn ok() -> StatusResponse {
+        StatusResponse {
+            status: Status::Ok,
+            reason: None,
+        }
+    }
+
+    pub fn error(reason: impl Into<String>) -> StatusResponse {
+        StatusResponse {
+            status: Status::Error,
+            reason: Some(reason.into()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+/// The first reply of a flow, which `tag` says how to continue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "tag", rename_all = "camelCase")]
+pub enum LnurlParams {
+    ChannelRequest(ChannelRequest),
+    WithdrawRequest(WithdrawRequest),
+    PayRequest(PayRequest),
+}
+
+impl LnurlParams {
+    /// The tags LnurlParams parses, for telling an unsupported tag from a
+    /// malformed reply
+    pub const TAGS: [&'static str; 3] =
+        [CHANNEL_REQUEST_TAG, WITHDRAW_REQUEST_TAG, PAY_REQUEST_TAG];
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            LnurlParams::ChannelRequest(_) => CHANNEL_REQUEST_TAG,
+            LnurlParams::WithdrawRequest(_) => WITHDRAW_REQUEST_TAG,
+            LnurlParams::PayRequest(_) => PAY_REQUEST_TAG,
+        }
+    }
+
+    pub fn into_channel_request(self) -> Result<ChannelRequest, WrongTag> {
+        match self {
+            LnurlParams::ChannelRequest(request) => Ok(request),
+            other => Err(WrongTag::new(CHANNEL_REQUEST_TAG, &other)),
+        }
+    }
+
+    pub fn into_withdraw_request(self) -> Result<WithdrawRequest, WrongTag> {
+        match self {
+            LnurlParams::WithdrawRequest(request) => Ok(request),
+            other => Err(WrongTag::new(WITHDRAW_REQUEST_TAG, &other)),
+        }
+    }
+
+    pub fn into_pay_request(self) -> Result<PayRequest, WrongTag> {
+        match self {
+            LnurlParams::PayRequest(request) => Ok(request),
+            other => Err(WrongTag::new(PAY_REQUEST_TAG, &other)),
+        }
+    }
+}
+
+impl From<ChannelRequest> for LnurlParams {
+    fn from(request: ChannelRequest) -> LnurlParams {
+        LnurlParams::ChannelRequest(request)
+    }
+}
+
+impl From<WithdrawRequest> for LnurlParams {
+    fn from(request: WithdrawRequest) -> LnurlParams {
+        LnurlParams::WithdrawRequest(request)
+    }
+}
+
+impl From<PayRequest> for LnurlParams {
+    fn from(request: PayRequest) -> LnurlParams {
+        LnurlParams::PayRequest(request)
+    }
+}
+
+/// A flow got the first reply of another flow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrongTag {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl WrongTag {
+    fn new(expected: &'static str, found: &LnurlParams) -> WrongTag {
+        WrongTag {
+            expected,
+            found: found.tag(),
+        }
+    }
+}
+
+impl fmt::Display for WrongTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expected a {} but got a {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for WrongTag {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn withdraw_request() -> WithdrawRequest {
+        WithdrawRequest {
+            callback: "https://service.example/withdraw".to_string(),
+            k1: "k1".to_string(),
+            default_description: "Withdrawal from service".to_string(),
+            min_withdrawable: 1_000,
+            max_withdrawable: 100_000,
+            balance_check: None,
+            pay_link: None,
+        }
+    }
+
+    #[test]
+    fn status_is_uppercase() {
+        assert_eq!(
+            serde_json::to_value(StatusResponse::ok()).unwrap(),
+            json!({"status": "OK"})
+        );
+        assert_eq!(
+            serde_json::to_value(StatusResponse::error("Invalid k1")).unwrap(),
+            json!({"status": "ERROR", "reason": "Invalid k1"})
+        );
+    }
+
+    #[test]
+    fn status_response_parses() {
+        let ok: StatusResponse = serde_json::from_value(json!({"status": "OK"})).unwrap();
+        assert!(ok.is_ok());
+        assert_eq!(ok.reason, None);
+
+        let error: StatusResponse =
+            serde_json::from_value(json!({"status": "ERROR", "reason": "nope"})).unwrap();
+        assert!(!error.is_ok());
+        assert_eq!(error.reason.as_deref(), Some("nope"));
+    }
+
+    #[test]
+    fn status_must_be_ok_or_error() {
+        assert!(serde_json::from_value::<StatusResponse>(json!({"status": "ok"})).is_err());
+        assert!(serde_json::from_value::<StatusResponse>(json!({})).is_err());
+    }
+
+    #[test]
+    fn params_carry_their_tag() {
+        let params = LnurlParams::from(withdraw_request());
+        let value = serde_json::to_value(&params).unwrap();
+        assert_eq!(value["tag"], "withdrawRequest");
+        assert_eq!(value["k1"], "k1");
+        assert_eq!(params.tag(), WITHDRAW_REQUEST_TAG);
+    }
+
+    #[test]
+    fn params_parse_by_tag() {
+        let params: LnurlParams = serde_json::from_value(json!({
+            "tag": "channelRequest",
+            "uri": "02abc@127.0.0.1:9735",
+            "callback": "https://service.example/open-channel",
+            "k1": "k1",
+        }))
+        .unwrap();
+        assert_eq!(params.tag(), CHANNEL_REQUEST_TAG);
+        assert_eq!(
+            params.into_channel_request().unwrap().uri,
+            "02abc@127.0.0.1:9735"
+        );
+    }
+
+    #[test]
+    fn params_round_trip() {
+        let params = LnurlParams::from(withdraw_request());
+        let json = serde_json::to_string(&params).unwrap();
+        assert_eq!(serde_json::from_str::<LnurlParams>(&json).unwrap(), params);
+    }
+
+    #[test]
+    fn unknown_tag_is_an_error() {
+        let result = serde_json::from_value::<LnurlParams>(json!({"tag": "hostedChannelRequest"}));
+        assert!(result.is_err());
+        assert!(!LnurlParams::TAGS.contains(&"hostedChannelRequest"));
+    }
+
+    #[test]
+    fn missing_tag_is_an_error() {
+        let mut value = serde_json::to_value(withdraw_request()).unwrap();
+        assert!(serde_json::from_value::<LnurlParams>(value.clone()).is_err());
+        value["tag"] = json!("withdrawRequest");
+        assert!(serde_json::from_value::<LnurlParams>(value).is_ok());
+    }
+
+    #[test]
+    fn wrong_tag_names_both() {
+        let error = LnurlParams::from(withdraw_request())
+            .into_pay_request()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            WrongTag {
+                expected: PAY_REQUEST_TAG,
+                found: WITHDRAW_REQUEST_TAG
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Expected a payRequest but got a withdrawRequest"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// Nostr events (NIP-01), as the client's zaps and the server's direct
+// messages to its operator carry them.
+//
+// Events are signed over their id, sha256 of the JSON array
+//   [0, <pubkey>, <created_at>, <kind>, <tags>, <content>]
+// with a BIP-340 Schnorr signature by the x-only pubkey.
+
+use secp256k1::{schnorr, Keypair, Message, Secp256k1, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventError {
+    /// The id is not the hash of the event's contents
+    IdMismatch,
+    BadPubkey,
+    BadSignature,
+    /// The signature is not the pubkey's
+    SignatureMismatch,
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventError::IdMismatch => write!(f, "Event id does not match its contents"),
+            EventError::BadPubkey => write!(f, "Bad event pubkey"),
+            EventError::BadSignature => write!(f, "Bad event signature"),
+            EventError::SignatureMismatch => write!(f, "Event signature does not verify"),
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,     // hex
+    pub pubkey: String, // hex, x-only
+    pub created_at: u64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String, // hex, Schnorr
+}
+
+impl Event {
+    /// An event by `keypair`'s x-only pubkey, created now
+    pub fn sign(keypair: &Keypair, kind: u32, tags: Vec<Vec<String>>, content: &str) -> Event {
+        let pubkey = keypair.x_only_public_key().0.to_string();
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let id = event_id(&pubkey, created_at, kind, &tags, content);
+
+        let mut aux = [0u8; 32];
+        // Without fresh randomness BIP-340 signing is still safe, only less
+        // hardened against side channels
+        let _ = getrandom::getrandom(&mut aux);
+        let sig =
+            Secp256k1::new().sign_schnorr_with_aux_rand(&Message::from_digest(id), keypair, &aux);
+
+        Event {
+            id: hex::encode(id),
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content: content.to_string(),
+            sig: hex::encode(sig.as_ref()),
+        }
+    }
+
+    /// Checks the id is the hash of the contents and the signature is the
+    /// pubkey's
+    pub fn verify(&self) -> Result<(), EventError> {
+        let id = event_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        if hex::encode(id) != self.id {
+            return Err(EventError::IdMismatch);
+        }
+        let pubkey = XOnlyPublicKey::from_str(&self.pubkey).map_err(|_| EventError::BadPubkey)?;
+        let sig = hex::decode(&self.sig)
+            .ok()
+            .and_then(|sig| schnorr::Signature::from_slice(&sig).ok())
+            .ok_or(EventError::BadSignature)?;
+        Secp256k1::verification_only()
+            .verify_schnorr(&sig, &Message::from_digest(id), &pubkey)
+            .map_err(|_| EventError::SignatureMismatch)
+    }
+
+    /// The first value of the first `name` tag
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.first().map(String::as_str) == Some(name))
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+}
+
+fn event_id(
+    pubkey: &str,
+    created_at: u64,
+    kind: u32,
+    tags: &[Vec<String>],
+    content: &str,
+) -> [u8; 32] {
+    let serialized = json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    Sha256::digest(serialized.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keypair() -> Keypair {
+        let secret = "0000000000000000000000000000000000000000000000000000000000000003";
+        Keypair::from_seckey_str(&Secp256k1::new(), secret).unwrap()
+    }
+
+    #[test]
+    fn events_are_signed_over_their_id() {
+        let keypair = keypair();
+        let tags = vec![vec!["p".to_string(), "ab".to_string()]];
+        let event = Event::sign(&keypair, 4, tags.clone(), "hi");
+        let serialized = json!([0, event.pubkey, event.created_at, 4, tags, "hi"]);
+        let id: [u8; 32] = Sha256::digest(serialized.to_string().as_bytes()).into();
+        assert_eq!(event.id, hex::encode(id));
+        assert_eq!(event.pubkey, keypair.x_only_public_key().0.to_string());
+        assert_eq!(event.verify(), Ok(()));
+        assert_eq!(event.tag("p"), Some("ab"));
+        assert_eq!(event.tag("e"), None);
+    }
+
+    #[test]
+    fn tampered_events_do_not_verify() {
+        let event = Event::sign(&keypair(), 1, vec![], "hi");
+        let mut edited = event.clone();
+        edited.content = "bye".to_string();
+        assert_eq!(edited.verify(), Err(EventError::IdMismatch));
+
+        // A consistent id, but not what the pubkey signed
+        let mut forged = Event::sign(&keypair(), 1, vec![], "bye");
+        forged.sig = event.sig.clone();
+        assert_eq!(forged.verify(), Err(EventError::SignatureMismatch));
+        forged.sig = "00".to_string();
+        assert_eq!(forged.verify(), Err(EventError::BadSignature));
+    }
+}
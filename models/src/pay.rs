@@ -0,0 +1,251 @@
+// LUD-06: the server describes what it sells and for how much, the wallet
+// asks the callback for an invoice of the amount it picked (amount, and a
+// comment under LUD-12) and pays it. The invoice may come with something to
+// show once paid (successAction, LUD-09 and LUD-10), and a URL to check
+// whether it was (verify, LUD-21).
+
+use serde::{Deserialize, Serialize};
+
+use crate::Status;
+
+/// LUD-06 payRequest, less its tag (see LnurlParams)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    pub callback: String,
+    pub metadata: String,  // JSON array of [mime type, content] pairs
+    pub min_sendable: u64, // millisatoshis
+    pub max_sendable: u64, // millisatoshis
+    /// LUD-12: max comment length in characters, 0 = no comments
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub comment_allowed: u64,
+    /// NIP-57: takes zap requests, publishes zap receipts
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub allows_nostr: bool,
+    /// NIP-57: signs the zap receipts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nostr_pubkey: Option<String>,
+    /// A BOLT12 offer to pay instead of the callback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bolt12: Option<String>,
+    /// LUD-11: false for a link wallets may keep and pay again; a missing
+    /// flag means true, a link for one payment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disposable: Option<bool>,
+    /// LUD-19: a withdrawRequest to take funds back out of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdraw_link: Option<String>,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// The pay callback's reply
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayCallbackResponse {
+    pub pr: String, // BOLT-11 invoice
+    /// Always empty: LUD-06 keeps the field but no longer uses it
+    #[serde(default)]
+    pub routes: Vec<serde_json::Value>,
+    /// Kept raw so a successAction we can't read doesn't stop the payment;
+    /// parse it with SuccessAction after paying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_action: Option<serde_json::Value>,
+    /// LUD-21: where to GET a VerifyResponse for the invoice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<String>,
+}
+
+/// LUD-21: whether the invoice of a pay callback was paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    pub status: Status,
+    pub settled: bool,
+    pub preimage: Option<String>, // hex, once settled
+    pub pr: String,
+}
+
+impl VerifyResponse {
+    pub fn ok(settled: bool, preimage: Option<String>, pr: String) -> VerifyResponse {
+        VerifyResponse {
+            status: Status::Ok,
+            settled,
+            preimage,
+            pr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+pub enum SuccessAction {
+    Message {
+        message: String,
+    },
+    Url {
+        description: String,
+        url: String,
+    },
+    /// LUD-10
+    Aes {
+        description: String,
+        ciphertext: String, // base64, AES-256-CBC with the preimage as key
+        iv: String,         // base64, 16 bytes
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pay_request() -> PayRequest {
+        PayRequest {
+            callback: "https://service.example/pay".to_string(),
+            metadata: r#"[["text/plain","coffee"]]"#.to_string(),
+            min_sendable: 1_000,
+            max_sendable: 1_000_000,
+            comment_allowed: 0,
+            allows_nostr: false,
+            nostr_pubkey: None,
+            bolt12: None,
+            disposable: None,
+            withdraw_link: None,
+        }
+    }
+
+    #[test]
+    fn request_is_camel_case() {
+        assert_eq!(
+            serde_json::to_value(pay_request()).unwrap(),
+            json!({
+                "callback": "https://service.example/pay",
+                "metadata": r#"[["text/plain","coffee"]]"#,
+                "minSendable": 1_000,
+                "maxSendable": 1_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn extensions_round_trip() {
+        let request = PayRequest {
+            comment_allowed: 140,
+            allows_nostr: true,
+            nostr_pubkey: Some("ab".repeat(32)),
+            bolt12: Some("lno1...".to_string()),
+            disposable: Some(false),
+            withdraw_link: Some("lnurlw://service.example/withdraw".to_string()),
+            ..pay_request()
+        };
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["commentAllowed"], 140);
+        assert_eq!(value["disposable"], false);
+        assert_eq!(value["withdrawLink"], "lnurlw://service.example/withdraw");
+        assert_eq!(value["allowsNostr"], true);
+        assert_eq!(value["nostrPubkey"], "ab".repeat(32));
+        assert_eq!(
+            serde_json::from_value::<PayRequest>(value).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn missing_extensions_default() {
+        let request: PayRequest = serde_json::from_value(json!({
+            "callback": "https://service.example/pay",
+            "metadata": "[]",
+            "minSendable": 1,
+            "maxSendable": 2,
+        }))
+        .unwrap();
+        assert_eq!(request.comment_allowed, 0);
+        assert!(!request.allows_nostr);
+        assert_eq!(request.nostr_pubkey, None);
+        assert_eq!(request.disposable, None);
+        assert_eq!(request.withdraw_link, None);
+    }
+
+    #[test]
+    fn callback_reply_keeps_routes() {
+        let reply = PayCallbackResponse {
+            pr: "lnbc1...".to_string(),
+            routes: Vec::new(),
+            success_action: None,
+            verify: None,
+        };
+        assert_eq!(
+            serde_json::to_value(reply).unwrap(),
+            json!({"pr": "lnbc1...", "routes": []})
+        );
+
+        // ...but doesn't insist on them
+        let reply: PayCallbackResponse = serde_json::from_value(json!({"pr": "lnbc1..."})).unwrap();
+        assert!(reply.routes.is_empty());
+    }
+
+    #[test]
+    fn unreadable_success_action_still_parses() {
+        let reply: PayCallbackResponse = serde_json::from_value(json!({
+            "pr": "lnbc1...",
+            "successAction": {"tag": "hologram"},
+        }))
+        .unwrap();
+        let action = reply.success_action.unwrap();
+        assert!(serde_json::from_value::<SuccessAction>(action).is_err());
+    }
+
+    #[test]
+    fn success_actions_by_tag() {
+        let message: SuccessAction =
+            serde_json::from_value(json!({"tag": "message", "message": "thanks"})).unwrap();
+        assert_eq!(
+            message,
+            SuccessAction::Message {
+                message: "thanks".to_string()
+            }
+        );
+
+        let url = SuccessAction::Url {
+            description: "receipt".to_string(),
+            url: "https://service.example/r/1".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&url).unwrap(),
+            json!({"tag": "url", "description": "receipt", "url": "https://service.example/r/1"})
+        );
+
+        let aes: SuccessAction = serde_json::from_value(json!({
+            "tag": "aes",
+            "description": "code",
+            "ciphertext": "AAAA",
+            "iv": "BBBB",
+        }))
+        .unwrap();
+        assert!(matches!(aes, SuccessAction::Aes { .. }));
+    }
+
+    #[test]
+    fn verify_reply_keeps_a_null_preimage() {
+        let pending = VerifyResponse::ok(false, None, "lnbc1...".to_string());
+        assert_eq!(
+            serde_json::to_value(&pending).unwrap(),
+            json!({"status": "OK", "settled": false, "preimage": null, "pr": "lnbc1..."})
+        );
+        let settled: VerifyResponse = serde_json::from_value(json!({
+            "status": "OK",
+            "settled": true,
+            "preimage": "ab".repeat(32),
+            "pr": "lnbc1...",
+        }))
+        .unwrap();
+        assert!(settled.settled);
+        assert_eq!(settled.preimage, Some("ab".repeat(32)));
+    }
+}
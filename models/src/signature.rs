@@ -0,0 +1,271 @@
+// Node signatures in the two shapes LNURL-auth meets them:
+//
+//   zbase — CLN's signmessage/checkmessage (and LND's): zbase32 of
+//           <recovery header> || r || s, over
+//           sha256d("Lightning Signed Message:" || message). The header
+//           (31 + recovery id) lets the signer's pubkey be recovered.
+//   DER   — LUD-04's `sig`: hex DER of r || s, with the pubkey sent beside it
+//
+// Converting between them changes the encoding only; a signature still
+// verifies against the digest it was made over. Going from DER to zbase
+// needs that digest and the pubkey, to find the recovery id DER leaves out.
+
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+const MESSAGE_PREFIX: &str = "Lightning Signed Message:";
+const ZBASE32_ALPHABET: &[u8; 32] = b"ybndrfg8ejkmcpqxot1uwisza345h769";
+/// Header byte of a recoverable signature by a compressed key, less the
+/// recovery id
+const COMPRESSED_HEADER: u8 = 31;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Not zbase32, the offending character
+    Zbase32(char),
+    /// A zbase signature that isn't 65 bytes
+    Length(usize),
+    /// A header byte no recovery id maps to
+    Header(u8),
+    Hex,
+    Der,
+    /// No recovery id makes the signature recover the given pubkey
+    KeyMismatch,
+    /// Does not verify, or recovers no key
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Zbase32(c) => write!(f, "Invalid zbase32 character {:?}", c),
+            SignatureError::Length(len) => {
+                write!(f, "Signature is {} bytes, expected 65", len)
+            }
+            SignatureError::Header(byte) => write!(f, "Invalid signature header {}", byte),
+            SignatureError::Hex => write!(f, "Signature is not hex"),
+            SignatureError::Der => write!(f, "Signature is not DER"),
+            SignatureError::KeyMismatch => write!(f, "Signature was not made by that key"),
+            SignatureError::Invalid => write!(f, "Invalid signature"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+pub fn zbase32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ZBASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ZBASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+/// The bytes of `text`, trailing bits that don't fill a byte dropped
+pub fn zbase32_decode(text: &str) -> Result<Vec<u8>, SignatureError> {
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.chars() {
+        let value = ZBASE32_ALPHABET
+            .iter()
+            .position(|&symbol| symbol as char == c)
+            .ok_or(SignatureError::Zbase32(c))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// What signmessage signs for `message`: sha256d(MESSAGE_PREFIX || message)
+pub fn message_digest(message: &str) -> [u8; 32] {
+    let once = Sha256::new()
+        .chain_update(MESSAGE_PREFIX)
+        .chain_update(message)
+        .finalize();
+    Sha256::digest(once).into()
+}
+
+fn parse_zbase(zbase: &str) -> Result<RecoverableSignature, SignatureError> {
+    let bytes = zbase32_decode(zbase)?;
+    if bytes.len() != 65 {
+        return Err(SignatureError::Length(bytes.len()));
+    }
+    // 27..=30 for uncompressed keys, which some signers still write
+    let header = bytes[0];
+    let recovery = match header {
+        27..=34 => RecoveryId::from_i32(((header - 27) % 4) as i32)
+            .map_err(|_| SignatureError::Header(header))?,
+        _ => return Err(SignatureError::Header(header)),
+    };
+    RecoverableSignature::from_compact(&bytes[1..], recovery).map_err(|_| SignatureError::Invalid)
+}
+
+fn zbase_of(signature: &RecoverableSignature) -> String {
+    let (recovery, compact) = signature.serialize_compact();
+    let mut bytes = Vec::with_capacity(65);
+    bytes.push(COMPRESSED_HEADER + recovery.to_i32() as u8);
+    bytes.extend_from_slice(&compact);
+    zbase32_encode(&bytes)
+}
+
+/// Signs `message` like signmessage does, in zbase
+pub fn sign_message(secret: &SecretKey, message: &str) -> String {
+    let digest = Message::from_digest(message_digest(message));
+    zbase_of(&Secp256k1::signing_only().sign_ecdsa_recoverable(&digest, secret))
+}
+
+/// The key that made the zbase signature over `digest`
+pub fn recover_pubkey(digest: &[u8; 32], zbase: &str) -> Result<PublicKey, SignatureError> {
+    let signature = parse_zbase(zbase)?;
+    Secp256k1::verification_only()
+        .recover_ecdsa(&Message::from_digest(*digest), &signature)
+        .map_err(|_| SignatureError::Invalid)
+}
+
+/// Checks a hex DER signature over `digest` against `pubkey`
+pub fn verify_der(
+    digest: &[u8; 32],
+    der_hex: &str,
+    pubkey: &PublicKey,
+) -> Result<(), SignatureError> {
+    let signature = parse_der(der_hex)?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(&Message::from_digest(*digest), &signature, pubkey)
+        .map_err(|_| SignatureError::Invalid)
+}
+
+fn parse_der(der_hex: &str) -> Result<Signature, SignatureError> {
+    let der = hex::decode(der_hex).map_err(|_| SignatureError::Hex)?;
+    let mut signature = Signature::from_der(&der).map_err(|_| SignatureError::Der)?;
+    // libsecp256k1 only verifies low-S signatures, both are valid ECDSA
+    signature.normalize_s();
+    Ok(signature)
+}
+
+/// The zbase signature as hex DER
+pub fn zbase_to_der(zbase: &str) -> Result<String, SignatureError> {
+    let signature = parse_zbase(zbase)?.to_standard();
+    Ok(hex::encode(signature.serialize_der()))
+}
+
+/// The hex DER signature `pubkey` made over `digest`, as zbase
+pub fn der_to_zbase(
+    digest: &[u8; 32],
+    der_hex: &str,
+    pubkey: &PublicKey,
+) -> Result<String, SignatureError> {
+    let compact = parse_der(der_hex)?.serialize_compact();
+    let secp = Secp256k1::verification_only();
+    let message = Message::from_digest(*digest);
+    for id in 0..4 {
+        let recovery = RecoveryId::from_i32(id).expect("0..4 are recovery ids");
+        let Ok(signature) = RecoverableSignature::from_compact(&compact, recovery) else {
+            continue;
+        };
+        if secp.recover_ecdsa(&message, &signature).as_ref() == Ok(pubkey) {
+            return Ok(zbase_of(&signature));
+        }
+    }
+    Err(SignatureError::KeyMismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> (SecretKey, PublicKey) {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        (secret, secret.public_key(&Secp256k1::new()))
+    }
+
+    #[test]
+    fn zbase32_round_trips() {
+        // From the z-base-32 spec
+        assert_eq!(zbase32_encode(&[0xf0, 0xbf, 0xc7]), "6n9hq");
+        assert_eq!(zbase32_decode("6n9hq").unwrap(), [0xf0, 0xbf, 0xc7]);
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(zbase32_decode(&zbase32_encode(&bytes)).unwrap(), bytes);
+        assert_eq!(zbase32_decode("6n0hq"), Err(SignatureError::Zbase32('0')));
+    }
+
+    #[test]
+    fn signed_messages_recover_their_key() {
+        let (secret, public) = key(0x11);
+        let zbase = sign_message(&secret, "k1");
+        assert_eq!(zbase.len(), 104);
+        assert_eq!(recover_pubkey(&message_digest("k1"), &zbase), Ok(public));
+        assert_ne!(recover_pubkey(&message_digest("k2"), &zbase), Ok(public));
+    }
+
+    #[test]
+    fn conversions_keep_the_signature() {
+        let (secret, public) = key(0x22);
+        let digest = message_digest("0123abcd");
+        let zbase = sign_message(&secret, "0123abcd");
+
+        let der = zbase_to_der(&zbase).unwrap();
+        assert_eq!(verify_der(&digest, &der, &public), Ok(()));
+        assert_eq!(der_to_zbase(&digest, &der, &public).unwrap(), zbase);
+
+        let (_, other) = key(0x33);
+        assert_eq!(
+            verify_der(&digest, &der, &other),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            der_to_zbase(&digest, &der, &other),
+            Err(SignatureError::KeyMismatch)
+        );
+    }
+
+    #[test]
+    fn lud04_der_signatures_convert_too() {
+        let (secret, public) = key(0x44);
+        let k1 = [0x5a; 32];
+        let signature = Secp256k1::new().sign_ecdsa(&Message::from_digest(k1), &secret);
+        let der = hex::encode(signature.serialize_der());
+
+        let zbase = der_to_zbase(&k1, &der, &public).unwrap();
+        assert_eq!(recover_pubkey(&k1, &zbase), Ok(public));
+        assert_eq!(zbase_to_der(&zbase).unwrap(), der);
+    }
+
+    #[test]
+    fn malformed_signatures_are_refused() {
+        let (secret, _) = key(0x11);
+        let zbase = sign_message(&secret, "k1");
+        assert_eq!(zbase_to_der(&zbase[..100]), Err(SignatureError::Length(62)));
+        let mut bytes = zbase32_decode(&zbase).unwrap();
+        bytes[0] = 26;
+        let bad_header = zbase32_encode(&bytes);
+        assert_eq!(zbase_to_der(&bad_header), Err(SignatureError::Header(26)));
+        assert_eq!(zbase_to_der("zbase?"), Err(SignatureError::Zbase32('?')));
+        let digest = message_digest("k1");
+        let (_, public) = key(0x11);
+        assert_eq!(
+            verify_der(&digest, "30zz", &public),
+            Err(SignatureError::Hex)
+        );
+        assert_eq!(
+            verify_der(&digest, "3006", &public),
+            Err(SignatureError::Der)
+        );
+    }
+}
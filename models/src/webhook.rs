@@ -0,0 +1,211 @@
+// Webhook signatures: deliveries carry an HMAC of their body under a secret
+// shared with the receiver, in one header:
+//
+//   X-Lnurl-Signature: t=<unix seconds>,v1=<hex HMAC-SHA256(secret, "<t>.<body>")>
+//
+// Signing the timestamp with the body lets receivers refuse old deliveries,
+// so a captured one can't be replayed later. A header may carry several v1
+// entries (one per secret, while rotating); any one that matches will do.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SIGNATURE_HEADER: &str = "X-Lnurl-Signature";
+/// How far a delivery's timestamp may be from the receiver's clock, either way
+pub const DEFAULT_TOLERANCE_SECS: u64 = 300;
+
+const SCHEME: &str = "v1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    MalformedHeader,
+    /// Signed too long ago, or too far ahead of the receiver's clock
+    OutsideTolerance {
+        timestamp: u64,
+        now: u64,
+    },
+    BadSignature,
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::MalformedHeader => write!(f, "Malformed {} header", SIGNATURE_HEADER),
+            WebhookError::OutsideTolerance { timestamp, now } => write!(
+                f,
+                "Webhook signed at {}, too far from now ({})",
+                timestamp, now
+            ),
+            WebhookError::BadSignature => write!(f, "Webhook signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+fn mac(payload: &[u8], secret: &[u8], timestamp: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+    mac
+}
+
+/// The SIGNATURE_HEADER value for delivering `payload` now
+pub fn sign_webhook(payload: &[u8], secret: &[u8]) -> String {
+    sign_webhook_at(payload, secret, now())
+}
+
+pub fn sign_webhook_at(payload: &[u8], secret: &[u8], timestamp: u64) -> String {
+    let signature = mac(payload, secret, timestamp).finalize().into_bytes();
+    format!("t={},{}={}", timestamp, SCHEME, hex::encode(signature))
+}
+
+/// Checks a delivery's SIGNATURE_HEADER against its raw body (as received,
+/// before any parsing), returning when it was signed
+pub fn verify_webhook(
+    payload: &[u8],
+    header: &str,
+    secret: &[u8],
+    tolerance_secs: u64,
+) -> Result<u64, WebhookError> {
+    verify_webhook_at(payload, header, secret, tolerance_secs, now())
+}
+
+pub fn verify_webhook_at(
+    payload: &[u8],
+    header: &str,
+    secret: &[u8],
+    tolerance_secs: u64,
+    now: u64,
+) -> Result<u64, WebhookError> {
+    let mut timestamp: Option<u64> = None;
+    let mut signatures = Vec::new();
+    for entry in header.split(',').map(str::trim) {
+        match entry.split_once('=') {
+            Some(("t", value)) => {
+                let parsed = value.parse().map_err(|_| WebhookError::MalformedHeader)?;
+                if timestamp.replace(parsed).is_some() {
+                    return Err(WebhookError::MalformedHeader);
+                }
+            }
+            Some((SCHEME, value)) => {
+                signatures.push(hex::decode(value).map_err(|_| WebhookError::MalformedHeader)?)
+            }
+            // Schemes from later versions
+            Some(_) => {}
+            None => return Err(WebhookError::MalformedHeader),
+        }
+    }
+    let Some(timestamp) = timestamp else {
+        return Err(WebhookError::MalformedHeader);
+    };
+    if signatures.is_empty() {
+        return Err(WebhookError::MalformedHeader);
+    }
+
+    if timestamp.abs_diff(now) > tolerance_secs {
+        return Err(WebhookError::OutsideTolerance { timestamp, now });
+    }
+    let expected = mac(payload, secret, timestamp);
+    // verify_slice compares in constant time
+    match signatures
+        .iter()
+        .any(|signature| expected.clone().verify_slice(signature).is_ok())
+    {
+        true => Ok(timestamp),
+        false => Err(WebhookError::BadSignature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"whsec_test";
+    const BODY: &[u8] = br#"{"event":"withdrawal.paid","k1":"abc"}"#;
+    const NOW: u64 = 1_760_000_000;
+
+    fn verify(header: &str, now: u64) -> Result<u64, WebhookError> {
+        verify_webhook_at(BODY, header, SECRET, DEFAULT_TOLERANCE_SECS, now)
+    }
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let header = sign_webhook_at(BODY, SECRET, NOW);
+        // HMAC-SHA256("whsec_test", "1760000000." + BODY), as computed by Python's hmac
+        assert_eq!(
+            header,
+            "t=1760000000,v1=7cd3eb9cd65e776d39d539a8a510028011d2544fac2287c391c901c39d52f5fc"
+        );
+        assert_eq!(verify(&header, NOW), Ok(NOW));
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let header = sign_webhook_at(BODY, SECRET, NOW);
+        let other_body = verify_webhook_at(b"{}", &header, SECRET, DEFAULT_TOLERANCE_SECS, NOW);
+        assert_eq!(other_body, Err(WebhookError::BadSignature));
+        let other_secret = verify_webhook_at(BODY, &header, b"nope", DEFAULT_TOLERANCE_SECS, NOW);
+        assert_eq!(other_secret, Err(WebhookError::BadSignature));
+
+        // Moving the timestamp invalidates the signature
+        let signature = header.split_once(',').unwrap().1;
+        let moved = format!("t={},{}", NOW + 1, signature);
+        assert_eq!(verify(&moved, NOW), Err(WebhookError::BadSignature));
+    }
+
+    #[test]
+    fn timestamp_tolerance() {
+        let header = sign_webhook_at(BODY, SECRET, NOW);
+        assert!(verify(&header, NOW + DEFAULT_TOLERANCE_SECS).is_ok());
+        assert!(verify(&header, NOW - DEFAULT_TOLERANCE_SECS).is_ok());
+        assert_eq!(
+            verify(&header, NOW + DEFAULT_TOLERANCE_SECS + 1),
+            Err(WebhookError::OutsideTolerance {
+                timestamp: NOW,
+                now: NOW + DEFAULT_TOLERANCE_SECS + 1
+            })
+        );
+        assert!(verify(&header, NOW - DEFAULT_TOLERANCE_SECS - 1).is_err());
+    }
+
+    #[test]
+    fn any_of_several_signatures() {
+        let old = sign_webhook_at(BODY, b"old secret", NOW);
+        let new = sign_webhook_at(BODY, SECRET, NOW);
+        let rotating = format!("{},{},v2=later", old, new.split_once(',').unwrap().1);
+        assert_eq!(verify(&rotating, NOW), Ok(NOW));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let signature = sign_webhook_at(BODY, SECRET, NOW);
+        let signature = signature.split_once(',').unwrap().1;
+        for header in [
+            "",
+            signature,
+            "t=1760000000",
+            "t=soon,v1=00",
+            "t=1760000000,v1=zz",
+            "t=1760000000,t=1760000000,v1=00",
+            "t=1760000000;v1=00",
+        ] {
+            assert_eq!(
+                verify(header, NOW),
+                Err(WebhookError::MalformedHeader),
+                "{}",
+                header
+            );
+        }
+    }
+}
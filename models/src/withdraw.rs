@@ -0,0 +1,206 @@
+// LUD-03: the server offers an amount range, the wallet answers with an
+// invoice through the callback (k1, pr) and the server pays it. Our server
+// pays in the background and reports the outcome at /withdraw-status.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Status;
+
+/// LUD-03 withdrawRequest, less its tag (see LnurlParams)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawRequest {
+    pub callback: String,
+    pub k1: String,
+    #[serde(default)]
+    pub default_description: String,
+    pub min_withdrawable: u64, // millisatoshis
+    pub max_withdrawable: u64, // millisatoshis
+    /// LUD-15: fetch for a fresh withdrawRequest later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance_check: Option<String>,
+    /// LUD-19: a payRequest to pay back into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pay_link: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WithdrawalStatus {
+    Pending,
+    Paid,
+    Failed,
+}
+
+impl WithdrawalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WithdrawalStatus::Pending => "pending",
+            WithdrawalStatus::Paid => "paid",
+            WithdrawalStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<WithdrawalStatus> {
+        match s {
+            "pending" => Some(WithdrawalStatus::Pending),
+            "paid" => Some(WithdrawalStatus::Paid),
+            "failed" => Some(WithdrawalStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// GET /withdraw-status?k1=<k1>, not part of LUD-03: whether the background
+/// payment of an accepted withdraw went through
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WithdrawStatusResponse {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdrawal_status: Option<WithdrawalStatus>,
+    /// hex, once paid, if the server shares it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+    /// Routing fee the server paid on top of the amount, once paid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_msat: Option<u64>,
+}
+
+impl WithdrawStatusResponse {
+    pub fn ok(withdrawal_status: WithdrawalStatus) -> WithdrawStatusResponse {
+        WithdrawStatusResponse {
+            status: Status::Ok,
+            reason: None,
+            withdrawal_status: Some(withdrawal_status),
+            preimage: None,
+            fee_msat: None,
+        }
+    }
+
+    pub fn error(reason: impl Into<String>) -> WithdrawStatusResponse {
+        WithdrawStatusResponse {
+            status: Status::Error,
+            reason: Some(reason.into()),
+            withdrawal_status: None,
+            preimage: None,
+            fee_msat: None,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn request_is_camel_case() {
+        let request = WithdrawRequest {
+            callback: "https://service.example/withdraw".to_string(),
+            k1: "k1".to_string(),
+            default_description: "Withdrawal from service".to_string(),
+            min_withdrawable: 1_000,
+            max_withdrawable: 100_000,
+            balance_check: Some("https://service.example/balance".to_string()),
+            pay_link: Some("https://service.example/pay".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(request).unwrap(),
+            json!({
+                "callback": "https://service.example/withdraw",
+                "k1": "k1",
+                "defaultDescription": "Withdrawal from service",
+                "minWithdrawable": 1_000,
+                "maxWithdrawable": 100_000,
+                "balanceCheck": "https://service.example/balance",
+                "payLink": "https://service.example/pay",
+            })
+        );
+    }
+
+    #[test]
+    fn extensions_are_left_out_when_unset() {
+        let request: WithdrawRequest = serde_json::from_value(json!({
+            "callback": "https://service.example/withdraw",
+            "k1": "k1",
+            "defaultDescription": "",
+            "minWithdrawable": 1,
+            "maxWithdrawable": 2,
+        }))
+        .unwrap();
+        assert_eq!(request.balance_check, None);
+        assert_eq!(request.pay_link, None);
+
+        let value = serde_json::to_value(request).unwrap();
+        assert!(value.get("balanceCheck").is_none());
+        assert!(value.get("payLink").is_none());
+    }
+
+    #[test]
+    fn missing_description_is_empty() {
+        let request: WithdrawRequest = serde_json::from_value(json!({
+            "callback": "https://service.example/withdraw",
+            "k1": "k1",
+            "minWithdrawable": 1,
+            "maxWithdrawable": 2,
+        }))
+        .unwrap();
+        assert_eq!(request.default_description, "");
+    }
+
+    #[test]
+    fn snake_case_amounts_are_rejected() {
+        let result = serde_json::from_value::<WithdrawRequest>(json!({
+            "callback": "https://service.example/withdraw",
+            "k1": "k1",
+            "min_withdrawable": 1,
+            "max_withdrawable": 2,
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn withdrawal_status_names() {
+        for status in [
+            WithdrawalStatus::Pending,
+            WithdrawalStatus::Paid,
+            WithdrawalStatus::Failed,
+        ] {
+            assert_eq!(WithdrawalStatus::parse(status.as_str()), Some(status));
+            assert_eq!(
+                serde_json::to_value(status).unwrap(),
+                json!(status.as_str())
+            );
+        }
+        assert_eq!(WithdrawalStatus::parse("PAID"), None);
+    }
+
+    #[test]
+    fn status_response() {
+        assert_eq!(
+            serde_json::to_value(WithdrawStatusResponse::ok(WithdrawalStatus::Pending)).unwrap(),
+            json!({"status": "OK", "withdrawal_status": "pending"})
+        );
+        assert_eq!(
+            serde_json::to_value(WithdrawStatusResponse::error("Unknown withdrawal")).unwrap(),
+            json!({"status": "ERROR", "reason": "Unknown withdrawal"})
+        );
+
+        let paid: WithdrawStatusResponse = serde_json::from_value(json!({
+            "status": "OK",
+            "withdrawal_status": "paid",
+            "preimage": "00".repeat(32),
+            "fee_msat": 12,
+        }))
+        .unwrap();
+        assert_eq!(paid.withdrawal_status, Some(WithdrawalStatus::Paid));
+        assert_eq!(paid.preimage, Some("00".repeat(32)));
+        assert_eq!(paid.fee_msat, Some(12));
+    }
+}
@@ -0,0 +1,371 @@
+// =============================================================================
+// Withdraw load generator
+// =============================================================================
+//
+// Fires concurrent request-withdraw + withdraw callback pairs at the router
+// (in process, no sockets) over a mock node that takes --latency-ms per call,
+// and reports throughput and latency percentiles for the pair.
+//
+// Two backends are measured:
+//
+//   serialized — every node call holds one lock, as ClnBackend's single RPC
+//                socket does: a payment in flight stalls the next decode
+//   concurrent — node calls overlap, as a pooled or async backend allows
+//
+// The gap between the two is what the global client mutex costs.
+//
+//   cargo bench -p lnurl-server --bench withdraw_load -- \
+//       [--mode serialized|concurrent] [--requests N] [--concurrency N] \
+//       [--latency-ms N] [--min-throughput PAIRS_PER_SEC]
+//
+// The handlers log to stdout, the report goes to stderr; add > /dev/null to
+// see just the report. With --min-throughput the run exits non-zero when any
+// measured mode falls below it, for use as a regression check.
+
+use async_trait::async_trait;
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use cln_rpc::primitives::PublicKey;
+use lnurl_server::backend::{
+    Backend, BackendResult, ChannelBalance, ChannelFees, DecodedInvoice, FeeUpdate, FundedChannel,
+    Funds, InvoiceStatus, IssuedInvoice, NewInvoice, NodeStatus, Payment,
+};
+use lnurl_server::storage::MemoryStorage;
+use lnurl_server::{app, AppState};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+
+const NODE_ID: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+/// Within the default withdraw limits
+const INVOICE: &str = "lntb10000";
+
+// =============================================================================
+// CLI Parsing
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Serialized,
+    Concurrent,
+}
+
+#[derive(Debug)]
+struct Options {
+    modes: Vec<Mode>,
+    requests: usize,
+    concurrency: usize,
+    latency: Duration,
+    min_throughput: Option<f64>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        modes: vec![Mode::Serialized, Mode::Concurrent],
+        requests: 1_000,
+        concurrency: 64,
+        latency: Duration::from_millis(5),
+        min_throughput: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--mode" => {
+                options.modes = match value()?.as_str() {
+                    "serialized" => vec![Mode::Serialized],
+                    "concurrent" => vec![Mode::Concurrent],
+                    other => return Err(format!("Unknown mode: {}", other)),
+                }
+            }
+            "--requests" => options.requests = number(&value()?)?,
+            "--concurrency" => options.concurrency = number(&value()?)?,
+            "--latency-ms" => options.latency = Duration::from_millis(number(&value()?)?),
+            "--min-throughput" => options.min_throughput = Some(number(&value()?)?),
+            // Passed by cargo bench
+            "--bench" => {}
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+
+    if options.requests == 0 || options.concurrency == 0 {
+        return Err("--requests and --concurrency must be at least 1".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Not a number: {}", value))
+}
+
+// =============================================================================
+// Mock node
+// =============================================================================
+
+/// Every call takes `latency`; with `socket` set, calls also queue on it
+struct SlowNode {
+    latency: Duration,
+    socket: Option<Mutex<()>>,
+}
+
+impl SlowNode {
+    async fn call(&self) {
+        let _held = match &self.socket {
+            Some(socket) => Some(socket.lock().await),
+            None => None,
+        };
+        tokio::time::sleep(self.latency).await;
+    }
+}
+
+#[async_trait]
+impl Backend for SlowNode {
+    async fn node_id(&self) -> BackendResult<String> {
+        Ok(NODE_ID.to_string())
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice> {
+        self.call().await;
+        let amount_msat = bolt11
+            .strip_prefix("lntb")
+            .and_then(|amount| amount.parse().ok())
+            .ok_or_else(|| "Invalid bech32 string".to_string())?;
+        Ok(DecodedInvoice {
+            amount_msat: Some(amount_msat),
+            description: None,
+        })
+    }
+
+    async fn fund_channel(
+        &self,
+        _node_id: PublicKey,
+        _capacity_sat: u64,
+        _announce: bool,
+    ) -> BackendResult<FundedChannel> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn pay(&self, _bolt11: &str) -> BackendResult<Payment> {
+        self.call().await;
+        Ok(Payment {
+            preimage: vec![0x11; 32],
+            amount_sent_msat: 0,
+        })
+    }
+
+    async fn check_message(
+        &self,
+        _message: &str,
+        _zbase: &str,
+        _pubkey: PublicKey,
+    ) -> BackendResult<bool> {
+        Ok(false)
+    }
+
+    async fn funds(&self) -> BackendResult<Funds> {
+        self.call().await;
+        // One channel deep enough for every withdraw of a run
+        Ok(Funds {
+            channels: vec![ChannelBalance {
+                peer_id: NODE_ID.to_string(),
+                channel_id: None,
+                short_channel_id: None,
+                state: "CHANNELD_NORMAL".to_string(),
+                outbound_msat: u64::MAX / 200,
+                inbound_msat: 0,
+            }],
+            ..Funds::default()
+        })
+    }
+
+    async fn set_channel_fees(
+        &self,
+        _channel_id: &str,
+        _update: &FeeUpdate,
+    ) -> BackendResult<ChannelFees> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn create_invoice(
+        &self,
+        _amount_msat: u64,
+        _label: &str,
+        _description: &str,
+        _expiry_secs: u64,
+    ) -> BackendResult<String> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn create_invoice_hashed(
+        &self,
+        _amount_msat: u64,
+        _label: &str,
+        _description: &str,
+        _expiry_secs: u64,
+        _preimage: Option<&[u8; 32]>,
+    ) -> BackendResult<NewInvoice> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn invoice_status(&self, _label: &str) -> BackendResult<Option<InvoiceStatus>> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn invoice_by_hash(&self, _payment_hash: &str) -> BackendResult<Option<IssuedInvoice>> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn opening_feerate_perkb(&self) -> BackendResult<u64> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+
+    async fn node_status(&self) -> BackendResult<NodeStatus> {
+        Err("Not part of the withdraw path".to_string().into())
+    }
+}
+
+// =============================================================================
+// Load
+// =============================================================================
+
+async fn get(app: &Router, uri: &str) -> Result<serde_json::Value, String> {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+    match status {
+        StatusCode::OK => Ok(json),
+        _ => Err(format!("{} from {}: {}", status, uri, json)),
+    }
+}
+
+/// One request-withdraw and its callback
+async fn withdraw_pair(app: &Router) -> Result<(), String> {
+    let request = get(app, "/request-withdraw").await?;
+    let k1 = request["k1"].as_str().ok_or("No k1 in withdraw request")?;
+    let reply = get(app, &format!("/withdraw?k1={}&pr={}", k1, INVOICE)).await?;
+    match reply["status"].as_str() {
+        Some("OK") => Ok(()),
+        _ => Err(format!("Withdraw refused: {}", reply)),
+    }
+}
+
+struct Report {
+    elapsed: Duration,
+    latencies: Vec<Duration>, // sorted
+    errors: Vec<String>,
+}
+
+impl Report {
+    fn throughput(&self) -> f64 {
+        self.latencies.len() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+async fn run(mode: Mode, options: &Options) -> Report {
+    let node = SlowNode {
+        latency: options.latency,
+        socket: (mode == Mode::Serialized).then(|| Mutex::new(())),
+    };
+    let app = app(AppState::new(
+        Arc::new(node),
+        Arc::new(MemoryStorage::default()),
+    ));
+
+    let started = Instant::now();
+    let mut workers = Vec::new();
+    for worker in 0..options.concurrency {
+        // Spread the pairs as evenly as they go
+        let pairs = (options.requests + options.concurrency - worker - 1) / options.concurrency;
+        let app = app.clone();
+        workers.push(tokio::spawn(async move {
+            let mut latencies = Vec::with_capacity(pairs);
+            let mut errors = Vec::new();
+            for _ in 0..pairs {
+                let start = Instant::now();
+                match withdraw_pair(&app).await {
+                    Ok(()) => latencies.push(start.elapsed()),
+                    Err(e) => errors.push(e),
+                }
+            }
+            (latencies, errors)
+        }));
+    }
+
+    let mut report = Report {
+        elapsed: Duration::ZERO,
+        latencies: Vec::with_capacity(options.requests),
+        errors: Vec::new(),
+    };
+    for worker in workers {
+        let (latencies, errors) = worker.await.expect("Worker panicked");
+        report.latencies.extend(latencies);
+        report.errors.extend(errors);
+    }
+    report.elapsed = started.elapsed();
+    report.latencies.sort();
+    report
+}
+
+// =============================================================================
+// Main
+// =============================================================================
+
+#[tokio::main]
+async fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    eprintln!(
+        "{} withdraw pairs, {} at a time, {:?} per node call",
+        options.requests, options.concurrency, options.latency
+    );
+
+    let mut regressed = false;
+    for &mode in &options.modes {
+        let report = run(mode, &options).await;
+        eprintln!(
+            "{:<11} {:>8.1} pairs/s  p50 {:>9.2?}  p99 {:>9.2?}  max {:>9.2?}  errors {}",
+            format!("{:?}", mode).to_lowercase(),
+            report.throughput(),
+            report.percentile(50.0),
+            report.percentile(99.0),
+            report.percentile(100.0),
+            report.errors.len(),
+        );
+        if let Some(e) = report.errors.first() {
+            eprintln!("  first error: {}", e);
+            regressed = true;
+        }
+        if let Some(min) = options.min_throughput {
+            if report.throughput() < min {
+                eprintln!("  below --min-throughput {}", min);
+                regressed = true;
+            }
+        }
+    }
+
+    if regressed {
+        std::process::exit(1);
+    }
+}
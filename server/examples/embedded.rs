@@ -0,0 +1,504 @@
+// =============================================================================
+// Embedding the LNURL router in an existing web app
+// =============================================================================
+//
+// A small shop that pays out customer refunds over LNURL-withdraw. The shop
+// has its own axum app and state; lnurl-server is mounted under /lnurl with:
+//
+//   storage — ShopStorage, the in-memory storage plus a hook that makes every
+//             LNURL account a shop customer
+//   policy  — a daily payout cap (WithdrawPolicy) and a budget per customer
+//             tier (AuthHandler), both reading the shop's state
+//
+// Run next to a CLN node (LIGHTNING_RPC, default ~/.lightning/testnet4/...):
+//
+//   SHOP_URL=http://192.168.27.72:8080 cargo run -p lnurl-server --example embedded
+//
+// then point a wallet at $SHOP_URL/lnurl (e.g. lnurl-client withdraw), and
+// see what the shop saw at $SHOP_URL/customers and $SHOP_URL/payouts.
+
+use async_trait::async_trait;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use lnurl_server::backend::{Backend, ChannelFees, ClnBackend};
+use lnurl_server::policy::{AuthHandler, Verdict, WithdrawBounds, WithdrawPolicy};
+use lnurl_server::storage::{
+    Account, Allowance, Channel, Deletion, DeletionRequester, K1Purpose, K1Status, K1Ttls,
+    LiquidityReport, MemoryStorage, Order, OrderState, Snapshot, Storage, StorageResult,
+    StorageStats, Voucher, Withdrawal, WithdrawalStatus,
+};
+use lnurl_server::{app, AppState, Limits, IP_ADDRESS, NODE_URI};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What the shop pays out per day, across all customers
+const DAILY_PAYOUT_CAP_MSAT: u64 = 50_000_000; // 50k sats
+
+// =============================================================================
+// The shop
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Tier {
+    Regular,
+    Gold,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Payout {
+    customer: Option<String>,
+    amount_msat: u64,
+    paid: bool,
+}
+
+/// The shop's own state, shared with the LNURL side through the storage and
+/// policies below
+#[derive(Default)]
+struct Shop {
+    customers: Mutex<HashMap<String, Tier>>, // by linking key
+    payouts: Mutex<Vec<Payout>>,
+    paid_today_msat: Mutex<u64>,
+}
+
+async fn home() -> &'static str {
+    "Refunds: point your wallet at /lnurl\n"
+}
+
+async fn customers(State(shop): State<Arc<Shop>>) -> Json<HashMap<String, Tier>> {
+    Json(shop.customers.lock().unwrap().clone())
+}
+
+/// The shop's back office promotes customers by linking key; those promoted
+/// before their first login start with ten times the budget
+async fn make_gold(State(shop): State<Arc<Shop>>, Path(linking_key): Path<String>) -> StatusCode {
+    shop.customers
+        .lock()
+        .unwrap()
+        .insert(linking_key, Tier::Gold);
+    StatusCode::NO_CONTENT
+}
+
+async fn payouts(State(shop): State<Arc<Shop>>) -> Json<Vec<Payout>> {
+    Json(shop.payouts.lock().unwrap().clone())
+}
+
+// =============================================================================
+// Storage: the in-memory one, with new accounts becoming customers
+// =============================================================================
+
+struct ShopStorage {
+    inner: MemoryStorage,
+    shop: Arc<Shop>,
+}
+
+#[async_trait]
+impl Storage for ShopStorage {
+    async fn insert_k1(&self, k1: &str, purpose: K1Purpose) -> StorageResult<bool> {
+        self.inner.insert_k1(k1, purpose).await
+    }
+
+    async fn restore_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        issued_at: u64,
+        capacity_sat: Option<u64>,
+    ) -> StorageResult<bool> {
+        self.inner
+            .restore_k1(k1, purpose, issued_at, capacity_sat)
+            .await
+    }
+
+    async fn k1_issued_at(&self, k1: &str) -> StorageResult<Option<u64>> {
+        self.inner.k1_issued_at(k1).await
+    }
+
+    async fn consume_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        ttls: &K1Ttls,
+    ) -> StorageResult<K1Status> {
+        self.inner.consume_k1(k1, purpose, ttls).await
+    }
+
+    async fn set_k1_capacity(&self, k1: &str, capacity_sat: u64) -> StorageResult<()> {
+        self.inner.set_k1_capacity(k1, capacity_sat).await
+    }
+
+    async fn k1_capacity(&self, k1: &str) -> StorageResult<Option<u64>> {
+        self.inner.k1_capacity(k1).await
+    }
+
+    async fn sold_k1s(&self) -> StorageResult<Vec<String>> {
+        self.inner.sold_k1s().await
+    }
+
+    async fn list_k1s(&self, limit: usize) -> StorageResult<Vec<(String, Option<K1Purpose>)>> {
+        self.inner.list_k1s(limit).await
+    }
+
+    async fn sweep_k1s(&self, ttls: &K1Ttls, now: u64) -> StorageResult<Vec<Option<K1Purpose>>> {
+        self.inner.sweep_k1s(ttls, now).await
+    }
+
+    async fn ensure_account(
+        &self,
+        linking_key: &str,
+        withdraw_budget_msat: u64,
+    ) -> StorageResult<bool> {
+        let created = self
+            .inner
+            .ensure_account(linking_key, withdraw_budget_msat)
+            .await?;
+        self.shop
+            .customers
+            .lock()
+            .unwrap()
+            .entry(linking_key.to_string())
+            .or_insert(Tier::Regular);
+        Ok(created)
+    }
+
+    async fn get_account(&self, linking_key: &str) -> StorageResult<Option<Account>> {
+        self.inner.get_account(linking_key).await
+    }
+
+    async fn list_accounts(&self, limit: usize) -> StorageResult<Vec<Account>> {
+        self.inner.list_accounts(limit).await
+    }
+
+    async fn record_login(&self, linking_key: &str, at: u64) -> StorageResult<()> {
+        self.inner.record_login(linking_key, at).await
+    }
+
+    async fn set_account_metadata(
+        &self,
+        linking_key: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> StorageResult<bool> {
+        self.inner.set_account_metadata(linking_key, metadata).await
+    }
+
+    async fn debit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<bool> {
+        self.inner.debit_budget(linking_key, amount_msat).await
+    }
+
+    async fn credit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<()> {
+        self.inner.credit_budget(linking_key, amount_msat).await
+    }
+
+    async fn put_allowance(&self, allowance: &Allowance) -> StorageResult<()> {
+        self.inner.put_allowance(allowance).await
+    }
+
+    async fn get_allowance(&self, linking_key: &str) -> StorageResult<Option<Allowance>> {
+        self.inner.get_allowance(linking_key).await
+    }
+
+    async fn allowance_by_link(&self, link: &str) -> StorageResult<Option<Allowance>> {
+        self.inner.allowance_by_link(link).await
+    }
+
+    async fn list_allowances(&self) -> StorageResult<Vec<Allowance>> {
+        self.inner.list_allowances().await
+    }
+
+    async fn remove_allowance(&self, linking_key: &str) -> StorageResult<bool> {
+        self.inner.remove_allowance(linking_key).await
+    }
+
+    async fn set_balance_notify(&self, linking_key: &str, url: &str) -> StorageResult<()> {
+        self.inner.set_balance_notify(linking_key, url).await
+    }
+
+    async fn refill_allowance(&self, linking_key: &str, next_refill_at: u64) -> StorageResult<u64> {
+        self.inner
+            .refill_allowance(linking_key, next_refill_at)
+            .await
+    }
+
+    async fn balance_link(&self, linking_key: &str, new: &str) -> StorageResult<String> {
+        self.inner.balance_link(linking_key, new).await
+    }
+
+    async fn balance_link_owner(&self, link: &str) -> StorageResult<Option<String>> {
+        self.inner.balance_link_owner(link).await
+    }
+
+    async fn insert_session(&self, token: &str, linking_key: &str) -> StorageResult<()> {
+        self.inner.insert_session(token, linking_key).await
+    }
+
+    async fn session_linking_key(
+        &self,
+        token: &str,
+        ttl_secs: u64,
+        now: u64,
+    ) -> StorageResult<Option<String>> {
+        self.inner.session_linking_key(token, ttl_secs, now).await
+    }
+
+    async fn remove_session(&self, token: &str) -> StorageResult<bool> {
+        self.inner.remove_session(token).await
+    }
+
+    async fn sweep_sessions(&self, ttl_secs: u64, now: u64) -> StorageResult<usize> {
+        self.inner.sweep_sessions(ttl_secs, now).await
+    }
+
+    async fn list_sessions(&self, limit: usize) -> StorageResult<Vec<(String, String)>> {
+        self.inner.list_sessions(limit).await
+    }
+
+    async fn insert_voucher(&self, voucher: &Voucher) -> StorageResult<()> {
+        self.inner.insert_voucher(voucher).await
+    }
+
+    async fn take_voucher(&self, k1: &str) -> StorageResult<Option<String>> {
+        self.inner.take_voucher(k1).await
+    }
+
+    async fn get_voucher(&self, k1: &str) -> StorageResult<Option<Voucher>> {
+        self.inner.get_voucher(k1).await
+    }
+
+    async fn vouchers_for(&self, linking_key: &str) -> StorageResult<Vec<String>> {
+        self.inner.vouchers_for(linking_key).await
+    }
+
+    async fn list_vouchers(&self) -> StorageResult<Vec<Voucher>> {
+        self.inner.list_vouchers().await
+    }
+
+    async fn insert_withdrawal(&self, withdrawal: &Withdrawal) -> StorageResult<()> {
+        self.inner.insert_withdrawal(withdrawal).await
+    }
+
+    async fn finish_withdrawal(
+        &self,
+        k1: &str,
+        status: WithdrawalStatus,
+        preimage_enc: Option<&str>,
+        fee_msat: Option<u64>,
+    ) -> StorageResult<()> {
+        self.inner
+            .finish_withdrawal(k1, status, preimage_enc, fee_msat)
+            .await
+    }
+
+    async fn get_withdrawal(&self, k1: &str) -> StorageResult<Option<Withdrawal>> {
+        self.inner.get_withdrawal(k1).await
+    }
+
+    async fn list_withdrawals(&self, limit: usize) -> StorageResult<Vec<Withdrawal>> {
+        self.inner.list_withdrawals(limit).await
+    }
+
+    async fn withdrawals_for(
+        &self,
+        linking_key: &str,
+        limit: usize,
+    ) -> StorageResult<Vec<Withdrawal>> {
+        self.inner.withdrawals_for(linking_key, limit).await
+    }
+
+    async fn insert_channel(&self, channel: &Channel) -> StorageResult<()> {
+        self.inner.insert_channel(channel).await
+    }
+
+    async fn list_channels(&self, limit: usize) -> StorageResult<Vec<Channel>> {
+        self.inner.list_channels(limit).await
+    }
+
+    async fn set_channel_fees(&self, channel_id: &str, fees: &ChannelFees) -> StorageResult<()> {
+        self.inner.set_channel_fees(channel_id, fees).await
+    }
+
+    async fn insert_order(&self, order: &Order) -> StorageResult<()> {
+        self.inner.insert_order(order).await
+    }
+
+    async fn get_order(&self, id: &str) -> StorageResult<Option<Order>> {
+        self.inner.get_order(id).await
+    }
+
+    async fn list_orders(&self, state: OrderState) -> StorageResult<Vec<Order>> {
+        self.inner.list_orders(state).await
+    }
+
+    async fn claim_order(&self, id: &str, paid_at: u64) -> StorageResult<bool> {
+        self.inner.claim_order(id, paid_at).await
+    }
+
+    async fn update_order(&self, order: &Order) -> StorageResult<()> {
+        self.inner.update_order(order).await
+    }
+
+    async fn delete_account(
+        &self,
+        linking_key: &str,
+        requested_by: DeletionRequester,
+    ) -> StorageResult<Option<Deletion>> {
+        self.shop.customers.lock().unwrap().remove(linking_key);
+        self.inner.delete_account(linking_key, requested_by).await
+    }
+
+    async fn list_deletions(&self, limit: usize) -> StorageResult<Vec<Deletion>> {
+        self.inner.list_deletions(limit).await
+    }
+
+    async fn stats(&self) -> StorageResult<StorageStats> {
+        self.inner.stats().await
+    }
+
+    async fn insert_liquidity_report(&self, report: &LiquidityReport) -> StorageResult<()> {
+        self.inner.insert_liquidity_report(report).await
+    }
+
+    async fn list_liquidity_reports(&self, limit: usize) -> StorageResult<Vec<LiquidityReport>> {
+        self.inner.list_liquidity_reports(limit).await
+    }
+
+    async fn export(&self) -> StorageResult<Snapshot> {
+        self.inner.export().await
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> StorageResult<()> {
+        self.inner.import(snapshot).await
+    }
+}
+
+// =============================================================================
+// Policies: a daily cap, and budgets by tier
+// =============================================================================
+
+struct PayoutCap(Arc<Shop>);
+
+impl PayoutCap {
+    fn left_today_msat(&self) -> u64 {
+        DAILY_PAYOUT_CAP_MSAT.saturating_sub(*self.0.paid_today_msat.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl WithdrawPolicy for PayoutCap {
+    async fn bounds(&self, _owner: Option<&str>, limits: &Limits) -> WithdrawBounds {
+        WithdrawBounds {
+            min_msat: limits.min_withdrawable_msat,
+            max_msat: limits.max_withdrawable_msat.min(self.left_today_msat()),
+        }
+    }
+
+    /// Counts only what was paid, so withdrawals in flight can overshoot
+    /// the cap by their sum
+    async fn approve(&self, withdrawal: &Withdrawal) -> Verdict {
+        match withdrawal.amount_msat <= self.left_today_msat() {
+            true => Ok(()),
+            false => Err("Refunds are paused until tomorrow".to_string()),
+        }
+    }
+
+    async fn on_settled(&self, withdrawal: &Withdrawal) {
+        let paid = withdrawal.status == WithdrawalStatus::Paid;
+        if paid {
+            *self.0.paid_today_msat.lock().unwrap() += withdrawal.amount_msat;
+        }
+        self.0.payouts.lock().unwrap().push(Payout {
+            customer: withdrawal.linking_key.clone(),
+            amount_msat: withdrawal.amount_msat,
+            paid,
+        });
+    }
+}
+
+struct Tiers(Arc<Shop>);
+
+#[async_trait]
+impl AuthHandler for Tiers {
+    async fn withdraw_budget_msat(&self, linking_key: &str, limits: &Limits) -> u64 {
+        match self.0.customers.lock().unwrap().get(linking_key) {
+            Some(Tier::Gold) => limits.withdraw_budget_msat * 10,
+            _ => limits.withdraw_budget_msat,
+        }
+    }
+
+    async fn on_login(&self, linking_key: &str) {
+        println!("Customer {} logged in", linking_key);
+    }
+}
+
+// =============================================================================
+// Main
+// =============================================================================
+
+#[tokio::main]
+async fn main() {
+    // The library logs through tracing; this takes the server's LNURL_LOG setup
+    if let Err(e) = lnurl_server::logging::init() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let shop_url = std::env::var("SHOP_URL").unwrap_or("http://127.0.0.1:8080".to_string());
+    let rpc_path = std::env::var("LIGHTNING_RPC").unwrap_or_else(|_| {
+        let home = std::env::var("HOME").expect("HOME env var not set");
+        format!("{home}/.lightning/testnet4/lightning-rpc")
+    });
+
+    let backend = match ClnBackend::connect(&rpc_path).await {
+        Ok(backend) => Arc::new(backend),
+        Err(e) => {
+            eprintln!("Failed to connect to CLN RPC at {}: {}", rpc_path, e);
+            std::process::exit(1);
+        }
+    };
+    match backend.node_id().await {
+        Ok(pubkey) => {
+            NODE_URI.get_or_init(|| format!("{}@{}", pubkey, IP_ADDRESS));
+        }
+        Err(e) => {
+            eprintln!("Failed to get node info: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let shop = Arc::new(Shop::default());
+    let storage = ShopStorage {
+        inner: MemoryStorage::default(),
+        shop: shop.clone(),
+    };
+    // The cap is per day
+    let midnight = shop.clone();
+    tokio::spawn(async move {
+        let mut day = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+        loop {
+            day.tick().await;
+            *midnight.paid_today_msat.lock().unwrap() = 0;
+        }
+    });
+
+    let lnurl = AppState::new(backend, Arc::new(storage))
+        .with_callback_url(&format!("{}/lnurl", shop_url))
+        .with_withdraw_policy(Arc::new(PayoutCap(shop.clone())))
+        .with_auth_handler(Arc::new(Tiers(shop.clone())));
+
+    let app = Router::new()
+        .route("/", get(home))
+        .route("/customers", get(customers))
+        .route("/customers/:linking_key/gold", post(make_gold))
+        .route("/payouts", get(payouts))
+        .with_state(shop)
+        .nest("/lnurl", app(lnurl));
+
+    println!(
+        "Shop listening on 0.0.0.0:8080, LNURL at {}/lnurl",
+        shop_url
+    );
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
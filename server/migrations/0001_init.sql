@@ -0,0 +1,26 @@
+-- Amounts are millisatoshis, timestamps unix seconds.
+
+CREATE TABLE k1s (
+    k1          TEXT PRIMARY KEY,
+    created_at  BIGINT NOT NULL
+);
+
+CREATE TABLE accounts (
+    linking_key           TEXT PRIMARY KEY,
+    created_at            BIGINT NOT NULL,
+    withdraw_budget_msat  BIGINT NOT NULL CHECK (withdraw_budget_msat >= 0)
+);
+
+CREATE TABLE sessions (
+    token        TEXT PRIMARY KEY,
+    linking_key  TEXT NOT NULL REFERENCES accounts (linking_key) ON DELETE CASCADE,
+    created_at   BIGINT NOT NULL
+);
+
+CREATE TABLE vouchers (
+    k1           TEXT PRIMARY KEY,
+    linking_key  TEXT NOT NULL REFERENCES accounts (linking_key) ON DELETE CASCADE,
+    created_at   BIGINT NOT NULL
+);
+
+CREATE INDEX vouchers_linking_key_idx ON vouchers (linking_key);
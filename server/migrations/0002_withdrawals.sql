@@ -0,0 +1,13 @@
+-- preimage_enc holds a crypto::FieldCipher ciphertext, never the raw preimage.
+
+CREATE TABLE withdrawals (
+    k1            TEXT PRIMARY KEY,
+    linking_key   TEXT REFERENCES accounts (linking_key) ON DELETE SET NULL,
+    bolt11        TEXT NOT NULL,
+    amount_msat   BIGINT NOT NULL,
+    status        TEXT NOT NULL,
+    preimage_enc  TEXT,
+    created_at    BIGINT NOT NULL
+);
+
+CREATE INDEX withdrawals_created_at_idx ON withdrawals (created_at);
@@ -0,0 +1,14 @@
+-- Audit trail of account deletions. Deliberately holds no linking key or
+-- other identifier of the deleted account, only what was removed.
+
+CREATE TABLE deletions (
+    id                      TEXT PRIMARY KEY,
+    deleted_at              BIGINT NOT NULL,
+    requested_by            TEXT NOT NULL,
+    sessions_removed        BIGINT NOT NULL,
+    vouchers_removed        BIGINT NOT NULL,
+    withdrawals_anonymized  BIGINT NOT NULL,
+    forfeited_budget_msat   BIGINT NOT NULL
+);
+
+CREATE INDEX deletions_deleted_at_idx ON deletions (deleted_at);
@@ -0,0 +1,4 @@
+-- The flow each k1 was issued for: channel, withdraw or auth. NULL for k1s
+-- issued before this column existed.
+
+ALTER TABLE k1s ADD COLUMN purpose TEXT;
@@ -0,0 +1,5 @@
+-- Routing fee a withdrawal's payment cost on top of its amount, set once
+-- paid. NULL for pending and failed withdrawals and for ones paid before this
+-- column existed.
+
+ALTER TABLE withdrawals ADD COLUMN fee_msat BIGINT;
@@ -0,0 +1,14 @@
+-- History of the liquidity report job (liquidity.rs). Channels are kept as
+-- the JSON array the admin API returns; nothing queries into them.
+
+CREATE TABLE liquidity_reports (
+    id                        BIGSERIAL PRIMARY KEY,
+    taken_at                  BIGINT NOT NULL,
+    onchain_sat               BIGINT NOT NULL,
+    onchain_unconfirmed_sat   BIGINT NOT NULL,
+    channels                  TEXT NOT NULL,
+    outbound_msat             BIGINT NOT NULL,
+    inbound_msat              BIGINT NOT NULL,
+    pending_withdrawals_msat  BIGINT NOT NULL,
+    outstanding_budget_msat   BIGINT NOT NULL
+);
@@ -0,0 +1,19 @@
+-- Channels opened through LUD-02. The fee columns are set together, with
+-- fees_set_at, once the fee setup (fees.rs) has run on the channel; they stay
+-- NULL when no fee setup is configured.
+
+CREATE TABLE channels (
+    channel_id      TEXT PRIMARY KEY,
+    node_id         TEXT NOT NULL,
+    capacity_sat    BIGINT NOT NULL,
+    private         BOOLEAN NOT NULL,
+    txid            TEXT NOT NULL,
+    opened_at       BIGINT NOT NULL,
+    fee_base_msat   BIGINT,
+    fee_ppm         BIGINT,
+    htlc_min_msat   BIGINT,
+    htlc_max_msat   BIGINT,
+    fees_set_at     BIGINT
+);
+
+CREATE INDEX channels_opened_at_idx ON channels (opened_at);
@@ -0,0 +1,12 @@
+-- Withdraw budgets that refill on a schedule (allowance.rs), one per account
+-- at most. `link` is the secret id of the account's reusable withdraw link.
+
+CREATE TABLE allowances (
+    linking_key     TEXT PRIMARY KEY REFERENCES accounts (linking_key) ON DELETE CASCADE,
+    link            TEXT NOT NULL UNIQUE,
+    amount_msat     BIGINT NOT NULL,
+    period_secs     BIGINT NOT NULL,
+    next_refill_at  BIGINT NOT NULL,
+    balance_notify  TEXT,
+    created_at      BIGINT NOT NULL
+);
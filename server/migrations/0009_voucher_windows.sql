@@ -0,0 +1,5 @@
+-- When a voucher can be redeemed: from valid_from (inclusive) until
+-- valid_until (exclusive), open-ended on a side left NULL.
+
+ALTER TABLE vouchers ADD COLUMN valid_from BIGINT;
+ALTER TABLE vouchers ADD COLUMN valid_until BIGINT;
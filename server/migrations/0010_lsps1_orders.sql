@@ -0,0 +1,23 @@
+-- Channels bought through LSPS1 (lsps1.rs). The channel columns are set
+-- together once the order's channel is funded.
+
+CREATE TABLE lsps1_orders (
+    order_id                        TEXT PRIMARY KEY,
+    node_id                         TEXT NOT NULL,
+    capacity_sat                    BIGINT NOT NULL,
+    announce                        BOOLEAN NOT NULL,
+    required_confirmations          BIGINT NOT NULL,
+    funding_confirms_within_blocks  BIGINT NOT NULL,
+    channel_expiry_blocks           BIGINT NOT NULL,
+    token                           TEXT NOT NULL,
+    price_msat                      BIGINT NOT NULL,
+    bolt11                          TEXT NOT NULL,
+    state                           TEXT NOT NULL,
+    created_at                      BIGINT NOT NULL,
+    paid_at                         BIGINT,
+    channel_id                      TEXT,
+    funding_outpoint                TEXT,
+    funded_at                       BIGINT
+);
+
+CREATE INDEX lsps1_orders_state_idx ON lsps1_orders (state, created_at);
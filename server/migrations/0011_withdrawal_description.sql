@@ -0,0 +1,6 @@
+-- The paid invoice's description, cleaned of control characters and cut to
+-- 256 characters (text::clean_description). NULL when the invoice only
+-- carries a description hash, and for withdrawals made before this column
+-- existed.
+
+ALTER TABLE withdrawals ADD COLUMN description TEXT;
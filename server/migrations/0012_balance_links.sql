@@ -0,0 +1,8 @@
+-- Reusable withdraw links of accounts (LUD-14 balanceCheck), one per account
+-- at most, made the first time a withdraw request of the account needs one.
+-- `link` is its secret id.
+
+CREATE TABLE balance_links (
+    linking_key     TEXT PRIMARY KEY REFERENCES accounts (linking_key) ON DELETE CASCADE,
+    link            TEXT NOT NULL UNIQUE
+);
@@ -0,0 +1,6 @@
+-- Accounts as a registry of the users behind them: when each last logged in
+-- through LNURL-auth (NULL for logins from before this column existed), and
+-- the operator's labels about it, a JSON object of strings.
+
+ALTER TABLE accounts ADD COLUMN last_login_at BIGINT;
+ALTER TABLE accounts ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+-- The capacity a channel k1 was sold at (pricing.rs), which /open-channel
+-- funds exactly once the invoice is paid. NULL for every other k1.
+
+ALTER TABLE k1s ADD COLUMN capacity_sat BIGINT;
@@ -0,0 +1,843 @@
+// =============================================================================
+// ACME certificates
+// =============================================================================
+//
+// Built with --features acme, the public listener can get its certificate
+// from an ACME CA (RFC 8555), Let's Encrypt unless tls.acme_directory names
+// another, for the names in tls.acme_domains (see config.rs). The CA checks
+// that we control them with http-01 challenges: it fetches
+// http://<domain>/.well-known/acme-challenge/<token>, so tls.acme_http_addr
+// has to be what port 80 of those names leads to. Any other request there is
+// redirected to https.
+//
+// The account key, the certificate and its key are kept in tls.acme_cache,
+// so a restart reuses them. A certificate with less than RENEW_BEFORE left,
+// or one that doesn't cover every domain, is replaced at startup; after that
+// it is checked twice a day and swapped on the listener without a restart
+// once renewed. A failed renewal is logged and tried again at the next check,
+// the current certificate having weeks left.
+
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL, Engine};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::ServerConfig;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use x509_parser::extensions::GeneralName;
+
+use crate::config::AcmeConfig;
+use crate::listener;
+
+/// Certificates with less than this left are renewed
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 3600);
+pub const RENEW_CHECK_EVERY: Duration = Duration::from_secs(12 * 3600);
+const TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_EVERY: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 60;
+
+#[derive(Debug)]
+pub struct AcmeError(String);
+
+impl fmt::Display for AcmeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for AcmeError {}
+
+fn error(context: &str, e: impl fmt::Display) -> AcmeError {
+    AcmeError(format!("{}: {}", context, e))
+}
+
+/// Key authorizations of the challenges in progress, by token
+type Challenges = Arc<Mutex<HashMap<String, String>>>;
+
+/// Answers challenges on tls.acme_http_addr, gets the certificate or reuses
+/// the cached one, and keeps it renewed; the public listener's TLS
+/// configuration
+pub async fn start(config: AcmeConfig) -> Result<RustlsConfig, AcmeError> {
+    let challenges = Challenges::default();
+    let listener = tokio::net::TcpListener::bind(config.http_addr)
+        .await
+        .map_err(|e| error(&format!("Cannot listen on {}", config.http_addr), e))?;
+    let app = challenge_app(challenges.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    info!("ACME challenges answered on {}", config.http_addr);
+
+    let (cert, key, _) = certificate(&config, &challenges).await?;
+    let tls = RustlsConfig::from_config(Arc::new(tls_config(&cert, &key)?));
+    tokio::spawn(renew(config, challenges, tls.clone(), RENEW_CHECK_EVERY));
+    Ok(tls)
+}
+
+/// Checks the certificate `every` so often and swaps in renewed ones
+async fn renew(config: AcmeConfig, challenges: Challenges, tls: RustlsConfig, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let renewed = match certificate(&config, &challenges).await {
+            Ok((cert, key, true)) => tls_config(&cert, &key),
+            Ok((_, _, false)) => continue,
+            Err(e) => Err(e),
+        };
+        match renewed {
+            Ok(renewed) => tls.reload_from_config(Arc::new(renewed)),
+            Err(e) => warn!("ACME renewal failed, trying again later: {}", e),
+        }
+    }
+}
+
+fn tls_config(cert: &str, key: &str) -> Result<ServerConfig, AcmeError> {
+    listener::public_tls_config("ACME", cert.as_bytes(), key.as_bytes())
+        .map_err(|e| AcmeError(e.to_string()))
+}
+
+/// The cached certificate and key if they will do, else new ones, and
+/// whether they are new
+async fn certificate(
+    config: &AcmeConfig,
+    challenges: &Challenges,
+) -> Result<(String, String, bool), AcmeError> {
+    let cert_path = config.cache.join("cert.pem");
+    let key_path = config.cache.join("key.pem");
+    let cached = std::fs::read_to_string(&cert_path)
+        .ok()
+        .zip(std::fs::read_to_string(&key_path).ok());
+    if let Some((cert, key)) = cached {
+        match validity(&cert, &config.domains) {
+            Some(left) if left > RENEW_BEFORE => return Ok((cert, key, false)),
+            Some(left) => info!(
+                "ACME certificate expires in {}d, renewing",
+                left.as_secs() / 86400
+            ),
+            None => info!(
+                "ACME certificate does not cover {}, replacing it",
+                config.domains.join(", ")
+            ),
+        }
+    }
+
+    let (ordering, answering) = (config.clone(), challenges.clone());
+    let (cert, key) = tokio::task::spawn_blocking(move || order(&ordering, &answering))
+        .await
+        .map_err(|e| AcmeError(e.to_string()))??;
+    write_private(&key_path, key.as_bytes())?;
+    std::fs::write(&cert_path, &cert).map_err(|e| error(&cert_path.display().to_string(), e))?;
+    info!("ACME certificate issued for {}", config.domains.join(", "));
+    Ok((cert, key, true))
+}
+
+/// How long the certificate has left, None when it can't be read or doesn't
+/// cover every domain
+fn validity(cert_pem: &str, domains: &[String]) -> Option<Duration> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes()).ok()?;
+    let cert = pem.parse_x509().ok()?;
+    let names: Vec<String> = match cert.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    if !domains
+        .iter()
+        .all(|domain| names.contains(&domain.to_ascii_lowercase()))
+    {
+        return None;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let left = cert
+        .validity()
+        .not_after
+        .timestamp()
+        .saturating_sub(now)
+        .max(0);
+    Some(Duration::from_secs(left as u64))
+}
+
+/// Creates or replaces `path`, readable by us only (private keys)
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), AcmeError> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| error(&dir.display().to_string(), e))?;
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| error(&path.display().to_string(), e))
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), AcmeError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| error(&dir.display().to_string(), e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| error(&path.display().to_string(), e))
+}
+
+// -----------------------------------------------------------------------------
+// http-01
+// -----------------------------------------------------------------------------
+
+fn challenge_app(challenges: Challenges) -> Router {
+    Router::new()
+        .route("/.well-known/acme-challenge/:token", get(answer))
+        .fallback(to_https)
+        .with_state(challenges)
+}
+
+async fn answer(State(challenges): State<Challenges>, UrlPath(token): UrlPath<String>) -> Response {
+    match challenges.lock().unwrap().get(&token) {
+        Some(key_authorization) => key_authorization.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn to_https(headers: HeaderMap, uri: Uri) -> Response {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    let plain_host = |c: char| c.is_ascii_alphanumeric() || ".-:[]".contains(c);
+    match host {
+        Some(host) if !host.is_empty() && host.chars().all(plain_host) => {
+            let host = host.strip_suffix(":80").unwrap_or(host);
+            let path = uri.path_and_query().map_or("/", |path| path.as_str());
+            Redirect::permanent(&format!("https://{}{}", host, path)).into_response()
+        }
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// RFC 8555, blocking, ureq being what the server already speaks HTTP with
+// -----------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+    error: Option<Value>,
+}
+
+/// The `detail` of an RFC 7807 problem
+fn detail(problem: Option<&Value>) -> &str {
+    problem
+        .and_then(|problem| problem["detail"].as_str())
+        .unwrap_or("no details")
+}
+
+/// An account with the CA, which signs our requests with its key
+struct Account {
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    directory: Directory,
+    kid: Option<String>, // account URL, once registered
+    nonce: Option<String>,
+}
+
+impl Account {
+    /// Registers the cached key (made on first use) with the CA, which
+    /// answers with the existing account if it knows the key
+    fn open(config: &AcmeConfig) -> Result<Account, AcmeError> {
+        let rng = SystemRandom::new();
+        let path = config.cache.join("account.key");
+        let pkcs8 = match std::fs::read(&path) {
+            Ok(pkcs8) => pkcs8,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| AcmeError("Cannot generate an ACME account key".to_string()))?;
+                write_private(&path, pkcs8.as_ref())?;
+                pkcs8.as_ref().to_vec()
+            }
+            Err(e) => return Err(error(&path.display().to_string(), e)),
+        };
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|e| error(&format!("Invalid ACME account key {}", path.display()), e))?;
+        let directory = ureq::get(&config.directory)
+            .timeout(TIMEOUT)
+            .call()
+            .map_err(|e| error(&format!("ACME directory {}", config.directory), e))?
+            .into_json()
+            .map_err(|e| error(&format!("ACME directory {}", config.directory), e))?;
+
+        let mut account = Account {
+            key,
+            rng,
+            directory,
+            kid: None,
+            nonce: None,
+        };
+        let mut registration = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = &config.email {
+            registration["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = account.directory.new_account.clone();
+        let response = account.post(&url, Some(&registration))?;
+        let kid = response
+            .header("Location")
+            .ok_or_else(|| AcmeError("No account URL from the ACME CA".to_string()))?;
+        account.kid = Some(kid.to_string());
+        Ok(account)
+    }
+
+    /// The x and y of the public key, base64url
+    fn coordinates(&self) -> (String, String) {
+        let point = self.key.public_key().as_ref(); // 0x04 || x || y
+        (
+            BASE64URL.encode(&point[1..33]),
+            BASE64URL.encode(&point[33..65]),
+        )
+    }
+
+    /// The RFC 7638 thumbprint of the key, which key authorizations end with
+    fn thumbprint(&self) -> String {
+        let (x, y) = self.coordinates();
+        // Members in lexicographic order, no whitespace, as the RFC requires
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        BASE64URL.encode(Sha256::digest(jwk.as_bytes()))
+    }
+
+    fn nonce(&mut self) -> Result<String, AcmeError> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = ureq::head(&self.directory.new_nonce)
+            .timeout(TIMEOUT)
+            .call()
+            .map_err(|e| error("Cannot get an ACME nonce", e))?;
+        replay_nonce(&response).ok_or_else(|| AcmeError("No nonce from the ACME CA".to_string()))
+    }
+
+    /// The request as a flattened JWS, signed with the account key
+    fn sign(&mut self, url: &str, payload: Option<&Value>) -> Result<String, AcmeError> {
+        let mut protected = json!({ "alg": "ES256", "nonce": self.nonce()?, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => {
+                let (x, y) = self.coordinates();
+                protected["jwk"] = json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y });
+            }
+        }
+        let protected = BASE64URL.encode(protected.to_string());
+        // POST-as-GET has an empty payload
+        let payload = payload.map_or(String::new(), |payload| {
+            BASE64URL.encode(payload.to_string())
+        });
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| AcmeError("Cannot sign with the ACME account key".to_string()))?;
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": BASE64URL.encode(signature.as_ref()),
+        });
+        Ok(jws.to_string())
+    }
+
+    /// A signed POST, or POST-as-GET without `payload`; tried again once when
+    /// the CA refuses the nonce, as RFC 8555 expects clients to
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<ureq::Response, AcmeError> {
+        let mut retried = false;
+        loop {
+            let body = self.sign(url, payload)?;
+            let result = ureq::post(url)
+                .timeout(TIMEOUT)
+                .set("Content-Type", "application/jose+json")
+                .send_string(&body);
+            match result {
+                Ok(response) => {
+                    self.nonce = replay_nonce(&response);
+                    return Ok(response);
+                }
+                Err(ureq::Error::Status(code, response)) => {
+                    self.nonce = replay_nonce(&response);
+                    let problem: Value = response.into_json().unwrap_or_default();
+                    let kind = problem["type"].as_str().unwrap_or_default();
+                    if kind.ends_with(":badNonce") && !retried {
+                        retried = true;
+                        continue;
+                    }
+                    return Err(AcmeError(format!(
+                        "{} answered {}: {}",
+                        url,
+                        code,
+                        detail(Some(&problem))
+                    )));
+                }
+                Err(ureq::Error::Transport(e)) => {
+                    return Err(error(&format!("{} unreachable", url), e))
+                }
+            }
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&mut self, url: &str) -> Result<T, AcmeError> {
+        self.post(url, None)?
+            .into_json()
+            .map_err(|e| error(&format!("Unexpected answer from {}", url), e))
+    }
+}
+
+fn replay_nonce(response: &ureq::Response) -> Option<String> {
+    response.header("Replay-Nonce").map(str::to_string)
+}
+
+/// Asks `check` every POLL_EVERY until it has an answer
+fn poll<T>(mut check: impl FnMut() -> Result<Option<T>, AcmeError>) -> Result<T, AcmeError> {
+    for _ in 0..POLL_ATTEMPTS {
+        if let Some(done) = check()? {
+            return Ok(done);
+        }
+        std::thread::sleep(POLL_EVERY);
+    }
+    Err(AcmeError("The ACME CA took too long".to_string()))
+}
+
+/// Orders a certificate for the domains; its PEM chain and private key
+fn order(config: &AcmeConfig, challenges: &Challenges) -> Result<(String, String), AcmeError> {
+    let mut account = Account::open(config)?;
+    let identifiers: Vec<Value> = config
+        .domains
+        .iter()
+        .map(|domain| json!({ "type": "dns", "value": domain }))
+        .collect();
+    let url = account.directory.new_order.clone();
+    let response = account.post(&url, Some(&json!({ "identifiers": identifiers })))?;
+    let order_url = response
+        .header("Location")
+        .ok_or_else(|| AcmeError("No order URL from the ACME CA".to_string()))?
+        .to_string();
+    let order: Order = response
+        .into_json()
+        .map_err(|e| error("Unexpected order from the ACME CA", e))?;
+
+    for url in &order.authorizations {
+        authorize(&mut account, url, challenges)?;
+    }
+
+    let key = rcgen::KeyPair::generate().map_err(|e| error("Cannot generate a key", e))?;
+    let csr = rcgen::CertificateParams::new(config.domains.clone())
+        .and_then(|params| params.serialize_request(&key))
+        .map_err(|e| error("Cannot make the certificate request", e))?;
+    account.post(
+        &order.finalize,
+        Some(&json!({ "csr": BASE64URL.encode(csr.der()) })),
+    )?;
+    let order = poll(|| {
+        let order: Order = account.get(&order_url)?;
+        match order.status.as_str() {
+            "valid" => Ok(Some(order)),
+            "pending" | "ready" | "processing" => Ok(None),
+            status => Err(AcmeError(format!(
+                "ACME order {}: {}",
+                status,
+                detail(order.error.as_ref())
+            ))),
+        }
+    })?;
+    let url = order
+        .certificate
+        .ok_or_else(|| AcmeError("No certificate URL from the ACME CA".to_string()))?;
+    let cert = account
+        .post(&url, None)?
+        .into_string()
+        .map_err(|e| error("Cannot read the certificate", e))?;
+    Ok((cert, key.serialize_pem()))
+}
+
+/// Proves control of one domain of the order with its http-01 challenge
+fn authorize(account: &mut Account, url: &str, challenges: &Challenges) -> Result<(), AcmeError> {
+    let authorization: Authorization = account.get(url)?;
+    if authorization.status == "valid" {
+        return Ok(());
+    }
+    let domain = authorization.identifier.value;
+    let challenge = authorization
+        .challenges
+        .into_iter()
+        .find(|challenge| challenge.kind == "http-01")
+        .ok_or_else(|| AcmeError(format!("No http-01 challenge for {}", domain)))?;
+    let token = challenge
+        .token
+        .ok_or_else(|| AcmeError(format!("No http-01 token for {}", domain)))?;
+    let key_authorization = format!("{}.{}", token, account.thumbprint());
+    challenges
+        .lock()
+        .unwrap()
+        .insert(token.clone(), key_authorization);
+
+    let mut prove = || {
+        account.post(&challenge.url, Some(&json!({})))?;
+        poll(|| {
+            let authorization: Authorization = account.get(url)?;
+            match authorization.status.as_str() {
+                "valid" => Ok(Some(())),
+                "pending" | "processing" => Ok(None),
+                status => {
+                    let problem = authorization
+                        .challenges
+                        .iter()
+                        .find_map(|c| c.error.as_ref());
+                    Err(AcmeError(format!(
+                        "ACME authorization of {} {}: {}",
+                        domain,
+                        status,
+                        detail(problem)
+                    )))
+                }
+            }
+        })
+    };
+    let proved = prove();
+    challenges.lock().unwrap().remove(&token);
+    proved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::routing::{head, post};
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    /// A CA of one order, which fetches its http-01 challenge from
+    /// `challenge_addr` and checks the signature of every request
+    #[derive(Clone)]
+    struct FakeCa {
+        base: String,
+        challenge_addr: SocketAddr,
+        jwk: Arc<Mutex<Option<Value>>>,
+        authorized: Arc<Mutex<bool>>,
+        csr: Arc<Mutex<Option<Vec<u8>>>>,
+        nonces: Arc<Mutex<u32>>,
+    }
+
+    impl FakeCa {
+        fn nonce(&self) -> String {
+            let mut nonces = self.nonces.lock().unwrap();
+            *nonces += 1;
+            format!("nonce-{}", nonces)
+        }
+
+        /// The payload of a JWS body, after checking its signature
+        fn open(&self, body: &[u8]) -> Value {
+            let jws: Value = serde_json::from_slice(body).unwrap();
+            let protected = jws["protected"].as_str().unwrap();
+            let header: Value =
+                serde_json::from_slice(&BASE64URL.decode(protected).unwrap()).unwrap();
+            if header.get("jwk").is_some() {
+                *self.jwk.lock().unwrap() = Some(header["jwk"].clone());
+            } else {
+                assert_eq!(header["kid"], format!("{}/account/1", self.base));
+            }
+            let jwk = self.jwk.lock().unwrap().clone().unwrap();
+            let coordinate = |name: &str| BASE64URL.decode(jwk[name].as_str().unwrap()).unwrap();
+            let point = [vec![4], coordinate("x"), coordinate("y")].concat();
+            let payload = jws["payload"].as_str().unwrap();
+            let signed = format!("{}.{}", protected, payload);
+            let signature = BASE64URL
+                .decode(jws["signature"].as_str().unwrap())
+                .unwrap();
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+                .verify(signed.as_bytes(), &signature)
+                .expect("signed with the account key");
+            match payload.is_empty() {
+                true => Value::Null,
+                false => serde_json::from_slice(&BASE64URL.decode(payload).unwrap()).unwrap(),
+            }
+        }
+
+        fn reply(&self, status: StatusCode, location: Option<&str>, body: Value) -> Response {
+            let mut response = (status, axum::Json(body)).into_response();
+            let headers = response.headers_mut();
+            headers.insert("Replay-Nonce", self.nonce().parse().unwrap());
+            if let Some(location) = location {
+                headers.insert("Location", location.parse().unwrap());
+            }
+            response
+        }
+    }
+
+    async fn fake_ca(challenge_addr: SocketAddr) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let ca = FakeCa {
+            base: base.clone(),
+            challenge_addr,
+            jwk: Arc::default(),
+            authorized: Arc::default(),
+            csr: Arc::default(),
+            nonces: Arc::default(),
+        };
+        let app = Router::new()
+            .route(
+                "/directory",
+                get(|State(ca): State<FakeCa>| async move {
+                    axum::Json(json!({
+                        "newNonce": format!("{}/nonce", ca.base),
+                        "newAccount": format!("{}/account", ca.base),
+                        "newOrder": format!("{}/order", ca.base),
+                    }))
+                }),
+            )
+            .route(
+                "/nonce",
+                head(|State(ca): State<FakeCa>| async move {
+                    ca.reply(StatusCode::OK, None, Value::Null)
+                }),
+            )
+            .route(
+                "/account",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    let registration = ca.open(&body);
+                    assert_eq!(registration["contact"][0], "mailto:ops@lnurl.example");
+                    let account = format!("{}/account/1", ca.base);
+                    ca.reply(
+                        StatusCode::CREATED,
+                        Some(&account),
+                        json!({ "status": "valid" }),
+                    )
+                }),
+            )
+            .route(
+                "/order",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    let order = ca.open(&body);
+                    assert_eq!(order["identifiers"][0]["value"], "localhost");
+                    let location = format!("{}/order/1", ca.base);
+                    let order = json!({
+                        "status": "pending",
+                        "authorizations": [format!("{}/authz/1", ca.base)],
+                        "finalize": format!("{}/finalize/1", ca.base),
+                    });
+                    ca.reply(StatusCode::CREATED, Some(&location), order)
+                }),
+            )
+            .route(
+                "/authz/1",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    ca.open(&body);
+                    let status = match *ca.authorized.lock().unwrap() {
+                        true => "valid",
+                        false => "pending",
+                    };
+                    let authorization = json!({
+                        "status": status,
+                        "identifier": { "type": "dns", "value": "localhost" },
+                        "challenges": [{
+                            "type": "http-01",
+                            "url": format!("{}/challenge/1", ca.base),
+                            "token": "tok3n",
+                        }],
+                    });
+                    ca.reply(StatusCode::OK, None, authorization)
+                }),
+            )
+            .route(
+                "/challenge/1",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    assert_eq!(ca.open(&body), json!({}));
+                    // Fetch the key authorization the way the CA would
+                    let url = format!(
+                        "http://{}/.well-known/acme-challenge/tok3n",
+                        ca.challenge_addr
+                    );
+                    let answer = tokio::task::spawn_blocking(move || {
+                        ureq::get(&url).call().unwrap().into_string().unwrap()
+                    })
+                    .await
+                    .unwrap();
+                    let (token, thumbprint) = answer.split_once('.').unwrap();
+                    assert_eq!(token, "tok3n");
+                    let jwk = ca.jwk.lock().unwrap().clone().unwrap();
+                    let canonical = format!(
+                        r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+                        jwk["x"], jwk["y"]
+                    );
+                    assert_eq!(thumbprint, BASE64URL.encode(Sha256::digest(canonical)));
+                    *ca.authorized.lock().unwrap() = true;
+                    ca.reply(StatusCode::OK, None, json!({ "status": "processing" }))
+                }),
+            )
+            .route(
+                "/finalize/1",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    let finalize = ca.open(&body);
+                    let csr = BASE64URL.decode(finalize["csr"].as_str().unwrap()).unwrap();
+                    *ca.csr.lock().unwrap() = Some(csr);
+                    ca.reply(StatusCode::OK, None, json!({ "status": "processing" }))
+                }),
+            )
+            .route(
+                "/order/1",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    ca.open(&body);
+                    let order = json!({
+                        "status": "valid",
+                        "finalize": format!("{}/finalize/1", ca.base),
+                        "certificate": format!("{}/cert/1", ca.base),
+                    });
+                    ca.reply(StatusCode::OK, None, order)
+                }),
+            )
+            .route(
+                "/cert/1",
+                post(|State(ca): State<FakeCa>, body: Bytes| async move {
+                    ca.open(&body);
+                    let csr = ca.csr.lock().unwrap().take().unwrap();
+                    let csr = rcgen::CertificateSigningRequestParams::from_der(&csr.into());
+                    let params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+                    let issuer = rcgen::Issuer::new(params, rcgen::KeyPair::generate().unwrap());
+                    let cert = csr.unwrap().signed_by(&issuer).unwrap();
+                    let mut response = cert.pem().into_response();
+                    response
+                        .headers_mut()
+                        .insert("Replay-Nonce", ca.nonce().parse().unwrap());
+                    response
+                }),
+            )
+            .with_state(ca);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        base
+    }
+
+    #[tokio::test]
+    async fn certificates_are_ordered_once_and_then_cached() {
+        let challenges = Challenges::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = listener.local_addr().unwrap();
+        let app = challenge_app(challenges.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let base = fake_ca(http_addr).await;
+        let cache = std::env::temp_dir().join(format!("lnurl-acme-{}", crate::random_hex_32()));
+        let config = AcmeConfig {
+            domains: vec!["localhost".to_string()],
+            email: Some("ops@lnurl.example".to_string()),
+            directory: format!("{}/directory", base),
+            cache: cache.clone(),
+            http_addr,
+        };
+
+        let (cert, key, new) = certificate(&config, &challenges).await.unwrap();
+        assert!(new);
+        assert!(cert.starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(challenges.lock().unwrap().is_empty());
+        let left = validity(&cert, &config.domains).unwrap();
+        assert!(left > RENEW_BEFORE);
+        tls_config(&cert, &key).unwrap();
+
+        // Fresh enough to be kept; another domain wants a new one
+        let (cached, _, new) = certificate(&config, &challenges).await.unwrap();
+        assert!(!new);
+        assert_eq!(cached, cert);
+        assert_eq!(validity(&cert, &["other.example".to_string()]), None);
+
+        std::fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_http_is_redirected_to_https() {
+        let challenges = Challenges::default();
+        challenges
+            .lock()
+            .unwrap()
+            .insert("tok3n".to_string(), "tok3n.thumb".to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, challenge_app(challenges)).await });
+
+        let fetch = move |path: &'static str, host: &'static str| {
+            tokio::task::spawn_blocking(move || {
+                let agent = ureq::AgentBuilder::new().redirects(0).build();
+                let url = format!("http://{}{}", addr, path);
+                let response = match agent.get(&url).set("Host", host).call() {
+                    Ok(response) => response,
+                    Err(ureq::Error::Status(_, response)) => response,
+                    Err(e) => panic!("{}", e),
+                };
+                let location = response.header("Location").map(str::to_string);
+                (response.status(), location, response.into_string().unwrap())
+            })
+        };
+        let (status, _, body) = fetch("/.well-known/acme-challenge/tok3n", "lnurl.example")
+            .await
+            .unwrap();
+        assert_eq!((status, body.as_str()), (200, "tok3n.thumb"));
+        let (status, _, _) = fetch("/.well-known/acme-challenge/other", "lnurl.example")
+            .await
+            .unwrap();
+        assert_eq!(status, 404);
+        let (status, location, _) = fetch("/request-pay?x=1", "lnurl.example:80").await.unwrap();
+        assert_eq!(status, 308);
+        assert_eq!(
+            location.as_deref(),
+            Some("https://lnurl.example/request-pay?x=1")
+        );
+    }
+}
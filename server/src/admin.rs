@@ -0,0 +1,1017 @@
+// =============================================================================
+// Admin API
+// =============================================================================
+//
+// Mounted under /admin. Every request must carry an `X-Api-Key` header whose
+// key is listed in LNURL_ADMIN_KEYS as comma-separated `key:role` pairs:
+//
+//   LNURL_ADMIN_KEYS="s3cret:admin,dashboard-key:read-only"
+//
+// `read-only` keys may call the GET endpoints (stats, listings) so monitoring
+// dashboards can poll them; anything that changes state (issuing or voiding
+// vouchers, changing limits, deleting accounts) requires an `admin` key.
+// Encrypted columns are only decrypted for `admin` keys. The operations
+// themselves live in service.rs, shared with the gRPC service.
+//
+// With
+//
+//   LNURL_ADMIN_ALLOW_CIDRS="10.0.0.0/8, 127.0.0.1"
+//
+// only those networks reach the admin API (REST and gRPC), whatever key they
+// present; others get 403, as do requests whose address the server isn't
+// told. The list is separate from the public LNURL_ALLOW_CIDRS (screen.rs).
+// To take the admin API off the public listener altogether, or put it behind
+// mutual TLS, see listener.rs.
+//
+// GET /metrics, outside /admin where Prometheus looks for it, takes the same
+// keys (read-only will do) and allowlist. Scrapers that can't set X-Api-Key
+// may send the key as `Authorization: Bearer <key>`.
+//
+// Backup and restore (used by the `lnurl-admin` binary) always require an
+// `admin` key. They take the write gate exclusively, so they wait for
+// in-flight requests to finish and hold back new ones until the snapshot is
+// taken or applied.
+
+use axum::{
+    extract::{ConnectInfo, DefaultBodyLimit, Path, Query, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+use lnurl_models::StatusResponse;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use tracing::{info, warn};
+
+use crate::allowance::{self, AllowanceView};
+use crate::liquidity::{self, Outlook};
+use crate::logging::redact;
+use crate::metrics::{self, K1Rates, Rate};
+use crate::screen::{self, Cidr, ScreenConfigError};
+use crate::service::{self, IssuedVoucher, ServiceError, WithdrawalView};
+use crate::storage::{
+    Account, Channel, DeletionRequester, K1Purpose, LiquidityReport, Snapshot, ValidityWindow,
+    Voucher, WindowStatus,
+};
+use crate::{AppState, Limits};
+
+/// Ordered so that a higher role satisfies every lower requirement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    ReadOnly,
+    Admin,
+}
+
+impl Role {
+    fn parse(s: &str) -> Option<Role> {
+        match s {
+            "read-only" => Some(Role::ReadOnly),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Reads LNURL_ADMIN_KEYS; an empty map disables the admin API entirely
+pub fn load_keys() -> HashMap<String, Role> {
+    let mut keys = HashMap::new();
+    let Ok(raw) = std::env::var("LNURL_ADMIN_KEYS") else {
+        info!("LNURL_ADMIN_KEYS not set, admin API disabled");
+        return keys;
+    };
+
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry
+            .rsplit_once(':')
+            .and_then(|(key, role)| Some((key, Role::parse(role)?)))
+        {
+            Some((key, role)) => {
+                keys.insert(key.to_string(), role);
+            }
+            None => warn!("Ignoring malformed LNURL_ADMIN_KEYS entry (expected key:role)"),
+        }
+    }
+
+    info!("Admin API enabled with {} key(s)", keys.len());
+    keys
+}
+
+/// Reads LNURL_ADMIN_ALLOW_CIDRS; empty when it is unset, letting any
+/// address through to the key check
+pub fn load_allowlist() -> Result<Vec<Cidr>, ScreenConfigError> {
+    let allow = screen::load_list("LNURL_ADMIN_ALLOW_CIDRS")?;
+    if !allow.is_empty() {
+        let list = allow.iter().map(Cidr::to_string).collect::<Vec<_>>();
+        info!("Admin API only answers {}", list.join(", "));
+    }
+    Ok(allow)
+}
+
+/// Whether the allowlist lets `peer` through
+pub(crate) fn peer_allowed(state: &AppState, peer: Option<IpAddr>) -> bool {
+    state.admin_allow.is_empty()
+        || peer.is_some_and(|ip| state.admin_allow.iter().any(|cidr| cidr.contains(ip)))
+}
+
+pub fn router(state: AppState) -> Router<AppState> {
+    let gated = Router::new()
+        .route("/stats", get(stats))
+        .route("/store", get(store))
+        .route("/store/dump", get(dump_store))
+        .route("/limits", get(get_limits).put(update_limits))
+        .route("/vouchers", get(list_vouchers).post(issue_voucher))
+        .route("/vouchers/:k1", delete(void_voucher))
+        .route("/withdrawals", get(list_withdrawals))
+        .route("/withdrawals/:k1", get(get_withdrawal))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/:linking_key", get(get_account).delete(delete_account))
+        .route("/accounts/:linking_key/metadata", put(set_account_metadata))
+        .route(
+            "/accounts/:linking_key/allowance",
+            put(grant_allowance).delete(revoke_allowance),
+        )
+        .route("/allowances", get(list_allowances))
+        .route("/deletions", get(list_deletions))
+        .route("/liquidity", get(liquidity_reports))
+        .route("/channels", get(list_channels))
+        .route_layer(middleware::from_fn_with_state(state.clone(), hold_write_gate));
+
+    // Not behind the gate: these take it exclusively themselves
+    let maintenance = Router::new()
+        .route("/backup", post(backup))
+        // Backups easily exceed the default 2 MB body limit
+        .route("/restore", post(restore).layer(DefaultBodyLimit::disable()));
+
+    gated
+        .merge(maintenance)
+        .route_layer(middleware::from_fn_with_state(state, authorize))
+}
+
+/// GET /metrics, see the top of the file
+pub fn metrics_router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route_layer(middleware::from_fn_with_state(state, authorize))
+}
+
+// -----------------------------------------------------------------------------
+// Authorization layer
+// -----------------------------------------------------------------------------
+
+fn error(code: StatusCode, reason: &str) -> Response {
+    crate::error_reply(code, reason.to_string()).into_response()
+}
+
+fn storage_error(e: crate::storage::StorageError) -> Response {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Storage error: {}", e))
+}
+
+fn service_error(e: ServiceError) -> Response {
+    match e {
+        ServiceError::NotFound(_) => error(StatusCode::NOT_FOUND, &e.to_string()),
+        ServiceError::Invalid(_) => error(StatusCode::BAD_REQUEST, &e.to_string()),
+        ServiceError::Storage(e) => storage_error(e),
+    }
+}
+
+/// Reads are open to read-only keys, everything else needs admin
+fn required_role(method: &Method) -> Role {
+    if method == Method::GET || method == Method::HEAD {
+        Role::ReadOnly
+    } else {
+        Role::Admin
+    }
+}
+
+/// Checks the API key and hands its role to the handler as an extension
+async fn authorize(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if !peer_allowed(&state, peer) {
+        return error(StatusCode::FORBIDDEN, "Address not allowed");
+    }
+
+    let headers = request.headers();
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok()?.strip_prefix("Bearer "));
+    let role = headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .or(bearer)
+        .and_then(|key| state.admin_keys.get(key).copied());
+
+    match role {
+        None => error(StatusCode::UNAUTHORIZED, "Missing or invalid API key"),
+        Some(role) if role < required_role(request.method()) => error(
+            StatusCode::FORBIDDEN,
+            "API key is not allowed to perform this operation",
+        ),
+        Some(role) => {
+            request.extensions_mut().insert(role);
+            next.run(request).await
+        }
+    }
+}
+
+/// Holds the write gate (shared) for the whole request, see backup/restore
+pub async fn hold_write_gate(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let _writing = state.write_gate.read().await;
+    next.run(request).await
+}
+
+// -----------------------------------------------------------------------------
+// GET /admin/stats
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+struct StatsResponse {
+    status: String,
+    accounts: usize,
+    sessions: usize,
+    pending_k1s: usize,
+    vouchers: usize,
+    outstanding_budget_msat: u64, // sum of remaining account budgets
+}
+
+async fn stats(State(state): State<AppState>) -> Response {
+    let stats = match service::stats(&state).await {
+        Ok(stats) => stats,
+        Err(e) => return service_error(e),
+    };
+
+    (
+        StatusCode::OK,
+        Json(StatsResponse {
+            status: "OK".to_string(),
+            accounts: stats.accounts,
+            sessions: stats.sessions,
+            pending_k1s: stats.pending_k1s,
+            vouchers: stats.vouchers,
+            outstanding_budget_msat: stats.outstanding_budget_msat,
+        }),
+    )
+        .into_response()
+}
+
+// -----------------------------------------------------------------------------
+// GET /metrics
+// -----------------------------------------------------------------------------
+
+async fn metrics(State(state): State<AppState>) -> Response {
+    let stats = match service::stats(&state).await {
+        Ok(stats) => stats,
+        Err(e) => return service_error(e),
+    };
+    let page = metrics::render(
+        &state.flow_metrics,
+        &state.store_metrics,
+        &stats,
+        state.backend.rpc_latency(),
+    );
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], page).into_response()
+}
+
+// -----------------------------------------------------------------------------
+// GET /admin/store, GET /admin/store/dump?limit=<n>
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+struct K1Flow {
+    active: usize,
+    #[serde(flatten)]
+    rates: K1Rates,
+}
+
+#[derive(Debug, Serialize)]
+struct K1Store {
+    active: usize, // including k1s of no known flow
+    by_purpose: HashMap<K1Purpose, K1Flow>,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionStore {
+    active: usize,
+    opened: Rate,
+}
+
+#[derive(Debug, Serialize)]
+struct StoreResponse {
+    status: String,
+    k1s: K1Store,
+    sessions: SessionStore,
+}
+
+/// What the k1 and session stores hold now (across replicas) and what this
+/// process has seen go through them (see metrics.rs)
+async fn store(State(state): State<AppState>) -> Response {
+    let stats = match service::stats(&state).await {
+        Ok(stats) => stats,
+        Err(e) => return service_error(e),
+    };
+    let rates = state.store_metrics.rates();
+
+    let by_purpose = rates
+        .k1s
+        .into_iter()
+        .map(|(purpose, rates)| {
+            let active = stats.pending_k1s_by_purpose.get(&purpose).copied();
+            (
+                purpose,
+                K1Flow {
+                    active: active.unwrap_or(0),
+                    rates,
+                },
+            )
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(StoreResponse {
+            status: "OK".to_string(),
+            k1s: K1Store {
+                active: stats.pending_k1s,
+                by_purpose,
+            },
+            sessions: SessionStore {
+                active: stats.sessions,
+                opened: rates.sessions_opened,
+            },
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct DumpParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct K1Entry {
+    k1: String,
+    purpose: Option<K1Purpose>,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionEntry {
+    token: String,
+    linking_key: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DumpResponse {
+    status: String,
+    k1s: Vec<K1Entry>,
+    sessions: Vec<SessionEntry>,
+}
+
+async fn dump_store(State(state): State<AppState>, Query(params): Query<DumpParams>) -> Response {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    let k1s = match state.storage.list_k1s(limit).await {
+        Ok(k1s) => k1s
+            .into_iter()
+            .map(|(k1, purpose)| K1Entry {
+                k1: redact(&k1),
+                purpose,
+            })
+            .collect(),
+        Err(e) => return storage_error(e),
+    };
+    let sessions = match state.storage.list_sessions(limit).await {
+        Ok(sessions) => sessions
+            .into_iter()
+            .map(|(token, linking_key)| SessionEntry {
+                token: redact(&token),
+                linking_key: redact(&linking_key),
+            })
+            .collect(),
+        Err(e) => return storage_error(e),
+    };
+
+    (
+        StatusCode::OK,
+        Json(DumpResponse {
+            status: "OK".to_string(),
+            k1s,
+            sessions,
+        }),
+    )
+        .into_response()
+}
+
+// -----------------------------------------------------------------------------
+// GET/PUT /admin/limits
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+struct LimitsResponse {
+    status: String,
+    #[serde(flatten)]
+    limits: Limits,
+}
+
+async fn get_limits(State(state): State<AppState>) -> (StatusCode, Json<LimitsResponse>) {
+    let limits = state.limits.lock().await.clone();
+    (
+        StatusCode::OK,
+        Json(LimitsResponse {
+            status: "OK".to_string(),
+            limits,
+        }),
+    )
+}
+
+/// No amount can be more than all the bitcoin there will ever be
+const MAX_SUPPLY_SAT: u64 = 21_000_000 * 100_000_000;
+
+/// Only the fields present in the body are changed
+#[derive(Debug, Deserialize)]
+struct LimitsUpdate {
+    min_withdrawable_msat: Option<u64>,
+    max_withdrawable_msat: Option<u64>,
+    channel_capacity_sat: Option<u64>,
+    withdraw_budget_msat: Option<u64>,
+}
+
+async fn update_limits(
+    State(state): State<AppState>,
+    Json(update): Json<LimitsUpdate>,
+) -> Response {
+    let mut limits = state.limits.lock().await;
+    let mut updated = limits.clone();
+
+    if let Some(v) = update.min_withdrawable_msat {
+        updated.min_withdrawable_msat = v;
+    }
+    if let Some(v) = update.max_withdrawable_msat {
+        updated.max_withdrawable_msat = v;
+    }
+    if let Some(v) = update.channel_capacity_sat {
+        updated.channel_capacity_sat = v;
+    }
+    if let Some(v) = update.withdraw_budget_msat {
+        updated.withdraw_budget_msat = v;
+    }
+
+    if updated.min_withdrawable_msat > updated.max_withdrawable_msat {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "min_withdrawable_msat must not exceed max_withdrawable_msat",
+        );
+    }
+    for (name, value, max) in [
+        ("max_withdrawable_msat", update.max_withdrawable_msat, MAX_SUPPLY_SAT * 1000),
+        ("channel_capacity_sat", update.channel_capacity_sat, MAX_SUPPLY_SAT),
+        ("withdraw_budget_msat", update.withdraw_budget_msat, MAX_SUPPLY_SAT * 1000),
+    ] {
+        if value.is_some_and(|value| value == 0 || value > max) {
+            return error(
+                StatusCode::BAD_REQUEST,
+                &format!("{} must be between 1 and {}", name, max),
+            );
+        }
+    }
+
+    info!(limits = ?updated, "Limits updated");
+    *limits = updated.clone();
+
+    (
+        StatusCode::OK,
+        Json(LimitsResponse {
+            status: "OK".to_string(),
+            limits: updated,
+        }),
+    )
+        .into_response()
+}
+
+// -----------------------------------------------------------------------------
+// GET/POST /admin/vouchers, DELETE /admin/vouchers/:k1
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+struct VoucherEntry {
+    #[serde(flatten)]
+    voucher: Voucher,
+    /// Of its window: not_yet_active, active or expired
+    window_status: WindowStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct VouchersResponse {
+    status: String,
+    vouchers: Vec<VoucherEntry>,
+}
+
+/// A voucher's k1 redeems it, so read-only keys only see the start of it
+async fn list_vouchers(State(state): State<AppState>, Extension(role): Extension<Role>) -> Response {
+    let now = crate::unix_now();
+    let vouchers = match state.storage.list_vouchers().await {
+        Ok(vouchers) => vouchers
+            .into_iter()
+            .map(|mut voucher| {
+                if role < Role::Admin {
+                    voucher.k1 = redact(&voucher.k1);
+                }
+                VoucherEntry {
+                    window_status: voucher.window.status(now),
+                    voucher,
+                }
+            })
+            .collect(),
+        Err(e) => return storage_error(e),
+    };
+
+    (
+        StatusCode::OK,
+        Json(VouchersResponse {
+            status: "OK".to_string(),
+            vouchers,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct VoucherIssue {
+    linking_key: String,
+    #[serde(flatten)]
+    window: ValidityWindow,
+}
+
+#[derive(Debug, Serialize)]
+struct IssuedVoucherResponse {
+    status: String,
+    #[serde(flatten)]
+    voucher: IssuedVoucher,
+}
+
+async fn issue_voucher(State(state): State<AppState>, Json(issue): Json<VoucherIssue>) -> Response {
+    match service::issue_voucher(&state, &issue.linking_key, issue.window).await {
+        Ok(voucher) => (
+            StatusCode::OK,
+            Json(IssuedVoucherResponse {
+                status: "OK".to_string(),
+                voucher,
+            }),
+        )
+            .into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+async fn void_voucher(State(state): State<AppState>, Path(k1): Path<String>) -> Response {
+    match state.storage.take_voucher(&k1).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Unknown voucher"),
+        Err(e) => return storage_error(e),
+    }
+    // Consuming the k1 makes the withdraw callback reject it
+    if let Err(e) = state.storage.consume_k1(&k1, K1Purpose::Withdraw, &state.k1_ttls).await {
+        return storage_error(e);
+    }
+
+    info!(k1 = %k1, "Voucher voided");
+    (StatusCode::OK, Json(StatusResponse::ok())).into_response()
+}
+
+// -----------------------------------------------------------------------------
+// GET /admin/withdrawals?limit=<n>, GET /admin/withdrawals/:k1
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct WithdrawalsParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct WithdrawalsResponse {
+    status: String,
+    withdrawals: Vec<WithdrawalView>,
+}
+
+async fn list_withdrawals(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Query(params): Query<WithdrawalsParams>,
+) -> Response {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    match service::withdrawals(&state, role, limit).await {
+        Ok(withdrawals) => (
+            StatusCode::OK,
+            Json(WithdrawalsResponse {
+                status: "OK".to_string(),
+                withdrawals,
+            }),
+        )
+            .into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WithdrawalResponse {
+    status: String,
+    withdrawal: WithdrawalView,
+}
+
+async fn get_withdrawal(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Path(k1): Path<String>,
+) -> Response {
+    match service::withdrawal(&state, role, &k1).await {
+        Ok(withdrawal) => (
+            StatusCode::OK,
+            Json(WithdrawalResponse {
+                status: "OK".to_string(),
+                withdrawal,
+            }),
+        )
+            .into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// GET /admin/accounts?limit=<n>, GET /admin/accounts/:linking_key,
+// PUT /admin/accounts/:linking_key/metadata
+// -----------------------------------------------------------------------------
+
+/// Metadata is for labels, not documents
+const MAX_METADATA_ENTRIES: usize = 32;
+const MAX_METADATA_LEN: usize = 256;
+
+#[derive(Debug, Deserialize)]
+struct AccountsParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct AccountsResponse {
+    status: String,
+    accounts: Vec<Account>,
+}
+
+/// The most recent logins first
+async fn list_accounts(
+    State(state): State<AppState>,
+    Query(params): Query<AccountsParams>,
+) -> Response {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    match state.storage.list_accounts(limit).await {
+        Ok(accounts) => (
+            StatusCode::OK,
+            Json(AccountsResponse {
+                status: "OK".to_string(),
+                accounts,
+            }),
+        )
+            .into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AccountResponse {
+    status: String,
+    #[serde(flatten)]
+    account: Account,
+}
+
+async fn get_account(State(state): State<AppState>, Path(linking_key): Path<String>) -> Response {
+    match state.storage.get_account(&linking_key).await {
+        Ok(Some(account)) => (
+            StatusCode::OK,
+            Json(AccountResponse {
+                status: "OK".to_string(),
+                account,
+            }),
+        )
+            .into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, "Unknown account"),
+        Err(e) => storage_error(e),
+    }
+}
+
+/// Replaces the account's metadata with the JSON object of strings sent
+async fn set_account_metadata(
+    State(state): State<AppState>,
+    Path(linking_key): Path<String>,
+    Json(metadata): Json<BTreeMap<String, String>>,
+) -> Response {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        let reason = format!("At most {} metadata entries", MAX_METADATA_ENTRIES);
+        return error(StatusCode::BAD_REQUEST, &reason);
+    }
+    let too_long = |text: &String| text.chars().count() > MAX_METADATA_LEN;
+    if metadata.iter().any(|(key, value)| too_long(key) || too_long(value)) {
+        let reason = format!(
+            "Metadata keys and values are at most {} characters",
+            MAX_METADATA_LEN
+        );
+        return error(StatusCode::BAD_REQUEST, &reason);
+    }
+    match state.storage.set_account_metadata(&linking_key, &metadata).await {
+        Ok(true) => {
+            info!("Metadata of {} set ({} entries)", linking_key, metadata.len());
+            (StatusCode::OK, Json(StatusResponse::ok())).into_response()
+        }
+        Ok(false) => error(StatusCode::NOT_FOUND, "Unknown account"),
+        Err(e) => storage_error(e),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// DELETE /admin/accounts/:linking_key, GET /admin/deletions?limit=<n>
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+struct DeletionResponse {
+    status: String,
+    #[serde(flatten)]
+    deletion: crate::storage::Deletion,
+}
+
+async fn delete_account(State(state): State<AppState>, Path(linking_key): Path<String>) -> Response {
+    let deletion = match state
+        .storage
+        .delete_account(&linking_key, DeletionRequester::Admin)
+        .await
+    {
+        Ok(Some(deletion)) => deletion,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Unknown account"),
+        Err(e) => return storage_error(e),
+    };
+
+    info!("Account deleted by admin (audit {})", deletion.id);
+    (
+        StatusCode::OK,
+        Json(DeletionResponse {
+            status: "OK".to_string(),
+            deletion,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct DeletionsParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeletionsResponse {
+    status: String,
+    deletions: Vec<crate::storage::Deletion>,
+}
+
+async fn list_deletions(
+    State(state): State<AppState>,
+    Query(params): Query<DeletionsParams>,
+) -> Response {
+    match state
+        .storage
+        .list_deletions(params.limit.unwrap_or(100).min(1000))
+        .await
+    {
+        Ok(deletions) => (
+            StatusCode::OK,
+            Json(DeletionsResponse {
+                status: "OK".to_string(),
+                deletions,
+            }),
+        )
+            .into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// PUT/DELETE /admin/accounts/:linking_key/allowance, GET /admin/allowances
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct AllowanceGrant {
+    amount_msat: u64,
+    period_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct AllowanceResponse {
+    status: String,
+    #[serde(flatten)]
+    allowance: AllowanceView,
+}
+
+async fn grant_allowance(
+    State(state): State<AppState>,
+    Path(linking_key): Path<String>,
+    Json(grant): Json<AllowanceGrant>,
+) -> Response {
+    if grant.period_secs < allowance::MIN_PERIOD_SECS {
+        return error(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "period_secs must be at least {}",
+                allowance::MIN_PERIOD_SECS
+            ),
+        );
+    }
+    match allowance::grant(&state, &linking_key, grant.amount_msat, grant.period_secs).await {
+        Ok(allowance) => (
+            StatusCode::OK,
+            Json(AllowanceResponse {
+                status: "OK".to_string(),
+                allowance,
+            }),
+        )
+            .into_response(),
+        Err(e) => service_error(e),
+    }
+}
+
+/// The budget stays as it is; it just stops refilling
+async fn revoke_allowance(State(state): State<AppState>, Path(linking_key): Path<String>) -> Response {
+    match state.storage.remove_allowance(&linking_key).await {
+        Ok(true) => {
+            info!("Allowance of {} revoked", linking_key);
+            (StatusCode::OK, Json(StatusResponse::ok())).into_response()
+        }
+        Ok(false) => error(StatusCode::NOT_FOUND, "No allowance for this account"),
+        Err(e) => storage_error(e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AllowancesResponse {
+    status: String,
+    allowances: Vec<AllowanceView>,
+}
+
+async fn list_allowances(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+) -> Response {
+    match state.storage.list_allowances().await {
+        Ok(allowances) => (
+            StatusCode::OK,
+            Json(AllowancesResponse {
+                status: "OK".to_string(),
+                allowances: allowances
+                    .into_iter()
+                    .map(|allowance| allowance::view(&state, role, allowance))
+                    .collect(),
+            }),
+        )
+            .into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// GET /admin/channels?limit=<n>
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct ChannelsParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChannelsResponse {
+    status: String,
+    channels: Vec<Channel>,
+}
+
+async fn list_channels(
+    State(state): State<AppState>,
+    Query(params): Query<ChannelsParams>,
+) -> Response {
+    match state
+        .storage
+        .list_channels(params.limit.unwrap_or(100).min(1000))
+        .await
+    {
+        Ok(channels) => (
+            StatusCode::OK,
+            Json(ChannelsResponse {
+                status: "OK".to_string(),
+                channels,
+            }),
+        )
+            .into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// GET /admin/liquidity?limit=<n>
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct LiquidityParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct LiquidityResponse {
+    status: String,
+    /// Over the reports listed; None until the first report is taken
+    outlook: Option<Outlook>,
+    reports: Vec<LiquidityReport>, // most recent first
+}
+
+/// The liquidity report history, see liquidity.rs. The default limit covers
+/// a week of hourly reports.
+async fn liquidity_reports(
+    State(state): State<AppState>,
+    Query(params): Query<LiquidityParams>,
+) -> Response {
+    let limit = params.limit.unwrap_or(168).clamp(1, 1000);
+    match state.storage.list_liquidity_reports(limit).await {
+        Ok(reports) => (
+            StatusCode::OK,
+            Json(LiquidityResponse {
+                status: "OK".to_string(),
+                outlook: liquidity::outlook(&reports),
+                reports,
+            }),
+        )
+            .into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// POST /admin/backup, POST /admin/restore
+// -----------------------------------------------------------------------------
+
+/// Bumped when the backup layout changes incompatibly
+pub const BACKUP_FORMAT: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Backup {
+    format: u32,
+    created_at: u64,
+    limits: Limits,
+    snapshot: Snapshot,
+}
+
+async fn backup(State(state): State<AppState>) -> Response {
+    let _quiesced = state.write_gate.write().await;
+
+    let snapshot = match state.storage.export().await {
+        Ok(snapshot) => snapshot,
+        Err(e) => return storage_error(e),
+    };
+    let limits = state.limits.lock().await.clone();
+
+    info!(
+        "Backup taken: {} accounts, {} withdrawals",
+        snapshot.accounts.len(),
+        snapshot.withdrawals.len()
+    );
+    (
+        StatusCode::OK,
+        Json(Backup {
+            format: BACKUP_FORMAT,
+            created_at: crate::unix_now(),
+            limits,
+            snapshot,
+        }),
+    )
+        .into_response()
+}
+
+async fn restore(State(state): State<AppState>, Json(backup): Json<Backup>) -> Response {
+    if backup.format != BACKUP_FORMAT {
+        return error(
+            StatusCode::BAD_REQUEST,
+            &format!("Unsupported backup format {}", backup.format),
+        );
+    }
+    if backup.limits.min_withdrawable_msat > backup.limits.max_withdrawable_msat {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "min_withdrawable_msat must not exceed max_withdrawable_msat",
+        );
+    }
+
+    let _quiesced = state.write_gate.write().await;
+
+    if let Err(e) = state.storage.import(backup.snapshot).await {
+        return storage_error(e);
+    }
+    *state.limits.lock().await = backup.limits;
+
+    info!("Backup from {} restored", backup.created_at);
+    (StatusCode::OK, Json(StatusResponse::ok())).into_response()
+}
@@ -0,0 +1,617 @@
+// =============================================================================
+// Node backend
+// =============================================================================
+//
+// Everything the handlers ask of the Lightning node goes through the
+// `Backend` trait, so that they can be driven without one (see tests.rs).
+// `ClnBackend` is the real thing, over CLN's RPC socket.
+
+use async_trait::async_trait;
+use cln_rpc::model::requests::{
+    CheckmessageRequest, DecodeRequest, FeeratesRequest, FeeratesStyle, FundchannelRequest,
+    InvoiceRequest, ListfundsRequest, ListinvoicesRequest, ListpeerchannelsRequest, PayRequest,
+    SetchannelRequest,
+};
+use cln_rpc::model::responses::{
+    ListfundsOutputsStatus, ListinvoicesInvoices, ListinvoicesInvoicesStatus,
+    ListpeerchannelsChannelsState,
+};
+use cln_rpc::primitives::{Amount, AmountOrAll, AmountOrAny, PublicKey};
+use cln_rpc::{ClnRpc, Request, Response};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+use crate::metrics::RpcLatency;
+
+/// A channel whose funding transaction was broadcast
+#[derive(Debug, Clone)]
+pub struct FundedChannel {
+    pub channel_id: String,
+    pub txid: String,
+    pub tx: String, // raw, hex
+    pub outnum: u32,
+    pub mindepth: Option<u32>,
+}
+
+/// A completed outgoing payment
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub preimage: Vec<u8>,
+    pub amount_sent_msat: u64, // fees included
+}
+
+/// What the server reads from a BOLT11 invoice
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodedInvoice {
+    pub amount_msat: Option<u64>,    // None for an amountless invoice
+    pub description: Option<String>, // as the wallet wrote it, None when only hashed
+}
+
+/// What one channel can send and receive right now
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelBalance {
+    pub peer_id: String,
+    #[serde(default)]
+    pub channel_id: Option<String>, // hex, None before the funding is negotiated
+    pub short_channel_id: Option<String>, // None until confirmed
+    pub state: String,                    // e.g. CHANNELD_NORMAL
+    pub outbound_msat: u64,               // spendable
+    pub inbound_msat: u64,                // receivable
+}
+
+impl ChannelBalance {
+    /// Whether payments can go through it
+    pub fn is_usable(&self) -> bool {
+        self.state == "CHANNELD_NORMAL"
+    }
+}
+
+/// The node's money: on-chain outputs and its side of each channel
+#[derive(Debug, Clone, Default)]
+pub struct Funds {
+    pub onchain_sat: u64, // confirmed and not reserved for a pending open
+    pub onchain_unconfirmed_sat: u64,
+    pub channels: Vec<ChannelBalance>,
+}
+
+impl Funds {
+    /// The largest payment `pay` could make right now, wherever it goes: what
+    /// the usable channels can send together, less the fees it may spend
+    pub fn payable_msat(&self) -> u64 {
+        let usable = self.channels.iter().filter(|channel| channel.is_usable());
+        let spendable_msat: u64 = usable.map(|channel| channel.outbound_msat).sum();
+        (spendable_msat as u128 * 100 / (100 + MAX_FEE_PERCENT) as u128) as u64
+    }
+}
+
+/// Fee and HTLC settings to change on a channel; None keeps what the node
+/// has
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeeUpdate {
+    pub base_msat: Option<u64>,
+    pub ppm: Option<u32>,
+    pub htlc_min_msat: Option<u64>,
+    pub htlc_max_msat: Option<u64>,
+}
+
+/// A channel's fee and HTLC settings, as the node has them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelFees {
+    pub base_msat: u64,
+    pub ppm: u32,
+    pub htlc_min_msat: u64,
+    pub htlc_max_msat: u64,
+}
+
+/// Where an invoice the node issued stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceStatus {
+    Unpaid,
+    Paid,
+    Expired,
+}
+
+/// An invoice just issued, with the hash its payment reveals the preimage of
+#[derive(Debug, Clone)]
+pub struct NewInvoice {
+    pub bolt11: String,
+    pub payment_hash: String, // hex
+}
+
+/// An invoice the node issued, found by its payment hash
+#[derive(Debug, Clone)]
+pub struct IssuedInvoice {
+    pub label: String,
+    pub bolt11: Option<String>, // None for a BOLT12 invoice
+    pub status: InvoiceStatus,
+    pub preimage: Option<Vec<u8>>, // once paid
+}
+
+/// The node's view of the chain, for /readyz
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub blockheight: u32,
+    pub sync_warning: Option<String>, // None once bitcoind and the node caught up
+}
+
+#[derive(Debug)]
+pub struct BackendError(String);
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<cln_rpc::RpcError> for BackendError {
+    fn from(e: cln_rpc::RpcError) -> Self {
+        BackendError(e.to_string())
+    }
+}
+
+impl From<String> for BackendError {
+    fn from(reason: String) -> Self {
+        BackendError(reason)
+    }
+}
+
+pub type BackendResult<T> = Result<T, BackendError>;
+
+/// The most `pay` spends on routing fees, in percent of the amount
+pub const MAX_FEE_PERCENT: u64 = 1;
+
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// The node's pubkey, hex
+    async fn node_id(&self) -> BackendResult<String>;
+
+    /// The invoice's amount and description
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice>;
+
+    /// Opens a channel to `node_id` (already connected), announced unless
+    /// `announce` is false
+    async fn fund_channel(
+        &self,
+        node_id: PublicKey,
+        capacity_sat: u64,
+        announce: bool,
+    ) -> BackendResult<FundedChannel>;
+
+    /// Pays the invoice, returning once it succeeded or failed for good
+    async fn pay(&self, bolt11: &str) -> BackendResult<Payment>;
+
+    /// Whether `zbase` is `pubkey`'s signature of `message`, CLN
+    /// checkmessage style
+    async fn check_message(
+        &self,
+        message: &str,
+        zbase: &str,
+        pubkey: PublicKey,
+    ) -> BackendResult<bool>;
+
+    /// On-chain balance and channel liquidity, for liquidity reports
+    async fn funds(&self) -> BackendResult<Funds>;
+
+    /// Applies `update` to the channel, returning the settings it ends up with
+    async fn set_channel_fees(
+        &self,
+        channel_id: &str,
+        update: &FeeUpdate,
+    ) -> BackendResult<ChannelFees>;
+
+    /// Issues an invoice for `amount_msat` under `label`, returning its
+    /// bolt11
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+    ) -> BackendResult<String>;
+
+    /// Like `create_invoice`, but the invoice commits to `description` by
+    /// its sha256 only (the BOLT-11 description hash), as LUD-06 has it for
+    /// the payRequest metadata. `preimage` is the payment's when the caller
+    /// needs to know it (LUD-10); None lets the node pick one.
+    async fn create_invoice_hashed(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+        preimage: Option<&[u8; 32]>,
+    ) -> BackendResult<NewInvoice>;
+
+    /// The invoice issued under `label`, None if there is none
+    async fn invoice_status(&self, label: &str) -> BackendResult<Option<InvoiceStatus>>;
+
+    /// The invoice with `payment_hash` (hex), None if there is none
+    async fn invoice_by_hash(&self, payment_hash: &str) -> BackendResult<Option<IssuedInvoice>>;
+
+    /// What opening a channel costs on-chain now, in sat per 1000 vbytes
+    async fn opening_feerate_perkb(&self) -> BackendResult<u64>;
+
+    /// The node's height and whether it is still syncing. Answers even
+    /// while other calls are under way.
+    async fn node_status(&self) -> BackendResult<NodeStatus>;
+
+    /// How long the node's RPC calls take, for /metrics; None when there is
+    /// no RPC to time
+    fn rpc_latency(&self) -> Option<&RpcLatency> {
+        None
+    }
+}
+
+/// Core Lightning over its RPC socket. Calls are serialized.
+pub struct ClnBackend {
+    rpc: Mutex<ClnRpc>,
+    rpc_path: PathBuf, // for node_status, which connects on its own
+    latency: RpcLatency,
+}
+
+impl ClnBackend {
+    pub async fn connect(rpc_path: impl AsRef<Path>) -> BackendResult<ClnBackend> {
+        let client = ClnRpc::new(rpc_path.as_ref())
+            .await
+            .map_err(|e| BackendError(e.to_string()))?;
+        Ok(ClnBackend {
+            rpc: Mutex::new(client),
+            rpc_path: rpc_path.as_ref().to_path_buf(),
+            latency: RpcLatency::default(),
+        })
+    }
+
+    /// Times the call itself, not the wait for the socket
+    async fn call(&self, request: Request) -> BackendResult<Response> {
+        let method = rpc_method(&request);
+        let mut rpc = self.rpc.lock().await;
+        let started = Instant::now();
+        let response = rpc.call(request).await;
+        self.latency.observe(method, started.elapsed());
+        Ok(response?)
+    }
+
+    async fn invoice(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+        deschashonly: bool,
+        preimage: Option<&[u8; 32]>,
+    ) -> BackendResult<NewInvoice> {
+        let request = InvoiceRequest {
+            amount_msat: AmountOrAny::Amount(Amount::from_msat(amount_msat)),
+            label: label.to_string(),
+            description: description.to_string(),
+            expiry: Some(expiry_secs),
+            fallbacks: None,
+            preimage: preimage.map(hex::encode),
+            cltv: None,
+            deschashonly: Some(deschashonly),
+            exposeprivatechannels: None,
+        };
+        match self.call(Request::Invoice(request)).await? {
+            Response::Invoice(response) => Ok(NewInvoice {
+                bolt11: response.bolt11,
+                payment_hash: response.payment_hash.to_string(),
+            }),
+            _ => Err(unexpected("invoice")),
+        }
+    }
+
+    async fn list_invoices(
+        &self,
+        request: ListinvoicesRequest,
+    ) -> BackendResult<Option<ListinvoicesInvoices>> {
+        match self.call(Request::ListInvoices(request)).await? {
+            Response::ListInvoices(response) => Ok(response.invoices.into_iter().next()),
+            _ => Err(unexpected("listinvoices")),
+        }
+    }
+}
+
+/// The CLN method of `request`, as /metrics labels it
+fn rpc_method(request: &Request) -> &'static str {
+    match request {
+        Request::Getinfo(_) => "getinfo",
+        Request::Decode(_) => "decode",
+        Request::FundChannel(_) => "fundchannel",
+        Request::Pay(_) => "pay",
+        Request::CheckMessage(_) => "checkmessage",
+        Request::ListFunds(_) => "listfunds",
+        Request::ListPeerChannels(_) => "listpeerchannels",
+        Request::SetChannel(_) => "setchannel",
+        Request::Invoice(_) => "invoice",
+        Request::ListInvoices(_) => "listinvoices",
+        Request::Feerates(_) => "feerates",
+        _ => "other",
+    }
+}
+
+fn unexpected(method: &str) -> BackendError {
+    BackendError(format!("Unexpected response type from {}", method))
+}
+
+fn invoice_status(status: ListinvoicesInvoicesStatus) -> InvoiceStatus {
+    match status {
+        ListinvoicesInvoicesStatus::UNPAID => InvoiceStatus::Unpaid,
+        ListinvoicesInvoicesStatus::PAID => InvoiceStatus::Paid,
+        ListinvoicesInvoicesStatus::EXPIRED => InvoiceStatus::Expired,
+    }
+}
+
+#[async_trait]
+impl Backend for ClnBackend {
+    async fn node_id(&self) -> BackendResult<String> {
+        let request = cln_rpc::model::requests::GetinfoRequest {};
+        match self.call(Request::Getinfo(request)).await? {
+            Response::Getinfo(response) => Ok(response.id.to_string()),
+            _ => Err(unexpected("getinfo")),
+        }
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice> {
+        let request = DecodeRequest {
+            string: bolt11.to_string(),
+        };
+        match self.call(Request::Decode(request)).await? {
+            Response::Decode(decoded) => Ok(DecodedInvoice {
+                amount_msat: decoded.amount_msat.map(|amount| amount.msat()),
+                description: decoded.description,
+            }),
+            _ => Err(unexpected("decode")),
+        }
+    }
+
+    async fn fund_channel(
+        &self,
+        node_id: PublicKey,
+        capacity_sat: u64,
+        announce: bool,
+    ) -> BackendResult<FundedChannel> {
+        let request = FundchannelRequest {
+            id: node_id,
+            amount: AmountOrAll::Amount(Amount::from_sat(capacity_sat)),
+            announce: Some(announce),
+            feerate: None,
+            minconf: None,
+            mindepth: None,
+            utxos: None,
+            push_msat: None,
+            close_to: None,
+            request_amt: None,
+            compact_lease: None,
+            reserve: None,
+            channel_type: None,
+        };
+        match self.call(Request::FundChannel(request)).await? {
+            Response::FundChannel(response) => Ok(FundedChannel {
+                channel_id: response.channel_id.to_string(),
+                txid: response.txid,
+                tx: response.tx,
+                outnum: response.outnum,
+                mindepth: response.mindepth,
+            }),
+            _ => Err(unexpected("fundchannel")),
+        }
+    }
+
+    async fn pay(&self, bolt11: &str) -> BackendResult<Payment> {
+        let request = PayRequest {
+            bolt11: bolt11.to_string(),
+            amount_msat: None,
+            label: None,
+            riskfactor: None,
+            maxfeepercent: Some(MAX_FEE_PERCENT as f64),
+            retry_for: Some(60),
+            maxdelay: None,
+            exemptfee: None,
+            localinvreqid: None,
+            exclude: None,
+            maxfee: None,
+            description: None,
+            partial_msat: None,
+        };
+        match self.call(Request::Pay(request)).await? {
+            Response::Pay(response) => Ok(Payment {
+                preimage: response.payment_preimage.to_vec(),
+                amount_sent_msat: response.amount_sent_msat.msat(),
+            }),
+            _ => Err(unexpected("pay")),
+        }
+    }
+
+    async fn check_message(
+        &self,
+        message: &str,
+        zbase: &str,
+        pubkey: PublicKey,
+    ) -> BackendResult<bool> {
+        let request = CheckmessageRequest {
+            message: message.to_string(),
+            zbase: zbase.to_string(),
+            pubkey: Some(pubkey),
+        };
+        match self.call(Request::CheckMessage(request)).await? {
+            Response::CheckMessage(response) => Ok(response.verified),
+            _ => Err(unexpected("checkmessage")),
+        }
+    }
+    async fn funds(&self) -> BackendResult<Funds> {
+        let mut funds = Funds::default();
+        let request = ListfundsRequest { spent: Some(false) };
+        match self.call(Request::ListFunds(request)).await? {
+            Response::ListFunds(response) => {
+                for output in response.outputs {
+                    let sat = output.amount_msat.msat() / 1000;
+                    match output.status {
+                        ListfundsOutputsStatus::CONFIRMED if !output.reserved => {
+                            funds.onchain_sat += sat
+                        }
+                        ListfundsOutputsStatus::UNCONFIRMED => funds.onchain_unconfirmed_sat += sat,
+                        _ => {}
+                    }
+                }
+            }
+            _ => return Err(unexpected("listfunds")),
+        }
+
+        // listpeerchannels rather than listfunds' channels: it knows what is
+        // spendable once reserves and in-flight HTLCs are taken off
+        let request = ListpeerchannelsRequest { id: None };
+        match self.call(Request::ListPeerChannels(request)).await? {
+            Response::ListPeerChannels(response) => {
+                let msat = |amount: Option<Amount>| amount.map_or(0, |amount| amount.msat());
+                funds.channels = response
+                    .channels
+                    .into_iter()
+                    .map(|channel| ChannelBalance {
+                        peer_id: channel.peer_id.to_string(),
+                        channel_id: channel.channel_id.map(|id| id.to_string()),
+                        short_channel_id: channel.short_channel_id.map(|scid| scid.to_string()),
+                        state: channel_state(channel.state),
+                        outbound_msat: msat(channel.spendable_msat),
+                        inbound_msat: msat(channel.receivable_msat),
+                    })
+                    .collect();
+            }
+            _ => return Err(unexpected("listpeerchannels")),
+        }
+        Ok(funds)
+    }
+
+    async fn set_channel_fees(
+        &self,
+        channel_id: &str,
+        update: &FeeUpdate,
+    ) -> BackendResult<ChannelFees> {
+        let request = SetchannelRequest {
+            id: channel_id.to_string(),
+            feebase: update.base_msat.map(Amount::from_msat),
+            feeppm: update.ppm,
+            htlcmin: update.htlc_min_msat.map(Amount::from_msat),
+            htlcmax: update.htlc_max_msat.map(Amount::from_msat),
+            enforcedelay: None,
+            ignorefeelimits: None,
+        };
+        match self.call(Request::SetChannel(request)).await? {
+            Response::SetChannel(response) => {
+                let channel = response.channels.into_iter().next().ok_or_else(|| {
+                    BackendError(format!("setchannel matched no channel {}", channel_id))
+                })?;
+                Ok(ChannelFees {
+                    base_msat: channel.fee_base_msat.msat(),
+                    ppm: channel.fee_proportional_millionths,
+                    htlc_min_msat: channel.minimum_htlc_out_msat.msat(),
+                    htlc_max_msat: channel.maximum_htlc_out_msat.msat(),
+                })
+            }
+            _ => Err(unexpected("setchannel")),
+        }
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+    ) -> BackendResult<String> {
+        self.invoice(amount_msat, label, description, expiry_secs, false, None)
+            .await
+            .map(|invoice| invoice.bolt11)
+    }
+
+    async fn create_invoice_hashed(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+        preimage: Option<&[u8; 32]>,
+    ) -> BackendResult<NewInvoice> {
+        self.invoice(amount_msat, label, description, expiry_secs, true, preimage)
+            .await
+    }
+
+    async fn invoice_status(&self, label: &str) -> BackendResult<Option<InvoiceStatus>> {
+        let request = ListinvoicesRequest {
+            label: Some(label.to_string()),
+            invstring: None,
+            payment_hash: None,
+            offer_id: None,
+            index: None,
+            start: None,
+            limit: None,
+        };
+        let invoice = self.list_invoices(request).await?;
+        Ok(invoice.map(|invoice| invoice_status(invoice.status)))
+    }
+
+    async fn invoice_by_hash(&self, payment_hash: &str) -> BackendResult<Option<IssuedInvoice>> {
+        let request = ListinvoicesRequest {
+            label: None,
+            invstring: None,
+            payment_hash: Some(payment_hash.to_string()),
+            offer_id: None,
+            index: None,
+            start: None,
+            limit: None,
+        };
+        let invoice = self.list_invoices(request).await?;
+        Ok(invoice.map(|invoice| IssuedInvoice {
+            label: invoice.label,
+            bolt11: invoice.bolt11,
+            status: invoice_status(invoice.status),
+            preimage: invoice.payment_preimage.map(|preimage| preimage.to_vec()),
+        }))
+    }
+
+    async fn opening_feerate_perkb(&self) -> BackendResult<u64> {
+        let request = FeeratesRequest {
+            style: FeeratesStyle::PERKB,
+        };
+        match self.call(Request::Feerates(request)).await? {
+            Response::Feerates(response) => response
+                .perkb
+                .and_then(|perkb| perkb.opening)
+                .map(u64::from)
+                .ok_or_else(|| BackendError("The node has no opening feerate yet".to_string())),
+            _ => Err(unexpected("feerates")),
+        }
+    }
+
+    async fn node_status(&self) -> BackendResult<NodeStatus> {
+        let mut rpc = ClnRpc::new(&self.rpc_path)
+            .await
+            .map_err(|e| BackendError(e.to_string()))?;
+        let request = Request::Getinfo(cln_rpc::model::requests::GetinfoRequest {});
+        let started = Instant::now();
+        let response = rpc.call(request).await;
+        self.latency.observe("getinfo", started.elapsed());
+        match response? {
+            Response::Getinfo(response) => Ok(NodeStatus {
+                blockheight: response.blockheight,
+                sync_warning: response
+                    .warning_bitcoind_sync
+                    .or(response.warning_lightningd_sync),
+            }),
+            _ => Err(unexpected("getinfo")),
+        }
+    }
+
+    fn rpc_latency(&self) -> Option<&RpcLatency> {
+        Some(&self.latency)
+    }
+}
+
+/// CLN's own name for the state, as listpeerchannels prints it
+fn channel_state(state: ListpeerchannelsChannelsState) -> String {
+    format!("{:?}", state)
+}
@@ -0,0 +1,299 @@
+// =============================================================================
+// lnurl-admin — backup and restore for a running lnurl-server
+// =============================================================================
+//
+// Talks to the server's admin API rather than the database directly, so it
+// works the same for in-memory and PostgreSQL storage and the server can
+// quiesce writes while the snapshot is taken (see admin.rs).
+//
+// The archive is a plain tar:
+//
+//   manifest.json  — format version and creation time
+//   config.json    — runtime limits
+//   database.json  — accounts, sessions, vouchers, k1s, withdrawals
+//
+// Preimages stay encrypted in database.json; restore into a server configured
+// with the same LNURL_ENCRYPTION_KEY to be able to read them.
+//
+// An admin listener behind mutual TLS (listener.rs) is reached with --ca, the
+// CA of its certificate, and --client-cert/--client-key, PEM files of a
+// certificate its LNURL_ADMIN_TLS_CLIENT_CA issued.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+
+const DEFAULT_SERVER: &str = "http://127.0.0.1:3000";
+
+const MANIFEST_FILE: &str = "manifest.json";
+const CONFIG_FILE: &str = "config.json";
+const DATABASE_FILE: &str = "database.json";
+
+// =============================================================================
+// CLI Parsing
+// =============================================================================
+
+#[derive(Debug)]
+enum Command {
+    Backup { out: String },
+    Restore { input: String, force: bool },
+}
+
+#[derive(Debug)]
+struct Options {
+    command: Command,
+    server: String,
+    api_key: String,
+    agent: ureq::Agent,
+}
+
+fn print_usage() {
+    eprintln!("Usage:");
+    eprintln!("  lnurl-admin backup --out <file.tar> [--server <url>] [--key <api-key>]");
+    eprintln!("  lnurl-admin restore --in <file.tar> [--force] [--server <url>] [--key <api-key>]");
+    eprintln!();
+    eprintln!("The API key defaults to $LNURL_ADMIN_KEY and must have the admin role.");
+    eprintln!("An admin listener behind TLS takes --ca <pem>, and with client certificates");
+    eprintln!("--client-cert <pem> --client-key <pem> as well.");
+}
+
+fn parse_args() -> Result<Options> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else {
+        print_usage();
+        bail!("No command provided");
+    };
+
+    let mut out = None;
+    let mut input = None;
+    let mut force = false;
+    let mut server = DEFAULT_SERVER.to_string();
+    let mut api_key = std::env::var("LNURL_ADMIN_KEY").ok();
+    let (mut ca, mut client_cert, mut client_key) = (None, None, None);
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let mut value = || {
+            rest.next()
+                .cloned()
+                .ok_or_else(|| anyhow!("{} requires a value", flag))
+        };
+        match flag.as_str() {
+            "--out" => out = Some(value()?),
+            "--in" => input = Some(value()?),
+            "--server" => server = value()?,
+            "--key" => api_key = Some(value()?),
+            "--ca" => ca = Some(value()?),
+            "--client-cert" => client_cert = Some(value()?),
+            "--client-key" => client_key = Some(value()?),
+            "--force" => force = true,
+            other => {
+                print_usage();
+                bail!("Unknown argument: {}", other);
+            }
+        }
+    }
+
+    let command = match command.as_str() {
+        "backup" => Command::Backup {
+            out: out.ok_or_else(|| anyhow!("backup requires --out <file.tar>"))?,
+        },
+        "restore" => Command::Restore {
+            input: input.ok_or_else(|| anyhow!("restore requires --in <file.tar>"))?,
+            force,
+        },
+        other => {
+            print_usage();
+            bail!("Unknown command: {}", other);
+        }
+    };
+
+    Ok(Options {
+        command,
+        server: server.trim_end_matches('/').to_string(),
+        api_key: api_key.ok_or_else(|| anyhow!("No API key, pass --key or set LNURL_ADMIN_KEY"))?,
+        agent: agent(ca, client_cert, client_key)?,
+    })
+}
+
+/// An agent trusting only `ca` and presenting the client certificate, when
+/// they are given
+fn agent(ca: Option<String>, cert: Option<String>, key: Option<String>) -> Result<ureq::Agent> {
+    let read = |path: &str| std::fs::read(path).with_context(|| format!("Failed to read {}", path));
+    let ca = match (ca, &cert) {
+        (None, None) => return Ok(ureq::agent()),
+        (Some(ca), _) => ca,
+        (None, Some(_)) => bail!("--client-cert needs --ca, the CA of the admin listener"),
+    };
+
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &read(&ca)?[..]) {
+        roots.add(cert.context("Invalid --ca")?).context("Invalid --ca")?;
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+    let config = match (cert, key) {
+        (None, None) => builder.with_no_client_auth(),
+        (Some(cert), Some(key)) => {
+            let chain = rustls_pemfile::certs(&mut &read(&cert)?[..])
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid --client-cert")?;
+            let key = rustls_pemfile::private_key(&mut &read(&key)?[..])
+                .context("Invalid --client-key")?
+                .ok_or_else(|| anyhow!("No private key in --client-key"))?;
+            builder.with_client_auth_cert(chain, key)?
+        }
+        _ => bail!("--client-cert and --client-key go together"),
+    };
+    Ok(ureq::AgentBuilder::new().tls_config(Arc::new(config)).build())
+}
+
+// =============================================================================
+// Admin API calls
+// =============================================================================
+
+/// Turns error statuses into the server's `reason`
+fn call(request: ureq::Request, body: Option<&Value>) -> Result<Value> {
+    let response = match body {
+        Some(body) => request.send_json(body),
+        None => request.call(),
+    };
+
+    match response {
+        Ok(response) => response.into_json().context("Invalid JSON from server"),
+        Err(ureq::Error::Status(code, response)) => {
+            let reason = response
+                .into_json::<Value>()
+                .ok()
+                .and_then(|body| body["reason"].as_str().map(str::to_string))
+                .unwrap_or_else(|| "no reason given".to_string());
+            Err(anyhow!("Server returned {}: {}", code, reason))
+        }
+        Err(e) => Err(anyhow!("Request failed: {}", e)),
+    }
+}
+
+// =============================================================================
+// Backup
+// =============================================================================
+
+fn backup(options: &Options, out: &str) -> Result<()> {
+    println!("Requesting backup from {} (writes are paused meanwhile)...", options.server);
+    let mut backup = call(
+        options.agent.post(&format!("{}/admin/backup", options.server)).set("X-Api-Key", &options.api_key),
+        None,
+    )?;
+
+    let manifest = json!({
+        "format": backup["format"],
+        "created_at": backup["created_at"],
+    });
+    let config = backup["limits"].take();
+    let database = backup["snapshot"].take();
+
+    let file = File::create(out).with_context(|| format!("Failed to create {}", out))?;
+    let mut archive = tar::Builder::new(file);
+    for (name, value) in [
+        (MANIFEST_FILE, &manifest),
+        (CONFIG_FILE, &config),
+        (DATABASE_FILE, &database),
+    ] {
+        let bytes = serde_json::to_vec_pretty(value)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o600); // contains session tokens
+        header.set_mtime(backup["created_at"].as_u64().unwrap_or(0));
+        header.set_cksum();
+        archive.append_data(&mut header, name, bytes.as_slice())?;
+    }
+    archive.finish().with_context(|| format!("Failed to write {}", out))?;
+
+    println!("Backup written to {}", out);
+    println!(
+        "  {} accounts, {} sessions, {} vouchers, {} withdrawals",
+        database["accounts"].as_array().map_or(0, Vec::len),
+        database["sessions"].as_array().map_or(0, Vec::len),
+        database["vouchers"].as_array().map_or(0, Vec::len),
+        database["withdrawals"].as_array().map_or(0, Vec::len),
+    );
+    Ok(())
+}
+
+// =============================================================================
+// Restore
+// =============================================================================
+
+fn read_archive(path: &str) -> Result<HashMap<String, Value>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let mut archive = tar::Archive::new(file);
+    let mut files = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = String::new();
+        entry
+            .read_to_string(&mut contents)
+            .with_context(|| format!("Failed to read {} from archive", name))?;
+        let value = serde_json::from_str(&contents)
+            .with_context(|| format!("{} in archive is not valid JSON", name))?;
+        files.insert(name, value);
+    }
+    Ok(files)
+}
+
+fn restore(options: &Options, input: &str, force: bool) -> Result<()> {
+    let mut files = read_archive(input)?;
+    let mut take = |name: &str| {
+        files
+            .remove(name)
+            .ok_or_else(|| anyhow!("{} is missing {}", input, name))
+    };
+    let manifest = take(MANIFEST_FILE)?;
+    let config = take(CONFIG_FILE)?;
+    let database = take(DATABASE_FILE)?;
+
+    // Refuse to silently overwrite a deployment that is already in use
+    if !force {
+        let stats = call(
+            options.agent.get(&format!("{}/admin/stats", options.server)).set("X-Api-Key", &options.api_key),
+            None,
+        )?;
+        let in_use = ["accounts", "sessions", "vouchers"]
+            .iter()
+            .any(|field| stats[field].as_u64().unwrap_or(0) > 0);
+        if in_use {
+            bail!(
+                "{} already holds data, pass --force to replace it with the backup",
+                options.server
+            );
+        }
+    }
+
+    println!("Restoring {} into {} (writes are paused meanwhile)...", input, options.server);
+    call(
+        options.agent.post(&format!("{}/admin/restore", options.server)).set("X-Api-Key", &options.api_key),
+        Some(&json!({
+            "format": manifest["format"],
+            "created_at": manifest["created_at"],
+            "limits": config,
+            "snapshot": database,
+        })),
+    )?;
+
+    println!("Restore complete");
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let options = parse_args()?;
+    match &options.command {
+        Command::Backup { out } => backup(&options, out),
+        Command::Restore { input, force } => restore(&options, input, *force),
+    }
+}
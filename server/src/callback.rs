@@ -0,0 +1,278 @@
+// =============================================================================
+// Callback URLs
+// =============================================================================
+//
+// LUD-01 wants the callbacks handed to wallets on https, with plain http only
+// for .onion hosts, where Tor already encrypts and authenticates the
+// connection. The binary refuses any other callback URL at startup unless it
+// runs as a dev setup with --allow-insecure-http (a LAN or tunnel without
+// certificates, like the WireGuard network in the README).
+//
+// With callback_from_request (see config.rs) each request's callbacks are
+// built from where the wallet reached the server instead: the scheme, host
+// and path prefix a reverse proxy passes on in X-Forwarded-Proto,
+// X-Forwarded-Host and X-Forwarded-Prefix, or the Host header, with the
+// configured URL's scheme and path where those are missing. So one server
+// answers on several hostnames, or behind proxies it does not know of. A URL
+// built this way that LUD-01 would refuse (plain http to a host that isn't a
+// .onion) or that isn't a plain host[:port] is dropped for the configured
+// one. The headers are the client's word, but a client that sends false ones
+// only gets callbacks it cannot use itself.
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackUrlError {
+    Malformed(String),
+    InsecureHttp(String),
+}
+
+impl fmt::Display for CallbackUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackUrlError::Malformed(url) => write!(
+                f,
+                "Malformed callback URL {} (expected https://<host>[/path])",
+                url
+            ),
+            CallbackUrlError::InsecureHttp(url) => write!(
+                f,
+                "Callback URL {} is plain http, which LUD-01 only allows for .onion hosts \
+                 (pass --allow-insecure-http for a dev setup)",
+                url
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CallbackUrlError {}
+
+/// The host of an http(s) URL, without userinfo, its port if given, and
+/// whether the scheme is https
+fn split_url(url: &str) -> Option<(bool, &str, Option<&str>)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let https = match scheme.to_ascii_lowercase().as_str() {
+        "https" => true,
+        "http" => false,
+        _ => return None,
+    };
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let (host, port) = match host_port.strip_prefix('[') {
+        Some(v6) => {
+            let (host, rest) = v6.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match host_port.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        },
+    };
+    match host.is_empty() {
+        true => None,
+        false => Some((https, host, port)),
+    }
+}
+
+pub(crate) fn is_onion(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".onion")
+}
+
+/// Ok for https URLs and for http ones on a .onion host
+pub fn check_callback_url(url: &str) -> Result<(), CallbackUrlError> {
+    let Some((https, host, _)) = split_url(url) else {
+        return Err(CallbackUrlError::Malformed(url.to_string()));
+    };
+    match https || is_onion(host) {
+        true => Ok(()),
+        false => Err(CallbackUrlError::InsecureHttp(url.to_string())),
+    }
+}
+
+/// The host a callback URL points at and its port, 443 or 80 by scheme when
+/// the URL has none
+pub(crate) fn host_and_port(url: &str) -> Option<(&str, u16)> {
+    let (https, host, port) = split_url(url)?;
+    match port {
+        Some(port) => Some((host, port.parse().ok()?)),
+        None => Some((host, if https { 443 } else { 80 })),
+    }
+}
+
+/// The path of an http(s) URL, "" when it has none
+fn url_path(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    rest.find('/').map_or("", |start| &rest[start..])
+}
+
+/// The first value of a header, as proxies append theirs to a list
+fn first_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let first = value.split(',').next()?.trim();
+    (!first.is_empty()).then_some(first)
+}
+
+/// `configured` moved to where the request says it reached the server, see
+/// the top of the file
+fn from_headers(headers: &HeaderMap, configured: &str) -> Option<String> {
+    let host = first_value(headers, "x-forwarded-host").or_else(|| first_value(headers, "host"))?;
+    let plain_host = |c: char| c.is_ascii_alphanumeric() || ".-:[]".contains(c);
+    if !host.chars().all(plain_host) {
+        return None;
+    }
+    let (configured_https, _, _) = split_url(configured)?;
+    let scheme = match first_value(headers, "x-forwarded-proto") {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+        Some(proto) if proto.eq_ignore_ascii_case("http") => "http",
+        Some(_) => return None,
+        None if configured_https => "https",
+        None => "http",
+    };
+    let prefix = match first_value(headers, "x-forwarded-prefix") {
+        Some(prefix) => prefix,
+        None => url_path(configured),
+    };
+    let plain_path = |c: char| c.is_ascii_alphanumeric() || "/-._~".contains(c);
+    if !prefix.chars().all(plain_path) {
+        return None;
+    }
+    let prefix = prefix.trim_matches('/');
+    let url = match prefix.is_empty() {
+        true => format!("{}://{}/", scheme, host),
+        false => format!("{}://{}/{}/", scheme, host, prefix),
+    };
+    check_callback_url(&url).ok()?;
+    Some(url)
+}
+
+/// Where the callbacks of a request with these headers point, with a trailing
+/// slash
+pub(crate) fn base_url(state: &AppState, headers: &HeaderMap) -> Arc<str> {
+    if !state.callback_from_request {
+        return state.callback_url.clone();
+    }
+    match from_headers(headers, &state.callback_url) {
+        Some(url) => url.into(),
+        None => state.callback_url.clone(),
+    }
+}
+
+/// The URL a request's callbacks are built from, see the top of the file
+pub struct BaseUrl(pub Arc<str>);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for BaseUrl {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(BaseUrl(base_url(state, &parts.headers)))
+    }
+}
+
+/// Checks the configured callback_url, the public URL of the server that
+/// callbacks are built from (see config.rs). Plain http is let through with a
+/// warning when `allow_insecure_http` is set.
+pub fn load_callback_url(url: &str, allow_insecure_http: bool) -> Result<String, CallbackUrlError> {
+    match check_callback_url(url) {
+        Ok(()) => {}
+        Err(CallbackUrlError::InsecureHttp(_)) if allow_insecure_http => {
+            warn!(
+                "--allow-insecure-http, callbacks go out as plain http ({}): wallets may \
+                 refuse them and anyone on the path can change them",
+                url
+            );
+        }
+        Err(e) => return Err(e),
+    }
+    info!("Callbacks at {}", url);
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn https_or_onion() {
+        for url in [
+            "https://shop.example/lnurl",
+            "HTTPS://shop.example:8443/",
+            "https://192.168.27.72:3000/",
+            "http://2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion/",
+            "http://abc.onion:3000/lnurl",
+            "http://abc.ONION./",
+        ] {
+            assert_eq!(check_callback_url(url), Ok(()), "{}", url);
+        }
+    }
+
+    #[test]
+    fn clearnet_http_is_refused() {
+        for url in [
+            "http://192.168.27.72:3000/",
+            "http://shop.example/lnurl",
+            "http://abc.onion.shop.example/",
+            "http://abc.onion@shop.example/",
+            "http://[::1]:3000/",
+        ] {
+            assert_eq!(
+                check_callback_url(url),
+                Err(CallbackUrlError::InsecureHttp(url.to_string())),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn host_and_port_default_by_scheme() {
+        assert_eq!(
+            host_and_port("https://shop.example/lnurl"),
+            Some(("shop.example", 443))
+        );
+        assert_eq!(
+            host_and_port("http://abc.onion:3000/"),
+            Some(("abc.onion", 3000))
+        );
+        assert_eq!(host_and_port("https://[::1]:8443/"), Some(("::1", 8443)));
+        assert_eq!(
+            host_and_port("http://user@shop.example"),
+            Some(("shop.example", 80))
+        );
+        assert_eq!(host_and_port("https://shop.example:port/"), None);
+    }
+
+    #[test]
+    fn malformed_urls() {
+        for url in [
+            "",
+            "shop.example/lnurl",
+            "ftp://shop.example/",
+            "https:///lnurl",
+            "https://[::1/",
+        ] {
+            assert_eq!(
+                check_callback_url(url),
+                Err(CallbackUrlError::Malformed(url.to_string())),
+                "{}",
+                url
+            );
+        }
+    }
+}
@@ -0,0 +1,315 @@
+// =============================================================================
+// Debug capture
+// =============================================================================
+//
+// `lnurl-server --capture <dir>` records the exchanges the server handles,
+// request and response in full, to one JSON Lines file in <dir>. When a
+// third-party wallet misbehaves against us, its developers get that file
+// rather than our description of it.
+//
+// Capture stops after a window (--capture-for <secs>, 10 minutes by default)
+// and can be narrowed to one k1 (--capture-k1 <k1>): the exchanges carrying
+// it in their query, plus the one that handed it out in its response.
+//
+// Secrets are redacted unless --capture-raw is given: query parameters and
+// JSON fields among the ones logs redact (logging.rs), such as k1s, invoices,
+// session tokens and preimages, and API keys in headers keep only a prefix,
+// enough to follow one k1 through a flow. The admin API is never captured. LNURL requests carry no body, so
+// only response bodies are recorded.
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::logging::{redact, SECRET_FIELDS};
+
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Headers that are redacted, besides the SECRET_FIELDS of queries and bodies
+const SECRET_HEADERS: [&str; 3] = ["authorization", "x-api-key", "cookie"];
+
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    pub dir: PathBuf,
+    pub window: Duration,
+    pub k1: Option<String>, // None: every exchange
+    pub raw: bool,          // skip redaction
+}
+
+struct Inner {
+    config: CaptureConfig,
+    path: PathBuf,
+    file: Mutex<File>,
+    until: u64,
+    recorded: AtomicU64,
+    finished: AtomicBool,
+}
+
+/// A running capture, to layer over app() with `record`
+#[derive(Clone)]
+pub struct Capture {
+    inner: Arc<Inner>,
+}
+
+impl Capture {
+    /// Creates the capture file, headed by a line describing the capture
+    pub fn start(config: CaptureConfig) -> io::Result<Capture> {
+        std::fs::create_dir_all(&config.dir)?;
+        let started_at = crate::unix_now();
+        let path = config
+            .dir
+            .join(format!("lnurl-capture-{}.jsonl", started_at));
+        let mut file = File::create(&path)?;
+
+        let until = started_at + config.window.as_secs();
+        let header = json!({
+            "capture": {
+                "server": concat!("lnurl-server ", env!("CARGO_PKG_VERSION")),
+                "started_at": started_at,
+                "until": until,
+                "k1": config.k1.as_deref().map(|k1| match config.raw {
+                    true => k1.to_string(),
+                    false => redact(k1),
+                }),
+                "redacted": !config.raw,
+            }
+        });
+        writeln!(file, "{}", header)?;
+
+        Ok(Capture {
+            inner: Arc::new(Inner {
+                config,
+                path,
+                file: Mutex::new(file),
+                until,
+                recorded: AtomicU64::new(0),
+                finished: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Whether the window is still open, saying so once when it closes
+    fn active(&self) -> bool {
+        if crate::unix_now() < self.inner.until {
+            return true;
+        }
+        if !self.inner.finished.swap(true, Ordering::Relaxed) {
+            info!(
+                "Capture finished: {} exchanges in {}",
+                self.inner.recorded.load(Ordering::Relaxed),
+                self.inner.path.display()
+            );
+        }
+        false
+    }
+
+    fn matches(&self, query: &str, response_body: &[u8]) -> bool {
+        let Some(ref k1) = self.inner.config.k1 else {
+            return true;
+        };
+        let in_query = query
+            .split('&')
+            .any(|pair| pair.split_once('=') == Some(("k1", k1.as_str())));
+        in_query
+            || response_body
+                .windows(k1.len())
+                .any(|window| window == k1.as_bytes())
+    }
+
+    fn redact_query(&self, query: &str) -> String {
+        if self.inner.config.raw {
+            return query.to_string();
+        }
+        query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, value)) if SECRET_FIELDS.contains(&name) => {
+                    format!("{}={}", name, redact(value))
+                }
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Value {
+        let mut map = Map::new();
+        for (name, value) in headers {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            let secret = !self.inner.config.raw && SECRET_HEADERS.contains(&name.as_str());
+            let value = match (secret, value.split_once(' ')) {
+                (false, _) => value,
+                // Keep the scheme of `Bearer <token>`
+                (true, Some((scheme, secret))) => format!("{} {}", scheme, redact(secret)),
+                (true, None) => redact(&value),
+            };
+            map.insert(name.as_str().to_string(), Value::String(value));
+        }
+        Value::Object(map)
+    }
+
+    fn body(&self, bytes: &[u8]) -> Value {
+        match serde_json::from_slice::<Value>(bytes) {
+            Ok(mut body) => {
+                if !self.inner.config.raw {
+                    redact_fields(&mut body);
+                }
+                body
+            }
+            Err(_) => Value::String(String::from_utf8_lossy(bytes).into_owned()),
+        }
+    }
+
+    fn write(&self, entry: &Value) {
+        let mut file = self.inner.file.lock().unwrap();
+        match writeln!(file, "{}", entry) {
+            Ok(()) => {
+                self.inner.recorded.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => error!("Failed to write capture: {}", e),
+        }
+    }
+}
+
+fn redact_fields(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (name, field) in map.iter_mut() {
+                match field {
+                    Value::String(secret) if SECRET_FIELDS.contains(&name.as_str()) => {
+                        *secret = redact(secret)
+                    }
+                    _ => redact_fields(field),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_fields),
+        _ => {}
+    }
+}
+
+/// Middleware recording each exchange that matches the capture
+pub async fn record(State(capture): State<Capture>, request: Request, next: Next) -> Response {
+    if request.uri().path().starts_with("/admin") || !capture.active() {
+        return next.run(request).await;
+    }
+
+    let at = crate::unix_now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let query = request.uri().query().unwrap_or("").to_string();
+    let request_headers = capture.headers(request.headers());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+
+    let (parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to capture response to {}: {}", path, e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    if capture.matches(&query, &bytes) {
+        let uri = match query.is_empty() {
+            true => path,
+            false => format!("{}?{}", path, capture.redact_query(&query)),
+        };
+        capture.write(&json!({
+            "at": at,
+            "elapsed_ms": elapsed_ms,
+            "request": {
+                "method": method,
+                "uri": uri,
+                "headers": request_headers,
+            },
+            "response": {
+                "status": parts.status.as_u16(),
+                "headers": capture.headers(&parts.headers),
+                "body": capture.body(&bytes),
+            },
+        }));
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(k1: Option<&str>, raw: bool) -> Capture {
+        let dir = std::env::temp_dir().join(format!("lnurl-capture-{}", uuid::Uuid::new_v4()));
+        Capture::start(CaptureConfig {
+            dir,
+            window: DEFAULT_WINDOW,
+            k1: k1.map(str::to_string),
+            raw,
+        })
+        .unwrap()
+    }
+
+    fn remove(capture: Capture) {
+        std::fs::remove_dir_all(capture.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn redacts_secrets_unless_raw() {
+        let redacting = capture(None, false);
+        assert_eq!(
+            redacting.redact_query("k1=0123456789abcdef&pr=lntb5000"),
+            "k1=012345…&pr=lntb50…"
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer 0123456789abcdef".parse().unwrap());
+        headers.insert("user-agent", "SomeWallet/1.2".parse().unwrap());
+        assert_eq!(
+            redacting.headers(&headers),
+            json!({"authorization": "Bearer 012345…", "user-agent": "SomeWallet/1.2"})
+        );
+        let body =
+            br#"{"status":"OK","token":"0123456789abcdef","vouchers":[{"k1":"fedcba9876543210"}]}"#;
+        assert_eq!(
+            redacting.body(body),
+            json!({"status": "OK", "token": "012345…", "vouchers": [{"k1": "fedcba…"}]})
+        );
+
+        let raw = capture(None, true);
+        assert_eq!(
+            raw.redact_query("k1=0123456789abcdef"),
+            "k1=0123456789abcdef"
+        );
+        assert_eq!(raw.body(body)["token"], "0123456789abcdef");
+
+        remove(redacting);
+        remove(raw);
+    }
+
+    #[test]
+    fn follows_one_k1() {
+        let capture = capture(Some("abc123"), false);
+        assert!(capture.matches("k1=abc123&pr=lntb5000", b""));
+        assert!(capture.matches("", br#"{"k1":"abc123","tag":"withdrawRequest"}"#));
+        assert!(!capture.matches("k1=abc1234", b""));
+        assert!(!capture.matches("", br#"{"k1":"other"}"#));
+        remove(capture);
+    }
+}
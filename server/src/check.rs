@@ -0,0 +1,369 @@
+// =============================================================================
+// Self-check
+// =============================================================================
+//
+// `lnurl-server check` goes through what startup needs from the setup: the
+// config file and flags, the LNURL_* configuration, the TLS certificate, the
+// CLN RPC, the database and its migrations, and the callback URL. It reports
+// on every step instead of stopping at the first failure, and serves and
+// changes nothing (pending migrations are listed, not applied), so it can run
+// next to a live server or in a deploy pipeline before the switch.
+//
+// With --self-request it also answers a one-off token on listen_addr and
+// fetches it back through the callback URL, the way a wallet would reach the
+// server. That catches a proxy, tunnel or port forward that does not lead to
+// this host, or that keeps a path prefix the server does not route. It needs
+// listen_addr free, so it cannot run while the server does.
+
+use axum::routing::get;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::ServerConfig;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+use crate::backend::{Backend, ClnBackend};
+use crate::config::{Config, Tls};
+use crate::storage::postgres::MigrationStatus;
+use crate::storage::{self, PostgresStorage};
+use crate::{
+    admin, callback, crypto, fees, listener, notify, pay, pricing, screen, text, throttle, webhook,
+};
+
+const SELF_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct CheckOptions {
+    pub config: Config,
+    pub database_url: Option<String>, // None: in-memory storage
+    pub allow_insecure_http: bool,
+    pub self_request: bool,
+}
+
+/// One checked part of the setup, with what was found or what is wrong
+pub struct Step {
+    pub name: &'static str,
+    pub outcome: Result<String, String>,
+}
+
+fn step<T, E: fmt::Display>(
+    name: &'static str,
+    result: Result<T, E>,
+    found: impl FnOnce(T) -> String,
+) -> Step {
+    Step {
+        name,
+        outcome: result.map(found).map_err(|e| e.to_string()),
+    }
+}
+
+fn found(name: &'static str, found: String) -> Step {
+    Step {
+        name,
+        outcome: Ok(found),
+    }
+}
+
+pub struct Report(pub Vec<Step>);
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.0.iter().all(|step| step.outcome.is_ok())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.0 {
+            match &step.outcome {
+                Ok(found) => writeln!(f, "OK    {:<16} {}", step.name, found)?,
+                Err(e) => writeln!(f, "FAIL  {:<16} {}", step.name, e)?,
+            }
+        }
+        let failed = self.0.iter().filter(|step| step.outcome.is_err()).count();
+        match failed {
+            0 => write!(f, "All {} checks passed", self.0.len()),
+            _ => write!(f, "{} of {} checks failed", failed, self.0.len()),
+        }
+    }
+}
+
+fn set_or_not<T>(value: Option<T>, set: &str, not_set: &str) -> String {
+    match value {
+        Some(_) => set.to_string(),
+        None => not_set.to_string(),
+    }
+}
+
+/// Runs every step, see the top of the file
+pub async fn run(options: &CheckOptions) -> Report {
+    let mut steps = Vec::new();
+
+    let config = &options.config;
+    steps.push(found(
+        "config",
+        format!("{}, announced as {}", config.network, config.announce_addr),
+    ));
+    let callback_url =
+        callback::load_callback_url(&config.callback_url, options.allow_insecure_http);
+    steps.push(step("callback URL", callback_url.clone(), |url| url));
+    let tls = match &config.tls {
+        Some(Tls::Files { cert, key }) => listener::load_public_tls(cert, key).map(Some),
+        _ => Ok(None),
+    };
+    steps.push(match (&config.tls, &tls) {
+        (None, _) => found("tls", "off, plain http".to_string()),
+        (Some(description), tls) => step("tls", tls.as_ref().map_err(|e| e.to_string()), |_| {
+            description.to_string()
+        }),
+    });
+    steps.push(step("encryption", crypto::load_cipher(), |cipher| {
+        set_or_not(cipher, "enabled", "off, payment preimages are not stored")
+    }));
+    steps.push(step("channel fees", fees::load_fee_update(), |update| {
+        set_or_not(update, "set up on open", "node defaults")
+    }));
+    steps.push(step(
+        "channel pricing",
+        pricing::load_pricing(),
+        |pricing| set_or_not(pricing, "enabled", "channels are free"),
+    ));
+    steps.push(step(
+        "withdraw text",
+        text::load_withdraw_description(),
+        |description| description.unwrap_or_else(|| crate::DEFAULT_DESCRIPTION.to_string()),
+    ));
+    steps.push(step("pay requests", pay::load_pay_config(), |config| {
+        format!(
+            "{} to {} msat, {} Lightning Address(es)",
+            config.min_sendable_msat,
+            config.max_sendable_msat,
+            config.addresses.len()
+        )
+    }));
+    steps.push(step("screening", screen::load_screener(), |screener| {
+        set_or_not(screener, "enabled", "off")
+    }));
+    steps.push(step(
+        "admin networks",
+        admin::load_allowlist(),
+        |networks| match networks.len() {
+            0 => "any address".to_string(),
+            n => format!("{} network(s)", n),
+        },
+    ));
+    steps.push(step(
+        "admin listener",
+        listener::load_admin_listener(),
+        |listener| match listener {
+            Some(listener) => listener.to_string(),
+            None => format!("public listener ({})", config.listen_addr),
+        },
+    ));
+    steps.push(step("notifications", notify::load_notifications(), |_| {
+        "ok".to_string()
+    }));
+    steps.push(step(
+        "auth webhook",
+        webhook::load_auth_webhook(),
+        |webhook| set_or_not(webhook, "enabled", "off"),
+    ));
+    // Lenient loaders, which warn above about a malformed value and keep the
+    // default
+    let keys = match admin::load_keys().len() {
+        0 => "none, admin API disabled".to_string(),
+        n => format!("{} key(s)", n),
+    };
+    steps.push(found("admin keys", keys));
+    let limit = throttle::load_rate_limit();
+    steps.push(found(
+        "account limit",
+        format!(
+            "bursts of {}, {} per minute",
+            limit.burst, limit.refill_per_minute
+        ),
+    ));
+    let ttls = storage::load_k1_ttls();
+    steps.push(found(
+        "k1 TTLs",
+        format!(
+            "channel {}s, withdraw {}s, auth {}s, pay {}s",
+            ttls.channel_secs, ttls.withdraw_secs, ttls.auth_secs, ttls.pay_secs
+        ),
+    ));
+
+    steps.push(step("node", node_id(&config.rpc_path).await, |pubkey| {
+        pubkey
+    }));
+    steps.push(match &options.database_url {
+        Some(url) => step(
+            "storage",
+            PostgresStorage::migration_status(url).await,
+            describe_migrations,
+        ),
+        None => found(
+            "storage",
+            "LNURL_DATABASE_URL not set, in-memory (lost on restart)".to_string(),
+        ),
+    });
+
+    if let Ok(url) = &callback_url {
+        steps.push(step("callback host", resolve(url).await, |found| found));
+        if options.self_request {
+            let result = match (TcpListener::bind(config.listen_addr).await, tls) {
+                (_, Err(e)) => Err(format!("Cannot serve without the certificate: {}", e)),
+                (_, Ok(None)) if matches!(config.tls, Some(Tls::Acme(_))) => {
+                    Err("Not run with ACME certificates, they are only got at startup".to_string())
+                }
+                (Ok(listener), Ok(tls)) => self_request(listener, tls, url).await,
+                (Err(e), _) => Err(format!(
+                    "Cannot listen on {} ({}), is the server running?",
+                    config.listen_addr, e
+                )),
+            };
+            steps.push(step("self-request", result, |found| found));
+        }
+    }
+    Report(steps)
+}
+
+async fn node_id(rpc_path: &Path) -> Result<String, String> {
+    let backend = ClnBackend::connect(rpc_path).await.map_err(|e| {
+        format!(
+            "Failed to connect to CLN RPC at {}: {}",
+            rpc_path.display(),
+            e
+        )
+    })?;
+    backend
+        .node_id()
+        .await
+        .map_err(|e| format!("Failed to get node info: {}", e))
+}
+
+fn describe_migrations(status: MigrationStatus) -> String {
+    match status.pending.is_empty() {
+        true => format!("PostgreSQL, {} migrations applied", status.applied),
+        false => format!(
+            "PostgreSQL, {} migrations applied, pending until the next start: {}",
+            status.applied,
+            status.pending.join(", ")
+        ),
+    }
+}
+
+/// Whether the callback host resolves, which .onion hosts only do over Tor
+async fn resolve(url: &str) -> Result<String, String> {
+    let Some((host, port)) = callback::host_and_port(url) else {
+        return Err(format!("No host or a bad port in {}", url));
+    };
+    if callback::is_onion(host) {
+        return Ok(format!("{} is an onion service, left to Tor", host));
+    }
+    let addrs: Vec<String> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("{} does not resolve: {}", host, e))?
+        .map(|addr| addr.ip().to_string())
+        .collect();
+    Ok(format!("{} resolves to {}", host, addrs.join(", ")))
+}
+
+/// Serves a random token on `listener`, over TLS with `tls`, and fetches it
+/// through `callback_url`
+async fn self_request(
+    listener: TcpListener,
+    tls: Option<ServerConfig>,
+    callback_url: &str,
+) -> Result<String, String> {
+    let token = crate::random_hex_32();
+    let answer = token.clone();
+    let app = Router::new().route(
+        &format!("/lnurl-check/{}", token),
+        get(|| async move { answer }),
+    );
+    let server = match tls {
+        Some(tls) => {
+            let listener = listener.into_std().map_err(|e| e.to_string())?;
+            let tls = RustlsConfig::from_config(Arc::new(tls));
+            let server = axum_server::from_tcp_rustls(listener, tls);
+            tokio::spawn(async move { server.serve(app.into_make_service()).await })
+        }
+        None => tokio::spawn(async move { axum::serve(listener, app).await }),
+    };
+
+    let url = format!(
+        "{}/lnurl-check/{}",
+        callback_url.trim_end_matches('/'),
+        token
+    );
+    let fetched = tokio::task::spawn_blocking(move || {
+        match ureq::get(&url).timeout(SELF_REQUEST_TIMEOUT).call() {
+            Ok(response) => response.into_string().map_err(|e| e.to_string()),
+            Err(ureq::Error::Status(code, _)) => Err(format!(
+                "{} answered {}, it does not lead to this server's routes",
+                url, code
+            )),
+            Err(ureq::Error::Transport(e)) => Err(format!("{} unreachable: {}", url, e)),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string());
+    server.abort();
+
+    match fetched?? == token {
+        true => Ok(format!("{} reaches this server", callback_url)),
+        false => Err(format!("{} reaches another server", callback_url)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn self_request_goes_through_the_callback_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = format!("http://127.0.0.1:{}/", port);
+        let found = self_request(listener, None, &url).await.unwrap();
+        assert_eq!(found, format!("{} reaches this server", url));
+
+        // A path prefix the server does not route
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = format!("http://127.0.0.1:{}/lnurl", port);
+        let error = self_request(listener, None, &url).await.unwrap_err();
+        assert!(error.contains("answered 404"), "{}", error);
+
+        // Nothing behind the URL
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", closed.local_addr().unwrap());
+        drop(closed);
+        let error = self_request(listener, None, &url).await.unwrap_err();
+        assert!(error.contains("unreachable"), "{}", error);
+    }
+
+    #[test]
+    fn report_lists_every_step() {
+        let report = Report(vec![
+            Step {
+                name: "callback URL",
+                outcome: Ok("https://shop.example".to_string()),
+            },
+            Step {
+                name: "node",
+                outcome: Err("Failed to connect to CLN RPC".to_string()),
+            },
+        ]);
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "OK    callback URL     https://shop.example\n\
+             FAIL  node             Failed to connect to CLN RPC\n\
+             1 of 2 checks failed"
+        );
+    }
+}
@@ -0,0 +1,704 @@
+// =============================================================================
+// Configuration
+// =============================================================================
+//
+// Read at startup from config.toml in the working directory (or --config
+// <path>, or LNURL_CONFIG), then overridden by environment variables, so a
+// container can do without the file, and those by the flag of the same name
+// on the command line (--listen-addr, --callback-url, ...):
+//
+//   listen_addr    LNURL_LISTEN_ADDR    where the public API listens,
+//                                       default 0.0.0.0:3000
+//   callback_url   LNURL_CALLBACK_URL   public URL wallets reach it at, see
+//                                       callback.rs
+//   callback_from_request  LNURL_CALLBACK_FROM_REQUEST
+//                  true (default) builds each request's callbacks from its
+//                  Host and X-Forwarded-* headers, false from callback_url only
+//   announce_addr  LNURL_ANNOUNCE_ADDR  host:port other nodes reach the CLN
+//                                       node on, handed out by request-channel
+//   network        LNURL_NETWORK        bitcoin, testnet, testnet4 (default),
+//                                       signet or regtest
+//   rpc_path       LNURL_RPC_PATH       CLN socket, default
+//                                       ~/.lightning/<network>/lightning-rpc
+//
+//   [limits]       amounts at startup, the admin API changes them later
+//   min_withdrawable_msat  LNURL_MIN_WITHDRAWABLE_MSAT
+//   max_withdrawable_msat  LNURL_MAX_WITHDRAWABLE_MSAT
+//   channel_capacity_sat   LNURL_CHANNEL_CAPACITY_SAT
+//   withdraw_budget_msat   LNURL_WITHDRAW_BUDGET_MSAT
+//
+//   [tls]          https on listen_addr, see listener.rs; plain http without
+//   cert           LNURL_TLS_CERT       PEM certificate chain, reloaded when
+//   key            LNURL_TLS_KEY        the files change (e.g. by certbot)
+//   or, built with --features acme, a certificate from an ACME CA, see acme.rs:
+//   acme_domains   LNURL_ACME_DOMAINS   the names it is for, comma-separated
+//   acme_email     LNURL_ACME_EMAIL     contact for expiry notices
+//   acme_directory LNURL_ACME_DIRECTORY default Let's Encrypt
+//   acme_cache     LNURL_ACME_CACHE     account key and certificate, default
+//                                       ./acme
+//   acme_http_addr LNURL_ACME_HTTP_ADDR where http-01 challenges are answered,
+//                                       default 0.0.0.0:80
+//
+// Example config.toml:
+//
+//   listen_addr = "0.0.0.0:3000"
+//   callback_url = "https://lnurl.example/"
+//   announce_addr = "203.0.113.7:9735"
+//   network = "signet"
+//
+//   [limits]
+//   min_withdrawable_msat = 1000
+//   max_withdrawable_msat = 1000000
+//   channel_capacity_sat = 100000
+//   withdraw_budget_msat = 10000000  # given to each new account
+//
+//   [tls]
+//   cert = "/etc/letsencrypt/live/lnurl.example/fullchain.pem"
+//   key = "/etc/letsencrypt/live/lnurl.example/privkey.pem"
+
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::Limits;
+
+/// Read when present, unless --config names another file
+pub const DEFAULT_PATH: &str = "config.toml";
+
+const DEFAULT_ACME_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+const DEFAULT_ACME_CACHE: &str = "acme";
+const DEFAULT_ACME_HTTP_ADDR: &str = "0.0.0.0:80";
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The chain the CLN node runs on, named the way CLN names its directories
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Bitcoin,
+    Testnet,
+    #[default]
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Network::Bitcoin => "bitcoin",
+            Network::Testnet => "testnet",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What config.toml and the flags set, None where they leave it to the layer
+/// below
+#[derive(Debug, Clone, Default, Deserialize, Args)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Address the public API listens on [default: 0.0.0.0:3000]
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub listen_addr: Option<SocketAddr>,
+
+    /// Public URL wallets reach the server at, callbacks are built from it
+    #[arg(long, global = true, value_name = "URL")]
+    pub callback_url: Option<String>,
+
+    /// Build callbacks from each request's Host and X-Forwarded-* headers,
+    /// false to always use the callback URL [default: true]
+    #[arg(long, global = true, value_name = "BOOL")]
+    pub callback_from_request: Option<bool>,
+
+    /// host:port other nodes reach the CLN node on, for request-channel
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    pub announce_addr: Option<String>,
+
+    /// Chain the CLN node runs on [default: testnet4]
+    #[arg(long, global = true, value_enum)]
+    pub network: Option<Network>,
+
+    /// CLN socket [default: ~/.lightning/<network>/lightning-rpc]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub rpc_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub limits: LimitSettings,
+
+    #[command(flatten)]
+    pub tls: TlsSettings,
+}
+
+/// The [limits] table, see Limits
+#[derive(Debug, Clone, Default, Deserialize, Args)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitSettings {
+    /// Smallest withdraw [default: 1000]
+    #[arg(long, global = true, value_name = "MSAT")]
+    pub min_withdrawable_msat: Option<u64>,
+
+    /// Largest withdraw [default: 1000000]
+    #[arg(long, global = true, value_name = "MSAT")]
+    pub max_withdrawable_msat: Option<u64>,
+
+    /// Largest channel opened on request [default: 100000]
+    #[arg(long, global = true, value_name = "SAT")]
+    pub channel_capacity_sat: Option<u64>,
+
+    /// Withdraw budget of each new account [default: 10000000]
+    #[arg(long, global = true, value_name = "MSAT")]
+    pub withdraw_budget_msat: Option<u64>,
+}
+
+/// The [tls] table
+#[derive(Debug, Clone, Default, Deserialize, Args)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    /// PEM certificate chain, to serve https [default: plain http]
+    #[arg(long = "tls-cert", global = true, value_name = "PATH")]
+    pub cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[arg(long = "tls-key", global = true, value_name = "PATH")]
+    pub key: Option<PathBuf>,
+
+    /// Get the certificate from an ACME CA for these names (--features acme)
+    #[arg(long, global = true, value_name = "DOMAINS", value_delimiter = ',')]
+    pub acme_domains: Option<Vec<String>>,
+
+    /// Contact the ACME CA sends expiry notices to
+    #[arg(long, global = true, value_name = "EMAIL")]
+    pub acme_email: Option<String>,
+
+    /// ACME directory URL [default: Let's Encrypt]
+    #[arg(long, global = true, value_name = "URL")]
+    pub acme_directory: Option<String>,
+
+    /// Where the ACME account key and certificate are kept [default: ./acme]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub acme_cache: Option<PathBuf>,
+
+    /// Where ACME http-01 challenges are answered [default: 0.0.0.0:80]
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub acme_http_addr: Option<SocketAddr>,
+}
+
+impl TlsSettings {
+    fn or(self, lower: TlsSettings) -> TlsSettings {
+        TlsSettings {
+            cert: self.cert.or(lower.cert),
+            key: self.key.or(lower.key),
+            acme_domains: self.acme_domains.or(lower.acme_domains),
+            acme_email: self.acme_email.or(lower.acme_email),
+            acme_directory: self.acme_directory.or(lower.acme_directory),
+            acme_cache: self.acme_cache.or(lower.acme_cache),
+            acme_http_addr: self.acme_http_addr.or(lower.acme_http_addr),
+        }
+    }
+
+    fn is_acme(&self) -> bool {
+        self.acme_domains.is_some()
+            || self.acme_email.is_some()
+            || self.acme_directory.is_some()
+            || self.acme_cache.is_some()
+            || self.acme_http_addr.is_some()
+    }
+}
+
+impl Settings {
+    /// These settings, with `lower`'s where these have none
+    pub fn or(self, lower: Settings) -> Settings {
+        Settings {
+            listen_addr: self.listen_addr.or(lower.listen_addr),
+            callback_url: self.callback_url.or(lower.callback_url),
+            callback_from_request: self.callback_from_request.or(lower.callback_from_request),
+            announce_addr: self.announce_addr.or(lower.announce_addr),
+            network: self.network.or(lower.network),
+            rpc_path: self.rpc_path.or(lower.rpc_path),
+            limits: LimitSettings {
+                min_withdrawable_msat: self
+                    .limits
+                    .min_withdrawable_msat
+                    .or(lower.limits.min_withdrawable_msat),
+                max_withdrawable_msat: self
+                    .limits
+                    .max_withdrawable_msat
+                    .or(lower.limits.max_withdrawable_msat),
+                channel_capacity_sat: self
+                    .limits
+                    .channel_capacity_sat
+                    .or(lower.limits.channel_capacity_sat),
+                withdraw_budget_msat: self
+                    .limits
+                    .withdraw_budget_msat
+                    .or(lower.limits.withdraw_budget_msat),
+            },
+            tls: self.tls.or(lower.tls),
+        }
+    }
+
+    /// Reads the LNURL_* variables of the top of the file through `var`
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Settings, ConfigError> {
+        let var = |name: &str| {
+            var(name)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        Ok(Settings {
+            listen_addr: parse_var("LNURL_LISTEN_ADDR", var("LNURL_LISTEN_ADDR"))?,
+            callback_url: var("LNURL_CALLBACK_URL"),
+            callback_from_request: match var("LNURL_CALLBACK_FROM_REQUEST") {
+                Some(raw) => Some(crate::parse_flag(&raw).ok_or_else(|| {
+                    ConfigError(format!(
+                        "LNURL_CALLBACK_FROM_REQUEST must be true or false, got {}",
+                        raw
+                    ))
+                })?),
+                None => None,
+            },
+            announce_addr: var("LNURL_ANNOUNCE_ADDR"),
+            network: match var("LNURL_NETWORK") {
+                Some(raw) => Some(ValueEnum::from_str(&raw, true).map_err(|_| {
+                    ConfigError(format!(
+                        "LNURL_NETWORK must be bitcoin, testnet, testnet4, signet or regtest, \
+                         got {}",
+                        raw
+                    ))
+                })?),
+                None => None,
+            },
+            rpc_path: var("LNURL_RPC_PATH").map(PathBuf::from),
+            limits: LimitSettings {
+                min_withdrawable_msat: parse_var(
+                    "LNURL_MIN_WITHDRAWABLE_MSAT",
+                    var("LNURL_MIN_WITHDRAWABLE_MSAT"),
+                )?,
+                max_withdrawable_msat: parse_var(
+                    "LNURL_MAX_WITHDRAWABLE_MSAT",
+                    var("LNURL_MAX_WITHDRAWABLE_MSAT"),
+                )?,
+                channel_capacity_sat: parse_var(
+                    "LNURL_CHANNEL_CAPACITY_SAT",
+                    var("LNURL_CHANNEL_CAPACITY_SAT"),
+                )?,
+                withdraw_budget_msat: parse_var(
+                    "LNURL_WITHDRAW_BUDGET_MSAT",
+                    var("LNURL_WITHDRAW_BUDGET_MSAT"),
+                )?,
+            },
+            tls: TlsSettings {
+                cert: var("LNURL_TLS_CERT").map(PathBuf::from),
+                key: var("LNURL_TLS_KEY").map(PathBuf::from),
+                acme_domains: var("LNURL_ACME_DOMAINS").map(|domains| {
+                    domains
+                        .split(',')
+                        .map(|domain| domain.trim().to_string())
+                        .collect()
+                }),
+                acme_email: var("LNURL_ACME_EMAIL"),
+                acme_directory: var("LNURL_ACME_DIRECTORY"),
+                acme_cache: var("LNURL_ACME_CACHE").map(PathBuf::from),
+                acme_http_addr: parse_var("LNURL_ACME_HTTP_ADDR", var("LNURL_ACME_HTTP_ADDR"))?,
+            },
+        })
+    }
+
+    /// Reads a config file
+    pub fn from_file(path: &Path) -> Result<Settings, ConfigError> {
+        let raw = std::fs::read_to_string(path).map_err(|e| {
+            ConfigError(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        toml::from_str(&raw)
+            .map_err(|e| ConfigError(format!("Invalid config file {}: {}", path.display(), e)))
+    }
+}
+
+fn parse_var<T: std::str::FromStr>(
+    name: &str,
+    value: Option<String>,
+) -> Result<Option<T>, ConfigError> {
+    match value {
+        Some(raw) => raw
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError(format!("Invalid {}: {}", name, raw))),
+        None => Ok(None),
+    }
+}
+
+/// How the public listener speaks https
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tls {
+    /// From PEM files, see listener::load_public_tls
+    Files { cert: PathBuf, key: PathBuf },
+    /// From an ACME CA, see acme.rs
+    Acme(AcmeConfig),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcmeConfig {
+    pub domains: Vec<String>,
+    pub email: Option<String>,
+    pub directory: String,
+    pub cache: PathBuf,
+    pub http_addr: SocketAddr,
+}
+
+impl fmt::Display for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tls::Files { cert, .. } => write!(f, "certificate {}", cert.display()),
+            Tls::Acme(acme) => write!(
+                f,
+                "ACME certificate for {} from {}",
+                acme.domains.join(", "),
+                acme.directory
+            ),
+        }
+    }
+}
+
+/// None for plain http
+fn resolve_tls(tls: TlsSettings) -> Result<Option<Tls>, ConfigError> {
+    let acme = tls.is_acme();
+    let domains = tls.acme_domains.unwrap_or_default();
+    match (tls.cert, tls.key) {
+        (Some(_), Some(_)) if acme => Err(ConfigError(
+            "tls.cert and tls.key exclude the tls.acme_* settings".to_string(),
+        )),
+        (Some(cert), Some(key)) => Ok(Some(Tls::Files { cert, key })),
+        (None, None) if !acme => Ok(None),
+        (None, None) if domains.is_empty() => Err(ConfigError(
+            "tls.acme_* settings need tls.acme_domains".to_string(),
+        )),
+        (None, None) => {
+            if cfg!(not(feature = "acme")) {
+                return Err(ConfigError(
+                    "tls.acme_domains needs a server built with --features acme".to_string(),
+                ));
+            }
+            // http-01 challenges cannot prove wildcards
+            let valid = |domain: &String| {
+                !domain.is_empty()
+                    && domain
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+            };
+            if let Some(domain) = domains.iter().find(|domain| !valid(domain)) {
+                return Err(ConfigError(format!(
+                    "tls.acme_domains: {:?} is not a domain name",
+                    domain
+                )));
+            }
+            Ok(Some(Tls::Acme(AcmeConfig {
+                domains,
+                email: tls.acme_email,
+                directory: tls
+                    .acme_directory
+                    .unwrap_or_else(|| DEFAULT_ACME_DIRECTORY.to_string()),
+                cache: tls
+                    .acme_cache
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_ACME_CACHE)),
+                http_addr: tls.acme_http_addr.unwrap_or_else(|| {
+                    DEFAULT_ACME_HTTP_ADDR
+                        .parse()
+                        .expect("valid DEFAULT_ACME_HTTP_ADDR")
+                }),
+            })))
+        }
+        _ => Err(ConfigError(
+            "tls.cert and tls.key must be set together".to_string(),
+        )),
+    }
+}
+
+/// The settings in effect, every default filled in
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen_addr: SocketAddr,
+    pub callback_url: String,
+    pub callback_from_request: bool,
+    pub announce_addr: String,
+    pub network: Network,
+    pub rpc_path: PathBuf,
+    pub limits: Limits,
+    pub tls: Option<Tls>,
+}
+
+impl Config {
+    /// Reads `path` or LNURL_CONFIG (which must exist) or config.toml (which
+    /// may not), and lays the environment and then `flags` over it
+    pub fn load(path: Option<&Path>, flags: Settings) -> Result<Config, ConfigError> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("LNURL_CONFIG").map(PathBuf::from));
+        let file = match path {
+            Some(path) => Settings::from_file(&path)?,
+            None if Path::new(DEFAULT_PATH).exists() => {
+                Settings::from_file(Path::new(DEFAULT_PATH))?
+            }
+            None => Settings::default(),
+        };
+        let env = Settings::from_vars(|name| std::env::var(name).ok())?;
+        Config::from_settings(flags.or(env).or(file))
+    }
+
+    /// Fills in the defaults and checks the result
+    pub fn from_settings(settings: Settings) -> Result<Config, ConfigError> {
+        let network = settings.network.unwrap_or_default();
+        let rpc_path = match settings.rpc_path {
+            Some(path) => path,
+            None => {
+                let home = std::env::var("HOME")
+                    .map_err(|_| ConfigError("HOME not set, configure rpc_path".to_string()))?;
+                PathBuf::from(home)
+                    .join(".lightning")
+                    .join(network.to_string())
+                    .join("lightning-rpc")
+            }
+        };
+
+        let announce_addr = settings
+            .announce_addr
+            .unwrap_or_else(|| crate::IP_ADDRESS.to_string());
+        match announce_addr.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => {
+                return Err(ConfigError(format!(
+                    "announce_addr must be host:port, got {}",
+                    announce_addr
+                )))
+            }
+        }
+
+        let defaults = Limits::default();
+        let limits = Limits {
+            min_withdrawable_msat: settings
+                .limits
+                .min_withdrawable_msat
+                .unwrap_or(defaults.min_withdrawable_msat),
+            max_withdrawable_msat: settings
+                .limits
+                .max_withdrawable_msat
+                .unwrap_or(defaults.max_withdrawable_msat),
+            channel_capacity_sat: settings
+                .limits
+                .channel_capacity_sat
+                .unwrap_or(defaults.channel_capacity_sat),
+            withdraw_budget_msat: settings
+                .limits
+                .withdraw_budget_msat
+                .unwrap_or(defaults.withdraw_budget_msat),
+        };
+        if limits.min_withdrawable_msat > limits.max_withdrawable_msat {
+            return Err(ConfigError(
+                "min_withdrawable_msat must not exceed max_withdrawable_msat".to_string(),
+            ));
+        }
+
+        Ok(Config {
+            listen_addr: settings
+                .listen_addr
+                .unwrap_or_else(|| crate::LISTEN_ADDR.parse().expect("valid LISTEN_ADDR")),
+            callback_url: settings
+                .callback_url
+                .unwrap_or_else(|| crate::CALLBACK_URL.to_string()),
+            callback_from_request: settings.callback_from_request.unwrap_or(true),
+            announce_addr,
+            network,
+            rpc_path,
+            limits,
+            tls: resolve_tls(settings.tls)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Settings, toml::de::Error> {
+        toml::from_str(raw)
+    }
+
+    #[test]
+    fn file_settings_are_read() {
+        let settings = parse(
+            r#"
+            listen_addr = "127.0.0.1:8080"
+            callback_url = "https://lnurl.example/"
+            announce_addr = "203.0.113.7:9735"
+            network = "signet"
+
+            [limits]
+            max_withdrawable_msat = 5000000
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_settings(settings).unwrap();
+        assert_eq!(config.listen_addr, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(config.callback_url, "https://lnurl.example/");
+        assert_eq!(config.announce_addr, "203.0.113.7:9735");
+        assert_eq!(config.network, Network::Signet);
+        assert!(config.rpc_path.ends_with(".lightning/signet/lightning-rpc"));
+        assert_eq!(config.limits.max_withdrawable_msat, 5_000_000);
+        assert_eq!(config.limits.min_withdrawable_msat, 1_000);
+
+        // Typos are refused rather than silently ignored
+        assert!(parse("listen_address = \"0.0.0.0:3000\"").is_err());
+        assert!(parse("[limits]\nmax_withdrawable = 1").is_err());
+        assert!(parse("network = \"mainnet\"").is_err());
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let file = parse(
+            r#"
+            network = "regtest"
+            rpc_path = "/run/cln/lightning-rpc"
+            [limits]
+            channel_capacity_sat = 50000
+            withdraw_budget_msat = 1
+            "#,
+        )
+        .unwrap();
+        let flags = Settings {
+            network: Some(Network::Bitcoin),
+            limits: LimitSettings {
+                channel_capacity_sat: Some(250_000),
+                ..LimitSettings::default()
+            },
+            ..Settings::default()
+        };
+        let config = Config::from_settings(flags.or(file)).unwrap();
+        assert_eq!(config.network, Network::Bitcoin);
+        assert_eq!(config.rpc_path, PathBuf::from("/run/cln/lightning-rpc"));
+        assert_eq!(config.limits.channel_capacity_sat, 250_000);
+        assert_eq!(config.limits.withdraw_budget_msat, 1);
+        assert_eq!(config.callback_url, crate::CALLBACK_URL);
+        assert!(config.callback_from_request);
+        assert_eq!(config.announce_addr, crate::IP_ADDRESS);
+    }
+
+    #[test]
+    fn environment_sits_between_the_file_and_the_flags() {
+        let vars = |name: &str| match name {
+            "LNURL_LISTEN_ADDR" => Some("127.0.0.1:9000".to_string()),
+            "LNURL_CALLBACK_URL" => Some("https://env.example/".to_string()),
+            "LNURL_CALLBACK_FROM_REQUEST" => Some("0".to_string()),
+            "LNURL_NETWORK" => Some("Regtest".to_string()),
+            "LNURL_RPC_PATH" => Some(" ".to_string()), // blank, left unset
+            "LNURL_MAX_WITHDRAWABLE_MSAT" => Some("2000000".to_string()),
+            _ => None,
+        };
+        let env = Settings::from_vars(vars).unwrap();
+        let file = parse(
+            r#"
+            callback_url = "https://file.example/"
+            rpc_path = "/run/cln/lightning-rpc"
+            [limits]
+            max_withdrawable_msat = 3000000
+            channel_capacity_sat = 50000
+            "#,
+        )
+        .unwrap();
+        let flags = Settings {
+            listen_addr: Some("0.0.0.0:8080".parse().unwrap()),
+            ..Settings::default()
+        };
+        let config = Config::from_settings(flags.or(env).or(file)).unwrap();
+        assert_eq!(config.listen_addr, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(config.callback_url, "https://env.example/");
+        assert!(!config.callback_from_request);
+        assert_eq!(config.network, Network::Regtest);
+        assert_eq!(config.rpc_path, PathBuf::from("/run/cln/lightning-rpc"));
+        assert_eq!(config.limits.max_withdrawable_msat, 2_000_000);
+        assert_eq!(config.limits.channel_capacity_sat, 50_000);
+
+        for (name, value) in [
+            ("LNURL_LISTEN_ADDR", "localhost"),
+            ("LNURL_NETWORK", "mainnet"),
+            ("LNURL_CHANNEL_CAPACITY_SAT", "100k"),
+        ] {
+            let vars = |var: &str| (var == name).then(|| value.to_string());
+            assert!(Settings::from_vars(vars).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn tls_takes_files_or_acme() {
+        let config = Config::from_settings(parse("").unwrap()).unwrap();
+        assert_eq!(config.tls, None);
+
+        let files = parse("[tls]\ncert = \"/etc/lnurl/cert.pem\"\nkey = \"/etc/lnurl/key.pem\"");
+        let config = Config::from_settings(files.unwrap()).unwrap();
+        assert_eq!(
+            config.tls,
+            Some(Tls::Files {
+                cert: PathBuf::from("/etc/lnurl/cert.pem"),
+                key: PathBuf::from("/etc/lnurl/key.pem"),
+            })
+        );
+
+        let refused = [
+            "[tls]\ncert = \"/etc/lnurl/cert.pem\"",
+            "[tls]\ncert = \"c.pem\"\nkey = \"k.pem\"\nacme_domains = [\"lnurl.example\"]",
+            "[tls]\nacme_email = \"ops@lnurl.example\"",
+        ];
+        for raw in refused {
+            assert!(
+                Config::from_settings(parse(raw).unwrap()).is_err(),
+                "{}",
+                raw
+            );
+        }
+
+        let acme =
+            Config::from_settings(parse("[tls]\nacme_domains = [\"lnurl.example\"]").unwrap());
+        match cfg!(feature = "acme") {
+            true => {
+                let Some(Tls::Acme(acme)) = acme.unwrap().tls else {
+                    panic!("expected ACME");
+                };
+                assert_eq!(acme.domains, ["lnurl.example"]);
+                assert_eq!(acme.directory, DEFAULT_ACME_DIRECTORY);
+                assert_eq!(acme.http_addr, "0.0.0.0:80".parse().unwrap());
+            }
+            false => assert!(acme.unwrap_err().to_string().contains("--features acme")),
+        }
+        let wildcard = parse("[tls]\nacme_domains = [\"*.lnurl.example\"]").unwrap();
+        assert!(Config::from_settings(wildcard).is_err());
+    }
+
+    #[test]
+    fn inconsistent_settings_are_refused() {
+        let limits = parse("[limits]\nmin_withdrawable_msat = 2000\nmax_withdrawable_msat = 1000");
+        assert!(Config::from_settings(limits.unwrap()).is_err());
+        for addr in ["203.0.113.7", "203.0.113.7:port", ":9735"] {
+            let settings = Settings {
+                announce_addr: Some(addr.to_string()),
+                ..Settings::default()
+            };
+            assert!(Config::from_settings(settings).is_err(), "{}", addr);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// =============================================================================
+// Encryption at rest
+// =============================================================================
+//
+// Sensitive columns (payment preimages today) are encrypted before they reach
+// storage, so a leaked database alone does not reveal them. Storage backends
+// only ever see the opaque `v1:<hex nonce||ciphertext>` string.
+//
+// The default `LocalKeyCipher` uses XChaCha20-Poly1305 with a 32-byte key from
+// LNURL_ENCRYPTION_KEY (64 hex chars). Deployments using a KMS implement
+// `FieldCipher` themselves and plug it into `AppState::cipher`.
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use std::fmt;
+
+const VERSION_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug)]
+pub struct CryptoError(String);
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Encrypts/decrypts individual column values (KMS hook)
+pub trait FieldCipher: Send + Sync {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<String, CryptoError>;
+    fn decrypt(&self, sealed: &str) -> Result<Vec<u8>, CryptoError>;
+}
+
+pub struct LocalKeyCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl LocalKeyCipher {
+    pub fn from_hex_key(key_hex: &str) -> Result<LocalKeyCipher, CryptoError> {
+        let key = hex::decode(key_hex.trim())
+            .map_err(|e| CryptoError(format!("Invalid encryption key: {}", e)))?;
+        if key.len() != 32 {
+            return Err(CryptoError(format!(
+                "Encryption key must be 32 bytes, got {}",
+                key.len()
+            )));
+        }
+        let cipher = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|e| CryptoError(format!("Invalid encryption key: {}", e)))?;
+        Ok(LocalKeyCipher { cipher })
+    }
+}
+
+impl FieldCipher for LocalKeyCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<String, CryptoError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CryptoError("Encryption failed".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", VERSION_PREFIX, hex::encode(sealed)))
+    }
+
+    fn decrypt(&self, sealed: &str) -> Result<Vec<u8>, CryptoError> {
+        let sealed = sealed
+            .strip_prefix(VERSION_PREFIX)
+            .ok_or_else(|| CryptoError("Unknown ciphertext version".to_string()))?;
+        let bytes =
+            hex::decode(sealed).map_err(|e| CryptoError(format!("Malformed ciphertext: {}", e)))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(CryptoError("Ciphertext too short".to_string()));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError("Decryption failed (wrong key?)".to_string()))
+    }
+}
+
+/// Reads LNURL_ENCRYPTION_KEY; without it sensitive values are not persisted at all
+pub fn load_cipher() -> Result<Option<LocalKeyCipher>, CryptoError> {
+    match std::env::var("LNURL_ENCRYPTION_KEY") {
+        Ok(key) => LocalKeyCipher::from_hex_key(&key).map(Some),
+        Err(_) => Ok(None),
+    }
+}
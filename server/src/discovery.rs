@@ -0,0 +1,246 @@
+// =============================================================================
+// Discovery documents
+// =============================================================================
+//
+// The payRequest of /request-pay and those of the Lightning Addresses are the
+// same for every wallet that fetches them. They only change with the pay
+// config, fixed at startup, and with the base URL their callbacks are built
+// on, which with callback_from_request (callback.rs) is each hostname's own:
+// every hostname the server answers on is a tenant with documents of its own.
+// Each document is built and serialized once per base URL and user, kept in
+// memory, and served with
+//
+//   ETag           a hash of the body; a wallet or CDN that sends it back in
+//                  If-None-Match gets 304 Not Modified, without the body
+//   Cache-Control  public, max-age=MAX_AGE_SECS, so that CDNs in front answer
+//                  for the server in the meantime
+//   Vary           the forwarded headers the base URL comes from, with
+//                  callback_from_request
+//
+// Base URLs come from the request's headers, so anyone can have documents
+// built for hosts of their making. At most MAX_DOCUMENTS are kept, and room
+// for a new one is made by dropping the least recently served of those that
+// were never served again after being built, or when all were, the least
+// recently served of all. A flood of made-up hosts then mostly evicts itself,
+// while the documents wallets keep fetching stay.
+// A document that hands out a k1 (a disposable /request-pay) is never cached:
+// it goes out with Cache-Control: no-store.
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use lnurl_models::LnurlParams;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{error_reply, AppState, ErrorReply};
+
+/// How long wallets and CDNs may keep a document without asking again
+pub const MAX_AGE_SECS: u64 = 300;
+/// Documents kept at most, across base URLs and users
+const MAX_DOCUMENTS: usize = 1_000;
+/// The headers a base URL is built from, see callback.rs
+const VARY: &str = "Host, X-Forwarded-Host, X-Forwarded-Proto, X-Forwarded-Prefix";
+
+#[derive(Debug, Clone)]
+struct Document {
+    body: Bytes,
+    etag: HeaderValue,
+}
+
+impl Document {
+    fn new(params: &LnurlParams) -> Result<Document, ErrorReply> {
+        let body = serde_json::to_vec(params).map_err(|e| {
+            error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Cannot serialize the document: {}", e),
+            )
+        })?;
+        let hash = Sha256::digest(&body);
+        let etag = format!("\"{}\"", hex::encode(&hash[..16]));
+        Ok(Document {
+            body: body.into(),
+            etag: HeaderValue::from_str(&etag).expect("hex is a valid header value"),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    document: Document,
+    last_served: u64, // on Documents' clock
+    served_again: bool,
+}
+
+#[derive(Debug, Default)]
+struct Documents {
+    entries: HashMap<(String, Option<String>), Entry>,
+    clock: u64, // one tick per document served
+}
+
+impl Documents {
+    /// Drops the entry to make room for a new one, see the top of this file
+    fn evict(&mut self) {
+        let victim = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| (entry.served_again, entry.last_served))
+            .map(|(key, _)| key.clone());
+        if let Some(key) = victim {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// Documents by base URL and user, None for /request-pay's
+#[derive(Debug, Default)]
+pub struct DocumentCache {
+    documents: Mutex<Documents>,
+}
+
+impl DocumentCache {
+    fn get_or_build(
+        &self,
+        base: &str,
+        user: Option<&str>,
+        build: impl FnOnce() -> Result<LnurlParams, ErrorReply>,
+    ) -> Result<Document, ErrorReply> {
+        let key = (base.to_string(), user.map(str::to_string));
+        {
+            let mut documents = self.documents.lock().unwrap();
+            documents.clock += 1;
+            let now = documents.clock;
+            if let Some(entry) = documents.entries.get_mut(&key) {
+                entry.last_served = now;
+                entry.served_again = true;
+                return Ok(entry.document.clone());
+            }
+        }
+        // Errors, such as an unknown user, are not kept
+        let document = Document::new(&build()?)?;
+        let mut documents = self.documents.lock().unwrap();
+        if !documents.entries.contains_key(&key) && documents.entries.len() >= MAX_DOCUMENTS {
+            documents.evict();
+        }
+        let entry = Entry {
+            document: document.clone(),
+            last_served: documents.clock,
+            served_again: false,
+        };
+        documents.entries.insert(key, entry);
+        Ok(document)
+    }
+}
+
+/// Whether If-None-Match names `etag`, or any
+fn not_modified(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        // If-None-Match compares weakly (RFC 9110)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The document of `user` on `base`, None for /request-pay's, from the cache
+/// or by `build`: 304 for a wallet that already holds it
+pub fn serve(
+    state: &AppState,
+    headers: &HeaderMap,
+    base: &str,
+    user: Option<&str>,
+    build: impl FnOnce() -> Result<LnurlParams, ErrorReply>,
+) -> Result<Response, ErrorReply> {
+    let document = state.documents.get_or_build(base, user, build)?;
+    let cache_control = format!("public, max-age={}", MAX_AGE_SECS);
+    let mut response = match not_modified(headers, &document.etag) {
+        true => StatusCode::NOT_MODIFIED.into_response(),
+        false => (
+            [(header::CONTENT_TYPE, "application/json")],
+            Body::from(document.body),
+        )
+            .into_response(),
+    };
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::ETAG, document.etag);
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&cache_control).expect("ASCII"),
+    );
+    if state.callback_from_request {
+        response_headers.insert(header::VARY, HeaderValue::from_static(VARY));
+    }
+    Ok(response)
+}
+
+/// A document made for this request alone, which no one is to keep
+pub fn uncached(params: LnurlParams) -> Response {
+    ([(header::CACHE_CONTROL, "no-store")], Json(params)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_lists_and_weak_tags() {
+        let etag = HeaderValue::from_static("\"abc\"");
+        let matching = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static(value));
+            not_modified(&headers, &etag)
+        };
+        assert!(matching("\"abc\""));
+        assert!(matching("\"xyz\", W/\"abc\""));
+        assert!(matching("*"));
+        assert!(!matching("\"abcd\""));
+        assert!(!matching("abc"));
+        assert!(!not_modified(&HeaderMap::new(), &etag));
+    }
+
+    fn pay_request(base: &str) -> Result<LnurlParams, ErrorReply> {
+        let params = serde_json::json!({
+            "tag": "payRequest",
+            "callback": format!("{}pay", base),
+            "metadata": "[]",
+            "minSendable": 1_000,
+            "maxSendable": 1_000_000,
+        });
+        Ok(serde_json::from_value(params).unwrap())
+    }
+
+    #[test]
+    fn made_up_hosts_do_not_push_out_the_ones_in_use() {
+        let cache = DocumentCache::default();
+        let legit = "https://shop.example/";
+        cache.get_or_build(legit, None, || pay_request(legit)).unwrap();
+        cache.get_or_build(legit, None, || pay_request(legit)).unwrap();
+
+        for i in 0..2 * MAX_DOCUMENTS {
+            let forged = format!("https://forged-{}.example/", i);
+            cache.get_or_build(&forged, None, || pay_request(&forged)).unwrap();
+        }
+        assert_eq!(cache.documents.lock().unwrap().entries.len(), MAX_DOCUMENTS);
+
+        // Still cached: served without building it again
+        let cached = cache.get_or_build(legit, None, || panic!("rebuilt {}", legit));
+        assert!(cached.unwrap().body.starts_with(b"{"));
+        // And a forged host made once is gone first
+        let first = "https://forged-0.example/";
+        let mut rebuilt = false;
+        cache
+            .get_or_build(first, None, || {
+                rebuilt = true;
+                pay_request(first)
+            })
+            .unwrap();
+        assert!(rebuilt);
+    }
+}
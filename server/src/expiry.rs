@@ -0,0 +1,92 @@
+// =============================================================================
+// k1 expiry
+// =============================================================================
+//
+// A k1 past its flow's TTL (K1Ttls, see storage/mod.rs) is refused when it is
+// presented, but one that never is would stay in storage for good: a wallet
+// that scanned a code and gave up, or someone filling the store. Every
+// SWEEP_EVERY a background job removes them, counting them as expired in
+// /admin/store and /metrics: each k1 issued ends up consumed or expired, or
+// is still held.
+//
+// Vouchers are not k1s in that sense: scanning one stores its k1 anew, so
+// sweeping the k1 takes nothing from the voucher, which keeps to its own
+// window. Nor is a sold channel's (pricing.rs): it lives as long as its
+// invoice may be paid, and once paid until the wallet has its channel, so
+// it goes only when the node reports the invoice expired, or gone. With
+// several replicas each one sweeps; that is harmless.
+//
+// Login sessions past their TTL (LNURL_SESSION_TTL_SECS) are refused the same
+// way, and removed by the same job.
+
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+use crate::backend::InvoiceStatus;
+use crate::pricing;
+use crate::storage::{K1Purpose, K1Status, StorageResult};
+use crate::AppState;
+
+pub const SWEEP_EVERY: Duration = Duration::from_secs(60);
+
+/// Removes the expired k1s, returning how many there were
+pub async fn sweep(state: &AppState) -> StorageResult<usize> {
+    let swept = state
+        .storage
+        .sweep_k1s(&state.k1_ttls, crate::unix_now())
+        .await?;
+    // Those stored before purposes were recorded count in no flow
+    for purpose in swept.iter().flatten() {
+        state.store_metrics.k1_expired(*purpose);
+    }
+
+    let mut unsold = 0;
+    for k1 in state.storage.sold_k1s().await? {
+        match state.backend.invoice_status(&pricing::invoice_label(&k1)).await {
+            Ok(Some(InvoiceStatus::Expired) | None) => {}
+            Ok(Some(InvoiceStatus::Unpaid | InvoiceStatus::Paid)) => continue,
+            // Kept until the node can tell
+            Err(e) => {
+                warn!(k1 = %k1, "Cannot look up a sold channel's invoice: {}", e);
+                continue;
+            }
+        }
+        let status = state
+            .storage
+            .consume_k1(&k1, K1Purpose::Channel, &state.k1_ttls)
+            .await?;
+        if status != K1Status::Unknown {
+            state.store_metrics.k1_expired(K1Purpose::Channel);
+            unsold += 1;
+        }
+    }
+    Ok(swept.len() + unsold)
+}
+
+/// Removes the sessions past their TTL, returning how many there were
+pub async fn sweep_sessions(state: &AppState) -> StorageResult<usize> {
+    state
+        .storage
+        .sweep_sessions(state.session_ttl_secs, crate::unix_now())
+        .await
+}
+
+/// Runs `sweep` and `sweep_sessions` every `every`, under the write gate (shared) as requests are,
+/// so that it never sweeps a snapshot being restored
+pub async fn run(state: AppState, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        let _writing = state.write_gate.read().await;
+        match sweep(&state).await {
+            Ok(0) => {}
+            Ok(swept) => debug!(swept, "Expired k1s removed"),
+            Err(e) => error!("Failed to remove expired k1s: {}", e),
+        }
+        match sweep_sessions(&state).await {
+            Ok(0) => {}
+            Ok(swept) => debug!(swept, "Expired sessions removed"),
+            Err(e) => error!("Failed to remove expired sessions: {}", e),
+        }
+    }
+}
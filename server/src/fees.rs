@@ -0,0 +1,151 @@
+// =============================================================================
+// Channel fee setup
+// =============================================================================
+//
+// LUD-02 channels open with the node's default fees. When any of
+//
+//   LNURL_CHANNEL_FEE_BASE_MSAT, LNURL_CHANNEL_FEE_PPM,
+//   LNURL_CHANNEL_HTLC_MIN_MSAT, LNURL_CHANNEL_HTLC_MAX_MSAT
+//
+// is set, a background job watches the channels opened through the flow and,
+// as soon as one reaches normal state, sets those on it with `setchannel`
+// (the ones left unset keep the node's defaults). What the channel ended up
+// with is recorded in the channel history, see GET /admin/channels.
+//
+// A channel still not in normal state two weeks after its open has most
+// likely been abandoned, and is no longer watched.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::backend::FeeUpdate;
+use crate::AppState;
+
+pub const CHECK_EVERY: Duration = Duration::from_secs(30);
+const GIVE_UP_AFTER: u64 = 14 * 24 * 60 * 60;
+
+#[derive(Debug)]
+pub struct FeeConfigError(String);
+
+impl fmt::Display for FeeConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FeeConfigError {}
+
+fn var<T: FromStr>(name: &str) -> Result<Option<T>, FeeConfigError> {
+    match std::env::var(name) {
+        Err(_) => Ok(None),
+        Ok(raw) => raw
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| FeeConfigError(format!("{} must be a whole number", name))),
+    }
+}
+
+/// Reads the LNURL_CHANNEL_* settings above, None when none is set
+pub fn load_fee_update() -> Result<Option<FeeUpdate>, FeeConfigError> {
+    let update = FeeUpdate {
+        base_msat: var("LNURL_CHANNEL_FEE_BASE_MSAT")?,
+        ppm: var("LNURL_CHANNEL_FEE_PPM")?,
+        htlc_min_msat: var("LNURL_CHANNEL_HTLC_MIN_MSAT")?,
+        htlc_max_msat: var("LNURL_CHANNEL_HTLC_MAX_MSAT")?,
+    };
+    if let (Some(min), Some(max)) = (update.htlc_min_msat, update.htlc_max_msat) {
+        if min > max {
+            return Err(FeeConfigError(
+                "LNURL_CHANNEL_HTLC_MIN_MSAT must not exceed LNURL_CHANNEL_HTLC_MAX_MSAT"
+                    .to_string(),
+            ));
+        }
+    }
+
+    if update == FeeUpdate::default() {
+        info!("No channel fee setup, opened channels keep the node's fees");
+        return Ok(None);
+    }
+    info!("Channel fee setup: {:?}", update);
+    Ok(Some(update))
+}
+
+/// Sets `update` on every watched channel that reached normal state,
+/// returning how many it set up
+pub async fn apply(state: &AppState, update: &FeeUpdate) -> usize {
+    let now = crate::unix_now();
+    let waiting: Vec<_> = match state.storage.list_channels(1000).await {
+        Ok(channels) => channels
+            .into_iter()
+            .filter(|c| c.fees_set_at.is_none() && now < c.opened_at + GIVE_UP_AFTER)
+            .collect(),
+        Err(e) => {
+            error!("Failed to list channels for fee setup: {}", e);
+            return 0;
+        }
+    };
+    if waiting.is_empty() {
+        return 0;
+    }
+
+    let funds = match state.backend.funds().await {
+        Ok(funds) => funds,
+        Err(e) => {
+            error!("Failed to list channels for fee setup: {}", e);
+            return 0;
+        }
+    };
+
+    let mut set_up = 0;
+    for channel in waiting {
+        let normal = funds.channels.iter().any(|balance| {
+            balance.channel_id.as_deref() == Some(channel.channel_id.as_str())
+                && balance.is_usable()
+        });
+        if !normal {
+            continue;
+        }
+        let fees = match state
+            .backend
+            .set_channel_fees(&channel.channel_id, update)
+            .await
+        {
+            Ok(fees) => fees,
+            Err(e) => {
+                error!(
+                    "Failed to set fees on channel {}: {}",
+                    channel.channel_id, e
+                );
+                continue;
+            }
+        };
+        if let Err(e) = state
+            .storage
+            .set_channel_fees(&channel.channel_id, &fees)
+            .await
+        {
+            warn!(
+                "Fees set on channel {} but not recorded: {}",
+                channel.channel_id, e
+            );
+            continue;
+        }
+        info!("Channel {} set up with {:?}", channel.channel_id, fees);
+        set_up += 1;
+    }
+    set_up
+}
+
+/// Runs `apply` every `every`, under the write gate (shared) as requests are,
+/// so that a backup or restore never sees fees set but not recorded
+pub async fn run(state: AppState, update: FeeUpdate, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        let _writing = state.write_gate.read().await;
+        apply(&state, &update).await;
+    }
+}
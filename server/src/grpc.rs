@@ -0,0 +1,318 @@
+// =============================================================================
+// gRPC admin service
+// =============================================================================
+//
+// lnurl.admin.v1.Admin (proto/admin.proto), for operators whose tooling
+// speaks gRPC: voucher issuing, withdrawal status and stats, the same
+// operations as the REST admin API and through the same service layer
+// (service.rs). Built with the `grpc` feature; the binary serves it on
+// LNURL_GRPC_ADDR when that is set.
+//
+// The messages are written out below rather than generated, as the client's
+// LND wallet does. Keys and roles are the REST API's: `x-api-key` metadata,
+// read-only keys for reads, admin keys for IssueVoucher. So is the address
+// allowlist, LNURL_ADMIN_ALLOW_CIDRS; the service has no TLS of its own.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::server::{NamedService, UnaryService};
+use tonic::transport::server::TcpConnectInfo;
+use tonic::{Request, Response, Status};
+
+use crate::admin::{self, Role};
+use crate::service::{self, ServiceError};
+use crate::storage::ValidityWindow;
+use crate::AppState;
+
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatsRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatsReply {
+        #[prost(uint64, tag = "1")]
+        pub accounts: u64,
+        #[prost(uint64, tag = "2")]
+        pub sessions: u64,
+        #[prost(uint64, tag = "3")]
+        pub pending_k1s: u64,
+        #[prost(uint64, tag = "4")]
+        pub vouchers: u64,
+        #[prost(uint64, tag = "5")]
+        pub outstanding_budget_msat: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IssueVoucherRequest {
+        #[prost(string, tag = "1")]
+        pub linking_key: String,
+        #[prost(uint64, optional, tag = "2")]
+        pub valid_from: Option<u64>,
+        #[prost(uint64, optional, tag = "3")]
+        pub valid_until: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IssueVoucherReply {
+        #[prost(string, tag = "1")]
+        pub k1: String,
+        #[prost(string, tag = "2")]
+        pub linking_key: String,
+        #[prost(string, tag = "3")]
+        pub url: String,
+        #[prost(uint64, optional, tag = "4")]
+        pub valid_from: Option<u64>,
+        #[prost(uint64, optional, tag = "5")]
+        pub valid_until: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WithdrawStatusRequest {
+        #[prost(string, tag = "1")]
+        pub k1: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WithdrawStatusReply {
+        #[prost(string, tag = "1")]
+        pub k1: String,
+        #[prost(string, optional, tag = "2")]
+        pub linking_key: Option<String>,
+        #[prost(string, tag = "3")]
+        pub bolt11: String,
+        #[prost(uint64, tag = "4")]
+        pub amount_msat: u64,
+        #[prost(string, tag = "5")]
+        pub status: String, // pending, paid or failed
+        #[prost(uint64, tag = "6")]
+        pub created_at: u64,
+        #[prost(string, optional, tag = "7")]
+        pub preimage: Option<String>, // hex, admin keys only
+        #[prost(uint64, optional, tag = "8")]
+        pub fee_msat: Option<u64>, // once paid
+        #[prost(string, optional, tag = "9")]
+        pub description: Option<String>, // the invoice's, cleaned
+    }
+}
+
+pub const SERVICE_NAME: &str = "lnurl.admin.v1.Admin";
+
+/// Serves the admin service on `addr` until the process exits
+pub async fn serve(state: AppState, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(AdminService::new(state))
+        .serve(addr)
+        .await
+}
+
+// -----------------------------------------------------------------------------
+// Authorization
+// -----------------------------------------------------------------------------
+
+/// Reads are open to read-only keys, IssueVoucher needs admin; None for
+/// methods the service does not have
+fn required_role(path: &str) -> Option<Role> {
+    match path {
+        "/lnurl.admin.v1.Admin/Stats" | "/lnurl.admin.v1.Admin/WithdrawStatus" => {
+            Some(Role::ReadOnly)
+        }
+        "/lnurl.admin.v1.Admin/IssueVoucher" => Some(Role::Admin),
+        _ => None,
+    }
+}
+
+/// The role of the request's API key, if it has a valid one
+fn key_role<B>(state: &AppState, request: &http::Request<B>) -> Option<Role> {
+    request
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|key| state.admin_keys.get(key).copied())
+}
+
+fn refuse(status: Status) -> BoxFuture<http::Response<BoxBody>, Infallible> {
+    Box::pin(async { Ok(status.into_http()) })
+}
+
+fn service_error(e: ServiceError) -> Status {
+    match e {
+        ServiceError::NotFound(_) => Status::not_found(e.to_string()),
+        ServiceError::Invalid(_) => Status::invalid_argument(e.to_string()),
+        ServiceError::Storage(_) => Status::internal(e.to_string()),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Methods
+// -----------------------------------------------------------------------------
+
+async fn stats(
+    state: AppState,
+    _request: Request<proto::StatsRequest>,
+) -> Result<Response<proto::StatsReply>, Status> {
+    let _writing = state.write_gate.read().await;
+
+    let stats = service::stats(&state).await.map_err(service_error)?;
+    Ok(Response::new(proto::StatsReply {
+        accounts: stats.accounts as u64,
+        sessions: stats.sessions as u64,
+        pending_k1s: stats.pending_k1s as u64,
+        vouchers: stats.vouchers as u64,
+        outstanding_budget_msat: stats.outstanding_budget_msat,
+    }))
+}
+
+async fn issue_voucher(
+    state: AppState,
+    request: Request<proto::IssueVoucherRequest>,
+) -> Result<Response<proto::IssueVoucherReply>, Status> {
+    let _writing = state.write_gate.read().await;
+
+    let request = request.get_ref();
+    let window = ValidityWindow {
+        valid_from: request.valid_from,
+        valid_until: request.valid_until,
+    };
+    let voucher = service::issue_voucher(&state, &request.linking_key, window)
+        .await
+        .map_err(service_error)?;
+    Ok(Response::new(proto::IssueVoucherReply {
+        k1: voucher.k1,
+        linking_key: voucher.linking_key,
+        url: voucher.url,
+        valid_from: voucher.window.valid_from,
+        valid_until: voucher.window.valid_until,
+    }))
+}
+
+async fn withdraw_status(
+    state: AppState,
+    role: Role,
+    request: Request<proto::WithdrawStatusRequest>,
+) -> Result<Response<proto::WithdrawStatusReply>, Status> {
+    let _writing = state.write_gate.read().await;
+
+    let withdrawal = service::withdrawal(&state, role, &request.get_ref().k1)
+        .await
+        .map_err(service_error)?;
+    Ok(Response::new(proto::WithdrawStatusReply {
+        k1: withdrawal.k1,
+        linking_key: withdrawal.linking_key,
+        bolt11: withdrawal.bolt11,
+        amount_msat: withdrawal.amount_msat,
+        status: withdrawal.status.as_str().to_string(),
+        created_at: withdrawal.created_at,
+        preimage: withdrawal.preimage,
+        fee_msat: withdrawal.fee_msat,
+        description: withdrawal.description,
+    }))
+}
+
+// -----------------------------------------------------------------------------
+// Routing
+// -----------------------------------------------------------------------------
+
+/// The service, to add to a tonic server of one's own or run with serve()
+#[derive(Clone)]
+pub struct AdminService {
+    state: AppState,
+}
+
+impl AdminService {
+    pub fn new(state: AppState) -> Self {
+        AdminService { state }
+    }
+}
+
+impl NamedService for AdminService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+/// Adapts a method above to tonic's unary handling
+struct Unary<F>(F);
+
+impl<Req, Resp, F, Fut> UnaryService<Req> for Unary<F>
+where
+    F: FnMut(Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Response<Resp>, Status>> + Send + 'static,
+{
+    type Response = Resp;
+    type Future = BoxFuture<Response<Resp>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        Box::pin((self.0)(request))
+    }
+}
+
+fn unary<B, Req, Resp, F, Fut>(
+    request: http::Request<B>,
+    method: F,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    F: FnMut(Request<Req>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Response<Resp>, Status>> + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = tonic::server::Grpc::new(ProstCodec::<Resp, Req>::default());
+        Ok(grpc.unary(Unary(method), request).await)
+    })
+}
+
+impl<B> Service<http::Request<B>> for AdminService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let state = self.state.clone();
+        // Checks the address and the API key against the method, as admin.rs
+        // does for REST
+        let peer = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr);
+        if !admin::peer_allowed(&state, peer.map(|addr| addr.ip())) {
+            return refuse(Status::permission_denied("Address not allowed"));
+        }
+        let Some(required) = required_role(request.uri().path()) else {
+            return refuse(Status::unimplemented("Unknown method"));
+        };
+        let role = match key_role(&state, &request) {
+            None => return refuse(Status::unauthenticated("Missing or invalid API key")),
+            Some(role) if role < required => {
+                return refuse(Status::permission_denied(
+                    "API key is not allowed to perform this operation",
+                ))
+            }
+            Some(role) => role,
+        };
+        match request.uri().path() {
+            "/lnurl.admin.v1.Admin/Stats" => {
+                unary(request, move |request| stats(state.clone(), request))
+            }
+            "/lnurl.admin.v1.Admin/IssueVoucher" => unary(request, move |request| {
+                issue_voucher(state.clone(), request)
+            }),
+            "/lnurl.admin.v1.Admin/WithdrawStatus" => unary(request, move |request| {
+                withdraw_status(state.clone(), role, request)
+            }),
+            _ => refuse(Status::unimplemented("Unknown method")),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// =============================================================================
+// Health probes
+// =============================================================================
+//
+// For orchestrators and load balancers, on the public listener:
+//
+//   GET /healthz  the process is up and serving; never asks the node
+//   GET /readyz   the service can do its job: CLN answers its RPC, has
+//                 caught up with bitcoind and the network, and its tip moved
+//                 within LNURL_READY_MAX_BLOCK_AGE_SECS
+//
+// Both answer 200 {"status":"OK"}, or 503 with a reason. A failing /readyz
+// takes the replica out of rotation until the node is back, while /healthz
+// keeps it from being restarted for the node's fault.
+//
+// getinfo gives the node's height but not when that block came, so the tip's
+// age is how long this process has seen it unchanged. Blocks come every ten
+// minutes on average and two hours without one is rare, hence the default of
+// 7200s; 0 turns the check off, as by default on regtest, where blocks only
+// come when mined. The probe asks the node over a connection of its own, so
+// it doesn't queue behind a payment, and gives up after PROBE_TIMEOUT.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use lnurl_models::StatusResponse;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::Network;
+use crate::AppState;
+
+pub const DEFAULT_MAX_BLOCK_AGE: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// How long /readyz waits for the node
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What /readyz remembers between probes
+#[derive(Debug)]
+pub struct Readiness {
+    max_block_age: Option<Duration>, // None: the tip's age isn't checked
+    tip: Mutex<Option<(u32, Instant)>>, // height, and when it was first seen
+}
+
+impl Readiness {
+    pub fn new(max_block_age: Option<Duration>) -> Readiness {
+        Readiness {
+            max_block_age,
+            tip: Mutex::new(None),
+        }
+    }
+
+    /// How long the tip has been at `height`, as of `now`
+    fn tip_age(&self, height: u32, now: Instant) -> Duration {
+        let mut tip = self.tip.lock().unwrap();
+        match *tip {
+            Some((seen, since)) if seen == height => now.saturating_duration_since(since),
+            _ => {
+                *tip = Some((height, now));
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Readiness {
+        Readiness::new(Some(DEFAULT_MAX_BLOCK_AGE))
+    }
+}
+
+/// Reads LNURL_READY_MAX_BLOCK_AGE_SECS, None when the tip's age isn't
+/// checked, as by default on regtest
+pub fn load_max_block_age(network: Network) -> Option<Duration> {
+    let default = match network {
+        Network::Regtest => Duration::ZERO,
+        _ => DEFAULT_MAX_BLOCK_AGE,
+    };
+    let max_age = match std::env::var("LNURL_READY_MAX_BLOCK_AGE_SECS") {
+        Err(_) => default,
+        Ok(raw) => match raw.trim().parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                warn!("Ignoring malformed LNURL_READY_MAX_BLOCK_AGE_SECS (expected a number)");
+                default
+            }
+        },
+    };
+
+    if max_age.is_zero() {
+        info!("Readiness doesn't check the chain tip's age");
+        return None;
+    }
+    Some(max_age)
+}
+
+fn not_ready(reason: String) -> (StatusCode, Json<StatusResponse>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(StatusResponse::error(reason)),
+    )
+}
+
+// GET /healthz
+pub async fn healthz() -> Json<StatusResponse> {
+    Json(StatusResponse::ok())
+}
+
+// GET /readyz
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<StatusResponse>) {
+    let status = match tokio::time::timeout(PROBE_TIMEOUT, state.backend.node_status()).await {
+        Ok(Ok(status)) => status,
+        Ok(Err(e)) => return not_ready(format!("Node unreachable: {}", e)),
+        Err(_) => {
+            let secs = PROBE_TIMEOUT.as_secs();
+            return not_ready(format!("Node unreachable: no answer in {}s", secs));
+        }
+    };
+    if let Some(warning) = status.sync_warning {
+        return not_ready(format!("Node not synced: {}", warning));
+    }
+    let readiness = &state.readiness;
+    let age = readiness.tip_age(status.blockheight, Instant::now());
+    match readiness.max_block_age {
+        Some(max_age) if age > max_age => not_ready(format!(
+            "No new block for {}s, the node's tip is still {}",
+            age.as_secs(),
+            status.blockheight
+        )),
+        _ => (StatusCode::OK, Json(StatusResponse::ok())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tip_ages_until_the_height_moves() {
+        let readiness = Readiness::default();
+        let start = Instant::now();
+        let later = |secs| start + Duration::from_secs(secs);
+        assert_eq!(readiness.tip_age(800_000, start), Duration::ZERO);
+        assert_eq!(
+            readiness.tip_age(800_000, later(600)),
+            Duration::from_secs(600)
+        );
+        assert_eq!(readiness.tip_age(800_001, later(900)), Duration::ZERO);
+        assert_eq!(
+            readiness.tip_age(800_001, later(1000)),
+            Duration::from_secs(100)
+        );
+        // A reorg to a lower height is a new tip too
+        assert_eq!(readiness.tip_age(800_000, later(1100)), Duration::ZERO);
+    }
+}
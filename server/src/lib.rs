@@ -0,0 +1,1851 @@
+// =============================================================================
+// lnurl-server
+// =============================================================================
+//
+// The LNURL service behind the lnurl-server binary, for other services to
+// mount in their own axum app. app() builds the router from an AppState:
+//
+//   backend  — the Lightning node, backend::Backend (CLN, or a mock)
+//   storage  — accounts, k1s and withdrawals, storage::Storage
+//   policy   — business rules for withdraws, channels and logins, policy.rs
+//
+// AppState::new takes the two that have no sensible default; the rest are
+// set with the with_* methods.
+
+use axum::{
+    middleware,
+    routing::{delete, get, post},
+    http::{header, HeaderMap, StatusCode},
+    Json, Router,
+    extract::{ConnectInfo, FromRequestParts, Query, State},
+    http::request::Parts,
+};
+use lnurl_models::{
+    AuthChallenge, AuthResponse, ChannelRequest, LnurlParams, LoginEvent, OpenChannelResponse,
+    StatusResponse, WithdrawRequest, WithdrawStatusResponse,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error, info, instrument, warn, Instrument};
+use rand::RngCore;
+
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod admin;
+pub mod allowance;
+pub mod backend;
+pub mod callback;
+pub mod capture;
+pub mod check;
+pub mod config;
+pub mod crypto;
+pub mod discovery;
+pub mod expiry;
+pub mod fees;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod liquidity;
+pub mod listener;
+pub mod lnurl;
+pub mod logging;
+pub mod lsps1;
+pub mod metrics;
+pub mod notify;
+pub mod pay;
+pub mod policy;
+pub mod pricing;
+pub mod screen;
+pub mod service;
+pub mod storage;
+pub mod text;
+pub mod throttle;
+pub mod webhook;
+#[cfg(test)]
+mod tests;
+
+use backend::{Backend, BackendResult, FundedChannel, InvoiceStatus};
+use callback::BaseUrl;
+use crypto::FieldCipher;
+use discovery::DocumentCache;
+use health::Readiness;
+use policy::{
+    AuthHandler, ChannelOpen, ChannelPolicy, DefaultPolicy, RequestScreener, Screened,
+    WithdrawPolicy,
+};
+use metrics::{AuthOutcome, FlowMetrics, StoreMetrics};
+use notify::{Notification, NotificationKind, Notifications};
+use pay::PayConfig;
+use pricing::ChannelPricing;
+use screen::Cidr;
+use throttle::{AccountThrottle, RateLimit};
+use webhook::AuthWebhook;
+use storage::{
+    Account, Channel, K1Purpose, K1Status, K1Ttls, Storage, StorageResult, Voucher, WindowStatus,
+    Withdrawal, WithdrawalStatus,
+};
+
+type SharedBackend = Arc<dyn Backend>;
+type SharedStorage = Arc<dyn Storage>;
+type SharedLimits = Arc<Mutex<Limits>>;
+
+#[derive(Clone)]
+pub struct AppState {
+    backend: SharedBackend,
+    storage: SharedStorage,
+    limits: SharedLimits,
+    admin_keys: Arc<HashMap<String, admin::Role>>,
+    admin_allow: Arc<[Cidr]>, // empty: any address
+    cipher: Option<Arc<dyn FieldCipher>>, // None: sensitive values are not persisted
+    write_gate: Arc<RwLock<()>>,          // shared by writers, exclusive during backup/restore
+    callback_url: Arc<str>,               // where app() is reachable, with a trailing slash
+    callback_from_request: bool,          // or where each request says, see callback.rs
+    store_metrics: Arc<StoreMetrics>,
+    flow_metrics: Arc<FlowMetrics>,
+    account_throttle: Arc<AccountThrottle>,
+    k1_ttls: K1Ttls,
+    session_ttl_secs: u64,
+    readiness: Arc<Readiness>,
+    notifications: Arc<Notifications>,
+    auth_webhook: Option<Arc<AuthWebhook>>, // None: logins are not posted anywhere
+    channel_pricing: Option<ChannelPricing>, // None: channels are free
+    withdraw_description: Arc<str>,          // LUD-03 defaultDescription
+    pay: Arc<PayConfig>,                     // LUD-06 amounts and metadata
+    documents: Arc<DocumentCache>,           // the payRequests built on it, see discovery.rs
+    // Business rules, see policy.rs
+    withdraw_policy: Arc<dyn WithdrawPolicy>,
+    channel_policy: Arc<dyn ChannelPolicy>,
+    auth_handler: Arc<dyn AuthHandler>,
+    request_screener: Arc<dyn RequestScreener>,
+}
+
+impl AppState {
+    /// In-process state for app(): default limits and policies, no admin keys
+    /// and no encryption at rest
+    pub fn new(backend: Arc<dyn Backend>, storage: Arc<dyn Storage>) -> AppState {
+        AppState {
+            backend,
+            storage,
+            limits: Arc::new(Mutex::new(Limits::default())),
+            admin_keys: Arc::new(HashMap::new()),
+            admin_allow: Arc::new([]),
+            cipher: None,
+            write_gate: Arc::new(RwLock::new(())),
+            callback_url: CALLBACK_URL.into(),
+            callback_from_request: false,
+            store_metrics: Arc::new(StoreMetrics::default()),
+            flow_metrics: Arc::new(FlowMetrics::default()),
+            account_throttle: Arc::new(AccountThrottle::new(RateLimit::default())),
+            k1_ttls: K1Ttls::default(),
+            session_ttl_secs: storage::DEFAULT_SESSION_TTL_SECS,
+            readiness: Arc::new(Readiness::default()),
+            notifications: Arc::new(Notifications::default()),
+            auth_webhook: None,
+            channel_pricing: None,
+            withdraw_description: DEFAULT_DESCRIPTION.into(),
+            pay: Arc::new(PayConfig::default()),
+            documents: Arc::new(DocumentCache::default()),
+            withdraw_policy: Arc::new(DefaultPolicy),
+            channel_policy: Arc::new(DefaultPolicy),
+            auth_handler: Arc::new(DefaultPolicy),
+            request_screener: Arc::new(DefaultPolicy),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> AppState {
+        self.limits = Arc::new(Mutex::new(limits));
+        self
+    }
+
+    /// The public URL the router is mounted at, which callbacks are built
+    /// from (e.g. `https://shop.example/lnurl` when nested under /lnurl).
+    /// Wallets expect https, see callback::check_callback_url
+    pub fn with_callback_url(mut self, url: &str) -> AppState {
+        self.callback_url = format!("{}/", url.trim_end_matches('/')).into();
+        self
+    }
+
+    /// Builds each request's callbacks from its Host and X-Forwarded-*
+    /// headers, falling back to the callback URL, see callback.rs
+    pub fn with_callback_from_request(mut self, enabled: bool) -> AppState {
+        self.callback_from_request = enabled;
+        self
+    }
+
+    /// How often each account may ask for withdraws or its balance, see
+    /// throttle.rs
+    pub fn with_account_rate_limit(mut self, limit: RateLimit) -> AppState {
+        self.account_throttle = Arc::new(AccountThrottle::new(limit));
+        self
+    }
+
+    /// How long k1s of each flow stay valid, see storage::load_k1_ttls
+    pub fn with_k1_ttls(mut self, ttls: K1Ttls) -> AppState {
+        self.k1_ttls = ttls;
+        self
+    }
+
+    /// How long a login session lasts, see storage::load_session_ttl_secs
+    pub fn with_session_ttl_secs(mut self, ttl_secs: u64) -> AppState {
+        self.session_ttl_secs = ttl_secs;
+        self
+    }
+
+    /// How long the chain tip may stay put before /readyz fails, None to not
+    /// check it, see health.rs
+    pub fn with_max_block_age(mut self, max_age: Option<Duration>) -> AppState {
+        self.readiness = Arc::new(Readiness::new(max_age));
+        self
+    }
+
+    /// Where operators are alerted, see notify.rs
+    pub fn with_notifications(mut self, notifications: Notifications) -> AppState {
+        self.notifications = Arc::new(notifications);
+        self
+    }
+
+    /// Where successful logins are posted, see webhook.rs
+    pub fn with_auth_webhook(mut self, webhook: AuthWebhook) -> AppState {
+        self.auth_webhook = Some(Arc::new(webhook));
+        self
+    }
+
+    /// API keys for /admin, see admin::load_keys
+    pub fn with_admin_keys(mut self, keys: HashMap<String, admin::Role>) -> AppState {
+        self.admin_keys = Arc::new(keys);
+        self
+    }
+
+    /// The only networks the admin API answers, see admin::load_allowlist
+    pub fn with_admin_allowlist(mut self, networks: Vec<Cidr>) -> AppState {
+        self.admin_allow = networks.into();
+        self
+    }
+
+    /// Encrypts payment preimages at rest; without it they are not stored
+    pub fn with_cipher(mut self, cipher: Arc<dyn FieldCipher>) -> AppState {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Sells channels instead of giving them away, see pricing.rs
+    pub fn with_channel_pricing(mut self, pricing: ChannelPricing) -> AppState {
+        self.channel_pricing = Some(pricing);
+        self
+    }
+
+    /// The description wallets suggest for withdraw invoices, checked by
+    /// text::check_description when read from the environment
+    pub fn with_withdraw_description(mut self, description: &str) -> AppState {
+        self.withdraw_description = description.into();
+        self
+    }
+
+    /// What /request-pay takes and describes, see pay::load_pay_config
+    pub fn with_pay_config(mut self, config: PayConfig) -> AppState {
+        self.pay = Arc::new(config);
+        self.documents = Arc::new(DocumentCache::default());
+        self
+    }
+
+    pub fn with_withdraw_policy(mut self, policy: Arc<dyn WithdrawPolicy>) -> AppState {
+        self.withdraw_policy = policy;
+        self
+    }
+
+    pub fn with_channel_policy(mut self, policy: Arc<dyn ChannelPolicy>) -> AppState {
+        self.channel_policy = policy;
+        self
+    }
+
+    pub fn with_auth_handler(mut self, handler: Arc<dyn AuthHandler>) -> AppState {
+        self.auth_handler = handler;
+        self
+    }
+
+    /// Who gets k1s and channels by network, e.g. screen::CidrScreener. The
+    /// peer address is only known when the app is served with connect info
+    /// (`into_make_service_with_connect_info::<SocketAddr>()`).
+    pub fn with_request_screener(mut self, screener: Arc<dyn RequestScreener>) -> AppState {
+        self.request_screener = screener;
+        self
+    }
+
+    /// Funds the approved `open` to `node_id` (already connected), then
+    /// records the channel and tells the channel policy
+    async fn fund_channel(
+        &self,
+        node_id: cln_rpc::primitives::PublicKey,
+        open: &ChannelOpen,
+    ) -> BackendResult<FundedChannel> {
+        info!(
+            capacity_sat = open.capacity_sat,
+            private = open.private,
+            node_id = %open.node_id,
+            "Opening a channel"
+        );
+        let funded = self
+            .backend
+            .fund_channel(node_id, open.capacity_sat, !open.private)
+            .await?;
+        self.flow_metrics.channel_opened();
+
+        let channel = Channel {
+            channel_id: funded.channel_id.clone(),
+            node_id: open.node_id.clone(),
+            capacity_sat: open.capacity_sat,
+            private: open.private,
+            txid: funded.txid.clone(),
+            opened_at: unix_now(),
+            fees: None,
+            fees_set_at: None,
+        };
+        // The channel is funded either way; a missing record only costs its
+        // fee setup (fees.rs) and history
+        if let Err(e) = self.storage.insert_channel(&channel).await {
+            error!(channel_id = %channel.channel_id, "Failed to record channel: {}", e);
+        }
+        self.channel_policy
+            .on_opened(open, &funded.channel_id, &funded.txid)
+            .await;
+        Ok(funded)
+    }
+
+    /// Stores a k1, counting it as issued in the store metrics unless it
+    /// was already held: a voucher's is stored anew each time it is scanned
+    async fn issue_k1(&self, k1: &str, purpose: K1Purpose) -> StorageResult<()> {
+        if self.storage.insert_k1(k1, purpose).await? {
+            self.store_metrics.k1_issued(purpose);
+        }
+        Ok(())
+    }
+
+    /// Stores a k1 consumed at the last step again as it was, see
+    /// Storage::restore_k1, counting it as issued anew
+    async fn restore_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        issued_at: u64,
+        capacity_sat: Option<u64>,
+    ) -> StorageResult<()> {
+        if self.storage.restore_k1(k1, purpose, issued_at, capacity_sat).await? {
+            self.store_metrics.k1_issued(purpose);
+        }
+        Ok(())
+    }
+
+    /// Spends a k1 presented to the `purpose` callback, returning whether it
+    /// was still valid
+    async fn consume_k1(&self, k1: &str, purpose: K1Purpose) -> StorageResult<K1Status> {
+        let status = self.storage.consume_k1(k1, purpose, &self.k1_ttls).await?;
+        match status {
+            K1Status::Valid => self.store_metrics.k1_consumed(purpose),
+            K1Status::Expired => self.store_metrics.k1_expired(purpose),
+            K1Status::Unknown => self.store_metrics.k1_refused(purpose),
+        }
+        Ok(status)
+    }
+}
+
+/// Amount limits, adjustable at runtime through the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Limits {
+    pub min_withdrawable_msat: u64,
+    pub max_withdrawable_msat: u64,
+    pub channel_capacity_sat: u64,
+    pub withdraw_budget_msat: u64, // given to each new account
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            min_withdrawable_msat: 1_000,     // 1 sat
+            max_withdrawable_msat: 1_000_000, // 1000 sats
+            channel_capacity_sat: 100_000,
+            withdraw_budget_msat: 10_000_000, // 10k sats
+        }
+    }
+}
+
+/// Errors from endpoints whose success body has no status field get a plain
+/// status reply
+type ErrorReply = (StatusCode, Json<StatusResponse>);
+
+fn error_reply(code: StatusCode, reason: String) -> ErrorReply {
+    (code, Json(StatusResponse::error(reason)))
+}
+
+/// The address a request came from, when the app is served with connect info
+type Peer = Option<ConnectInfo<SocketAddr>>;
+
+/// Runs the request past the screener, see policy::RequestScreener
+async fn screen(state: &AppState, peer: Peer, screened: Screened) -> Result<(), ErrorReply> {
+    let peer = peer.map(|ConnectInfo(addr)| addr.ip());
+    state
+        .request_screener
+        .screen(peer, screened)
+        .await
+        .map_err(|reason| error_reply(StatusCode::FORBIDDEN, reason))
+}
+
+/// Takes one of the account's requests, see throttle.rs
+fn throttle_account(state: &AppState, linking_key: &str) -> Result<(), ErrorReply> {
+    state.account_throttle.check(linking_key).map_err(|wait| {
+        info!(linking_key, ?wait, "Throttled");
+        let reason = format!(
+            "Too many requests for this account, try again in {}s",
+            wait.as_secs_f64().ceil()
+        );
+        error_reply(StatusCode::TOO_MANY_REQUESTS, reason)
+    })
+}
+
+const DEFAULT_DESCRIPTION: &str = "Withdrawal from service";
+
+// Defaults of announce_addr, callback_url and listen_addr, set them in
+// config.toml or with flags, see config.rs
+pub const IP_ADDRESS: &str = "192.168.27.72:49735";
+const CALLBACK_URL: &str = "http://192.168.27.72:3000/";
+/// Where the binary serves app(), the target of CALLBACK_URL
+pub const LISTEN_ADDR: &str = "0.0.0.0:3000";
+
+/// `<pubkey>@<host:port>` handed out by request-channel, set once the node is
+/// known
+pub static NODE_URI: OnceLock<String> = OnceLock::new();
+
+// =============================================================================
+// request-channel (LUD-02)
+// =============================================================================
+
+async fn request_channel(
+    State(state): State<AppState>,
+    peer: Peer,
+    BaseUrl(base): BaseUrl,
+) -> Result<(StatusCode, Json<LnurlParams>), ErrorReply> {
+    screen(&state, peer, Screened::K1(K1Purpose::Channel)).await?;
+    // Set by main once the node answered; an embedder may not have yet
+    let Some(uri) = NODE_URI.get().cloned() else {
+        return Err(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The node's URI is not known yet".to_string(),
+        ));
+    };
+    let k1 = Uuid::new_v4().to_string();
+
+    let quote = match state.channel_pricing {
+        Some(ref pricing) => Some(pricing::quote(&state, pricing, &k1).await.map_err(|e| {
+            error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Node error: {}", e))
+        })?),
+        None => None,
+    };
+
+    state.issue_k1(&k1, K1Purpose::Channel).await.map_err(|e| {
+        error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Storage error: {}", e))
+    })?;
+    if let Some(ref quote) = quote {
+        state.storage.set_k1_capacity(&k1, quote.capacity_sat).await.map_err(|e| {
+            error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Storage error: {}", e))
+        })?;
+    }
+
+    let response = ChannelRequest {
+        uri,
+        callback: format!("{}open-channel", base),
+        k1,
+        capacity_sat: quote.as_ref().map(|quote| quote.capacity_sat),
+        pr: quote.map(|quote| quote.bolt11),
+    };
+
+    debug!(k1 = %response.k1, capacity_sat = ?response.capacity_sat, "Channel request issued");
+    Ok((StatusCode::OK, Json(response.into())))
+}
+
+// GET /open-channel?remoteid=<pubkey>&k1=<k1>&private=<1|0>
+// GET /open-channel?remoteid=<pubkey>&k1=<k1>&cancel=1
+#[derive(Debug, Deserialize)]
+struct OpenChannelParams {
+    remoteid: String,
+    k1: String,
+    #[serde(default)]
+    private: Option<String>, // LUD-02 sends 1/0
+    #[serde(default)]
+    amount: Option<u64>, // sats, at most limits.channel_capacity_sat (default), or as paid for
+    #[serde(default)]
+    cancel: Option<String>, // LUD-02: the wallet declines, the k1 is spent
+}
+
+/// Accepts LUD-02's 1/0 as well as true/false
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Validates and consumes a channel k1 (single-use)
+async fn spend_channel_k1(
+    state: &AppState,
+    k1: &str,
+) -> Result<(), (StatusCode, Json<OpenChannelResponse>)> {
+    let (status, reason) = match state.consume_k1(k1, K1Purpose::Channel).await {
+        Ok(K1Status::Valid) => return Ok(()),
+        Ok(K1Status::Expired) => (StatusCode::BAD_REQUEST, "Expired k1, request a new one".into()),
+        Ok(K1Status::Unknown) => (StatusCode::BAD_REQUEST, "Invalid or already used k1".into()),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Storage error: {}", e)),
+    };
+    Err((status, Json(OpenChannelResponse::error(reason))))
+}
+
+async fn open_channel(
+    State(state): State<AppState>,
+    peer: Peer,
+    Query(params): Query<OpenChannelParams>,
+) -> (StatusCode, Json<OpenChannelResponse>) {
+    debug!(
+        k1 = %params.k1,
+        remoteid = %text::for_log(&params.remoteid),
+        private = ?params.private,
+        amount = ?params.amount,
+        cancel = ?params.cancel,
+        "Open channel"
+    );
+
+    // A sold channel is funded once paid for (pricing.rs), at the capacity
+    // quoted; until then the k1 stays good
+    let cancelling = params.cancel.as_deref().and_then(parse_flag) == Some(true);
+    let mut quoted_sat = None;
+    if state.channel_pricing.is_some() && !cancelling {
+        let label = pricing::invoice_label(&params.k1);
+        let refusal = match state.backend.invoice_status(&label).await {
+            Ok(Some(InvoiceStatus::Paid)) => None,
+            Ok(Some(InvoiceStatus::Unpaid)) => Some((
+                StatusCode::PAYMENT_REQUIRED,
+                "The channel's invoice is not paid yet".to_string(),
+            )),
+            Ok(Some(InvoiceStatus::Expired)) => Some((
+                StatusCode::BAD_REQUEST,
+                "The channel's invoice expired, request a new channel".to_string(),
+            )),
+            Ok(None) => Some((StatusCode::BAD_REQUEST, "Invalid or already used k1".to_string())),
+            Err(e) => Some((StatusCode::INTERNAL_SERVER_ERROR, format!("Node error: {}", e))),
+        };
+        if let Some((status, reason)) = refusal {
+            return (status, Json(OpenChannelResponse::error(reason)));
+        }
+        quoted_sat = match state.storage.k1_capacity(&params.k1).await {
+            Ok(Some(capacity_sat)) => Some(capacity_sat),
+            Ok(None) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(OpenChannelResponse::error("Invalid or already used k1")),
+                );
+            }
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(OpenChannelResponse::error(format!("Storage error: {}", e))),
+                );
+            }
+        };
+    }
+
+    // A paid open keeps its k1 until funding, so that a refusal doesn't cost
+    // the wallet what it paid
+    if quoted_sat.is_none() {
+        if let Err(refusal) = spend_channel_k1(&state, &params.k1).await {
+            return refusal;
+        }
+    }
+
+    let node_id = match cln_rpc::primitives::PublicKey::from_str(&params.remoteid) {
+        Ok(id) => id,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(OpenChannelResponse::error(format!("Invalid node id: {}", e))),
+            );
+        }
+    };
+
+    match params.cancel.as_deref().map(parse_flag) {
+        None | Some(Some(false)) => {}
+        Some(Some(true)) => {
+            info!(k1 = %params.k1, remoteid = %params.remoteid, "Channel request cancelled");
+            return (
+                StatusCode::OK,
+                Json(OpenChannelResponse::ok()),
+            );
+        }
+        Some(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(OpenChannelResponse::error("cancel must be 1 or 0")),
+            );
+        }
+    }
+
+    let private = match params.private.as_deref().map(parse_flag) {
+        None => false,
+        Some(Some(private)) => private,
+        Some(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(OpenChannelResponse::error("private must be 1 or 0")),
+            );
+        }
+    };
+
+    // A sold channel was within the limits when quoted, and is opened as sold
+    let capacity_sat = match quoted_sat {
+        Some(quoted_sat) if params.amount.is_some_and(|amount| amount != quoted_sat) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(OpenChannelResponse::error(format!(
+                    "amount must be {} sats, the capacity paid for",
+                    quoted_sat
+                ))),
+            );
+        }
+        Some(quoted_sat) => quoted_sat,
+        None => {
+            let limits = state.limits.lock().await.clone();
+            let max_capacity_sat = state
+                .channel_policy
+                .max_capacity_sat(&params.remoteid, &limits)
+                .await;
+            let capacity_sat = params.amount.unwrap_or(max_capacity_sat);
+            if capacity_sat == 0 || capacity_sat > max_capacity_sat {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(OpenChannelResponse::error(format!(
+                        "amount must be between 1 and {} sats",
+                        max_capacity_sat
+                    ))),
+                );
+            }
+            capacity_sat
+        }
+    };
+
+    let open = ChannelOpen {
+        k1: params.k1.clone(),
+        node_id: params.remoteid.clone(),
+        capacity_sat,
+        private,
+    };
+    let peer = peer.map(|ConnectInfo(addr)| addr.ip());
+    if let Err(reason) = state.request_screener.screen(peer, Screened::ChannelFunding).await {
+        return (StatusCode::FORBIDDEN, Json(OpenChannelResponse::error(reason)));
+    }
+    if let Err(reason) = state.channel_policy.approve(&open).await {
+        info!(k1 = %open.k1, "Channel request denied: {}", reason);
+        return (StatusCode::FORBIDDEN, Json(OpenChannelResponse::error(reason)));
+    }
+    let mut issued_at = None;
+    if quoted_sat.is_some() {
+        // Read before it is spent, for a k1 given back to keep its expiry
+        issued_at = match state.storage.k1_issued_at(&params.k1).await {
+            Ok(issued_at) => issued_at,
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(OpenChannelResponse::error(format!("Storage error: {}", e))),
+                );
+            }
+        };
+        if let Err(refusal) = spend_channel_k1(&state, &params.k1).await {
+            return refusal;
+        }
+    }
+
+    match state.fund_channel(node_id, &open).await {
+        Ok(funded) => (
+            StatusCode::OK,
+            Json(OpenChannelResponse {
+                mindepth: funded.mindepth,
+                channel_id: Some(funded.channel_id),
+                outnum: Some(funded.outnum),
+                tx: Some(funded.tx),
+                txid: Some(funded.txid),
+                capacity_sat: Some(capacity_sat),
+                private: Some(private),
+                ..OpenChannelResponse::ok()
+            }),
+        ),
+        Err(e) => {
+            // The channel is paid for: the k1 is good again, for a retry
+            if let (Some(quoted_sat), Some(issued_at)) = (quoted_sat, issued_at) {
+                let given_back = state
+                    .restore_k1(&params.k1, K1Purpose::Channel, issued_at, Some(quoted_sat))
+                    .await;
+                if let Err(e) = given_back {
+                    error!(k1 = %params.k1, "Failed to give back a paid channel's k1: {}", e);
+                }
+            }
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(OpenChannelResponse::error(format!("Failed to open channel: {}", e))),
+            )
+        }
+    }
+}
+
+// =============================================================================
+// request-withdraw (LUD-03)
+// =============================================================================
+
+// GET /request-withdraw
+// GET /request-withdraw?k1=<k1>  — a voucher issued by the operator (service.rs)
+// GET /request-withdraw?allowance=<link>  — an account's reusable link (allowance.rs)
+// GET /request-withdraw?balance=<link>  — the same for any account, its LUD-14 balanceCheck
+#[derive(Debug, Deserialize)]
+struct RequestWithdrawParams {
+    #[serde(default)]
+    k1: Option<String>,
+    #[serde(default)]
+    allowance: Option<String>,
+    #[serde(default)]
+    balance: Option<String>,
+}
+
+async fn request_withdraw(
+    State(state): State<AppState>,
+    peer: Peer,
+    BaseUrl(base): BaseUrl,
+    Query(params): Query<RequestWithdrawParams>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<LnurlParams>), ErrorReply> {
+    screen(&state, peer, Screened::K1(K1Purpose::Withdraw)).await?;
+    let storage_error =
+        |e| error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Storage error: {}", e));
+
+    let limits = state.limits.lock().await.clone();
+    let mut owner = None;
+
+    let k1 = match (params.k1, params.allowance, params.balance) {
+        // Issued ahead of time: bound to its account already, and redeemable
+        // by whoever holds the link
+        (Some(k1), _, _) => {
+            let unknown = || error_reply(StatusCode::NOT_FOUND, "Unknown or redeemed voucher".to_string());
+            let voucher =
+                state.storage.get_voucher(&k1).await.map_err(storage_error)?.ok_or_else(unknown)?;
+            if let Some(reason) = window_refusal(&voucher) {
+                return Err(error_reply(StatusCode::FORBIDDEN, reason));
+            }
+            throttle_account(&state, &voucher.linking_key)?;
+            let account = state.storage.get_account(&voucher.linking_key).await.map_err(storage_error)?;
+            let account = account.ok_or_else(unknown)?;
+            check_budget(&state, &account, &limits).await?;
+            owner = Some(account);
+            // The voucher may have waited for longer than a withdraw k1 lives;
+            // its k1's TTL starts over with each wallet that scans it, and it
+            // is issued again if it was swept in the meantime
+            state.issue_k1(&k1, K1Purpose::Withdraw).await.map_err(storage_error)?;
+            k1
+        }
+        // Reusable: a fresh voucher of the allowance's account each time
+        (None, Some(link), _) => {
+            let allowance = state.storage.allowance_by_link(&link).await.map_err(storage_error)?;
+            let linking_key = allowance.map(|allowance| allowance.linking_key);
+            let (k1, account) = reissue(&state, &limits, linking_key, "Unknown allowance").await?;
+            info!(linking_key = %account.linking_key, "Voucher issued through its allowance");
+            owner = Some(account);
+            k1
+        }
+        // LUD-14: the same through the account's balance link
+        (None, None, Some(link)) => {
+            let linking_key = state.storage.balance_link_owner(&link).await.map_err(storage_error)?;
+            let (k1, account) =
+                reissue(&state, &limits, linking_key, "Unknown balance link").await?;
+            info!(linking_key = %account.linking_key, "Voucher issued through its balance link");
+            owner = Some(account);
+            k1
+        }
+        (None, None, None) => {
+            let session = session_linking_key(&state, &headers).await;
+            let mut account = None;
+            if let Some(linking_key) = &session {
+                throttle_account(&state, linking_key)?;
+                account = state.storage.get_account(linking_key).await.map_err(storage_error)?;
+            }
+            if let Some(account) = &account {
+                check_budget(&state, account, &limits).await?;
+            }
+            let k1 = Uuid::new_v4().to_string();
+            state.issue_k1(&k1, K1Purpose::Withdraw).await.map_err(storage_error)?;
+
+            // Authenticated callers get a voucher bound to their account, capped by its budget
+            if let Some(linking_key) = session {
+                if let Some(account) = account {
+                    let voucher = Voucher {
+                        k1: k1.clone(),
+                        linking_key: linking_key.clone(),
+                        window: Default::default(),
+                    };
+                    state.storage.insert_voucher(&voucher).await.map_err(storage_error)?;
+                    info!(linking_key, "Voucher issued");
+                    owner = Some(account);
+                }
+            }
+            k1
+        }
+    };
+
+    let linking_key = owner.as_ref().map(|account| account.linking_key.as_str());
+    let bounds = state.withdraw_policy.bounds(linking_key, &limits).await;
+    let mut max_withdrawable = bounds.max_msat;
+    let mut balance_check = None;
+    if let Some(account) = &owner {
+        max_withdrawable = max_withdrawable.min(account.withdraw_budget_msat);
+        // LUD-14: where to look again, e.g. once the allowance refills
+        let allowance = state.storage.get_allowance(&account.linking_key).await.map_err(storage_error)?;
+        balance_check = Some(match allowance {
+            Some(allowance) => allowance::link_url(&base, &allowance),
+            None => balance_link_url(&state, &base, &account.linking_key)
+                .await
+                .map_err(storage_error)?,
+        });
+    }
+
+    let response = WithdrawRequest {
+        callback: format!("{}withdraw", base),
+        k1,
+        default_description: state.withdraw_description.to_string(),
+        min_withdrawable: bounds.min_msat,
+        max_withdrawable,
+        balance_check,
+        // LUD-19, see pay.rs
+        pay_link: state.pay.bridge.then(|| {
+            lnurl::with_scheme(&format!("{}request-pay", base), "lnurlp")
+        }),
+    };
+
+    debug!(
+        k1 = %response.k1,
+        min_withdrawable = response.min_withdrawable,
+        max_withdrawable = response.max_withdrawable,
+        "Withdraw request issued"
+    );
+    Ok((StatusCode::OK, Json(response.into())))
+}
+
+/// A fresh voucher of the account behind a reusable link, and the account;
+/// 404 with `unknown` when the link or its account is gone, 403 when its
+/// budget is exhausted
+async fn reissue(
+    state: &AppState,
+    limits: &Limits,
+    linking_key: Option<String>,
+    unknown: &str,
+) -> Result<(String, Account), ErrorReply> {
+    let storage_error =
+        |e| error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Storage error: {}", e));
+    let unknown = || error_reply(StatusCode::NOT_FOUND, unknown.to_string());
+    let linking_key = linking_key.ok_or_else(unknown)?;
+    throttle_account(state, &linking_key)?;
+    let account = state.storage.get_account(&linking_key).await.map_err(storage_error)?;
+    let account = account.ok_or_else(unknown)?;
+    check_budget(state, &account, limits).await?;
+    let k1 = Uuid::new_v4().to_string();
+    state.issue_k1(&k1, K1Purpose::Withdraw).await.map_err(storage_error)?;
+    let voucher = Voucher {
+        k1: k1.clone(),
+        linking_key: account.linking_key.clone(),
+        window: Default::default(),
+    };
+    state.storage.insert_voucher(&voucher).await.map_err(storage_error)?;
+    Ok((k1, account))
+}
+
+/// 403 when the account's budget is under the least it may withdraw, which
+/// would have it offered a maximum below the minimum
+async fn check_budget(
+    state: &AppState,
+    account: &Account,
+    limits: &Limits,
+) -> Result<(), ErrorReply> {
+    let bounds = state.withdraw_policy.bounds(Some(&account.linking_key), limits).await;
+    if account.withdraw_budget_msat < bounds.min_msat {
+        return Err(error_reply(StatusCode::FORBIDDEN, "Withdraw budget exhausted".to_string()));
+    }
+    Ok(())
+}
+
+/// The account's reusable withdraw link for wallets to check its balance
+/// with (LUD-14), made the first time it is asked for
+async fn balance_link_url(
+    state: &AppState,
+    base: &str,
+    linking_key: &str,
+) -> StorageResult<String> {
+    let new = Uuid::new_v4().simple().to_string();
+    let link = state.storage.balance_link(linking_key, &new).await?;
+    Ok(format!("{}request-withdraw?balance={}", base, link))
+}
+
+/// Why the voucher can't be redeemed now, None within its window
+fn window_refusal(voucher: &Voucher) -> Option<String> {
+    match voucher.window.status(unix_now()) {
+        WindowStatus::NotYetActive => Some(format!(
+            "Voucher not yet active (valid from {})",
+            voucher.window.valid_from.unwrap_or_default()
+        )),
+        WindowStatus::Active => None,
+        WindowStatus::Expired => Some(format!(
+            "Voucher expired (valid until {})",
+            voucher.window.valid_until.unwrap_or_default()
+        )),
+    }
+}
+
+// GET /withdraw?k1=<k1>&pr=<bolt11>[&balanceNotify=<url>]
+#[derive(Debug, Deserialize)]
+struct WithdrawParams {
+    k1: String,
+    pr: String, // BOLT-11 invoice
+    /// LUD-15, kept for accounts with an allowance (allowance.rs)
+    #[serde(rename = "balanceNotify")]
+    balance_notify: Option<String>,
+}
+
+#[instrument(name = "withdraw", skip_all, fields(k1 = %params.k1))]
+async fn withdraw(
+    State(state): State<AppState>,
+    Query(params): Query<WithdrawParams>,
+) -> (StatusCode, Json<StatusResponse>) {
+    debug!(pr = %params.pr, "Withdraw callback");
+
+    // Everything that can turn the withdraw away without spending anything comes
+    // first: the voucher's window, the invoice and the channels. A voucher that
+    // is not yet active still works once it is, and a wallet refused for
+    // liquidity can ask again for less with the same k1, on its original TTL.
+    let voucher = match state.storage.get_voucher(&params.k1).await {
+        Ok(voucher) => {
+            if let Some(reason) = voucher.as_ref().and_then(window_refusal) {
+                return (StatusCode::FORBIDDEN, Json(StatusResponse::error(reason)));
+            }
+            voucher
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(StatusResponse::error(format!("Storage error: {}", e))),
+            );
+        }
+    };
+    // Vouchers issued to an account draw down that account's budget
+    let owner = voucher.map(|voucher| voucher.linking_key);
+
+    // Not worth a call to the node otherwise; consume_k1 below has the last word
+    match state.storage.k1_issued_at(&params.k1).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            state.store_metrics.k1_refused(K1Purpose::Withdraw);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(StatusResponse::error("Invalid or already used k1")),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(StatusResponse::error(format!("Storage error: {}", e))),
+            );
+        }
+    }
+
+    // Decode invoice and validate amount
+    let limits = state.limits.lock().await.clone();
+    let bounds = state.withdraw_policy.bounds(owner.as_deref(), &limits).await;
+    let invoice = match state.backend.decode_invoice(&params.pr).await {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(StatusResponse::error(format!("Invalid invoice: {}", e))),
+            );
+        }
+    };
+    // The description is the wallet's to write, keep it off our logs as is
+    let description = invoice.description.as_deref().map(text::clean_description);
+    debug!(description = ?description, amount_msat = ?invoice.amount_msat, "Invoice decoded");
+    let invoice_amount_msat = match invoice.amount_msat {
+        Some(msat) => {
+            if msat < bounds.min_msat {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(StatusResponse::error(format!(
+                        "Amount {} msat below minimum {} msat",
+                        msat, bounds.min_msat
+                    ))),
+                );
+            }
+            if msat > bounds.max_msat {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(StatusResponse::error(format!(
+                        "Amount {} msat exceeds maximum {} msat",
+                        msat, bounds.max_msat
+                    ))),
+                );
+            }
+            msat
+        }
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(StatusResponse::error("Invoice has no amount")),
+            );
+        }
+    };
+
+    // An amount the channels can't carry right now is turned away here rather
+    // than left to pay's minute of retries
+    if let Some(reason) = liquidity_refusal(&state, invoice_amount_msat, bounds.min_msat).await {
+        info!("Withdraw turned away: {}", reason);
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(StatusResponse::error(reason)));
+    }
+
+    // Validate and consume k1. From here on the k1 is spent, and its voucher
+    // with it, whatever happens next.
+    match state.consume_k1(&params.k1, K1Purpose::Withdraw).await {
+        Ok(K1Status::Valid) => {}
+        Ok(K1Status::Expired) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(StatusResponse::error("Expired k1, request a new one")),
+            );
+        }
+        Ok(K1Status::Unknown) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(StatusResponse::error("Invalid or already used k1")),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(StatusResponse::error(format!("Storage error: {}", e))),
+            );
+        }
+    }
+    if let Err(e) = state.storage.take_voucher(&params.k1).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(StatusResponse::error(format!("Storage error: {}", e))),
+        );
+    }
+
+    let withdrawal = Withdrawal {
+        k1: params.k1.clone(),
+        linking_key: owner.clone(),
+        bolt11: params.pr.clone(),
+        amount_msat: invoice_amount_msat,
+        status: WithdrawalStatus::Pending,
+        preimage_enc: None,
+        created_at: unix_now(),
+        fee_msat: None,
+        description,
+    };
+    if let Err(reason) = state.withdraw_policy.approve(&withdrawal).await {
+        info!("Withdraw denied: {}", reason);
+        return (StatusCode::FORBIDDEN, Json(StatusResponse::error(reason)));
+    }
+
+    if let Some(ref linking_key) = owner {
+        match state.storage.debit_budget(linking_key, invoice_amount_msat).await {
+            Ok(true) if state.notifications.wants(NotificationKind::BudgetExhausted) => {
+                if let Ok(Some(account)) = state.storage.get_account(linking_key).await {
+                    if account.withdraw_budget_msat == 0 {
+                        state
+                            .notifications
+                            .notify(Notification::budget_exhausted(linking_key));
+                    }
+                }
+            }
+            Ok(true) => {}
+            Ok(false) => {
+                let remaining = match state.storage.get_account(linking_key).await {
+                    Ok(Some(account)) => account.withdraw_budget_msat,
+                    _ => 0,
+                };
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(StatusResponse::error(format!(
+                        "Amount {} msat exceeds remaining budget {} msat",
+                        invoice_amount_msat, remaining
+                    ))),
+                );
+            }
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(StatusResponse::error(format!("Storage error: {}", e))),
+                );
+            }
+        }
+    }
+
+    if let Err(e) = state.storage.insert_withdrawal(&withdrawal).await {
+        error!("Failed to record the withdrawal: {}", e);
+    }
+
+    if let (Some(linking_key), Some(url)) = (&owner, &params.balance_notify) {
+        remember_balance_notify(&state, linking_key, url).await;
+    }
+
+    // Pay the invoice asynchronously — return OK immediately, pay in background
+    // Per the LNURL spec: server "attempts to pay the invoice asynchronously"
+    let k1 = params.k1.clone();
+    let bolt11 = params.pr.clone();
+    let backend_clone = state.backend.clone();
+    let storage_clone = state.storage.clone();
+    let cipher_clone = state.cipher.clone();
+    let gate_clone = state.write_gate.clone();
+    let policy_clone = state.withdraw_policy.clone();
+    let notifications_clone = state.notifications.clone();
+    let metrics_clone = state.flow_metrics.clone();
+    state.flow_metrics.withdraw_requested();
+    info!(amount_msat = invoice_amount_msat, "Withdraw accepted, paying in the background");
+
+    tokio::spawn(async move {
+        let pay_result = backend_clone.pay(&bolt11).await;
+        // The request has already returned, so hold the gate ourselves while recording the result
+        let writing = gate_clone.read().await;
+
+        let settled = match pay_result {
+            Ok(payment) => {
+                info!(
+                    preimage = %hex::encode(&payment.preimage),
+                    amount_sent_msat = payment.amount_sent_msat,
+                    "Withdraw paid"
+                );
+                metrics_clone.withdraw_paid();
+                let fee_msat = payment.amount_sent_msat.saturating_sub(invoice_amount_msat);
+
+                // Only ever persist the preimage encrypted
+                let preimage_enc = match cipher_clone.as_deref() {
+                    Some(cipher) => match cipher.encrypt(&payment.preimage) {
+                        Ok(sealed) => Some(sealed),
+                        Err(e) => {
+                            error!("Failed to encrypt preimage: {}", e);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                if let Err(e) = storage_clone
+                    .finish_withdrawal(
+                        &k1,
+                        WithdrawalStatus::Paid,
+                        preimage_enc.as_deref(),
+                        Some(fee_msat),
+                    )
+                    .await
+                {
+                    error!("Failed to update the withdrawal: {}", e);
+                }
+                Withdrawal {
+                    status: WithdrawalStatus::Paid,
+                    preimage_enc,
+                    fee_msat: Some(fee_msat),
+                    ..withdrawal
+                }
+            }
+            Err(e) => {
+                warn!("Withdraw payment failed: {}", e);
+                metrics_clone.withdraw_failed();
+                notifications_clone.notify(Notification::payment_failed(&withdrawal, &e.to_string()));
+                // Give the budget back, the sats never left
+                if let Some(linking_key) = owner {
+                    if let Err(e) = storage_clone.credit_budget(&linking_key, invoice_amount_msat).await {
+                        error!(linking_key, "Failed to refund the budget: {}", e);
+                    }
+                }
+                if let Err(e) = storage_clone
+                    .finish_withdrawal(&k1, WithdrawalStatus::Failed, None, None)
+                    .await
+                {
+                    error!("Failed to update the withdrawal: {}", e);
+                }
+                Withdrawal {
+                    status: WithdrawalStatus::Failed,
+                    ..withdrawal
+                }
+            }
+        };
+
+        // The policy may take its time, don't hold up backups meanwhile
+        drop(writing);
+        policy_clone.on_settled(&settled).await;
+    }
+    // Its lines stay those of the request that started it
+    .in_current_span());
+
+    (StatusCode::OK, Json(StatusResponse::ok()))
+}
+
+/// Why `amount_msat` can't be withdrawn with the node's channels as they are,
+/// None when it can or the node won't say
+async fn liquidity_refusal(state: &AppState, amount_msat: u64, min_msat: u64) -> Option<String> {
+    let payable_msat = match state.backend.funds().await {
+        Ok(funds) => funds.payable_msat(),
+        Err(e) => {
+            error!("Failed to check outbound liquidity: {}", e);
+            return None;
+        }
+    };
+    if amount_msat <= payable_msat {
+        None
+    } else if payable_msat < min_msat {
+        Some("Not enough outbound liquidity right now, try again later".to_string())
+    } else {
+        Some(format!(
+            "Not enough outbound liquidity for {} msat, temporarily reduce amount to {} msat",
+            amount_msat, payable_msat
+        ))
+    }
+}
+
+/// Keeps the wallet's balanceNotify URL on the account's allowance, if it
+/// has one; without one there is no balance change to tell of
+async fn remember_balance_notify(state: &AppState, linking_key: &str, url: &str) {
+    match state.storage.get_allowance(linking_key).await {
+        Ok(Some(allowance)) if allowance.balance_notify.as_deref() != Some(url) => {}
+        Ok(_) => return,
+        Err(e) => {
+            error!(linking_key, "Failed to look up the allowance: {}", e);
+            return;
+        }
+    }
+    if callback::check_callback_url(url).is_err() {
+        info!(url = %text::for_log(url), "Ignoring balanceNotify (https only, or http on .onion)");
+        return;
+    }
+    if let Err(e) = state.storage.set_balance_notify(linking_key, url).await {
+        error!(linking_key, "Failed to keep balanceNotify: {}", e);
+    }
+}
+
+// GET /withdraw-status?k1=<k1>
+// Not part of LUD-03: lets the wallet learn whether the background payment
+// of an accepted withdraw went through, instead of waiting for an invoice
+// that will never be paid. Once paid it also carries the fee and, when
+// preimages are kept (LNURL_ENCRYPTION_KEY), the preimage: proof the payment
+// was made that the recipient can check against their invoice.
+#[derive(Debug, Deserialize)]
+struct WithdrawStatusParams {
+    k1: String,
+}
+
+async fn withdraw_status(
+    State(state): State<AppState>,
+    Query(params): Query<WithdrawStatusParams>,
+) -> (StatusCode, Json<WithdrawStatusResponse>) {
+    match state.storage.get_withdrawal(&params.k1).await {
+        Ok(Some(withdrawal)) => {
+            let mut response = WithdrawStatusResponse::ok(withdrawal.status);
+            if withdrawal.status == WithdrawalStatus::Paid {
+                response.preimage = service::preimage(&state, &withdrawal);
+                response.fee_msat = withdrawal.fee_msat;
+            }
+            (StatusCode::OK, Json(response))
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(WithdrawStatusResponse::error("Unknown withdrawal")),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(WithdrawStatusResponse::error(format!("Storage error: {}", e))),
+        ),
+    }
+}
+
+// =============================================================================
+// lnurl-auth (LUD-04)
+// =============================================================================
+//
+// Flow:
+//   1. GET /auth-challenge  → { k1: "<hex 32 random bytes>", url, lnurl }
+//      url is the LUD-04 login link <callback url>auth-response?tag=login&k1=<k1>
+//   2. The wallet signs the k1 with its linking key and calls the link back:
+//      GET /auth-response?tag=login&k1=<k1>&sig=<hex DER>&key=<hex pubkey>
+//   3. Server verifies the signature itself, over the 32 bytes of the k1
+//
+// As a fallback, a node can log in with its CLN signmessage instead:
+//      GET /auth-response?k1=<k1>&signature=<zbase>&pubkey=<node_pubkey>
+// which is verified via CLN checkmessage, over the k1 as text.
+//
+// /auth-challenge?action=<register|login|link|auth> puts LUD-04's action in
+// the login link, for the wallet to show; it comes back with the callback
+// and goes to the auth webhook (webhook.rs). Without one it is a login.
+//
+// ⚠️  The "catch": CLN checkmessage expects zbase-encoded signatures,
+//     NOT DER-hex as the standard LNURL-auth spec describes.
+//     signmessage returns { signature, recid, zbase } — use the `zbase` field.
+
+/// LUD-04's actions, what the login is for
+const AUTH_ACTIONS: [&str; 4] = ["register", "login", "link", "auth"];
+const DEFAULT_AUTH_ACTION: &str = "login";
+
+fn check_auth_action(action: &str) -> Result<(), String> {
+    match AUTH_ACTIONS.contains(&action) {
+        true => Ok(()),
+        false => Err(format!(
+            "Unknown action {:?}, expected {}",
+            action,
+            AUTH_ACTIONS.join(", ")
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthChallengeParams {
+    action: Option<String>,
+}
+
+async fn auth_challenge(
+    State(state): State<AppState>,
+    peer: Peer,
+    BaseUrl(base): BaseUrl,
+    Query(params): Query<AuthChallengeParams>,
+) -> Result<(StatusCode, Json<AuthChallenge>), ErrorReply> {
+    if let Some(action) = &params.action {
+        check_auth_action(action).map_err(|e| error_reply(StatusCode::BAD_REQUEST, e))?;
+    }
+    screen(&state, peer, Screened::K1(K1Purpose::Auth)).await?;
+    let k1 = random_hex_32();
+
+    debug!(k1 = %k1, "Auth challenge issued");
+
+    state.issue_k1(&k1, K1Purpose::Auth).await.map_err(|e| {
+        error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Storage error: {}", e))
+    })?;
+
+    let mut url = format!(
+        "{}auth-response?tag={}&k1={}",
+        base,
+        lnurl_models::LOGIN_TAG,
+        k1
+    );
+    if let Some(action) = &params.action {
+        url.push_str(&format!("&action={}", action));
+    }
+    let challenge = AuthChallenge { k1, lnurl: lnurl::encode(&url).ok(), url: Some(url) };
+    Ok((StatusCode::OK, Json(challenge)))
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthResponseParams {
+    k1: String,
+    // LUD-04
+    sig: Option<String>, // hex DER over the k1 bytes
+    key: Option<String>, // hex-encoded compressed linking key
+    // CLN fallback
+    signature: Option<String>, // zbase-encoded (NOT DER-hex)
+    pubkey: Option<String>,    // hex-encoded compressed node pubkey
+    action: Option<String>,    // from the login link, see the top of the section
+}
+
+/// A login's signature of the k1, in either shape the server takes
+enum LoginSignature {
+    Der(String),
+    Zbase(String),
+}
+
+/// Whether `signature` is `linking_key`'s signature of `k1`; Err for a
+/// malformed request (400) or a failed checkmessage (500)
+async fn verify_login(
+    state: &AppState,
+    k1: &str,
+    linking_key: &str,
+    signature: &LoginSignature,
+) -> Result<bool, (StatusCode, String)> {
+    let bad_request = |reason: String| (StatusCode::BAD_REQUEST, reason);
+    match signature {
+        LoginSignature::Der(sig) => {
+            let pubkey = lnurl_models::encoding::parse_pubkey(linking_key)
+                .map_err(|e| bad_request(format!("Invalid key: {}", e)))?;
+            let k1: [u8; 32] = lnurl_models::encoding::hex_array(k1)
+                .map_err(|e| bad_request(format!("Invalid k1: {}", e)))?;
+            match lnurl_models::signature::verify_der(&k1, sig, &pubkey) {
+                Ok(()) => Ok(true),
+                Err(lnurl_models::signature::SignatureError::Invalid) => Ok(false),
+                Err(e) => Err(bad_request(format!("Invalid sig: {}", e))),
+            }
+        }
+        LoginSignature::Zbase(zbase) => {
+            let pubkey = cln_rpc::primitives::PublicKey::from_str(linking_key)
+                .map_err(|e| bad_request(format!("Invalid pubkey: {}", e)))?;
+            state.backend.check_message(k1, zbase, pubkey).await.map_err(|e| {
+                error!("checkmessage failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Verification error: {}", e))
+            })
+        }
+    }
+}
+
+#[instrument(name = "auth", skip_all, fields(k1 = %params.k1))]
+async fn auth_response(
+    State(state): State<AppState>,
+    Query(params): Query<AuthResponseParams>,
+) -> (StatusCode, Json<AuthResponse>) {
+    let (linking_key, signature) = match (params.sig, params.key, params.signature, params.pubkey) {
+        (Some(sig), Some(key), _, _) => (key, LoginSignature::Der(sig)),
+        (_, _, Some(zbase), Some(pubkey)) => (pubkey, LoginSignature::Zbase(zbase)),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(AuthResponse::error("Expected sig and key (or signature and pubkey)")),
+            );
+        }
+    };
+
+    let action = params.action.as_deref().unwrap_or(DEFAULT_AUTH_ACTION);
+    if let Err(e) = check_auth_action(action) {
+        return (StatusCode::BAD_REQUEST, Json(AuthResponse::error(e)));
+    }
+
+    match &signature {
+        LoginSignature::Der(sig) => debug!(sig = %sig, key = %text::for_log(&linking_key), "Login"),
+        LoginSignature::Zbase(zbase) => {
+            debug!(signature = %zbase, key = %text::for_log(&linking_key), "Login")
+        }
+    }
+
+    // Validate and consume k1
+    match state.consume_k1(&params.k1, K1Purpose::Auth).await {
+        Ok(K1Status::Valid) => {}
+        Ok(K1Status::Expired) => {
+            state.flow_metrics.auth(AuthOutcome::BadK1);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(AuthResponse::error("Expired k1, request a new one")),
+            );
+        }
+        Ok(K1Status::Unknown) => {
+            state.flow_metrics.auth(AuthOutcome::BadK1);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(AuthResponse::error("Invalid or expired k1")),
+            );
+        }
+        Err(e) => {
+            state.flow_metrics.auth(AuthOutcome::Error);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(AuthResponse::error(format!("Storage error: {}", e))),
+            );
+        }
+    }
+
+    match verify_login(&state, &params.k1, &linking_key, &signature).await {
+        Ok(true) => {
+            info!(linking_key, "Auth succeeded");
+            if let Err(reason) = state.auth_handler.approve(&linking_key).await {
+                info!(linking_key, "Login denied: {}", reason);
+                state.flow_metrics.auth(AuthOutcome::Denied);
+                return (StatusCode::FORBIDDEN, Json(AuthResponse::error(reason)));
+            }
+            match open_session(&state, &linking_key).await {
+                Ok((token, registered)) => {
+                    state.flow_metrics.auth(AuthOutcome::Success);
+                    state.auth_handler.on_login(&linking_key).await;
+                    let response = match registered {
+                        true => AuthResponse::registered(token),
+                        false => AuthResponse::logged_in(token),
+                    };
+                    if let Some(webhook) = &state.auth_webhook {
+                        webhook.deliver(LoginEvent {
+                            event: response.event.clone().unwrap_or_default(),
+                            linking_key: linking_key.clone(),
+                            action: action.to_string(),
+                            at: unix_now(),
+                        });
+                    }
+                    (StatusCode::OK, Json(response))
+                }
+                Err(e) => {
+                    state.flow_metrics.auth(AuthOutcome::Error);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(AuthResponse::error(format!("Storage error: {}", e))),
+                    )
+                }
+            }
+        }
+        Ok(false) => {
+            info!(key = %text::for_log(&linking_key), "Auth failed: signature not verified");
+            state.flow_metrics.auth(AuthOutcome::BadSignature);
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(AuthResponse::error("Signature verification failed")),
+            )
+        }
+        Err((code, reason)) => {
+            let outcome = match code.is_server_error() {
+                true => AuthOutcome::Error,
+                false => AuthOutcome::BadSignature, // malformed
+            };
+            state.flow_metrics.auth(outcome);
+            (code, Json(AuthResponse::error(reason)))
+        }
+    }
+}
+
+// =============================================================================
+// Sessions & accounts
+// =============================================================================
+//
+// A successful LNURL-auth creates the account on first login and hands out an
+// opaque session token. Authenticated endpoints read it from the
+// `Authorization: Bearer <token>` header, through the `Session` extractor.
+// The token is bound to the linking key by the session store rather than by
+// a signature, so deleting the account revokes it, and so does DELETE
+// /me/session for that one token. A session lasts LNURL_SESSION_TTL_SECS from
+// the login, after which it is refused and, in time, swept (expiry.rs).
+
+/// 32 random bytes, hex-encoded (auth k1s and session tokens)
+fn random_hex_32() -> String {
+    let mut random_bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut random_bytes);
+    hex::encode(random_bytes)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Creates the account on first login, notes the login and returns a fresh
+/// session token, with whether the account is new
+async fn open_session(
+    state: &AppState,
+    linking_key: &str,
+) -> storage::StorageResult<(String, bool)> {
+    let limits = state.limits.lock().await.clone();
+    let withdraw_budget_msat = state
+        .auth_handler
+        .withdraw_budget_msat(linking_key, &limits)
+        .await;
+    let registered = state
+        .storage
+        .ensure_account(linking_key, withdraw_budget_msat)
+        .await?;
+    state.storage.record_login(linking_key, unix_now()).await?;
+
+    let token = random_hex_32();
+    state.storage.insert_session(&token, linking_key).await?;
+    state.store_metrics.session_opened();
+    Ok((token, registered))
+}
+
+/// The bearer token in `headers`
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Resolves the bearer token in `headers` to the linking key it was issued
+/// for, unless its session has run out
+async fn session_linking_key(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    let token = bearer_token(headers)?;
+    let lookup = state
+        .storage
+        .session_linking_key(token, state.session_ttl_secs, unix_now())
+        .await;
+    match lookup {
+        Ok(linking_key) => linking_key,
+        Err(e) => {
+            error!("Session lookup failed: {}", e);
+            None
+        }
+    }
+}
+
+/// The linking key of an authenticated request. A handler taking it is only
+/// reached with a valid session token, others get a 401.
+struct Session(String);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Session {
+    type Rejection = ErrorReply;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Session, ErrorReply> {
+        match session_linking_key(state, &parts.headers).await {
+            Some(linking_key) => Ok(Session(linking_key)),
+            None => Err(error_reply(
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid session token".to_string(),
+            )),
+        }
+    }
+}
+
+// GET /me  (Authorization: Bearer <token>)
+#[derive(Debug, Serialize, Default)]
+struct MeResponse {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linking_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_login_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    withdraw_budget_msat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vouchers: Option<Vec<String>>, // unredeemed withdraw k1s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<std::collections::BTreeMap<String, String>>,
+}
+
+async fn me(
+    State(state): State<AppState>,
+    Session(linking_key): Session,
+) -> (StatusCode, Json<MeResponse>) {
+    if let Err((code, Json(error))) = throttle_account(&state, &linking_key) {
+        return (
+            code,
+            Json(MeResponse {
+                status: "ERROR".to_string(),
+                reason: error.reason,
+                ..Default::default()
+            }),
+        );
+    }
+
+    let account = match state.storage.get_account(&linking_key).await {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MeResponse {
+                    status: "ERROR".to_string(),
+                    reason: Some("Account not found".to_string()),
+                    ..Default::default()
+                }),
+            );
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(MeResponse {
+                    status: "ERROR".to_string(),
+                    reason: Some(format!("Storage error: {}", e)),
+                    ..Default::default()
+                }),
+            );
+        }
+    };
+
+    let vouchers = match state.storage.vouchers_for(&linking_key).await {
+        Ok(vouchers) => vouchers,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(MeResponse {
+                    status: "ERROR".to_string(),
+                    reason: Some(format!("Storage error: {}", e)),
+                    ..Default::default()
+                }),
+            );
+        }
+    };
+
+    (
+        StatusCode::OK,
+        Json(MeResponse {
+            status: "OK".to_string(),
+            reason: None,
+            linking_key: Some(account.linking_key),
+            created_at: Some(account.created_at),
+            last_login_at: account.last_login_at,
+            withdraw_budget_msat: Some(account.withdraw_budget_msat),
+            vouchers: Some(vouchers),
+            metadata: Some(account.metadata),
+        }),
+    )
+}
+
+// DELETE /me  (Authorization: Bearer <token>)
+// Erases the caller's personal data, see Storage::delete_account
+#[derive(Debug, Serialize, Default)]
+struct DeleteMeResponse {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deletion_id: Option<String>, // audit record id
+}
+
+async fn delete_me(
+    State(state): State<AppState>,
+    Session(linking_key): Session,
+) -> (StatusCode, Json<DeleteMeResponse>) {
+    match state
+        .storage
+        .delete_account(&linking_key, storage::DeletionRequester::User)
+        .await
+    {
+        Ok(Some(deletion)) => {
+            info!(audit = deletion.id, "Account deleted at user request");
+            (
+                StatusCode::OK,
+                Json(DeleteMeResponse {
+                    status: "OK".to_string(),
+                    reason: None,
+                    deletion_id: Some(deletion.id),
+                }),
+            )
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(DeleteMeResponse {
+                status: "ERROR".to_string(),
+                reason: Some("Account not found".to_string()),
+                ..Default::default()
+            }),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(DeleteMeResponse {
+                status: "ERROR".to_string(),
+                reason: Some(format!("Storage error: {}", e)),
+                ..Default::default()
+            }),
+        ),
+    }
+}
+
+// DELETE /me/session  (Authorization: Bearer <token>)
+// Logs out: the token presented stops working, the account's other sessions
+// are left alone
+async fn logout(
+    State(state): State<AppState>,
+    Session(linking_key): Session,
+    headers: HeaderMap,
+) -> (StatusCode, Json<StatusResponse>) {
+    // The extractor has already found it
+    let token = bearer_token(&headers).unwrap_or_default();
+    match state.storage.remove_session(token).await {
+        Ok(_) => {
+            info!(linking_key, "Logged out");
+            (StatusCode::OK, Json(StatusResponse::ok()))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(StatusResponse::error(format!("Storage error: {}", e))),
+        ),
+    }
+}
+
+// GET /me/withdrawals?limit=<n>  (Authorization: Bearer <token>)
+// The account's withdraw history, most recent first
+#[derive(Debug, Deserialize)]
+struct MyWithdrawalsParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct MyWithdrawalsResponse {
+    status: String,
+    withdrawals: Vec<service::WithdrawalView>,
+}
+
+async fn my_withdrawals(
+    State(state): State<AppState>,
+    Session(linking_key): Session,
+    Query(params): Query<MyWithdrawalsParams>,
+) -> Result<(StatusCode, Json<MyWithdrawalsResponse>), ErrorReply> {
+    throttle_account(&state, &linking_key)?;
+    let limit = params.limit.unwrap_or(100).min(1000);
+    let withdrawals = service::account_withdrawals(&state, &linking_key, limit)
+        .await
+        .map_err(|e| error_reply(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((
+        StatusCode::OK,
+        Json(MyWithdrawalsResponse {
+            status: "OK".to_string(),
+            withdrawals,
+        }),
+    ))
+}
+
+// =============================================================================
+// Router
+// =============================================================================
+
+/// Every endpoint, relative to wherever the router is mounted. Until NODE_URI
+/// is set, request-channel answers 500.
+pub fn app(state: AppState) -> Router {
+    public_app(state.clone()).merge(admin_app(state))
+}
+
+/// The endpoints wallets call, without the admin API
+pub fn public_app(state: AppState) -> Router {
+    // LUD-01: the request endpoints give their LNURL for ?format=lnurl
+    let as_lnurl = middleware::from_fn_with_state(state.clone(), lnurl::as_lnurl);
+    Router::new()
+        // LUD-02: Channel Request
+        .route("/request-channel", get(request_channel).layer(as_lnurl.clone()))
+        .route("/open-channel", get(open_channel))
+        // LUD-03: Withdraw Request
+        .route("/request-withdraw", get(request_withdraw).layer(as_lnurl.clone()))
+        .route("/withdraw", get(withdraw))
+        .route("/withdraw-status", get(withdraw_status))
+        // LUD-06: Pay Request
+        .route("/request-pay", get(pay::request_pay).layer(as_lnurl))
+        .route("/pay", get(pay::pay))
+        // LUD-21: Pay verification
+        .route("/verify/:payment_hash", get(pay::verify))
+        // LUD-16: Lightning Address
+        .route("/.well-known/lnurlp/:username", get(pay::address))
+        .route("/pay/:username", get(pay::pay_address))
+        // LUD-04: Auth
+        .route("/auth-challenge", get(auth_challenge))
+        .route("/auth-response", get(auth_response))
+        // Account info for authenticated sessions
+        .route("/me", get(me).delete(delete_me))
+        .route("/me/withdrawals", get(my_withdrawals))
+        .route("/me/session", delete(logout))
+        // LSPS1: channel orders
+        .route("/lsps1/get_info", get(lsps1::get_info))
+        .route("/lsps1/create_order", post(lsps1::create_order))
+        .route("/lsps1/get_order", get(lsps1::get_order))
+        .route_layer(middleware::from_fn_with_state(state.clone(), admin::hold_write_gate))
+        // Probes for orchestrators and load balancers, outside the write gate:
+        // they answer while a backup or restore holds it
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .with_state(state)
+}
+
+/// Only the operator API (X-Api-Key, see admin.rs), under /admin, and the
+/// /metrics it guards, for a listener of its own (listener.rs)
+pub fn admin_app(state: AppState) -> Router {
+    Router::new()
+        .nest("/admin", admin::router(state.clone()))
+        .merge(admin::metrics_router(state.clone()))
+        .with_state(state)
+}
+
@@ -0,0 +1,215 @@
+// =============================================================================
+// Liquidity reports
+// =============================================================================
+//
+// A background job that, every LNURL_LIQUIDITY_REPORT_SECS (an hour by
+// default, 0 to turn it off), writes down what the node could pay out against
+// what the service has promised: on-chain balance, each channel's outbound
+// and inbound liquidity, withdraws accepted but not yet paid and the account
+// budgets still to be withdrawn. The reports are kept in storage, so the
+// history survives restarts, and served at GET /admin/liquidity along with an
+// outlook: how far outbound liquidity covers what is owed, and when it runs
+// out if it keeps falling the way it has.
+//
+// With several replicas each one takes reports; run the job on one of them.
+
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::backend::BackendError;
+use crate::storage::{LiquidityReport, StorageError};
+use crate::AppState;
+
+pub const DEFAULT_EVERY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub enum ReportError {
+    Backend(BackendError),
+    Storage(StorageError),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::Backend(e) => write!(f, "Node error: {}", e),
+            ReportError::Storage(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+impl From<BackendError> for ReportError {
+    fn from(e: BackendError) -> Self {
+        ReportError::Backend(e)
+    }
+}
+
+impl From<StorageError> for ReportError {
+    fn from(e: StorageError) -> Self {
+        ReportError::Storage(e)
+    }
+}
+
+/// Reads LNURL_LIQUIDITY_REPORT_SECS, None when reports are turned off
+pub fn load_interval() -> Option<Duration> {
+    let every = match std::env::var("LNURL_LIQUIDITY_REPORT_SECS") {
+        Err(_) => DEFAULT_EVERY,
+        Ok(raw) => match raw.trim().parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                warn!("Ignoring malformed LNURL_LIQUIDITY_REPORT_SECS (expected a number)");
+                DEFAULT_EVERY
+            }
+        },
+    };
+
+    if every.is_zero() {
+        info!("Liquidity reports disabled");
+        return None;
+    }
+    info!("Liquidity report every {}s", every.as_secs());
+    Some(every)
+}
+
+/// Asks the node and storage for a report, without storing it
+pub async fn take_report(state: &AppState) -> Result<LiquidityReport, ReportError> {
+    let funds = state.backend.funds().await?;
+    let stats = state.storage.stats().await?;
+
+    let usable = funds.channels.iter().filter(|channel| channel.is_usable());
+    let (outbound_msat, inbound_msat) = usable.fold((0, 0), |(outbound, inbound), channel| {
+        (
+            outbound + channel.outbound_msat,
+            inbound + channel.inbound_msat,
+        )
+    });
+    Ok(LiquidityReport {
+        taken_at: crate::unix_now(),
+        onchain_sat: funds.onchain_sat,
+        onchain_unconfirmed_sat: funds.onchain_unconfirmed_sat,
+        channels: funds.channels,
+        outbound_msat,
+        inbound_msat,
+        pending_withdrawals_msat: stats.pending_withdrawals_msat,
+        outstanding_budget_msat: stats.outstanding_budget_msat,
+    })
+}
+
+/// Takes and stores a report every `every`, the first one right away
+pub async fn run(state: AppState, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        let report = match take_report(&state).await {
+            Ok(report) => report,
+            Err(e) => {
+                error!("Failed to take liquidity report: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = state.storage.insert_liquidity_report(&report).await {
+            error!("Failed to store liquidity report: {}", e);
+            continue;
+        }
+        info!(
+            "Liquidity: {} sat on-chain, {} msat outbound, {} msat owed",
+            report.onchain_sat,
+            report.outbound_msat,
+            report.pending_withdrawals_msat + report.outstanding_budget_msat
+        );
+    }
+}
+
+/// Where liquidity is heading, from a run of reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Outlook {
+    /// Latest outbound liquidity less everything owed; negative when the
+    /// node can't pay out every budget already
+    pub headroom_msat: i64,
+    /// Average change of outbound liquidity per day over the reports
+    pub outbound_change_per_day_msat: i64,
+    /// When outbound liquidity runs out at that pace, None unless it falls
+    pub dry_at: Option<u64>,
+}
+
+/// From reports ordered most recent first, as storage lists them. None
+/// without any report.
+pub fn outlook(reports: &[LiquidityReport]) -> Option<Outlook> {
+    let latest = reports.first()?;
+    let oldest = reports.last()?;
+
+    let owed = latest.pending_withdrawals_msat + latest.outstanding_budget_msat;
+    let headroom_msat = latest.outbound_msat as i64 - owed as i64;
+
+    let elapsed = latest.taken_at.saturating_sub(oldest.taken_at);
+    let change = latest.outbound_msat as i64 - oldest.outbound_msat as i64;
+    let outbound_change_per_day_msat = match elapsed {
+        0 => 0,
+        _ => (change as i128 * 86_400 / elapsed as i128) as i64,
+    };
+    let dry_at = match change {
+        change if change < 0 => {
+            let secs =
+                latest.outbound_msat as u128 * elapsed as u128 / change.unsigned_abs() as u128;
+            Some(latest.taken_at + secs as u64)
+        }
+        _ => None,
+    };
+
+    Some(Outlook {
+        headroom_msat,
+        outbound_change_per_day_msat,
+        dry_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(taken_at: u64, outbound_msat: u64) -> LiquidityReport {
+        LiquidityReport {
+            taken_at,
+            onchain_sat: 50_000,
+            onchain_unconfirmed_sat: 0,
+            channels: vec![],
+            outbound_msat,
+            inbound_msat: 0,
+            pending_withdrawals_msat: 100_000,
+            outstanding_budget_msat: 900_000,
+        }
+    }
+
+    #[test]
+    fn falling_liquidity_has_a_dry_date() {
+        let start = 1_760_000_000;
+        // Most recent first: 4M msat gone in a day, 2M left
+        let reports = [report(start + 86_400, 2_000_000), report(start, 6_000_000)];
+        assert_eq!(
+            outlook(&reports),
+            Some(Outlook {
+                headroom_msat: 1_000_000,
+                outbound_change_per_day_msat: -4_000_000,
+                dry_at: Some(start + 86_400 + 43_200),
+            })
+        );
+    }
+
+    #[test]
+    fn steady_or_rising_liquidity_never_runs_dry() {
+        let start = 1_760_000_000;
+        let rising = [report(start + 3_600, 800_000), report(start, 500_000)];
+        let outlook_rising = outlook(&rising).unwrap();
+        assert_eq!(outlook_rising.headroom_msat, -200_000);
+        assert_eq!(outlook_rising.outbound_change_per_day_msat, 7_200_000);
+        assert_eq!(outlook_rising.dry_at, None);
+
+        let single = outlook(&[report(start, 500_000)]).unwrap();
+        assert_eq!(single.outbound_change_per_day_msat, 0);
+        assert_eq!(single.dry_at, None);
+        assert_eq!(outlook(&[]), None);
+    }
+}
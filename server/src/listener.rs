@@ -0,0 +1,413 @@
+// =============================================================================
+// Listeners
+// =============================================================================
+//
+// The public listener speaks https when config.toml's [tls] gives it a
+// certificate, as LUD-01 wants for anything but a .onion. Certificate files
+// are checked for changes every minute and reloaded, so a certbot renewal
+// takes effect without a restart; a bad renewal keeps the old certificate.
+// ACME certificates are acme.rs's business.
+//
+// By default the admin API is served under /admin on the public listener,
+// guarded by its API keys (admin.rs) and LNURL_ADMIN_ALLOW_CIDRS. To keep
+// operational controls off the address wallets reach, give it a listener of
+// its own:
+//
+//   LNURL_ADMIN_ADDR           e.g. 127.0.0.1:3001; /admin is then served
+//                              there and nowhere else
+//
+// which can speak TLS, and require client certificates (mutual TLS):
+//
+//   LNURL_ADMIN_TLS_CERT       PEM certificate chain of the admin listener
+//   LNURL_ADMIN_TLS_KEY        PEM private key for it
+//   LNURL_ADMIN_TLS_CLIENT_CA  PEM CA certificate(s); only clients with a
+//                              certificate one of them issued may connect
+//
+// The files are read at startup; a bad one stops the server rather than
+// leaving the admin API less protected than configured.
+
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// How often the public listener's certificate files are checked for changes
+pub const TLS_RELOAD_CHECK_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct ListenerError(String);
+
+impl fmt::Display for ListenerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ListenerError {}
+
+/// Where the admin API is served, and how
+pub struct AdminListener {
+    pub addr: SocketAddr,
+    pub tls: Option<Arc<ServerConfig>>, // None: plain HTTP
+    pub mutual: bool,                   // client certificates required
+}
+
+impl fmt::Display for AdminListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match &self.tls {
+            Some(_) if self.mutual => "https, client certificates required",
+            Some(_) => "https",
+            None => "http",
+        };
+        write!(f, "{} ({})", self.addr, scheme)
+    }
+}
+
+fn read(var: &str, path: &str) -> Result<Vec<u8>, ListenerError> {
+    std::fs::read(Path::new(path)).map_err(|e| ListenerError(format!("{} {}: {}", var, path, e)))
+}
+
+fn certificates(var: &str, pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, ListenerError> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ListenerError(format!("{}: {}", var, e)))?;
+    match certs.is_empty() {
+        true => Err(ListenerError(format!("{}: no PEM certificate", var))),
+        false => Ok(certs),
+    }
+}
+
+fn private_key(var: &str, pem: &[u8]) -> Result<PrivateKeyDer<'static>, ListenerError> {
+    rustls_pemfile::private_key(&mut &pem[..])
+        .map_err(|e| ListenerError(format!("{}: {}", var, e)))?
+        .ok_or_else(|| ListenerError(format!("{}: no PEM private key", var)))
+}
+
+/// A TLS configuration from PEM: the server's certificate chain and key, and
+/// the CAs whose client certificates it requires, if any
+pub fn tls_config(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    client_ca_pem: Option<&[u8]>,
+) -> Result<ServerConfig, ListenerError> {
+    server_config(
+        ("LNURL_ADMIN_TLS_CERT", cert_pem),
+        ("LNURL_ADMIN_TLS_KEY", key_pem),
+        client_ca_pem,
+    )
+}
+
+/// A TLS configuration for the public listener from the PEM certificate
+/// chain and key, `source` naming them in errors
+pub fn public_tls_config(
+    source: &str,
+    cert_pem: &[u8],
+    key_pem: &[u8],
+) -> Result<ServerConfig, ListenerError> {
+    server_config(
+        (&format!("{} certificate", source), cert_pem),
+        (&format!("{} key", source), key_pem),
+        None,
+    )
+}
+
+/// The public listener's TLS configuration from config.toml's [tls] files
+pub fn load_public_tls(cert: &Path, key: &Path) -> Result<ServerConfig, ListenerError> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| ListenerError(format!("TLS {}: {}", path.display(), e)))
+    };
+    public_tls_config(
+        &format!("TLS {}", cert.display()),
+        &read(cert)?,
+        &read(key)?,
+    )
+}
+
+fn server_config(
+    (cert_name, cert_pem): (&str, &[u8]),
+    (key_name, key_pem): (&str, &[u8]),
+    client_ca_pem: Option<&[u8]>,
+) -> Result<ServerConfig, ListenerError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| ListenerError(e.to_string()))?;
+    let builder = match client_ca_pem {
+        Some(pem) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates("LNURL_ADMIN_TLS_CLIENT_CA", pem)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| ListenerError(format!("LNURL_ADMIN_TLS_CLIENT_CA: {}", e)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| ListenerError(format!("LNURL_ADMIN_TLS_CLIENT_CA: {}", e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let cert = certificates(cert_name, cert_pem)?;
+    let key = private_key(key_name, key_pem)?;
+    builder
+        .with_single_cert(cert, key)
+        .map_err(|e| ListenerError(format!("{}: {}", key_name, e)))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Reloads `tls` from the certificate files whenever they change, see the top
+/// of the file
+pub async fn watch_tls_files(tls: RustlsConfig, cert: PathBuf, key: PathBuf, every: Duration) {
+    let mut seen = (modified(&cert), modified(&key));
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let now = (modified(&cert), modified(&key));
+        if now == seen {
+            continue;
+        }
+        seen = now;
+        match load_public_tls(&cert, &key) {
+            Ok(config) => {
+                tls.reload_from_config(Arc::new(config));
+                info!("Reloaded the TLS certificate from {}", cert.display());
+            }
+            Err(e) => warn!("Keeping the current TLS certificate: {}", e),
+        }
+    }
+}
+
+/// Serves `app` (the public router) with TLS on `addr` until the process
+/// exits
+pub async fn serve_public_tls(
+    addr: SocketAddr,
+    tls: RustlsConfig,
+    app: Router,
+) -> std::io::Result<()> {
+    // Connect info gives handlers the peer address, for the request screener
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum_server::bind_rustls(addr, tls).serve(app).await
+}
+
+/// Reads LNURL_ADMIN_ADDR and the LNURL_ADMIN_TLS_* files, None when the
+/// admin API stays on the public listener
+pub fn load_admin_listener() -> Result<Option<AdminListener>, ListenerError> {
+    let var = |name| {
+        std::env::var(name)
+            .ok()
+            .filter(|value: &String| !value.is_empty())
+    };
+    let tls_vars = (
+        var("LNURL_ADMIN_TLS_CERT"),
+        var("LNURL_ADMIN_TLS_KEY"),
+        var("LNURL_ADMIN_TLS_CLIENT_CA"),
+    );
+    let Some(addr) = var("LNURL_ADMIN_ADDR") else {
+        if tls_vars != (None, None, None) {
+            return Err(ListenerError(
+                "LNURL_ADMIN_TLS_* need LNURL_ADMIN_ADDR, the admin listener they secure"
+                    .to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|e| ListenerError(format!("Invalid LNURL_ADMIN_ADDR {}: {}", addr, e)))?;
+
+    let mutual = tls_vars.2.is_some();
+    let tls = match tls_vars {
+        (None, None, None) => None,
+        (Some(cert), Some(key), client_ca) => {
+            let client_ca = match &client_ca {
+                Some(path) => Some(read("LNURL_ADMIN_TLS_CLIENT_CA", path)?),
+                None => None,
+            };
+            let config = tls_config(
+                &read("LNURL_ADMIN_TLS_CERT", &cert)?,
+                &read("LNURL_ADMIN_TLS_KEY", &key)?,
+                client_ca.as_deref(),
+            )?;
+            Some(Arc::new(config))
+        }
+        _ => {
+            return Err(ListenerError(
+                "LNURL_ADMIN_TLS_CERT and LNURL_ADMIN_TLS_KEY must be set together".to_string(),
+            ))
+        }
+    };
+    Ok(Some(AdminListener { addr, tls, mutual }))
+}
+
+/// Serves `app` (the admin router) on the listener until the process exits
+pub async fn serve_admin(listener: AdminListener, app: Router) -> std::io::Result<()> {
+    // Connect info feeds the LNURL_ADMIN_ALLOW_CIDRS check
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    match listener.tls {
+        Some(config) => {
+            axum_server::bind_rustls(listener.addr, RustlsConfig::from_config(config))
+                .serve(app)
+                .await
+        }
+        None => axum_server::bind(listener.addr).serve(app).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+    use rustls::pki_types::ServerName;
+    use rustls::ClientConfig;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    /// A CA, PEM of a certificate for localhost it issued and its key, and
+    /// a client certificate it issued with its key
+    struct Pki {
+        ca: CertificateDer<'static>,
+        ca_pem: String,
+        server_pem: (String, String),
+        client: (CertificateDer<'static>, KeyPair),
+    }
+
+    fn pki() -> Pki {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = params.self_signed(&ca_key).unwrap();
+        let issuer = Issuer::new(params, ca_key);
+        let issue = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            (params.signed_by(&key, &issuer).unwrap(), key)
+        };
+        let (server, server_key) = issue("localhost");
+        let (client, client_key) = issue("operator");
+        Pki {
+            ca: ca.der().clone(),
+            ca_pem: ca.pem(),
+            server_pem: (server.pem(), server_key.serialize_pem()),
+            client: (client.der().clone(), client_key),
+        }
+    }
+
+    fn client_config(pki: &Pki, with_cert: bool) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots.add(pki.ca.clone()).unwrap();
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        match with_cert {
+            true => {
+                let key = PrivateKeyDer::try_from(pki.client.1.serialize_der()).unwrap();
+                builder
+                    .with_client_auth_cert(vec![pki.client.0.clone()], key)
+                    .unwrap()
+            }
+            false => builder.with_no_client_auth(),
+        }
+    }
+
+    /// Whether the server side completes a handshake with the client
+    async fn handshake(server: ServerConfig, client: ClientConfig) -> bool {
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let acceptor = TlsAcceptor::from(Arc::new(server));
+        let connector = TlsConnector::from(Arc::new(client));
+        let name = ServerName::try_from("localhost").unwrap();
+        let (accepted, _) = tokio::join!(
+            acceptor.accept(server_io),
+            connector.connect(name, client_io)
+        );
+        accepted.is_ok()
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_required_with_a_client_ca() {
+        let pki = pki();
+        let (cert, key) = &pki.server_pem;
+        let ca = Some(pki.ca_pem.as_bytes());
+        let mtls = || tls_config(cert.as_bytes(), key.as_bytes(), ca).unwrap();
+
+        assert!(handshake(mtls(), client_config(&pki, true)).await);
+        assert!(!handshake(mtls(), client_config(&pki, false)).await);
+
+        let tls = tls_config(cert.as_bytes(), key.as_bytes(), None).unwrap();
+        assert!(handshake(tls, client_config(&pki, false)).await);
+    }
+
+    #[tokio::test]
+    async fn public_certificates_are_reloaded_when_their_files_change() {
+        let issued = pki();
+        let dir = std::env::temp_dir().join(format!("lnurl-tls-{}", crate::random_hex_32()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, &issued.server_pem.0).unwrap();
+        std::fs::write(&key, &issued.server_pem.1).unwrap();
+
+        let config = load_public_tls(&cert, &key).unwrap();
+        assert!(handshake(config.clone(), client_config(&issued, false)).await);
+        let tls = RustlsConfig::from_config(Arc::new(config));
+        let watch = tokio::spawn(watch_tls_files(
+            tls.clone(),
+            cert.clone(),
+            key.clone(),
+            Duration::from_millis(20),
+        ));
+
+        // A broken renewal keeps the certificate in use
+        let before = tls.get_inner();
+        std::fs::write(&cert, "not pem").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(Arc::ptr_eq(&before, &tls.get_inner()));
+
+        // A good one replaces it, here by another from another CA
+        let renewed = pki();
+        std::fs::write(&cert, &renewed.server_pem.0).unwrap();
+        std::fs::write(&key, &renewed.server_pem.1).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let reloaded = (*tls.get_inner()).clone();
+        assert!(handshake(reloaded.clone(), client_config(&renewed, false)).await);
+        assert!(!handshake(reloaded, client_config(&issued, false)).await);
+
+        watch.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_pem_is_refused() {
+        let pki = pki();
+        let (cert, key) = &pki.server_pem;
+        let error = tls_config(b"not pem", key.as_bytes(), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "LNURL_ADMIN_TLS_CERT: no PEM certificate"
+        );
+        let error = tls_config(cert.as_bytes(), cert.as_bytes(), None).unwrap_err();
+        assert_eq!(error.to_string(), "LNURL_ADMIN_TLS_KEY: no PEM private key");
+        let error = tls_config(cert.as_bytes(), key.as_bytes(), Some(b"")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "LNURL_ADMIN_TLS_CLIENT_CA: no PEM certificate"
+        );
+        let error = public_tls_config("TLS c.pem", key.as_bytes(), key.as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "TLS c.pem certificate: no PEM certificate"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+// =============================================================================
+// LNURL strings (LUD-01)
+// =============================================================================
+//
+// The request endpoints answer JSON, which is what wallets fetch, but many
+// wallets only take a link as a bech32 LNURL1... string or its QR code. Any
+// of them answers `?format=lnurl` with that string instead, in plain text:
+//
+//   GET /request-withdraw?k1=<k1>&format=lnurl  — LNURL1... of
+//       <callback url>request-withdraw?k1=<k1>
+//
+// The LNURL is the same request without `format`, built on the callback URL
+// like the callbacks are, and nothing is issued to make it: the k1 comes when
+// a wallet opens it. So a session token sent along is not carried over; an
+// account's link to share is a voucher or its allowance, whose views hold
+// their LNURL as well.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use lnurl_models::encoding::{encode_lnurl, EncodingError};
+
+use crate::{callback, error_reply, AppState};
+
+const FORMAT_LNURL: &str = "format=lnurl";
+
+/// `url` as an LNURL, in uppercase for denser QR codes
+pub fn encode(url: &str) -> Result<String, EncodingError> {
+    encode_lnurl(url)
+}
+
+/// `url` under a LUD-17 scheme such as lnurlp, the form of LUD-19's links
+pub fn with_scheme(url: &str, scheme: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    format!("{}://{}", scheme, rest)
+}
+
+/// The query without its `format=lnurl` pairs, None when it has none
+fn strip_format(query: &str) -> Option<String> {
+    let pairs: Vec<&str> = query.split('&').collect();
+    if !pairs.contains(&FORMAT_LNURL) {
+        return None;
+    }
+    let rest: Vec<&str> = pairs
+        .into_iter()
+        .filter(|pair| *pair != FORMAT_LNURL && !pair.is_empty())
+        .collect();
+    Some(rest.join("&"))
+}
+
+/// Answers `?format=lnurl` with the LNURL of the request, see the top of the
+/// file; passes anything else on
+pub async fn as_lnurl(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(query) = request.uri().query().and_then(strip_format) else {
+        return next.run(request).await;
+    };
+    // Relative to wherever the router is mounted, as the callback URL is
+    let path = request.uri().path().trim_start_matches('/');
+    let base = callback::base_url(&state, request.headers());
+    let url = match query.is_empty() {
+        true => format!("{}{}", base, path),
+        false => format!("{}{}?{}", base, path, query),
+    };
+    match encode(&url) {
+        Ok(lnurl) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain")],
+            lnurl,
+        )
+            .into_response(),
+        Err(e) => error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Cannot encode {} as an LNURL: {}", url, e),
+        )
+        .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_taken_out_of_the_query() {
+        assert_eq!(strip_format("k1=abc"), None);
+        assert_eq!(strip_format("format=json"), None);
+        assert_eq!(strip_format("format=lnurl"), Some(String::new()));
+        assert_eq!(
+            strip_format("k1=abc&format=lnurl"),
+            Some("k1=abc".to_string())
+        );
+        assert_eq!(
+            strip_format("format=lnurl&allowance=a%2Fb"),
+            Some("allowance=a%2Fb".to_string())
+        );
+    }
+
+    #[test]
+    fn links_take_the_lud17_scheme() {
+        assert_eq!(
+            with_scheme("https://shop.example/lnurl/request-pay", "lnurlp"),
+            "lnurlp://shop.example/lnurl/request-pay"
+        );
+        assert_eq!(
+            with_scheme("http://abc.onion/request-withdraw", "lnurlw"),
+            "lnurlw://abc.onion/request-withdraw"
+        );
+    }
+}
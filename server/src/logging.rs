@@ -0,0 +1,446 @@
+// =============================================================================
+// Logging
+// =============================================================================
+//
+// The server logs through `tracing`, one line per event on stdout:
+//
+//   LNURL_LOG         levels, default "info"; e.g. "debug" or
+//                     "info,lnurl_server::pay=debug,sqlx=warn"
+//   LNURL_LOG_FORMAT  text (default) or json, one object per line for log
+//                     collectors
+//
+// Every request runs in a span carrying an id, the method and the route it
+// matched, so the lines a request causes, including those of the payment it
+// starts in the background, can be told apart from a concurrent one's. Its
+// last line gives the status and the time taken; for the PROBES, polled every
+// few seconds, only when they fail, unless at DEBUG. The route is the pattern
+// (/admin/vouchers/:k1), never the path or the query, which carry k1s,
+// signatures and invoices.
+//
+// Those values are bearer secrets or close to it: a k1 spends a withdraw, a
+// session token is a login. Fields named in SECRET_FIELDS are cut to their
+// first few characters whatever the call site, enough to follow one through
+// the logs but not to use it, so logs can be shared or shipped elsewhere.
+// Call sites put such values in fields, not in the message. The admin API's
+// read-only views and debug captures (capture.rs) cut them the same way.
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Instrument, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Fields whose values are never logged, listed to read-only keys or
+/// captured in full
+pub const SECRET_FIELDS: &[&str] = &[
+    "k1",
+    "pr",
+    "bolt11",
+    "invoice",
+    "sig",
+    "signature",
+    "preimage",
+    "token",
+    "secret",
+];
+
+/// Routes whose successful requests are logged at DEBUG only
+const PROBES: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+/// Characters of a redacted value that are kept
+const KEPT_CHARS: usize = 6;
+
+#[derive(Debug)]
+pub struct LogConfigError(String);
+
+impl fmt::Display for LogConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for LogConfigError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// The first KEPT_CHARS characters of `value`, then an ellipsis: enough of
+/// a k1, token or key to tell entries apart and spot repeats, too little to
+/// use it. Values no longer than that are hidden whole.
+pub fn redact(value: &str) -> String {
+    match value.char_indices().nth(KEPT_CHARS) {
+        Some((end, _)) => format!("{}…", &value[..end]),
+        None => "…".to_string(),
+    }
+}
+
+/// Fields of a span or event, message apart, redacted as they are recorded
+#[derive(Clone, Default)]
+struct Fields {
+    message: Option<String>,
+    values: Vec<(&'static str, Value)>,
+}
+
+impl Fields {
+    fn add(&mut self, field: &Field, value: Value) {
+        let value = match (SECRET_FIELDS.contains(&field.name()), value) {
+            (true, Value::String(value)) => Value::String(redact(&value)),
+            (true, _) => Value::String("…".to_string()),
+            (false, value) => value,
+        };
+        match field.name() {
+            "message" => self.message = value.as_str().map(str::to_string),
+            name => self.values.push((name, value)),
+        }
+    }
+
+    fn text(&self, out: &mut String) {
+        for (name, value) in &self.values {
+            if !out.is_empty() && !out.ends_with('{') {
+                out.push(' ');
+            }
+            let value = match value {
+                Value::String(value) if value.is_empty() || value.contains([' ', '"', '=']) => {
+                    format!("{:?}", value)
+                }
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            out.push_str(&format!("{}={}", name, value));
+        }
+    }
+
+    fn json(&self, object: &mut Map<String, Value>) {
+        for (name, value) in &self.values {
+            object.insert(name.to_string(), value.clone());
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.add(field, Value::String(value.to_string()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.add(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.add(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.add(field, Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.add(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.add(field, Value::String(format!("{:?}", value)));
+    }
+}
+
+/// Writes each event as a line of `format` to what `make_writer` gives
+pub struct LogLayer<W> {
+    format: LogFormat,
+    make_writer: W,
+}
+
+impl<W> LogLayer<W> {
+    pub fn new(format: LogFormat, make_writer: W) -> LogLayer<W> {
+        LogLayer {
+            format,
+            make_writer,
+        }
+    }
+}
+
+impl<S, W> Layer<S> for LogLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let spans: Vec<(&'static str, Fields)> = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| {
+                        let fields = span.extensions().get::<Fields>().cloned();
+                        (span.name(), fields.unwrap_or_default())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut timestamp = String::new();
+        let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+        let metadata = event.metadata();
+        let mut line = match self.format {
+            LogFormat::Text => {
+                let mut line = format!("{} {:>5} ", timestamp, metadata.level());
+                for (name, fields) in &spans {
+                    line.push_str(name);
+                    if !fields.values.is_empty() {
+                        line.push('{');
+                        fields.text(&mut line);
+                        line.push('}');
+                    }
+                    line.push_str(": ");
+                }
+                line.push_str(fields.message.as_deref().unwrap_or_default());
+                let mut rest = String::new();
+                fields.text(&mut rest);
+                if !rest.is_empty() {
+                    line.push(' ');
+                    line.push_str(&rest);
+                }
+                line
+            }
+            LogFormat::Json => {
+                let mut object = Map::new();
+                object.insert("timestamp".to_string(), Value::String(timestamp));
+                object.insert("level".to_string(), metadata.level().as_str().into());
+                object.insert("target".to_string(), metadata.target().into());
+                if let Some(message) = fields.message.take() {
+                    object.insert("message".to_string(), Value::String(message));
+                }
+                fields.json(&mut object);
+                if !spans.is_empty() {
+                    let spans = spans.iter().map(|(name, fields)| {
+                        let mut span = Map::new();
+                        span.insert("name".to_string(), (*name).into());
+                        fields.json(&mut span);
+                        Value::Object(span)
+                    });
+                    object.insert("spans".to_string(), Value::Array(spans.collect()));
+                }
+                Value::Object(object).to_string()
+            }
+        };
+        line.push('\n');
+        let _ = self.make_writer.make_writer().write_all(line.as_bytes());
+    }
+}
+
+/// The levels and format configured in the environment
+pub fn load_log_config() -> Result<(Targets, LogFormat), LogConfigError> {
+    let var = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let levels = var("LNURL_LOG").unwrap_or_else(|| "info".to_string());
+    let targets = levels.parse::<Targets>().map_err(|e| {
+        LogConfigError(format!(
+            "LNURL_LOG must be levels such as \"info\" ({}), got {}",
+            e, levels
+        ))
+    })?;
+    let format = match var("LNURL_LOG_FORMAT").as_deref() {
+        None | Some("text") => LogFormat::Text,
+        Some("json") => LogFormat::Json,
+        Some(other) => {
+            return Err(LogConfigError(format!(
+                "LNURL_LOG_FORMAT must be text or json, got {}",
+                other
+            )))
+        }
+    };
+    Ok((targets, format))
+}
+
+/// Sends the process's events to stdout, as configured in the environment
+pub fn init() -> Result<(), LogConfigError> {
+    let (targets, format) = load_log_config()?;
+    tracing_subscriber::registry()
+        .with(LogLayer::new(format, std::io::stdout).with_filter(targets))
+        .try_init()
+        .map_err(|e| LogConfigError(e.to_string()))
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Middleware running each request in its span, see the top of the file
+pub async fn request_span(request: Request, next: Next) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+    let probe = PROBES.contains(&route.as_str());
+    let span = tracing::info_span!(
+        "request",
+        id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        method = %request.method(),
+        route,
+    );
+    async move {
+        let started = Instant::now();
+        let response = next.run(request).await;
+        let status = response.status().as_u16();
+        let ms = started.elapsed().as_millis() as u64;
+        match (response.status(), probe) {
+            (code, _) if code.is_server_error() => {
+                tracing::event!(Level::WARN, status, ms, "served")
+            }
+            (code, true) if code.is_success() => {
+                tracing::event!(Level::DEBUG, status, ms, "served")
+            }
+            _ => tracing::event!(Level::INFO, status, ms, "served"),
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<String> {
+            let bytes = self.0.lock().unwrap().clone();
+            String::from_utf8(bytes)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    fn subscriber(format: LogFormat, levels: &str) -> (impl Subscriber, Buffer) {
+        let buffer = Buffer::default();
+        let out = buffer.clone();
+        let layer = LogLayer::new(format, move || out.clone());
+        let subscriber = tracing_subscriber::registry()
+            .with(layer.with_filter(levels.parse::<Targets>().unwrap()));
+        (subscriber, buffer)
+    }
+
+    #[test]
+    fn secrets_are_redacted_whatever_the_format() {
+        let k1 = "a".repeat(64);
+        for format in [LogFormat::Text, LogFormat::Json] {
+            let (subscriber, buffer) = subscriber(format, "info");
+            tracing::subscriber::with_default(subscriber, || {
+                let span = tracing::info_span!("payment", k1 = %k1);
+                let _entered = span.enter();
+                tracing::info!(pr = "lntb1u1pjabcdefgh", msat = 1000u64, "Withdraw paid");
+                tracing::debug!("not at info");
+            });
+            let lines = buffer.lines();
+            assert_eq!(lines.len(), 1);
+            assert!(!lines[0].contains(&k1), "{}", lines[0]);
+            assert!(!lines[0].contains("lntb1u1pjabcdefgh"), "{}", lines[0]);
+            match format {
+                LogFormat::Text => assert!(
+                    lines[0]
+                        .ends_with(" INFO payment{k1=aaaaaa…}: Withdraw paid pr=lntb1u… msat=1000"),
+                    "{}",
+                    lines[0]
+                ),
+                LogFormat::Json => {
+                    let line: Value = serde_json::from_str(&lines[0]).unwrap();
+                    assert_eq!(line["level"], "INFO");
+                    assert_eq!(line["message"], "Withdraw paid");
+                    assert_eq!(line["pr"], "lntb1u…");
+                    assert_eq!(line["msat"], 1000);
+                    assert_eq!(line["spans"][0]["name"], "payment");
+                    assert_eq!(line["spans"][0]["k1"], "aaaaaa…");
+                }
+            }
+        }
+        assert_eq!(redact("short"), "…");
+        assert_eq!(redact("ééééééééé"), "éééééé…");
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_by_route_not_path() {
+        let (subscriber, buffer) = subscriber(LogFormat::Json, "info");
+        let _default = tracing::subscriber::set_default(subscriber);
+        let app = Router::new()
+            .route(
+                "/vouchers/:k1",
+                get(|| async {
+                    tracing::info!("voided");
+                    "ok"
+                }),
+            )
+            .layer(axum::middleware::from_fn(request_span));
+        let secret = "b".repeat(64);
+        let request = Request::get(format!("/vouchers/{}?sig={}", secret, secret))
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| !line.contains(&secret)));
+        let served: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(served["message"], "served");
+        assert_eq!(served["status"], 200);
+        assert_eq!(served["spans"][0]["route"], "/vouchers/:k1");
+        assert_eq!(served["spans"][0]["method"], "GET");
+        let voided: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(voided["spans"][0]["id"], served["spans"][0]["id"]);
+    }
+}
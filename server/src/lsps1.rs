@@ -0,0 +1,540 @@
+// =============================================================================
+// LSPS1 channel orders
+// =============================================================================
+//
+// Wallets that speak LSPS1 (bLIP-51) rather than LNURL-channel buy channels
+// here, over plain HTTP with LSPS1's messages:
+//
+//   GET  /lsps1/get_info                — what channels are on sale
+//   POST /lsps1/create_order            — an order, and the bolt11 to pay
+//   GET  /lsps1/get_order?order_id=<id> — where the order stands
+//
+// Orders are priced like paid LUD-02 opens (pricing.rs, the funding fee alone
+// when channels are free there), rounded up to the sat, and are funded through
+// the same path as /open-channel: the request screener and channel policy
+// decide on them when they are created, before anything is paid. Over HTTP
+// the server can't tell which node is asking, so create_order takes the
+// client's node id as `public_key`. The client must be connected to the node
+// once it has paid.
+//
+// A paid order is funded by the next get_order or by a background job (every
+// DELIVERY_CHECK_EVERY), whichever comes first. When funding fails, e.g. the
+// client isn't connected, it is tried again until a day after the order was
+// made; then the order fails and refunding it is up to the operator. Only
+// bolt11 payments are offered and no client balance is pushed.
+
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::backend::InvoiceStatus;
+use crate::policy::{ChannelOpen, Screened};
+use crate::pricing::INVOICE_EXPIRY_SECS;
+use crate::storage::{Order, OrderChannel, OrderState};
+use crate::{text, AppState, Peer, NODE_URI};
+
+pub const DELIVERY_CHECK_EVERY: Duration = Duration::from_secs(30);
+const GIVE_UP_AFTER: u64 = 24 * 60 * 60;
+const MIN_CAPACITY_SAT: u64 = 20_000;
+const MIN_REQUIRED_CONFIRMATIONS: u32 = 3; // no zero-conf
+const MIN_FUNDING_CONFIRMS_WITHIN_BLOCKS: u32 = 6; // what the opening feerate aims at
+const MAX_CHANNEL_EXPIRY_BLOCKS: u32 = 13_140; // about three months
+
+// LSPS1 error codes
+const INVALID_PARAMS: i32 = -32602;
+const INTERNAL_ERROR: i32 = -32603;
+const CLIENT_REJECTED: i32 = 1;
+const OPTION_MISMATCH: i32 = 100;
+const NOT_FOUND: i32 = 101;
+
+/// LSPS0 sends sat amounts as strings
+mod sat_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(sat: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(sat)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lsps1Error {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+type ErrorReply = (StatusCode, Json<Lsps1Error>);
+
+fn error_reply(status: StatusCode, code: i32, message: &str, data: Option<Value>) -> ErrorReply {
+    let error = Lsps1Error {
+        code,
+        message: message.to_string(),
+        data,
+    };
+    (status, Json(error))
+}
+
+fn option_mismatch(property: &str) -> ErrorReply {
+    let data = json!({ "property": property });
+    error_reply(
+        StatusCode::BAD_REQUEST,
+        OPTION_MISMATCH,
+        "Option mismatch",
+        Some(data),
+    )
+}
+
+fn internal_error(e: impl std::fmt::Display) -> ErrorReply {
+    let data = json!({ "message": e.to_string() });
+    error_reply(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        INTERNAL_ERROR,
+        "Internal error",
+        Some(data),
+    )
+}
+
+/// The label of the invoice paying for order `id`
+pub fn invoice_label(id: &str) -> String {
+    format!("lsps1-{}", id)
+}
+
+/// `unix` as LSPS0 writes datetimes, e.g. 2025-10-09T08:53:20Z
+fn datetime(unix: u64) -> String {
+    // Days to civil date, after Howard Hinnant's `civil_from_days`
+    let z = (unix / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let secs = unix % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+// -----------------------------------------------------------------------------
+// GET /lsps1/get_info
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    uris: Vec<String>,
+    min_required_channel_confirmations: u32,
+    min_funding_confirms_within_blocks: u32,
+    supports_zero_channel_reserve: bool,
+    max_channel_expiry_blocks: u32,
+    #[serde(with = "sat_string")]
+    min_initial_client_balance_sat: u64,
+    #[serde(with = "sat_string")]
+    max_initial_client_balance_sat: u64,
+    #[serde(with = "sat_string")]
+    min_initial_lsp_balance_sat: u64,
+    #[serde(with = "sat_string")]
+    max_initial_lsp_balance_sat: u64,
+    #[serde(with = "sat_string")]
+    min_channel_balance_sat: u64,
+    #[serde(with = "sat_string")]
+    max_channel_balance_sat: u64,
+}
+
+pub async fn get_info(State(state): State<AppState>) -> Json<Info> {
+    let max_sat = state.limits.lock().await.channel_capacity_sat;
+    let min_sat = MIN_CAPACITY_SAT.min(max_sat);
+    Json(Info {
+        uris: NODE_URI.get().into_iter().cloned().collect(),
+        min_required_channel_confirmations: MIN_REQUIRED_CONFIRMATIONS,
+        min_funding_confirms_within_blocks: MIN_FUNDING_CONFIRMS_WITHIN_BLOCKS,
+        supports_zero_channel_reserve: false,
+        max_channel_expiry_blocks: MAX_CHANNEL_EXPIRY_BLOCKS,
+        min_initial_client_balance_sat: 0,
+        max_initial_client_balance_sat: 0,
+        min_initial_lsp_balance_sat: min_sat,
+        max_initial_lsp_balance_sat: max_sat,
+        min_channel_balance_sat: min_sat,
+        max_channel_balance_sat: max_sat,
+    })
+}
+
+// -----------------------------------------------------------------------------
+// Orders as LSPS1 shows them
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Serialize)]
+pub struct OrderReply {
+    order_id: String,
+    #[serde(with = "sat_string")]
+    lsp_balance_sat: u64,
+    #[serde(with = "sat_string")]
+    client_balance_sat: u64,
+    required_channel_confirmations: u32,
+    funding_confirms_within_blocks: u32,
+    channel_expiry_blocks: u32,
+    token: String,
+    created_at: String,
+    announce_channel: bool,
+    order_state: &'static str,
+    payment: PaymentReply,
+    channel: Option<ChannelReply>,
+}
+
+#[derive(Debug, Serialize)]
+struct PaymentReply {
+    bolt11: Bolt11Reply,
+    onchain: Option<Value>, // not offered
+}
+
+#[derive(Debug, Serialize)]
+struct Bolt11Reply {
+    state: &'static str,
+    expires_at: String,
+    #[serde(with = "sat_string")]
+    fee_total_sat: u64,
+    #[serde(with = "sat_string")]
+    order_total_sat: u64,
+    invoice: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChannelReply {
+    funded_at: String,
+    funding_outpoint: String,
+    expires_at: String,
+}
+
+fn reply(order: Order) -> Json<OrderReply> {
+    let order_state = match order.state {
+        OrderState::Created | OrderState::Funding => "CREATED",
+        OrderState::Completed => "COMPLETED",
+        OrderState::Failed => "FAILED",
+    };
+    // Refunds are the operator's, out of band
+    let payment_state = match order.paid_at {
+        Some(_) => "PAID",
+        None => "EXPECT_PAYMENT",
+    };
+    let channel = order.channel.map(|channel| ChannelReply {
+        funded_at: datetime(channel.funded_at),
+        funding_outpoint: channel.funding_outpoint,
+        expires_at: datetime(channel.funded_at + order.channel_expiry_blocks as u64 * 600),
+    });
+    Json(OrderReply {
+        order_id: order.id,
+        lsp_balance_sat: order.capacity_sat,
+        client_balance_sat: 0,
+        required_channel_confirmations: order.required_confirmations,
+        funding_confirms_within_blocks: order.funding_confirms_within_blocks,
+        channel_expiry_blocks: order.channel_expiry_blocks,
+        token: order.token,
+        created_at: datetime(order.created_at),
+        announce_channel: order.announce,
+        order_state,
+        payment: PaymentReply {
+            bolt11: Bolt11Reply {
+                state: payment_state,
+                expires_at: datetime(order.created_at + INVOICE_EXPIRY_SECS),
+                fee_total_sat: order.price_msat / 1000,
+                order_total_sat: order.price_msat / 1000,
+                invoice: order.bolt11,
+            },
+            onchain: None,
+        },
+        channel,
+    })
+}
+
+// -----------------------------------------------------------------------------
+// POST /lsps1/create_order
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrder {
+    public_key: String, // the client's node, which LSPS1 over Lightning knows
+    #[serde(with = "sat_string")]
+    lsp_balance_sat: u64,
+    #[serde(with = "sat_string", default)]
+    client_balance_sat: u64,
+    required_channel_confirmations: u32,
+    funding_confirms_within_blocks: u32,
+    channel_expiry_blocks: u32,
+    #[serde(default)]
+    token: Option<String>,
+    announce_channel: bool,
+}
+
+pub async fn create_order(
+    State(state): State<AppState>,
+    peer: Peer,
+    Json(request): Json<CreateOrder>,
+) -> Result<Json<OrderReply>, ErrorReply> {
+    debug!(
+        public_key = %text::for_log(&request.public_key),
+        lsp_balance_sat = request.lsp_balance_sat,
+        announce_channel = request.announce_channel,
+        "LSPS1 order request"
+    );
+    if cln_rpc::primitives::PublicKey::from_str(&request.public_key).is_err() {
+        let data = json!({ "message": "public_key is not a node id" });
+        return Err(error_reply(
+            StatusCode::BAD_REQUEST,
+            INVALID_PARAMS,
+            "Invalid params",
+            Some(data),
+        ));
+    }
+
+    let limits = state.limits.lock().await.clone();
+    let max_capacity_sat = state
+        .channel_policy
+        .max_capacity_sat(&request.public_key, &limits)
+        .await;
+    let capacity_sat = request.lsp_balance_sat;
+    if capacity_sat < MIN_CAPACITY_SAT.min(max_capacity_sat) || capacity_sat > max_capacity_sat {
+        return Err(option_mismatch("lsp_balance_sat"));
+    }
+    if request.client_balance_sat != 0 {
+        return Err(option_mismatch("client_balance_sat"));
+    }
+    if request.required_channel_confirmations < MIN_REQUIRED_CONFIRMATIONS {
+        return Err(option_mismatch("required_channel_confirmations"));
+    }
+    if request.funding_confirms_within_blocks < MIN_FUNDING_CONFIRMS_WITHIN_BLOCKS {
+        return Err(option_mismatch("funding_confirms_within_blocks"));
+    }
+    if request.channel_expiry_blocks > MAX_CHANNEL_EXPIRY_BLOCKS {
+        return Err(option_mismatch("channel_expiry_blocks"));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let open = ChannelOpen {
+        k1: id.clone(),
+        node_id: request.public_key.clone(),
+        capacity_sat,
+        private: !request.announce_channel,
+    };
+    let peer = peer.map(|ConnectInfo(addr)| addr.ip());
+    let verdict = match state
+        .request_screener
+        .screen(peer, Screened::ChannelFunding)
+        .await
+    {
+        Ok(()) => state.channel_policy.approve(&open).await,
+        refused => refused,
+    };
+    if let Err(reason) = verdict {
+        info!("LSPS1 order for {} refused: {}", open.node_id, reason);
+        let data = json!({ "message": reason });
+        return Err(error_reply(
+            StatusCode::FORBIDDEN,
+            CLIENT_REJECTED,
+            "Client rejected",
+            Some(data),
+        ));
+    }
+
+    let pricing = state.channel_pricing.unwrap_or_default();
+    let feerate_perkb = state
+        .backend
+        .opening_feerate_perkb()
+        .await
+        .map_err(internal_error)?;
+    let price_msat = pricing
+        .price_msat(capacity_sat, feerate_perkb)
+        .div_ceil(1000)
+        * 1000;
+    let bolt11 = state
+        .backend
+        .create_invoice(
+            price_msat,
+            &invoice_label(&id),
+            &format!("Inbound channel of {} sats", capacity_sat),
+            INVOICE_EXPIRY_SECS,
+        )
+        .await
+        .map_err(internal_error)?;
+
+    let order = Order {
+        id,
+        node_id: request.public_key,
+        capacity_sat,
+        announce: request.announce_channel,
+        required_confirmations: request.required_channel_confirmations,
+        funding_confirms_within_blocks: request.funding_confirms_within_blocks,
+        channel_expiry_blocks: request.channel_expiry_blocks,
+        token: request.token.unwrap_or_default(),
+        price_msat,
+        bolt11,
+        state: OrderState::Created,
+        created_at: crate::unix_now(),
+        paid_at: None,
+        channel: None,
+    };
+    state
+        .storage
+        .insert_order(&order)
+        .await
+        .map_err(internal_error)?;
+    info!(
+        "LSPS1 order {}: {} sat channel to {} for {} msat",
+        order.id, order.capacity_sat, order.node_id, order.price_msat
+    );
+    Ok(reply(order))
+}
+
+// -----------------------------------------------------------------------------
+// GET /lsps1/get_order?order_id=<id>
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct GetOrderParams {
+    order_id: String,
+}
+
+pub async fn get_order(
+    State(state): State<AppState>,
+    Query(params): Query<GetOrderParams>,
+) -> Result<Json<OrderReply>, ErrorReply> {
+    let order = state
+        .storage
+        .get_order(&params.order_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error_reply(StatusCode::NOT_FOUND, NOT_FOUND, "Not found", None))?;
+    Ok(reply(advance(&state, order).await))
+}
+
+// -----------------------------------------------------------------------------
+// Delivery
+// -----------------------------------------------------------------------------
+
+/// Moves a Created order along: funds it once paid, fails it once its
+/// invoice expired unpaid. Returns the order as it now stands.
+pub async fn advance(state: &AppState, mut order: Order) -> Order {
+    if order.state != OrderState::Created {
+        return order;
+    }
+    match state
+        .backend
+        .invoice_status(&invoice_label(&order.id))
+        .await
+    {
+        Ok(Some(InvoiceStatus::Paid)) => deliver(state, order).await,
+        Ok(Some(InvoiceStatus::Expired)) => {
+            order.state = OrderState::Failed;
+            if let Err(e) = state.storage.update_order(&order).await {
+                error!("Failed to expire order {}: {}", order.id, e);
+            }
+            order
+        }
+        Ok(_) => order,
+        Err(e) => {
+            error!("Failed to check the invoice of order {}: {}", order.id, e);
+            order
+        }
+    }
+}
+
+/// Funds a paid order, unless another caller (or replica) already is
+async fn deliver(state: &AppState, mut order: Order) -> Order {
+    let now = crate::unix_now();
+    match state.storage.claim_order(&order.id, now).await {
+        Ok(true) => {}
+        Ok(false) => return order,
+        Err(e) => {
+            error!("Failed to claim order {}: {}", order.id, e);
+            return order;
+        }
+    }
+    order.paid_at = order.paid_at.or(Some(now));
+
+    let open = ChannelOpen {
+        k1: order.id.clone(),
+        node_id: order.node_id.clone(),
+        capacity_sat: order.capacity_sat,
+        private: !order.announce,
+    };
+    let funded = match cln_rpc::primitives::PublicKey::from_str(&order.node_id) {
+        Ok(node_id) => state.fund_channel(node_id, &open).await,
+        Err(e) => Err(format!("Invalid node id: {}", e).into()),
+    };
+    order.state = match funded {
+        Ok(funded) => {
+            order.channel = Some(OrderChannel {
+                channel_id: funded.channel_id,
+                funding_outpoint: format!("{}:{}", funded.txid, funded.outnum),
+                funded_at: now,
+            });
+            OrderState::Completed
+        }
+        Err(e) if now < order.created_at + GIVE_UP_AFTER => {
+            error!("Failed to fund order {}, will retry: {}", order.id, e);
+            OrderState::Created
+        }
+        Err(e) => {
+            warn!("Giving up on order {}, refund it: {}", order.id, e);
+            OrderState::Failed
+        }
+    };
+    // Left in Funding when this fails, so that it is never funded twice
+    if let Err(e) = state.storage.update_order(&order).await {
+        error!("Failed to save order {}: {}", order.id, e);
+    }
+    order
+}
+
+/// Advances every Created order every `every`, each under the write gate
+/// (shared), as requests are: a backup or restore never lands between an
+/// order's claim, its funding and its update, which could fund it twice
+pub async fn run(state: AppState, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        let orders = match state.storage.list_orders(OrderState::Created).await {
+            Ok(orders) => orders,
+            Err(e) => {
+                error!("Failed to list LSPS1 orders: {}", e);
+                continue;
+            }
+        };
+        for order in orders {
+            let _writing = state.write_gate.read().await;
+            advance(&state, order).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datetimes_are_utc() {
+        assert_eq!(datetime(0), "1970-01-01T00:00:00Z");
+        assert_eq!(datetime(1_760_000_000), "2025-10-09T08:53:20Z");
+        // Leap day, last second
+        assert_eq!(datetime(951_868_799), "2000-02-29T23:59:59Z");
+    }
+}
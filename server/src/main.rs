@@ -1,611 +1,426 @@
-use axum::{
-    routing::get,
-    http::StatusCode,
-    Json, Router,
-    extract::{Query, State},
+use axum::middleware;
+use axum_server::tls_rustls::RustlsConfig;
+use clap::{Parser, Subcommand};
+use lnurl_server::backend::{Backend, ClnBackend};
+use lnurl_server::capture::{self, Capture, CaptureConfig};
+use lnurl_server::check::{self, CheckOptions};
+use lnurl_server::config::{Config, Settings, Tls};
+use lnurl_server::crypto::{self, FieldCipher};
+use lnurl_server::notify::{self, NotificationKind};
+use lnurl_server::storage::{self, MemoryStorage, PostgresStorage, Storage};
+use lnurl_server::{
+    admin, admin_app, allowance, app, callback, expiry, fees, health, liquidity, listener, logging,
+    lsps1, pay, pricing, public_app, screen, text, throttle, webhook, AppState, NODE_URI,
 };
-use cln_rpc::{self, primitives::Sha256};
-use cln_rpc::model::requests::FundchannelRequest;
-use cln_rpc::primitives::{Amount, AmountOrAll};
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-use std::str::FromStr;
-use std::sync::{Arc, OnceLock};
-use std::collections::HashSet;
-use tokio::sync::Mutex;
-use rand::RngCore;
-
-type SharedClient = Arc<Mutex<cln_rpc::ClnRpc>>;
-type SharedK1Store = Arc<Mutex<HashSet<String>>>;
-
-#[derive(Clone)]
-struct AppState {
-    client: SharedClient,
-    k1_store: SharedK1Store,
-}
-
-const CHANNEL_REQUEST_TAG: &str = "channelRequest";
-const WITHDRAW_REQUEST_TAG: &str = "withdrawRequest";
-const DEFAULT_DESCRIPTION: &str = "Withdrawal from service";
-
-// ⚠️ UPDATE THESE to match your actual machine
-//const IP_ADDRESS: &str = "192.168.27.72:9735";
-
-const IP_ADDRESS: &str = "192.168.27.72:49735";
-const CALLBACK_URL: &str = "http://192.168.27.72:3000/";
-
-static NODE_URI: OnceLock<String> = OnceLock::new();
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, info_span, Instrument};
 
 // =============================================================================
-// request-channel (LUD-02)
+// Main
 // =============================================================================
 
-#[derive(Debug, Serialize)]
-struct RequestChannelResponse {
-    uri: &'static str,
-    callback: String,
-    k1: String,
-    tag: &'static str,
-}
+/// LNURL server in front of a CLN node
+#[derive(Debug, Parser)]
+#[command(name = "lnurl-server", version)]
+struct Cli {
+    /// Config file [default: config.toml, if there is one]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
 
-async fn request_channel(
-    State(state): State<AppState>,
-) -> (StatusCode, Json<RequestChannelResponse>) {
-    println!("Request channel received");
-    let k1 = Uuid::new_v4().to_string();
+    #[command(flatten)]
+    settings: Settings,
 
-    {
-        let mut k1_store = state.k1_store.lock().await;
-        k1_store.insert(k1.clone());
-    }
+    /// Let a plain http callback URL through with a warning, for a dev setup
+    #[arg(long, global = true)]
+    allow_insecure_http: bool,
 
-    let response = RequestChannelResponse {
-        uri: NODE_URI.get().expect("NODE_URI should be set at startup"),
-        callback: format!("{}open-channel", CALLBACK_URL),
-        k1,
-        tag: CHANNEL_REQUEST_TAG,
-    };
+    /// Record the exchanges to a file in this directory
+    #[arg(long, value_name = "DIR")]
+    capture: Option<PathBuf>,
 
-    println!("Request channel response: {:?}", response);
-    (StatusCode::OK, Json(response))
-}
+    /// How long to capture for, in seconds
+    #[arg(long, value_name = "SECS", requires = "capture")]
+    capture_for: Option<u64>,
+
+    /// Only capture the exchanges of this k1
+    #[arg(long, value_name = "K1", requires = "capture")]
+    capture_k1: Option<String>,
+
+    /// Capture bodies as they are, without masking
+    #[arg(long, requires = "capture")]
+    capture_raw: bool,
 
-// GET /open-channel?remoteid=<pubkey>&k1=<k1>&private=<bool>
-#[derive(Debug, Deserialize)]
-struct OpenChannelParams {
-    remoteid: String,
-    k1: String,
-    #[serde(default)]
-    private: Option<bool>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Serialize, Default)]
-struct OpenChannelResponse {
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    reason: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    mindepth: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    channel_id: Option<Sha256>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    outnum: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tx: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    txid: Option<String>,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Go through the setup without serving anything
+    Check {
+        /// Also fetch a token through the callback URL, needs the listen
+        /// address free
+        #[arg(long)]
+        self_request: bool,
+    },
 }
 
-async fn open_channel(
-    State(state): State<AppState>,
-    Query(params): Query<OpenChannelParams>,
-) -> (StatusCode, Json<OpenChannelResponse>) {
-    println!("Open channel request received");
-    println!("Params: {:?}", params);
-
-    // Validate and consume k1 (single-use)
-    let k1_valid = {
-        let mut k1_store = state.k1_store.lock().await;
-        k1_store.remove(&params.k1)
+/// `lnurl-server check`, see check.rs
+async fn run_check(config: Config, allow_insecure_http: bool, self_request: bool) -> ! {
+    let options = CheckOptions {
+        config,
+        database_url: std::env::var("LNURL_DATABASE_URL").ok(),
+        allow_insecure_http,
+        self_request,
     };
+    let report = check::run(&options).await;
+    println!();
+    println!("{}", report);
+    std::process::exit(if report.passed() { 0 } else { 1 });
+}
 
-    if !k1_valid {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(OpenChannelResponse {
-                status: "ERROR".to_string(),
-                reason: Some("Invalid or already used k1".to_string()),
-                ..Default::default()
-            }),
-        );
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = logging::init() {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
-
-    let node_id = match params.remoteid.parse() {
-        Ok(id) => id,
+    let config = match Config::load(cli.config.as_deref(), cli.settings) {
+        Ok(config) => config,
         Err(e) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(OpenChannelResponse {
-                    status: "ERROR".to_string(),
-                    reason: Some(format!("Invalid node id: {}", e)),
-                    ..Default::default()
-                }),
-            );
+            error!("{}", e);
+            std::process::exit(1);
         }
     };
-
-    let amount = AmountOrAll::Amount(Amount::from_sat(100_000));
-
-    let request = FundchannelRequest {
-        id: node_id,
-        amount,
-        announce: params.private,
-        feerate: None,
-        minconf: None,
-        mindepth: None,
-        utxos: None,
-        push_msat: None,
-        close_to: None,
-        request_amt: None,
-        compact_lease: None,
-        reserve: None,
-        channel_type: None,
-    };
-
-    let mut client_guard = state.client.lock().await;
-    match client_guard
-        .call(cln_rpc::Request::FundChannel(request))
-        .await
-    {
-        Ok(cln_rpc::Response::FundChannel(response)) => (
-            StatusCode::OK,
-            Json(OpenChannelResponse {
-                status: "OK".to_string(),
-                reason: None,
-                mindepth: Some(response.mindepth.unwrap()),
-                channel_id: Some(response.channel_id),
-                outnum: Some(response.outnum),
-                tx: Some(response.tx),
-                txid: Some(response.txid),
-            }),
-        ),
-        Ok(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(OpenChannelResponse {
-                status: "ERROR".to_string(),
-                reason: Some("Unexpected response type".to_string()),
-                ..Default::default()
-            }),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(OpenChannelResponse {
-                status: "ERROR".to_string(),
-                reason: Some(format!("Failed to open channel: {}", e)),
-                ..Default::default()
-            }),
-        ),
-    }
-}
-
-// =============================================================================
-// request-withdraw (LUD-03)
-// =============================================================================
-
-#[derive(Debug, Serialize)]
-#[allow(non_snake_case)]
-struct RequestWithdrawResponse {
-    callback: String,
-    k1: String,
-    tag: &'static str,
-    defaultDescription: &'static str,
-    minWithdrawable: u64, // millisatoshis
-    maxWithdrawable: u64, // millisatoshis
-}
-
-async fn request_withdraw(
-    State(state): State<AppState>,
-) -> (StatusCode, Json<RequestWithdrawResponse>) {
-    println!("Request withdraw received");
-    let k1 = Uuid::new_v4().to_string();
-
-    {
-        let mut k1_store = state.k1_store.lock().await;
-        k1_store.insert(k1.clone());
+    if let Some(Command::Check { self_request }) = cli.command {
+        run_check(config, cli.allow_insecure_http, self_request).await;
     }
 
-    let response = RequestWithdrawResponse {
-        callback: format!("{}withdraw", CALLBACK_URL),
-        k1,
-        tag: WITHDRAW_REQUEST_TAG,
-        defaultDescription: DEFAULT_DESCRIPTION,
-        minWithdrawable: 1_000,       // 1 sat in msats
-        maxWithdrawable: 1_000_000,   // 1000 sats in msats
+    let capture = cli.capture.map(|dir| CaptureConfig {
+        dir,
+        window: cli
+            .capture_for
+            .map_or(capture::DEFAULT_WINDOW, Duration::from_secs),
+        k1: cli.capture_k1,
+        raw: cli.capture_raw,
+    });
+    let callback_url = callback::load_callback_url(&config.callback_url, cli.allow_insecure_http);
+    let callback_url = match callback_url {
+        Ok(url) => url,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
     };
 
-    println!("Request withdraw response: {:?}", response);
-    (StatusCode::OK, Json(response))
-}
-
-// GET /withdraw?k1=<k1>&pr=<bolt11>
-#[derive(Debug, Deserialize)]
-struct WithdrawParams {
-    k1: String,
-    pr: String, // BOLT-11 invoice
-}
+    let rpc_path = &config.rpc_path;
+    info!("CLN RPC at {} ({})", rpc_path.display(), config.network);
 
-#[derive(Serialize, Default)]
-struct WithdrawResponse {
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    reason: Option<String>,
-}
-
-async fn withdraw(
-    State(state): State<AppState>,
-    Query(params): Query<WithdrawParams>,
-) -> (StatusCode, Json<WithdrawResponse>) {
-    println!("Withdraw request received");
-    println!("  k1: {}", params.k1);
-    println!("  pr: {}", params.pr);
-
-    // Validate and consume k1
-    let k1_valid = {
-        let mut k1_store = state.k1_store.lock().await;
-        k1_store.remove(&params.k1)
+    let backend: Arc<dyn Backend> = match ClnBackend::connect(rpc_path).await {
+        Ok(backend) => Arc::new(backend),
+        Err(e) => {
+            error!("Failed to connect to CLN RPC at {}: {}", rpc_path.display(), e);
+            std::process::exit(1);
+        }
     };
 
-    if !k1_valid {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(WithdrawResponse {
-                status: "ERROR".to_string(),
-                reason: Some("Invalid or already used k1".to_string()),
-            }),
-        );
-    }
-
-    // Decode invoice and validate amount
-    let mut client_guard = state.client.lock().await;
-
-    let decode_request = cln_rpc::model::requests::DecodeRequest {
-        string: params.pr.clone(),
+    let storage: Arc<dyn Storage> = match std::env::var("LNURL_DATABASE_URL") {
+        Ok(url) => match PostgresStorage::connect(&url).await {
+            Ok(storage) => {
+                info!("Using PostgreSQL storage");
+                Arc::new(storage)
+            }
+            Err(e) => {
+                error!("Failed to connect to database: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => {
+            info!("LNURL_DATABASE_URL not set, using in-memory storage");
+            Arc::new(MemoryStorage::default())
+        }
     };
 
-    let invoice_amount_msat = match client_guard
-        .call(cln_rpc::Request::Decode(decode_request))
-        .await
-    {
-        Ok(cln_rpc::Response::Decode(decoded)) => {
-            match decoded.amount_msat {
-                Some(amount) => {
-                    let msat = amount.msat();
-                    println!("  Invoice amount: {} msat", msat);
-                    if msat < 1_000 {
-                        return (
-                            StatusCode::BAD_REQUEST,
-                            Json(WithdrawResponse {
-                                status: "ERROR".to_string(),
-                                reason: Some(format!(
-                                    "Amount {} msat below minimum 1000 msat", msat
-                                )),
-                            }),
-                        );
-                    }
-                    if msat > 1_000_000 {
-                        return (
-                            StatusCode::BAD_REQUEST,
-                            Json(WithdrawResponse {
-                                status: "ERROR".to_string(),
-                                reason: Some(format!(
-                                    "Amount {} msat exceeds maximum 1000000 msat", msat
-                                )),
-                            }),
-                        );
-                    }
-                    msat
-                }
-                None => {
-                    return (
-                        StatusCode::BAD_REQUEST,
-                        Json(WithdrawResponse {
-                            status: "ERROR".to_string(),
-                            reason: Some("Invoice has no amount".to_string()),
-                        }),
-                    );
-                }
-            }
+    let cipher: Option<Arc<dyn FieldCipher>> = match crypto::load_cipher() {
+        Ok(Some(cipher)) => {
+            info!("Encryption at rest enabled");
+            Some(Arc::new(cipher))
         }
-        Ok(_) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(WithdrawResponse {
-                    status: "ERROR".to_string(),
-                    reason: Some("Failed to decode invoice".to_string()),
-                }),
-            );
+        Ok(None) => {
+            info!("LNURL_ENCRYPTION_KEY not set, payment preimages will not be stored");
+            None
         }
         Err(e) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(WithdrawResponse {
-                    status: "ERROR".to_string(),
-                    reason: Some(format!("Invalid invoice: {}", e)),
-                }),
-            );
+            error!("{}", e);
+            std::process::exit(1);
         }
     };
 
-    // Pay the invoice asynchronously — return OK immediately, pay in background
-    // Per the LNURL spec: server "attempts to pay the invoice asynchronously"
-    let bolt11 = params.pr.clone();
-    let client_clone = state.client.clone();
-    println!("Accepted withdraw for {} msat, paying asynchronously...", invoice_amount_msat);
-
-    tokio::spawn(async move {
-        let mut client = client_clone.lock().await;
-        let pay_request = cln_rpc::model::requests::PayRequest {
-            bolt11,
-            amount_msat: None,
-            label: None,
-            riskfactor: None,
-            maxfeepercent: Some(1.0),
-            retry_for: Some(60),
-            maxdelay: None,
-            exemptfee: None,
-            localinvreqid: None,
-            exclude: None,
-            maxfee: None,
-            description: None,
-            partial_msat: None,
-        };
-
-        match client.call(cln_rpc::Request::Pay(pay_request)).await {
-            Ok(cln_rpc::Response::Pay(pay_resp)) => {
-                println!("Withdraw payment successful!");
-                println!("  Payment preimage: {:?}", pay_resp.payment_preimage);
-                println!("  Amount sent: {:?}", pay_resp.amount_sent_msat);
-            }
-            Ok(_) => eprintln!("Unexpected response type from pay"),
-            Err(e) => eprintln!("Withdraw payment failed: {}", e),
+    let fee_update = match fees::load_fee_update() {
+        Ok(update) => update,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
         }
-    });
-
-    (
-        StatusCode::OK,
-        Json(WithdrawResponse {
-            status: "OK".to_string(),
-            reason: None,
-        }),
-    )
-}
-
-// =============================================================================
-// lnurl-auth (LUD-04)
-// =============================================================================
-//
-// Flow:
-//   1. GET /auth-challenge  → { k1: "<hex 32 random bytes>" }
-//   2. Client signs k1 with their node key via CLN signmessage
-//   3. GET /auth-response?k1=<k1>&signature=<zbase>&pubkey=<node_pubkey>
-//   4. Server verifies via CLN checkmessage
-//
-// ⚠️  The "catch": CLN checkmessage expects zbase-encoded signatures,
-//     NOT DER-hex as the standard LNURL-auth spec describes.
-//     signmessage returns { signature, recid, zbase } — use the `zbase` field.
-
-#[derive(Debug, Serialize)]
-struct AuthChallengeResponse {
-    k1: String,
-}
-
-async fn auth_challenge(
-    State(state): State<AppState>,
-) -> (StatusCode, Json<AuthChallengeResponse>) {
-    let mut random_bytes = [0u8; 32];
-    rand::thread_rng().fill_bytes(&mut random_bytes);
-    let k1 = random_bytes
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>();
-
-    println!("Auth challenge issued: {}", k1);
-
-    {
-        let mut k1_store = state.k1_store.lock().await;
-        k1_store.insert(k1.clone());
-    }
-
-    (StatusCode::OK, Json(AuthChallengeResponse { k1 }))
-}
-
-#[derive(Debug, Deserialize)]
-struct AuthResponseParams {
-    k1: String,
-    signature: String, // zbase-encoded (NOT DER-hex)
-    pubkey: String,    // hex-encoded compressed node pubkey
-}
+    };
 
-#[derive(Debug, Serialize)]
-struct AuthResult {
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    event: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    reason: Option<String>,
-}
+    let channel_pricing = match pricing::load_pricing() {
+        Ok(pricing) => pricing,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-async fn auth_response(
-    State(state): State<AppState>,
-    Query(params): Query<AuthResponseParams>,
-) -> (StatusCode, Json<AuthResult>) {
-    println!("Auth response received:");
-    println!("  k1: {}", params.k1);
-    println!("  signature (zbase): {}", params.signature);
-    println!("  pubkey: {}", params.pubkey);
-
-    // Validate and consume k1
-    let k1_valid = {
-        let mut k1_store = state.k1_store.lock().await;
-        k1_store.remove(&params.k1)
+    let withdraw_description = match text::load_withdraw_description() {
+        Ok(description) => description,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
     };
 
-    if !k1_valid {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(AuthResult {
-                status: "ERROR".to_string(),
-                event: None,
-                reason: Some("Invalid or expired k1".to_string()),
-            }),
-        );
-    }
+    let pay_config = match pay::load_pay_config() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Validate pubkey format
-    let pubkey = match cln_rpc::primitives::PublicKey::from_str(&params.pubkey) {
-        Ok(pk) => pk,
+    let screener = match screen::load_screener() {
+        Ok(screener) => screener,
         Err(e) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(AuthResult {
-                    status: "ERROR".to_string(),
-                    event: None,
-                    reason: Some(format!("Invalid pubkey: {}", e)),
-                }),
-            );
+            error!("{}", e);
+            std::process::exit(1);
         }
     };
 
-    // Verify signature via CLN checkmessage
-    let check_request = cln_rpc::model::requests::CheckmessageRequest {
-        message: params.k1.clone(),
-        zbase: params.signature.clone(),
-        pubkey: Some(pubkey),
+    let admin_allow = match admin::load_allowlist() {
+        Ok(networks) => networks,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
     };
 
-    let mut client_guard = state.client.lock().await;
-    match client_guard
-        .call(cln_rpc::Request::CheckMessage(check_request))
-        .await
-    {
-        Ok(cln_rpc::Response::CheckMessage(check_resp)) => {
-            if check_resp.verified {
-                println!("Auth SUCCESS for pubkey {}", params.pubkey);
-                (
-                    StatusCode::OK,
-                    Json(AuthResult {
-                        status: "OK".to_string(),
-                        event: Some("LOGGEDIN".to_string()),
-                        reason: None,
-                    }),
-                )
-            } else {
-                println!("Auth FAILED: signature not verified");
-                (
-                    StatusCode::UNAUTHORIZED,
-                    Json(AuthResult {
-                        status: "ERROR".to_string(),
-                        event: None,
-                        reason: Some("Signature verification failed".to_string()),
-                    }),
-                )
-            }
+    let admin_listener = match listener::load_admin_listener() {
+        Ok(Some(listener)) => {
+            info!("Admin API listening on {}", listener);
+            Some(listener)
         }
-        Ok(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(AuthResult {
-                status: "ERROR".to_string(),
-                event: None,
-                reason: Some("Unexpected response from checkmessage".to_string()),
-            }),
-        ),
+        Ok(None) => None,
         Err(e) => {
-            eprintln!("checkmessage error: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(AuthResult {
-                    status: "ERROR".to_string(),
-                    event: None,
-                    reason: Some(format!("Verification error: {}", e)),
-                }),
-            )
+            error!("{}", e);
+            std::process::exit(1);
         }
-    }
-}
-
-// =============================================================================
-// Main
-// =============================================================================
-
-#[tokio::main]
-async fn main() {
-    let home = std::env::var("HOME").expect("HOME env var not set");
-    let rpc_path = format!("{home}/.lightning/testnet4/lightning-rpc");
+    };
 
-    let client = match cln_rpc::ClnRpc::new(&rpc_path).await {
-        Ok(c) => c,
+    let notifications = match notify::load_notifications() {
+        Ok(notifications) => notifications,
         Err(e) => {
-            eprintln!("Failed to connect to CLN RPC at {}: {}", rpc_path, e);
+            error!("{}", e);
             std::process::exit(1);
         }
     };
-
-    let shared_client = Arc::new(Mutex::new(client));
-    let k1_store: SharedK1Store = Arc::new(Mutex::new(HashSet::new()));
-
-    let app_state = AppState {
-        client: shared_client.clone(),
-        k1_store: k1_store.clone(),
+    let auth_webhook = match webhook::load_auth_webhook() {
+        Ok(webhook) => webhook,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
     };
+    if notifications.wants(NotificationKind::NodeUnreachable) {
+        let every = Duration::from_secs(60);
+        let watch = notify::watch_node(backend.clone(), notifications.clone(), every);
+        tokio::spawn(watch.instrument(info_span!("node_watch")));
+    }
+
+    // Swap in a service's own rules here with the with_*_policy methods, see
+    // policy.rs
+    let mut app_state = AppState::new(backend.clone(), storage)
+        .with_callback_url(&callback_url)
+        .with_callback_from_request(config.callback_from_request)
+        .with_limits(config.limits.clone())
+        .with_notifications(notifications)
+        .with_admin_keys(admin::load_keys())
+        .with_admin_allowlist(admin_allow)
+        .with_account_rate_limit(throttle::load_rate_limit())
+        .with_k1_ttls(storage::load_k1_ttls())
+        .with_session_ttl_secs(storage::load_session_ttl_secs())
+        .with_max_block_age(health::load_max_block_age(config.network))
+        .with_pay_config(pay_config);
+    if let Some(cipher) = cipher {
+        app_state = app_state.with_cipher(cipher);
+    }
+    if let Some(pricing) = channel_pricing {
+        app_state = app_state.with_channel_pricing(pricing);
+    }
+    if let Some(description) = withdraw_description {
+        app_state = app_state.with_withdraw_description(&description);
+    }
+    if let Some(screener) = screener {
+        app_state = app_state.with_request_screener(Arc::new(screener));
+    }
+    if let Some(webhook) = auth_webhook {
+        app_state = app_state.with_auth_webhook(webhook);
+    }
 
     // Fetch node pubkey at startup and cache in NODE_URI
-    let node_info = shared_client
-        .lock()
-        .await
-        .call(cln_rpc::Request::Getinfo(
-            cln_rpc::model::requests::GetinfoRequest {},
-        ))
-        .await;
-
-    match node_info {
-        Ok(cln_rpc::model::Response::Getinfo(response)) => {
-            let pubkey = response.id.to_string();
+    match backend.node_id().await {
+        Ok(pubkey) => {
             NODE_URI
-                .set(format!("{}@{}", pubkey, IP_ADDRESS))
+                .set(format!("{}@{}", pubkey, config.announce_addr))
                 .expect("Failed to set NODE_URI");
-            println!("Node initialized: {}", NODE_URI.get().unwrap());
+            info!("Node initialized: {}", NODE_URI.get().unwrap());
         }
         Err(e) => {
-            eprintln!("Failed to get node info: {}", e);
+            error!("Failed to get node info: {}", e);
             std::process::exit(1);
         }
-        _ => {
-            eprintln!("Unexpected response type from getinfo");
+    }
+
+    // Background tasks log in spans of their own, as requests do
+    let allowances = allowance::run(app_state.clone(), allowance::REFILL_CHECK_EVERY);
+    tokio::spawn(allowances.instrument(info_span!("allowances")));
+    let sweeper = expiry::run(app_state.clone(), expiry::SWEEP_EVERY);
+    tokio::spawn(sweeper.instrument(info_span!("expiry")));
+    let orders = lsps1::run(app_state.clone(), lsps1::DELIVERY_CHECK_EVERY);
+    tokio::spawn(orders.instrument(info_span!("lsps1")));
+    if let Some(every) = liquidity::load_interval() {
+        let reports = liquidity::run(app_state.clone(), every);
+        tokio::spawn(reports.instrument(info_span!("liquidity")));
+    }
+    if let Some(update) = fee_update {
+        let setup = fees::run(app_state.clone(), update, fees::CHECK_EVERY);
+        tokio::spawn(setup.instrument(info_span!("fees")));
+    }
+
+    // Optional gRPC twin of the admin API, see grpc.rs
+    #[cfg(feature = "grpc")]
+    if let Ok(addr) = std::env::var("LNURL_GRPC_ADDR") {
+        let addr: std::net::SocketAddr = match addr.parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Invalid LNURL_GRPC_ADDR {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+        let grpc_state = app_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = lnurl_server::grpc::serve(grpc_state, addr).await {
+                error!("gRPC admin service failed: {}", e);
+            }
+        });
+        info!("gRPC admin service listening on {}", addr);
+    }
+
+    // The admin API moves to its own listener when it has one
+    let admin_on_public = admin_listener.is_none();
+    if let Some(listener) = admin_listener {
+        let admin = admin_app(app_state.clone()).layer(middleware::from_fn(logging::request_span));
+        tokio::spawn(async move {
+            if let Err(e) = listener::serve_admin(listener, admin).await {
+                error!("Admin listener failed: {}", e);
+                std::process::exit(1);
+            }
+        });
+    }
+
+    let mut app = match admin_on_public {
+        true => app(app_state),
+        false => public_app(app_state),
+    };
+    app = app.layer(middleware::from_fn(logging::request_span));
+    if let Some(config) = capture {
+        let window = config.window.as_secs();
+        match Capture::start(config) {
+            Ok(capture) => {
+                info!(
+                    "Capturing exchanges to {} for {}s",
+                    capture.path().display(),
+                    window
+                );
+                app = app.layer(middleware::from_fn_with_state(capture, capture::record));
+            }
+            Err(e) => {
+                error!("Failed to start capture: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let scheme = if config.tls.is_some() { "https" } else { "http" };
+    info!("LNURL server listening on {}://{}", scheme, config.listen_addr);
+    if let Some(tls) = &config.tls {
+        info!("TLS: {}", tls);
+    }
+    debug!("Endpoints:");
+    debug!("  GET /request-channel   - LUD-02 channel request");
+    debug!("  GET /open-channel      - LUD-02 channel open callback");
+    debug!("  GET /request-withdraw  - LUD-03 withdraw request");
+    debug!("  GET /withdraw          - LUD-03 withdraw callback");
+    debug!("  GET /withdraw-status   - result of an accepted withdraw's payment");
+    debug!("  GET /request-pay       - LUD-06 pay request");
+    debug!("  GET /pay               - LUD-06 pay callback (invoice)");
+    debug!("  GET /verify/<hash>     - LUD-21 whether a pay invoice was paid");
+    debug!("  GET /.well-known/lnurlp/<user> - LUD-16 Lightning Address");
+    debug!("  GET /auth-challenge    - LUD-04 auth challenge");
+    debug!("  GET /auth-response     - LUD-04 auth verify");
+    debug!("  GET /me                - account info (bearer session token)");
+    debug!("  GET /me/withdrawals    - withdraw history (bearer session token)");
+    debug!("  DELETE /me             - delete account personal data (bearer session token)");
+    debug!("  GET /healthz           - liveness probe");
+    debug!("  GET /readyz            - readiness probe (node reachable and synced)");
+    if admin_on_public {
+        debug!("  /admin/*               - operator API (X-Api-Key)");
+    }
+
+    let tls = match &config.tls {
+        None => None,
+        Some(Tls::Files { cert, key }) => {
+            let tls = match listener::load_public_tls(cert, key) {
+                Ok(tls) => RustlsConfig::from_config(Arc::new(tls)),
+                Err(e) => {
+                    error!("Failed to load the TLS certificate: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let every = listener::TLS_RELOAD_CHECK_EVERY;
+            tokio::spawn(listener::watch_tls_files(tls.clone(), cert.clone(), key.clone(), every));
+            Some(tls)
+        }
+        #[cfg(feature = "acme")]
+        Some(Tls::Acme(acme)) => match lnurl_server::acme::start(acme.clone()).await {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                error!("Failed to get an ACME certificate: {}", e);
+                std::process::exit(1);
+            }
+        },
+        #[cfg(not(feature = "acme"))]
+        Some(Tls::Acme(_)) => unreachable!("config.rs refuses ACME without the acme feature"),
+    };
+    if let Some(tls) = tls {
+        if let Err(e) = listener::serve_public_tls(config.listen_addr, tls, app).await {
+            error!("Failed to listen on {}: {}", config.listen_addr, e);
             std::process::exit(1);
         }
+        return;
     }
 
-    let app = Router::new()
-        // LUD-02: Channel Request
-        .route("/request-channel", get(request_channel))
-        .route("/open-channel", get(open_channel))
-        // LUD-03: Withdraw Request
-        .route("/request-withdraw", get(request_withdraw))
-        .route("/withdraw", get(withdraw))
-        // LUD-04: Auth
-        .route("/auth-challenge", get(auth_challenge))
-        .route("/auth-response", get(auth_response))
-        .with_state(app_state);
-
-    println!("LNURL server listening on 0.0.0.0:3000");
-    println!("Endpoints:");
-    println!("  GET /request-channel   - LUD-02 channel request");
-    println!("  GET /open-channel      - LUD-02 channel open callback");
-    println!("  GET /request-withdraw  - LUD-03 withdraw request");
-    println!("  GET /withdraw          - LUD-03 withdraw callback");
-    println!("  GET /auth-challenge    - LUD-04 auth challenge");
-    println!("  GET /auth-response     - LUD-04 auth verify");
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let listener = match tokio::net::TcpListener::bind(config.listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", config.listen_addr, e);
+            std::process::exit(1);
+        }
+    };
+    // Connect info gives handlers the peer address, for the request screener
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
     axum::serve(listener, app).await.unwrap();
 }
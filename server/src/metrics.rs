@@ -0,0 +1,502 @@
+// =============================================================================
+// Metrics
+// =============================================================================
+//
+// What went in and out of the k1 and session stores, counted per process:
+// k1s issued, consumed, refused (unknown or already used) and expired
+// (presented too late, or swept, see expiry.rs), per flow, and sessions
+// opened. A store-exhaustion attack shows up as k1s
+// issued far faster than they are consumed, or as a run of refusals.
+//
+// The gauges (k1s and sessions held right now) are not kept here; they come
+// from Storage::stats, which is right across replicas and restarts.
+//
+// Next to them, what the flows came to (withdraws accepted, paid and failed,
+// channels opened, logins by outcome) and how long each CLN RPC method takes.
+// GET /metrics (admin.rs) gives all of it in the Prometheus text format, so
+// operators can alert on failed payouts, refused logins or a slow node.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::storage::{K1Purpose, StorageStats};
+
+/// A count since start and over the last full minute
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Rate {
+    pub total: u64,
+    pub last_minute: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    total: u64,
+    minute: u64, // unix minute this_minute counts
+    this_minute: u64,
+    last_minute: u64,
+}
+
+impl Counter {
+    /// Moves the window up to `minute`
+    fn roll(&mut self, minute: u64) {
+        if minute > self.minute {
+            self.last_minute = match minute == self.minute + 1 {
+                true => self.this_minute,
+                false => 0,
+            };
+            self.this_minute = 0;
+            self.minute = minute;
+        }
+    }
+
+    fn record(&mut self, now: u64) {
+        self.roll(now / 60);
+        self.total += 1;
+        self.this_minute += 1;
+    }
+
+    fn rate(&mut self, now: u64) -> Rate {
+        self.roll(now / 60);
+        Rate {
+            total: self.total,
+            last_minute: self.last_minute,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct K1Counters {
+    issued: Counter,
+    consumed: Counter,
+    refused: Counter,
+    expired: Counter,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct K1Rates {
+    pub issued: Rate,
+    pub consumed: Rate,
+    /// Presented to a callback but unknown or already used
+    pub refused: Rate,
+    /// Presented past their TTL, or swept
+    pub expired: Rate,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StoreRates {
+    pub k1s: HashMap<K1Purpose, K1Rates>,
+    pub sessions_opened: Rate,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    k1s: HashMap<K1Purpose, K1Counters>,
+    sessions_opened: Counter,
+}
+
+#[derive(Debug, Default)]
+pub struct StoreMetrics {
+    counters: Mutex<Counters>,
+}
+
+impl StoreMetrics {
+    fn k1(&self, purpose: K1Purpose, now: u64, counter: fn(&mut K1Counters) -> &mut Counter) {
+        let mut counters = self.counters.lock().unwrap();
+        counter(counters.k1s.entry(purpose).or_default()).record(now);
+    }
+
+    pub fn k1_issued(&self, purpose: K1Purpose) {
+        self.k1(purpose, crate::unix_now(), |k1s| &mut k1s.issued);
+    }
+
+    pub fn k1_consumed(&self, purpose: K1Purpose) {
+        self.k1(purpose, crate::unix_now(), |k1s| &mut k1s.consumed);
+    }
+
+    pub fn k1_refused(&self, purpose: K1Purpose) {
+        self.k1(purpose, crate::unix_now(), |k1s| &mut k1s.refused);
+    }
+
+    /// For storage that expires k1s; neither built-in one does yet
+    pub fn k1_expired(&self, purpose: K1Purpose) {
+        self.k1(purpose, crate::unix_now(), |k1s| &mut k1s.expired);
+    }
+
+    pub fn session_opened(&self) {
+        let now = crate::unix_now();
+        self.counters.lock().unwrap().sessions_opened.record(now);
+    }
+
+    pub fn rates(&self) -> StoreRates {
+        self.rates_at(crate::unix_now())
+    }
+
+    fn rates_at(&self, now: u64) -> StoreRates {
+        let mut counters = self.counters.lock().unwrap();
+        let mut k1s = HashMap::new();
+        for purpose in K1Purpose::ALL {
+            let counters = counters.k1s.entry(purpose).or_default();
+            k1s.insert(
+                purpose,
+                K1Rates {
+                    issued: counters.issued.rate(now),
+                    consumed: counters.consumed.rate(now),
+                    refused: counters.refused.rate(now),
+                    expired: counters.expired.rate(now),
+                },
+            );
+        }
+        StoreRates {
+            k1s,
+            sessions_opened: counters.sessions_opened.rate(now),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Flows and node latency
+// -----------------------------------------------------------------------------
+
+/// How an LNURL-auth login attempt ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthOutcome {
+    Success,
+    BadK1,        // unknown, used or expired
+    BadSignature, // not verified, or malformed
+    Denied,       // verified, refused by the AuthHandler
+    Error,        // the node or the storage failed
+}
+
+impl AuthOutcome {
+    const ALL: [AuthOutcome; 5] = [
+        AuthOutcome::Success,
+        AuthOutcome::BadK1,
+        AuthOutcome::BadSignature,
+        AuthOutcome::Denied,
+        AuthOutcome::Error,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuthOutcome::Success => "success",
+            AuthOutcome::BadK1 => "bad_k1",
+            AuthOutcome::BadSignature => "bad_signature",
+            AuthOutcome::Denied => "denied",
+            AuthOutcome::Error => "error",
+        }
+    }
+}
+
+/// Counts of what the flows came to since start
+#[derive(Debug, Default)]
+pub struct FlowMetrics {
+    withdraws_requested: AtomicU64, // callbacks accepted, paying
+    withdraws_paid: AtomicU64,
+    withdraws_failed: AtomicU64,
+    channels_opened: AtomicU64,
+    auth: [AtomicU64; 5], // by AuthOutcome
+}
+
+impl FlowMetrics {
+    pub fn withdraw_requested(&self) {
+        self.withdraws_requested.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn withdraw_paid(&self) {
+        self.withdraws_paid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn withdraw_failed(&self) {
+        self.withdraws_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn channel_opened(&self) {
+        self.channels_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn auth(&self, outcome: AuthOutcome) {
+        self.auth[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Upper bounds of the latency buckets, in seconds; pay may take a minute
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // not cumulative
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// How long the node takes to answer, per RPC method
+#[derive(Debug, Default)]
+pub struct RpcLatency(Mutex<BTreeMap<&'static str, Histogram>>);
+
+impl RpcLatency {
+    pub fn observe(&self, method: &'static str, took: Duration) {
+        let mut methods = self.0.lock().unwrap();
+        methods
+            .entry(method)
+            .or_default()
+            .observe(took.as_secs_f64());
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Prometheus text format
+// -----------------------------------------------------------------------------
+
+/// Writes metric families in the Prometheus text format, version 0.0.4
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, value))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+
+    /// A family of one sample without labels
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+/// One of the counts of K1Rates
+type PickRate = fn(&K1Rates) -> Rate;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Everything above as a /metrics page; `latency` is None for a node that
+/// isn't reached over RPC
+pub fn render(
+    flows: &FlowMetrics,
+    store: &StoreMetrics,
+    stats: &StorageStats,
+    latency: Option<&RpcLatency>,
+) -> String {
+    let mut out = Exposition::default();
+    let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    out.single(
+        "lnurl_withdraws_requested_total",
+        "counter",
+        "Withdraw callbacks accepted and paid in the background",
+        count(&flows.withdraws_requested),
+    );
+    out.single(
+        "lnurl_withdraws_paid_total",
+        "counter",
+        "Withdraws whose payment succeeded",
+        count(&flows.withdraws_paid),
+    );
+    out.single(
+        "lnurl_withdraws_failed_total",
+        "counter",
+        "Withdraws whose payment failed",
+        count(&flows.withdraws_failed),
+    );
+    out.single(
+        "lnurl_channels_opened_total",
+        "counter",
+        "Channels funded",
+        count(&flows.channels_opened),
+    );
+    out.family(
+        "lnurl_auth_total",
+        "counter",
+        "LNURL-auth login attempts by outcome",
+    );
+    for outcome in AuthOutcome::ALL {
+        let value = count(&flows.auth[outcome as usize]);
+        out.sample("lnurl_auth_total", &[("outcome", outcome.as_str())], value);
+    }
+
+    out.family(
+        "lnurl_k1s_active",
+        "gauge",
+        "k1s issued and not yet used, by flow",
+    );
+    for purpose in K1Purpose::ALL {
+        let active = stats.pending_k1s_by_purpose.get(&purpose).copied();
+        out.sample(
+            "lnurl_k1s_active",
+            &[("flow", purpose.as_str())],
+            active.unwrap_or(0),
+        );
+    }
+    let rates = store.rates();
+    let k1_counters: [(&str, &str, PickRate); 4] = [
+        ("lnurl_k1s_issued_total", "k1s issued, by flow", |k1s| {
+            k1s.issued
+        }),
+        ("lnurl_k1s_consumed_total", "k1s used, by flow", |k1s| {
+            k1s.consumed
+        }),
+        (
+            "lnurl_k1s_refused_total",
+            "Unknown or used k1s presented to a callback, by flow",
+            |k1s| k1s.refused,
+        ),
+        (
+            "lnurl_k1s_expired_total",
+            "k1s presented past their TTL or swept, by flow",
+            |k1s| k1s.expired,
+        ),
+    ];
+    for (name, help, rate) in k1_counters {
+        out.family(name, "counter", help);
+        for purpose in K1Purpose::ALL {
+            let total = rates.k1s.get(&purpose).map_or(0, |k1s| rate(k1s).total);
+            out.sample(name, &[("flow", purpose.as_str())], total);
+        }
+    }
+    out.single(
+        "lnurl_sessions_active",
+        "gauge",
+        "Login sessions held",
+        stats.sessions,
+    );
+
+    if let Some(latency) = latency {
+        let name = "lnurl_cln_rpc_duration_seconds";
+        out.family(
+            name,
+            "histogram",
+            "Time CLN takes to answer an RPC call, by method",
+        );
+        for (method, histogram) in latency.0.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let bound = bound.to_string();
+                let labels = [("method", *method), ("le", bound.as_str())];
+                out.sample(&format!("{}_bucket", name), &labels, cumulative);
+            }
+            let labels = [("method", *method), ("le", "+Inf")];
+            out.sample(&format!("{}_bucket", name), &labels, histogram.count);
+            out.sample(
+                &format!("{}_sum", name),
+                &[("method", method)],
+                histogram.sum,
+            );
+            out.sample(
+                &format!("{}_count", name),
+                &[("method", method)],
+                histogram.count,
+            );
+        }
+    }
+    out.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_minute_is_the_previous_full_minute() {
+        let mut counter = Counter::default();
+        let start = 1_760_000_020; // 40s into a minute
+        counter.record(start);
+        counter.record(start + 10);
+        assert_eq!(counter.rate(start + 15), Rate { total: 2, last_minute: 0 });
+
+        counter.record(start + 25); // the next minute
+        assert_eq!(counter.rate(start + 30), Rate { total: 3, last_minute: 2 });
+        assert_eq!(counter.rate(start + 85), Rate { total: 3, last_minute: 1 });
+        // Nothing for a while
+        assert_eq!(counter.rate(start + 600), Rate { total: 3, last_minute: 0 });
+    }
+
+    #[test]
+    fn counts_per_purpose() {
+        let metrics = StoreMetrics::default();
+        let now = 1_760_000_000;
+        metrics.k1(K1Purpose::Auth, now, |k1s| &mut k1s.issued);
+        metrics.k1(K1Purpose::Auth, now, |k1s| &mut k1s.issued);
+        metrics.k1(K1Purpose::Auth, now, |k1s| &mut k1s.refused);
+
+        let rates = metrics.rates_at(now + 60);
+        let auth = rates.k1s[&K1Purpose::Auth];
+        assert_eq!(auth.issued, Rate { total: 2, last_minute: 2 });
+        assert_eq!(auth.refused, Rate { total: 1, last_minute: 1 });
+        assert_eq!(auth.consumed, Rate::default());
+        assert_eq!(rates.k1s[&K1Purpose::Withdraw].issued, Rate::default());
+    }
+
+    #[test]
+    fn metrics_are_rendered_for_prometheus() {
+        let flows = FlowMetrics::default();
+        flows.withdraw_requested();
+        flows.withdraw_requested();
+        flows.withdraw_paid();
+        flows.auth(AuthOutcome::BadSignature);
+        let store = StoreMetrics::default();
+        store.k1_issued(K1Purpose::Withdraw);
+        let stats = StorageStats {
+            sessions: 3,
+            pending_k1s: 1,
+            pending_k1s_by_purpose: HashMap::from([(K1Purpose::Withdraw, 1)]),
+            ..Default::default()
+        };
+        let latency = RpcLatency::default();
+        latency.observe("pay", Duration::from_millis(300));
+        latency.observe("pay", Duration::from_secs(90));
+
+        let page = render(&flows, &store, &stats, Some(&latency));
+        for line in [
+            "# TYPE lnurl_withdraws_requested_total counter",
+            "lnurl_withdraws_requested_total 2",
+            "lnurl_withdraws_paid_total 1",
+            "lnurl_withdraws_failed_total 0",
+            "lnurl_auth_total{outcome=\"bad_signature\"} 1",
+            "lnurl_auth_total{outcome=\"success\"} 0",
+            "lnurl_k1s_active{flow=\"withdraw\"} 1",
+            "lnurl_k1s_active{flow=\"auth\"} 0",
+            "lnurl_k1s_issued_total{flow=\"withdraw\"} 1",
+            "lnurl_sessions_active 3",
+            "# TYPE lnurl_cln_rpc_duration_seconds histogram",
+            "lnurl_cln_rpc_duration_seconds_bucket{method=\"pay\",le=\"0.25\"} 0",
+            "lnurl_cln_rpc_duration_seconds_bucket{method=\"pay\",le=\"0.5\"} 1",
+            "lnurl_cln_rpc_duration_seconds_bucket{method=\"pay\",le=\"60\"} 1",
+            "lnurl_cln_rpc_duration_seconds_bucket{method=\"pay\",le=\"+Inf\"} 2",
+            "lnurl_cln_rpc_duration_seconds_sum{method=\"pay\"} 90.3",
+            "lnurl_cln_rpc_duration_seconds_count{method=\"pay\"} 2",
+        ] {
+            assert!(page.lines().any(|l| l == line), "{} not in\n{}", line, page);
+        }
+        let page = render(&flows, &store, &stats, None);
+        assert!(!page.contains("lnurl_cln_rpc"));
+    }
+}
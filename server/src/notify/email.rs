@@ -0,0 +1,55 @@
+// Email over SMTP. The URL says how to reach the relay: smtps:// for TLS from
+// the start (port 465), smtp://...?tls=required for STARTTLS (port 587),
+// with the login as its userinfo.
+
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use super::{Notification, Notifier, NotifyError};
+
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Mailbox,
+}
+
+impl EmailNotifier {
+    pub fn new(smtp_url: &str, from: &str, to: &str) -> Result<EmailNotifier, NotifyError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+            .map_err(|e| NotifyError(format!("Invalid SMTP URL: {}", e)))?
+            .build();
+        let mailbox = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| NotifyError(format!("Invalid email address {}: {}", address, e)))
+        };
+        Ok(EmailNotifier {
+            transport,
+            from: mailbox(from)?,
+            to: mailbox(to)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(format!("[lnurl-server] {}", notification.subject))
+            .header(ContentType::TEXT_PLAIN)
+            .body(notification.body.clone())
+            .map_err(|e| NotifyError(format!("Failed to build email: {}", e)))?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| NotifyError(format!("SMTP error: {}", e)))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+// Nostr direct messages (NIP-04, kind 4) from the server's own key to the
+// operator's, published to the configured relays. The content is encrypted
+// with AES-256-CBC under the x coordinate of the ECDH point of the two keys,
+// which most clients still read even though NIP-17 is meant to replace it.
+// Relays only see that the server wrote to the operator, and when.
+//
+// Events are signed as the client's zaps are, by lnurl-models
+// (models/src/nostr.rs).
+
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::{future, SinkExt, StreamExt};
+use lnurl_models::encoding;
+use lnurl_models::nostr::Event;
+use rand::RngCore;
+use secp256k1::{ecdh, Keypair, Parity, PublicKey, Secp256k1, XOnlyPublicKey};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message as Frame;
+
+use super::{Notification, Notifier, NotifyError};
+
+const KIND_DIRECT_MESSAGE: u32 = 4;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+pub struct NostrNotifier {
+    keypair: Keypair,
+    recipient: XOnlyPublicKey,
+    relays: Vec<String>,
+}
+
+impl NostrNotifier {
+    /// `secret` as nsec1... or hex, `recipient` as npub1... or hex
+    pub fn new(
+        secret: &str,
+        recipient: &str,
+        relays: Vec<String>,
+    ) -> Result<NostrNotifier, NotifyError> {
+        let keypair = Keypair::from_seckey_slice(&Secp256k1::new(), &decode_key(secret, "nsec")?)
+            .map_err(|_| NotifyError("Not a valid Nostr secret key".to_string()))?;
+        let recipient = XOnlyPublicKey::from_slice(&decode_key(recipient, "npub")?)
+            .map_err(|_| NotifyError("Not a valid Nostr public key".to_string()))?;
+        if relays.is_empty() {
+            return Err(NotifyError("No Nostr relays to publish to".to_string()));
+        }
+        Ok(NostrNotifier {
+            keypair,
+            recipient,
+            relays,
+        })
+    }
+
+    fn direct_message(&self, text: &str) -> Event {
+        let content = encrypt(&self.keypair, &self.recipient, text);
+        let tags = vec![vec!["p".to_string(), self.recipient.to_string()]];
+        Event::sign(&self.keypair, KIND_DIRECT_MESSAGE, tags, &content)
+    }
+}
+
+/// 32 bytes, as NIP-19 bech32 with `hrp` or as hex
+fn decode_key(input: &str, hrp: &str) -> Result<[u8; 32], NotifyError> {
+    encoding::bech32_or_hex32(input, hrp)
+        .map_err(|e| NotifyError(format!("Invalid {}: {}", hrp, e)))
+}
+
+/// The NIP-04 key both sides derive: the x coordinate of the ECDH point
+fn shared_key(keypair: &Keypair, other: &XOnlyPublicKey) -> [u8; 32] {
+    // Either parity gives the same x
+    let other = PublicKey::from_x_only_public_key(*other, Parity::Even);
+    let point = ecdh::shared_secret_point(&other, &keypair.secret_key());
+    point[..32].try_into().unwrap()
+}
+
+/// `<base64 ciphertext>?iv=<base64 iv>`
+fn encrypt(keypair: &Keypair, recipient: &XOnlyPublicKey, text: &str) -> String {
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut iv);
+    let ciphertext = Aes256CbcEnc::new(&shared_key(keypair, recipient).into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(text.as_bytes());
+    format!("{}?iv={}", BASE64.encode(ciphertext), BASE64.encode(iv))
+}
+
+/// Sends the event and waits for the relay's OK
+async fn publish(relay: &str, event: &Event) -> Result<(), NotifyError> {
+    let exchange = async {
+        let (mut socket, _) = tokio_tungstenite::connect_async(relay)
+            .await
+            .map_err(|e| NotifyError(e.to_string()))?;
+        let request = json!(["EVENT", event]).to_string();
+        socket
+            .send(Frame::Text(request))
+            .await
+            .map_err(|e| NotifyError(e.to_string()))?;
+
+        while let Some(frame) = socket.next().await {
+            let Ok(Frame::Text(text)) = frame else {
+                continue;
+            };
+            let Ok(message) = serde_json::from_str::<Vec<Value>>(&text) else {
+                continue;
+            };
+            if message.first().and_then(Value::as_str) != Some("OK")
+                || message.get(1).and_then(Value::as_str) != Some(&event.id)
+            {
+                continue;
+            }
+            let _ = socket.close(None).await;
+            return match message.get(2).and_then(Value::as_bool) {
+                Some(true) => Ok(()),
+                _ => Err(NotifyError(format!(
+                    "refused: {}",
+                    message.get(3).and_then(Value::as_str).unwrap_or_default()
+                ))),
+            };
+        }
+        Err(NotifyError("connection closed".to_string()))
+    };
+    match tokio::time::timeout(TIMEOUT, exchange).await {
+        Ok(result) => result,
+        Err(_) => Err(NotifyError("timed out".to_string())),
+    }
+}
+
+#[async_trait]
+impl Notifier for NostrNotifier {
+    fn name(&self) -> &str {
+        "nostr"
+    }
+
+    /// Done once any relay has taken the message
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let text = format!("{}\n\n{}", notification.subject, notification.body);
+        let event = self.direct_message(&text);
+        let results =
+            future::join_all(self.relays.iter().map(|relay| publish(relay, &event))).await;
+        if results.iter().any(Result::is_ok) {
+            return Ok(());
+        }
+        let errors: Vec<_> = self
+            .relays
+            .iter()
+            .zip(results)
+            .map(|(relay, result)| format!("{}: {}", relay, result.unwrap_err()))
+            .collect();
+        Err(NotifyError(format!(
+            "No relay took the message ({})",
+            errors.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockDecryptMut;
+
+    const SERVER_KEY: &str = "0000000000000000000000000000000000000000000000000000000000000003";
+    const OPERATOR_KEY: &str = "0000000000000000000000000000000000000000000000000000000000000005";
+
+    fn keypair(secret: &str) -> Keypair {
+        Keypair::from_seckey_str(&Secp256k1::new(), secret).unwrap()
+    }
+
+    #[test]
+    fn operator_can_read_the_message() {
+        let operator = keypair(OPERATOR_KEY);
+        let notifier = NostrNotifier::new(
+            SERVER_KEY,
+            &operator.x_only_public_key().0.to_string(),
+            vec!["wss://relay.example".to_string()],
+        )
+        .unwrap();
+        let event = notifier.direct_message("Node unreachable");
+        assert_eq!(event.kind, KIND_DIRECT_MESSAGE);
+        assert_eq!(event.tags[0][1], operator.x_only_public_key().0.to_string());
+
+        // Decrypting as the operator, from the server's pubkey
+        let server = XOnlyPublicKey::from_slice(&hex::decode(&event.pubkey).unwrap()).unwrap();
+        let (ciphertext, iv) = event.content.split_once("?iv=").unwrap();
+        let iv: [u8; 16] = BASE64.decode(iv).unwrap().try_into().unwrap();
+        let text =
+            cbc::Decryptor::<aes::Aes256>::new(&shared_key(&operator, &server).into(), &iv.into())
+                .decrypt_padded_vec_mut::<Pkcs7>(&BASE64.decode(ciphertext).unwrap())
+                .unwrap();
+        assert_eq!(text, b"Node unreachable");
+    }
+
+    #[test]
+    fn keys_as_bech32_or_hex() {
+        // NIP-19's example npub
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        assert_eq!(
+            hex::encode(decode_key(npub, "npub").unwrap()),
+            "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e"
+        );
+        assert!(decode_key(npub, "nsec").is_err());
+        assert_eq!(decode_key(SERVER_KEY, "nsec").unwrap()[31], 3);
+        assert!(decode_key("abcd", "npub").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+// Telegram through a bot: create one with @BotFather, say something to it
+// from the chat that should get the alerts, and read that chat's id from
+// https://api.telegram.org/bot<token>/getUpdates.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::{Notification, Notifier, NotifyError};
+
+const API: &str = "https://api.telegram.org";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct TelegramNotifier {
+    token: String,
+    chat_id: String,
+}
+
+impl TelegramNotifier {
+    pub fn new(token: &str, chat_id: &str) -> TelegramNotifier {
+        TelegramNotifier {
+            token: token.to_string(),
+            chat_id: chat_id.to_string(),
+        }
+    }
+}
+
+/// Blocking, ureq being what the server already speaks HTTP with
+fn post(url: &str, payload: Value) -> Result<(), NotifyError> {
+    // ureq's errors show the URL, which holds the bot's token
+    match ureq::post(url).timeout(TIMEOUT).send_json(payload) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, response)) => {
+            let description = response
+                .into_json::<Value>()
+                .ok()
+                .and_then(|body| body["description"].as_str().map(str::to_string))
+                .unwrap_or_default();
+            Err(NotifyError(format!(
+                "Telegram said {}: {}",
+                code, description
+            )))
+        }
+        Err(ureq::Error::Transport(e)) => {
+            Err(NotifyError(format!("Telegram unreachable: {}", e.kind())))
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let url = format!("{}/bot{}/sendMessage", API, self.token);
+        let payload = json!({
+            "chat_id": self.chat_id,
+            "text": format!("{}\n\n{}", notification.subject, notification.body),
+        });
+        tokio::task::spawn_blocking(move || post(&url, payload))
+            .await
+            .map_err(|e| NotifyError(e.to_string()))?
+    }
+}
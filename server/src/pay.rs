@@ -0,0 +1,796 @@
+// =============================================================================
+// Pay requests (LUD-06)
+// =============================================================================
+//
+// Wallets pay the service through lnurl-pay:
+//
+//   GET /request-pay         — payRequest: callback, amount bounds, metadata
+//   GET /pay?amount=<msat>   — { pr, routes: [] }, a bolt11 for that amount
+//
+// and through Lightning Addresses (LUD-16), user@domain where the domain is
+// the callback URL's host:
+//
+//   GET /.well-known/lnurlp/<user>    — the same payRequest for one user
+//   GET /pay/<user>?amount=<msat>     — the callback of its invoices
+//
+// The metadata holds one text/plain entry, the description wallets show for
+// the payment, and the invoice commits to it by its sha256 (the BOLT-11
+// description hash), which wallets check before paying. Invoices are the
+// node's, labelled lnurl-pay-<random>, so pay requests need no storage.
+//
+//   LNURL_PAY_MIN_SENDABLE_MSAT  smallest payment, default 1000 (1 sat)
+//   LNURL_PAY_MAX_SENDABLE_MSAT  largest payment, default 100000000
+//                                (100k sats)
+//   LNURL_PAY_DESCRIPTION        the text/plain entry, one line like
+//                                LNURL_WITHDRAW_DESCRIPTION (text.rs)
+//   LNURL_PAY_LONG_DESCRIPTION   a text/long-desc entry (LUD-20) as well, for
+//                                wallets to show in full, several lines
+//                                allowed
+//   LNURL_PAY_ADDRESSES          the users, comma separated, each optionally
+//                                with its own text: alice,bob:Tips for Bob
+//   LNURL_PAY_SUCCESS_ACTION     a LUD-09 successAction for the wallet to
+//                                show once paid, as JSON, for /request-pay
+//                                and the addresses without their own:
+//                                {"tag":"message","message":"Thanks!"}
+//   LNURL_PAY_ADDRESS_SUCCESS_ACTIONS
+//                                the addresses' own, a JSON object of user to
+//                                successAction
+//
+// Addresses are static, for wallets to keep and pay again: their payRequest
+// says `disposable: false` (LUD-11). /request-pay says so too unless it is
+// made one-time:
+//
+//   LNURL_PAY_DISPOSABLE         1 or true: every /request-pay hands out a
+//                                k1 with its callback, pay?k1=<k1>, which
+//                                takes one payment within its TTL
+//                                (LNURL_PAY_K1_TTL_SECS, storage/mod.rs)
+//
+// For a point of sale whose static QR both takes and hands out sats, pay and
+// withdraw requests can point at each other (LUD-19):
+//
+//   LNURL_PAY_WITHDRAW_BRIDGE    1 or true: /request-pay gives a withdrawLink,
+//                                lnurlw://<callback host>/request-withdraw,
+//                                and withdraw requests a payLink,
+//                                lnurlp://<callback host>/request-pay
+//
+// Besides LUD-09's message and url actions, an action can be a secret for
+// the payer only (LUD-10), e.g. a coupon code:
+//
+//   {"tag":"aes","description":"Your code","plaintext":"SAVE10"}
+//
+// Each invoice then gets a preimage from the server rather than the node,
+// which the plaintext is AES-256-CBC encrypted with: the wallet reads it once
+// the payment hands it the preimage. The plaintext itself is the same for
+// every payer, so anyone who paid once can pass it on.
+//
+// A user's metadata adds a text/identifier entry, its address, which wallets
+// show and check against the address they were given. Wallets fetch
+// /.well-known from the root of the domain, so a server mounted under a path
+// prefix needs the proxy in front to route it there.
+//
+// Every invoice comes with a verify URL (LUD-21), for whoever holds the
+// invoice, the payer or a point of sale showing it, to check it was paid:
+//
+//   GET /verify/<payment hash>  — { settled, preimage, pr }, from the node's
+//                                 listinvoices
+//
+// It answers for the invoices of pay requests only, not the node's others.
+
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::Json;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use lnurl_models::{LnurlParams, PayCallbackResponse, PayRequest, SuccessAction, VerifyResponse};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use tracing::{error, info};
+
+use crate::backend::{InvoiceStatus, IssuedInvoice};
+use crate::callback::BaseUrl;
+use crate::policy::Screened;
+use crate::storage::{K1Purpose, K1Status};
+use crate::{callback, discovery, error_reply, lnurl, text, AppState, ErrorReply, Peer};
+
+/// How long the wallet has to pay the invoice
+pub const INVOICE_EXPIRY_SECS: u64 = 10 * 60;
+/// Of the labels of pay invoices, which /verify keeps to
+const LABEL_PREFIX: &str = "lnurl-pay-";
+const DEFAULT_DESCRIPTION: &str = "Payment to the LNURL service";
+/// LUD-09's limit on a message and on a url action's description
+const MAX_SUCCESS_TEXT_CHARS: usize = 144;
+/// The longest plaintext whose ciphertext, padded and in base64, stays within
+/// LUD-10's 4kb
+const MAX_AES_PLAINTEXT_BYTES: usize = 3071;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+#[derive(Debug)]
+pub struct PayConfigError(String);
+
+impl fmt::Display for PayConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PayConfigError {}
+
+/// What /request-pay offers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayConfig {
+    pub min_sendable_msat: u64,
+    pub max_sendable_msat: u64,
+    pub description: String,
+    pub long_description: Option<String>,
+    pub disposable: bool, // /request-pay's callback takes one payment
+    pub bridge: bool,     // LUD-19: /request-pay and withdraw requests link to each other
+    pub addresses: BTreeMap<String, Option<String>>, // user: its own text
+    pub success_action: Option<SuccessActionConfig>,
+    pub address_success_actions: BTreeMap<String, SuccessActionConfig>,
+}
+
+/// A successAction as configured, see the top of the file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+pub enum SuccessActionConfig {
+    Message {
+        message: String,
+    },
+    Url {
+        description: String,
+        url: String,
+    },
+    /// LUD-10, encrypted anew for each invoice
+    Aes {
+        description: String,
+        plaintext: String,
+    },
+}
+
+impl SuccessActionConfig {
+    /// The successAction of an invoice paid with `preimage`, which only aes
+    /// actions need: None for those without it
+    pub fn for_invoice(&self, preimage: Option<&[u8; 32]>) -> Option<SuccessAction> {
+        Some(match self {
+            SuccessActionConfig::Message { message } => SuccessAction::Message {
+                message: message.clone(),
+            },
+            SuccessActionConfig::Url { description, url } => SuccessAction::Url {
+                description: description.clone(),
+                url: url.clone(),
+            },
+            SuccessActionConfig::Aes {
+                description,
+                plaintext,
+            } => {
+                let iv: [u8; 16] = rand::random();
+                let ciphertext = Aes256CbcEnc::new(preimage?.into(), &iv.into())
+                    .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+                SuccessAction::Aes {
+                    description: description.clone(),
+                    ciphertext: BASE64.encode(ciphertext),
+                    iv: BASE64.encode(iv),
+                }
+            }
+        })
+    }
+}
+
+impl Default for PayConfig {
+    fn default() -> Self {
+        PayConfig {
+            min_sendable_msat: 1_000,
+            max_sendable_msat: 100_000_000,
+            description: DEFAULT_DESCRIPTION.to_string(),
+            long_description: None,
+            disposable: false,
+            bridge: false,
+            addresses: BTreeMap::new(),
+            success_action: None,
+            address_success_actions: BTreeMap::new(),
+        }
+    }
+}
+
+impl PayConfig {
+    /// LUD-06 metadata, a JSON array of [mime type, content] entries. The
+    /// invoice hashes this exact string, so it is built the same way each
+    /// time.
+    pub fn metadata(&self) -> String {
+        let mut entries = vec![["text/plain", self.description.as_str()]];
+        if let Some(long) = &self.long_description {
+            entries.push(["text/long-desc", long]);
+        }
+        serde_json::json!(entries).to_string()
+    }
+
+    /// The metadata of `user`'s Lightning Address on `domain`, None for a
+    /// user that is not set up
+    pub fn address_metadata(&self, user: &str, domain: &str) -> Option<String> {
+        let text = self.addresses.get(user)?;
+        let identifier = format!("{}@{}", user, domain);
+        let text = match text {
+            Some(text) => text.clone(),
+            None => format!("Payment to {}", identifier),
+        };
+        let metadata = serde_json::json!([["text/plain", text], ["text/identifier", identifier]]);
+        Some(metadata.to_string())
+    }
+
+    /// What the wallet shows once it paid `user`, or paid through
+    /// /request-pay for None
+    pub fn success_action(&self, user: Option<&str>) -> Option<&SuccessActionConfig> {
+        user.and_then(|user| self.address_success_actions.get(user))
+            .or(self.success_action.as_ref())
+    }
+}
+
+/// The label of a pay invoice, naming the user it was paid to if any
+pub fn invoice_label(user: Option<&str>, id: &str) -> String {
+    match user {
+        Some(user) => format!("{}{}-{}", LABEL_PREFIX, user, id),
+        None => format!("{}{}", LABEL_PREFIX, id),
+    }
+}
+
+/// LUD-16 usernames: a-z, 0-9, - _ and .
+fn valid_user(user: &str) -> bool {
+    !user.is_empty()
+        && user
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-_.".contains(&b))
+}
+
+/// Parses LNURL_PAY_ADDRESSES, see the top of the file
+fn parse_addresses(raw: &str) -> Result<BTreeMap<String, Option<String>>, PayConfigError> {
+    let mut addresses = BTreeMap::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (user, text) = match entry.split_once(':') {
+            Some((user, text)) => (user.trim(), Some(text.trim())),
+            None => (entry, None),
+        };
+        if !valid_user(user) {
+            return Err(PayConfigError(format!(
+                "LNURL_PAY_ADDRESSES: {:?} is not a username (a-z, 0-9, -, _ and . only)",
+                user
+            )));
+        }
+        if let Some(text) = text {
+            text::check_description(&format!("LNURL_PAY_ADDRESSES text of {}", user), text)
+                .map_err(|e| PayConfigError(e.to_string()))?;
+        }
+        if addresses
+            .insert(user.to_string(), text.map(str::to_string))
+            .is_some()
+        {
+            return Err(PayConfigError(format!(
+                "LNURL_PAY_ADDRESSES lists {} twice",
+                user
+            )));
+        }
+    }
+    Ok(addresses)
+}
+
+fn check_success_text(name: &str, what: &str, text: &str) -> Result<(), PayConfigError> {
+    match text.trim().is_empty() || text.chars().count() > MAX_SUCCESS_TEXT_CHARS {
+        true => Err(PayConfigError(format!(
+            "{}: the {} must be 1 to {} characters",
+            name, what, MAX_SUCCESS_TEXT_CHARS
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Checks a configured successAction against LUD-09 and LUD-10
+fn check_success_action(name: &str, action: &SuccessActionConfig) -> Result<(), PayConfigError> {
+    match action {
+        SuccessActionConfig::Message { message } => check_success_text(name, "message", message),
+        SuccessActionConfig::Url { description, url } => {
+            check_success_text(name, "description", description)?;
+            callback::check_callback_url(url)
+                .map_err(|e| PayConfigError(format!("{}: {}", name, e)))
+        }
+        SuccessActionConfig::Aes {
+            description,
+            plaintext,
+        } => {
+            check_success_text(name, "description", description)?;
+            match plaintext.is_empty() || plaintext.len() > MAX_AES_PLAINTEXT_BYTES {
+                true => Err(PayConfigError(format!(
+                    "{}: the plaintext must be 1 to {} bytes",
+                    name, MAX_AES_PLAINTEXT_BYTES
+                ))),
+                false => Ok(()),
+            }
+        }
+    }
+}
+
+fn parse_success_action(name: &str, raw: &str) -> Result<SuccessActionConfig, PayConfigError> {
+    let action = serde_json::from_str(raw).map_err(|e| {
+        PayConfigError(format!(
+            "{} must be a successAction like {{\"tag\":\"message\",\"message\":\"Thanks!\"}}: {}",
+            name, e
+        ))
+    })?;
+    check_success_action(name, &action)?;
+    Ok(action)
+}
+
+/// Parses LNURL_PAY_ADDRESS_SUCCESS_ACTIONS, for users of `addresses`
+fn parse_address_success_actions(
+    raw: &str,
+    addresses: &BTreeMap<String, Option<String>>,
+) -> Result<BTreeMap<String, SuccessActionConfig>, PayConfigError> {
+    const NAME: &str = "LNURL_PAY_ADDRESS_SUCCESS_ACTIONS";
+    let actions: BTreeMap<String, SuccessActionConfig> =
+        serde_json::from_str(raw).map_err(|e| {
+            PayConfigError(format!(
+                "{} must be a JSON object of user to successAction: {}",
+                NAME, e
+            ))
+        })?;
+    for (user, action) in &actions {
+        if !addresses.contains_key(user) {
+            return Err(PayConfigError(format!(
+                "{}: {} is not in LNURL_PAY_ADDRESSES",
+                NAME, user
+            )));
+        }
+        check_success_action(&format!("{} of {}", NAME, user), action)?;
+    }
+    Ok(actions)
+}
+
+fn var(name: &str) -> Result<Option<u64>, PayConfigError> {
+    match std::env::var(name) {
+        Err(_) => Ok(None),
+        Ok(raw) => raw
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| PayConfigError(format!("{} must be a whole number of msat", name))),
+    }
+}
+
+/// Reads the LNURL_PAY_* variables at the top of the file, keeping the
+/// default of whichever is unset
+pub fn load_pay_config() -> Result<PayConfig, PayConfigError> {
+    let mut config = PayConfig::default();
+    if let Some(min) = var("LNURL_PAY_MIN_SENDABLE_MSAT")? {
+        config.min_sendable_msat = min;
+    }
+    if let Some(max) = var("LNURL_PAY_MAX_SENDABLE_MSAT")? {
+        config.max_sendable_msat = max;
+    }
+    if config.min_sendable_msat == 0 || config.min_sendable_msat > config.max_sendable_msat {
+        return Err(PayConfigError(format!(
+            "LNURL_PAY_MIN_SENDABLE_MSAT ({}) must be at least 1 and at most \
+             LNURL_PAY_MAX_SENDABLE_MSAT ({})",
+            config.min_sendable_msat, config.max_sendable_msat
+        )));
+    }
+    if let Ok(description) = std::env::var("LNURL_PAY_DESCRIPTION") {
+        text::check_description("LNURL_PAY_DESCRIPTION", &description)
+            .map_err(|e| PayConfigError(e.to_string()))?;
+        config.description = description;
+    }
+    if let Ok(long) = std::env::var("LNURL_PAY_LONG_DESCRIPTION") {
+        text::check_long_description("LNURL_PAY_LONG_DESCRIPTION", &long)
+            .map_err(|e| PayConfigError(e.to_string()))?;
+        config.long_description = Some(long);
+    }
+    if let Ok(raw) = std::env::var("LNURL_PAY_DISPOSABLE") {
+        config.disposable = crate::parse_flag(raw.trim()).ok_or_else(|| {
+            PayConfigError("LNURL_PAY_DISPOSABLE must be 1, 0, true or false".to_string())
+        })?;
+    }
+    if let Ok(raw) = std::env::var("LNURL_PAY_WITHDRAW_BRIDGE") {
+        config.bridge = crate::parse_flag(raw.trim()).ok_or_else(|| {
+            PayConfigError("LNURL_PAY_WITHDRAW_BRIDGE must be 1, 0, true or false".to_string())
+        })?;
+    }
+    if let Ok(raw) = std::env::var("LNURL_PAY_ADDRESSES") {
+        config.addresses = parse_addresses(&raw)?;
+    }
+    if let Ok(raw) = std::env::var("LNURL_PAY_SUCCESS_ACTION") {
+        config.success_action = Some(parse_success_action("LNURL_PAY_SUCCESS_ACTION", &raw)?);
+    }
+    if let Ok(raw) = std::env::var("LNURL_PAY_ADDRESS_SUCCESS_ACTIONS") {
+        config.address_success_actions = parse_address_success_actions(&raw, &config.addresses)?;
+    }
+    info!(
+        "Taking payments of {} to {} msat: {}",
+        config.min_sendable_msat, config.max_sendable_msat, config.description
+    );
+    if !config.addresses.is_empty() {
+        let users: Vec<&str> = config.addresses.keys().map(String::as_str).collect();
+        info!("Lightning Addresses for {}", users.join(", "));
+    }
+    Ok(config)
+}
+
+fn pay_request(
+    config: &PayConfig,
+    callback: String,
+    metadata: String,
+    disposable: bool,
+    withdraw_link: Option<String>,
+) -> LnurlParams {
+    let response = PayRequest {
+        callback,
+        metadata,
+        min_sendable: config.min_sendable_msat,
+        max_sendable: config.max_sendable_msat,
+        comment_allowed: 0,
+        allows_nostr: false,
+        nostr_pubkey: None,
+        bolt12: None,
+        disposable: Some(disposable),
+        withdraw_link,
+    };
+    response.into()
+}
+
+/// The metadata of `user`'s address, or a 404 for a user not set up
+fn address_metadata(state: &AppState, base: &str, user: &str) -> Result<String, ErrorReply> {
+    let domain = callback::host_and_port(base).map_or("", |(host, _)| host);
+    state.pay.address_metadata(user, domain).ok_or_else(|| {
+        error_reply(
+            StatusCode::NOT_FOUND,
+            "Unknown Lightning Address".to_string(),
+        )
+    })
+}
+
+// GET /request-pay
+pub async fn request_pay(
+    State(state): State<AppState>,
+    peer: Peer,
+    BaseUrl(base): BaseUrl,
+    headers: HeaderMap,
+) -> Result<Response, ErrorReply> {
+    let withdraw_link = state
+        .pay
+        .bridge
+        .then(|| lnurl::with_scheme(&format!("{}request-withdraw", base), "lnurlw"));
+    if !state.pay.disposable {
+        return discovery::serve(&state, &headers, &base, None, || {
+            let callback = format!("{}pay", base);
+            Ok(pay_request(
+                &state.pay,
+                callback,
+                state.pay.metadata(),
+                false,
+                withdraw_link,
+            ))
+        });
+    }
+
+    crate::screen(&state, peer, Screened::K1(K1Purpose::Pay)).await?;
+    let k1 = crate::random_hex_32();
+    state.issue_k1(&k1, K1Purpose::Pay).await.map_err(|e| {
+        error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Storage error: {}", e),
+        )
+    })?;
+    let callback = format!("{}pay?k1={}", base, k1);
+    let metadata = state.pay.metadata();
+    Ok(discovery::uncached(pay_request(
+        &state.pay,
+        callback,
+        metadata,
+        true,
+        withdraw_link,
+    )))
+}
+
+// GET /.well-known/lnurlp/<user>
+pub async fn address(
+    State(state): State<AppState>,
+    BaseUrl(base): BaseUrl,
+    Path(user): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ErrorReply> {
+    let user = user.to_ascii_lowercase();
+    discovery::serve(&state, &headers, &base, Some(&user), || {
+        let metadata = address_metadata(&state, &base, &user)?;
+        let callback = format!("{}pay/{}", base, user);
+        Ok(pay_request(&state.pay, callback, metadata, false, None))
+    })
+}
+
+// GET /pay?amount=<msat>
+// GET /pay?amount=<msat>&k1=<k1>  — LNURL_PAY_DISPOSABLE
+#[derive(Debug, Deserialize)]
+pub struct PayParams {
+    amount: Option<String>,
+    k1: Option<String>,
+}
+
+pub async fn pay(
+    State(state): State<AppState>,
+    BaseUrl(base): BaseUrl,
+    Query(params): Query<PayParams>,
+) -> Result<Json<PayCallbackResponse>, ErrorReply> {
+    let metadata = state.pay.metadata();
+    let k1 = match state.pay.disposable {
+        true => Some(params.k1.clone().ok_or_else(|| {
+            error_reply(
+                StatusCode::BAD_REQUEST,
+                "This pay link takes one payment, request a new one".to_string(),
+            )
+        })?),
+        false => None,
+    };
+    invoice(&state, &base, None, &metadata, params, k1.as_deref()).await
+}
+
+// GET /pay/<user>?amount=<msat>
+pub async fn pay_address(
+    State(state): State<AppState>,
+    BaseUrl(base): BaseUrl,
+    Path(user): Path<String>,
+    Query(params): Query<PayParams>,
+) -> Result<Json<PayCallbackResponse>, ErrorReply> {
+    let user = user.to_ascii_lowercase();
+    let metadata = address_metadata(&state, &base, &user)?;
+    invoice(&state, &base, Some(&user), &metadata, params, None).await
+}
+
+/// Checks the amount, spends `k1` if the link is disposable, and has the
+/// node sign an invoice committing to `metadata`
+async fn invoice(
+    state: &AppState,
+    base: &str,
+    user: Option<&str>,
+    metadata: &str,
+    params: PayParams,
+    k1: Option<&str>,
+) -> Result<Json<PayCallbackResponse>, ErrorReply> {
+    let config = &state.pay;
+    let amount_msat = params
+        .amount
+        .and_then(|amount| amount.trim().parse::<u64>().ok())
+        .ok_or_else(|| {
+            error_reply(
+                StatusCode::BAD_REQUEST,
+                "amount must be a number of msat".to_string(),
+            )
+        })?;
+    if amount_msat < config.min_sendable_msat || amount_msat > config.max_sendable_msat {
+        return Err(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "amount must be between {} and {} msat",
+                config.min_sendable_msat, config.max_sendable_msat
+            ),
+        ));
+    }
+
+    // After the amount checks, so a mistyped amount doesn't cost the link
+    if let Some(k1) = k1 {
+        let refused = |reason: &str| error_reply(StatusCode::BAD_REQUEST, reason.to_string());
+        match state.consume_k1(k1, K1Purpose::Pay).await {
+            Ok(K1Status::Valid) => {}
+            Ok(K1Status::Expired) => return Err(refused("Expired k1, request a new one")),
+            Ok(K1Status::Unknown) => return Err(refused("Invalid or already used k1")),
+            Err(e) => {
+                return Err(error_reply(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Storage error: {}", e),
+                ))
+            }
+        }
+    }
+
+    let action = config.success_action(user);
+    // Only a secret for the payer needs the preimage known here
+    let preimage: Option<[u8; 32]> =
+        matches!(action, Some(SuccessActionConfig::Aes { .. })).then(rand::random);
+    let label = invoice_label(user, &crate::random_hex_32());
+    let pr = state
+        .backend
+        .create_invoice_hashed(
+            amount_msat,
+            &label,
+            metadata,
+            INVOICE_EXPIRY_SECS,
+            preimage.as_ref(),
+        )
+        .await;
+    let invoice = match pr {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            // The k1 stays spent: stored again, its TTL would start over
+            error!("Failed to create pay invoice: {}", e);
+            return Err(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to create invoice: {}", e),
+            ));
+        }
+    };
+    info!(label, amount_msat, "Pay invoice created");
+    let success_action = action
+        .and_then(|action| action.for_invoice(preimage.as_ref()))
+        .and_then(|action| serde_json::to_value(action).ok());
+    Ok(Json(PayCallbackResponse {
+        pr: invoice.bolt11,
+        routes: Vec::new(),
+        success_action,
+        verify: Some(format!("{}verify/{}", base, invoice.payment_hash)),
+    }))
+}
+
+// GET /verify/<payment hash>
+pub async fn verify(
+    State(state): State<AppState>,
+    Path(payment_hash): Path<String>,
+) -> Result<Json<VerifyResponse>, ErrorReply> {
+    let not_found = || error_reply(StatusCode::NOT_FOUND, "Not found".to_string());
+    let payment_hash = payment_hash.to_ascii_lowercase();
+    if !matches!(hex::decode(&payment_hash), Ok(bytes) if bytes.len() == 32) {
+        return Err(not_found());
+    }
+    let invoice = state
+        .backend
+        .invoice_by_hash(&payment_hash)
+        .await
+        .map_err(|e| {
+            error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to look up invoice: {}", e),
+            )
+        })?;
+    let Some(IssuedInvoice {
+        label,
+        bolt11: Some(pr),
+        status,
+        preimage,
+    }) = invoice
+    else {
+        return Err(not_found());
+    };
+    if !label.starts_with(LABEL_PREFIX) {
+        return Err(not_found());
+    }
+    let settled = status == InvoiceStatus::Paid;
+    let preimage = preimage.filter(|_| settled).map(hex::encode);
+    Ok(Json(VerifyResponse::ok(settled, preimage, pr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_is_a_text_plain_entry() {
+        let config = PayConfig {
+            description: "Coffee \"to go\"".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.metadata(), r#"[["text/plain","Coffee \"to go\""]]"#);
+
+        let config = PayConfig {
+            long_description: Some("Hand-roasted.\nOrigin: Huila".to_string()),
+            ..config
+        };
+        assert_eq!(
+            config.metadata(),
+            r#"[["text/plain","Coffee \"to go\""],["text/long-desc","Hand-roasted.\nOrigin: Huila"]]"#
+        );
+    }
+
+    #[test]
+    fn address_metadata_names_the_address() {
+        let config = PayConfig {
+            addresses: parse_addresses("alice, bob:Tips for Bob").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.address_metadata("alice", "shop.example").unwrap(),
+            r#"[["text/plain","Payment to alice@shop.example"],["text/identifier","alice@shop.example"]]"#
+        );
+        assert_eq!(
+            config.address_metadata("bob", "shop.example").unwrap(),
+            r#"[["text/plain","Tips for Bob"],["text/identifier","bob@shop.example"]]"#
+        );
+        assert_eq!(config.address_metadata("carol", "shop.example"), None);
+    }
+
+    #[test]
+    fn success_actions_follow_lud09() {
+        let thanks = parse_success_action(
+            "LNURL_PAY_SUCCESS_ACTION",
+            r#"{"tag":"message","message":"Thanks!"}"#,
+        )
+        .unwrap();
+        let receipt = r#"{"tag":"url","description":"Receipt","url":"https://shop.example/r"}"#;
+        assert!(parse_success_action("LNURL_PAY_SUCCESS_ACTION", receipt).is_ok());
+        for bad in [
+            r#"{"tag":"message","message":""}"#,
+            r#"{"tag":"url","description":"Receipt","url":"http://shop.example/r"}"#,
+            r#"{"tag":"aes","description":"Code","ciphertext":"AAAA","iv":"BBBB"}"#,
+            r#"{"tag":"aes","description":"Code","plaintext":""}"#,
+            r#"{"tag":"hologram"}"#,
+        ] {
+            assert!(
+                parse_success_action("LNURL_PAY_SUCCESS_ACTION", bad).is_err(),
+                "{}",
+                bad
+            );
+        }
+        let long = format!(r#"{{"tag":"message","message":"{}"}}"#, "a".repeat(145));
+        assert!(parse_success_action("LNURL_PAY_SUCCESS_ACTION", &long).is_err());
+
+        // Addresses fall back to the default
+        let addresses = parse_addresses("alice,bob").unwrap();
+        let config = PayConfig {
+            success_action: Some(thanks.clone()),
+            address_success_actions: parse_address_success_actions(
+                &format!(r#"{{"bob":{}}}"#, receipt),
+                &addresses,
+            )
+            .unwrap(),
+            addresses,
+            ..Default::default()
+        };
+        assert_eq!(config.success_action(None), Some(&thanks));
+        assert_eq!(config.success_action(Some("alice")), Some(&thanks));
+        assert!(matches!(
+            config.success_action(Some("bob")),
+            Some(SuccessActionConfig::Url { .. })
+        ));
+        let carol = format!(r#"{{"carol":{}}}"#, receipt);
+        assert!(parse_address_success_actions(&carol, &config.addresses).is_err());
+    }
+
+    #[test]
+    fn aes_actions_open_with_the_preimage() {
+        use aes::cipher::BlockDecryptMut;
+
+        let action = parse_success_action(
+            "LNURL_PAY_SUCCESS_ACTION",
+            r#"{"tag":"aes","description":"Your code","plaintext":"SAVE10"}"#,
+        )
+        .unwrap();
+        assert_eq!(action.for_invoice(None), None);
+        let preimage = [7u8; 32];
+        let Some(SuccessAction::Aes {
+            description,
+            ciphertext,
+            iv,
+        }) = action.for_invoice(Some(&preimage))
+        else {
+            panic!("not an aes action");
+        };
+        assert_eq!(description, "Your code");
+        let iv: [u8; 16] = BASE64.decode(iv).unwrap().try_into().unwrap();
+        let plaintext = cbc::Decryptor::<aes::Aes256>::new(&preimage.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&BASE64.decode(ciphertext).unwrap())
+            .unwrap();
+        assert_eq!(plaintext, b"SAVE10");
+
+        // A fresh iv each time
+        assert_ne!(
+            action.for_invoice(Some(&preimage)),
+            action.for_invoice(Some(&preimage))
+        );
+    }
+
+    #[test]
+    fn addresses_need_lud16_usernames() {
+        assert!(parse_addresses("").unwrap().is_empty());
+        assert!(parse_addresses("tips.jar_2-a").is_ok());
+        assert!(parse_addresses("Alice").is_err());
+        assert!(parse_addresses("al ice").is_err());
+        assert!(parse_addresses(":Tips").is_err());
+        assert!(parse_addresses("bob:").is_err());
+        assert!(parse_addresses("bob,bob:Tips").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// =============================================================================
+// Policies
+// =============================================================================
+//
+// The business rules around each flow, for services running this server to
+// plug in their own without touching the handlers. The handlers ask the
+// policy before committing to anything and tell it what happened after:
+//
+//   WithdrawPolicy  — amount bounds per voucher owner, approve/deny a
+//                     withdraw, the background payment's outcome
+//   ChannelPolicy   — largest channel per node, approve/deny an open, the
+//                     funding transaction
+//   AuthHandler     — budget of new accounts, approve/deny a login, the login
+//   RequestScreener — approve/deny by the address a request comes from,
+//                     before a k1 is issued or a channel funded (screen.rs
+//                     has the built-in CIDR lists)
+//
+// Every method has a default that does what the server does without a
+// policy, so an implementation only overrides what it cares about. A denial
+// is an `Err(reason)`, which goes back to the wallet as the ERROR reason.
+//
+//   struct PositiveBalanceOnly(Arc<dyn Storage>);
+//
+//   #[async_trait]
+//   impl WithdrawPolicy for PositiveBalanceOnly {
+//       async fn approve(&self, withdrawal: &Withdrawal) -> Result<(), String> {
+//           match withdrawal.linking_key.as_deref() {
+//               Some(key) if has_positive_balance(&self.0, key).await => Ok(()),
+//               _ => Err("Only accounts with a positive balance can withdraw".into()),
+//           }
+//       }
+//   }
+//
+// and in main(): `withdraw_policy: Arc::new(PositiveBalanceOnly(storage.clone()))`.
+
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+use crate::storage::{K1Purpose, Withdrawal};
+use crate::Limits;
+
+/// What a denied request is told
+pub type Verdict = Result<(), String>;
+
+/// The amounts a withdraw may be for, msat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawBounds {
+    pub min_msat: u64,
+    pub max_msat: u64,
+}
+
+#[async_trait]
+pub trait WithdrawPolicy: Send + Sync {
+    /// Bounds advertised by /request-withdraw and enforced by /withdraw.
+    /// `owner` is the account a voucher is bound to, whose remaining budget
+    /// caps the advertised maximum on top of these.
+    async fn bounds(&self, _owner: Option<&str>, limits: &Limits) -> WithdrawBounds {
+        WithdrawBounds {
+            min_msat: limits.min_withdrawable_msat,
+            max_msat: limits.max_withdrawable_msat,
+        }
+    }
+
+    /// Called once the invoice checks out, before any budget is debited or
+    /// anything paid. `withdrawal` is still Pending.
+    async fn approve(&self, _withdrawal: &Withdrawal) -> Verdict {
+        Ok(())
+    }
+
+    /// Called after the background payment, `withdrawal.status` being Paid
+    /// or Failed (with the voucher's budget already refunded)
+    async fn on_settled(&self, _withdrawal: &Withdrawal) {}
+}
+
+/// A channel the server is about to open
+#[derive(Debug, Clone)]
+pub struct ChannelOpen {
+    pub k1: String, // or the LSPS1 order id (lsps1.rs)
+    pub node_id: String,
+    pub capacity_sat: u64,
+    pub private: bool,
+}
+
+#[async_trait]
+pub trait ChannelPolicy: Send + Sync {
+    /// The largest channel `node_id` may ask for, also what it gets when it
+    /// names no amount
+    async fn max_capacity_sat(&self, _node_id: &str, limits: &Limits) -> u64 {
+        limits.channel_capacity_sat
+    }
+
+    /// Called before funding the channel
+    async fn approve(&self, _open: &ChannelOpen) -> Verdict {
+        Ok(())
+    }
+
+    /// Called once the funding transaction is broadcast
+    async fn on_opened(&self, _open: &ChannelOpen, _channel_id: &str, _txid: &str) {}
+}
+
+#[async_trait]
+pub trait AuthHandler: Send + Sync {
+    /// Withdraw budget given to the account created on `linking_key`'s first
+    /// login
+    async fn withdraw_budget_msat(&self, _linking_key: &str, limits: &Limits) -> u64 {
+        limits.withdraw_budget_msat
+    }
+
+    /// Called once the signature is verified, before the account or session
+    /// is created
+    async fn approve(&self, _linking_key: &str) -> Verdict {
+        Ok(())
+    }
+
+    /// Called after the session is opened
+    async fn on_login(&self, _linking_key: &str) {}
+}
+
+/// What a screened request is after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screened {
+    /// A k1 for this flow (request-channel, request-withdraw, auth-challenge)
+    K1(K1Purpose),
+    /// Funding the channel of an accepted open-channel callback
+    ChannelFunding,
+}
+
+#[async_trait]
+pub trait RequestScreener: Send + Sync {
+    /// `peer` is the address the request came from, None when the server
+    /// isn't told (embedded without connect info). Proxies in front of the
+    /// server show up as the peer.
+    async fn screen(&self, _peer: Option<IpAddr>, _screened: Screened) -> Verdict {
+        Ok(())
+    }
+}
+
+/// The server's own behaviour: the limits as set through the admin API,
+/// everything approved
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultPolicy;
+
+impl WithdrawPolicy for DefaultPolicy {}
+impl ChannelPolicy for DefaultPolicy {}
+impl AuthHandler for DefaultPolicy {}
+impl RequestScreener for DefaultPolicy {}
@@ -0,0 +1,152 @@
+// =============================================================================
+// Paid channel opens
+// =============================================================================
+//
+// LUD-02 channels are free by default. When either of
+//
+//   LNURL_CHANNEL_PRICE_BASE_SAT, LNURL_CHANNEL_PRICE_PPM
+//
+// is set, /request-channel sells them instead: besides the usual uri,
+// callback and k1 it returns a bolt11 (`pr`) for a channel of the capacity
+// limit (`capacity_sat`), priced at
+//
+//   base + capacity * ppm / 1_000_000 + the on-chain fee of the funding tx
+//
+// the latter at the node's current opening feerate. /open-channel funds
+// nothing until that invoice is paid: before, it answers 402 and the k1 stays
+// good, so the wallet can call again once its payment went through. Invoices
+// are the node's, looked up by their label (`lnurl-channel-<k1>`); the
+// capacity quoted is stored with the k1, and is what the channel is opened
+// at, whatever the limits have become since. Such a k1 is not held to the
+// channel TTL, which could run out before a payment made late in the
+// invoice's INVOICE_EXPIRY_SECS: it stays good for as long as the invoice
+// may be paid, and once paid until the channel is opened (see expiry.rs). A
+// paid open spends its k1 only once every check passed, right before
+// funding, and gets it back as it was if funding fails, so that the wallet
+// can retry without paying twice.
+//
+// Cancelling (cancel=1) after paying does not refund; that is up to the
+// operator.
+
+use std::fmt;
+use tracing::info;
+
+use crate::backend::BackendResult;
+use crate::AppState;
+
+/// How long the wallet has to pay for the channel
+pub const INVOICE_EXPIRY_SECS: u64 = 10 * 60;
+/// A funding tx spending one P2WPKH output into the channel and change
+const FUNDING_TX_VBYTES: u64 = 154;
+
+#[derive(Debug)]
+pub struct PricingConfigError(String);
+
+impl fmt::Display for PricingConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PricingConfigError {}
+
+/// What a channel costs on top of its funding tx's fee
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelPricing {
+    pub base_sat: u64,
+    pub ppm: u64, // of the capacity
+}
+
+impl ChannelPricing {
+    /// The price of a `capacity_sat` channel funded at `feerate_perkb` (sat
+    /// per 1000 vbytes)
+    pub fn price_msat(&self, capacity_sat: u64, feerate_perkb: u64) -> u64 {
+        let proportional_msat = capacity_sat as u128 * 1000 * self.ppm as u128 / 1_000_000;
+        let funding_msat = feerate_perkb * FUNDING_TX_VBYTES; // sat/kvB * vB = msat
+        self.base_sat * 1000 + proportional_msat as u64 + funding_msat
+    }
+}
+
+/// A channel on sale
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub capacity_sat: u64,
+    pub price_msat: u64,
+    pub bolt11: String,
+}
+
+/// The label of the invoice selling the channel of `k1`
+pub fn invoice_label(k1: &str) -> String {
+    format!("lnurl-channel-{}", k1)
+}
+
+/// Prices the channel of `k1` at the capacity limit and has the node issue
+/// its invoice
+pub async fn quote(state: &AppState, pricing: &ChannelPricing, k1: &str) -> BackendResult<Quote> {
+    let capacity_sat = state.limits.lock().await.channel_capacity_sat;
+    let feerate_perkb = state.backend.opening_feerate_perkb().await?;
+    let price_msat = pricing.price_msat(capacity_sat, feerate_perkb);
+    let bolt11 = state
+        .backend
+        .create_invoice(
+            price_msat,
+            &invoice_label(k1),
+            &format!("Inbound channel of {} sats", capacity_sat),
+            INVOICE_EXPIRY_SECS,
+        )
+        .await?;
+    Ok(Quote {
+        capacity_sat,
+        price_msat,
+        bolt11,
+    })
+}
+
+fn var(name: &str) -> Result<Option<u64>, PricingConfigError> {
+    match std::env::var(name) {
+        Err(_) => Ok(None),
+        Ok(raw) => raw
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| PricingConfigError(format!("{} must be a whole number", name))),
+    }
+}
+
+/// Reads LNURL_CHANNEL_PRICE_BASE_SAT and LNURL_CHANNEL_PRICE_PPM, None when
+/// neither is set and channels are free
+pub fn load_pricing() -> Result<Option<ChannelPricing>, PricingConfigError> {
+    let base_sat = var("LNURL_CHANNEL_PRICE_BASE_SAT")?;
+    let ppm = var("LNURL_CHANNEL_PRICE_PPM")?;
+    if base_sat.is_none() && ppm.is_none() {
+        return Ok(None);
+    }
+
+    let pricing = ChannelPricing {
+        base_sat: base_sat.unwrap_or(0),
+        ppm: ppm.unwrap_or(0),
+    };
+    info!(
+        "Selling channels for {} sat + {} ppm + the funding fee",
+        pricing.base_sat, pricing.ppm
+    );
+    Ok(Some(pricing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_adds_base_proportional_and_funding_fee() {
+        let pricing = ChannelPricing {
+            base_sat: 1_000,
+            ppm: 5_000,
+        };
+        // 1M sat channel at 2 sat/vB: 1000 + 5000 + 308 sat
+        assert_eq!(pricing.price_msat(1_000_000, 2_000), 6_308_000);
+        // Sub-sat parts are kept
+        assert_eq!(pricing.price_msat(1_001, 0), 1_005_005);
+        assert_eq!(ChannelPricing::default().price_msat(1_000_000, 253), 38_962);
+    }
+}
@@ -0,0 +1,226 @@
+// =============================================================================
+// CIDR request screening
+// =============================================================================
+//
+// The built-in RequestScreener (policy.rs): networks listed in
+//
+//   LNURL_DENY_CIDRS   e.g. "203.0.113.0/24, 2001:db8::/32"
+//
+// get no k1s and no channels, and when
+//
+//   LNURL_ALLOW_CIDRS
+//
+// is set only the networks it lists do. Deny wins over allow. Entries are
+// separated by commas or whitespace; a bare address is a network of one.
+// IPv4 addresses arriving as IPv4-mapped IPv6 (::ffff:a.b.c.d) match IPv4
+// networks.
+//
+// The server sees the address it is connected to: behind a reverse proxy,
+// screen there instead. With an allow list, requests whose address the server
+// isn't told (embedded without connect info) are refused.
+
+use async_trait::async_trait;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use tracing::info;
+
+use crate::policy::{RequestScreener, Screened, Verdict};
+
+#[derive(Debug)]
+pub struct ScreenConfigError(String);
+
+impl fmt::Display for ScreenConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ScreenConfigError {}
+
+/// An address block, `<address>/<prefix length>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ScreenConfigError;
+
+    fn from_str(s: &str) -> Result<Cidr, ScreenConfigError> {
+        let invalid = || ScreenConfigError(format!("Invalid network: {}", s));
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network = IpAddr::from_str(address)
+            .map_err(|_| invalid())?
+            .to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max,
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CidrScreener {
+    allow: Vec<Cidr>, // empty: every network not denied
+    deny: Vec<Cidr>,
+}
+
+impl CidrScreener {
+    pub fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> CidrScreener {
+        CidrScreener { allow, deny }
+    }
+
+    fn check(&self, peer: Option<IpAddr>) -> Verdict {
+        let refused = Err("Requests from your network are not accepted".to_string());
+        let Some(peer) = peer else {
+            return match self.allow.is_empty() {
+                true => Ok(()),
+                false => refused,
+            };
+        };
+        if self.deny.iter().any(|cidr| cidr.contains(peer)) {
+            return refused;
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|cidr| cidr.contains(peer)) {
+            return refused;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RequestScreener for CidrScreener {
+    async fn screen(&self, peer: Option<IpAddr>, screened: Screened) -> Verdict {
+        let verdict = self.check(peer);
+        if verdict.is_err() {
+            let peer = peer.map_or("an unknown address".to_string(), |ip| ip.to_string());
+            info!("Screened out {:?} from {}", screened, peer);
+        }
+        verdict
+    }
+}
+
+/// The networks listed in `var`, none when it is unset
+pub(crate) fn load_list(var: &str) -> Result<Vec<Cidr>, ScreenConfigError> {
+    let Ok(raw) = std::env::var(var) else {
+        return Ok(Vec::new());
+    };
+    raw.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse()
+                .map_err(|e: ScreenConfigError| ScreenConfigError(format!("{}: {}", var, e)))
+        })
+        .collect()
+}
+
+/// Reads LNURL_ALLOW_CIDRS and LNURL_DENY_CIDRS, None when both are unset
+/// or empty
+pub fn load_screener() -> Result<Option<CidrScreener>, ScreenConfigError> {
+    let allow = load_list("LNURL_ALLOW_CIDRS")?;
+    let deny = load_list("LNURL_DENY_CIDRS")?;
+    if allow.is_empty() && deny.is_empty() {
+        return Ok(None);
+    }
+
+    let list = |cidrs: &[Cidr]| {
+        cidrs
+            .iter()
+            .map(Cidr::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if !allow.is_empty() {
+        info!("Only serving k1s and channels to {}", list(&allow));
+    }
+    if !deny.is_empty() {
+        info!("Refusing k1s and channels to {}", list(&deny));
+    }
+    Ok(Some(CidrScreener::new(allow, deny)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    fn cidrs(list: &[&str]) -> Vec<Cidr> {
+        list.iter().map(|cidr| cidr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn networks_match_on_their_prefix() {
+        let cidr: Cidr = "203.0.113.0/24".parse().unwrap();
+        assert!(cidr.contains("203.0.113.77".parse().unwrap()));
+        assert!(!cidr.contains("203.0.114.1".parse().unwrap()));
+        // As a dual-stack socket reports IPv4 peers
+        assert!(cidr.contains("::ffff:203.0.113.77".parse().unwrap()));
+
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!cidr.contains("2001:db9::1".parse().unwrap()));
+        assert!(!cidr.contains("203.0.113.77".parse().unwrap()));
+
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("198.51.100.1".parse().unwrap()));
+        let single: Cidr = "198.51.100.1".parse().unwrap();
+        assert_eq!(single.to_string(), "198.51.100.1/32");
+
+        assert!("198.51.100.0/33".parse::<Cidr>().is_err());
+        assert!("198.51.100/24".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let screener = CidrScreener::new(
+            cidrs(&["10.0.0.0/8"]),
+            cidrs(&["10.66.0.0/16", "192.0.2.1"]),
+        );
+        assert!(screener.check(ip("10.1.2.3")).is_ok());
+        assert!(screener.check(ip("10.66.2.3")).is_err());
+        assert!(screener.check(ip("192.0.2.1")).is_err());
+        // Outside the allow list, or unknown
+        assert!(screener.check(ip("198.51.100.1")).is_err());
+        assert!(screener.check(None).is_err());
+
+        let deny_only = CidrScreener::new(vec![], cidrs(&["192.0.2.0/24"]));
+        assert!(deny_only.check(ip("198.51.100.1")).is_ok());
+        assert!(deny_only.check(None).is_ok());
+    }
+}
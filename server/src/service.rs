@@ -0,0 +1,193 @@
+// =============================================================================
+// Operator service layer
+// =============================================================================
+//
+// The operations both operator APIs offer, the admin HTTP API (admin.rs) and
+// the gRPC service (grpc.rs), written once. Callers authenticate, check the
+// role and hold the write gate; these talk to storage and decide what the
+// role gets to see.
+
+use serde::Serialize;
+use std::fmt;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::admin::Role;
+use crate::storage::{
+    K1Purpose, StorageError, StorageStats, ValidityWindow, Voucher, Withdrawal, WithdrawalStatus,
+};
+use crate::{lnurl, AppState};
+
+#[derive(Debug)]
+pub enum ServiceError {
+    NotFound(&'static str),
+    Invalid(String),
+    Storage(StorageError),
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::NotFound(what) => write!(f, "Unknown {}", what),
+            ServiceError::Invalid(reason) => write!(f, "{}", reason),
+            ServiceError::Storage(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<StorageError> for ServiceError {
+    fn from(e: StorageError) -> Self {
+        ServiceError::Storage(e)
+    }
+}
+
+pub type ServiceResult<T> = Result<T, ServiceError>;
+
+pub async fn stats(state: &AppState) -> ServiceResult<StorageStats> {
+    Ok(state.storage.stats().await?)
+}
+
+// -----------------------------------------------------------------------------
+// Vouchers
+// -----------------------------------------------------------------------------
+
+/// A withdraw k1 bound to an account, as if its owner had asked for it
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedVoucher {
+    pub k1: String,
+    pub linking_key: String,
+    #[serde(flatten)]
+    pub window: ValidityWindow,
+    /// The withdrawRequest for the wallet, to share as a link or QR code
+    pub url: String,
+    /// `url` as an LNURL, for wallets that only take those
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lnurl: Option<String>,
+}
+
+/// Issues a voucher to an existing account. Whoever holds the URL can
+/// withdraw up to the account's budget with it, once, within `window`.
+pub async fn issue_voucher(
+    state: &AppState,
+    linking_key: &str,
+    window: ValidityWindow,
+) -> ServiceResult<IssuedVoucher> {
+    if let (Some(from), Some(until)) = (window.valid_from, window.valid_until) {
+        if until <= from {
+            return Err(ServiceError::Invalid(
+                "valid_until must be after valid_from".to_string(),
+            ));
+        }
+    }
+    if state.storage.get_account(linking_key).await?.is_none() {
+        return Err(ServiceError::NotFound("account"));
+    }
+
+    let k1 = Uuid::new_v4().to_string();
+    state.issue_k1(&k1, K1Purpose::Withdraw).await?;
+    let voucher = Voucher {
+        k1: k1.clone(),
+        linking_key: linking_key.to_string(),
+        window,
+    };
+    state.storage.insert_voucher(&voucher).await?;
+    info!(k1 = %k1, linking_key, "Voucher issued by an operator");
+
+    let url = format!("{}request-withdraw?k1={}", state.callback_url, k1);
+    Ok(IssuedVoucher {
+        lnurl: lnurl::encode(&url).ok(),
+        url,
+        k1,
+        linking_key: voucher.linking_key,
+        window,
+    })
+}
+
+// -----------------------------------------------------------------------------
+// Withdrawals
+// -----------------------------------------------------------------------------
+
+/// A withdrawal as operators (or its account) see it
+#[derive(Debug, Clone, Serialize)]
+pub struct WithdrawalView {
+    pub k1: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linking_key: Option<String>,
+    pub bolt11: String,
+    pub amount_msat: u64,
+    pub status: WithdrawalStatus,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>, // hex, admin role only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_msat: Option<u64>, // once paid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>, // the invoice's, cleaned
+}
+
+/// The withdrawal's preimage in hex, when there is one and a cipher to read it
+pub fn preimage(state: &AppState, withdrawal: &Withdrawal) -> Option<String> {
+    let cipher = state.cipher.as_deref()?;
+    let sealed = withdrawal.preimage_enc.as_deref()?;
+    match cipher.decrypt(sealed) {
+        Ok(preimage) => Some(hex::encode(preimage)),
+        Err(e) => {
+            error!(k1 = %withdrawal.k1, "Failed to decrypt preimage: {}", e);
+            None
+        }
+    }
+}
+
+/// Decrypts the preimage for admins
+pub fn view(state: &AppState, role: Role, withdrawal: Withdrawal) -> WithdrawalView {
+    let preimage = match role {
+        Role::Admin => preimage(state, &withdrawal),
+        Role::ReadOnly => None,
+    };
+    WithdrawalView {
+        k1: withdrawal.k1,
+        linking_key: withdrawal.linking_key,
+        bolt11: withdrawal.bolt11,
+        amount_msat: withdrawal.amount_msat,
+        status: withdrawal.status,
+        created_at: withdrawal.created_at,
+        preimage,
+        fee_msat: withdrawal.fee_msat,
+        description: withdrawal.description,
+    }
+}
+
+pub async fn withdrawal(state: &AppState, role: Role, k1: &str) -> ServiceResult<WithdrawalView> {
+    match state.storage.get_withdrawal(k1).await? {
+        Some(withdrawal) => Ok(view(state, role, withdrawal)),
+        None => Err(ServiceError::NotFound("withdrawal")),
+    }
+}
+
+pub async fn withdrawals(
+    state: &AppState,
+    role: Role,
+    limit: usize,
+) -> ServiceResult<Vec<WithdrawalView>> {
+    let withdrawals = state.storage.list_withdrawals(limit).await?;
+    Ok(withdrawals
+        .into_iter()
+        .map(|withdrawal| view(state, role, withdrawal))
+        .collect())
+}
+
+/// An account's own withdrawals, most recent first. Their preimages are the
+/// account's to see, as /withdraw-status shows them to whoever holds the k1.
+pub async fn account_withdrawals(
+    state: &AppState,
+    linking_key: &str,
+    limit: usize,
+) -> ServiceResult<Vec<WithdrawalView>> {
+    let withdrawals = state.storage.withdrawals_for(linking_key, limit).await?;
+    Ok(withdrawals
+        .into_iter()
+        .map(|withdrawal| view(state, Role::Admin, withdrawal))
+        .collect())
+}
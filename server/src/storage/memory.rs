@@ -0,0 +1,714 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::Mutex;
+
+use super::{
+    Account, Allowance, Channel, Deletion, DeletionRequester, K1Purpose, K1Status, K1Ttls,
+    LiquidityReport, Order, OrderState, Snapshot, Storage, StorageResult, StorageStats,
+    ValidityWindow, Voucher, Withdrawal, WithdrawalStatus,
+};
+use crate::backend::ChannelFees;
+
+#[derive(Default)]
+struct Inner {
+    // k1 -> (purpose, issued at); no purpose: restored from an older backup
+    k1s: HashMap<String, (Option<K1Purpose>, u64)>,
+    k1_capacities: HashMap<String, u64>, // channel k1 -> capacity it was sold at
+    accounts: HashMap<String, Account>, // linking key -> account
+    allowances: HashMap<String, Allowance>, // linking key -> allowance
+    balance_links: HashMap<String, String>, // linking key -> link
+    sessions: HashMap<String, (String, u64)>, // token -> (linking key, opened at)
+    vouchers: HashMap<String, Voucher>, // withdraw k1 -> voucher
+    withdrawals: Vec<Withdrawal>,       // insertion order
+    deletions: Vec<Deletion>,           // insertion order
+    channels: Vec<Channel>,             // insertion order
+    orders: Vec<Order>,                 // insertion order
+    liquidity: Vec<LiquidityReport>,    // insertion order
+}
+
+/// In-process storage, lost on restart
+#[derive(Default)]
+pub struct MemoryStorage {
+    inner: Mutex<Inner>,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn insert_k1(&self, k1: &str, purpose: K1Purpose) -> StorageResult<bool> {
+        let issued = (Some(purpose), crate::unix_now());
+        Ok(self.inner.lock().await.k1s.insert(k1.to_string(), issued).is_none())
+    }
+
+    async fn restore_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        issued_at: u64,
+        capacity_sat: Option<u64>,
+    ) -> StorageResult<bool> {
+        let mut inner = self.inner.lock().await;
+        match capacity_sat {
+            Some(capacity_sat) => inner.k1_capacities.insert(k1.to_string(), capacity_sat),
+            None => inner.k1_capacities.remove(k1),
+        };
+        Ok(inner.k1s.insert(k1.to_string(), (Some(purpose), issued_at)).is_none())
+    }
+
+    async fn k1_issued_at(&self, k1: &str) -> StorageResult<Option<u64>> {
+        Ok(self.inner.lock().await.k1s.get(k1).map(|(_, issued_at)| *issued_at))
+    }
+
+    async fn consume_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        ttls: &K1Ttls,
+    ) -> StorageResult<K1Status> {
+        let mut inner = self.inner.lock().await;
+        if matches!(inner.k1s.get(k1), Some((Some(issued_for), _)) if *issued_for != purpose) {
+            return Ok(K1Status::Unknown);
+        }
+        let sold = inner.k1_capacities.remove(k1).is_some();
+        Ok(match inner.k1s.remove(k1) {
+            Some(_) if sold => K1Status::Valid,
+            Some((purpose, issued_at)) if ttls.is_live(purpose, issued_at, crate::unix_now()) => {
+                K1Status::Valid
+            }
+            Some(_) => K1Status::Expired,
+            None => K1Status::Unknown,
+        })
+    }
+
+    async fn set_k1_capacity(&self, k1: &str, capacity_sat: u64) -> StorageResult<()> {
+        let mut inner = self.inner.lock().await;
+        if inner.k1s.contains_key(k1) {
+            inner.k1_capacities.insert(k1.to_string(), capacity_sat);
+        }
+        Ok(())
+    }
+
+    async fn k1_capacity(&self, k1: &str) -> StorageResult<Option<u64>> {
+        Ok(self.inner.lock().await.k1_capacities.get(k1).copied())
+    }
+
+    async fn sold_k1s(&self) -> StorageResult<Vec<String>> {
+        Ok(self.inner.lock().await.k1_capacities.keys().cloned().collect())
+    }
+
+    async fn list_k1s(&self, limit: usize) -> StorageResult<Vec<(String, Option<K1Purpose>)>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .k1s
+            .iter()
+            .take(limit)
+            .map(|(k1, (purpose, _))| (k1.clone(), *purpose))
+            .collect())
+    }
+
+    async fn sweep_k1s(&self, ttls: &K1Ttls, now: u64) -> StorageResult<Vec<Option<K1Purpose>>> {
+        let mut swept = Vec::new();
+        let mut inner = self.inner.lock().await;
+        let Inner { k1s, k1_capacities, .. } = &mut *inner;
+        k1s.retain(|k1, (purpose, issued_at)| {
+            let live = ttls.is_live(*purpose, *issued_at, now) || k1_capacities.contains_key(k1);
+            if !live {
+                swept.push(*purpose);
+            }
+            live
+        });
+        Ok(swept)
+    }
+
+    async fn ensure_account(&self, linking_key: &str, withdraw_budget_msat: u64) -> StorageResult<bool> {
+        let mut inner = self.inner.lock().await;
+        if inner.accounts.contains_key(linking_key) {
+            return Ok(false);
+        }
+        let account = Account {
+            linking_key: linking_key.to_string(),
+            created_at: crate::unix_now(),
+            withdraw_budget_msat,
+            last_login_at: None,
+            metadata: BTreeMap::new(),
+        };
+        inner.accounts.insert(linking_key.to_string(), account);
+        Ok(true)
+    }
+
+    async fn get_account(&self, linking_key: &str) -> StorageResult<Option<Account>> {
+        Ok(self.inner.lock().await.accounts.get(linking_key).cloned())
+    }
+
+    async fn list_accounts(&self, limit: usize) -> StorageResult<Vec<Account>> {
+        let mut accounts: Vec<Account> =
+            self.inner.lock().await.accounts.values().cloned().collect();
+        accounts.sort_by(|a, b| {
+            (b.last_login_at, b.created_at).cmp(&(a.last_login_at, a.created_at))
+        });
+        accounts.truncate(limit);
+        Ok(accounts)
+    }
+
+    async fn record_login(&self, linking_key: &str, at: u64) -> StorageResult<()> {
+        if let Some(account) = self.inner.lock().await.accounts.get_mut(linking_key) {
+            account.last_login_at = Some(at);
+        }
+        Ok(())
+    }
+
+    async fn set_account_metadata(
+        &self,
+        linking_key: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> StorageResult<bool> {
+        match self.inner.lock().await.accounts.get_mut(linking_key) {
+            Some(account) => {
+                account.metadata = metadata.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn debit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<bool> {
+        let mut inner = self.inner.lock().await;
+        match inner.accounts.get_mut(linking_key) {
+            Some(account) if account.withdraw_budget_msat >= amount_msat => {
+                account.withdraw_budget_msat -= amount_msat;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn credit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<()> {
+        if let Some(account) = self.inner.lock().await.accounts.get_mut(linking_key) {
+            account.withdraw_budget_msat += amount_msat;
+        }
+        Ok(())
+    }
+
+    async fn put_allowance(&self, allowance: &Allowance) -> StorageResult<()> {
+        self.inner
+            .lock()
+            .await
+            .allowances
+            .insert(allowance.linking_key.clone(), allowance.clone());
+        Ok(())
+    }
+
+    async fn get_allowance(&self, linking_key: &str) -> StorageResult<Option<Allowance>> {
+        Ok(self.inner.lock().await.allowances.get(linking_key).cloned())
+    }
+
+    async fn allowance_by_link(&self, link: &str) -> StorageResult<Option<Allowance>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .allowances
+            .values()
+            .find(|a| a.link == link)
+            .cloned())
+    }
+
+    async fn list_allowances(&self) -> StorageResult<Vec<Allowance>> {
+        Ok(self.inner.lock().await.allowances.values().cloned().collect())
+    }
+
+    async fn remove_allowance(&self, linking_key: &str) -> StorageResult<bool> {
+        Ok(self.inner.lock().await.allowances.remove(linking_key).is_some())
+    }
+
+    async fn set_balance_notify(&self, linking_key: &str, url: &str) -> StorageResult<()> {
+        if let Some(allowance) = self.inner.lock().await.allowances.get_mut(linking_key) {
+            allowance.balance_notify = Some(url.to_string());
+        }
+        Ok(())
+    }
+
+    async fn refill_allowance(&self, linking_key: &str, next_refill_at: u64) -> StorageResult<u64> {
+        let mut inner = self.inner.lock().await;
+        let inner = &mut *inner;
+        let (Some(allowance), Some(account)) = (
+            inner.allowances.get_mut(linking_key),
+            inner.accounts.get_mut(linking_key),
+        ) else {
+            return Ok(0);
+        };
+        let added = allowance
+            .amount_msat
+            .saturating_sub(account.withdraw_budget_msat);
+        account.withdraw_budget_msat += added;
+        allowance.next_refill_at = next_refill_at;
+        Ok(added)
+    }
+
+    async fn balance_link(&self, linking_key: &str, new: &str) -> StorageResult<String> {
+        let mut inner = self.inner.lock().await;
+        let link = inner
+            .balance_links
+            .entry(linking_key.to_string())
+            .or_insert_with(|| new.to_string());
+        Ok(link.clone())
+    }
+
+    async fn balance_link_owner(&self, link: &str) -> StorageResult<Option<String>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .balance_links
+            .iter()
+            .find(|(_, owned)| *owned == link)
+            .map(|(linking_key, _)| linking_key.clone()))
+    }
+
+    async fn insert_session(&self, token: &str, linking_key: &str) -> StorageResult<()> {
+        self.inner
+            .lock()
+            .await
+            .sessions
+            .insert(token.to_string(), (linking_key.to_string(), crate::unix_now()));
+        Ok(())
+    }
+
+    async fn session_linking_key(
+        &self,
+        token: &str,
+        ttl_secs: u64,
+        now: u64,
+    ) -> StorageResult<Option<String>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .sessions
+            .get(token)
+            .filter(|(_, opened_at)| now < opened_at.saturating_add(ttl_secs))
+            .map(|(owner, _)| owner.clone()))
+    }
+
+    async fn remove_session(&self, token: &str) -> StorageResult<bool> {
+        Ok(self.inner.lock().await.sessions.remove(token).is_some())
+    }
+
+    async fn sweep_sessions(&self, ttl_secs: u64, now: u64) -> StorageResult<usize> {
+        let mut inner = self.inner.lock().await;
+        let before = inner.sessions.len();
+        inner
+            .sessions
+            .retain(|_, (_, opened_at)| now < opened_at.saturating_add(ttl_secs));
+        Ok(before - inner.sessions.len())
+    }
+
+    async fn list_sessions(&self, limit: usize) -> StorageResult<Vec<(String, String)>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .sessions
+            .iter()
+            .take(limit)
+            .map(|(token, (owner, _))| (token.clone(), owner.clone()))
+            .collect())
+    }
+
+    async fn insert_voucher(&self, voucher: &Voucher) -> StorageResult<()> {
+        self.inner
+            .lock()
+            .await
+            .vouchers
+            .insert(voucher.k1.clone(), voucher.clone());
+        Ok(())
+    }
+
+    async fn take_voucher(&self, k1: &str) -> StorageResult<Option<String>> {
+        let voucher = self.inner.lock().await.vouchers.remove(k1);
+        Ok(voucher.map(|voucher| voucher.linking_key))
+    }
+
+    async fn get_voucher(&self, k1: &str) -> StorageResult<Option<Voucher>> {
+        Ok(self.inner.lock().await.vouchers.get(k1).cloned())
+    }
+
+    async fn vouchers_for(&self, linking_key: &str) -> StorageResult<Vec<String>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .vouchers
+            .values()
+            .filter(|voucher| voucher.linking_key == linking_key)
+            .map(|voucher| voucher.k1.clone())
+            .collect())
+    }
+
+    async fn list_vouchers(&self) -> StorageResult<Vec<Voucher>> {
+        Ok(self.inner.lock().await.vouchers.values().cloned().collect())
+    }
+
+    async fn insert_withdrawal(&self, withdrawal: &Withdrawal) -> StorageResult<()> {
+        self.inner.lock().await.withdrawals.push(withdrawal.clone());
+        Ok(())
+    }
+
+    async fn finish_withdrawal(
+        &self,
+        k1: &str,
+        status: WithdrawalStatus,
+        preimage_enc: Option<&str>,
+        fee_msat: Option<u64>,
+    ) -> StorageResult<()> {
+        let mut inner = self.inner.lock().await;
+        if let Some(withdrawal) = inner.withdrawals.iter_mut().find(|w| w.k1 == k1) {
+            withdrawal.status = status;
+            withdrawal.preimage_enc = preimage_enc.map(str::to_string);
+            withdrawal.fee_msat = fee_msat;
+        }
+        Ok(())
+    }
+
+    async fn get_withdrawal(&self, k1: &str) -> StorageResult<Option<Withdrawal>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .withdrawals
+            .iter()
+            .find(|w| w.k1 == k1)
+            .cloned())
+    }
+
+    async fn list_withdrawals(&self, limit: usize) -> StorageResult<Vec<Withdrawal>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .withdrawals
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn withdrawals_for(
+        &self,
+        linking_key: &str,
+        limit: usize,
+    ) -> StorageResult<Vec<Withdrawal>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .withdrawals
+            .iter()
+            .rev()
+            .filter(|withdrawal| withdrawal.linking_key.as_deref() == Some(linking_key))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_channel(&self, channel: &Channel) -> StorageResult<()> {
+        self.inner.lock().await.channels.push(channel.clone());
+        Ok(())
+    }
+
+    async fn list_channels(&self, limit: usize) -> StorageResult<Vec<Channel>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .channels
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn set_channel_fees(&self, channel_id: &str, fees: &ChannelFees) -> StorageResult<()> {
+        let mut inner = self.inner.lock().await;
+        if let Some(channel) = inner
+            .channels
+            .iter_mut()
+            .find(|c| c.channel_id == channel_id)
+        {
+            channel.fees = Some(fees.clone());
+            channel.fees_set_at = Some(crate::unix_now());
+        }
+        Ok(())
+    }
+
+    async fn insert_order(&self, order: &Order) -> StorageResult<()> {
+        self.inner.lock().await.orders.push(order.clone());
+        Ok(())
+    }
+
+    async fn get_order(&self, id: &str) -> StorageResult<Option<Order>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .orders
+            .iter()
+            .find(|o| o.id == id)
+            .cloned())
+    }
+
+    async fn list_orders(&self, state: OrderState) -> StorageResult<Vec<Order>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .orders
+            .iter()
+            .filter(|o| o.state == state)
+            .cloned()
+            .collect())
+    }
+
+    async fn claim_order(&self, id: &str, paid_at: u64) -> StorageResult<bool> {
+        let mut inner = self.inner.lock().await;
+        match inner.orders.iter_mut().find(|o| o.id == id) {
+            Some(order) if order.state == OrderState::Created => {
+                order.state = OrderState::Funding;
+                order.paid_at = order.paid_at.or(Some(paid_at));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn update_order(&self, order: &Order) -> StorageResult<()> {
+        let mut inner = self.inner.lock().await;
+        if let Some(stored) = inner.orders.iter_mut().find(|o| o.id == order.id) {
+            *stored = order.clone();
+        }
+        Ok(())
+    }
+
+    async fn delete_account(
+        &self,
+        linking_key: &str,
+        requested_by: DeletionRequester,
+    ) -> StorageResult<Option<Deletion>> {
+        let mut inner = self.inner.lock().await;
+        let Some(account) = inner.accounts.remove(linking_key) else {
+            return Ok(None);
+        };
+
+        inner.allowances.remove(linking_key);
+        inner.balance_links.remove(linking_key);
+        let sessions_before = inner.sessions.len();
+        inner.sessions.retain(|_, (owner, _)| owner != linking_key);
+        let sessions_removed = sessions_before - inner.sessions.len();
+
+        let voucher_k1s: Vec<String> = inner
+            .vouchers
+            .values()
+            .filter(|voucher| voucher.linking_key == linking_key)
+            .map(|voucher| voucher.k1.clone())
+            .collect();
+        for k1 in &voucher_k1s {
+            inner.vouchers.remove(k1);
+            inner.k1s.remove(k1);
+        }
+
+        let mut withdrawals_anonymized = 0;
+        for withdrawal in inner.withdrawals.iter_mut() {
+            if withdrawal.linking_key.as_deref() == Some(linking_key) {
+                withdrawal.linking_key = None;
+                withdrawal.bolt11.clear();
+                withdrawal.description = None;
+                withdrawals_anonymized += 1;
+            }
+        }
+
+        let deletion = Deletion {
+            id: uuid::Uuid::new_v4().to_string(),
+            deleted_at: crate::unix_now(),
+            requested_by,
+            sessions_removed: sessions_removed as u64,
+            vouchers_removed: voucher_k1s.len() as u64,
+            withdrawals_anonymized,
+            forfeited_budget_msat: account.withdraw_budget_msat,
+        };
+        inner.deletions.push(deletion.clone());
+        Ok(Some(deletion))
+    }
+
+    async fn list_deletions(&self, limit: usize) -> StorageResult<Vec<Deletion>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .deletions
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn stats(&self) -> StorageResult<StorageStats> {
+        let inner = self.inner.lock().await;
+        Ok(StorageStats {
+            accounts: inner.accounts.len(),
+            sessions: inner.sessions.len(),
+            pending_k1s: inner.k1s.len(),
+            pending_k1s_by_purpose: inner
+                .k1s
+                .values()
+                .filter_map(|(purpose, _)| *purpose)
+                .fold(HashMap::new(), |mut counts, purpose| {
+                    *counts.entry(purpose).or_default() += 1;
+                    counts
+                }),
+            vouchers: inner.vouchers.len(),
+            outstanding_budget_msat: inner
+                .accounts
+                .values()
+                .map(|a| a.withdraw_budget_msat)
+                .sum(),
+            pending_withdrawals_msat: inner
+                .withdrawals
+                .iter()
+                .filter(|w| w.status == WithdrawalStatus::Pending)
+                .map(|w| w.amount_msat)
+                .sum(),
+        })
+    }
+
+    async fn insert_liquidity_report(&self, report: &LiquidityReport) -> StorageResult<()> {
+        self.inner.lock().await.liquidity.push(report.clone());
+        Ok(())
+    }
+
+    async fn list_liquidity_reports(&self, limit: usize) -> StorageResult<Vec<LiquidityReport>> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .liquidity
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn export(&self) -> StorageResult<Snapshot> {
+        let inner = self.inner.lock().await;
+        Ok(Snapshot {
+            k1s: inner.k1s.keys().cloned().collect(),
+            k1_purposes: inner
+                .k1s
+                .iter()
+                .filter_map(|(k1, (purpose, _))| Some((k1.clone(), (*purpose)?)))
+                .collect(),
+            k1_capacities: inner.k1_capacities.clone(),
+            k1_issued_at: inner
+                .k1s
+                .iter()
+                .map(|(k1, (_, issued_at))| (k1.clone(), *issued_at))
+                .collect(),
+            accounts: inner.accounts.values().cloned().collect(),
+            sessions: inner
+                .sessions
+                .iter()
+                .map(|(token, (owner, _))| (token.clone(), owner.clone()))
+                .collect(),
+            session_opened_at: inner
+                .sessions
+                .iter()
+                .map(|(token, (_, opened_at))| (token.clone(), *opened_at))
+                .collect(),
+            vouchers: inner
+                .vouchers
+                .values()
+                .map(|voucher| (voucher.k1.clone(), voucher.linking_key.clone()))
+                .collect(),
+            voucher_windows: inner
+                .vouchers
+                .values()
+                .filter(|voucher| voucher.window != ValidityWindow::default())
+                .map(|voucher| (voucher.k1.clone(), voucher.window))
+                .collect(),
+            withdrawals: inner.withdrawals.clone(),
+            deletions: inner.deletions.clone(),
+            channels: inner.channels.clone(),
+            allowances: inner.allowances.values().cloned().collect(),
+            balance_links: inner
+                .balance_links
+                .iter()
+                .map(|(linking_key, link)| (link.clone(), linking_key.clone()))
+                .collect(),
+            orders: inner.orders.clone(),
+        })
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> StorageResult<()> {
+        let now = crate::unix_now();
+        let mut inner = self.inner.lock().await;
+        *inner = Inner {
+            k1s: snapshot
+                .k1s
+                .into_iter()
+                .map(|k1| {
+                    let purpose = snapshot.k1_purposes.get(&k1).copied();
+                    let issued_at = snapshot.k1_issued_at.get(&k1).copied();
+                    (k1, (purpose, issued_at.unwrap_or(now)))
+                })
+                .collect(),
+            k1_capacities: snapshot.k1_capacities,
+            accounts: snapshot
+                .accounts
+                .into_iter()
+                .map(|a| (a.linking_key.clone(), a))
+                .collect(),
+            sessions: snapshot
+                .sessions
+                .into_iter()
+                .map(|(token, linking_key)| {
+                    let opened_at = snapshot.session_opened_at.get(&token).copied();
+                    (token, (linking_key, opened_at.unwrap_or(now)))
+                })
+                .collect(),
+            vouchers: snapshot
+                .vouchers
+                .into_iter()
+                .map(|(k1, linking_key)| {
+                    let window = snapshot.voucher_windows.get(&k1).copied();
+                    let voucher = Voucher {
+                        k1: k1.clone(),
+                        linking_key,
+                        window: window.unwrap_or_default(),
+                    };
+                    (k1, voucher)
+                })
+                .collect(),
+            withdrawals: snapshot.withdrawals,
+            deletions: snapshot.deletions,
+            channels: snapshot.channels,
+            allowances: snapshot
+                .allowances
+                .into_iter()
+                .map(|a| (a.linking_key.clone(), a))
+                .collect(),
+            balance_links: snapshot
+                .balance_links
+                .into_iter()
+                .map(|(link, linking_key)| (linking_key, link))
+                .collect(),
+            orders: snapshot.orders,
+            // Not part of backups
+            liquidity: std::mem::take(&mut inner.liquidity),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,625 @@
+// =============================================================================
+// Storage
+// =============================================================================
+//
+// Everything that must survive between the request and the callback (k1s,
+// accounts, sessions, vouchers) and the withdrawal history goes through the
+// `Storage` trait so that several server replicas can share one database.
+// Sensitive columns arrive already encrypted (see crypto.rs).
+//
+//   memory    — default, lost on restart, single instance only
+//   postgres  — selected with LNURL_DATABASE_URL=postgres://...
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use tracing::{info, warn};
+
+use crate::backend::{ChannelBalance, ChannelFees};
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::MemoryStorage;
+pub use postgres::PostgresStorage;
+// Withdrawals are stored with the status /withdraw-status reports
+pub use lnurl_models::WithdrawalStatus;
+
+/// The flow a k1 was issued for, and the only one whose callback takes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum K1Purpose {
+    Channel,
+    Withdraw,
+    Auth,
+    Pay, // a disposable pay link's callback (pay.rs)
+}
+
+impl K1Purpose {
+    pub const ALL: [K1Purpose; 4] = [
+        K1Purpose::Channel,
+        K1Purpose::Withdraw,
+        K1Purpose::Auth,
+        K1Purpose::Pay,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            K1Purpose::Channel => "channel",
+            K1Purpose::Withdraw => "withdraw",
+            K1Purpose::Auth => "auth",
+            K1Purpose::Pay => "pay",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<K1Purpose> {
+        match s {
+            "channel" => Some(K1Purpose::Channel),
+            "withdraw" => Some(K1Purpose::Withdraw),
+            "auth" => Some(K1Purpose::Auth),
+            "pay" => Some(K1Purpose::Pay),
+            _ => None,
+        }
+    }
+}
+
+/// How long a k1 stays valid after it is issued, per flow. Auth k1s are
+/// signed right away, withdraw k1s may sit in a wallet while its user picks
+/// an amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct K1Ttls {
+    pub channel_secs: u64,
+    pub withdraw_secs: u64,
+    pub auth_secs: u64,
+    pub pay_secs: u64,
+}
+
+impl Default for K1Ttls {
+    fn default() -> Self {
+        K1Ttls {
+            channel_secs: 10 * 60,
+            withdraw_secs: 60 * 60,
+            auth_secs: 2 * 60,
+            pay_secs: 10 * 60,
+        }
+    }
+}
+
+impl K1Ttls {
+    /// The TTL of a stored k1; those stored before purposes were recorded get
+    /// the longest
+    pub fn of(&self, purpose: Option<K1Purpose>) -> u64 {
+        match purpose {
+            Some(K1Purpose::Channel) => self.channel_secs,
+            Some(K1Purpose::Withdraw) => self.withdraw_secs,
+            Some(K1Purpose::Auth) => self.auth_secs,
+            Some(K1Purpose::Pay) => self.pay_secs,
+            None => [self.channel_secs, self.withdraw_secs, self.auth_secs, self.pay_secs]
+                .into_iter()
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether a k1 issued at `issued_at` is still valid at `now`
+    pub fn is_live(&self, purpose: Option<K1Purpose>, issued_at: u64, now: u64) -> bool {
+        now < issued_at.saturating_add(self.of(purpose))
+    }
+}
+
+/// Reads LNURL_CHANNEL_K1_TTL_SECS, LNURL_WITHDRAW_K1_TTL_SECS,
+/// LNURL_AUTH_K1_TTL_SECS and LNURL_PAY_K1_TTL_SECS, keeping the default for
+/// whichever is unset or malformed
+pub fn load_k1_ttls() -> K1Ttls {
+    let mut ttls = K1Ttls::default();
+    for (var, value) in [
+        ("LNURL_CHANNEL_K1_TTL_SECS", &mut ttls.channel_secs),
+        ("LNURL_WITHDRAW_K1_TTL_SECS", &mut ttls.withdraw_secs),
+        ("LNURL_AUTH_K1_TTL_SECS", &mut ttls.auth_secs),
+        ("LNURL_PAY_K1_TTL_SECS", &mut ttls.pay_secs),
+    ] {
+        let Ok(raw) = std::env::var(var) else {
+            continue;
+        };
+        match raw.trim().parse() {
+            Ok(0) | Err(_) => warn!("Ignoring malformed {} (expected seconds above 0)", var),
+            Ok(parsed) => *value = parsed,
+        }
+    }
+
+    info!(
+        "k1 lifetimes: channel {}s, withdraw {}s, auth {}s, pay {}s",
+        ttls.channel_secs, ttls.withdraw_secs, ttls.auth_secs, ttls.pay_secs
+    );
+    ttls
+}
+
+/// How long a login session lasts unless LNURL_SESSION_TTL_SECS says otherwise:
+/// a week
+pub const DEFAULT_SESSION_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Reads LNURL_SESSION_TTL_SECS, keeping the default if it is unset or malformed
+pub fn load_session_ttl_secs() -> u64 {
+    let ttl_secs = match std::env::var("LNURL_SESSION_TTL_SECS") {
+        Err(_) => DEFAULT_SESSION_TTL_SECS,
+        Ok(raw) => match raw.trim().parse() {
+            Ok(0) | Err(_) => {
+                warn!("Ignoring malformed LNURL_SESSION_TTL_SECS (expected seconds above 0)");
+                DEFAULT_SESSION_TTL_SECS
+            }
+            Ok(parsed) => parsed,
+        },
+    };
+    info!("Sessions last {}s", ttl_secs);
+    ttl_secs
+}
+
+/// What became of a k1 presented to a callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum K1Status {
+    Valid,
+    /// Issued longer ago than its purpose's TTL
+    Expired,
+    /// Never issued, issued for another flow, or already used
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub linking_key: String,
+    pub created_at: u64,           // unix seconds
+    pub withdraw_budget_msat: u64, // remaining
+    /// The last LNURL-auth login, None for accounts from before they were kept
+    #[serde(default)]
+    pub last_login_at: Option<u64>,
+    /// Labels the operator keeps about the user, e.g. a name or a customer id
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// A withdraw k1 bound to an account, redeemable only within its window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Voucher {
+    pub k1: String,
+    pub linking_key: String,
+    #[serde(flatten)]
+    pub window: ValidityWindow,
+}
+
+/// When a voucher can be redeemed; open-ended on the sides left unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidityWindow {
+    pub valid_from: Option<u64>,  // unix seconds, inclusive
+    pub valid_until: Option<u64>, // unix seconds, exclusive
+}
+
+impl ValidityWindow {
+    pub fn status(&self, now: u64) -> WindowStatus {
+        match (self.valid_from, self.valid_until) {
+            (Some(from), _) if now < from => WindowStatus::NotYetActive,
+            (_, Some(until)) if now >= until => WindowStatus::Expired,
+            _ => WindowStatus::Active,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowStatus {
+    NotYetActive,
+    Active,
+    Expired,
+}
+
+/// A withdraw budget that refills on a schedule, one per account at most
+/// (see allowance.rs)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allowance {
+    pub linking_key: String,
+    pub link: String,        // secret id of its reusable withdraw link
+    pub amount_msat: u64,    // each refill tops the budget up to this
+    pub period_secs: u64,
+    pub next_refill_at: u64,
+    /// LUD-15 URL the wallet gave with its last withdraw, pinged on refills
+    pub balance_notify: Option<String>,
+    pub created_at: u64,
+}
+
+/// One accepted withdraw callback, keyed by its k1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub k1: String,
+    pub linking_key: Option<String>, // set when redeemed through a voucher
+    pub bolt11: String,
+    pub amount_msat: u64,
+    pub status: WithdrawalStatus,
+    pub preimage_enc: Option<String>, // sealed by crypto::FieldCipher
+    pub created_at: u64,
+    /// Routing fee the payment cost on top of amount_msat, once paid
+    #[serde(default)]
+    pub fee_msat: Option<u64>,
+    /// The invoice's description, cleaned by text::clean_description
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Who asked for an account deletion, kept in the audit record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletionRequester {
+    User,
+    Admin,
+}
+
+impl DeletionRequester {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletionRequester::User => "user",
+            DeletionRequester::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<DeletionRequester> {
+        match s {
+            "user" => Some(DeletionRequester::User),
+            "admin" => Some(DeletionRequester::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Audit record of an account deletion. Identifies nothing about the account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deletion {
+    pub id: String,
+    pub deleted_at: u64,
+    pub requested_by: DeletionRequester,
+    pub sessions_removed: u64,
+    pub vouchers_removed: u64,
+    pub withdrawals_anonymized: u64,
+    pub forfeited_budget_msat: u64, // remaining budget at deletion
+}
+
+/// A channel opened through the LUD-02 flow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Channel {
+    pub channel_id: String, // hex
+    pub node_id: String,    // the wallet's
+    pub capacity_sat: u64,
+    pub private: bool,
+    pub txid: String,
+    pub opened_at: u64,
+    /// What the fee setup (fees.rs) left it with, once it is done
+    pub fees: Option<ChannelFees>,
+    pub fees_set_at: Option<u64>,
+}
+
+/// A channel bought through LSPS1 (see lsps1.rs)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Order {
+    pub id: String,
+    pub node_id: String,   // the client's
+    pub capacity_sat: u64, // LSPS1's lsp_balance_sat
+    pub announce: bool,
+    pub required_confirmations: u32,
+    pub funding_confirms_within_blocks: u32,
+    pub channel_expiry_blocks: u32,
+    pub token: String,
+    pub price_msat: u64,
+    pub bolt11: String, // what the client pays
+    pub state: OrderState,
+    pub created_at: u64,
+    pub paid_at: Option<u64>, // first seen paid
+    pub channel: Option<OrderChannel>,
+}
+
+/// Where an order stands. A paid order whose funding failed goes back to
+/// Created, to be funded again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderState {
+    Created,   // waiting for its payment, or for its funding to be retried
+    Funding,   // claimed by the one funding it
+    Completed, // channel funded
+    Failed,    // invoice expired, or given up on after payment
+}
+
+impl OrderState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderState::Created => "created",
+            OrderState::Funding => "funding",
+            OrderState::Completed => "completed",
+            OrderState::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<OrderState> {
+        match s {
+            "created" => Some(OrderState::Created),
+            "funding" => Some(OrderState::Funding),
+            "completed" => Some(OrderState::Completed),
+            "failed" => Some(OrderState::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// The channel delivering an order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderChannel {
+    pub channel_id: String,       // hex
+    pub funding_outpoint: String, // <txid>:<outnum>
+    pub funded_at: u64,
+}
+
+/// The node's liquidity against what the service owes at one moment, taken
+/// by liquidity.rs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiquidityReport {
+    pub taken_at: u64,
+    pub onchain_sat: u64, // confirmed and not reserved
+    pub onchain_unconfirmed_sat: u64,
+    pub channels: Vec<ChannelBalance>,
+    pub outbound_msat: u64, // sum over usable channels
+    pub inbound_msat: u64,  // sum over usable channels
+    /// Withdraws accepted but not yet paid
+    pub pending_withdrawals_msat: u64,
+    /// Sum of remaining account budgets, all of which may be withdrawn
+    pub outstanding_budget_msat: u64,
+}
+
+/// Full storage contents, used by backup/restore. Liquidity reports are the
+/// node's history, not the service's, and are left out. Encrypted columns stay encrypted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub k1s: Vec<String>,
+    /// Purposes of the k1s above; backups from before k1s had one lack it
+    #[serde(default)]
+    pub k1_purposes: HashMap<String, K1Purpose>,
+    /// Capacities the channel k1s above were sold at
+    #[serde(default)]
+    pub k1_capacities: HashMap<String, u64>,
+    /// When the k1s above were issued; backups from before this was kept lack
+    /// it, and their TTLs restart at the restore
+    #[serde(default)]
+    pub k1_issued_at: HashMap<String, u64>,
+    pub accounts: Vec<Account>,
+    pub sessions: Vec<(String, String)>, // (token, linking key)
+    /// When the sessions above were opened; backups from before this was kept
+    /// lack it, and theirs restart at the restore
+    #[serde(default)]
+    pub session_opened_at: HashMap<String, u64>,
+    pub vouchers: Vec<(String, String)>, // (k1, linking key)
+    /// Windows of the vouchers above that have one
+    #[serde(default)]
+    pub voucher_windows: HashMap<String, ValidityWindow>,
+    pub withdrawals: Vec<Withdrawal>,
+    #[serde(default)]
+    pub deletions: Vec<Deletion>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    #[serde(default)]
+    pub allowances: Vec<Allowance>,
+    #[serde(default)]
+    pub balance_links: Vec<(String, String)>, // (link, linking key)
+    #[serde(default)]
+    pub orders: Vec<Order>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StorageStats {
+    pub accounts: usize,
+    pub sessions: usize,
+    pub pending_k1s: usize,
+    /// Of pending_k1s; k1s stored before purposes were recorded are in none
+    pub pending_k1s_by_purpose: HashMap<K1Purpose, usize>,
+    pub vouchers: usize,
+    pub outstanding_budget_msat: u64, // sum of remaining account budgets
+    pub pending_withdrawals_msat: u64, // sum of withdrawals still being paid
+}
+
+#[derive(Debug)]
+pub struct StorageError(String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(e: sqlx::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StorageError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+impl From<String> for StorageError {
+    fn from(e: String) -> Self {
+        StorageError(e)
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // k1 challenges (single-use)
+    /// Stores the k1, restarting its TTL if it is already there; returns
+    /// whether it wasn't
+    async fn insert_k1(&self, k1: &str, purpose: K1Purpose) -> StorageResult<bool>;
+    /// Stores a consumed k1 again as it was: issued at `issued_at`, with the
+    /// capacity it was sold at if any; returns whether it wasn't there
+    async fn restore_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        issued_at: u64,
+        capacity_sat: Option<u64>,
+    ) -> StorageResult<bool>;
+    /// When the k1 was issued, if it is held
+    async fn k1_issued_at(&self, k1: &str) -> StorageResult<Option<u64>>;
+    /// Removes the k1, returning whether it was still valid: issued for
+    /// `purpose`, unused and within the TTL `ttls` gives it. A k1 of another
+    /// flow is Unknown and left for its own callback; one stored before
+    /// purposes were recorded passes for any. A sold channel's (one with a
+    /// capacity) has no TTL: its invoice decides (pricing.rs).
+    async fn consume_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        ttls: &K1Ttls,
+    ) -> StorageResult<K1Status>;
+    /// Notes the capacity a channel k1 was sold at (pricing.rs), kept for as
+    /// long as the k1 is
+    async fn set_k1_capacity(&self, k1: &str, capacity_sat: u64) -> StorageResult<()>;
+    /// The capacity noted for the k1, which stays in place
+    async fn k1_capacity(&self, k1: &str) -> StorageResult<Option<u64>>;
+    /// The k1s with a capacity noted
+    async fn sold_k1s(&self) -> StorageResult<Vec<String>>;
+    /// Up to `limit` pending k1s, newest first where the storage knows
+    async fn list_k1s(&self, limit: usize) -> StorageResult<Vec<(String, Option<K1Purpose>)>>;
+    /// Removes the k1s no longer within the TTL `ttls` gives their purpose at
+    /// `now`, returning the purposes of those removed. Sold channel k1s stay.
+    async fn sweep_k1s(&self, ttls: &K1Ttls, now: u64) -> StorageResult<Vec<Option<K1Purpose>>>;
+
+    // Accounts
+    /// Creates the account with `withdraw_budget_msat` unless it already exists,
+    /// returning whether it did
+    async fn ensure_account(&self, linking_key: &str, withdraw_budget_msat: u64) -> StorageResult<bool>;
+    async fn get_account(&self, linking_key: &str) -> StorageResult<Option<Account>>;
+    /// Up to `limit` accounts, the most recent login first
+    async fn list_accounts(&self, limit: usize) -> StorageResult<Vec<Account>>;
+    async fn record_login(&self, linking_key: &str, at: u64) -> StorageResult<()>;
+    /// Replaces the account's metadata, returning false for an unknown account
+    async fn set_account_metadata(
+        &self,
+        linking_key: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> StorageResult<bool>;
+    /// Subtracts from the budget, returning false (and changing nothing) if it is too low
+    async fn debit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<bool>;
+    async fn credit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<()>;
+
+    // Allowances
+    /// Creates or replaces the account's allowance
+    async fn put_allowance(&self, allowance: &Allowance) -> StorageResult<()>;
+    async fn get_allowance(&self, linking_key: &str) -> StorageResult<Option<Allowance>>;
+    async fn allowance_by_link(&self, link: &str) -> StorageResult<Option<Allowance>>;
+    async fn list_allowances(&self) -> StorageResult<Vec<Allowance>>;
+    /// Returns whether there was one
+    async fn remove_allowance(&self, linking_key: &str) -> StorageResult<bool>;
+    async fn set_balance_notify(&self, linking_key: &str, url: &str) -> StorageResult<()>;
+    /// Tops the budget up to the allowance's amount and moves its next refill
+    /// to `next_refill_at`, in one step. Returns what was added.
+    async fn refill_allowance(&self, linking_key: &str, next_refill_at: u64) -> StorageResult<u64>;
+
+    // Balance links (LUD-14)
+    /// The secret id of the account's balance link, `new` if it had none yet
+    async fn balance_link(&self, linking_key: &str, new: &str) -> StorageResult<String>;
+    /// The account the balance link belongs to
+    async fn balance_link_owner(&self, link: &str) -> StorageResult<Option<String>>;
+
+    // Sessions
+    /// Stores a session opened now
+    async fn insert_session(&self, token: &str, linking_key: &str) -> StorageResult<()>;
+    /// The linking key the session was opened for, unless it was opened
+    /// `ttl_secs` or more before `now`
+    async fn session_linking_key(
+        &self,
+        token: &str,
+        ttl_secs: u64,
+        now: u64,
+    ) -> StorageResult<Option<String>>;
+    /// Returns whether there was one
+    async fn remove_session(&self, token: &str) -> StorageResult<bool>;
+    /// Removes the sessions opened `ttl_secs` or more before `now`, returning
+    /// how many there were
+    async fn sweep_sessions(&self, ttl_secs: u64, now: u64) -> StorageResult<usize>;
+    /// Up to `limit` sessions as (token, linking key), newest first where the
+    /// storage knows
+    async fn list_sessions(&self, limit: usize) -> StorageResult<Vec<(String, String)>>;
+
+    // Vouchers (withdraw k1s bound to an account)
+    async fn insert_voucher(&self, voucher: &Voucher) -> StorageResult<()>;
+    /// Removes the voucher, returning the account it belonged to
+    async fn take_voucher(&self, k1: &str) -> StorageResult<Option<String>>;
+    /// Leaves the voucher in place
+    async fn get_voucher(&self, k1: &str) -> StorageResult<Option<Voucher>>;
+    async fn vouchers_for(&self, linking_key: &str) -> StorageResult<Vec<String>>;
+    async fn list_vouchers(&self) -> StorageResult<Vec<Voucher>>;
+
+    // Withdrawals
+    async fn insert_withdrawal(&self, withdrawal: &Withdrawal) -> StorageResult<()>;
+    async fn finish_withdrawal(
+        &self,
+        k1: &str,
+        status: WithdrawalStatus,
+        preimage_enc: Option<&str>,
+        fee_msat: Option<u64>,
+    ) -> StorageResult<()>;
+    async fn get_withdrawal(&self, k1: &str) -> StorageResult<Option<Withdrawal>>;
+    /// Most recent first
+    async fn list_withdrawals(&self, limit: usize) -> StorageResult<Vec<Withdrawal>>;
+    /// Up to `limit` withdrawals redeemed through the account's vouchers, most
+    /// recent first
+    async fn withdrawals_for(
+        &self,
+        linking_key: &str,
+        limit: usize,
+    ) -> StorageResult<Vec<Withdrawal>>;
+
+    // Channels
+    async fn insert_channel(&self, channel: &Channel) -> StorageResult<()>;
+    /// Most recent first
+    async fn list_channels(&self, limit: usize) -> StorageResult<Vec<Channel>>;
+    /// Records the fees the channel was set up with
+    async fn set_channel_fees(&self, channel_id: &str, fees: &ChannelFees) -> StorageResult<()>;
+
+    // LSPS1 orders
+    async fn insert_order(&self, order: &Order) -> StorageResult<()>;
+    async fn get_order(&self, id: &str) -> StorageResult<Option<Order>>;
+    /// Orders in `state`, oldest first
+    async fn list_orders(&self, state: OrderState) -> StorageResult<Vec<Order>>;
+    /// Moves a Created order to Funding, noting it paid at `paid_at` unless it
+    /// already was. Returns false (and changes nothing) if it wasn't Created:
+    /// only one caller gets to fund it.
+    async fn claim_order(&self, id: &str, paid_at: u64) -> StorageResult<bool>;
+    /// Saves the order's state, payment and channel; for the one that
+    /// claimed it, or to expire it
+    async fn update_order(&self, order: &Order) -> StorageResult<()>;
+
+    // Account deletion
+    /// Removes everything personal about the account in one step: the account,
+    /// its sessions (login history) and vouchers (whose k1s stop working).
+    /// Its withdrawals are kept for accounting, minus the linking key and
+    /// invoice. Returns the audit record, or None for an unknown account.
+    async fn delete_account(
+        &self,
+        linking_key: &str,
+        requested_by: DeletionRequester,
+    ) -> StorageResult<Option<Deletion>>;
+    /// Most recent first
+    async fn list_deletions(&self, limit: usize) -> StorageResult<Vec<Deletion>>;
+
+    async fn stats(&self) -> StorageResult<StorageStats>;
+
+    // Liquidity history (see liquidity.rs)
+    async fn insert_liquidity_report(&self, report: &LiquidityReport) -> StorageResult<()>;
+    /// Most recent first
+    async fn list_liquidity_reports(&self, limit: usize) -> StorageResult<Vec<LiquidityReport>>;
+
+    // Backup/restore. Callers quiesce writes around these (see admin.rs).
+    async fn export(&self) -> StorageResult<Snapshot>;
+    /// Replaces all contents with the snapshot
+    async fn import(&self, snapshot: Snapshot) -> StorageResult<()>;
+}
@@ -0,0 +1,1487 @@
+use async_trait::async_trait;
+use sqlx::migrate::Migration;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::collections::BTreeMap;
+
+use super::{
+    Account, Allowance, Channel, Deletion, DeletionRequester, K1Purpose, K1Status, K1Ttls,
+    LiquidityReport, Order, OrderChannel, OrderState, Snapshot, Storage, StorageError,
+    StorageResult, StorageStats, ValidityWindow, Voucher, Withdrawal, WithdrawalStatus,
+};
+use crate::backend::ChannelFees;
+
+type WithdrawalRow = (
+    String,
+    Option<String>,
+    String,
+    i64,
+    String,
+    Option<String>,
+    i64,
+    Option<i64>,
+    Option<String>,
+);
+
+fn withdrawal_from_row(row: WithdrawalRow) -> StorageResult<Withdrawal> {
+    let (
+        k1,
+        linking_key,
+        bolt11,
+        amount_msat,
+        status,
+        preimage_enc,
+        created_at,
+        fee_msat,
+        description,
+    ) = row;
+    Ok(Withdrawal {
+        k1,
+        linking_key,
+        bolt11,
+        amount_msat: amount_msat as u64,
+        status: WithdrawalStatus::parse(&status)
+            .ok_or_else(|| StorageError(format!("Unknown withdrawal status: {}", status)))?,
+        preimage_enc,
+        created_at: created_at as u64,
+        fee_msat: fee_msat.map(|fee| fee as u64),
+        description,
+    })
+}
+
+type DeletionRow = (String, i64, String, i64, i64, i64, i64);
+
+fn deletion_from_row(row: DeletionRow) -> StorageResult<Deletion> {
+    let (id, deleted_at, requested_by, sessions, vouchers, withdrawals, forfeited) = row;
+    Ok(Deletion {
+        id,
+        deleted_at: deleted_at as u64,
+        requested_by: DeletionRequester::parse(&requested_by)
+            .ok_or_else(|| StorageError(format!("Unknown deletion requester: {}", requested_by)))?,
+        sessions_removed: sessions as u64,
+        vouchers_removed: vouchers as u64,
+        withdrawals_anonymized: withdrawals as u64,
+        forfeited_budget_msat: forfeited as u64,
+    })
+}
+
+type AccountRow = (String, i64, i64, Option<i64>, String);
+
+const ACCOUNT_COLUMNS: &str =
+    "linking_key, created_at, withdraw_budget_msat, last_login_at, metadata";
+
+fn account_from_row(row: AccountRow) -> StorageResult<Account> {
+    let (linking_key, created_at, budget, last_login_at, metadata) = row;
+    let metadata = serde_json::from_str(&metadata)
+        .map_err(|e| format!("Bad metadata for {}: {}", linking_key, e))?;
+    Ok(Account {
+        linking_key,
+        created_at: created_at as u64,
+        withdraw_budget_msat: budget as u64,
+        last_login_at: last_login_at.map(|at| at as u64),
+        metadata,
+    })
+}
+
+fn metadata_json(metadata: &BTreeMap<String, String>) -> String {
+    serde_json::to_string(metadata).expect("string maps serialize")
+}
+
+type VoucherRow = (String, String, Option<i64>, Option<i64>);
+
+const VOUCHER_COLUMNS: &str = "k1, linking_key, valid_from, valid_until";
+
+fn voucher_from_row(row: VoucherRow) -> Voucher {
+    let (k1, linking_key, valid_from, valid_until) = row;
+    Voucher {
+        k1,
+        linking_key,
+        window: ValidityWindow {
+            valid_from: valid_from.map(|at| at as u64),
+            valid_until: valid_until.map(|at| at as u64),
+        },
+    }
+}
+
+type AllowanceRow = (String, String, i64, i64, i64, Option<String>, i64);
+
+const ALLOWANCE_COLUMNS: &str =
+    "linking_key, link, amount_msat, period_secs, next_refill_at, balance_notify, created_at";
+
+fn allowance_from_row(row: AllowanceRow) -> Allowance {
+    let (linking_key, link, amount_msat, period_secs, next_refill_at, balance_notify, created_at) =
+        row;
+    Allowance {
+        linking_key,
+        link,
+        amount_msat: amount_msat as u64,
+        period_secs: period_secs as u64,
+        next_refill_at: next_refill_at as u64,
+        balance_notify,
+        created_at: created_at as u64,
+    }
+}
+
+async fn insert_allowance(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    a: &Allowance,
+) -> StorageResult<()> {
+    sqlx::query(&format!(
+        "INSERT INTO allowances ({}) VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (linking_key) DO UPDATE SET
+             link = EXCLUDED.link, amount_msat = EXCLUDED.amount_msat,
+             period_secs = EXCLUDED.period_secs, next_refill_at = EXCLUDED.next_refill_at,
+             balance_notify = EXCLUDED.balance_notify, created_at = EXCLUDED.created_at",
+        ALLOWANCE_COLUMNS
+    ))
+    .bind(&a.linking_key)
+    .bind(&a.link)
+    .bind(a.amount_msat as i64)
+    .bind(a.period_secs as i64)
+    .bind(a.next_refill_at as i64)
+    .bind(&a.balance_notify)
+    .bind(a.created_at as i64)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+type ChannelRow = (
+    String,
+    String,
+    i64,
+    bool,
+    String,
+    i64,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+
+const CHANNEL_COLUMNS: &str = "channel_id, node_id, capacity_sat, private, txid, opened_at,
+                               fee_base_msat, fee_ppm, htlc_min_msat, htlc_max_msat, fees_set_at";
+
+fn channel_from_row(row: ChannelRow) -> Channel {
+    let (channel_id, node_id, capacity_sat, private, txid, opened_at, base, ppm, min, max, set_at) =
+        row;
+    let fees = match (base, ppm, min, max) {
+        (Some(base), Some(ppm), Some(htlc_min), Some(htlc_max)) => Some(ChannelFees {
+            base_msat: base as u64,
+            ppm: ppm as u32,
+            htlc_min_msat: htlc_min as u64,
+            htlc_max_msat: htlc_max as u64,
+        }),
+        _ => None,
+    };
+    Channel {
+        channel_id,
+        node_id,
+        capacity_sat: capacity_sat as u64,
+        private,
+        txid,
+        opened_at: opened_at as u64,
+        fees,
+        fees_set_at: set_at.map(|at| at as u64),
+    }
+}
+
+async fn insert_channel(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    c: &Channel,
+) -> StorageResult<()> {
+    sqlx::query(&format!(
+        "INSERT INTO channels ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        CHANNEL_COLUMNS
+    ))
+    .bind(&c.channel_id)
+    .bind(&c.node_id)
+    .bind(c.capacity_sat as i64)
+    .bind(c.private)
+    .bind(&c.txid)
+    .bind(c.opened_at as i64)
+    .bind(c.fees.as_ref().map(|fees| fees.base_msat as i64))
+    .bind(c.fees.as_ref().map(|fees| fees.ppm as i64))
+    .bind(c.fees.as_ref().map(|fees| fees.htlc_min_msat as i64))
+    .bind(c.fees.as_ref().map(|fees| fees.htlc_max_msat as i64))
+    .bind(c.fees_set_at.map(|at| at as i64))
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+type OrderRow = (
+    String,
+    String,
+    i64,
+    bool,
+    i64,
+    i64,
+    i64,
+    String,
+    i64,
+    String,
+    String,
+    i64,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+);
+
+const ORDER_COLUMNS: &str = "order_id, node_id, capacity_sat, announce, required_confirmations,
+                             funding_confirms_within_blocks, channel_expiry_blocks, token,
+                             price_msat, bolt11, state, created_at, paid_at, channel_id,
+                             funding_outpoint, funded_at";
+
+fn order_from_row(row: OrderRow) -> StorageResult<Order> {
+    let (
+        id,
+        node_id,
+        capacity_sat,
+        announce,
+        required_confirmations,
+        funding_confirms_within_blocks,
+        channel_expiry_blocks,
+        token,
+        price_msat,
+        bolt11,
+        state,
+        created_at,
+        paid_at,
+        channel_id,
+        funding_outpoint,
+        funded_at,
+    ) = row;
+    let channel = match (channel_id, funding_outpoint, funded_at) {
+        (Some(channel_id), Some(funding_outpoint), Some(funded_at)) => Some(OrderChannel {
+            channel_id,
+            funding_outpoint,
+            funded_at: funded_at as u64,
+        }),
+        _ => None,
+    };
+    Ok(Order {
+        id,
+        node_id,
+        capacity_sat: capacity_sat as u64,
+        announce,
+        required_confirmations: required_confirmations as u32,
+        funding_confirms_within_blocks: funding_confirms_within_blocks as u32,
+        channel_expiry_blocks: channel_expiry_blocks as u32,
+        token,
+        price_msat: price_msat as u64,
+        bolt11,
+        state: OrderState::parse(&state)
+            .ok_or_else(|| StorageError(format!("Unknown order state: {}", state)))?,
+        created_at: created_at as u64,
+        paid_at: paid_at.map(|at| at as u64),
+        channel,
+    })
+}
+
+async fn insert_order(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    o: &Order,
+) -> StorageResult<()> {
+    sqlx::query(&format!(
+        "INSERT INTO lsps1_orders ({})
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+        ORDER_COLUMNS
+    ))
+    .bind(&o.id)
+    .bind(&o.node_id)
+    .bind(o.capacity_sat as i64)
+    .bind(o.announce)
+    .bind(o.required_confirmations as i64)
+    .bind(o.funding_confirms_within_blocks as i64)
+    .bind(o.channel_expiry_blocks as i64)
+    .bind(&o.token)
+    .bind(o.price_msat as i64)
+    .bind(&o.bolt11)
+    .bind(o.state.as_str())
+    .bind(o.created_at as i64)
+    .bind(o.paid_at.map(|at| at as i64))
+    .bind(o.channel.as_ref().map(|c| c.channel_id.clone()))
+    .bind(o.channel.as_ref().map(|c| c.funding_outpoint.clone()))
+    .bind(o.channel.as_ref().map(|c| c.funded_at as i64))
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+type LiquidityRow = (i64, i64, i64, String, i64, i64, i64, i64);
+
+fn liquidity_from_row(row: LiquidityRow) -> StorageResult<LiquidityReport> {
+    let (taken_at, onchain, unconfirmed, channels, outbound, inbound, pending, outstanding) = row;
+    Ok(LiquidityReport {
+        taken_at: taken_at as u64,
+        onchain_sat: onchain as u64,
+        onchain_unconfirmed_sat: unconfirmed as u64,
+        channels: serde_json::from_str(&channels)
+            .map_err(|e| StorageError(format!("Malformed liquidity channels: {}", e)))?,
+        outbound_msat: outbound as u64,
+        inbound_msat: inbound as u64,
+        pending_withdrawals_msat: pending as u64,
+        outstanding_budget_msat: outstanding as u64,
+    })
+}
+
+async fn insert_deletion(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    d: &Deletion,
+) -> StorageResult<()> {
+    sqlx::query(
+        "INSERT INTO deletions (id, deleted_at, requested_by, sessions_removed, vouchers_removed,
+                                withdrawals_anonymized, forfeited_budget_msat)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&d.id)
+    .bind(d.deleted_at as i64)
+    .bind(d.requested_by.as_str())
+    .bind(d.sessions_removed as i64)
+    .bind(d.vouchers_removed as i64)
+    .bind(d.withdrawals_anonymized as i64)
+    .bind(d.forfeited_budget_msat as i64)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Shared storage for running several replicas against one database.
+/// Migrations in `server/migrations` are applied on connect.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> StorageResult<PostgresStorage> {
+        let pool = PgPoolOptions::new().max_connections(10).connect(url).await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        Ok(PostgresStorage { pool })
+    }
+
+    /// Compares the migrations applied to the database at `url` with the ones
+    /// this build ships, without applying any (see `lnurl-server check`)
+    pub async fn migration_status(url: &str) -> StorageResult<MigrationStatus> {
+        let pool = PgPoolOptions::new().max_connections(1).connect(url).await?;
+        let tracked: bool =
+            sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                .fetch_one(&pool)
+                .await?;
+        let applied: Vec<(i64, Vec<u8>, bool)> = match tracked {
+            true => {
+                sqlx::query_as("SELECT version, checksum, success FROM _sqlx_migrations")
+                    .fetch_all(&pool)
+                    .await?
+            }
+            false => Vec::new(),
+        };
+        pool.close().await;
+        compare_migrations(&sqlx::migrate!("./migrations").migrations, &applied)
+    }
+}
+
+/// Where a database stands against this build's migrations
+#[derive(Debug, PartialEq)]
+pub struct MigrationStatus {
+    pub applied: usize,
+    pub pending: Vec<String>, // "0012 description", applied by the next connect
+}
+
+/// Err for the histories `connect` would refuse: a migration that failed
+/// halfway, one edited after it was applied, or one this build does not know
+/// (a newer build ran against the database)
+fn compare_migrations(
+    known: &[Migration],
+    applied: &[(i64, Vec<u8>, bool)],
+) -> StorageResult<MigrationStatus> {
+    let known: Vec<&Migration> = known
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .collect();
+    for (version, checksum, success) in applied {
+        let Some(migration) = known.iter().find(|m| m.version == *version) else {
+            return Err(StorageError(format!(
+                "Migration {:04} is applied but unknown to this build",
+                version
+            )));
+        };
+        if !success {
+            return Err(StorageError(format!(
+                "Migration {:04} failed partway, fix the database by hand",
+                version
+            )));
+        }
+        if *migration.checksum != checksum[..] {
+            return Err(StorageError(format!(
+                "Migration {:04} was edited after it was applied",
+                version
+            )));
+        }
+    }
+    let pending = known
+        .iter()
+        .filter(|m| !applied.iter().any(|(version, ..)| *version == m.version))
+        .map(|m| format!("{:04} {}", m.version, m.description))
+        .collect();
+    Ok(MigrationStatus {
+        applied: applied.len(),
+        pending,
+    })
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn insert_k1(&self, k1: &str, purpose: K1Purpose) -> StorageResult<bool> {
+        // The CTE sees the table as it was before the upsert
+        let new: bool = sqlx::query_scalar(
+            "WITH held AS (SELECT 1 FROM k1s WHERE k1 = $1)
+             INSERT INTO k1s (k1, created_at, purpose) VALUES ($1, $2, $3)
+             ON CONFLICT (k1) DO UPDATE
+             SET created_at = EXCLUDED.created_at, purpose = EXCLUDED.purpose
+             RETURNING NOT EXISTS (SELECT 1 FROM held)",
+        )
+        .bind(k1)
+        .bind(crate::unix_now() as i64)
+        .bind(purpose.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(new)
+    }
+
+    async fn restore_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        issued_at: u64,
+        capacity_sat: Option<u64>,
+    ) -> StorageResult<bool> {
+        let new: bool = sqlx::query_scalar(
+            "WITH held AS (SELECT 1 FROM k1s WHERE k1 = $1)
+             INSERT INTO k1s (k1, created_at, purpose, capacity_sat) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (k1) DO UPDATE
+             SET created_at = EXCLUDED.created_at, purpose = EXCLUDED.purpose,
+                 capacity_sat = EXCLUDED.capacity_sat
+             RETURNING NOT EXISTS (SELECT 1 FROM held)",
+        )
+        .bind(k1)
+        .bind(issued_at as i64)
+        .bind(purpose.as_str())
+        .bind(capacity_sat.map(|sat| sat as i64))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(new)
+    }
+
+    async fn k1_issued_at(&self, k1: &str) -> StorageResult<Option<u64>> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT created_at FROM k1s WHERE k1 = $1")
+            .bind(k1)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|(created_at,)| created_at as u64))
+    }
+
+    async fn consume_k1(
+        &self,
+        k1: &str,
+        purpose: K1Purpose,
+        ttls: &K1Ttls,
+    ) -> StorageResult<K1Status> {
+        let row: Option<(i64, Option<String>, Option<i64>)> = sqlx::query_as(
+            "DELETE FROM k1s WHERE k1 = $1 AND (purpose = $2 OR purpose IS NULL)
+             RETURNING created_at, purpose, capacity_sat",
+        )
+        .bind(k1)
+        .bind(purpose.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(match row {
+            Some((_, _, Some(_))) => K1Status::Valid,
+            Some((created_at, purpose, None)) => {
+                let purpose = purpose.as_deref().and_then(K1Purpose::parse);
+                match ttls.is_live(purpose, created_at as u64, crate::unix_now()) {
+                    true => K1Status::Valid,
+                    false => K1Status::Expired,
+                }
+            }
+            None => K1Status::Unknown,
+        })
+    }
+
+    async fn set_k1_capacity(&self, k1: &str, capacity_sat: u64) -> StorageResult<()> {
+        sqlx::query("UPDATE k1s SET capacity_sat = $2 WHERE k1 = $1")
+            .bind(k1)
+            .bind(capacity_sat as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn k1_capacity(&self, k1: &str) -> StorageResult<Option<u64>> {
+        let row: Option<(Option<i64>,)> =
+            sqlx::query_as("SELECT capacity_sat FROM k1s WHERE k1 = $1")
+                .bind(k1)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.and_then(|(capacity_sat,)| capacity_sat).map(|sat| sat as u64))
+    }
+
+    async fn sold_k1s(&self) -> StorageResult<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT k1 FROM k1s WHERE capacity_sat IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows.into_iter().map(|(k1,)| k1).collect())
+    }
+
+    async fn list_k1s(&self, limit: usize) -> StorageResult<Vec<(String, Option<K1Purpose>)>> {
+        let rows: Vec<(String, Option<String>)> =
+            sqlx::query_as("SELECT k1, purpose FROM k1s ORDER BY created_at DESC LIMIT $1")
+                .bind(limit as i64)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(k1, purpose)| (k1, purpose.as_deref().and_then(K1Purpose::parse)))
+            .collect())
+    }
+
+    async fn sweep_k1s(&self, ttls: &K1Ttls, now: u64) -> StorageResult<Vec<Option<K1Purpose>>> {
+        // Issued at or before its purpose's cutoff: past its TTL, as is_live has it
+        let cutoff = |purpose| now.saturating_sub(ttls.of(purpose)) as i64;
+        let rows: Vec<(Option<String>,)> = sqlx::query_as(
+            "DELETE FROM k1s WHERE created_at <= CASE purpose
+                 WHEN 'channel' THEN $1 WHEN 'withdraw' THEN $2
+                 WHEN 'auth' THEN $3 WHEN 'pay' THEN $4 ELSE $5 END
+             AND capacity_sat IS NULL
+             RETURNING purpose",
+        )
+        .bind(cutoff(Some(K1Purpose::Channel)))
+        .bind(cutoff(Some(K1Purpose::Withdraw)))
+        .bind(cutoff(Some(K1Purpose::Auth)))
+        .bind(cutoff(Some(K1Purpose::Pay)))
+        .bind(cutoff(None))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(purpose,)| purpose.as_deref().and_then(K1Purpose::parse))
+            .collect())
+    }
+
+    async fn ensure_account(&self, linking_key: &str, withdraw_budget_msat: u64) -> StorageResult<bool> {
+        let result = sqlx::query(
+            "INSERT INTO accounts (linking_key, created_at, withdraw_budget_msat)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (linking_key) DO NOTHING",
+        )
+        .bind(linking_key)
+        .bind(crate::unix_now() as i64)
+        .bind(withdraw_budget_msat as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn get_account(&self, linking_key: &str) -> StorageResult<Option<Account>> {
+        let row: Option<AccountRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM accounts WHERE linking_key = $1",
+            ACCOUNT_COLUMNS
+        ))
+        .bind(linking_key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(account_from_row).transpose()
+    }
+
+    async fn list_accounts(&self, limit: usize) -> StorageResult<Vec<Account>> {
+        let rows: Vec<AccountRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM accounts
+             ORDER BY last_login_at DESC NULLS LAST, created_at DESC LIMIT $1",
+            ACCOUNT_COLUMNS
+        ))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(account_from_row).collect()
+    }
+
+    async fn record_login(&self, linking_key: &str, at: u64) -> StorageResult<()> {
+        sqlx::query("UPDATE accounts SET last_login_at = $2 WHERE linking_key = $1")
+            .bind(linking_key)
+            .bind(at as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_account_metadata(
+        &self,
+        linking_key: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> StorageResult<bool> {
+        let result = sqlx::query("UPDATE accounts SET metadata = $2 WHERE linking_key = $1")
+            .bind(linking_key)
+            .bind(metadata_json(metadata))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn debit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<bool> {
+        // Check and subtract in one statement so concurrent replicas can't overdraw
+        let result = sqlx::query(
+            "UPDATE accounts SET withdraw_budget_msat = withdraw_budget_msat - $2
+             WHERE linking_key = $1 AND withdraw_budget_msat >= $2",
+        )
+        .bind(linking_key)
+        .bind(amount_msat as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn credit_budget(&self, linking_key: &str, amount_msat: u64) -> StorageResult<()> {
+        sqlx::query(
+            "UPDATE accounts SET withdraw_budget_msat = withdraw_budget_msat + $2
+             WHERE linking_key = $1",
+        )
+        .bind(linking_key)
+        .bind(amount_msat as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn put_allowance(&self, allowance: &Allowance) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_allowance(&mut tx, allowance).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_allowance(&self, linking_key: &str) -> StorageResult<Option<Allowance>> {
+        let row: Option<AllowanceRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM allowances WHERE linking_key = $1",
+            ALLOWANCE_COLUMNS
+        ))
+        .bind(linking_key)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(allowance_from_row))
+    }
+
+    async fn allowance_by_link(&self, link: &str) -> StorageResult<Option<Allowance>> {
+        let row: Option<AllowanceRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM allowances WHERE link = $1",
+            ALLOWANCE_COLUMNS
+        ))
+        .bind(link)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(allowance_from_row))
+    }
+
+    async fn list_allowances(&self) -> StorageResult<Vec<Allowance>> {
+        let rows: Vec<AllowanceRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM allowances ORDER BY next_refill_at",
+            ALLOWANCE_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(allowance_from_row).collect())
+    }
+
+    async fn remove_allowance(&self, linking_key: &str) -> StorageResult<bool> {
+        let result = sqlx::query("DELETE FROM allowances WHERE linking_key = $1")
+            .bind(linking_key)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn set_balance_notify(&self, linking_key: &str, url: &str) -> StorageResult<()> {
+        sqlx::query("UPDATE allowances SET balance_notify = $2 WHERE linking_key = $1")
+            .bind(linking_key)
+            .bind(url)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn refill_allowance(&self, linking_key: &str, next_refill_at: u64) -> StorageResult<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locks the account row so a concurrent debit waits for the refill
+        let row: Option<(i64, i64)> = sqlx::query_as(
+            "SELECT a.withdraw_budget_msat, l.amount_msat
+             FROM accounts a JOIN allowances l USING (linking_key)
+             WHERE a.linking_key = $1 FOR UPDATE",
+        )
+        .bind(linking_key)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((budget, amount)) = row else {
+            return Ok(0);
+        };
+
+        let added = (amount - budget).max(0);
+        sqlx::query(
+            "UPDATE accounts SET withdraw_budget_msat = withdraw_budget_msat + $2
+             WHERE linking_key = $1",
+        )
+        .bind(linking_key)
+        .bind(added)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE allowances SET next_refill_at = $2 WHERE linking_key = $1")
+            .bind(linking_key)
+            .bind(next_refill_at as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(added as u64)
+    }
+
+    async fn balance_link(&self, linking_key: &str, new: &str) -> StorageResult<String> {
+        sqlx::query(
+            "INSERT INTO balance_links (linking_key, link) VALUES ($1, $2)
+             ON CONFLICT (linking_key) DO NOTHING",
+        )
+        .bind(linking_key)
+        .bind(new)
+        .execute(&self.pool)
+        .await?;
+        let (link,): (String,) =
+            sqlx::query_as("SELECT link FROM balance_links WHERE linking_key = $1")
+                .bind(linking_key)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(link)
+    }
+
+    async fn balance_link_owner(&self, link: &str) -> StorageResult<Option<String>> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT linking_key FROM balance_links WHERE link = $1")
+                .bind(link)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(linking_key,)| linking_key))
+    }
+
+    async fn insert_session(&self, token: &str, linking_key: &str) -> StorageResult<()> {
+        sqlx::query("INSERT INTO sessions (token, linking_key, created_at) VALUES ($1, $2, $3)")
+            .bind(token)
+            .bind(linking_key)
+            .bind(crate::unix_now() as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn session_linking_key(
+        &self,
+        token: &str,
+        ttl_secs: u64,
+        now: u64,
+    ) -> StorageResult<Option<String>> {
+        // Opened after the cutoff: still within its TTL
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT linking_key FROM sessions WHERE token = $1 AND created_at > $2")
+                .bind(token)
+                .bind(now.saturating_sub(ttl_secs) as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(linking_key,)| linking_key))
+    }
+
+    async fn remove_session(&self, token: &str) -> StorageResult<bool> {
+        let result = sqlx::query("DELETE FROM sessions WHERE token = $1")
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn sweep_sessions(&self, ttl_secs: u64, now: u64) -> StorageResult<usize> {
+        let result = sqlx::query("DELETE FROM sessions WHERE created_at <= $1")
+            .bind(now.saturating_sub(ttl_secs) as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() as usize)
+    }
+
+    async fn list_sessions(&self, limit: usize) -> StorageResult<Vec<(String, String)>> {
+        Ok(sqlx::query_as(
+            "SELECT token, linking_key FROM sessions ORDER BY created_at DESC LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn insert_voucher(&self, voucher: &Voucher) -> StorageResult<()> {
+        sqlx::query(&format!(
+            "INSERT INTO vouchers ({}, created_at) VALUES ($1, $2, $3, $4, $5)",
+            VOUCHER_COLUMNS
+        ))
+        .bind(&voucher.k1)
+        .bind(&voucher.linking_key)
+        .bind(voucher.window.valid_from.map(|at| at as i64))
+        .bind(voucher.window.valid_until.map(|at| at as i64))
+        .bind(crate::unix_now() as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_voucher(&self, k1: &str) -> StorageResult<Option<String>> {
+        let row: Option<(String,)> =
+            sqlx::query_as("DELETE FROM vouchers WHERE k1 = $1 RETURNING linking_key")
+                .bind(k1)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(linking_key,)| linking_key))
+    }
+
+    async fn get_voucher(&self, k1: &str) -> StorageResult<Option<Voucher>> {
+        let row: Option<VoucherRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM vouchers WHERE k1 = $1",
+            VOUCHER_COLUMNS
+        ))
+        .bind(k1)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(voucher_from_row))
+    }
+
+    async fn vouchers_for(&self, linking_key: &str) -> StorageResult<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT k1 FROM vouchers WHERE linking_key = $1 ORDER BY created_at")
+                .bind(linking_key)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows.into_iter().map(|(k1,)| k1).collect())
+    }
+
+    async fn list_vouchers(&self) -> StorageResult<Vec<Voucher>> {
+        let rows: Vec<VoucherRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM vouchers ORDER BY created_at",
+            VOUCHER_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(voucher_from_row).collect())
+    }
+
+    async fn insert_withdrawal(&self, withdrawal: &Withdrawal) -> StorageResult<()> {
+        sqlx::query(
+            "INSERT INTO withdrawals (k1, linking_key, bolt11, amount_msat, status, preimage_enc, created_at, fee_msat,
+                                      description)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(&withdrawal.k1)
+        .bind(&withdrawal.linking_key)
+        .bind(&withdrawal.bolt11)
+        .bind(withdrawal.amount_msat as i64)
+        .bind(withdrawal.status.as_str())
+        .bind(&withdrawal.preimage_enc)
+        .bind(withdrawal.created_at as i64)
+        .bind(withdrawal.fee_msat.map(|fee| fee as i64))
+        .bind(&withdrawal.description)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn finish_withdrawal(
+        &self,
+        k1: &str,
+        status: WithdrawalStatus,
+        preimage_enc: Option<&str>,
+        fee_msat: Option<u64>,
+    ) -> StorageResult<()> {
+        sqlx::query(
+            "UPDATE withdrawals SET status = $2, preimage_enc = $3, fee_msat = $4 WHERE k1 = $1",
+        )
+        .bind(k1)
+        .bind(status.as_str())
+        .bind(preimage_enc)
+        .bind(fee_msat.map(|fee| fee as i64))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_withdrawal(&self, k1: &str) -> StorageResult<Option<Withdrawal>> {
+        let row: Option<WithdrawalRow> = sqlx::query_as(
+            "SELECT k1, linking_key, bolt11, amount_msat, status, preimage_enc, created_at, fee_msat,
+                    description
+             FROM withdrawals WHERE k1 = $1",
+        )
+        .bind(k1)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(withdrawal_from_row).transpose()
+    }
+
+    async fn list_withdrawals(&self, limit: usize) -> StorageResult<Vec<Withdrawal>> {
+        let rows: Vec<WithdrawalRow> = sqlx::query_as(
+            "SELECT k1, linking_key, bolt11, amount_msat, status, preimage_enc, created_at, fee_msat,
+                    description
+             FROM withdrawals ORDER BY created_at DESC LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(withdrawal_from_row).collect()
+    }
+
+    async fn withdrawals_for(
+        &self,
+        linking_key: &str,
+        limit: usize,
+    ) -> StorageResult<Vec<Withdrawal>> {
+        let rows: Vec<WithdrawalRow> = sqlx::query_as(
+            "SELECT k1, linking_key, bolt11, amount_msat, status, preimage_enc, created_at, fee_msat,
+                    description
+             FROM withdrawals WHERE linking_key = $1 ORDER BY created_at DESC LIMIT $2",
+        )
+        .bind(linking_key)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(withdrawal_from_row).collect()
+    }
+
+    async fn insert_channel(&self, channel: &Channel) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_channel(&mut tx, channel).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list_channels(&self, limit: usize) -> StorageResult<Vec<Channel>> {
+        let rows: Vec<ChannelRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM channels ORDER BY opened_at DESC LIMIT $1",
+            CHANNEL_COLUMNS
+        ))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(channel_from_row).collect())
+    }
+
+    async fn set_channel_fees(&self, channel_id: &str, fees: &ChannelFees) -> StorageResult<()> {
+        sqlx::query(
+            "UPDATE channels
+             SET fee_base_msat = $2, fee_ppm = $3, htlc_min_msat = $4, htlc_max_msat = $5,
+                 fees_set_at = $6
+             WHERE channel_id = $1",
+        )
+        .bind(channel_id)
+        .bind(fees.base_msat as i64)
+        .bind(fees.ppm as i64)
+        .bind(fees.htlc_min_msat as i64)
+        .bind(fees.htlc_max_msat as i64)
+        .bind(crate::unix_now() as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_order(&self, order: &Order) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_order(&mut tx, order).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_order(&self, id: &str) -> StorageResult<Option<Order>> {
+        let row: Option<OrderRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM lsps1_orders WHERE order_id = $1",
+            ORDER_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(order_from_row).transpose()
+    }
+
+    async fn list_orders(&self, state: OrderState) -> StorageResult<Vec<Order>> {
+        let rows: Vec<OrderRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM lsps1_orders WHERE state = $1 ORDER BY created_at",
+            ORDER_COLUMNS
+        ))
+        .bind(state.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(order_from_row).collect()
+    }
+
+    async fn claim_order(&self, id: &str, paid_at: u64) -> StorageResult<bool> {
+        let result = sqlx::query(
+            "UPDATE lsps1_orders SET state = $2, paid_at = COALESCE(paid_at, $3)
+             WHERE order_id = $1 AND state = $4",
+        )
+        .bind(id)
+        .bind(OrderState::Funding.as_str())
+        .bind(paid_at as i64)
+        .bind(OrderState::Created.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn update_order(&self, order: &Order) -> StorageResult<()> {
+        let channel = order.channel.as_ref();
+        sqlx::query(
+            "UPDATE lsps1_orders
+             SET state = $2, paid_at = $3, channel_id = $4, funding_outpoint = $5, funded_at = $6
+             WHERE order_id = $1",
+        )
+        .bind(&order.id)
+        .bind(order.state.as_str())
+        .bind(order.paid_at.map(|at| at as i64))
+        .bind(channel.map(|c| c.channel_id.clone()))
+        .bind(channel.map(|c| c.funding_outpoint.clone()))
+        .bind(channel.map(|c| c.funded_at as i64))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_account(
+        &self,
+        linking_key: &str,
+        requested_by: DeletionRequester,
+    ) -> StorageResult<Option<Deletion>> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locks the account row so a concurrent debit cannot slip in
+        let budget: Option<(i64,)> = sqlx::query_as(
+            "SELECT withdraw_budget_msat FROM accounts WHERE linking_key = $1 FOR UPDATE",
+        )
+        .bind(linking_key)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((budget,)) = budget else {
+            return Ok(None);
+        };
+
+        sqlx::query("DELETE FROM allowances WHERE linking_key = $1")
+            .bind(linking_key)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM balance_links WHERE linking_key = $1")
+            .bind(linking_key)
+            .execute(&mut *tx)
+            .await?;
+        let sessions = sqlx::query("DELETE FROM sessions WHERE linking_key = $1")
+            .bind(linking_key)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM k1s WHERE k1 IN (SELECT k1 FROM vouchers WHERE linking_key = $1)")
+            .bind(linking_key)
+            .execute(&mut *tx)
+            .await?;
+        let vouchers = sqlx::query("DELETE FROM vouchers WHERE linking_key = $1")
+            .bind(linking_key)
+            .execute(&mut *tx)
+            .await?;
+        let withdrawals = sqlx::query(
+            "UPDATE withdrawals SET linking_key = NULL, bolt11 = '', description = NULL
+             WHERE linking_key = $1",
+        )
+        .bind(linking_key)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM accounts WHERE linking_key = $1")
+            .bind(linking_key)
+            .execute(&mut *tx)
+            .await?;
+
+        let deletion = Deletion {
+            id: uuid::Uuid::new_v4().to_string(),
+            deleted_at: crate::unix_now(),
+            requested_by,
+            sessions_removed: sessions.rows_affected(),
+            vouchers_removed: vouchers.rows_affected(),
+            withdrawals_anonymized: withdrawals.rows_affected(),
+            forfeited_budget_msat: budget as u64,
+        };
+        insert_deletion(&mut tx, &deletion).await?;
+
+        tx.commit().await?;
+        Ok(Some(deletion))
+    }
+
+    async fn list_deletions(&self, limit: usize) -> StorageResult<Vec<Deletion>> {
+        let rows: Vec<DeletionRow> = sqlx::query_as(
+            "SELECT id, deleted_at, requested_by, sessions_removed, vouchers_removed,
+                    withdrawals_anonymized, forfeited_budget_msat
+             FROM deletions ORDER BY deleted_at DESC LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(deletion_from_row).collect()
+    }
+
+    async fn stats(&self) -> StorageResult<StorageStats> {
+        let (accounts, outstanding, sessions, pending_k1s, vouchers, pending_withdrawals): (
+            i64,
+            i64,
+            i64,
+            i64,
+            i64,
+            i64,
+        ) = sqlx::query_as(
+            "SELECT
+                (SELECT COUNT(*) FROM accounts),
+                (SELECT COALESCE(SUM(withdraw_budget_msat), 0)::BIGINT FROM accounts),
+                (SELECT COUNT(*) FROM sessions),
+                (SELECT COUNT(*) FROM k1s),
+                (SELECT COUNT(*) FROM vouchers),
+                (SELECT COALESCE(SUM(amount_msat), 0)::BIGINT FROM withdrawals
+                 WHERE status = 'pending')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let by_purpose: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT purpose, COUNT(*) FROM k1s WHERE purpose IS NOT NULL GROUP BY purpose",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(StorageStats {
+            accounts: accounts as usize,
+            sessions: sessions as usize,
+            pending_k1s: pending_k1s as usize,
+            pending_k1s_by_purpose: by_purpose
+                .into_iter()
+                .filter_map(|(purpose, count)| Some((K1Purpose::parse(&purpose)?, count as usize)))
+                .collect(),
+            vouchers: vouchers as usize,
+            outstanding_budget_msat: outstanding as u64,
+            pending_withdrawals_msat: pending_withdrawals as u64,
+        })
+    }
+
+    async fn insert_liquidity_report(&self, report: &LiquidityReport) -> StorageResult<()> {
+        let channels =
+            serde_json::to_string(&report.channels).map_err(|e| StorageError(e.to_string()))?;
+        sqlx::query(
+            "INSERT INTO liquidity_reports (taken_at, onchain_sat, onchain_unconfirmed_sat,
+                                            channels, outbound_msat, inbound_msat,
+                                            pending_withdrawals_msat, outstanding_budget_msat)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(report.taken_at as i64)
+        .bind(report.onchain_sat as i64)
+        .bind(report.onchain_unconfirmed_sat as i64)
+        .bind(channels)
+        .bind(report.outbound_msat as i64)
+        .bind(report.inbound_msat as i64)
+        .bind(report.pending_withdrawals_msat as i64)
+        .bind(report.outstanding_budget_msat as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn list_liquidity_reports(&self, limit: usize) -> StorageResult<Vec<LiquidityReport>> {
+        let rows: Vec<LiquidityRow> = sqlx::query_as(
+            "SELECT taken_at, onchain_sat, onchain_unconfirmed_sat, channels, outbound_msat,
+                    inbound_msat, pending_withdrawals_msat, outstanding_budget_msat
+             FROM liquidity_reports ORDER BY id DESC LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(liquidity_from_row).collect()
+    }
+
+    async fn export(&self) -> StorageResult<Snapshot> {
+        // One repeatable-read transaction so all tables come from the same point in time
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let k1s: Vec<(String, Option<String>, Option<i64>, i64)> =
+            sqlx::query_as("SELECT k1, purpose, capacity_sat, created_at FROM k1s")
+                .fetch_all(&mut *tx)
+                .await?;
+        let accounts: Vec<AccountRow> =
+            sqlx::query_as(&format!("SELECT {} FROM accounts", ACCOUNT_COLUMNS))
+                .fetch_all(&mut *tx)
+                .await?;
+        let sessions: Vec<(String, String, i64)> =
+            sqlx::query_as("SELECT token, linking_key, created_at FROM sessions")
+                .fetch_all(&mut *tx)
+                .await?;
+        let vouchers: Vec<VoucherRow> =
+            sqlx::query_as(&format!("SELECT {} FROM vouchers", VOUCHER_COLUMNS))
+                .fetch_all(&mut *tx)
+                .await?;
+        let vouchers: Vec<Voucher> = vouchers.into_iter().map(voucher_from_row).collect();
+        let withdrawals: Vec<WithdrawalRow> = sqlx::query_as(
+            "SELECT k1, linking_key, bolt11, amount_msat, status, preimage_enc, created_at, fee_msat,
+                    description
+             FROM withdrawals ORDER BY created_at",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let deletions: Vec<DeletionRow> = sqlx::query_as(
+            "SELECT id, deleted_at, requested_by, sessions_removed, vouchers_removed,
+                    withdrawals_anonymized, forfeited_budget_msat
+             FROM deletions ORDER BY deleted_at",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let channels: Vec<ChannelRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM channels ORDER BY opened_at",
+            CHANNEL_COLUMNS
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+        let allowances: Vec<AllowanceRow> =
+            sqlx::query_as(&format!("SELECT {} FROM allowances", ALLOWANCE_COLUMNS))
+                .fetch_all(&mut *tx)
+                .await?;
+        let balance_links: Vec<(String, String)> =
+            sqlx::query_as("SELECT link, linking_key FROM balance_links")
+                .fetch_all(&mut *tx)
+                .await?;
+        let orders: Vec<OrderRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM lsps1_orders ORDER BY created_at",
+            ORDER_COLUMNS
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Snapshot {
+            k1_purposes: k1s
+                .iter()
+                .filter_map(|(k1, purpose, _, _)| {
+                    Some((k1.clone(), K1Purpose::parse(purpose.as_deref()?)?))
+                })
+                .collect(),
+            k1_capacities: k1s
+                .iter()
+                .filter_map(|(k1, _, capacity_sat, _)| Some((k1.clone(), (*capacity_sat)? as u64)))
+                .collect(),
+            k1_issued_at: k1s
+                .iter()
+                .map(|(k1, _, _, issued_at)| (k1.clone(), *issued_at as u64))
+                .collect(),
+            k1s: k1s.into_iter().map(|(k1, _, _, _)| k1).collect(),
+            accounts: accounts
+                .into_iter()
+                .map(account_from_row)
+                .collect::<StorageResult<_>>()?,
+            session_opened_at: sessions
+                .iter()
+                .map(|(token, _, opened_at)| (token.clone(), *opened_at as u64))
+                .collect(),
+            sessions: sessions
+                .into_iter()
+                .map(|(token, linking_key, _)| (token, linking_key))
+                .collect(),
+            voucher_windows: vouchers
+                .iter()
+                .filter(|voucher| voucher.window != ValidityWindow::default())
+                .map(|voucher| (voucher.k1.clone(), voucher.window))
+                .collect(),
+            vouchers: vouchers
+                .into_iter()
+                .map(|voucher| (voucher.k1, voucher.linking_key))
+                .collect(),
+            withdrawals: withdrawals
+                .into_iter()
+                .map(withdrawal_from_row)
+                .collect::<StorageResult<_>>()?,
+            deletions: deletions
+                .into_iter()
+                .map(deletion_from_row)
+                .collect::<StorageResult<_>>()?,
+            channels: channels.into_iter().map(channel_from_row).collect(),
+            allowances: allowances.into_iter().map(allowance_from_row).collect(),
+            balance_links,
+            orders: orders
+                .into_iter()
+                .map(order_from_row)
+                .collect::<StorageResult<_>>()?,
+        })
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> StorageResult<()> {
+        let now = crate::unix_now() as i64;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "TRUNCATE k1s, sessions, vouchers, allowances, balance_links, withdrawals, accounts,
+                      deletions, channels, lsps1_orders",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for k1 in &snapshot.k1s {
+            let purpose = snapshot.k1_purposes.get(k1).map(K1Purpose::as_str);
+            let capacity_sat = snapshot.k1_capacities.get(k1).map(|&sat| sat as i64);
+            let issued_at = snapshot.k1_issued_at.get(k1).map(|&at| at as i64);
+            sqlx::query(
+                "INSERT INTO k1s (k1, created_at, purpose, capacity_sat) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(k1)
+            .bind(issued_at.unwrap_or(now))
+            .bind(purpose)
+            .bind(capacity_sat)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for account in &snapshot.accounts {
+            sqlx::query(&format!(
+                "INSERT INTO accounts ({}) VALUES ($1, $2, $3, $4, $5)",
+                ACCOUNT_COLUMNS
+            ))
+            .bind(&account.linking_key)
+            .bind(account.created_at as i64)
+            .bind(account.withdraw_budget_msat as i64)
+            .bind(account.last_login_at.map(|at| at as i64))
+            .bind(metadata_json(&account.metadata))
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (token, linking_key) in &snapshot.sessions {
+            let opened_at = snapshot.session_opened_at.get(token).map(|&at| at as i64);
+            sqlx::query("INSERT INTO sessions (token, linking_key, created_at) VALUES ($1, $2, $3)")
+                .bind(token)
+                .bind(linking_key)
+                .bind(opened_at.unwrap_or(now))
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (k1, linking_key) in &snapshot.vouchers {
+            let window = snapshot.voucher_windows.get(k1).copied().unwrap_or_default();
+            sqlx::query(&format!(
+                "INSERT INTO vouchers ({}, created_at) VALUES ($1, $2, $3, $4, $5)",
+                VOUCHER_COLUMNS
+            ))
+            .bind(k1)
+            .bind(linking_key)
+            .bind(window.valid_from.map(|at| at as i64))
+            .bind(window.valid_until.map(|at| at as i64))
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for w in &snapshot.withdrawals {
+            sqlx::query(
+                "INSERT INTO withdrawals (k1, linking_key, bolt11, amount_msat, status, preimage_enc, created_at, fee_msat,
+                                          description)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .bind(&w.k1)
+            .bind(&w.linking_key)
+            .bind(&w.bolt11)
+            .bind(w.amount_msat as i64)
+            .bind(w.status.as_str())
+            .bind(&w.preimage_enc)
+            .bind(w.created_at as i64)
+            .bind(w.fee_msat.map(|fee| fee as i64))
+            .bind(&w.description)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for deletion in &snapshot.deletions {
+            insert_deletion(&mut tx, deletion).await?;
+        }
+        for channel in &snapshot.channels {
+            insert_channel(&mut tx, channel).await?;
+        }
+        // After the accounts they belong to
+        for allowance in &snapshot.allowances {
+            insert_allowance(&mut tx, allowance).await?;
+        }
+        for (link, linking_key) in &snapshot.balance_links {
+            sqlx::query("INSERT INTO balance_links (linking_key, link) VALUES ($1, $2)")
+                .bind(linking_key)
+                .bind(link)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for order in &snapshot.orders {
+            insert_order(&mut tx, order).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_history_is_checked_against_the_build() {
+        let known = sqlx::migrate!("./migrations").migrations;
+        let mut applied: Vec<(i64, Vec<u8>, bool)> = known
+            .iter()
+            .map(|m| (m.version, m.checksum.to_vec(), true))
+            .collect();
+        let status = compare_migrations(&known, &applied).unwrap();
+        assert_eq!(status.applied, known.len());
+        assert!(status.pending.is_empty());
+
+        // Whichever migration is the latest, not one this test has to name
+        let latest = known.iter().max_by_key(|m| m.version).unwrap();
+        let last = applied.pop().unwrap();
+        assert_eq!(last.0, latest.version);
+        let status = compare_migrations(&known, &applied).unwrap();
+        assert_eq!(
+            status.pending,
+            vec![format!("{:04} {}", latest.version, latest.description)]
+        );
+
+        let mut edited = last.clone();
+        edited.1[0] ^= 1;
+        applied.push(edited);
+        let error = compare_migrations(&known, &applied).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Migration {:04} was edited after it was applied", latest.version)
+        );
+
+        applied.pop();
+        applied.push((last.0, last.1.clone(), false));
+        let error = compare_migrations(&known, &applied).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Migration {:04} failed partway, fix the database by hand", latest.version)
+        );
+
+        applied.pop();
+        applied.push(last);
+        let unknown = latest.version + 1;
+        applied.push((unknown, Vec::new(), true));
+        let error = compare_migrations(&known, &applied).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Migration {:04} is applied but unknown to this build", unknown)
+        );
+    }
+}
@@ -0,0 +1,2781 @@
+// =============================================================================
+// Handler tests
+// =============================================================================
+//
+// Each test drives the real router (`app`) with oneshot requests, over
+// memory storage and `MockNode` standing in for CLN.
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, Method, Request, StatusCode};
+use lnurl_models::encoding::decode_lnurl;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tower::ServiceExt;
+
+use crate::admin::Role;
+use crate::backend::{
+    Backend, BackendResult, ChannelBalance, ChannelFees, DecodedInvoice, FeeUpdate, Funds,
+    FundedChannel, InvoiceStatus, IssuedInvoice, NewInvoice, NodeStatus, Payment,
+};
+use crate::capture::{self, Capture, CaptureConfig};
+use crate::crypto::LocalKeyCipher;
+use crate::expiry;
+use crate::fees;
+use crate::liquidity;
+use crate::lsps1;
+use crate::notify::{Notification, NotificationKind, Notifications, Notifier, NotifyError};
+use crate::pay::{PayConfig, SuccessActionConfig};
+use crate::policy::{
+    AuthHandler, ChannelOpen, ChannelPolicy, Verdict, WithdrawPolicy,
+};
+use crate::storage::{K1Purpose, K1Ttls, MemoryStorage, Withdrawal};
+use crate::pricing::{self, ChannelPricing};
+use crate::screen::CidrScreener;
+use crate::throttle::RateLimit;
+use crate::webhook::AuthWebhook;
+use crate::{admin_app, app, public_app, AppState, IP_ADDRESS, NODE_URI};
+
+// Real curve points (G and 2G), so that the real cln-rpc parses them too
+const NODE_ID: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+const WALLET_ID: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const GOOD_SIGNATURE: &str = "d9good";
+/// What MockNode's payments cost on top of the invoice
+const ROUTING_FEE_MSAT: u64 = 12;
+/// MockNode's opening feerate, 1 sat/vB
+const FEERATE_PERKB: u64 = 1_000;
+const BLOCKHEIGHT: u32 = 80_000;
+
+// -----------------------------------------------------------------------------
+// Mock node
+// -----------------------------------------------------------------------------
+
+/// Label, bolt11 and preimage
+type HashedInvoice = (String, String, [u8; 32]);
+
+/// `lntb<msat>` invoices decode to that amount, a bare `lntb` has none and
+/// anything else is malformed; all carry `description`. Only GOOD_SIGNATURE verifies. Payments cost
+/// ROUTING_FEE_MSAT. Channels it funds are in normal state right away.
+/// Invoices it issues are `lntb<msat>`, unpaid until a test says otherwise.
+/// Hashed ones pay to the sha256 of their preimage, the sha256 of their
+/// label when none is given.
+/// `down` fails every call, `failing_payments` just the payments and
+/// `failing_funding` the channel opens. It is at
+/// BLOCKHEIGHT, still syncing with `sync_warning`.
+#[derive(Default)]
+struct MockNode {
+    down: bool,
+    failing_payments: bool,
+    failing_funding: bool,
+    sync_warning: Option<String>,
+    description: Option<String>,
+    funded: StdMutex<Vec<(String, u64, bool)>>, // node id, capacity, announce
+    paid: StdMutex<Vec<String>>,
+    fees_set: StdMutex<Vec<(String, FeeUpdate)>>, // channel id, update
+    invoices: StdMutex<HashMap<String, InvoiceStatus>>, // by label
+    hashed: StdMutex<Vec<(String, String)>>, // label, description only hashed into the invoice
+    preimages: StdMutex<Vec<[u8; 32]>>,      // given with hashed invoices
+    by_hash: StdMutex<HashMap<String, HashedInvoice>>, // by payment hash
+}
+
+impl MockNode {
+    fn check(&self) -> BackendResult<()> {
+        match self.down {
+            true => Err("Connection refused".to_string().into()),
+            false => Ok(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for MockNode {
+    async fn node_id(&self) -> BackendResult<String> {
+        self.check()?;
+        Ok(NODE_ID.to_string())
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice> {
+        self.check()?;
+        let amount_msat = match bolt11.strip_prefix("lntb") {
+            Some("") => None,
+            Some(amount) => Some(
+                amount
+                    .parse()
+                    .map_err(|_| "Invalid bech32 string".to_string())?,
+            ),
+            None => return Err("Invalid bech32 string".to_string().into()),
+        };
+        Ok(DecodedInvoice {
+            amount_msat,
+            description: self.description.clone(),
+        })
+    }
+
+    async fn fund_channel(
+        &self,
+        node_id: cln_rpc::primitives::PublicKey,
+        capacity_sat: u64,
+        announce: bool,
+    ) -> BackendResult<FundedChannel> {
+        self.check()?;
+        if self.failing_funding {
+            return Err("Insufficient funds".to_string().into());
+        }
+        self.funded
+            .lock()
+            .unwrap()
+            .push((node_id.to_string(), capacity_sat, announce));
+        Ok(FundedChannel {
+            channel_id: "cc".repeat(32),
+            txid: "aa".repeat(32),
+            tx: "0200".to_string(),
+            outnum: 1,
+            mindepth: Some(3),
+        })
+    }
+
+    async fn pay(&self, bolt11: &str) -> BackendResult<Payment> {
+        self.check()?;
+        if self.failing_payments {
+            return Err("Ran out of routes to try".to_string().into());
+        }
+        let amount_msat = self.decode_invoice(bolt11).await?.amount_msat.unwrap_or(0);
+        self.paid.lock().unwrap().push(bolt11.to_string());
+        Ok(Payment {
+            preimage: vec![0x11; 32],
+            amount_sent_msat: amount_msat + ROUTING_FEE_MSAT,
+        })
+    }
+
+    async fn check_message(
+        &self,
+        _message: &str,
+        zbase: &str,
+        _pubkey: cln_rpc::primitives::PublicKey,
+    ) -> BackendResult<bool> {
+        self.check()?;
+        Ok(zbase == GOOD_SIGNATURE)
+    }
+
+    /// One usable channel and one still waiting for its funding to confirm
+    async fn funds(&self) -> BackendResult<Funds> {
+        self.check()?;
+        Ok(Funds {
+            onchain_sat: 250_000,
+            onchain_unconfirmed_sat: 10_000,
+            channels: vec![
+                ChannelBalance {
+                    peer_id: WALLET_ID.to_string(),
+                    channel_id: Some("cc".repeat(32)), // the one fund_channel opens
+                    short_channel_id: Some("80000x1x0".to_string()),
+                    state: "CHANNELD_NORMAL".to_string(),
+                    outbound_msat: 4_000_000,
+                    inbound_msat: 1_000_000,
+                },
+                ChannelBalance {
+                    peer_id: WALLET_ID.to_string(),
+                    channel_id: Some("dd".repeat(32)),
+                    short_channel_id: None,
+                    state: "CHANNELD_AWAITING_LOCKIN".to_string(),
+                    outbound_msat: 2_000_000,
+                    inbound_msat: 0,
+                },
+            ],
+        })
+    }
+
+    /// Settings not in the update are CLN's defaults
+    async fn set_channel_fees(
+        &self,
+        channel_id: &str,
+        update: &FeeUpdate,
+    ) -> BackendResult<ChannelFees> {
+        self.check()?;
+        self.fees_set
+            .lock()
+            .unwrap()
+            .push((channel_id.to_string(), update.clone()));
+        Ok(ChannelFees {
+            base_msat: update.base_msat.unwrap_or(1_000),
+            ppm: update.ppm.unwrap_or(10),
+            htlc_min_msat: update.htlc_min_msat.unwrap_or(0),
+            htlc_max_msat: update.htlc_max_msat.unwrap_or(99_000_000),
+        })
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        _description: &str,
+        _expiry_secs: u64,
+    ) -> BackendResult<String> {
+        self.check()?;
+        self.invoices
+            .lock()
+            .unwrap()
+            .insert(label.to_string(), InvoiceStatus::Unpaid);
+        Ok(format!("lntb{}", amount_msat))
+    }
+
+    async fn create_invoice_hashed(
+        &self,
+        amount_msat: u64,
+        label: &str,
+        description: &str,
+        expiry_secs: u64,
+        preimage: Option<&[u8; 32]>,
+    ) -> BackendResult<NewInvoice> {
+        let bolt11 = self.create_invoice(amount_msat, label, description, expiry_secs).await?;
+        self.hashed.lock().unwrap().push((label.to_string(), description.to_string()));
+        self.preimages.lock().unwrap().extend(preimage);
+        let preimage = preimage.copied().unwrap_or_else(|| Sha256::digest(label).into());
+        let payment_hash = hex::encode(Sha256::digest(preimage));
+        self.by_hash
+            .lock()
+            .unwrap()
+            .insert(payment_hash.clone(), (label.to_string(), bolt11.clone(), preimage));
+        Ok(NewInvoice { bolt11, payment_hash })
+    }
+
+    async fn invoice_status(&self, label: &str) -> BackendResult<Option<InvoiceStatus>> {
+        self.check()?;
+        Ok(self.invoices.lock().unwrap().get(label).copied())
+    }
+
+    async fn invoice_by_hash(&self, payment_hash: &str) -> BackendResult<Option<IssuedInvoice>> {
+        self.check()?;
+        let found = self.by_hash.lock().unwrap().get(payment_hash).cloned();
+        let Some((label, bolt11, preimage)) = found else {
+            return Ok(None);
+        };
+        let status = self.invoices.lock().unwrap()[&label];
+        Ok(Some(IssuedInvoice {
+            label,
+            bolt11: Some(bolt11),
+            status,
+            preimage: (status == InvoiceStatus::Paid).then(|| preimage.to_vec()),
+        }))
+    }
+
+    async fn opening_feerate_perkb(&self) -> BackendResult<u64> {
+        self.check()?;
+        Ok(FEERATE_PERKB)
+    }
+
+    async fn node_status(&self) -> BackendResult<NodeStatus> {
+        self.check()?;
+        Ok(NodeStatus {
+            blockheight: BLOCKHEIGHT,
+            sync_warning: self.sync_warning.clone(),
+        })
+    }
+}
+
+/// Says no to everything
+struct DenyAll;
+
+#[async_trait::async_trait]
+impl WithdrawPolicy for DenyAll {
+    async fn approve(&self, _withdrawal: &Withdrawal) -> Verdict {
+        Err("Withdrawals are paused".to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl ChannelPolicy for DenyAll {
+    async fn approve(&self, _open: &ChannelOpen) -> Verdict {
+        Err("No channels today".to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthHandler for DenyAll {
+    async fn approve(&self, _linking_key: &str) -> Verdict {
+        Err("Not on the guest list".to_string())
+    }
+}
+
+/// Keeps the notifications operators would get
+#[derive(Default)]
+struct Alerts(StdMutex<Vec<Notification>>);
+
+#[async_trait::async_trait]
+impl Notifier for Alerts {
+    fn name(&self) -> &str {
+        "alerts"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        self.0.lock().unwrap().push(notification.clone());
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Helpers
+// -----------------------------------------------------------------------------
+
+fn state(node: &Arc<MockNode>) -> AppState {
+    NODE_URI.get_or_init(|| format!("{}@{}", NODE_ID, IP_ADDRESS));
+    AppState::new(node.clone(), Arc::new(MemoryStorage::default())).with_admin_keys(
+        HashMap::from([
+            ("admin-key".to_string(), Role::Admin),
+            ("dashboard-key".to_string(), Role::ReadOnly),
+        ]),
+    )
+}
+
+fn setup() -> (AppState, Arc<MockNode>) {
+    let node = Arc::new(MockNode::default());
+    (state(&node), node)
+}
+
+async fn send(state: &AppState, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app(state.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn get(state: &AppState, uri: &str) -> (StatusCode, Value) {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    send(state, request).await
+}
+
+/// GET with the session token
+async fn get_as(state: &AppState, uri: &str, token: &str) -> (StatusCode, Value) {
+    let request = Request::get(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    send(state, request).await
+}
+
+/// A request to the admin API with `key`, and a JSON body if given
+fn admin_request(method: Method, uri: &str, key: &str, body: Option<Value>) -> Request<Body> {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", key)
+        .header(header::CONTENT_TYPE, "application/json");
+    match body {
+        Some(body) => request.body(Body::from(body.to_string())).unwrap(),
+        None => request.body(Body::empty()).unwrap(),
+    }
+}
+
+fn reason(body: &Value) -> &str {
+    assert_eq!(body["status"], "ERROR", "{}", body);
+    body["reason"].as_str().unwrap()
+}
+
+async fn channel_k1(state: &AppState) -> String {
+    let (_, body) = get(state, "/request-channel").await;
+    body["k1"].as_str().unwrap().to_string()
+}
+
+async fn open_channel(state: &AppState, query: &str) -> (StatusCode, Value) {
+    let k1 = channel_k1(state).await;
+    get(
+        state,
+        &format!("/open-channel?k1={}&remoteid={}{}", k1, WALLET_ID, query),
+    )
+    .await
+}
+
+async fn withdraw_k1(state: &AppState, token: Option<&str>) -> String {
+    let (_, body) = match token {
+        Some(token) => get_as(state, "/request-withdraw", token).await,
+        None => get(state, "/request-withdraw").await,
+    };
+    body["k1"].as_str().unwrap().to_string()
+}
+
+async fn withdraw(state: &AppState, k1: &str, pr: &str) -> (StatusCode, Value) {
+    get(state, &format!("/withdraw?k1={}&pr={}", k1, pr)).await
+}
+
+/// Waits for the background payment of an accepted withdraw
+async fn settled_status(state: &AppState, k1: &str) -> String {
+    for _ in 0..200 {
+        let (_, body) = get(state, &format!("/withdraw-status?k1={}", k1)).await;
+        let status = body["withdrawal_status"].as_str().unwrap();
+        if status != "pending" {
+            return status.to_string();
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("withdrawal {} never settled", k1);
+}
+
+async fn auth_k1(state: &AppState) -> String {
+    let (_, body) = get(state, "/auth-challenge").await;
+    body["k1"].as_str().unwrap().to_string()
+}
+
+async fn auth_response(
+    state: &AppState,
+    k1: &str,
+    signature: &str,
+    pubkey: &str,
+) -> (StatusCode, Value) {
+    let uri = format!(
+        "/auth-response?k1={}&signature={}&pubkey={}",
+        k1, signature, pubkey
+    );
+    get(state, &uri).await
+}
+
+/// Logs WALLET_ID in, returning the session token
+async fn login(state: &AppState) -> String {
+    let k1 = auth_k1(state).await;
+    let (status, body) = auth_response(state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn budget(state: &AppState, token: &str) -> u64 {
+    let (_, body) = get_as(state, "/me", token).await;
+    body["withdraw_budget_msat"].as_u64().unwrap()
+}
+
+// -----------------------------------------------------------------------------
+// LUD-02
+// -----------------------------------------------------------------------------
+
+#[tokio::test]
+async fn request_channel_points_at_the_node() {
+    let (state, _) = setup();
+    let (status, body) = get(&state, "/request-channel").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["tag"], "channelRequest");
+    assert_eq!(body["uri"], format!("{}@{}", NODE_ID, IP_ADDRESS));
+    assert!(body["callback"]
+        .as_str()
+        .unwrap()
+        .ends_with("/open-channel"));
+}
+
+#[tokio::test]
+async fn open_channel_funds_the_default_capacity() {
+    let (state, node) = setup();
+    let (status, body) = open_channel(&state, "").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["status"], "OK");
+    assert_eq!(body["capacity_sat"], 100_000);
+    assert_eq!(body["private"], false);
+    assert_eq!(body["txid"], "aa".repeat(32));
+    assert_eq!(
+        *node.funded.lock().unwrap(),
+        [(WALLET_ID.to_string(), 100_000, true)]
+    );
+}
+
+#[tokio::test]
+async fn opened_channels_get_the_operators_fees() {
+    let (state, node) = setup();
+    let (status, body) = open_channel(&state, "").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let channels = |state: AppState| async move {
+        let request = admin_request(Method::GET, "/admin/channels", "dashboard-key", None);
+        let (status, body) = send(&state, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["channels"].clone()
+    };
+    let history = channels(state.clone()).await;
+    assert_eq!(history[0]["channel_id"], "cc".repeat(32));
+    assert_eq!(history[0]["capacity_sat"], 100_000);
+    assert_eq!(history[0]["fees"], Value::Null);
+
+    let update = FeeUpdate {
+        ppm: Some(500),
+        htlc_max_msat: Some(50_000_000),
+        ..Default::default()
+    };
+    assert_eq!(fees::apply(&state, &update).await, 1);
+    assert_eq!(
+        *node.fees_set.lock().unwrap(),
+        [("cc".repeat(32), update.clone())]
+    );
+    let history = channels(state.clone()).await;
+    assert_eq!(history[0]["fees"]["ppm"], 500);
+    assert_eq!(history[0]["fees"]["base_msat"], 1_000); // the node's own
+    assert_eq!(history[0]["fees"]["htlc_max_msat"], 50_000_000);
+    assert!(history[0]["fees_set_at"].is_u64());
+
+    // Set up once
+    assert_eq!(fees::apply(&state, &update).await, 0);
+    assert_eq!(node.fees_set.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn open_channel_private_and_smaller() {
+    let (state, node) = setup();
+    let (status, body) = open_channel(&state, "&private=1&amount=20000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["capacity_sat"], 20_000);
+    assert_eq!(body["private"], true);
+    assert_eq!(
+        *node.funded.lock().unwrap(),
+        [(WALLET_ID.to_string(), 20_000, false)]
+    );
+}
+
+#[tokio::test]
+async fn open_channel_rejects_unknown_k1() {
+    let (state, node) = setup();
+    let uri = format!("/open-channel?k1=nope&remoteid={}", WALLET_ID);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or already used k1");
+    assert!(node.funded.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn open_channel_k1_is_single_use() {
+    let (state, _) = setup();
+    let k1 = channel_k1(&state).await;
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+    assert_eq!(get(&state, &uri).await.0, StatusCode::OK);
+
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or already used k1");
+}
+
+#[tokio::test]
+async fn open_channel_rejects_malformed_node_id() {
+    let (state, _) = setup();
+    let k1 = channel_k1(&state).await;
+    let (status, body) = get(&state, &format!("/open-channel?k1={}&remoteid=02abcd", k1)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(reason(&body).starts_with("Invalid node id"), "{}", body);
+}
+
+#[tokio::test]
+async fn open_channel_cancel_spends_the_k1() {
+    let (state, node) = setup();
+    let k1 = channel_k1(&state).await;
+    let uri = format!("/open-channel?k1={}&remoteid={}&cancel=1", k1, WALLET_ID);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "OK");
+    assert!(node.funded.lock().unwrap().is_empty());
+
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+    assert_eq!(get(&state, &uri).await.0, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn open_channel_rejects_malformed_flags() {
+    let (state, _) = setup();
+    let (status, body) = open_channel(&state, "&cancel=maybe").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "cancel must be 1 or 0");
+
+    let (status, body) = open_channel(&state, "&private=yes").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "private must be 1 or 0");
+}
+
+#[tokio::test]
+async fn open_channel_amount_bounds() {
+    let (state, node) = setup();
+    for amount in [0, 100_001] {
+        let (status, body) = open_channel(&state, &format!("&amount={}", amount)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(reason(&body), "amount must be between 1 and 100000 sats");
+    }
+    assert!(node.funded.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn open_channel_reports_node_errors() {
+    let node = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let state = state(&node);
+    let (status, body) = open_channel(&state, "").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(reason(&body), "Failed to open channel: Connection refused");
+}
+
+#[tokio::test]
+async fn open_channel_policy_can_deny() {
+    let node = Arc::new(MockNode::default());
+    let state = AppState {
+        channel_policy: Arc::new(DenyAll),
+        ..state(&node)
+    };
+    let (status, body) = open_channel(&state, "").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(reason(&body), "No channels today");
+    assert!(node.funded.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn screened_networks_get_no_k1s_or_channels() {
+    let (state, node) = setup();
+    let screener = CidrScreener::new(vec![], vec!["203.0.113.0/24".parse().unwrap()]);
+    let state = state.with_request_screener(Arc::new(screener));
+    let from = |uri: &str, ip: &str| {
+        let mut request = Request::get(uri).body(Body::empty()).unwrap();
+        let peer = SocketAddr::new(ip.parse().unwrap(), 50_000);
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    };
+
+    for uri in ["/request-channel", "/request-withdraw", "/auth-challenge"] {
+        let (status, body) = send(&state, from(uri, "203.0.113.9")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+        assert_eq!(reason(&body), "Requests from your network are not accepted");
+        assert_eq!(send(&state, from(uri, "198.51.100.9")).await.0, StatusCode::OK);
+    }
+
+    // A k1 obtained elsewhere doesn't get the channel funded either
+    let k1 = channel_k1(&state).await;
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+    let (status, body) = send(&state, from(&uri, "203.0.113.9")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(reason(&body), "Requests from your network are not accepted");
+    assert!(node.funded.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn sold_channels_are_funded_once_paid_for() {
+    let (state, node) = setup();
+    let pricing = ChannelPricing {
+        base_sat: 1_000,
+        ppm: 10_000,
+    };
+    let state = state.with_channel_pricing(pricing);
+    let set_invoice = |k1: &str, status| {
+        let mut invoices = node.invoices.lock().unwrap();
+        invoices.insert(pricing::invoice_label(k1), status);
+    };
+
+    // 1000 sat + 1% of 100k sat + 154 vB at 1 sat/vB
+    let (status, body) = get(&state, "/request-channel").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["pr"], "lntb2154000");
+    assert_eq!(body["capacity_sat"], 100_000);
+    let k1 = body["k1"].as_str().unwrap().to_string();
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(reason(&body), "The channel's invoice is not paid yet");
+    assert!(node.funded.lock().unwrap().is_empty());
+
+    // Still good once paid, and only once
+    set_invoice(&k1, InvoiceStatus::Paid);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["capacity_sat"], 100_000);
+    assert_eq!(get(&state, &uri).await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(node.funded.lock().unwrap().len(), 1);
+
+    let k1 = channel_k1(&state).await;
+    set_invoice(&k1, InvoiceStatus::Expired);
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        reason(&body),
+        "The channel's invoice expired, request a new channel"
+    );
+    // Declining needs no payment
+    let (status, _) = get(&state, &format!("{}&cancel=1", uri)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let uri = format!("/open-channel?k1=unknown&remoteid={}", WALLET_ID);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or already used k1");
+    assert_eq!(node.funded.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn paid_channels_outlive_their_k1_ttl() {
+    let (state, node) = setup();
+    let pricing = ChannelPricing {
+        base_sat: 1_000,
+        ppm: 10_000,
+    };
+    let state = state
+        .with_channel_pricing(pricing)
+        .with_k1_ttls(K1Ttls {
+            channel_secs: 0,
+            ..K1Ttls::default()
+        });
+    let set_invoice = |k1: &str, status| {
+        let mut invoices = node.invoices.lock().unwrap();
+        invoices.insert(pricing::invoice_label(k1), status);
+    };
+    let paid = channel_k1(&state).await;
+    let unpaid = channel_k1(&state).await;
+    let expired = channel_k1(&state).await;
+    set_invoice(&expired, InvoiceStatus::Expired);
+
+    // Past the TTL, only the one whose invoice can no longer be paid goes
+    assert_eq!(expiry::sweep(&state).await.unwrap(), 1);
+    assert_eq!(state.storage.stats().await.unwrap().pending_k1s, 2);
+    assert_eq!(state.storage.k1_capacity(&expired).await.unwrap(), None);
+
+    // A payment made late in the invoice's life still gets its channel
+    set_invoice(&paid, InvoiceStatus::Paid);
+    assert_eq!(expiry::sweep(&state).await.unwrap(), 0);
+    let uri = format!("/open-channel?k1={}&remoteid={}", paid, WALLET_ID);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["capacity_sat"], 100_000);
+    assert_eq!(node.funded.lock().unwrap().len(), 1);
+
+    let uri = format!("/open-channel?k1={}&remoteid={}", unpaid, WALLET_ID);
+    assert_eq!(get(&state, &uri).await.0, StatusCode::PAYMENT_REQUIRED);
+}
+
+#[tokio::test]
+async fn paid_channel_opens_spend_their_k1_last() {
+    let failing = Arc::new(MockNode {
+        failing_funding: true,
+        ..Default::default()
+    });
+    let pricing = ChannelPricing {
+        base_sat: 1_000,
+        ppm: 10_000,
+    };
+    let state = self::state(&failing).with_channel_pricing(pricing);
+    let k1 = channel_k1(&state).await;
+    let label = pricing::invoice_label(&k1);
+    failing.invoices.lock().unwrap().insert(label.clone(), InvoiceStatus::Paid);
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+
+    // Refusals leave the k1 good
+    let (status, body) = get(&state, &format!("{}&amount=50000", uri)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "amount must be 100000 sats, the capacity paid for");
+    let malformed = format!("/open-channel?k1={}&remoteid=nope", k1);
+    assert_eq!(get(&state, &malformed).await.0, StatusCode::BAD_REQUEST);
+    let denied = AppState {
+        channel_policy: Arc::new(DenyAll),
+        ..state.clone()
+    };
+    assert_eq!(get(&denied, &uri).await.0, StatusCode::FORBIDDEN);
+
+    // So does a funding failure, for a retry, issued when it was
+    let issued_at = state.storage.k1_issued_at(&k1).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(reason(&body), "Failed to open channel: Insufficient funds");
+    assert_eq!(state.storage.k1_issued_at(&k1).await.unwrap(), issued_at);
+
+    // At the capacity sold, whatever the limits have become
+    let node = Arc::new(MockNode::default());
+    node.invoices.lock().unwrap().insert(label, InvoiceStatus::Paid);
+    let state = AppState {
+        backend: node.clone(),
+        ..state
+    };
+    state.limits.lock().await.channel_capacity_sat = 50_000;
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["capacity_sat"], 100_000);
+    assert_eq!(
+        *node.funded.lock().unwrap(),
+        vec![(WALLET_ID.to_string(), 100_000, true)]
+    );
+    assert_eq!(get(&state, &uri).await.0, StatusCode::BAD_REQUEST);
+}
+
+// -----------------------------------------------------------------------------
+// LSPS1
+// -----------------------------------------------------------------------------
+
+fn create_order(order: &Value) -> Request<Body> {
+    Request::post("/lsps1/create_order")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(order.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn lsps1_orders_are_funded_once_paid() {
+    let (state, node) = setup();
+    let (status, info) = get(&state, "/lsps1/get_info").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(info["uris"][0], format!("{}@{}", NODE_ID, IP_ADDRESS));
+    assert_eq!(info["max_channel_balance_sat"], "100000");
+
+    let order = serde_json::json!({
+        "public_key": WALLET_ID,
+        "lsp_balance_sat": "50000",
+        "client_balance_sat": "0",
+        "required_channel_confirmations": 3,
+        "funding_confirms_within_blocks": 6,
+        "channel_expiry_blocks": 4320,
+        "announce_channel": false,
+    });
+    let mut too_big = order.clone();
+    too_big["lsp_balance_sat"] = "200000".into();
+    let (status, body) = send(&state, create_order(&too_big)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], 100);
+    assert_eq!(body["data"]["property"], "lsp_balance_sat");
+    let denied = AppState {
+        channel_policy: Arc::new(DenyAll),
+        ..state.clone()
+    };
+    let (status, body) = send(&denied, create_order(&order)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["data"]["message"], "No channels today");
+
+    // Without channel pricing, just the funding fee: 154 vB at 1 sat/vB
+    let (status, body) = send(&state, create_order(&order)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["order_state"], "CREATED");
+    assert_eq!(body["payment"]["bolt11"]["state"], "EXPECT_PAYMENT");
+    assert_eq!(body["payment"]["bolt11"]["invoice"], "lntb154000");
+    assert_eq!(body["payment"]["bolt11"]["order_total_sat"], "154");
+    let id = body["order_id"].as_str().unwrap().to_string();
+    let uri = format!("/lsps1/get_order?order_id={}", id);
+    assert_eq!(get(&state, &uri).await.1["order_state"], "CREATED");
+    assert!(node.funded.lock().unwrap().is_empty());
+
+    let label = lsps1::invoice_label(&id);
+    node.invoices.lock().unwrap().insert(label, InvoiceStatus::Paid);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["order_state"], "COMPLETED");
+    assert_eq!(body["payment"]["bolt11"]["state"], "PAID");
+    assert_eq!(
+        body["channel"]["funding_outpoint"],
+        format!("{}:1", "aa".repeat(32))
+    );
+    // Funded once, private as ordered
+    get(&state, &uri).await;
+    assert_eq!(
+        *node.funded.lock().unwrap(),
+        [(WALLET_ID.to_string(), 50_000, false)]
+    );
+
+    let (status, body) = get(&state, "/lsps1/get_order?order_id=unknown").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], 101);
+}
+
+// -----------------------------------------------------------------------------
+// LUD-03
+// -----------------------------------------------------------------------------
+
+#[tokio::test]
+async fn request_withdraw_advertises_the_limits() {
+    let (state, _) = setup();
+    let (status, body) = get(&state, "/request-withdraw").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["tag"], "withdrawRequest");
+    assert_eq!(body["minWithdrawable"], 1_000);
+    assert_eq!(body["maxWithdrawable"], 1_000_000);
+    assert!(body["callback"].as_str().unwrap().ends_with("/withdraw"));
+}
+
+#[tokio::test]
+async fn callbacks_follow_the_mount_point() {
+    let node = Arc::new(MockNode::default());
+    let state = state(&node).with_callback_url("https://shop.example/lnurl/");
+    let (_, body) = get(&state, "/request-withdraw").await;
+    assert_eq!(body["callback"], "https://shop.example/lnurl/withdraw");
+    let (_, body) = get(&state, "/request-channel").await;
+    assert_eq!(body["callback"], "https://shop.example/lnurl/open-channel");
+}
+
+#[tokio::test]
+async fn callbacks_follow_the_request_host() {
+    let node = Arc::new(MockNode::default());
+    let state = state(&node)
+        .with_callback_url("https://shop.example/lnurl/")
+        .with_callback_from_request(true);
+    let callback = |headers: Vec<(&'static str, &'static str)>| {
+        let state = state.clone();
+        async move {
+            let mut request = Request::get("/request-withdraw");
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let (_, body) = send(&state, request.body(Body::empty()).unwrap()).await;
+            body["callback"].as_str().unwrap().to_string()
+        }
+    };
+
+    // Straight to the server, and behind a proxy
+    let direct = vec![("host", "pay.example")];
+    assert_eq!(callback(direct).await, "https://pay.example/lnurl/withdraw");
+    let proxied = vec![
+        ("host", "10.0.0.2:3000"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "other.example, proxy.internal"),
+        ("x-forwarded-prefix", "/ln"),
+    ];
+    assert_eq!(callback(proxied).await, "https://other.example/ln/withdraw");
+    let onion = vec![("host", "abc.onion"), ("x-forwarded-proto", "http")];
+    assert_eq!(callback(onion).await, "http://abc.onion/lnurl/withdraw");
+
+    // Plain http, or a host that isn't one, falls back to the callback URL
+    for headers in [
+        vec![("host", "pay.example"), ("x-forwarded-proto", "http")],
+        vec![("host", "evil.example/phish?")],
+        vec![("host", "pay.example"), ("x-forwarded-prefix", "/a b")],
+        vec![],
+    ] {
+        assert_eq!(callback(headers).await, "https://shop.example/lnurl/withdraw");
+    }
+
+    // Unless it is off
+    let state = state.with_callback_from_request(false);
+    let request = Request::get("/request-withdraw").header("host", "pay.example");
+    let (_, body) = send(&state, request.body(Body::empty()).unwrap()).await;
+    assert_eq!(body["callback"], "https://shop.example/lnurl/withdraw");
+}
+
+#[tokio::test]
+async fn request_endpoints_give_their_lnurl() {
+    let node = Arc::new(MockNode::default());
+    let state = state(&node).with_callback_url("https://shop.example/lnurl/");
+    let lnurl = |uri: &'static str| {
+        let state = state.clone();
+        async move {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let response = app(state).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let lnurl = String::from_utf8(body.to_vec()).unwrap();
+            assert!(lnurl.starts_with("LNURL1"), "{}", lnurl);
+            decode_lnurl(&lnurl).unwrap()
+        }
+    };
+    assert_eq!(
+        lnurl("/request-withdraw?format=lnurl").await,
+        "https://shop.example/lnurl/request-withdraw"
+    );
+    assert_eq!(
+        lnurl("/request-withdraw?k1=abc&format=lnurl").await,
+        "https://shop.example/lnurl/request-withdraw?k1=abc"
+    );
+    assert_eq!(
+        lnurl("/request-channel?format=lnurl").await,
+        "https://shop.example/lnurl/request-channel"
+    );
+    assert_eq!(
+        lnurl("/request-pay?format=lnurl").await,
+        "https://shop.example/lnurl/request-pay"
+    );
+    // Nothing was issued for them
+    assert_eq!(state.storage.stats().await.unwrap().pending_k1s, 0);
+}
+
+#[tokio::test]
+async fn request_withdraw_issues_vouchers_capped_by_budget() {
+    let (state, _) = setup();
+    state.limits.lock().await.withdraw_budget_msat = 50_000;
+    let token = login(&state).await;
+
+    let (status, body) = get_as(&state, "/request-withdraw", &token).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["maxWithdrawable"], 50_000);
+
+    let (_, me) = get_as(&state, "/me", &token).await;
+    assert_eq!(me["vouchers"], serde_json::json!([body["k1"]]));
+}
+
+#[tokio::test]
+async fn request_withdraw_refuses_budgets_under_the_minimum() {
+    let (state, _) = setup();
+    state.limits.lock().await.withdraw_budget_msat = 500;
+    let token = login(&state).await;
+
+    // 500 msat left, under the 1 sat minimum: nothing a wallet could take
+    let (status, body) = get_as(&state, "/request-withdraw", &token).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(reason(&body), "Withdraw budget exhausted");
+    let (_, me) = get_as(&state, "/me", &token).await;
+    assert_eq!(me["vouchers"], serde_json::json!([]));
+    assert_eq!(state.storage.stats().await.unwrap().pending_k1s, 0);
+}
+
+#[tokio::test]
+async fn balance_checks_give_what_is_left_of_the_budget() {
+    let (state, _) = setup();
+    state.limits.lock().await.withdraw_budget_msat = 50_000;
+    let token = login(&state).await;
+    let (_, body) = get_as(&state, "/request-withdraw", &token).await;
+    let url = body["balanceCheck"].as_str().unwrap().to_string();
+    let path = format!("/{}", url.strip_prefix(&*state.callback_url).unwrap());
+    assert!(path.starts_with("/request-withdraw?balance="), "{}", path);
+    let k1 = body["k1"].as_str().unwrap();
+    assert_eq!(withdraw(&state, k1, "lntb20000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, k1).await, "paid");
+
+    // A fresh withdraw request for what is left, pointing back at itself
+    let (status, check) = get(&state, &path).await;
+    assert_eq!(status, StatusCode::OK, "{}", check);
+    assert_eq!(check["maxWithdrawable"], 30_000);
+    assert_eq!(check["balanceCheck"], url);
+    assert_ne!(check["k1"], body["k1"]);
+    let (_, again) = get_as(&state, "/request-withdraw", &token).await;
+    assert_eq!(again["balanceCheck"], url);
+
+    // Anonymous requests have no balance to check
+    let (_, anonymous) = get(&state, "/request-withdraw").await;
+    assert!(anonymous.get("balanceCheck").is_none());
+
+    let (status, body) = get(&state, "/request-withdraw?balance=nope").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "Unknown balance link");
+    let delete = Request::builder()
+        .method(Method::DELETE)
+        .uri("/me")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&state, delete).await.0, StatusCode::OK);
+    assert_eq!(get(&state, &path).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn withdraw_pays_in_the_background() {
+    let (state, node) = setup();
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["status"], "OK");
+
+    assert_eq!(settled_status(&state, &k1).await, "paid");
+    assert_eq!(*node.paid.lock().unwrap(), ["lntb5000"]);
+
+    // No cipher, so no preimage was kept to show
+    let (_, body) = get(&state, &format!("/withdraw-status?k1={}", k1)).await;
+    assert_eq!(body["fee_msat"], ROUTING_FEE_MSAT);
+    assert!(body.get("preimage").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn withdraw_status_proves_payment() {
+    let (state, _) = setup();
+    let cipher = LocalKeyCipher::from_hex_key(&"42".repeat(32)).unwrap();
+    let state = state.with_cipher(Arc::new(cipher));
+    let k1 = withdraw_k1(&state, None).await;
+    withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(settled_status(&state, &k1).await, "paid");
+
+    let (_, body) = get(&state, &format!("/withdraw-status?k1={}", k1)).await;
+    assert_eq!(body["preimage"], "11".repeat(32));
+    assert_eq!(body["fee_msat"], ROUTING_FEE_MSAT);
+}
+
+#[tokio::test]
+async fn withdraw_reports_failed_payments() {
+    let node = Arc::new(MockNode {
+        failing_payments: true,
+        ..Default::default()
+    });
+    let state = state(&node);
+    let k1 = withdraw_k1(&state, None).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, &k1).await, "failed");
+
+    let (_, body) = get(&state, &format!("/withdraw-status?k1={}", k1)).await;
+    assert!(body.get("fee_msat").is_none(), "{}", body);
+}
+
+#[tokio::test]
+async fn withdraw_rejects_unknown_k1() {
+    let (state, node) = setup();
+    let (status, body) = withdraw(&state, "nope", "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or already used k1");
+    assert!(node.paid.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn withdraw_k1_is_single_use() {
+    let (state, _) = setup();
+    let k1 = withdraw_k1(&state, None).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or already used k1");
+}
+
+#[tokio::test]
+async fn withdraw_rejects_undecodable_invoices() {
+    let (state, _) = setup();
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = withdraw(&state, &k1, "garbage").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid invoice: Invalid bech32 string");
+}
+
+#[tokio::test]
+async fn withdraw_rejects_amountless_invoices() {
+    let (state, _) = setup();
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = withdraw(&state, &k1, "lntb").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invoice has no amount");
+}
+
+#[tokio::test]
+async fn withdraw_amount_bounds() {
+    let (state, node) = setup();
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = withdraw(&state, &k1, "lntb999").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Amount 999 msat below minimum 1000 msat");
+
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = withdraw(&state, &k1, "lntb1000001").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        reason(&body),
+        "Amount 1000001 msat exceeds maximum 1000000 msat"
+    );
+
+    // The bounds themselves are fine
+    for amount in [1_000, 1_000_000] {
+        let k1 = withdraw_k1(&state, None).await;
+        let (status, _) = withdraw(&state, &k1, &format!("lntb{}", amount)).await;
+        assert_eq!(status, StatusCode::OK);
+        settled_status(&state, &k1).await;
+    }
+    // A refused amount spends nothing, the k1 takes a corrected invoice
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    settled_status(&state, &k1).await;
+    assert_eq!(node.paid.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn withdraw_reports_node_errors() {
+    let node = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let state = state(&node);
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid invoice: Connection refused");
+}
+
+#[tokio::test]
+async fn withdraw_draws_down_the_voucher_budget() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, &k1).await, "paid");
+    assert_eq!(budget(&state, &token).await, 10_000_000 - 5_000);
+}
+
+#[tokio::test]
+async fn withdraw_refunds_the_budget_when_the_payment_fails() {
+    let node = Arc::new(MockNode {
+        failing_payments: true,
+        ..Default::default()
+    });
+    let state = state(&node);
+    let token = login(&state).await;
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, &k1).await, "failed");
+    assert_eq!(budget(&state, &token).await, 10_000_000);
+}
+
+#[tokio::test]
+async fn withdraw_rejects_amounts_over_budget() {
+    let (state, node) = setup();
+    state.limits.lock().await.withdraw_budget_msat = 2_000;
+    let token = login(&state).await;
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        reason(&body),
+        "Amount 5000 msat exceeds remaining budget 2000 msat"
+    );
+    assert!(node.paid.lock().unwrap().is_empty());
+    assert_eq!(budget(&state, &token).await, 2_000);
+}
+
+#[tokio::test]
+async fn withdraw_turns_away_what_the_channels_cant_carry() {
+    let (state, node) = setup();
+    state.limits.lock().await.max_withdrawable_msat = 10_000_000;
+    let token = login(&state).await;
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    let issued_at = state.storage.k1_issued_at(&k1).await.unwrap();
+    // Issue times are in seconds: a k1 put back now would look newer
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+
+    // 4000 sat outbound, less pay's 1% fee budget
+    let (status, body) = withdraw(&state, &k1, "lntb5000000").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        reason(&body),
+        "Not enough outbound liquidity for 5000000 msat, temporarily reduce amount to 3960396 msat"
+    );
+    assert!(node.paid.lock().unwrap().is_empty());
+    assert_eq!(budget(&state, &token).await, 10_000_000);
+    // Nothing was spent, so the k1 keeps its TTL
+    assert_eq!(state.storage.k1_issued_at(&k1).await.unwrap(), issued_at);
+
+    // The voucher is still good for less
+    assert_eq!(withdraw(&state, &k1, "lntb3960396").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, &k1).await, "paid");
+    assert_eq!(budget(&state, &token).await, 10_000_000 - 3_960_396);
+}
+
+#[tokio::test]
+async fn withdraws_keep_a_cleaned_invoice_description() {
+    let node = Arc::new(MockNode {
+        description: Some("coffee\n[admin] budget reset\u{7}".to_string()),
+        ..Default::default()
+    });
+    let state = state(&node).with_withdraw_description("Refund from the shop");
+    let (_, body) = get(&state, "/request-withdraw").await;
+    assert_eq!(body["defaultDescription"], "Refund from the shop");
+
+    let k1 = body["k1"].as_str().unwrap();
+    assert_eq!(withdraw(&state, k1, "lntb5000").await.0, StatusCode::OK);
+    let uri = format!("/admin/withdrawals/{}", k1);
+    let (_, body) = send(
+        &state,
+        admin_request(Method::GET, &uri, "dashboard-key", None),
+    )
+    .await;
+    assert_eq!(body["withdrawal"]["description"], "coffee [admin] budget reset");
+}
+
+#[tokio::test]
+async fn withdraw_policy_can_deny() {
+    let node = Arc::new(MockNode::default());
+    let state = AppState {
+        withdraw_policy: Arc::new(DenyAll),
+        ..state(&node)
+    };
+    let token = login(&state).await;
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(reason(&body), "Withdrawals are paused");
+    assert!(node.paid.lock().unwrap().is_empty());
+    assert_eq!(budget(&state, &token).await, 10_000_000);
+    // A denial is final: the k1 is spent, and its voucher with it
+    let (_, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(reason(&body), "Invalid or already used k1");
+    assert!(state.storage.get_voucher(&k1).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn withdraw_status_of_unknown_k1() {
+    let (state, _) = setup();
+    let (status, body) = get(&state, "/withdraw-status?k1=nope").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "Unknown withdrawal");
+}
+
+#[tokio::test]
+async fn operators_hear_of_spent_budgets_and_failed_payments() {
+    let node = Arc::new(MockNode {
+        failing_payments: true,
+        ..Default::default()
+    });
+    let alerts = Arc::new(Alerts::default());
+    let notifications =
+        Notifications::default().with_notifier(alerts.clone(), &NotificationKind::ALL);
+    let state = state(&node).with_notifications(notifications);
+    state.limits.lock().await.withdraw_budget_msat = 5_000;
+    let token = login(&state).await;
+
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, &k1).await, "failed");
+    // Both are sent in the background
+    for _ in 0..200 {
+        if alerts.0.lock().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let alerts = alerts.0.lock().unwrap();
+    let alert = |kind| match alerts.iter().find(|alert| alert.kind == kind) {
+        Some(alert) => alert.body.clone(),
+        None => panic!("no {} alert in {:?}", kind.as_str(), alerts),
+    };
+    assert!(alert(NotificationKind::BudgetExhausted).contains(WALLET_ID));
+    let failed = alert(NotificationKind::PaymentFailed);
+    assert!(failed.contains(&k1), "{}", failed);
+    assert!(failed.contains("Ran out of routes to try"), "{}", failed);
+    assert_eq!(alerts.len(), 2);
+}
+
+// -----------------------------------------------------------------------------
+// LUD-06
+// -----------------------------------------------------------------------------
+
+#[tokio::test]
+async fn pay_request_invoices_commit_to_the_metadata() {
+    let (state, node) = setup();
+    let state = state.with_pay_config(PayConfig {
+        min_sendable_msat: 5_000,
+        max_sendable_msat: 50_000,
+        description: "Tip jar".to_string(),
+        ..Default::default()
+    });
+    let (status, body) = get(&state, "/request-pay").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["tag"], "payRequest");
+    assert_eq!(body["callback"], "http://192.168.27.72:3000/pay");
+    assert_eq!(body["minSendable"], 5_000);
+    assert_eq!(body["maxSendable"], 50_000);
+    let metadata = body["metadata"].as_str().unwrap().to_string();
+    assert_eq!(metadata, r#"[["text/plain","Tip jar"]]"#);
+    assert_eq!(body["disposable"], false);
+
+    let (status, body) = get(&state, "/pay?amount=21000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["pr"], "lntb21000");
+    assert_eq!(body["routes"], serde_json::json!([]));
+    let hashed = node.hashed.lock().unwrap().clone();
+    assert_eq!(hashed.len(), 1);
+    assert!(hashed[0].0.starts_with("lnurl-pay-"), "{}", hashed[0].0);
+    assert_eq!(hashed[0].1, metadata);
+}
+
+#[tokio::test]
+async fn pay_callback_checks_the_amount() {
+    let down = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let down = state(&down);
+    let (state, node) = setup();
+    for query in ["", "?amount=", "?amount=1sat", "?amount=999", "?amount=100000001"] {
+        let (status, body) = get(&state, &format!("/pay{}", query)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        assert!(reason(&body).starts_with("amount must be"), "{}", query);
+    }
+    assert!(node.hashed.lock().unwrap().is_empty());
+
+    let (status, body) = get(&down, "/pay?amount=1000").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(reason(&body), "Failed to create invoice: Connection refused");
+}
+
+#[tokio::test]
+async fn bridged_pay_and_withdraw_requests_link_to_each_other() {
+    let (state, _) = setup();
+    let (_, pay) = get(&state, "/request-pay").await;
+    assert!(pay.get("withdrawLink").is_none());
+    let (_, withdraw) = get(&state, "/request-withdraw").await;
+    assert!(withdraw.get("payLink").is_none());
+
+    let state = state
+        .with_callback_url("https://shop.example/lnurl/")
+        .with_pay_config(PayConfig {
+            bridge: true,
+            ..Default::default()
+        });
+    let (_, pay) = get(&state, "/request-pay").await;
+    assert_eq!(pay["withdrawLink"], "lnurlw://shop.example/lnurl/request-withdraw");
+    let (_, withdraw) = get(&state, "/request-withdraw").await;
+    assert_eq!(withdraw["payLink"], "lnurlp://shop.example/lnurl/request-pay");
+    // An address is its user's, not the point of sale's
+    let mut config = PayConfig {
+        bridge: true,
+        ..Default::default()
+    };
+    config.addresses.insert("alice".to_string(), None);
+    let state = state.with_pay_config(config);
+    let (_, address) = get(&state, "/.well-known/lnurlp/alice").await;
+    assert!(address.get("withdrawLink").is_none());
+}
+
+#[tokio::test]
+async fn lightning_addresses_pay_their_user() {
+    let (state, node) = setup();
+    let mut config = PayConfig::default();
+    config.addresses.insert("alice".to_string(), None);
+    let state = state.with_pay_config(config);
+
+    let (status, body) = get(&state, "/.well-known/lnurlp/Alice").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["tag"], "payRequest");
+    assert_eq!(body["callback"], "http://192.168.27.72:3000/pay/alice");
+    assert_eq!(body["disposable"], false);
+    let metadata = body["metadata"].as_str().unwrap().to_string();
+    assert!(metadata.contains(r#"["text/identifier","alice@192.168.27.72"]"#), "{}", metadata);
+
+    let (status, body) = get(&state, "/pay/alice?amount=2000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let hashed = node.hashed.lock().unwrap().clone();
+    assert!(hashed[0].0.starts_with("lnurl-pay-alice-"), "{}", hashed[0].0);
+    assert_eq!(hashed[0].1, metadata);
+
+    for path in ["/.well-known/lnurlp/bob", "/pay/bob?amount=2000"] {
+        let (status, body) = get(&state, path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+        assert_eq!(reason(&body), "Unknown Lightning Address");
+    }
+    assert_eq!(node.hashed.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn pay_requests_are_tagged_for_caches() {
+    let (state, _) = setup();
+    let mut config = PayConfig::default();
+    config.addresses.insert("alice".to_string(), None);
+    let state = state.with_pay_config(config);
+    let fetch = |uri: &str, if_none_match: Option<&str>| {
+        let mut request = Request::get(uri);
+        if let Some(etag) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        app(state.clone()).oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let response = fetch("/.well-known/lnurlp/alice", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=300");
+    let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["callback"], "http://192.168.27.72:3000/pay/alice");
+
+    // A wallet or CDN holding it gets no body
+    let response = fetch("/.well-known/lnurlp/Alice", Some(&etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(body.is_empty());
+    let response = fetch("/.well-known/lnurlp/alice", Some("\"stale\"")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Each document has its own
+    let response = fetch("/request-pay", Some(&etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()[header::ETAG], etag.as_str());
+    let response = fetch("/.well-known/lnurlp/bob", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.headers().get(header::ETAG).is_none());
+
+    // One that hands out a k1 is for its wallet only
+    let state = state.with_pay_config(PayConfig {
+        disposable: true,
+        ..Default::default()
+    });
+    let request = Request::get("/request-pay").body(Body::empty()).unwrap();
+    let response = app(state).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    assert!(response.headers().get(header::ETAG).is_none());
+}
+
+#[tokio::test]
+async fn disposable_pay_links_take_one_payment() {
+    let (state, node) = setup();
+    let mut config = PayConfig {
+        disposable: true,
+        ..Default::default()
+    };
+    config.addresses.insert("alice".to_string(), None);
+    let state = state.with_pay_config(config);
+
+    let (status, body) = get(&state, "/request-pay").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["disposable"], true);
+    let callback = body["callback"].as_str().unwrap();
+    let path = callback.strip_prefix(&*state.callback_url).unwrap().to_string();
+    assert!(path.starts_with("pay?k1="), "{}", path);
+
+    let (status, body) = get(&state, "/pay?amount=1000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "This pay link takes one payment, request a new one");
+    // A bad amount leaves the k1 for a corrected one
+    let (status, _) = get(&state, &format!("/{}&amount=1", path)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = get(&state, &format!("/{}&amount=1000", path)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, body) = get(&state, &format!("/{}&amount=1000", path)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or already used k1");
+    assert_eq!(node.hashed.lock().unwrap().len(), 1);
+
+    // A k1 whose invoice couldn't be made stays spent
+    let down = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let down = self::state(&down).with_pay_config(PayConfig {
+        disposable: true,
+        ..Default::default()
+    });
+    let (_, body) = get(&down, "/request-pay").await;
+    let callback = body["callback"].as_str().unwrap();
+    let path = callback.strip_prefix(&*down.callback_url).unwrap().to_string();
+    let (status, _) = get(&down, &format!("/{}&amount=1000", path)).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let (_, body) = get(&down, &format!("/{}&amount=1000", path)).await;
+    assert_eq!(reason(&body), "Invalid or already used k1");
+
+    // Addresses stay reusable
+    let (_, body) = get(&state, "/.well-known/lnurlp/alice").await;
+    assert_eq!(body["disposable"], false);
+    assert_eq!(body["callback"], "http://192.168.27.72:3000/pay/alice");
+}
+
+#[tokio::test]
+async fn pay_callbacks_carry_the_success_action() {
+    let (state, _) = setup();
+    let (_, body) = get(&state, "/pay?amount=1000").await;
+    assert!(body.get("successAction").is_none(), "{}", body);
+
+    let mut config = PayConfig {
+        success_action: Some(SuccessActionConfig::Message {
+            message: "Thanks!".to_string(),
+        }),
+        ..Default::default()
+    };
+    config.addresses.insert("alice".to_string(), None);
+    config.addresses.insert("bob".to_string(), None);
+    let receipt = SuccessActionConfig::Url {
+        description: "Your receipt".to_string(),
+        url: "https://shop.example/receipts".to_string(),
+    };
+    config.address_success_actions.insert("bob".to_string(), receipt);
+    let state = state.with_pay_config(config);
+
+    let thanks = serde_json::json!({"tag": "message", "message": "Thanks!"});
+    let (_, body) = get(&state, "/pay?amount=1000").await;
+    assert_eq!(body["successAction"], thanks);
+    let (_, body) = get(&state, "/pay/alice?amount=1000").await;
+    assert_eq!(body["successAction"], thanks);
+    let (_, body) = get(&state, "/pay/bob?amount=1000").await;
+    assert_eq!(body["successAction"]["tag"], "url");
+    assert_eq!(body["successAction"]["url"], "https://shop.example/receipts");
+}
+
+#[tokio::test]
+async fn aes_success_actions_use_the_servers_preimage() {
+    let (state, node) = setup();
+    let state = state.with_pay_config(PayConfig {
+        success_action: Some(SuccessActionConfig::Aes {
+            description: "Your code".to_string(),
+            plaintext: "SAVE10".to_string(),
+        }),
+        ..Default::default()
+    });
+    let (status, body) = get(&state, "/pay?amount=1000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let action = &body["successAction"];
+    assert_eq!(action["tag"], "aes");
+    assert_eq!(action["description"], "Your code");
+    assert!(action["ciphertext"].is_string() && action["iv"].is_string(), "{}", action);
+    // Each invoice its own preimage
+    get(&state, "/pay?amount=1000").await;
+    let preimages = node.preimages.lock().unwrap().clone();
+    assert_eq!(preimages.len(), 2);
+    assert_ne!(preimages[0], preimages[1]);
+}
+
+#[tokio::test]
+async fn pay_invoices_verify_once_paid() {
+    let (state, node) = setup();
+    let (status, body) = get(&state, "/pay?amount=1000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let verify = body["verify"].as_str().unwrap();
+    let path = format!("/{}", verify.strip_prefix(&*state.callback_url).unwrap());
+    let (status, pending) = get(&state, &path).await;
+    assert_eq!(status, StatusCode::OK, "{}", pending);
+    assert_eq!(pending["status"], "OK");
+    assert_eq!(pending["settled"], false);
+    assert_eq!(pending["preimage"], Value::Null);
+    assert_eq!(pending["pr"], body["pr"]);
+
+    let payment_hash = path.strip_prefix("/verify/").unwrap();
+    let (label, _, preimage) = node.by_hash.lock().unwrap()[payment_hash].clone();
+    node.invoices.lock().unwrap().insert(label, InvoiceStatus::Paid);
+    let (_, settled) = get(&state, &path).await;
+    assert_eq!(settled["settled"], true);
+    assert_eq!(settled["preimage"], hex::encode(preimage));
+
+    // Only pay invoices, and only payment hashes
+    let other = node
+        .create_invoice_hashed(1_000, "lsps1-order", "order", 600, None)
+        .await
+        .unwrap();
+    for path in [
+        format!("/verify/{}", other.payment_hash),
+        format!("/verify/{}", "00".repeat(32)),
+        "/verify/not-a-hash".to_string(),
+    ] {
+        let (status, body) = get(&state, &path).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+        assert_eq!(reason(&body), "Not found");
+    }
+}
+
+// -----------------------------------------------------------------------------
+// LUD-04 and sessions
+// -----------------------------------------------------------------------------
+
+#[tokio::test]
+async fn auth_challenge_is_32_random_bytes() {
+    let (state, _) = setup();
+    let k1 = auth_k1(&state).await;
+    assert_eq!(hex::decode(&k1).unwrap().len(), 32);
+    assert_ne!(k1, auth_k1(&state).await);
+}
+
+#[tokio::test]
+async fn auth_opens_a_session() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let (status, me) = get_as(&state, "/me", &token).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["linking_key"], WALLET_ID);
+    assert_eq!(me["withdraw_budget_msat"], 10_000_000);
+    assert_eq!(me["vouchers"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn returning_users_are_recognized() {
+    let (state, _) = setup();
+    let k1 = auth_k1(&state).await;
+    let (_, first) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(first["event"], "REGISTERED");
+    let k1 = auth_k1(&state).await;
+    let (_, again) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(again["event"], "LOGGEDIN");
+    let (_, me) = get_as(&state, "/me", again["token"].as_str().unwrap()).await;
+    assert!(me["last_login_at"].as_u64().unwrap() >= me["created_at"].as_u64().unwrap());
+    assert_eq!(me["metadata"], serde_json::json!({}));
+
+    // The operator's labels
+    let uri = format!("/admin/accounts/{}/metadata", WALLET_ID);
+    let metadata = serde_json::json!({ "name": "Corner shop", "plan": "pro" });
+    let put = |key: &str, uri: &str, body: Value| admin_request(Method::PUT, uri, key, Some(body));
+    let (status, _) = send(&state, put("dashboard-key", &uri, metadata.clone())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = send(&state, put("admin-key", &uri, metadata.clone())).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let account = format!("/admin/accounts/{}", WALLET_ID);
+    let request = admin_request(Method::GET, &account, "dashboard-key", None);
+    let (status, body) = send(&state, request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["metadata"], metadata);
+    assert_eq!(body["last_login_at"], me["last_login_at"]);
+    let request = admin_request(Method::GET, "/admin/accounts", "dashboard-key", None);
+    let (_, body) = send(&state, request).await;
+    assert_eq!(body["accounts"][0]["linking_key"], WALLET_ID);
+
+    let too_many: serde_json::Map<String, Value> =
+        (0..33).map(|i| (i.to_string(), Value::from("x"))).collect();
+    let (status, body) = send(&state, put("admin-key", &uri, too_many.into())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "At most 32 metadata entries");
+    let unknown = format!("/admin/accounts/{}/metadata", NODE_ID);
+    let (status, body) = send(&state, put("admin-key", &unknown, metadata)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "Unknown account");
+}
+
+#[tokio::test]
+async fn logins_are_posted_to_the_auth_webhook() {
+    let (deliveries, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let hook = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move |headers: axum::http::HeaderMap, body: String| async move {
+            let signature = headers["x-lnurl-signature"].to_str().unwrap().to_string();
+            deliveries.send((signature, body)).unwrap();
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, hook).await });
+    let node = Arc::new(MockNode::default());
+    let state = state(&node).with_auth_webhook(AuthWebhook::new(&url, b"s3cret"));
+
+    let (_, challenge) = get(&state, "/auth-challenge?action=register").await;
+    let k1 = challenge["k1"].as_str().unwrap();
+    assert!(challenge["url"].as_str().unwrap().ends_with("&action=register"));
+    let uri = format!(
+        "/auth-response?tag=login&k1={}&action=register&signature={}&pubkey={}",
+        k1, GOOD_SIGNATURE, WALLET_ID
+    );
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (signature, body) = received.recv().await.unwrap();
+    lnurl_models::verify_webhook(body.as_bytes(), &signature, b"s3cret", 300).unwrap();
+    let event: lnurl_models::LoginEvent = serde_json::from_str(&body).unwrap();
+    assert_eq!(event.event, "REGISTERED");
+    assert_eq!(event.linking_key, WALLET_ID);
+    assert_eq!(event.action, "register");
+
+    login(&state).await;
+    let (_, body) = received.recv().await.unwrap();
+    let event: lnurl_models::LoginEvent = serde_json::from_str(&body).unwrap();
+    assert_eq!((event.event.as_str(), event.action.as_str()), ("LOGGEDIN", "login"));
+
+    let (status, body) = get(&state, "/auth-challenge?action=pay").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Unknown action \"pay\", expected register, login, link, auth");
+}
+
+/// Signs the k1 of a login link the way LUD-04 wallets do: DER over its bytes
+fn der_login(url: &str, secret: &secp256k1::SecretKey) -> String {
+    let k1 = url.split("k1=").nth(1).unwrap();
+    let digest: [u8; 32] = hex::decode(k1).unwrap().try_into().unwrap();
+    let secp = secp256k1::Secp256k1::new();
+    let sig = secp.sign_ecdsa(&secp256k1::Message::from_digest(digest), secret);
+    format!(
+        "{}&sig={}&key={}",
+        url,
+        hex::encode(sig.serialize_der()),
+        secret.public_key(&secp)
+    )
+}
+
+#[tokio::test]
+async fn wallets_log_in_with_a_der_signature_of_the_login_link() {
+    // No node needed to verify, only for the CLN fallback
+    let node = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let state = state(&node).with_callback_url("https://shop.example/lnurl/");
+    let secret = secp256k1::SecretKey::from_slice(&[0x42; 32]).unwrap();
+    let linking_key = secret.public_key(&secp256k1::Secp256k1::new()).to_string();
+
+    let (_, challenge) = get(&state, "/auth-challenge").await;
+    let url = challenge["url"].as_str().unwrap();
+    let k1 = challenge["k1"].as_str().unwrap();
+    assert_eq!(
+        url,
+        format!("https://shop.example/lnurl/auth-response?tag=login&k1={}", k1)
+    );
+    assert_eq!(decode_lnurl(challenge["lnurl"].as_str().unwrap()).unwrap(), url);
+
+    let callback = der_login(url, &secret);
+    let path = callback.strip_prefix(&*state.callback_url).unwrap();
+    let (status, body) = get(&state, &format!("/{}", path)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["event"], "REGISTERED");
+    let (_, me) = get_as(&state, "/me", body["token"].as_str().unwrap()).await;
+    assert_eq!(me["linking_key"], linking_key);
+
+    // Signed by another key than the one sent
+    let (_, challenge) = get(&state, "/auth-challenge").await;
+    let k1 = challenge["k1"].as_str().unwrap();
+    let other = secp256k1::SecretKey::from_slice(&[0x43; 32]).unwrap();
+    let forged = der_login(&format!("/auth-response?tag=login&k1={}", k1), &other)
+        .replace(&other.public_key(&secp256k1::Secp256k1::new()).to_string(), &linking_key);
+    let (status, body) = get(&state, &forged).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reason(&body), "Signature verification failed");
+
+    // Not DER at all
+    let k1 = auth_k1(&state).await;
+    let uri = format!("/auth-response?k1={}&sig=abcd&key={}", k1, linking_key);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid sig: Signature is not DER");
+
+    // Neither shape of signature
+    let k1 = auth_k1(&state).await;
+    let uri = format!("/auth-response?k1={}&key={}", k1, linking_key);
+    let (status, body) = get(&state, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Expected sig and key (or signature and pubkey)");
+}
+
+#[tokio::test]
+async fn auth_rejects_unknown_k1() {
+    let (state, _) = setup();
+    let (status, body) = auth_response(&state, &"00".repeat(32), GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or expired k1");
+}
+
+#[tokio::test]
+async fn k1s_only_work_for_the_flow_they_were_issued_for() {
+    let (state, _) = setup();
+    let k1 = withdraw_k1(&state, None).await;
+    let (status, body) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Invalid or expired k1");
+    let uri = format!("/open-channel?k1={}&remoteid={}", k1, WALLET_ID);
+    assert_eq!(get(&state, &uri).await.0, StatusCode::BAD_REQUEST);
+
+    // Left for its own callback
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let k1 = auth_k1(&state).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::BAD_REQUEST);
+    let (status, body) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+async fn k1s_expire_after_their_own_flows_ttl() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let body = serde_json::json!({ "linking_key": WALLET_ID });
+    let issue = admin_request(Method::POST, "/admin/vouchers", "admin-key", Some(body));
+    let (_, voucher) = send(&state, issue).await;
+    let state = state.with_k1_ttls(K1Ttls {
+        withdraw_secs: 0,
+        auth_secs: 0,
+        ..K1Ttls::default()
+    });
+
+    let k1 = auth_k1(&state).await;
+    let (status, body) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Expired k1, request a new one");
+    // Spent all the same
+    let (_, body) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(reason(&body), "Invalid or expired k1");
+
+    let k1 = withdraw_k1(&state, Some(&token)).await;
+    let (status, body) = withdraw(&state, &k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Expired k1, request a new one");
+
+    // Channel k1s keep their own, longer TTL
+    let (status, body) = open_channel(&state, "").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // A voucher's TTL starts over each time a wallet scans it, however long
+    // ago it was issued
+    let k1 = voucher["k1"].as_str().unwrap();
+    let state = state.with_k1_ttls(K1Ttls {
+        withdraw_secs: 2,
+        ..K1Ttls::default()
+    });
+    tokio::time::sleep(Duration::from_millis(2_100)).await;
+    let (status, body) = get(&state, &format!("/request-withdraw?k1={}", k1)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, body) = withdraw(&state, k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+async fn a_restore_keeps_when_k1s_were_issued() {
+    let (state, _) = setup();
+    let two_hours_ago = crate::unix_now() - 2 * 60 * 60;
+    state
+        .storage
+        .restore_k1("stale", K1Purpose::Withdraw, two_hours_ago, None)
+        .await
+        .unwrap();
+    let fresh = withdraw_k1(&state, None).await;
+    let issued_at = state.storage.k1_issued_at(&fresh).await.unwrap();
+
+    let snapshot = state.storage.export().await.unwrap();
+    state.storage.import(snapshot).await.unwrap();
+
+    // Past the withdraw TTL of an hour before the backup, and still after it
+    let (status, body) = withdraw(&state, "stale", "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "Expired k1, request a new one");
+    assert_eq!(state.storage.k1_issued_at(&fresh).await.unwrap(), issued_at);
+}
+
+#[tokio::test]
+async fn expired_k1s_are_swept_but_vouchers_stay() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let body = serde_json::json!({ "linking_key": WALLET_ID });
+    let issue = admin_request(Method::POST, "/admin/vouchers", "admin-key", Some(body));
+    let (_, voucher) = send(&state, issue).await;
+    channel_k1(&state).await;
+    let state = state.with_k1_ttls(K1Ttls {
+        withdraw_secs: 0,
+        auth_secs: 0,
+        ..K1Ttls::default()
+    });
+    let auth = auth_k1(&state).await;
+    withdraw_k1(&state, Some(&token)).await;
+
+    // The channel k1 has time left; the auth, withdraw and voucher ones don't
+    assert_eq!(expiry::sweep(&state).await.unwrap(), 3);
+    assert_eq!(expiry::sweep(&state).await.unwrap(), 0);
+    let stats = state.storage.stats().await.unwrap();
+    assert_eq!(stats.pending_k1s, 1);
+    let (_, body) = auth_response(&state, &auth, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(reason(&body), "Invalid or expired k1");
+
+    let request = admin_request(Method::GET, "/admin/store", "dashboard-key", None);
+    let (_, body) = send(&state, request).await;
+    assert_eq!(body["k1s"]["by_purpose"]["auth"]["expired"]["total"], 1);
+    assert_eq!(body["k1s"]["by_purpose"]["withdraw"]["expired"]["total"], 2);
+
+    // Scanning the voucher stores its k1 again
+    let state = state.with_k1_ttls(K1Ttls::default());
+    let k1 = voucher["k1"].as_str().unwrap();
+    let (status, body) = get(&state, &format!("/request-withdraw?k1={}", k1)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, body) = withdraw(&state, k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+async fn auth_rejects_malformed_pubkeys() {
+    let (state, _) = setup();
+    let k1 = auth_k1(&state).await;
+    let (status, body) = auth_response(&state, &k1, GOOD_SIGNATURE, "02abcd").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(reason(&body).starts_with("Invalid pubkey"), "{}", body);
+}
+
+#[tokio::test]
+async fn auth_rejects_bad_signatures() {
+    let (state, _) = setup();
+    let k1 = auth_k1(&state).await;
+    let (status, body) = auth_response(&state, &k1, "d9forged", WALLET_ID).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reason(&body), "Signature verification failed");
+}
+
+#[tokio::test]
+async fn auth_reports_node_errors() {
+    let node = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let state = state(&node);
+    let k1 = auth_k1(&state).await;
+    let (status, body) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(reason(&body), "Verification error: Connection refused");
+}
+
+#[tokio::test]
+async fn auth_handler_can_deny() {
+    let node = Arc::new(MockNode::default());
+    let state = AppState {
+        auth_handler: Arc::new(DenyAll),
+        ..state(&node)
+    };
+    let k1 = auth_k1(&state).await;
+    let (status, body) = auth_response(&state, &k1, GOOD_SIGNATURE, WALLET_ID).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(reason(&body), "Not on the guest list");
+    assert!(state
+        .storage
+        .get_account(WALLET_ID)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn me_needs_a_session() {
+    let (state, _) = setup();
+    let (status, body) = get(&state, "/me").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reason(&body), "Missing or invalid session token");
+
+    let (status, _) = get_as(&state, "/me", "not-a-token").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = get_as(&state, "/me/withdrawals", "not-a-token").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reason(&body), "Missing or invalid session token");
+}
+
+#[tokio::test]
+async fn me_withdrawals_lists_the_accounts_own() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let (_, voucher) = get_as(&state, "/request-withdraw", &token).await;
+    let k1 = voucher["k1"].as_str().unwrap();
+    assert_eq!(withdraw(&state, k1, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, k1).await, "paid");
+    // Someone else's
+    let (_, anonymous) = get(&state, "/request-withdraw").await;
+    let other = anonymous["k1"].as_str().unwrap();
+    assert_eq!(withdraw(&state, other, "lntb7000").await.0, StatusCode::OK);
+
+    let (status, body) = get_as(&state, "/me/withdrawals", &token).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let withdrawals = body["withdrawals"].as_array().unwrap();
+    assert_eq!(withdrawals.len(), 1);
+    assert_eq!(withdrawals[0]["k1"], k1);
+    assert_eq!(withdrawals[0]["amount_msat"], 5_000);
+    assert_eq!(withdrawals[0]["status"], "paid");
+}
+
+#[tokio::test]
+async fn delete_me_ends_the_account() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let request = Request::builder()
+        .method(Method::DELETE)
+        .uri("/me")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&state, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["deletion_id"].is_string());
+
+    // The session went with it
+    assert_eq!(
+        get_as(&state, "/me", &token).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn logout_ends_only_that_session() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let other = login(&state).await;
+    let request = Request::builder()
+        .method(Method::DELETE)
+        .uri("/me/session")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&state, request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    assert_eq!(
+        get_as(&state, "/me", &token).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(get_as(&state, "/me", &other).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn sessions_run_out_even_across_a_restore() {
+    let (state, _) = setup();
+    let state = state.with_session_ttl_secs(1);
+    let token = login(&state).await;
+    assert_eq!(get_as(&state, "/me", &token).await.0, StatusCode::OK);
+    assert_eq!(expiry::sweep_sessions(&state).await.unwrap(), 0);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (status, body) = get_as(&state, "/me", &token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reason(&body), "Missing or invalid session token");
+
+    // A restore keeps when it was opened rather than starting it over
+    let snapshot = state.storage.export().await.unwrap();
+    state.storage.import(snapshot).await.unwrap();
+    assert_eq!(
+        get_as(&state, "/me", &token).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+
+    assert_eq!(expiry::sweep_sessions(&state).await.unwrap(), 1);
+    assert_eq!(state.storage.stats().await.unwrap().sessions, 0);
+}
+
+// -----------------------------------------------------------------------------
+// Admin API
+// -----------------------------------------------------------------------------
+
+#[tokio::test]
+async fn admin_api_checks_the_key_and_role() {
+    let (state, _) = setup();
+    let (status, _) = get(&state, "/admin/stats").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let limits = |key: &str| {
+        Request::builder()
+            .method(Method::PUT)
+            .uri("/admin/limits")
+            .header("x-api-key", key)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"max_withdrawable_msat": 5000}"#))
+            .unwrap()
+    };
+    let (status, _) = send(&state, limits("dashboard-key")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(&state, limits("admin-key")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(state.limits.lock().await.max_withdrawable_msat, 5_000);
+}
+
+#[tokio::test]
+async fn admin_limits_must_be_in_range() {
+    let (state, _) = setup();
+    let update = |body: Value| admin_request(Method::PUT, "/admin/limits", "admin-key", Some(body));
+
+    for (body, expected) in [
+        (
+            serde_json::json!({ "channel_capacity_sat": 0 }),
+            "channel_capacity_sat must be between 1 and 2100000000000000",
+        ),
+        (
+            serde_json::json!({ "max_withdrawable_msat": 0, "min_withdrawable_msat": 0 }),
+            "max_withdrawable_msat must be between 1 and 2100000000000000000",
+        ),
+        (
+            serde_json::json!({ "withdraw_budget_msat": 2_100_000_000_000_000_001u64 }),
+            "withdraw_budget_msat must be between 1 and 2100000000000000000",
+        ),
+        (
+            serde_json::json!({ "min_withdrawable_msat": 2_000_000 }),
+            "min_withdrawable_msat must not exceed max_withdrawable_msat",
+        ),
+    ] {
+        let (status, reply) = send(&state, update(body)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(reason(&reply), expected);
+    }
+    // None of them got through
+    let limits = state.limits.lock().await.clone();
+    assert_eq!(limits.channel_capacity_sat, 100_000);
+    assert_eq!(limits.max_withdrawable_msat, 1_000_000);
+
+    let body = serde_json::json!({ "channel_capacity_sat": 16_777_215 });
+    assert_eq!(send(&state, update(body)).await.0, StatusCode::OK);
+    assert_eq!(state.limits.lock().await.channel_capacity_sat, 16_777_215);
+}
+
+#[tokio::test]
+async fn admin_api_answers_only_allowed_networks() {
+    let (state, _) = setup();
+    let state = state.with_admin_allowlist(vec!["10.0.0.0/8".parse().unwrap()]);
+    let from = |ip: Option<&str>| {
+        let mut request = admin_request(Method::GET, "/admin/stats", "admin-key", None);
+        if let Some(ip) = ip {
+            let peer = SocketAddr::new(ip.parse().unwrap(), 50_000);
+            request.extensions_mut().insert(ConnectInfo(peer));
+        }
+        request
+    };
+
+    assert_eq!(send(&state, from(Some("10.1.2.3"))).await.0, StatusCode::OK);
+    let (status, body) = send(&state, from(Some("203.0.113.9"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(reason(&body), "Address not allowed");
+    // Whoever the server can't place is turned away too
+    assert_eq!(send(&state, from(None)).await.0, StatusCode::FORBIDDEN);
+    // The public endpoints are not affected
+    assert_eq!(get(&state, "/auth-challenge").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admin_api_can_be_served_apart() {
+    let (state, _) = setup();
+    let status = |app: axum::Router, request: Request<Body>| async move {
+        app.oneshot(request).await.unwrap().status()
+    };
+    let stats = || admin_request(Method::GET, "/admin/stats", "admin-key", None);
+    let challenge = || Request::get("/auth-challenge").body(Body::empty()).unwrap();
+
+    assert_eq!(status(public_app(state.clone()), stats()).await, StatusCode::NOT_FOUND);
+    assert_eq!(status(public_app(state.clone()), challenge()).await, StatusCode::OK);
+    assert_eq!(status(admin_app(state.clone()), stats()).await, StatusCode::OK);
+    assert_eq!(status(admin_app(state), challenge()).await, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admin_vouchers_are_redeemable_once() {
+    let (state, _) = setup();
+    state.limits.lock().await.withdraw_budget_msat = 50_000;
+    let token = login(&state).await;
+    let issue = |key: &str, linking_key: &str| {
+        let body = serde_json::json!({ "linking_key": linking_key });
+        admin_request(Method::POST, "/admin/vouchers", key, Some(body))
+    };
+
+    let (status, _) = send(&state, issue("dashboard-key", WALLET_ID)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = send(&state, issue("admin-key", NODE_ID)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "Unknown account");
+
+    let (status, voucher) = send(&state, issue("admin-key", WALLET_ID)).await;
+    assert_eq!(status, StatusCode::OK, "{}", voucher);
+    let k1 = voucher["k1"].as_str().unwrap();
+    let url = voucher["url"].as_str().unwrap();
+    let path = url.strip_prefix(&*state.callback_url).unwrap();
+    assert_eq!(path, format!("request-withdraw?k1={}", k1));
+    assert_eq!(decode_lnurl(voucher["lnurl"].as_str().unwrap()).unwrap(), url);
+
+    // Whoever holds the link withdraws from the account, no session needed
+    let (status, body) = get(&state, &format!("/{}", path)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["k1"], k1);
+    assert_eq!(body["maxWithdrawable"], 50_000);
+    let (status, _) = withdraw(&state, k1, "lntb5000").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(settled_status(&state, k1).await, "paid");
+    assert_eq!(budget(&state, &token).await, 45_000);
+
+    let (status, body) = get(&state, &format!("/{}", path)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "Unknown or redeemed voucher");
+
+    // The scan issued the k1 the withdraw consumed
+    let request = admin_request(Method::GET, "/admin/store", "dashboard-key", None);
+    let (_, store) = send(&state, request).await;
+    let withdraw_k1s = &store["k1s"]["by_purpose"]["withdraw"];
+    assert_eq!(withdraw_k1s["issued"]["total"], 1);
+    assert_eq!(withdraw_k1s["consumed"]["total"], 1);
+
+    let uri = format!("/admin/withdrawals/{}", k1);
+    let (status, body) = send(
+        &state,
+        admin_request(Method::GET, &uri, "dashboard-key", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["withdrawal"]["linking_key"], WALLET_ID);
+    assert_eq!(body["withdrawal"]["status"], "paid");
+    let uri = "/admin/withdrawals/unknown";
+    let (status, _) = send(
+        &state,
+        admin_request(Method::GET, uri, "dashboard-key", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admin_vouchers_only_work_within_their_window() {
+    let (state, node) = setup();
+    login(&state).await;
+    let now = crate::unix_now();
+    let issue = |valid_from: Option<u64>, valid_until: Option<u64>| {
+        let body = serde_json::json!({
+            "linking_key": WALLET_ID,
+            "valid_from": valid_from,
+            "valid_until": valid_until,
+        });
+        admin_request(Method::POST, "/admin/vouchers", "admin-key", Some(body))
+    };
+
+    let (status, body) = send(&state, issue(Some(now), Some(now))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(reason(&body), "valid_until must be after valid_from");
+
+    let (_, later) = send(&state, issue(Some(now + 3600), None)).await;
+    assert_eq!(later["valid_from"], now + 3600);
+    let (_, lapsed) = send(&state, issue(None, Some(now - 60))).await;
+    let (_, open) = send(&state, issue(None, Some(now + 3600))).await;
+
+    let later = later["k1"].as_str().unwrap();
+    let (status, body) = get(&state, &format!("/request-withdraw?k1={}", later)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        reason(&body),
+        format!("Voucher not yet active (valid from {})", now + 3600)
+    );
+    // Refused without spending the k1
+    let (status, body) = withdraw(&state, later, "lntb5000").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(reason(&body).starts_with("Voucher not yet active"));
+    assert!(state.storage.get_voucher(later).await.unwrap().is_some());
+
+    let lapsed = lapsed["k1"].as_str().unwrap();
+    let (status, body) = withdraw(&state, lapsed, "lntb5000").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        reason(&body),
+        format!("Voucher expired (valid until {})", now - 60)
+    );
+    assert!(node.paid.lock().unwrap().is_empty());
+
+    let open = open["k1"].as_str().unwrap();
+    assert_eq!(withdraw(&state, open, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, open).await, "paid");
+
+    let (status, body) = send(
+        &state,
+        admin_request(Method::GET, "/admin/vouchers", "admin-key", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let window_status = |k1: &str| {
+        let vouchers = body["vouchers"].as_array().unwrap();
+        let voucher = vouchers.iter().find(|voucher| voucher["k1"] == k1).unwrap();
+        voucher["window_status"].as_str().unwrap().to_string()
+    };
+    assert_eq!(window_status(later), "not_yet_active");
+    assert_eq!(window_status(lapsed), "expired");
+
+    // A voucher's k1 spends it, so read-only keys don't get it whole
+    let (_, body) = send(
+        &state,
+        admin_request(Method::GET, "/admin/vouchers", "dashboard-key", None),
+    )
+    .await;
+    let listed: Vec<&str> = body["vouchers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|voucher| voucher["k1"].as_str().unwrap())
+        .collect();
+    assert!(listed.contains(&crate::logging::redact(later).as_str()), "{}", body);
+    assert!(!listed.contains(&later), "{}", body);
+}
+
+#[tokio::test]
+async fn allowances_refill_and_hand_out_fresh_vouchers() {
+    let (state, _) = setup();
+    let token = login(&state).await;
+    let uri = format!("/admin/accounts/{}/allowance", WALLET_ID);
+    let grant = |key: &str, period_secs: u64| {
+        let body = serde_json::json!({ "amount_msat": 20_000_000, "period_secs": period_secs });
+        admin_request(Method::PUT, &uri, key, Some(body))
+    };
+    let (status, _) = send(&state, grant("dashboard-key", 86_400)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = send(&state, grant("admin-key", 60)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    // Topped up to the amount right away
+    let (status, granted) = send(&state, grant("admin-key", 86_400)).await;
+    assert_eq!(status, StatusCode::OK, "{}", granted);
+    assert_eq!(budget(&state, &token).await, 20_000_000);
+    let url = granted["url"].as_str().unwrap();
+    let path = url.strip_prefix(&*state.callback_url).unwrap();
+
+    // The link is reusable, each fetch a fresh voucher
+    let (status, first) = get(&state, &format!("/{}", path)).await;
+    assert_eq!(status, StatusCode::OK, "{}", first);
+    assert_eq!(first["maxWithdrawable"], 1_000_000);
+    assert_eq!(first["balanceCheck"], url);
+    let (_, second) = get(&state, &format!("/{}", path)).await;
+    assert_ne!(first["k1"], second["k1"]);
+
+    // The wallet asks to hear of refills
+    let k1 = second["k1"].as_str().unwrap();
+    let uri_withdraw = format!(
+        "/withdraw?k1={}&pr=lntb5000&balanceNotify=https://wallet.example/notify",
+        k1
+    );
+    assert_eq!(get(&state, &uri_withdraw).await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, k1).await, "paid");
+    assert_eq!(budget(&state, &token).await, 20_000_000 - 5_000);
+    let allowance = state.storage.get_allowance(WALLET_ID).await.unwrap();
+    assert_eq!(
+        allowance.unwrap().balance_notify.as_deref(),
+        Some("https://wallet.example/notify")
+    );
+
+    let (status, body) = send(
+        &state,
+        admin_request(Method::GET, "/admin/allowances", "dashboard-key", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["allowances"][0]["linking_key"], WALLET_ID);
+    // The link spends the allowance, so read-only keys don't get it whole
+    let listed = body["allowances"][0]["url"].as_str().unwrap();
+    assert_ne!(listed, url);
+    assert!(listed.ends_with('…'), "{}", listed);
+    assert!(body["allowances"][0].get("lnurl").is_none(), "{}", body);
+
+    let revoke = || admin_request(Method::DELETE, &uri, "admin-key", None);
+    assert_eq!(send(&state, revoke()).await.0, StatusCode::OK);
+    let (status, body) = send(&state, revoke()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "No allowance for this account");
+    let (status, body) = get(&state, &format!("/{}", path)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reason(&body), "Unknown allowance");
+}
+
+#[tokio::test]
+async fn metrics_count_flows_for_scrapers_with_a_key() {
+    let (state, _) = setup();
+    let scrape = |authorization: Option<&str>| {
+        let request = Request::get("/metrics");
+        let request = match authorization {
+            Some(value) => request.header(header::AUTHORIZATION, value),
+            None => request,
+        };
+        app(state.clone()).oneshot(request.body(Body::empty()).unwrap())
+    };
+    let response = scrape(None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let k1 = withdraw_k1(&state, None).await;
+    assert_eq!(withdraw(&state, &k1, "lntb5000").await.0, StatusCode::OK);
+    assert_eq!(settled_status(&state, &k1).await, "paid");
+    login(&state).await;
+    let k1 = auth_k1(&state).await;
+    auth_response(&state, &k1, "d9forged", WALLET_ID).await;
+
+    // A read-only key is enough, sent the way Prometheus sends one
+    let response = scrape(Some("Bearer dashboard-key")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+    assert!(content_type.starts_with("text/plain"), "{}", content_type);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let page = String::from_utf8(body.to_vec()).unwrap();
+    for line in [
+        "lnurl_withdraws_requested_total 1",
+        "lnurl_withdraws_paid_total 1",
+        "lnurl_withdraws_failed_total 0",
+        "lnurl_auth_total{outcome=\"success\"} 1",
+        "lnurl_auth_total{outcome=\"bad_signature\"} 1",
+        "lnurl_k1s_consumed_total{flow=\"withdraw\"} 1",
+    ] {
+        assert!(page.lines().any(|l| l == line), "{} not in\n{}", line, page);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Probes
+// -----------------------------------------------------------------------------
+
+#[tokio::test]
+async fn readyz_fails_while_the_node_is_down_or_syncing() {
+    let (up, _) = setup();
+    assert_eq!(get(&up, "/healthz").await.0, StatusCode::OK);
+    let (status, body) = get(&up, "/readyz").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let node = Arc::new(MockNode {
+        down: true,
+        ..Default::default()
+    });
+    let down = state(&node);
+    // Up all the same, so not restarted for the node's fault
+    assert_eq!(get(&down, "/healthz").await.0, StatusCode::OK);
+    let (status, body) = get(&down, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(reason(&body), "Node unreachable: Connection refused");
+
+    let node = Arc::new(MockNode {
+        sync_warning: Some("Still loading latest blocks from bitcoind.".to_string()),
+        ..Default::default()
+    });
+    let (status, body) = get(&state(&node), "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        reason(&body),
+        "Node not synced: Still loading latest blocks from bitcoind."
+    );
+}
+
+#[tokio::test]
+async fn readyz_fails_once_the_tip_is_stale() {
+    let (state, _) = setup();
+    let state = state.with_max_block_age(Some(Duration::from_millis(50)));
+    assert_eq!(get(&state, "/readyz").await.0, StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (status, body) = get(&state, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(reason(&body), "No new block for 0s, the node's tip is still 80000");
+
+    let state = state.with_max_block_age(None);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(get(&state, "/readyz").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn probes_answer_during_a_restore() {
+    let (state, _) = setup();
+    let _restoring = state.write_gate.write().await;
+    for uri in ["/healthz", "/readyz"] {
+        let probe = tokio::time::timeout(Duration::from_secs(1), get(&state, uri));
+        assert_eq!(probe.await.expect(uri).0, StatusCode::OK);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// gRPC admin service
+// -----------------------------------------------------------------------------
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use super::*;
+    use crate::grpc::{proto, AdminService};
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::Code;
+
+    /// Calls lnurl.admin.v1.Admin/`method` in process, with `key`
+    async fn call<Req, Resp>(
+        state: &AppState,
+        method: &'static str,
+        key: &str,
+        message: Req,
+    ) -> Result<Resp, tonic::Status>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        let mut grpc = tonic::client::Grpc::new(AdminService::new(state.clone()));
+        grpc.ready().await.unwrap();
+        let mut request = tonic::Request::new(message);
+        request
+            .metadata_mut()
+            .insert("x-api-key", key.parse().unwrap());
+        let path = PathAndQuery::from_static(method);
+        let response = grpc.unary(request, path, ProstCodec::default()).await?;
+        Ok(response.into_inner())
+    }
+
+    async fn issue_voucher(
+        state: &AppState,
+        key: &str,
+        linking_key: &str,
+    ) -> Result<proto::IssueVoucherReply, tonic::Status> {
+        let request = proto::IssueVoucherRequest {
+            linking_key: linking_key.to_string(),
+            ..Default::default()
+        };
+        call(state, "/lnurl.admin.v1.Admin/IssueVoucher", key, request).await
+    }
+
+    async fn withdraw_status(
+        state: &AppState,
+        k1: &str,
+    ) -> Result<proto::WithdrawStatusReply, tonic::Status> {
+        let request = proto::WithdrawStatusRequest { k1: k1.to_string() };
+        call(
+            state,
+            "/lnurl.admin.v1.Admin/WithdrawStatus",
+            "dashboard-key",
+            request,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn checks_the_key_and_role() {
+        let (state, _) = setup();
+        let stats = |key| {
+            call::<_, proto::StatsReply>(
+                &state,
+                "/lnurl.admin.v1.Admin/Stats",
+                key,
+                proto::StatsRequest {},
+            )
+        };
+        assert_eq!(
+            stats("wrong-key").await.unwrap_err().code(),
+            Code::Unauthenticated
+        );
+        assert!(stats("dashboard-key").await.is_ok());
+
+        login(&state).await;
+        let denied = issue_voucher(&state, "dashboard-key", WALLET_ID).await;
+        assert_eq!(denied.unwrap_err().code(), Code::PermissionDenied);
+
+        let unknown = call::<_, proto::StatsReply>(
+            &state,
+            "/lnurl.admin.v1.Admin/Nope",
+            "admin-key",
+            proto::StatsRequest {},
+        );
+        assert_eq!(unknown.await.unwrap_err().code(), Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn issues_vouchers_and_reports_their_withdrawals() {
+        let (state, _) = setup();
+        let unknown = issue_voucher(&state, "admin-key", WALLET_ID).await;
+        assert_eq!(unknown.unwrap_err().code(), Code::NotFound);
+
+        login(&state).await;
+        let voucher = issue_voucher(&state, "admin-key", WALLET_ID).await.unwrap();
+        assert_eq!(voucher.linking_key, WALLET_ID);
+        assert!(voucher
+            .url
+            .ends_with(&format!("/request-withdraw?k1={}", voucher.k1)));
+
+        let stats: proto::StatsReply = call(
+            &state,
+            "/lnurl.admin.v1.Admin/Stats",
+            "admin-key",
+            proto::StatsRequest {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(stats.accounts, 1);
+        assert_eq!(stats.vouchers, 1);
+
+        assert_eq!(
+            withdraw_status(&state, &voucher.k1)
+                .await
+                .unwrap_err()
+                .code(),
+            Code::NotFound
+        );
+        withdraw(&state, &voucher.k1, "lntb5000").await;
+        assert_eq!(settled_status(&state, &voucher.k1).await, "paid");
+
+        let status = withdraw_status(&state, &voucher.k1).await.unwrap();
+        assert_eq!(status.status, "paid");
+        assert_eq!(status.amount_msat, 5_000);
+        assert_eq!(status.linking_key.as_deref(), Some(WALLET_ID));
+        assert_eq!(status.preimage, None);
+        assert_eq!(status.fee_msat, Some(ROUTING_FEE_MSAT));
+    }
+}
+
+#[tokio::test]
+async fn admin_store_counts_k1s_by_purpose() {
+    let (state, _) = setup();
+    auth_k1(&state).await;
+    login(&state).await;
+    channel_k1(&state).await;
+    let (status, _) = withdraw(&state, "not-a-k1", "lntb5000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let request = admin_request(Method::GET, "/admin/store", "dashboard-key", None);
+    let (status, body) = send(&state, request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let k1s = &body["k1s"];
+    assert_eq!(k1s["active"], 2);
+    assert_eq!(k1s["by_purpose"]["auth"]["active"], 1);
+    assert_eq!(k1s["by_purpose"]["auth"]["issued"]["total"], 2);
+    assert_eq!(k1s["by_purpose"]["auth"]["consumed"]["total"], 1);
+    assert_eq!(k1s["by_purpose"]["channel"]["active"], 1);
+    assert_eq!(k1s["by_purpose"]["withdraw"]["active"], 0);
+    assert_eq!(k1s["by_purpose"]["withdraw"]["refused"]["total"], 1);
+    assert_eq!(body["sessions"]["active"], 1);
+    assert_eq!(body["sessions"]["opened"]["total"], 1);
+
+    // The dump shows what is there, but nothing anyone could redeem
+    let request = admin_request(Method::GET, "/admin/store/dump", "dashboard-key", None);
+    let (status, body) = send(&state, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["k1s"].as_array().unwrap().len(), 2);
+    assert_eq!(body["sessions"][0]["linking_key"], format!("{}…", &WALLET_ID[..6]));
+    for k1 in body["k1s"].as_array().unwrap() {
+        assert_eq!(k1["k1"].as_str().unwrap().chars().count(), 7, "{}", k1);
+    }
+}
+
+#[tokio::test]
+async fn admin_liquidity_weighs_channels_against_budgets() {
+    let (state, _) = setup();
+    login(&state).await; // an account with the default 10k sat budget
+
+    let report = liquidity::take_report(&state).await.unwrap();
+    // The channel still confirming can't pay anything out yet
+    assert_eq!(report.outbound_msat, 4_000_000);
+    assert_eq!(report.inbound_msat, 1_000_000);
+    assert_eq!(report.channels.len(), 2);
+    assert_eq!(report.outstanding_budget_msat, 10_000_000);
+    assert_eq!(report.pending_withdrawals_msat, 0);
+    state.storage.insert_liquidity_report(&report).await.unwrap();
+
+    let request = admin_request(Method::GET, "/admin/liquidity", "dashboard-key", None);
+    let (status, body) = send(&state, request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["reports"].as_array().unwrap().len(), 1);
+    assert_eq!(body["reports"][0]["onchain_sat"], 250_000);
+    assert_eq!(body["reports"][0]["channels"][0]["short_channel_id"], "80000x1x0");
+    assert_eq!(body["outlook"]["headroom_msat"], -6_000_000);
+    assert_eq!(body["outlook"]["dry_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn accounts_are_throttled_separately() {
+    let node = Arc::new(MockNode::default());
+    let state = state(&node).with_account_rate_limit(RateLimit {
+        burst: 2,
+        refill_per_minute: 1,
+    });
+    let token = login(&state).await;
+
+    assert_eq!(get_as(&state, "/me", &token).await.0, StatusCode::OK);
+    let (status, _) = get_as(&state, "/request-withdraw", &token).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = get_as(&state, "/request-withdraw", &token).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        reason(&body),
+        "Too many requests for this account, try again in 60s"
+    );
+    let (status, body) = get_as(&state, "/me", &token).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["status"], "ERROR");
+
+    // Neither another account nor anonymous requests are held back
+    let k1 = auth_k1(&state).await;
+    let (_, body) = auth_response(&state, &k1, GOOD_SIGNATURE, NODE_ID).await;
+    let other = body["token"].as_str().unwrap();
+    assert_eq!(get_as(&state, "/me", other).await.0, StatusCode::OK);
+    assert_eq!(get(&state, "/request-withdraw").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn capture_records_redacted_exchanges() {
+    let (state, _) = setup();
+    let dir = std::env::temp_dir().join(format!("lnurl-capture-{}", uuid::Uuid::new_v4()));
+    let capture = Capture::start(CaptureConfig {
+        dir: dir.clone(),
+        window: capture::DEFAULT_WINDOW,
+        k1: None,
+        raw: false,
+    })
+    .unwrap();
+    let router = app(state.clone())
+        .layer(axum::middleware::from_fn_with_state(capture.clone(), capture::record));
+    let send = |request: Request<Body>| async {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<Value>(&body).unwrap()
+    };
+
+    let body = send(Request::get("/request-withdraw").body(Body::empty()).unwrap()).await;
+    // The wallet still gets the whole k1
+    let k1 = body["k1"].as_str().unwrap().to_string();
+    assert_eq!(k1.len(), 36);
+    let uri = format!("/withdraw?k1={}&pr=lntb5000", k1);
+    send(Request::get(uri).body(Body::empty()).unwrap()).await;
+    send(admin_request(Method::GET, "/admin/stats", "admin-key", None)).await;
+
+    let lines: Vec<Value> = std::fs::read_to_string(capture.path())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // The header, then both LNURL exchanges but not the admin one
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert_eq!(lines[0]["capture"]["redacted"], true);
+    let redacted = format!("{}…", &k1[..6]);
+    assert_eq!(lines[1]["request"]["uri"], "/request-withdraw");
+    assert_eq!(lines[1]["response"]["body"]["k1"], redacted);
+    assert_eq!(lines[1]["response"]["body"]["tag"], "withdrawRequest");
+    assert_eq!(
+        lines[2]["request"]["uri"],
+        format!("/withdraw?k1={}&pr=lntb50…", redacted)
+    );
+    assert_eq!(lines[2]["response"]["status"], 200);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
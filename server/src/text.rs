@@ -0,0 +1,185 @@
+// =============================================================================
+// Untrusted and configured text
+// =============================================================================
+//
+// Text that comes from wallets, such as query parameters and the description
+// of a submitted invoice, is cleaned before it is logged or stored. Control
+// characters could forge log lines or garble a terminal, so logs get them
+// escaped and storage drops them, and both cut the text to a bounded length.
+//
+// Text an operator configures ends up in what wallets show their users, so it
+// is checked rather than cleaned, and the server refuses to start with text
+// that breaks the rules:
+//
+//   LNURL_WITHDRAW_DESCRIPTION — LUD-03 defaultDescription, one line of at
+//                                most 639 bytes (what a BOLT11 description
+//                                holds)
+//   LNURL_PAY_LONG_DESCRIPTION — LUD-20 text/long-desc (pay.rs), any number
+//                                of lines up to 4 KiB: the invoice commits
+//                                to it by hash, so no BOLT11 limit applies
+
+use std::fmt;
+use tracing::info;
+
+/// The most a BOLT11 description can hold
+pub const MAX_DESCRIPTION_BYTES: usize = 639;
+/// A long description only goes in metadata, this is to keep payRequests
+/// a size any wallet takes
+pub const MAX_LONG_DESCRIPTION_BYTES: usize = 4096;
+/// Longer wallet text is cut in logs
+const MAX_LOGGED_CHARS: usize = 256;
+/// Longer invoice descriptions are cut before they are stored
+pub const MAX_STORED_DESCRIPTION_CHARS: usize = 256;
+
+#[derive(Debug)]
+pub struct TextError(String);
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TextError {}
+
+/// The first `max_chars` characters of `text`, marked when cut
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Wallet text fit for one log line: control characters escaped, cut to a
+/// bounded length
+pub fn for_log(text: &str) -> String {
+    let escaped: String = text
+        .chars()
+        .flat_map(|c| match c.is_control() {
+            true => c.escape_default().collect::<Vec<_>>(),
+            false => vec![c],
+        })
+        .collect();
+    truncate(&escaped, MAX_LOGGED_CHARS)
+}
+
+/// An invoice description fit to store: on one line, control characters
+/// dropped, cut to MAX_STORED_DESCRIPTION_CHARS
+pub fn clean_description(description: &str) -> String {
+    let printable: String = description
+        .chars()
+        .filter(|c| c.is_whitespace() || !c.is_control())
+        .collect();
+    let one_line = printable.split_whitespace().collect::<Vec<_>>().join(" ");
+    truncate(&one_line, MAX_STORED_DESCRIPTION_CHARS)
+}
+
+/// Checks a description an operator configured under `name`: one line of
+/// printable text, not empty and at most MAX_DESCRIPTION_BYTES
+pub fn check_description(name: &str, description: &str) -> Result<(), TextError> {
+    if description.trim().is_empty() {
+        return Err(TextError(format!("{} must not be empty", name)));
+    }
+    if description.chars().any(char::is_control) {
+        return Err(TextError(format!(
+            "{} must be one line without control characters",
+            name
+        )));
+    }
+    if description.len() > MAX_DESCRIPTION_BYTES {
+        return Err(TextError(format!(
+            "{} is {} bytes, at most {} fit an invoice",
+            name,
+            description.len(),
+            MAX_DESCRIPTION_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a long description an operator configured under `name`: printable
+/// text on any number of lines, not empty and at most
+/// MAX_LONG_DESCRIPTION_BYTES
+pub fn check_long_description(name: &str, description: &str) -> Result<(), TextError> {
+    if description.trim().is_empty() {
+        return Err(TextError(format!("{} must not be empty", name)));
+    }
+    if description
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+    {
+        return Err(TextError(format!(
+            "{} must not have control characters other than line breaks and tabs",
+            name
+        )));
+    }
+    if description.len() > MAX_LONG_DESCRIPTION_BYTES {
+        return Err(TextError(format!(
+            "{} is {} bytes, at most {}",
+            name,
+            description.len(),
+            MAX_LONG_DESCRIPTION_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// Reads LNURL_WITHDRAW_DESCRIPTION, None when it is not set
+pub fn load_withdraw_description() -> Result<Option<String>, TextError> {
+    let description = match std::env::var("LNURL_WITHDRAW_DESCRIPTION") {
+        Ok(description) => description,
+        Err(_) => return Ok(None),
+    };
+    check_description("LNURL_WITHDRAW_DESCRIPTION", &description)?;
+    info!("Withdraw description: {}", description);
+    Ok(Some(description))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logged_text_stays_on_one_line() {
+        assert_eq!(
+            for_log("k1\nWithdraw payment successful!"),
+            "k1\\nWithdraw payment successful!"
+        );
+        assert_eq!(for_log("\u{1b}[2Jcafé"), "\\u{1b}[2Jcafé");
+        let long = for_log(&"a".repeat(1_000));
+        assert_eq!(long.chars().count(), MAX_LOGGED_CHARS + 1);
+        assert!(long.ends_with('…'));
+    }
+
+    #[test]
+    fn stored_descriptions_are_printable_and_bounded() {
+        assert_eq!(
+            clean_description(" coffee\r\n\tfor two \u{7}"),
+            "coffee for two"
+        );
+        let long = clean_description(&"é".repeat(1_000));
+        assert_eq!(long.chars().count(), MAX_STORED_DESCRIPTION_CHARS + 1);
+    }
+
+    #[test]
+    fn configured_descriptions_are_checked() {
+        assert!(check_description("D", "Withdrawal from service").is_ok());
+        assert!(check_description("D", " ").is_err());
+        assert!(check_description("D", "two\nlines").is_err());
+        assert!(check_description("D", &"a".repeat(MAX_DESCRIPTION_BYTES)).is_ok());
+        let error = check_description("D", &"é".repeat(320)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "D is 640 bytes, at most 639 fit an invoice"
+        );
+    }
+
+    #[test]
+    fn long_descriptions_take_lines() {
+        assert!(check_long_description("D", "Hand-roasted.\n\n\tOrigin: Huila").is_ok());
+        assert!(check_long_description("D", "\n").is_err());
+        assert!(check_long_description("D", "bell\u{7}").is_err());
+        let long = "a".repeat(MAX_LONG_DESCRIPTION_BYTES + 1);
+        assert!(check_long_description("D", &long).is_err());
+    }
+}
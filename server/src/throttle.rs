@@ -0,0 +1,220 @@
+// =============================================================================
+// Per-account rate limiting
+// =============================================================================
+//
+// A token bucket per linking key for what accounts do through their session
+// or vouchers: asking for withdraws and checking their balance (/me). Keyed by
+// account rather than by IP, so one busy account behind a carrier NAT doesn't
+// get its neighbours blocked too. Each request takes a token; tokens come
+// back at refill_per_minute up to burst.
+//
+// Buckets live in this process, so each replica enforces the limit on its own.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Beyond this many buckets, full ones (the same as none) are dropped. The
+/// next pruning waits until the buckets left have doubled, so that a map of
+/// busy accounts isn't swept on every request.
+const PRUNE_AT: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32, // 0: unlimited
+    pub refill_per_minute: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            burst: 20,
+            refill_per_minute: 10,
+        }
+    }
+}
+
+/// Reads LNURL_ACCOUNT_BURST and LNURL_ACCOUNT_REFILL_PER_MIN, keeping the
+/// default for whichever is unset or malformed
+pub fn load_rate_limit() -> RateLimit {
+    let mut limit = RateLimit::default();
+    for (var, value) in [
+        ("LNURL_ACCOUNT_BURST", &mut limit.burst),
+        ("LNURL_ACCOUNT_REFILL_PER_MIN", &mut limit.refill_per_minute),
+    ] {
+        let Ok(raw) = std::env::var(var) else {
+            continue;
+        };
+        match raw.trim().parse() {
+            Ok(parsed) => *value = parsed,
+            Err(_) => warn!("Ignoring malformed {} (expected a number)", var),
+        }
+    }
+
+    match limit.burst {
+        0 => info!("Per-account rate limit disabled"),
+        _ => info!(
+            "Per-account rate limit: bursts of {}, {} per minute after",
+            limit.burst, limit.refill_per_minute
+        ),
+    }
+    limit
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_account: HashMap<String, Bucket>,
+    prune_at: usize,
+}
+
+#[derive(Debug)]
+pub struct AccountThrottle {
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+}
+
+impl AccountThrottle {
+    pub fn new(limit: RateLimit) -> AccountThrottle {
+        AccountThrottle {
+            limit,
+            buckets: Mutex::new(Buckets {
+                by_account: HashMap::new(),
+                prune_at: PRUNE_AT,
+            }),
+        }
+    }
+
+    /// Takes a token from the account's bucket, or says how long until there
+    /// is one
+    pub fn check(&self, linking_key: &str) -> Result<(), Duration> {
+        self.check_at(linking_key, Instant::now())
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        let refill = elapsed * self.limit.refill_per_minute as f64 / 60.0;
+        (bucket.tokens + refill).min(self.limit.burst as f64)
+    }
+
+    fn check_at(&self, linking_key: &str, now: Instant) -> Result<(), Duration> {
+        if self.limit.burst == 0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let burst = self.limit.burst as f64;
+        if buckets.by_account.len() >= buckets.prune_at {
+            buckets
+                .by_account
+                .retain(|_, bucket| self.refilled(bucket, now) < burst);
+            buckets.prune_at = PRUNE_AT.max(2 * buckets.by_account.len());
+        }
+
+        let bucket = buckets
+            .by_account
+            .entry(linking_key.to_string())
+            .or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        match self.limit.refill_per_minute {
+            0 => Err(Duration::MAX),
+            per_minute => {
+                let missing = 1.0 - bucket.tokens;
+                Err(Duration::from_secs_f64(missing * 60.0 / per_minute as f64))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(burst: u32, refill_per_minute: u32) -> AccountThrottle {
+        AccountThrottle::new(RateLimit {
+            burst,
+            refill_per_minute,
+        })
+    }
+
+    #[test]
+    fn bursts_then_refills() {
+        let throttle = throttle(3, 6); // a token every 10s
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(throttle.check_at("alice", start), Ok(()));
+        }
+        let wait = throttle.check_at("alice", start).unwrap_err();
+        assert_eq!(wait.as_secs_f64().round(), 10.0);
+
+        assert!(throttle.check_at("alice", start + Duration::from_secs(5)).is_err());
+        assert_eq!(throttle.check_at("alice", start + Duration::from_secs(10)), Ok(()));
+        // Refills stop at the burst
+        let later = start + Duration::from_secs(3600);
+        for _ in 0..3 {
+            assert_eq!(throttle.check_at("alice", later), Ok(()));
+        }
+        assert!(throttle.check_at("alice", later).is_err());
+    }
+
+    #[test]
+    fn accounts_have_their_own_buckets() {
+        let throttle = throttle(1, 1);
+        let now = Instant::now();
+        assert_eq!(throttle.check_at("alice", now), Ok(()));
+        assert!(throttle.check_at("alice", now).is_err());
+        assert_eq!(throttle.check_at("bob", now), Ok(()));
+    }
+
+    #[test]
+    fn zero_burst_is_unlimited() {
+        let throttle = throttle(0, 0);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(throttle.check_at("alice", now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let throttle = throttle(2, 60);
+        let now = Instant::now();
+        for i in 0..PRUNE_AT {
+            throttle.check_at(&i.to_string(), now).unwrap();
+        }
+        // A second later they are all full again
+        throttle
+            .check_at("alice", now + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(throttle.buckets.lock().unwrap().by_account.len(), 1);
+    }
+
+    #[test]
+    fn busy_buckets_put_off_the_next_pruning() {
+        let throttle = throttle(2, 1);
+        let now = Instant::now();
+        for i in 0..PRUNE_AT {
+            throttle.check_at(&i.to_string(), now).unwrap();
+        }
+        // None is full yet, so the next sweep waits for twice as many
+        throttle.check_at("alice", now).unwrap();
+        let buckets = throttle.buckets.lock().unwrap();
+        assert_eq!(buckets.by_account.len(), PRUNE_AT + 1);
+        assert_eq!(buckets.prune_at, 2 * PRUNE_AT);
+    }
+}
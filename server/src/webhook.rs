@@ -0,0 +1,239 @@
+// =============================================================================
+// Auth webhooks
+// =============================================================================
+//
+// Applications that build on the server's logins learn of each one from a
+// POST of its LoginEvent (the linking key, the LUD-04 action, whether the
+// account is new) to
+//
+//   LNURL_AUTH_WEBHOOK_URL     http(s) URL of the receiver
+//   LNURL_AUTH_WEBHOOK_SECRET  shared with the receiver, required with the URL
+//   LNURL_AUTH_WEBHOOK_RETRIES attempts after the first, default 5
+//
+// The body is signed with the secret in X-Lnurl-Signature, see
+// lnurl_models::verify_webhook for the receiver's side. Deliveries run in the
+// background and never hold up the login. Connection errors, timeouts, 5xx,
+// 408 and 429 replies are retried with exponential backoff (1s, 2s, 4s, ...
+// up to a minute), each attempt signed afresh so its timestamp stays within
+// the receiver's tolerance; other replies are the receiver refusing it, and a
+// repeat would be refused too.
+
+use lnurl_models::{sign_webhook, LoginEvent, SIGNATURE_HEADER};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RETRIES: u32 = 5;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct WebhookConfigError(String);
+
+impl fmt::Display for WebhookConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for WebhookConfigError {}
+
+/// Why an attempt failed, and whether another one may succeed
+#[derive(Debug)]
+struct DeliveryError {
+    reason: String,
+    retry: bool,
+}
+
+pub struct AuthWebhook {
+    url: String,
+    secret: Vec<u8>,
+    retries: u32,
+    first_backoff: Duration,
+}
+
+impl AuthWebhook {
+    pub fn new(url: &str, secret: &[u8]) -> AuthWebhook {
+        AuthWebhook {
+            url: url.to_string(),
+            secret: secret.to_vec(),
+            retries: DEFAULT_RETRIES,
+            first_backoff: FIRST_BACKOFF,
+        }
+    }
+
+    /// Attempts after the first, and the wait before the first of them,
+    /// doubled for each next one
+    pub fn with_retries(mut self, retries: u32, first_backoff: Duration) -> AuthWebhook {
+        self.retries = retries;
+        self.first_backoff = first_backoff;
+        self
+    }
+
+    /// Delivers the event in the background, see the top of the file
+    pub fn deliver(self: &Arc<Self>, event: LoginEvent) {
+        let webhook = self.clone();
+        tokio::spawn(async move {
+            let body = serde_json::to_vec(&event).expect("login events serialize");
+            if let Err(e) = webhook.deliver_now(body).await {
+                warn!(
+                    "Auth webhook of {} for {} dropped: {}",
+                    event.event, event.linking_key, e
+                );
+            }
+        });
+    }
+
+    /// Posts `body` until the receiver takes it or the retries run out
+    async fn deliver_now(&self, body: Vec<u8>) -> Result<(), String> {
+        let mut backoff = self.first_backoff;
+        let mut attempt = 0;
+        loop {
+            let url = self.url.clone();
+            let signature = sign_webhook(&body, &self.secret);
+            let payload = body.clone();
+            let result = tokio::task::spawn_blocking(move || post(&url, &signature, &payload))
+                .await
+                .map_err(|e| e.to_string())?;
+            let error = match result {
+                Ok(()) => return Ok(()),
+                Err(error) if !error.retry || attempt == self.retries => return Err(error.reason),
+                Err(error) => error,
+            };
+            attempt += 1;
+            warn!(
+                "Auth webhook failed ({}), retry {} of {} in {:?}",
+                error.reason, attempt, self.retries, backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+/// Blocking, ureq being what the server already speaks HTTP with
+fn post(url: &str, signature: &str, body: &[u8]) -> Result<(), DeliveryError> {
+    let result = ureq::post(url)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "application/json")
+        .set(SIGNATURE_HEADER, signature)
+        .send_bytes(body);
+    match result {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => Err(DeliveryError {
+            reason: format!("receiver answered {}", code),
+            retry: code >= 500 || code == 408 || code == 429,
+        }),
+        Err(ureq::Error::Transport(e)) => Err(DeliveryError {
+            reason: format!("receiver unreachable: {}", e.kind()),
+            retry: true,
+        }),
+    }
+}
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// The webhook configured in the environment, None when there is no URL
+pub fn load_auth_webhook() -> Result<Option<AuthWebhook>, WebhookConfigError> {
+    let Some(url) = var("LNURL_AUTH_WEBHOOK_URL") else {
+        return Ok(None);
+    };
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err(WebhookConfigError(format!(
+            "LNURL_AUTH_WEBHOOK_URL must be an http(s) URL, got {}",
+            url
+        )));
+    }
+    let Some(secret) = var("LNURL_AUTH_WEBHOOK_SECRET") else {
+        return Err(WebhookConfigError(
+            "LNURL_AUTH_WEBHOOK_SECRET must be set with LNURL_AUTH_WEBHOOK_URL".to_string(),
+        ));
+    };
+    let retries = match var("LNURL_AUTH_WEBHOOK_RETRIES") {
+        Some(raw) => raw.parse().map_err(|_| {
+            WebhookConfigError(format!(
+                "LNURL_AUTH_WEBHOOK_RETRIES must be a number, got {}",
+                raw
+            ))
+        })?,
+        None => DEFAULT_RETRIES,
+    };
+    info!("Posting logins to {} ({} retries)", url, retries);
+    let webhook = AuthWebhook::new(&url, secret.as_bytes()).with_retries(retries, FIRST_BACKOFF);
+    Ok(Some(webhook))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use lnurl_models::{verify_webhook, DEFAULT_TOLERANCE_SECS};
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+
+    type Received = Arc<Mutex<Vec<(String, Vec<u8>)>>>; // signature, body
+
+    /// A receiver answering `replies` in turn, recording what it got
+    async fn receiver(replies: Vec<StatusCode>) -> (String, Received) {
+        let received: Received = Arc::default();
+        let log = received.clone();
+        let replies = Arc::new(Mutex::new(replies.into_iter()));
+        let app = Router::new().route(
+            "/hook",
+            post(
+                move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                    let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+                    log.lock().unwrap().push((signature, body.to_vec()));
+                    replies.lock().unwrap().next().unwrap_or(StatusCode::OK)
+                },
+            ),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed_and_retried() {
+        let (url, received) = receiver(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TOO_MANY_REQUESTS,
+        ])
+        .await;
+        let webhook = AuthWebhook::new(&url, b"s3cret").with_retries(2, Duration::from_millis(10));
+        webhook.deliver_now(b"{\"a\":1}".to_vec()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        for (signature, body) in received.iter() {
+            assert_eq!(body, b"{\"a\":1}");
+            verify_webhook(body, signature, b"s3cret", DEFAULT_TOLERANCE_SECS).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn refused_deliveries_are_not_retried() {
+        let (url, received) = receiver(vec![StatusCode::BAD_REQUEST]).await;
+        let webhook = AuthWebhook::new(&url, b"s3cret").with_retries(3, Duration::from_millis(10));
+        let error = webhook.deliver_now(b"{}".to_vec()).await.unwrap_err();
+        assert_eq!(error, "receiver answered 400");
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // Out of retries
+        let (url, received) = receiver(vec![StatusCode::BAD_GATEWAY; 3]).await;
+        let webhook = AuthWebhook::new(&url, b"s3cret").with_retries(1, Duration::from_millis(10));
+        let error = webhook.deliver_now(b"{}".to_vec()).await.unwrap_err();
+        assert_eq!(error, "receiver answered 502");
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+}